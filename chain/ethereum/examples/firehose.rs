@@ -3,7 +3,7 @@ use graph::{
     env::env_var,
     log::logger,
     prelude::{prost, tokio, tonic},
-    {firehose, firehose::FirehoseEndpoint, firehose::ForkStep},
+    {firehose, firehose::FirehoseEndpoint, firehose::ForkStep, firehose::GrpcSettings},
 };
 use graph_chain_ethereum::codec;
 use hex::ToHex;
@@ -28,6 +28,7 @@ async fn main() -> Result<(), Error> {
             "https://api.streamingfast.io:443",
             token,
             false,
+            GrpcSettings::default(),
         )
         .await?,
     );
@@ -12,7 +12,7 @@ use mockall::predicate::*;
 use prost::Message;
 use prost_types::Any;
 use std::cmp;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt;
 use std::marker::Unpin;
 use thiserror::Error;
@@ -23,6 +23,7 @@ use graph::prelude::*;
 use graph::{
     blockchain as bc,
     components::metrics::{CounterVec, GaugeVec, HistogramVec},
+    components::store::EntityType,
     petgraph::{self, graphmap::GraphMap},
 };
 
@@ -31,6 +32,7 @@ const MULTI_LOG_FILTER_TYPE_URL: &str =
 const MULTI_CALL_TO_FILTER_TYPE_URL: &str =
     "type.googleapis.com/sf.ethereum.transform.v1.MultiCallToFilter";
 
+use crate::call_cache::InFlightCalls;
 use crate::capabilities::NodeCapabilities;
 use crate::data_source::{BlockHandlerFilter, DataSource};
 use crate::{Chain, Mapping, ENV_VARS};
@@ -125,6 +127,10 @@ pub struct TriggerFilter {
     pub(crate) log: EthereumLogFilter,
     pub(crate) call: EthereumCallFilter,
     pub(crate) block: EthereumBlockFilter,
+    /// Data sources with event handlers that declare calls, which the block
+    /// stream makes ahead of time
+    pub(crate) declared_calls: Vec<DataSource>,
+    pub(crate) entity: EthereumEntityFilter,
 }
 
 impl TriggerFilter {
@@ -140,7 +146,12 @@ impl bc::TriggerFilter<Chain> for TriggerFilter {
         self.call
             .extend(EthereumCallFilter::from_data_sources(data_sources.clone()));
         self.block
-            .extend(EthereumBlockFilter::from_data_sources(data_sources));
+            .extend(EthereumBlockFilter::from_data_sources(data_sources.clone()));
+        self.entity.extend(EthereumEntityFilter::from_data_sources(
+            data_sources.clone(),
+        ));
+        self.declared_calls
+            .extend(data_sources.filter(|ds| ds.has_declared_calls()).cloned());
     }
 
     fn node_capabilities(&self) -> NodeCapabilities {
@@ -727,10 +738,47 @@ impl EthereumBlockFilter {
     }
 }
 
+/// The entity types of other subgraphs whose changes data sources of kind
+/// `subgraph` handle, keyed by the subgraph
+#[derive(Clone, Debug, Default)]
+pub(crate) struct EthereumEntityFilter {
+    pub sources: BTreeMap<DeploymentHash, BTreeSet<EntityType>>,
+}
+
+impl EthereumEntityFilter {
+    pub fn from_data_sources<'a>(iter: impl IntoIterator<Item = &'a DataSource>) -> Self {
+        let mut filter = Self::default();
+        for data_source in iter {
+            if let Some(source) = &data_source.source.subgraph {
+                filter
+                    .sources
+                    .entry(source.clone())
+                    .or_default()
+                    .extend(data_source.source_entity_types());
+            }
+        }
+        filter
+    }
+
+    pub fn extend(&mut self, other: EthereumEntityFilter) {
+        for (source, entity_types) in other.sources {
+            self.sources.entry(source).or_default().extend(entity_types);
+        }
+    }
+
+    /// An empty filter is one that never matches.
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+}
+
 #[derive(Clone)]
 pub struct ProviderEthRpcMetrics {
     request_duration: Box<HistogramVec>,
     errors: Box<CounterVec>,
+    daily_requests: Box<GaugeVec>,
+    rate_limit_wait: Box<CounterVec>,
+    logs_range: Box<GaugeVec>,
 }
 
 impl ProviderEthRpcMetrics {
@@ -750,9 +798,33 @@ impl ProviderEthRpcMetrics {
                 vec![String::from("method"), String::from("provider")],
             )
             .unwrap();
+        let daily_requests = registry
+            .new_gauge_vec(
+                "eth_rpc_daily_requests",
+                "Counts eth rpc requests sent to a provider with limits since the start of the UTC day",
+                vec![String::from("provider")],
+            )
+            .unwrap();
+        let rate_limit_wait = registry
+            .new_counter_vec(
+                "eth_rpc_rate_limit_wait",
+                "Seconds that eth rpc requests waited for the rate limit of a provider",
+                vec![String::from("provider")],
+            )
+            .unwrap();
+        let logs_range = registry
+            .new_gauge_vec(
+                "eth_rpc_logs_range",
+                "The number of blocks that eth_getLogs requests to a provider currently span",
+                vec![String::from("provider")],
+            )
+            .unwrap();
         Self {
             request_duration,
             errors,
+            daily_requests,
+            rate_limit_wait,
+            logs_range,
         }
     }
 
@@ -765,6 +837,24 @@ impl ProviderEthRpcMetrics {
     pub fn add_error(&self, method: &str, provider: &str) {
         self.errors.with_label_values(&[method, provider]).inc();
     }
+
+    pub fn set_daily_requests(&self, requests: u64, provider: &str) {
+        self.daily_requests
+            .with_label_values(&[provider])
+            .set(requests as f64);
+    }
+
+    pub fn add_rate_limit_wait(&self, duration: f64, provider: &str) {
+        self.rate_limit_wait
+            .with_label_values(&[provider])
+            .inc_by(duration);
+    }
+
+    pub fn set_logs_range(&self, range: BlockNumber, provider: &str) {
+        self.logs_range
+            .with_label_values(&[provider])
+            .set(range as f64);
+    }
 }
 
 #[derive(Clone)]
@@ -895,12 +985,14 @@ pub trait EthereumAdapter: Send + Sync + 'static {
         block_number: BlockNumber,
     ) -> Box<dyn Future<Item = Option<H256>, Error = Error> + Send>;
 
-    /// Call the function of a smart contract.
+    /// Call the function of a smart contract. Identical calls that are in
+    /// `in_flight_calls` at the same time are only made once.
     fn contract_call(
         &self,
         logger: &Logger,
         call: EthereumContractCall,
         cache: Arc<dyn EthereumCallCache>,
+        in_flight_calls: Arc<InFlightCalls>,
     ) -> Box<dyn Future<Item = Vec<Token>, Error = EthereumContractCallError> + Send>;
 }
 
@@ -1035,6 +1127,8 @@ mod tests {
                 ]),
                 trigger_every_block: false,
             },
+            declared_calls: vec![],
+            entity: EthereumEntityFilter::default(),
         };
 
         let expected_call = MultiCallToFilter {
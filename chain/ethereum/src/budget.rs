@@ -0,0 +1,201 @@
+use graph::prelude::tokio;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::adapter::ProviderEthRpcMetrics;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// The limits on the requests that may be sent to a provider
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RequestLimits {
+    /// The number of requests per second that the token bucket refills with
+    pub requests_per_second: Option<f64>,
+    /// The size of the token bucket, i.e., how many requests may be sent
+    /// at once after the provider was idle. Defaults to one second worth of
+    /// requests
+    pub burst: Option<u32>,
+    /// How many requests may be sent per UTC day
+    pub daily_requests: Option<u64>,
+}
+
+impl RequestLimits {
+    fn capacity(&self, rate: f64) -> f64 {
+        self.burst
+            .map(|burst| burst as f64)
+            .unwrap_or_else(|| rate.ceil())
+            .max(1.0)
+    }
+}
+
+struct State {
+    /// The tokens left in the bucket. This becomes negative when a batch
+    /// takes more tokens than there are, and later requests wait until the
+    /// bucket has refilled
+    tokens: f64,
+    refilled_at: Instant,
+    /// The UTC day, in days since the epoch, that `used` counts for
+    day: u64,
+    used: u64,
+}
+
+impl State {
+    fn refill(&mut self, rate: f64, capacity: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * rate).min(capacity);
+        self.refilled_at = now;
+    }
+
+    /// Take `requests` tokens if there are any left, or return how long to
+    /// wait until there are
+    fn take(&mut self, requests: usize, rate: f64) -> Option<Duration> {
+        if self.tokens > 0.0 {
+            self.tokens -= requests as f64;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+
+    fn count(&mut self, requests: usize, day: u64) -> u64 {
+        if day != self.day {
+            self.day = day;
+            self.used = 0;
+        }
+        self.used += requests as u64;
+        self.used
+    }
+}
+
+/// Enforces the `RequestLimits` of one provider and tracks how much of its
+/// daily budget it has used
+pub struct RequestBudget {
+    provider: String,
+    limits: RequestLimits,
+    state: Mutex<State>,
+    metrics: Arc<ProviderEthRpcMetrics>,
+}
+
+impl RequestBudget {
+    pub fn new(
+        provider: String,
+        limits: RequestLimits,
+        metrics: Arc<ProviderEthRpcMetrics>,
+    ) -> Self {
+        let tokens = limits
+            .requests_per_second
+            .map(|rate| limits.capacity(rate))
+            .unwrap_or(0.0);
+        Self {
+            provider,
+            limits,
+            state: Mutex::new(State {
+                tokens,
+                refilled_at: Instant::now(),
+                day: today(),
+                used: 0,
+            }),
+            metrics,
+        }
+    }
+
+    /// Wait until the rate limit allows sending `requests` requests and
+    /// count them against the daily budget
+    pub async fn acquire(&self, requests: usize) {
+        if let Some(rate) = self.limits.requests_per_second {
+            let capacity = self.limits.capacity(rate);
+            let start = Instant::now();
+            loop {
+                let wait = {
+                    let mut state = self.state.lock().unwrap();
+                    state.refill(rate, capacity, Instant::now());
+                    state.take(requests, rate)
+                };
+                match wait {
+                    Some(wait) => tokio::time::sleep(wait).await,
+                    None => break,
+                }
+            }
+            let waited = start.elapsed();
+            if !waited.is_zero() {
+                self.metrics
+                    .add_rate_limit_wait(waited.as_secs_f64(), &self.provider);
+            }
+        }
+
+        let used = self.state.lock().unwrap().count(requests, today());
+        self.metrics.set_daily_requests(used, &self.provider);
+    }
+
+    /// Whether the provider has not used up its daily budget yet. Requests
+    /// are still sent to a provider that is over budget, but adapter
+    /// selection prefers providers that are not
+    pub fn is_within_budget(&self) -> bool {
+        match self.limits.daily_requests {
+            None => true,
+            Some(daily) => {
+                let state = self.state.lock().unwrap();
+                state.day != today() || state.used < daily
+            }
+        }
+    }
+}
+
+impl fmt::Debug for RequestBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestBudget")
+            .field("provider", &self.provider)
+            .field("limits", &self.limits)
+            .finish()
+    }
+}
+
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs() / SECONDS_PER_DAY)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(tokens: f64, now: Instant) -> State {
+        State {
+            tokens,
+            refilled_at: now,
+            day: 0,
+            used: 0,
+        }
+    }
+
+    #[test]
+    fn token_bucket() {
+        let now = Instant::now();
+        let mut state = state(2.0, now);
+
+        assert_eq!(None, state.take(1, 10.0));
+        assert_eq!(None, state.take(1, 10.0));
+        assert_eq!(Some(Duration::from_millis(100)), state.take(1, 10.0));
+
+        // Refilling never goes over capacity
+        state.refill(10.0, 2.0, now + Duration::from_secs(5));
+        assert_eq!(2.0, state.tokens);
+
+        // A batch can overdraw the bucket, and later requests wait for the
+        // debt to be paid off
+        assert_eq!(None, state.take(5, 10.0));
+        assert_eq!(Some(Duration::from_millis(400)), state.take(1, 10.0));
+    }
+
+    #[test]
+    fn daily_count_resets() {
+        let mut state = state(0.0, Instant::now());
+
+        assert_eq!(3, state.count(3, 0));
+        assert_eq!(5, state.count(2, 0));
+        assert_eq!(1, state.count(1, 1));
+    }
+}
@@ -0,0 +1,344 @@
+use anyhow::Error;
+use graph::blockchain::BlockPtr;
+use graph::prelude::futures03::channel::oneshot;
+use graph::prelude::{
+    ethabi::Address, web3::types::H256, CacheWeight, CachedEthereumCall, EthereumCallCache,
+};
+use graph::util::lfu_cache::LfuCache;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use crate::EthereumContractCallError;
+
+/// Identifies an `eth_call` by the contract, the call data and the hash of
+/// the block it is made at
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) struct CallKey {
+    address: Address,
+    data: Vec<u8>,
+    block_hash: H256,
+}
+
+impl CallKey {
+    pub(crate) fn new(address: Address, data: &[u8], block_ptr: &BlockPtr) -> Self {
+        Self {
+            address,
+            data: data.to_vec(),
+            block_hash: block_ptr.hash_as_h256(),
+        }
+    }
+}
+
+impl CacheWeight for CallKey {
+    fn indirect_weight(&self) -> usize {
+        self.data.capacity()
+    }
+}
+
+#[derive(Default)]
+struct CallResult(Vec<u8>);
+
+impl CacheWeight for CallResult {
+    fn indirect_weight(&self) -> usize {
+        self.0.capacity()
+    }
+}
+
+/// An in-memory cache of `eth_call` results in front of the call cache in
+/// the database. A chain uses one of these for all its deployments so that
+/// calls that many deployments make, like `decimals()` on a popular token,
+/// are answered from memory. Results at a block hash never change, and
+/// nothing here needs to be invalidated
+pub struct SharedCallCache {
+    store: Arc<dyn EthereumCallCache>,
+    results: Mutex<LfuCache<CallKey, CallResult>>,
+    max_weight: usize,
+}
+
+impl SharedCallCache {
+    /// Wrap `store` in a cache that holds up to `max_weight` bytes of call
+    /// results. Returns `store` if `max_weight` is 0
+    pub fn new(store: Arc<dyn EthereumCallCache>, max_weight: usize) -> Arc<dyn EthereumCallCache> {
+        if max_weight == 0 {
+            return store;
+        }
+        Arc::new(Self {
+            store,
+            results: Mutex::new(LfuCache::new()),
+            max_weight,
+        })
+    }
+
+    fn remember(&self, key: CallKey, result: &[u8]) {
+        let mut results = self.results.lock().unwrap();
+        results.insert(key, CallResult(result.to_vec()));
+        results.evict(self.max_weight);
+    }
+}
+
+impl EthereumCallCache for SharedCallCache {
+    fn get_call(
+        &self,
+        contract_address: Address,
+        encoded_call: &[u8],
+        block: BlockPtr,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let key = CallKey::new(contract_address, encoded_call, &block);
+        if let Some(result) = self.results.lock().unwrap().get(&key) {
+            return Ok(Some(result.0.clone()));
+        }
+
+        let result = self.store.get_call(contract_address, encoded_call, block)?;
+        if let Some(result) = &result {
+            self.remember(key, result);
+        }
+        Ok(result)
+    }
+
+    fn get_calls_in_block(&self, block: BlockPtr) -> Result<Vec<CachedEthereumCall>, Error> {
+        self.store.get_calls_in_block(block)
+    }
+
+    fn set_call(
+        &self,
+        contract_address: Address,
+        encoded_call: &[u8],
+        block: BlockPtr,
+        return_value: &[u8],
+    ) -> Result<(), Error> {
+        self.remember(
+            CallKey::new(contract_address, encoded_call, &block),
+            return_value,
+        );
+        self.store
+            .set_call(contract_address, encoded_call, block, return_value)
+    }
+}
+
+/// Up to how many bytes of results of prefetched calls are kept. They are
+/// needed only until the handlers for their block have run
+const PREFETCHED_CALLS_MAX_WEIGHT: usize = 64 * 1024 * 1024;
+
+/// Results of calls that were made ahead of the handlers that need them.
+/// They are kept in memory only and never written to the call cache, since
+/// calls batched through Multicall3 are made with a different sender and
+/// gas limit than the `eth_call` a handler makes. A result is only used for
+/// a handler's call with the same contract, call data and block hash
+#[derive(Default)]
+pub(crate) struct PrefetchedCalls {
+    results: Mutex<LfuCache<CallKey, CallResult>>,
+}
+
+impl PrefetchedCalls {
+    pub(crate) fn insert(
+        &self,
+        address: Address,
+        data: &[u8],
+        block_ptr: &BlockPtr,
+        result: Vec<u8>,
+    ) {
+        let mut results = self.results.lock().unwrap();
+        results.insert(CallKey::new(address, data, block_ptr), CallResult(result));
+        results.evict(PREFETCHED_CALLS_MAX_WEIGHT);
+    }
+
+    fn get(&self, key: &CallKey) -> Option<Vec<u8>> {
+        self.results
+            .lock()
+            .unwrap()
+            .get(key)
+            .map(|result| result.0.clone())
+    }
+}
+
+/// Answers calls with the results of prefetched calls before looking in
+/// `store`. Everything else, including writes, goes to `store`
+pub(crate) struct PrefetchingCallCache {
+    prefetched: Arc<PrefetchedCalls>,
+    store: Arc<dyn EthereumCallCache>,
+}
+
+impl PrefetchingCallCache {
+    pub(crate) fn new(
+        prefetched: Arc<PrefetchedCalls>,
+        store: Arc<dyn EthereumCallCache>,
+    ) -> Arc<dyn EthereumCallCache> {
+        Arc::new(Self { prefetched, store })
+    }
+}
+
+impl EthereumCallCache for PrefetchingCallCache {
+    fn get_call(
+        &self,
+        contract_address: Address,
+        encoded_call: &[u8],
+        block: BlockPtr,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let key = CallKey::new(contract_address, encoded_call, &block);
+        match self.prefetched.get(&key) {
+            Some(result) => Ok(Some(result)),
+            None => self.store.get_call(contract_address, encoded_call, block),
+        }
+    }
+
+    fn get_calls_in_block(&self, block: BlockPtr) -> Result<Vec<CachedEthereumCall>, Error> {
+        self.store.get_calls_in_block(block)
+    }
+
+    fn set_call(
+        &self,
+        contract_address: Address,
+        encoded_call: &[u8],
+        block: BlockPtr,
+        return_value: &[u8],
+    ) -> Result<(), Error> {
+        self.store
+            .set_call(contract_address, encoded_call, block, return_value)
+    }
+}
+
+/// What a call that is in flight tells the callers waiting for it. Only
+/// results and reverts are passed on; callers make the call themselves
+/// when it failed in some other way
+type Outcome = Result<Vec<u8>, Option<String>>;
+
+/// Makes sure that identical `eth_call`s that are made at the same time,
+/// for example by several deployments handling the same event, are sent to
+/// the provider only once. The first caller makes the call, and everybody
+/// else waits for its result. A chain uses one of these for all providers
+/// and deployments on its network
+#[derive(Default)]
+pub struct InFlightCalls {
+    waiting: Mutex<HashMap<CallKey, Vec<oneshot::Sender<Outcome>>>>,
+}
+
+/// Removes a call from the `InFlightCalls` when the caller that makes it is
+/// done, or drops the call. Dropping the senders wakes up the waiting
+/// callers, who then make the call themselves
+struct InFlight<'a> {
+    calls: &'a InFlightCalls,
+    /// `None` once the call has finished
+    key: Option<CallKey>,
+}
+
+impl InFlight<'_> {
+    fn remove(&mut self) -> Vec<oneshot::Sender<Outcome>> {
+        match self.key.take() {
+            Some(key) => self
+                .calls
+                .waiting
+                .lock()
+                .unwrap()
+                .remove(&key)
+                .unwrap_or_default(),
+            None => vec![],
+        }
+    }
+
+    fn finish(mut self, outcome: Outcome) {
+        for sender in self.remove() {
+            sender.send(outcome.clone()).ok();
+        }
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+impl InFlightCalls {
+    /// Make the call identified by `key` with `call`, unless an identical
+    /// call is already in flight, in which case its result is used
+    pub(crate) async fn call<F, Fut>(
+        &self,
+        key: CallKey,
+        call: F,
+    ) -> Result<Vec<u8>, EthereumContractCallError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Vec<u8>, EthereumContractCallError>>,
+    {
+        let receiver = {
+            let mut waiting = self.waiting.lock().unwrap();
+            match waiting.get_mut(&key) {
+                Some(senders) => {
+                    let (sender, receiver) = oneshot::channel();
+                    senders.push(sender);
+                    Some(receiver)
+                }
+                None => {
+                    waiting.insert(key.clone(), vec![]);
+                    None
+                }
+            }
+        };
+
+        match receiver {
+            Some(receiver) => match receiver.await {
+                Ok(Ok(result)) => Ok(result),
+                Ok(Err(Some(reason))) => Err(EthereumContractCallError::Revert(reason)),
+                Ok(Err(None)) | Err(oneshot::Canceled) => call().await,
+            },
+            None => {
+                let in_flight = InFlight {
+                    calls: self,
+                    key: Some(key),
+                };
+                let result = call().await;
+                in_flight.finish(match &result {
+                    Ok(result) => Ok(result.clone()),
+                    Err(EthereumContractCallError::Revert(reason)) => Err(Some(reason.clone())),
+                    Err(_) => Err(None),
+                });
+                result
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::prelude::futures03;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn key() -> CallKey {
+        CallKey {
+            address: Address::zero(),
+            data: vec![1, 2, 3],
+            block_hash: H256::zero(),
+        }
+    }
+
+    #[test]
+    fn identical_calls_are_made_once() {
+        let calls = InFlightCalls::default();
+        let made = AtomicUsize::new(0);
+        let (release, released) = oneshot::channel::<()>();
+
+        // `join!` polls in order: the first call is in flight when the
+        // second one is made, and finishes only after that
+        let first = calls.call(key(), || async {
+            made.fetch_add(1, Ordering::SeqCst);
+            released.await.ok();
+            Ok(vec![42])
+        });
+        let second = calls.call(key(), || async {
+            made.fetch_add(1, Ordering::SeqCst);
+            Ok(vec![0])
+        });
+        let release = async {
+            release.send(()).ok();
+        };
+        let (first, second, ()) =
+            futures03::executor::block_on(async { futures03::join!(first, second, release) });
+
+        assert_eq!(vec![42], first.unwrap());
+        assert_eq!(vec![42], second.unwrap());
+        assert_eq!(1, made.load(Ordering::SeqCst));
+        assert!(calls.waiting.lock().unwrap().is_empty());
+    }
+}
@@ -1,5 +1,6 @@
 use anyhow::Error;
 use graph::impl_slog_value;
+use graph::prelude::BlockNumber;
 use std::fmt;
 use std::str::FromStr;
 use std::{
@@ -70,6 +71,35 @@ impl fmt::Display for NodeCapabilities {
 
 impl_slog_value!(NodeCapabilities, "{}");
 
+/// What sending a few requests to a provider revealed about it. Fields are
+/// `None` when the outcome of a probe was unclear, e.g., because the
+/// request timed out
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ProbedCapabilities {
+    /// Whether the provider has the state for old blocks
+    pub archive: Option<bool>,
+    /// For providers that are not archive nodes, how many blocks behind
+    /// the chain head they still have the state for, at least
+    pub state_depth: Option<BlockNumber>,
+    /// Whether the provider supports `trace_filter`
+    pub traces: Option<bool>,
+    /// The largest block range that the provider answered `eth_getLogs`
+    /// for
+    pub max_logs_range: Option<BlockNumber>,
+}
+
+impl ProbedCapabilities {
+    /// The capabilities of a provider that was configured with
+    /// `configured`. What probing found out takes precedence over the
+    /// configuration
+    pub fn apply(&self, configured: NodeCapabilities) -> NodeCapabilities {
+        NodeCapabilities {
+            archive: self.archive.unwrap_or(configured.archive),
+            traces: self.traces.unwrap_or(configured.traces),
+        }
+    }
+}
+
 impl graph::blockchain::NodeCapabilities<crate::Chain> for NodeCapabilities {
     fn from_data_sources(data_sources: &[DataSource]) -> Self {
         NodeCapabilities {
@@ -1,9 +1,10 @@
-use anyhow::{Context, Error};
+use anyhow::{anyhow, Context, Error};
 use graph::blockchain::BlockchainKind;
 use graph::data::subgraph::UnifiedMappingApiVersion;
 use graph::firehose::{FirehoseEndpoint, FirehoseEndpoints, ForkStep};
 use graph::prelude::{EthereumBlock, EthereumCallCache, LightEthereumBlock, LightEthereumBlockExt};
-use graph::slog::debug;
+use graph::prometheus::labels;
+use graph::slog::{debug, info, warn};
 use graph::{
     blockchain::{
         block_stream::{
@@ -12,19 +13,21 @@ use graph::{
         },
         firehose_block_stream::FirehoseBlockStream,
         polling_block_stream::PollingBlockStream,
-        Block, BlockPtr, Blockchain, ChainHeadUpdateListener, IngestorError, TriggerFilter as _,
+        Block, BlockHash, BlockPtr, Blockchain, ChainHeadUpdateListener, IngestorError,
+        TriggerFilter as _,
     },
     cheap_clone::CheapClone,
     components::store::DeploymentLocator,
     firehose,
     prelude::{
         async_trait, o, serde_json as json, BlockNumber, ChainStore, EthereumBlockWithCalls,
-        Future01CompatExt, Logger, LoggerFactory, MetricsRegistry, NodeId,
+        Future01CompatExt, Gauge, Logger, LoggerFactory, MetricsRegistry, NodeId, SubgraphStore,
     },
 };
 use prost::Message;
 use std::collections::HashSet;
 use std::iter::FromIterator;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use crate::data_source::DataSourceTemplate;
@@ -32,15 +35,17 @@ use crate::data_source::UnresolvedDataSourceTemplate;
 use crate::RuntimeAdapter;
 use crate::{
     adapter::EthereumAdapter as _,
+    call_cache::{InFlightCalls, PrefetchedCalls, PrefetchingCallCache, SharedCallCache},
     codec,
     data_source::{DataSource, UnresolvedDataSource},
     ethereum_adapter::{
-        blocks_with_triggers, get_calls, parse_block_triggers, parse_call_triggers,
-        parse_log_triggers,
+        blocks_with_triggers, entity_changes_in_block_range, entity_triggers, get_calls,
+        parse_block_triggers, parse_call_triggers, parse_log_triggers,
     },
+    prefetch::prefetch_blocks,
     SubgraphEthRpcMetrics, TriggerFilter, ENV_VARS,
 };
-use crate::{network::EthereumNetworkAdapters, EthereumAdapter};
+use crate::{ingestor::HeadQuorum, network::EthereumNetworkAdapters, EthereumAdapter};
 use graph::blockchain::block_stream::{BlockStream, FirehoseCursor};
 
 /// Celo Mainnet: 42220, Testnet Alfajores: 44787, Testnet Baklava: 62320
@@ -55,9 +60,23 @@ pub struct Chain {
     eth_adapters: Arc<EthereumNetworkAdapters>,
     chain_store: Arc<dyn ChainStore>,
     call_cache: Arc<dyn EthereumCallCache>,
+    /// The store of the subgraphs whose entity changes data sources of
+    /// kind `subgraph` handle
+    subgraph_store: Arc<dyn SubgraphStore>,
+    /// Results of declared calls that were made ahead of the handlers.
+    /// `call_cache` answers calls with them
+    prefetched_calls: Arc<PrefetchedCalls>,
+    /// The `eth_call`s on this chain that are in flight, so that identical
+    /// calls from different deployments are only made once
+    in_flight_calls: Arc<InFlightCalls>,
     chain_head_update_listener: Arc<dyn ChainHeadUpdateListener>,
     reorg_threshold: BlockNumber,
     pub is_ingestible: bool,
+    /// Whether block streams use RPC providers because all Firehose
+    /// endpoints are down
+    firehose_fallback: AtomicBool,
+    firehose_fallback_gauge: Gauge,
+    head_disagreement_gauge: Gauge,
 }
 
 impl std::fmt::Debug for Chain {
@@ -75,12 +94,32 @@ impl Chain {
         registry: Arc<dyn MetricsRegistry>,
         chain_store: Arc<dyn ChainStore>,
         call_cache: Arc<dyn EthereumCallCache>,
+        subgraph_store: Arc<dyn SubgraphStore>,
         firehose_endpoints: FirehoseEndpoints,
         eth_adapters: EthereumNetworkAdapters,
         chain_head_update_listener: Arc<dyn ChainHeadUpdateListener>,
         reorg_threshold: BlockNumber,
         is_ingestible: bool,
     ) -> Self {
+        let firehose_fallback_gauge = registry
+            .global_gauge(
+                "ethereum_firehose_fallback",
+                "Whether block streams for a chain use RPC providers instead of Firehose because all Firehose endpoints are down (1 == RPC)",
+                labels! { String::from("network") => name.clone() },
+            )
+            .expect("failed to create `ethereum_firehose_fallback` gauge");
+        let head_disagreement_gauge = registry
+            .global_gauge(
+                "ethereum_chain_head_disagreement",
+                "Whether the block ingestor does not advance the chain head because fewer than GRAPH_ETHEREUM_HEAD_QUORUM providers agree on it (1 == disagreement)",
+                labels! { String::from("network") => name.clone() },
+            )
+            .expect("failed to create `ethereum_chain_head_disagreement` gauge");
+        let prefetched_calls = Arc::new(PrefetchedCalls::default());
+        let call_cache = PrefetchingCallCache::new(
+            prefetched_calls.cheap_clone(),
+            SharedCallCache::new(call_cache, ENV_VARS.call_cache_size),
+        );
         Chain {
             logger_factory,
             name,
@@ -90,9 +129,15 @@ impl Chain {
             eth_adapters: Arc::new(eth_adapters),
             chain_store,
             call_cache,
+            subgraph_store,
+            prefetched_calls,
+            in_flight_calls: Arc::new(InFlightCalls::default()),
             chain_head_update_listener,
             reorg_threshold,
             is_ingestible,
+            firehose_fallback: AtomicBool::new(false),
+            firehose_fallback_gauge,
+            head_disagreement_gauge,
         }
     }
 
@@ -104,6 +149,26 @@ impl Chain {
     pub fn cheapest_adapter(&self) -> Arc<EthereumAdapter> {
         self.eth_adapters.cheapest().unwrap().clone()
     }
+
+    /// The providers for this chain; providers can be added and removed
+    /// while the node is running
+    pub fn eth_adapters(&self) -> Arc<EthereumNetworkAdapters> {
+        self.eth_adapters.cheap_clone()
+    }
+
+    /// The check of new chain heads against the other providers for this
+    /// chain, or `None` if `GRAPH_ETHEREUM_HEAD_QUORUM` does not ask for
+    /// one
+    pub fn head_quorum(&self) -> Option<HeadQuorum> {
+        if ENV_VARS.head_quorum <= 1 {
+            return None;
+        }
+        Some(HeadQuorum::new(
+            ENV_VARS.head_quorum,
+            self.eth_adapters.cheap_clone(),
+            self.head_disagreement_gauge.clone(),
+        ))
+    }
 }
 
 #[async_trait]
@@ -164,6 +229,9 @@ impl Blockchain for Chain {
             ethrpc_metrics,
             eth_adapter,
             chain_store: self.chain_store.cheap_clone(),
+            call_cache: self.call_cache.cheap_clone(),
+            subgraph_store: self.subgraph_store.cheap_clone(),
+            prefetched_calls: self.prefetched_calls.cheap_clone(),
             unified_api_version,
         };
         Ok(Arc::new(adapter))
@@ -178,6 +246,21 @@ impl Blockchain for Chain {
         filter: Arc<Self::TriggerFilter>,
         unified_api_version: UnifiedMappingApiVersion,
     ) -> Result<Box<dyn BlockStream<Self>>, Error> {
+        // Firehose streams can not wait for source subgraphs to process a
+        // block before sending it; subgraphs that use the entity changes
+        // of other subgraphs always poll
+        if !filter.entity.is_empty() {
+            return self
+                .new_polling_block_stream(
+                    deployment,
+                    start_blocks,
+                    subgraph_current_block,
+                    filter,
+                    unified_api_version,
+                )
+                .await;
+        }
+
         let requirements = filter.node_capabilities();
         let adapter = self
             .triggers_adapter(&deployment, &requirements, unified_api_version)
@@ -186,7 +269,7 @@ impl Blockchain for Chain {
                 self.name, requirements
             ));
 
-        let firehose_endpoint = match self.firehose_endpoints.random() {
+        let firehose_endpoint = match self.firehose_endpoints.endpoint() {
             Some(e) => e.clone(),
             None => return Err(anyhow::format_err!("no firehose endpoint available",)),
         };
@@ -197,11 +280,11 @@ impl Blockchain for Chain {
             .new(o!("component" => "FirehoseBlockStream"));
 
         let firehose_mapper = Arc::new(FirehoseMapper {
-            endpoint: firehose_endpoint.cheap_clone(),
+            endpoint: firehose_endpoint,
         });
 
         Ok(Box::new(FirehoseBlockStream::new(
-            firehose_endpoint,
+            self.firehose_endpoints.cheap_clone(),
             subgraph_current_block,
             block_cursor,
             firehose_mapper,
@@ -284,15 +367,69 @@ impl Blockchain for Chain {
             .await
     }
 
+    async fn block_pointer_from_hash(
+        &self,
+        logger: &Logger,
+        hash: BlockHash,
+    ) -> Result<Option<BlockPtr>, IngestorError> {
+        let eth_adapter = self
+            .eth_adapters
+            .cheapest()
+            .with_context(|| format!("no adapter for chain {}", self.name))?
+            .clone();
+        let block = match eth_adapter
+            .block_by_hash(logger, hash.hash_as_h256())
+            .compat()
+            .await?
+        {
+            Some(block) => BlockFinality::Final(Arc::new(block)),
+            None => return Ok(None),
+        };
+        self.chain_store
+            .upsert_light_blocks(&[&block as &dyn Block])?;
+        Ok(Some(block.ptr()))
+    }
+
     fn runtime_adapter(&self) -> Arc<Self::RuntimeAdapter> {
         Arc::new(RuntimeAdapter {
             eth_adapters: self.eth_adapters.cheap_clone(),
             call_cache: self.call_cache.cheap_clone(),
+            prefetched_calls: self.prefetched_calls.cheap_clone(),
+            in_flight_calls: self.in_flight_calls.cheap_clone(),
         })
     }
 
     fn is_firehose_supported(&self) -> bool {
-        ENV_VARS.is_firehose_preferred && self.firehose_endpoints.len() > 0
+        if !ENV_VARS.is_firehose_preferred || self.firehose_endpoints.len() == 0 {
+            return false;
+        }
+
+        // While all Firehose endpoints are down, fall back to polling RPC
+        // providers if there are any, and use Firehose again once one of
+        // its endpoints recovers. Subgraph runners check this regularly
+        // and restart their block stream when it changes
+        let fallback = self.firehose_endpoints.is_down() && self.eth_adapters.cheapest().is_some();
+        if self.firehose_fallback.swap(fallback, Ordering::SeqCst) != fallback {
+            let logger = self
+                .logger_factory
+                .component_logger("EthereumChain", None)
+                .new(o!("network" => self.name.clone()));
+            if fallback {
+                warn!(
+                    logger,
+                    "All Firehose endpoints are down, using RPC providers for block streams"
+                );
+            } else {
+                info!(
+                    logger,
+                    "A Firehose endpoint recovered, using Firehose for block streams"
+                );
+            }
+        }
+        self.firehose_fallback_gauge
+            .set(if fallback { 1.0 } else { 0.0 });
+
+        !fallback
     }
 }
 
@@ -341,6 +478,10 @@ impl Block for BlockFinality {
         }
     }
 
+    fn timestamp(&self) -> Option<u64> {
+        Some(self.light_block().timestamp.low_u64())
+    }
+
     fn data(&self) -> Result<json::Value, json::Error> {
         // The serialization here very delicately depends on how the
         // `ChainStore`'s `blocks` and `ancestor_block` return the data we
@@ -380,10 +521,97 @@ pub struct TriggersAdapter {
     logger: Logger,
     ethrpc_metrics: Arc<SubgraphEthRpcMetrics>,
     chain_store: Arc<dyn ChainStore>,
+    call_cache: Arc<dyn EthereumCallCache>,
+    subgraph_store: Arc<dyn SubgraphStore>,
+    prefetched_calls: Arc<PrefetchedCalls>,
     eth_adapter: Arc<EthereumAdapter>,
     unified_api_version: UnifiedMappingApiVersion,
 }
 
+impl TriggersAdapter {
+    /// Make the calls that handlers declare for the triggers in `blocks` in
+    /// the background, so that their results are in memory by the time the
+    /// handlers run. Blocks are prefetched in order since that is the order
+    /// in which they are processed. Prefetching never fails the scan
+    fn prefetch_declared_calls(&self, filter: &TriggerFilter, blocks: &[BlockWithTriggers<Chain>]) {
+        if filter.declared_calls.is_empty() {
+            return;
+        }
+
+        let blocks: Vec<_> = blocks
+            .iter()
+            .filter(|block| !block.trigger_data.is_empty())
+            .map(|block| (block.ptr(), block.trigger_data.clone()))
+            .collect();
+        if blocks.is_empty() {
+            return;
+        }
+
+        let logger = self.logger.cheap_clone();
+        let eth_adapter = self.eth_adapter.cheap_clone();
+        let call_cache = self.call_cache.cheap_clone();
+        let prefetched_calls = self.prefetched_calls.cheap_clone();
+        let data_sources = filter.declared_calls.clone();
+        graph::spawn(async move {
+            prefetch_blocks(
+                &logger,
+                eth_adapter.as_ref(),
+                call_cache,
+                &prefetched_calls,
+                &data_sources,
+                blocks,
+            )
+            .await
+        });
+    }
+
+    /// Check that every source subgraph in `filter` has processed `block`,
+    /// and that it did so on the same fork of the chain, so that the
+    /// entity changes we loaded for `block` are the right ones
+    async fn check_sources_processed(
+        &self,
+        filter: &TriggerFilter,
+        block: &BlockPtr,
+    ) -> Result<(), Error> {
+        for source in filter.entity.sources.keys() {
+            let source_ptr = self
+                .subgraph_store
+                .least_block_ptr(source)
+                .await?
+                .filter(|ptr| ptr.number >= block.number)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "source subgraph {} has not processed block {} yet",
+                        source,
+                        block
+                    )
+                })?;
+            let on_same_fork = if source_ptr.number == block.number {
+                &source_ptr == block
+            } else {
+                let offset = source_ptr.number - block.number;
+                let ancestor: Option<EthereumBlock> = self
+                    .chain_store
+                    .cheap_clone()
+                    .ancestor_block(source_ptr.clone(), offset)
+                    .await?
+                    .map(json::from_value)
+                    .transpose()?;
+                ancestor.map_or(false, |ancestor| &BlockPtr::from(&ancestor) == block)
+            };
+            if !on_same_fork {
+                return Err(anyhow!(
+                    "source subgraph {} is at block {}, which is not on the same chain as block {}",
+                    source,
+                    source_ptr,
+                    block
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
 #[async_trait]
 impl TriggersAdapterTrait<Chain> for TriggersAdapter {
     async fn scan_triggers(
@@ -392,17 +620,20 @@ impl TriggersAdapterTrait<Chain> for TriggersAdapter {
         to: BlockNumber,
         filter: &TriggerFilter,
     ) -> Result<Vec<BlockWithTriggers<Chain>>, Error> {
-        blocks_with_triggers(
+        let blocks = blocks_with_triggers(
             self.eth_adapter.clone(),
             self.logger.clone(),
             self.chain_store.clone(),
+            self.subgraph_store.clone(),
             self.ethrpc_metrics.clone(),
             from,
             to,
             filter,
             self.unified_api_version.clone(),
         )
-        .await
+        .await?;
+        self.prefetch_declared_calls(filter, &blocks);
+        Ok(blocks)
     }
 
     async fn triggers_in_block(
@@ -427,6 +658,7 @@ impl TriggersAdapterTrait<Chain> for TriggersAdapter {
                     self.eth_adapter.clone(),
                     logger.clone(),
                     self.chain_store.clone(),
+                    self.subgraph_store.clone(),
                     self.ethrpc_metrics.clone(),
                     block_number,
                     block_number,
@@ -445,6 +677,22 @@ impl TriggersAdapterTrait<Chain> for TriggersAdapter {
                 ));
                 triggers.append(&mut parse_call_triggers(&filter.call, &full_block)?);
                 triggers.append(&mut parse_block_triggers(&filter.block, &full_block));
+                if !filter.entity.is_empty() {
+                    let ptr = block.ptr();
+                    let changes = entity_changes_in_block_range(
+                        self.subgraph_store.clone(),
+                        filter.entity.clone(),
+                        ptr.number,
+                        ptr.number,
+                    )
+                    .await?;
+                    // The sources might have reverted the block while we
+                    // loaded their changes; only check afterwards
+                    self.check_sources_processed(filter, &ptr).await?;
+                    for (_, changes) in changes {
+                        triggers.append(&mut entity_triggers(ptr.clone(), changes));
+                    }
+                }
                 Ok(BlockWithTriggers::new(block, triggers))
             }
         }
@@ -494,6 +742,24 @@ impl TriggersAdapterTrait<Chain> for TriggersAdapter {
 
         Ok(blocks[0].parent_ptr())
     }
+
+    async fn triggers_known_up_to(
+        &self,
+        filter: &TriggerFilter,
+    ) -> Result<Option<BlockNumber>, Error> {
+        let mut known_up_to = None;
+        for source in filter.entity.sources.keys() {
+            // A source that has not processed any block yet has no entity
+            // changes for any block
+            let number = self
+                .subgraph_store
+                .least_block_ptr(source)
+                .await?
+                .map_or(-1, |ptr| ptr.number);
+            known_up_to = Some(known_up_to.map_or(number, |known: BlockNumber| known.min(number)));
+        }
+        Ok(known_up_to)
+    }
 }
 
 pub struct FirehoseMapper {
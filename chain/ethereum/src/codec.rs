@@ -354,6 +354,10 @@ impl BlockchainBlock for Block {
     fn parent_ptr(&self) -> Option<BlockPtr> {
         self.parent_ptr()
     }
+
+    fn timestamp(&self) -> Option<u64> {
+        self.header().timestamp.as_ref().map(|ts| ts.seconds as u64)
+    }
 }
 
 impl HeaderOnlyBlock {
@@ -380,4 +384,8 @@ impl BlockchainBlock for HeaderOnlyBlock {
     fn parent_ptr(&self) -> Option<BlockPtr> {
         self.header().parent_ptr()
     }
+
+    fn timestamp(&self) -> Option<u64> {
+        self.header().timestamp.as_ref().map(|ts| ts.seconds as u64)
+    }
 }
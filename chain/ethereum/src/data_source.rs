@@ -1,7 +1,7 @@
 use anyhow::{anyhow, Error};
 use anyhow::{ensure, Context};
 use graph::blockchain::TriggerWithHandler;
-use graph::components::store::StoredDynamicDataSource;
+use graph::components::store::{EntityType, StoredDynamicDataSource};
 use graph::prelude::ethabi::ethereum_types::H160;
 use graph::prelude::ethabi::StateMutability;
 use graph::prelude::futures03::future::try_join;
@@ -14,13 +14,13 @@ use std::{convert::TryFrom, sync::Arc};
 use tiny_keccak::{keccak256, Keccak};
 
 use graph::{
-    blockchain::{self, Blockchain},
+    blockchain::{self, Blockchain, SUBGRAPH_DATA_SOURCE_KIND},
     prelude::{
         async_trait,
-        ethabi::{Address, Contract, Event, Function, LogParam, ParamType, RawLog},
+        ethabi::{Address, Contract, Event, Function, LogParam, ParamType, RawLog, Token},
         info, serde_json, warn,
         web3::types::{Log, Transaction, H256},
-        BlockNumber, CheapClone, DataSourceTemplateInfo, Deserialize, EthereumCall,
+        BlockNumber, CheapClone, DataSourceTemplateInfo, DeploymentHash, Deserialize, EthereumCall,
         LightEthereumBlock, LightEthereumBlockExt, LinkResolver, Logger, TryStreamExt,
     },
 };
@@ -87,6 +87,10 @@ impl blockchain::DataSource<Chain> for DataSource {
         self.creation_block
     }
 
+    fn source_subgraph(&self) -> Option<&DeploymentHash> {
+        self.source.subgraph.as_ref()
+    }
+
     fn is_duplicate_of(&self, other: &Self) -> bool {
         let DataSource {
             kind,
@@ -113,6 +117,7 @@ impl blockchain::DataSource<Chain> for DataSource {
             && mapping.event_handlers == other.mapping.event_handlers
             && mapping.call_handlers == other.mapping.call_handlers
             && mapping.block_handlers == other.mapping.block_handlers
+            && mapping.entity_handlers == other.mapping.entity_handlers
             && context == &other.context
     }
 
@@ -163,6 +168,11 @@ impl blockchain::DataSource<Chain> for DataSource {
     fn validate(&self) -> Vec<Error> {
         let mut errors = vec![];
 
+        if self.kind == SUBGRAPH_DATA_SOURCE_KIND {
+            errors.extend(self.validate_subgraph_source());
+            return errors;
+        }
+
         if !ETHEREUM_KINDS.contains(&self.kind.as_str()) {
             errors.push(anyhow!(
                 "data source has invalid `kind`, expected `ethereum` but found {}",
@@ -170,6 +180,13 @@ impl blockchain::DataSource<Chain> for DataSource {
             ))
         }
 
+        if self.source.subgraph.is_some() || !self.mapping.entity_handlers.is_empty() {
+            errors.push(anyhow!(
+                "only data sources of kind `{}` can have a source subgraph and entity handlers",
+                SUBGRAPH_DATA_SOURCE_KIND
+            ));
+        }
+
         // Validate that there is a `source` address if there are call or block handlers
         let no_source_address = self.address().is_none();
         let has_call_handlers = !self.mapping.call_handlers.is_empty();
@@ -212,6 +229,42 @@ impl blockchain::DataSource<Chain> for DataSource {
             }
         }
 
+        // Validate that declared calls name a function in one of the ABIs and
+        // only use parameters of the event
+        for handler in &self.mapping.event_handlers {
+            for call in &handler.calls {
+                if self.declared_function(call).is_none() {
+                    errors.push(anyhow!(
+                        "declared call `{}` of handler `{}` does not match a function `{}` with {} \
+                         arguments in the ABI `{}`",
+                        call.label,
+                        handler.handler,
+                        call.function,
+                        call.args.len(),
+                        call.contract
+                    ));
+                }
+                let event = match self.contract_event_with_signature(&handler.event) {
+                    Some(event) => event,
+                    None => continue,
+                };
+                for arg in std::iter::once(&call.address).chain(call.args.iter()) {
+                    if let CallArg::Param(name) = arg {
+                        if !event.inputs.iter().any(|input| &input.name == name) {
+                            errors.push(anyhow!(
+                                "declared call `{}` of handler `{}` uses `{}`, which is not a \
+                                 parameter of the event `{}`",
+                                call.label,
+                                handler.handler,
+                                name,
+                                handler.event
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
         errors
     }
 
@@ -235,9 +288,15 @@ impl DataSource {
     ) -> Result<Self, Error> {
         // Data sources in the manifest are created "before genesis" so they have no creation block.
         let creation_block = None;
-        let contract_abi = mapping
-            .find_abi(&source.abi)
-            .with_context(|| format!("data source `{}`", name))?;
+        // Data sources that handle the entity changes of another subgraph
+        // do not need to have a contract
+        let contract_abi = if kind == SUBGRAPH_DATA_SOURCE_KIND && source.abi.is_empty() {
+            Arc::new(MappingABI::empty())
+        } else {
+            mapping
+                .find_abi(&source.abi)
+                .with_context(|| format!("data source `{}`", name))?
+        };
 
         Ok(DataSource {
             kind,
@@ -251,6 +310,222 @@ impl DataSource {
         })
     }
 
+    /// Validate a data source of kind `subgraph`: it must name the source
+    /// subgraph and only have entity handlers
+    fn validate_subgraph_source(&self) -> Vec<Error> {
+        let mut errors = vec![];
+
+        if self.source.subgraph.is_none() {
+            errors.push(anyhow!(
+                "data sources of kind `{}` must have a `source.subgraph`",
+                SUBGRAPH_DATA_SOURCE_KIND
+            ));
+        }
+        if self.source.address.is_some() {
+            errors.push(anyhow!(
+                "data sources of kind `{}` can not have a `source.address`",
+                SUBGRAPH_DATA_SOURCE_KIND
+            ));
+        }
+        if self.mapping.entity_handlers.is_empty() {
+            errors.push(anyhow!(
+                "data sources of kind `{}` must have at least one entity handler",
+                SUBGRAPH_DATA_SOURCE_KIND
+            ));
+        }
+        if !self.mapping.event_handlers.is_empty()
+            || !self.mapping.call_handlers.is_empty()
+            || !self.mapping.block_handlers.is_empty()
+        {
+            errors.push(anyhow!(
+                "data sources of kind `{}` can only have entity handlers",
+                SUBGRAPH_DATA_SOURCE_KIND
+            ));
+        }
+
+        errors
+    }
+
+    /// The entity types of the source subgraph that this data source has
+    /// handlers for
+    pub(crate) fn source_entity_types(&self) -> impl Iterator<Item = EntityType> + '_ {
+        self.mapping
+            .entity_handlers
+            .iter()
+            .map(|handler| EntityType::new(handler.entity.clone()))
+    }
+
+    fn handler_for_entity(&self, entity_type: &EntityType) -> Option<&MappingEntityHandler> {
+        self.mapping
+            .entity_handlers
+            .iter()
+            .find(|handler| handler.entity == entity_type.as_str())
+    }
+
+    /// Finds the event handler for `log` and decodes the event parameters
+    fn match_log(
+        &self,
+        log: &Log,
+        logger: &Logger,
+    ) -> Result<Option<(MappingEventHandler, Vec<LogParam>)>, Error> {
+        let potential_handlers = self.handlers_for_log(log)?;
+
+        // Map event handlers to (event handler, event ABI) pairs; fail if there are
+        // handlers that don't exist in the contract ABI
+        let valid_handlers = potential_handlers
+            .into_iter()
+            .map(|event_handler| {
+                // Identify the event ABI in the contract
+                let event_abi = self
+                    .contract_event_with_signature(event_handler.event.as_str())
+                    .with_context(|| {
+                        anyhow!(
+                            "Event with the signature \"{}\" not found in \
+                                    contract \"{}\" of data source \"{}\"",
+                            event_handler.event,
+                            self.contract_abi.name,
+                            self.name,
+                        )
+                    })?;
+                Ok((event_handler, event_abi))
+            })
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+        // Filter out handlers whose corresponding event ABIs cannot decode the
+        // params (this is common for overloaded events that have the same topic0
+        // but have indexed vs. non-indexed params that are encoded differently).
+        //
+        // Map (handler, event ABI) pairs to (handler, decoded params) pairs.
+        let mut matching_handlers = valid_handlers
+            .into_iter()
+            .filter_map(|(event_handler, event_abi)| {
+                event_abi
+                    .parse_log(RawLog {
+                        topics: log.topics.clone(),
+                        data: log.data.clone().0,
+                    })
+                    .map(|log| log.params)
+                    .map_err(|e| {
+                        trace!(
+                            logger,
+                            "Skipping handler because the event parameters do not \
+                            match the event signature. This is typically the case \
+                            when parameters are indexed in the event but not in the \
+                            signature or the other way around";
+                            "handler" => &event_handler.handler,
+                            "event" => &event_handler.event,
+                            "error" => format!("{}", e),
+                        );
+                    })
+                    .ok()
+                    .map(|params| (event_handler, params))
+            })
+            .collect::<Vec<_>>();
+
+        if matching_handlers.is_empty() {
+            return Ok(None);
+        }
+
+        // Process the event with the matching handler
+        let (event_handler, params) = matching_handlers.pop().unwrap();
+
+        ensure!(
+            matching_handlers.is_empty(),
+            format!(
+                "Multiple handlers defined for event `{}`, only one is supported",
+                &event_handler.event
+            )
+        );
+
+        Ok(Some((event_handler, params)))
+    }
+
+    pub(crate) fn has_declared_calls(&self) -> bool {
+        self.mapping
+            .event_handlers
+            .iter()
+            .any(|handler| !handler.calls.is_empty())
+    }
+
+    /// The calls that the handler for `trigger` declares, with their
+    /// arguments taken from the event. Calls whose arguments do not fit the
+    /// function are left out; the handler finds out about that when it
+    /// makes them
+    pub(crate) fn declared_calls(
+        &self,
+        trigger: &EthereumTrigger,
+        block_number: BlockNumber,
+        logger: &Logger,
+    ) -> Result<Vec<PreparedCall>, Error> {
+        let log = match trigger {
+            EthereumTrigger::Log(log, _) => log,
+            _ => return Ok(vec![]),
+        };
+        if self
+            .mapping
+            .event_handlers
+            .iter()
+            .all(|handler| handler.calls.is_empty())
+            || !self.matches_trigger_address(trigger)
+            || self.source.start_block > block_number
+        {
+            return Ok(vec![]);
+        }
+
+        let (handler, params) = match self.match_log(log, logger)? {
+            Some(matched) => matched,
+            None => return Ok(vec![]),
+        };
+
+        let mut calls = vec![];
+        for call in &handler.calls {
+            let function = match self.declared_function(call) {
+                Some(function) => function,
+                None => continue,
+            };
+            let address = match call.address.token(log, &params) {
+                Some(Token::Address(address)) => address,
+                _ => continue,
+            };
+            let args = match call
+                .args
+                .iter()
+                .map(|arg| arg.token(log, &params))
+                .collect::<Option<Vec<_>>>()
+            {
+                Some(args) => args,
+                None => continue,
+            };
+            if args
+                .iter()
+                .zip(function.inputs.iter())
+                .any(|(token, param)| !token.type_check(&param.kind))
+            {
+                continue;
+            }
+            if let Ok(data) = function.encode_input(&args) {
+                calls.push(PreparedCall {
+                    address,
+                    data,
+                    pure: matches!(function.state_mutability, StateMutability::Pure),
+                });
+            }
+        }
+        Ok(calls)
+    }
+
+    fn declared_function(&self, call: &DeclaredCall) -> Option<&Function> {
+        self.mapping
+            .abis
+            .iter()
+            .find(|abi| abi.name == call.contract)?
+            .contract
+            .functions_by_name(&call.function)
+            .ok()?
+            .iter()
+            .find(|function| function.inputs.len() == call.args.len())
+    }
+
     fn handlers_for_log(&self, log: &Log) -> Result<Vec<MappingEventHandler>, Error> {
         // Get signature from the log
         let topic0 = log.topics.get(0).context("Ethereum event has no topics")?;
@@ -451,6 +726,9 @@ impl DataSource {
 
             // Unfiltered block triggers match any data source address.
             EthereumTrigger::Block(_, EthereumBlockTriggerType::Every) => return true,
+
+            // Entity triggers are matched by their source subgraph instead
+            EthereumTrigger::Entity(_) => return true,
         };
 
         ds_address == *trigger_address
@@ -472,6 +750,12 @@ impl DataSource {
             return Ok(None);
         }
 
+        // Data sources that are fed by another subgraph only handle the
+        // entity changes of that subgraph, and no other data source does
+        if self.source.subgraph.is_some() != matches!(trigger, EthereumTrigger::Entity(_)) {
+            return Ok(None);
+        }
+
         match trigger {
             EthereumTrigger::Block(_, trigger_type) => {
                 let handler = match self.handler_for_block(trigger_type) {
@@ -486,74 +770,10 @@ impl DataSource {
                 )))
             }
             EthereumTrigger::Log(log, receipt) => {
-                let potential_handlers = self.handlers_for_log(log)?;
-
-                // Map event handlers to (event handler, event ABI) pairs; fail if there are
-                // handlers that don't exist in the contract ABI
-                let valid_handlers = potential_handlers
-                    .into_iter()
-                    .map(|event_handler| {
-                        // Identify the event ABI in the contract
-                        let event_abi = self
-                            .contract_event_with_signature(event_handler.event.as_str())
-                            .with_context(|| {
-                                anyhow!(
-                                    "Event with the signature \"{}\" not found in \
-                                            contract \"{}\" of data source \"{}\"",
-                                    event_handler.event,
-                                    self.contract_abi.name,
-                                    self.name,
-                                )
-                            })?;
-                        Ok((event_handler, event_abi))
-                    })
-                    .collect::<Result<Vec<_>, anyhow::Error>>()?;
-
-                // Filter out handlers whose corresponding event ABIs cannot decode the
-                // params (this is common for overloaded events that have the same topic0
-                // but have indexed vs. non-indexed params that are encoded differently).
-                //
-                // Map (handler, event ABI) pairs to (handler, decoded params) pairs.
-                let mut matching_handlers = valid_handlers
-                    .into_iter()
-                    .filter_map(|(event_handler, event_abi)| {
-                        event_abi
-                            .parse_log(RawLog {
-                                topics: log.topics.clone(),
-                                data: log.data.clone().0,
-                            })
-                            .map(|log| log.params)
-                            .map_err(|e| {
-                                trace!(
-                                    logger,
-                                    "Skipping handler because the event parameters do not \
-                                    match the event signature. This is typically the case \
-                                    when parameters are indexed in the event but not in the \
-                                    signature or the other way around";
-                                    "handler" => &event_handler.handler,
-                                    "event" => &event_handler.event,
-                                    "error" => format!("{}", e),
-                                );
-                            })
-                            .ok()
-                            .map(|params| (event_handler, params))
-                    })
-                    .collect::<Vec<_>>();
-
-                if matching_handlers.is_empty() {
-                    return Ok(None);
-                }
-
-                // Process the event with the matching handler
-                let (event_handler, params) = matching_handlers.pop().unwrap();
-
-                ensure!(
-                    matching_handlers.is_empty(),
-                    format!(
-                        "Multiple handlers defined for event `{}`, only one is supported",
-                        &event_handler.event
-                    )
-                );
+                let (event_handler, params) = match self.match_log(log, logger)? {
+                    Some(matched) => matched,
+                    None => return Ok(None),
+                };
 
                 // Special case: In Celo, there are Epoch Rewards events, which do not have an
                 // associated transaction and instead have `transaction_hash == block.hash`,
@@ -700,6 +920,28 @@ impl DataSource {
                     logging_extras,
                 )))
             }
+            EthereumTrigger::Entity(trigger) => {
+                if self.source.subgraph.as_ref() != Some(&trigger.source) {
+                    return Ok(None);
+                }
+                let handler = match self.handler_for_entity(&trigger.key().entity_type) {
+                    Some(handler) => handler,
+                    None => return Ok(None),
+                };
+                let logging_extras = Arc::new(o! {
+                    "entity_type" => trigger.key().entity_type.to_string(),
+                    "id" => trigger.key().entity_id.clone(),
+                    "source" => trigger.source.to_string(),
+                });
+                Ok(Some(TriggerWithHandler::new_with_logging_extras(
+                    MappingTrigger::Entity {
+                        block: block.cheap_clone(),
+                        trigger: trigger.cheap_clone(),
+                    },
+                    handler.handler.clone(),
+                    logging_extras,
+                )))
+            }
         }
     }
 }
@@ -778,6 +1020,7 @@ impl TryFrom<DataSourceTemplateInfo<Chain>> for DataSource {
             name: template.name,
             source: Source {
                 address: Some(address),
+                subgraph: None,
                 abi: template.source.abi,
                 start_block: 0,
             },
@@ -849,6 +1092,7 @@ pub struct UnresolvedMapping {
     pub api_version: String,
     pub language: String,
     pub entities: Vec<String>,
+    #[serde(default)]
     pub abis: Vec<UnresolvedMappingABI>,
     #[serde(default)]
     pub block_handlers: Vec<MappingBlockHandler>,
@@ -856,6 +1100,8 @@ pub struct UnresolvedMapping {
     pub call_handlers: Vec<MappingCallHandler>,
     #[serde(default)]
     pub event_handlers: Vec<MappingEventHandler>,
+    #[serde(default)]
+    pub entity_handlers: Vec<MappingEntityHandler>,
     pub file: Link,
 }
 
@@ -869,6 +1115,7 @@ pub struct Mapping {
     pub block_handlers: Vec<MappingBlockHandler>,
     pub call_handlers: Vec<MappingCallHandler>,
     pub event_handlers: Vec<MappingEventHandler>,
+    pub entity_handlers: Vec<MappingEntityHandler>,
     pub runtime: Arc<Vec<u8>>,
     pub link: Link,
 }
@@ -913,6 +1160,7 @@ impl UnresolvedMapping {
             block_handlers,
             call_handlers,
             event_handlers,
+            entity_handlers,
             file: link,
         } = self;
 
@@ -946,6 +1194,7 @@ impl UnresolvedMapping {
             block_handlers: block_handlers.clone(),
             call_handlers: call_handlers.clone(),
             event_handlers: event_handlers.clone(),
+            entity_handlers,
             runtime,
             link,
         })
@@ -964,6 +1213,17 @@ pub struct MappingABI {
     pub contract: Contract,
 }
 
+impl MappingABI {
+    /// An ABI without any functions or events, for data sources that do
+    /// not have a contract
+    pub fn empty() -> Self {
+        MappingABI {
+            name: String::new(),
+            contract: Contract::load("[]".as_bytes()).expect("the empty ABI is valid"),
+        }
+    }
+}
+
 impl UnresolvedMappingABI {
     pub async fn resolve(
         self,
@@ -1006,6 +1266,14 @@ pub struct MappingCallHandler {
     pub handler: String,
 }
 
+/// A handler for the changes to entities of type `entity` in the source
+/// subgraph of a data source of kind `subgraph`
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
+pub struct MappingEntityHandler {
+    pub entity: String,
+    pub handler: String,
+}
+
 #[derive(Clone, Debug, Hash, Eq, PartialEq, Deserialize)]
 pub struct MappingEventHandler {
     pub event: String,
@@ -1013,6 +1281,115 @@ pub struct MappingEventHandler {
     pub handler: String,
     #[serde(default)]
     pub receipt: bool,
+    /// Calls that the handler makes, keyed by a label; they are made for
+    /// all events in a block before any handler runs
+    #[serde(default, deserialize_with = "deserialize_declared_calls")]
+    pub calls: Vec<DeclaredCall>,
+}
+
+/// An `ethereum.call` that an event handler declares in the manifest, for
+/// example `ERC20[event.address].balanceOf(event.params.to)`
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct DeclaredCall {
+    pub label: String,
+    /// The name of the ABI of the contract
+    pub contract: String,
+    pub address: CallArg,
+    pub function: String,
+    pub args: Vec<CallArg>,
+}
+
+/// A value from an event that a declared call uses
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub enum CallArg {
+    /// `event.address`
+    Address,
+    /// `event.params.<name>`
+    Param(String),
+}
+
+impl FromStr for CallArg {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s.trim() {
+            "event.address" => Ok(CallArg::Address),
+            s => match s.strip_prefix("event.params.") {
+                Some(name) if !name.is_empty() => Ok(CallArg::Param(name.to_string())),
+                _ => Err(anyhow!(
+                    "`{}` must be `event.address` or `event.params.<name>`",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl CallArg {
+    fn token(&self, log: &Log, params: &[LogParam]) -> Option<Token> {
+        match self {
+            CallArg::Address => Some(Token::Address(log.address)),
+            CallArg::Param(name) => params
+                .iter()
+                .find(|param| &param.name == name)
+                .map(|param| param.value.clone()),
+        }
+    }
+}
+
+/// A declared call with its arguments taken from an event, ready to be made
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub(crate) struct PreparedCall {
+    pub address: Address,
+    pub data: Vec<u8>,
+    /// Whether the function is `pure`, so that its result does not depend
+    /// on who calls it or with how much gas
+    pub pure: bool,
+}
+
+impl DeclaredCall {
+    /// Parse a call of the form `Contract[address].function(arg, ...)`
+    pub fn parse(label: &str, expr: &str) -> Result<Self, Error> {
+        let err = || {
+            anyhow!(
+                "declared call `{}: {}` must have the form `Contract[address].function(args)`",
+                label,
+                expr
+            )
+        };
+
+        let (contract, rest) = expr.trim().split_once('[').ok_or_else(err)?;
+        let (address, rest) = rest.split_once("].").ok_or_else(err)?;
+        let (function, rest) = rest.split_once('(').ok_or_else(err)?;
+        let args = rest.strip_suffix(')').ok_or_else(err)?;
+
+        let args = if args.trim().is_empty() {
+            vec![]
+        } else {
+            args.split(',')
+                .map(CallArg::from_str)
+                .collect::<Result<_, _>>()?
+        };
+        Ok(DeclaredCall {
+            label: label.to_string(),
+            contract: contract.trim().to_string(),
+            address: address.parse()?,
+            function: function.trim().to_string(),
+            args,
+        })
+    }
+}
+
+fn deserialize_declared_calls<'de, D>(deserializer: D) -> Result<Vec<DeclaredCall>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let calls = <BTreeMap<String, String> as serde::Deserialize>::deserialize(deserializer)?;
+    calls
+        .iter()
+        .map(|(label, expr)| DeclaredCall::parse(label, expr))
+        .collect::<Result<_, _>>()
+        .map_err(serde::de::Error::custom)
 }
 
 impl MappingEventHandler {
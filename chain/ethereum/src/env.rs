@@ -17,6 +17,21 @@ pub struct EnvVars {
     ///
     /// Set by the flag `GRAPH_ETHEREUM_IS_FIREHOSE_PREFERRED`. On by default.
     pub is_firehose_preferred: bool,
+    /// Whether to probe providers when connecting to them to find out if
+    /// they are archive nodes, support traces, and how large a block range
+    /// `eth_getLogs` accepts. What probing finds out takes precedence over
+    /// the `features` of the provider in the configuration.
+    ///
+    /// Set by the flag `GRAPH_ETHEREUM_PROBE_PROVIDERS`. On by default.
+    pub probe_providers: bool,
+    /// How many of the providers for a network must agree on the block
+    /// `reorg_threshold` blocks below a new chain head before the block
+    /// ingestor advances the chain head. Values of 0 and 1 turn the check
+    /// off.
+    ///
+    /// Set by the environment variable `GRAPH_ETHEREUM_HEAD_QUORUM`. The
+    /// default value is 0.
+    pub head_quorum: usize,
     /// Additional deterministic errors that have not yet been hardcoded.
     ///
     /// Set by the environment variable `GRAPH_GETH_ETH_CALL_ERRORS`, separated
@@ -42,7 +57,9 @@ pub struct EnvVars {
     /// Set by the environment variable `ETHEREUM_BLOCK_BATCH_SIZE`. The
     /// default value is 10 blocks.
     pub block_batch_size: usize,
-    /// Maximum number of blocks to request in each chunk.
+    /// Maximum number of blocks to request in each chunk, and the initial
+    /// block range of `eth_getLogs` requests to a provider, which then
+    /// adapts to what the provider can handle.
     ///
     /// Set by the environment variable `GRAPH_ETHEREUM_MAX_BLOCK_RANGE_SIZE`.
     /// The default value is 2000 blocks.
@@ -71,6 +88,20 @@ pub struct EnvVars {
     /// by default on macOS (to avoid DNS issues) and disabled by default on all
     /// other systems.
     pub fetch_receipts_in_batches: bool,
+    /// Whether to batch the contract calls to `pure` functions that are
+    /// prefetched for handlers into calls to the Multicall3 contract. When
+    /// this is off, or the contract is not available, prefetched calls are
+    /// made one at a time.
+    ///
+    /// Set by the flag `GRAPH_ETHEREUM_MULTICALL`. Off by default.
+    pub multicall: bool,
+    /// Size limit of the in-memory cache of `eth_call` results that all
+    /// deployments on a chain share, in bytes. 0 turns that cache off, and
+    /// calls are only cached in the database.
+    ///
+    /// Set by the environment variable `GRAPH_ETHEREUM_CALL_CACHE_SIZE`
+    /// (expressed in kilobytes). The default value is 10 megabytes.
+    pub call_cache_size: usize,
     /// `graph_node::config` disallows setting this in a store with multiple
     /// shards. See 8b6ad0c64e244023ac20ced7897fe666 for the reason.
     ///
@@ -102,6 +133,8 @@ impl From<Inner> for EnvVars {
     fn from(x: Inner) -> Self {
         Self {
             is_firehose_preferred: x.is_firehose_preferred.0,
+            probe_providers: x.probe_providers.0,
+            head_quorum: x.head_quorum,
             get_logs_max_contracts: x.get_logs_max_contracts,
             geth_eth_call_errors: x
                 .geth_eth_call_errors
@@ -122,6 +155,8 @@ impl From<Inner> for EnvVars {
                 .fetch_receipts_in_batches
                 .map(|b| b.0)
                 .unwrap_or(cfg!(target_os = "macos")),
+            multicall: x.multicall.0,
+            call_cache_size: x.call_cache_size_in_kb * 1000,
             cleanup_blocks: x.cleanup_blocks.0,
             target_triggers_per_block_range: x.target_triggers_per_block_range,
         }
@@ -138,6 +173,10 @@ impl Default for EnvVars {
 struct Inner {
     #[envconfig(from = "GRAPH_ETHEREUM_IS_FIREHOSE_PREFERRED", default = "true")]
     is_firehose_preferred: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_ETHEREUM_PROBE_PROVIDERS", default = "true")]
+    probe_providers: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_ETHEREUM_HEAD_QUORUM", default = "0")]
+    head_quorum: usize,
     #[envconfig(from = "GRAPH_GETH_ETH_CALL_ERRORS", default = "")]
     geth_eth_call_errors: String,
     #[envconfig(from = "GRAPH_ETH_GET_LOGS_MAX_CONTRACTS", default = "2000")]
@@ -165,6 +204,10 @@ struct Inner {
     block_ingestor_max_concurrent_json_rpc_calls: usize,
     #[envconfig(from = "GRAPH_ETHEREUM_FETCH_TXN_RECEIPTS_IN_BATCHES")]
     fetch_receipts_in_batches: Option<EnvVarBoolean>,
+    #[envconfig(from = "GRAPH_ETHEREUM_MULTICALL", default = "false")]
+    multicall: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_ETHEREUM_CALL_CACHE_SIZE", default = "10000")]
+    call_cache_size_in_kb: usize,
     #[envconfig(from = "GRAPH_ETHEREUM_CLEANUP_BLOCKS", default = "false")]
     cleanup_blocks: EnvVarBoolean,
     #[envconfig(
@@ -3,6 +3,7 @@ use futures::prelude::*;
 use futures03::{future::BoxFuture, stream::FuturesUnordered};
 use graph::blockchain::BlockHash;
 use graph::blockchain::ChainIdentifier;
+use graph::components::store::EntityType;
 use graph::components::transaction_receipt::LightTransactionReceipt;
 use graph::data::subgraph::UnifiedMappingApiVersion;
 use graph::data::subgraph::API_VERSION_0_0_5;
@@ -16,7 +17,7 @@ use graph::{
         anyhow::{self, anyhow, bail, ensure},
         async_trait, debug, error, ethabi,
         futures03::{self, compat::Future01CompatExt, FutureExt, StreamExt, TryStreamExt},
-        hex, info, retry, serde_json as json, stream, tiny_keccak, trace, warn,
+        hex, info, lazy_static, retry, serde_json as json, stream, tiny_keccak, trace, warn,
         web3::{
             self,
             types::{
@@ -24,8 +25,8 @@ use graph::{
                 FilterBuilder, Log, Transaction, TransactionReceipt, H256,
             },
         },
-        BlockNumber, ChainStore, CheapClone, DynTryFuture, Error, EthereumCallCache, Logger,
-        TimeoutError, TryFutureExt,
+        BlockNumber, ChainStore, CheapClone, DeploymentHash, DynTryFuture, EntityOperation, Error,
+        EthereumCallCache, Logger, SubgraphStore, TimeoutError, TryFutureExt,
     },
 };
 use graph::{
@@ -39,19 +40,22 @@ use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::iter::FromIterator;
 use std::pin::Pin;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Instant;
 
+use crate::call_cache::{CallKey, InFlightCalls};
 use crate::chain::BlockFinality;
+use crate::logs_range::LogsRange;
 use crate::{
     adapter::{
         EthGetLogsFilter, EthereumAdapter as EthereumAdapterTrait, EthereumBlockFilter,
-        EthereumCallFilter, EthereumContractCall, EthereumContractCallError, EthereumLogFilter,
-        ProviderEthRpcMetrics, SubgraphEthRpcMetrics,
+        EthereumCallFilter, EthereumContractCall, EthereumContractCallError, EthereumEntityFilter,
+        EthereumLogFilter, ProviderEthRpcMetrics, SubgraphEthRpcMetrics,
     },
     transport::Transport,
-    trigger::{EthereumBlockTriggerType, EthereumTrigger},
-    TriggerFilter, ENV_VARS,
+    trigger::{EntityTrigger, EthereumBlockTriggerType, EthereumTrigger},
+    ProbedCapabilities, TriggerFilter, ENV_VARS,
 };
 
 #[derive(Clone)]
@@ -63,6 +67,9 @@ pub struct EthereumAdapter {
     web3: Arc<Web3<Transport>>,
     metrics: Arc<ProviderEthRpcMetrics>,
     supports_eip_1898: bool,
+    /// The block range of `eth_getLogs` requests to this provider. The
+    /// largest range is set by `probe_capabilities`
+    logs_range: Arc<LogsRange>,
 }
 
 /// Gas limit for `eth_call`. The value of 50_000_000 is a protocol-wide parameter so this
@@ -75,6 +82,32 @@ pub struct EthereumAdapter {
 // See also f0af4ab0-6b7c-4b68-9141-5b79346a5f61.
 const ETH_CALL_GAS: u32 = 50_000_000;
 
+/// How many blocks behind the chain head `probe_capabilities` checks
+/// whether a provider that is not an archive node still has the state
+const PROBE_STATE_DEPTHS: [BlockNumber; 4] = [128, 1_024, 10_000, 100_000];
+
+/// The block ranges that `probe_capabilities` tries `eth_getLogs` with,
+/// largest first
+const PROBE_LOGS_RANGES: [BlockNumber; 4] = [10_000, 2_000, 500, 100];
+
+/// How many calls `multicall` puts into one call to the Multicall3 contract
+const MULTICALL_BATCH_SIZE: usize = 100;
+
+/// The `aggregate3` function of Multicall3
+const MULTICALL3_AGGREGATE3_ABI: &str = r#"[{"type":"function","name":"aggregate3","stateMutability":"payable","inputs":[{"name":"calls","type":"tuple[]","components":[{"name":"target","type":"address"},{"name":"allowFailure","type":"bool"},{"name":"callData","type":"bytes"}]}],"outputs":[{"name":"returnData","type":"tuple[]","components":[{"name":"success","type":"bool"},{"name":"returnData","type":"bytes"}]}]}]"#;
+
+lazy_static! {
+    /// Multicall3 is deployed at the same address on every chain that has it
+    static ref MULTICALL3_ADDRESS: Address =
+        Address::from_str("cA11bde05977b3631167028862bE2a173976CA11").unwrap();
+    static ref MULTICALL3_AGGREGATE3: ethabi::Function =
+        ethabi::Contract::load(MULTICALL3_AGGREGATE3_ABI.as_bytes())
+            .unwrap()
+            .function("aggregate3")
+            .unwrap()
+            .clone();
+}
+
 impl CheapClone for EthereumAdapter {
     fn cheap_clone(&self) -> Self {
         Self {
@@ -84,6 +117,7 @@ impl CheapClone for EthereumAdapter {
             web3: self.web3.cheap_clone(),
             metrics: self.metrics.cheap_clone(),
             supports_eip_1898: self.supports_eip_1898,
+            logs_range: self.logs_range.cheap_clone(),
         }
     }
 }
@@ -122,6 +156,212 @@ impl EthereumAdapter {
             web3,
             metrics: provider_metrics,
             supports_eip_1898: supports_eip_1898 && !is_ganache,
+            logs_range: Arc::new(LogsRange::new(ENV_VARS.max_block_range_size)),
+        }
+    }
+
+    /// Makes `calls`, given as contract addresses and call data, at
+    /// `block_ptr` with as few requests as possible by batching them into
+    /// calls to the Multicall3 contract. Up to `ETHEREUM_BLOCK_BATCH_SIZE`
+    /// batches are sent at once. The calls in a batch are made one at a
+    /// time if that contract is not available at the block or the batch
+    /// fails. The result for a call is `None` if it reverted or failed.
+    ///
+    /// Calls made through Multicall3 see that contract as `msg.sender` and
+    /// get less gas than an `eth_call`, so their results can differ from
+    /// those of `eth_call`. Only use this for calls to `pure` functions
+    pub async fn multicall(
+        &self,
+        logger: &Logger,
+        calls: &[(Address, Vec<u8>)],
+        block_ptr: &BlockPtr,
+    ) -> Vec<Option<Vec<u8>>> {
+        futures03::stream::iter(
+            calls
+                .chunks(MULTICALL_BATCH_SIZE)
+                .map(|batch| self.multicall_batch(logger, batch, block_ptr)),
+        )
+        .buffered(ENV_VARS.block_batch_size)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    async fn multicall_batch(
+        &self,
+        logger: &Logger,
+        batch: &[(Address, Vec<u8>)],
+        block_ptr: &BlockPtr,
+    ) -> Vec<Option<Vec<u8>>> {
+        if ENV_VARS.multicall && batch.len() > 1 {
+            if let Some(results) = self.aggregate3(logger, batch, block_ptr).await {
+                return results;
+            }
+        }
+        self.calls(logger, batch, block_ptr).await
+    }
+
+    /// Makes `calls`, given as contract addresses and call data, at
+    /// `block_ptr` with one `eth_call` each, all at once. The result for a
+    /// call is `None` if it reverted or failed
+    pub async fn calls(
+        &self,
+        logger: &Logger,
+        calls: &[(Address, Vec<u8>)],
+        block_ptr: &BlockPtr,
+    ) -> Vec<Option<Vec<u8>>> {
+        futures03::future::join_all(calls.iter().map(|(address, data)| {
+            self.call(
+                logger.clone(),
+                *address,
+                Bytes(data.clone()),
+                block_ptr.clone(),
+            )
+            .compat()
+        }))
+        .await
+        .into_iter()
+        .map(|result| result.ok().map(|output| output.0))
+        .collect()
+    }
+
+    /// Makes `calls` with one call to `aggregate3` of Multicall3, allowing
+    /// individual calls to fail. Returns `None` if that call failed or
+    /// returned nothing because the contract does not exist at the block
+    async fn aggregate3(
+        &self,
+        logger: &Logger,
+        calls: &[(Address, Vec<u8>)],
+        block_ptr: &BlockPtr,
+    ) -> Option<Vec<Option<Vec<u8>>>> {
+        let calls_token = Token::Array(
+            calls
+                .iter()
+                .map(|(address, data)| {
+                    Token::Tuple(vec![
+                        Token::Address(*address),
+                        Token::Bool(true),
+                        Token::Bytes(data.clone()),
+                    ])
+                })
+                .collect(),
+        );
+        let input = MULTICALL3_AGGREGATE3.encode_input(&[calls_token]).ok()?;
+        let output = self
+            .call(
+                logger.clone(),
+                *MULTICALL3_ADDRESS,
+                Bytes(input),
+                block_ptr.clone(),
+            )
+            .compat()
+            .await
+            .ok()?;
+
+        let results = match MULTICALL3_AGGREGATE3
+            .decode_output(&output.0)
+            .ok()?
+            .into_iter()
+            .next()?
+        {
+            Token::Array(results) if results.len() == calls.len() => results,
+            _ => return None,
+        };
+        results
+            .into_iter()
+            .map(|result| match result {
+                Token::Tuple(fields) => match fields.as_slice() {
+                    [Token::Bool(true), Token::Bytes(data)] => Some(Some(data.clone())),
+                    [Token::Bool(false), _] => Some(None),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether the provider has not used up its daily request budget. This
+    /// is always true for providers without a budget
+    pub fn is_within_budget(&self) -> bool {
+        self.web3
+            .transport()
+            .budget()
+            .map_or(true, |budget| budget.is_within_budget())
+    }
+
+    /// Find out what the provider supports by sending it a few cheap
+    /// requests. Requests that fail with a JSON-RPC error show that the
+    /// provider does not support something; other failures leave the
+    /// outcome of that probe unset. Later `eth_getLogs` requests are kept
+    /// within the block range that the provider accepted
+    pub async fn probe_capabilities(&self, logger: &Logger) -> ProbedCapabilities {
+        let mut probed = ProbedCapabilities::default();
+
+        let head = match self.web3.eth().block_number().await {
+            Ok(head) => head.as_u64() as BlockNumber,
+            Err(e) => {
+                warn!(logger, "Failed to get the chain head to probe the provider";
+                              "error" => e.to_string());
+                return probed;
+            }
+        };
+        let number = |number: BlockNumber| Web3BlockNumber::Number((number as u64).into());
+
+        // Only archive nodes have the state for old blocks
+        probed.archive = self.has_state_at(number(1)).await;
+        if probed.archive == Some(false) {
+            for depth in PROBE_STATE_DEPTHS.iter().filter(|depth| **depth < head) {
+                match self.has_state_at(number(head - depth)).await {
+                    Some(true) => probed.state_depth = Some(*depth),
+                    Some(false) | None => break,
+                }
+            }
+        }
+
+        let trace_filter = TraceFilterBuilder::default()
+            .from_block(number(head))
+            .to_block(number(head))
+            .build();
+        probed.traces = match self.web3.trace().filter(trace_filter).await {
+            Ok(_) => Some(true),
+            Err(web3::Error::Rpc(_)) => Some(false),
+            Err(_) => None,
+        };
+
+        // Nothing is ever logged by the zero address, so this only tests
+        // whether the provider accepts the range
+        for range in PROBE_LOGS_RANGES.iter() {
+            let log_filter = FilterBuilder::default()
+                .from_block(number((head - range + 1).max(0)))
+                .to_block(number(head))
+                .address(vec![Address::zero()])
+                .build();
+            match self.web3.eth().logs(log_filter).await {
+                Ok(_) => {
+                    probed.max_logs_range = Some(*range);
+                    break;
+                }
+                Err(web3::Error::Rpc(_)) => continue,
+                Err(_) => break,
+            }
+        }
+        if let Some(range) = probed.max_logs_range {
+            let range = self.logs_range.set_ceiling(range);
+            self.metrics.set_logs_range(range, &self.provider);
+        }
+
+        probed
+    }
+
+    /// Whether the provider has the state for block `number`, or `None` if
+    /// that is unclear
+    async fn has_state_at(&self, number: Web3BlockNumber) -> Option<bool> {
+        match self.web3.eth().balance(Address::zero(), Some(number)).await {
+            Ok(_) => Some(true),
+            Err(web3::Error::Rpc(_)) => Some(false),
+            Err(_) => None,
         }
     }
 
@@ -327,11 +567,14 @@ impl EthereumAdapter {
         filter: EthGetLogsFilter,
     ) -> DynTryFuture<'static, Vec<Log>, Error> {
         // Codes returned by Ethereum node providers if an eth_getLogs request is too heavy.
-        // The first one is for Infura when it hits the log limit, the rest for Alchemy timeouts.
+        // The first one is for Infura when it hits the log limit, the next two for Alchemy
+        // timeouts, and the rest are the messages for responses with too many logs.
         const TOO_MANY_LOGS_FINGERPRINTS: &[&str] = &[
             "ServerError(-32005)",
             "503 Service Unavailable",
             "ServerError(-32000)",
+            "query returned more than",
+            "Log response size exceeded",
         ];
 
         if from > to {
@@ -345,15 +588,17 @@ impl EthereumAdapter {
         let eth = self.cheap_clone();
         let filter = Arc::new(filter);
 
-        let step = match filter.contracts.is_empty() {
+        let max_step = match filter.contracts.is_empty() {
             // `to - from + 1`  blocks will be scanned.
             false => to - from,
             true => (to - from).min(ENV_VARS.max_event_only_range - 1),
         };
+        let step = max_step.min(self.logs_range.range() - 1);
 
-        // Typically this will loop only once and fetch the entire range in one request. But if the
-        // node returns an error that signifies the request is to heavy to process, the range will
-        // be broken down to smaller steps.
+        // The range is broken down into steps of the size that worked well for this provider
+        // before. The step size is adjusted after each request: it shrinks when a response has
+        // many logs, is slow, or the node returns an error that signifies the request is too
+        // heavy to process or it times out, and grows when responses are small and fast.
         futures03::stream::try_unfold((from, step), move |(start, step)| {
             let logger = logger.cheap_clone();
             let filter = filter.cheap_clone();
@@ -370,6 +615,7 @@ impl EthereumAdapter {
                     logger,
                     "Requesting logs for blocks [{}, {}], {}", start, end, filter
                 );
+                let started = Instant::now();
                 let res = eth
                     .logs_with_sigs(
                         logger.cheap_clone(),
@@ -387,14 +633,17 @@ impl EthereumAdapter {
 
                         // If the step is already 0, the request is too heavy even for a single
                         // block. We hope this never happens, but if it does, make sure to error.
-                        if TOO_MANY_LOGS_FINGERPRINTS
-                            .iter()
-                            .any(|f| string_err.contains(f))
+                        if (e.is_elapsed()
+                            || TOO_MANY_LOGS_FINGERPRINTS
+                                .iter()
+                                .any(|f| string_err.contains(f)))
                             && step > 0
                         {
                             // The range size for a request is `step + 1`. So it's ok if the step
                             // goes down to 0, in that case we'll request one block at a time.
-                            let new_step = step / 10;
+                            let range = eth.logs_range.failed(step + 1);
+                            eth.metrics.set_logs_range(range, &eth.provider);
+                            let new_step = range - 1;
                             debug!(logger, "Reducing block range size to scan for events";
                                                "new_size" => new_step + 1);
                             Ok(Some((vec![], (start, new_step))))
@@ -403,7 +652,15 @@ impl EthereumAdapter {
                             Err(anyhow!("{}", string_err))
                         }
                     }
-                    Ok(logs) => Ok(Some((logs, (end + 1, step)))),
+                    Ok(logs) => {
+                        let range = eth.logs_range.succeeded(
+                            end - start + 1,
+                            logs.len(),
+                            started.elapsed(),
+                        );
+                        eth.metrics.set_logs_range(range, &eth.provider);
+                        Ok(Some((logs, (end + 1, max_step.min(range - 1)))))
+                    }
                 }
             }
         })
@@ -1126,6 +1383,7 @@ impl EthereumAdapterTrait for EthereumAdapter {
         logger: &Logger,
         call: EthereumContractCall,
         cache: Arc<dyn EthereumCallCache>,
+        in_flight_calls: Arc<InFlightCalls>,
     ) -> Box<dyn Future<Item = Vec<Token>, Error = EthereumContractCallError> + Send> {
         // Emit custom error for type mismatches.
         for (token, kind) in call
@@ -1167,26 +1425,50 @@ impl EthereumAdapterTrait for EthereumAdapter {
                     let cache = cache.clone();
                     let call = call.clone();
                     let logger = logger.clone();
+                    let eth = self.cheap_clone();
+                    let key = CallKey::new(call.address, &call_data, &call.block_ptr);
                     Box::new(
-                        self.call(
-                            logger.clone(),
-                            call.address,
-                            Bytes(call_data.clone()),
-                            call.block_ptr.clone(),
-                        )
-                        .map(move |result| {
-                            // Don't block handler execution on writing to the cache.
-                            let for_cache = result.0.clone();
-                            let _ = graph::spawn_blocking_allow_panic(move || {
-                                cache
-                                    .set_call(call.address, &call_data, call.block_ptr, &for_cache)
-                                    .map_err(|e| {
-                                        error!(logger, "call cache set error";
-                                                   "error" => e.to_string())
-                                    })
-                            });
-                            result.0
-                        }),
+                        async move {
+                            // Callers that wait for an identical call get its
+                            // result without writing it to the cache again
+                            in_flight_calls
+                                .call(key, || async {
+                                    let result = eth
+                                        .call(
+                                            logger.clone(),
+                                            call.address,
+                                            Bytes(call_data.clone()),
+                                            call.block_ptr.clone(),
+                                        )
+                                        .compat()
+                                        .await
+                                        .map(|result| result.0);
+
+                                    // Don't block handler execution on writing to the cache.
+                                    if let Ok(result) = &result {
+                                        let cache = cache.clone();
+                                        let for_cache = result.clone();
+                                        let call_data = call_data.clone();
+                                        let (address, block_ptr) =
+                                            (call.address, call.block_ptr.clone());
+                                        let logger = logger.clone();
+                                        let _ = graph::spawn_blocking_allow_panic(move || {
+                                            cache
+                                                .set_call(
+                                                    address, &call_data, block_ptr, &for_cache,
+                                                )
+                                                .map_err(|e| {
+                                                    error!(logger, "call cache set error";
+                                                               "error" => e.to_string())
+                                                })
+                                        });
+                                    }
+                                    result
+                                })
+                                .await
+                        }
+                        .boxed()
+                        .compat(),
                     )
                 }
             }
@@ -1275,6 +1557,7 @@ pub(crate) async fn blocks_with_triggers(
     adapter: Arc<EthereumAdapter>,
     logger: Logger,
     chain_store: Arc<dyn ChainStore>,
+    subgraph_store: Arc<dyn SubgraphStore>,
     subgraph_metrics: Arc<SubgraphEthRpcMetrics>,
     from: BlockNumber,
     to: BlockNumber,
@@ -1347,6 +1630,32 @@ pub(crate) async fn blocks_with_triggers(
         trigger_futs.push(block_future)
     }
 
+    // Scan for entity changes in source subgraphs
+    if !filter.entity.is_empty() {
+        let eth = eth.clone();
+        let logger = logger.clone();
+        let entity_filter = filter.entity.clone();
+        let entity_future = async move {
+            let mut changes =
+                entity_changes_in_block_range(subgraph_store, entity_filter, from, to).await?;
+            let numbers = changes.keys().cloned().collect();
+            let ptrs: Vec<BlockPtr> = eth
+                .load_block_ptrs_rpc(logger, numbers)
+                .collect()
+                .compat()
+                .await?;
+            let mut triggers = Vec::new();
+            for ptr in ptrs {
+                for (_, changes) in changes.remove(&ptr.number).unwrap_or_default() {
+                    triggers.append(&mut entity_triggers(ptr.clone(), changes));
+                }
+            }
+            Ok(triggers)
+        }
+        .boxed();
+        trigger_futs.push(entity_future)
+    }
+
     // join on triger futures
     let triggers: Vec<EthereumTrigger> = trigger_futs.try_concat().await?;
 
@@ -1473,6 +1782,52 @@ pub(crate) async fn get_calls(
     }
 }
 
+/// Load the entity changes that the sources in `filter` made in the blocks
+/// `from..=to`, grouped by block and, within a block, by source
+pub(crate) async fn entity_changes_in_block_range(
+    subgraph_store: Arc<dyn SubgraphStore>,
+    filter: EthereumEntityFilter,
+    from: BlockNumber,
+    to: BlockNumber,
+) -> Result<BTreeMap<BlockNumber, Vec<(DeploymentHash, Vec<EntityOperation>)>>, Error> {
+    graph::spawn_blocking_allow_panic(move || {
+        let mut changes: BTreeMap<BlockNumber, Vec<(DeploymentHash, Vec<EntityOperation>)>> =
+            BTreeMap::new();
+        for (source, entity_types) in filter.sources {
+            let entity_types: Vec<EntityType> = entity_types.into_iter().collect();
+            let source_changes =
+                subgraph_store.entity_changes_in_block_range(&source, &entity_types, from, to)?;
+            for (block, ops) in source_changes {
+                changes
+                    .entry(block)
+                    .or_default()
+                    .push((source.clone(), ops));
+            }
+        }
+        Ok(changes)
+    })
+    .await?
+}
+
+/// Turn the entity changes one source made in `block` into triggers
+pub(crate) fn entity_triggers(
+    block: BlockPtr,
+    changes: Vec<EntityOperation>,
+) -> Vec<EthereumTrigger> {
+    changes
+        .into_iter()
+        .enumerate()
+        .map(|(index, op)| {
+            EthereumTrigger::Entity(Arc::new(EntityTrigger {
+                source: op.key().subgraph_id.clone(),
+                block: block.clone(),
+                index,
+                op,
+            }))
+        })
+        .collect()
+}
+
 pub(crate) fn parse_log_triggers(
     log_filter: &EthereumLogFilter,
     block: &EthereumBlock,
@@ -1,36 +1,134 @@
-use crate::{chain::BlockFinality, EthereumAdapter, EthereumAdapterTrait, ENV_VARS};
+use crate::{
+    chain::BlockFinality, network::EthereumNetworkAdapters, EthereumAdapter, EthereumAdapterTrait,
+    ENV_VARS,
+};
 use graph::{
     blockchain::{BlockHash, BlockPtr, IngestorError},
     cheap_clone::CheapClone,
     prelude::{
-        error, ethabi::ethereum_types::H256, info, tokio, trace, warn, ChainStore, Error,
-        EthereumBlockWithCalls, Future01CompatExt, LogCode, Logger,
+        anyhow, error, ethabi::ethereum_types::H256, futures03::future::join_all, info, tokio,
+        trace, warn, ChainStore, Error, EthereumBlockWithCalls, Future01CompatExt, Gauge, LogCode,
+        Logger,
     },
 };
 use std::{sync::Arc, time::Duration};
 
+/// Checks new chain head blocks against the other providers for a network
+/// so that a single provider that returns bogus blocks can not advance the
+/// chain head on its own
+pub struct HeadQuorum {
+    quorum: usize,
+    adapters: Arc<EthereumNetworkAdapters>,
+    /// Set to 1 while the providers disagree
+    disagreement: Gauge,
+}
+
+impl HeadQuorum {
+    pub(crate) fn new(
+        quorum: usize,
+        adapters: Arc<EthereumNetworkAdapters>,
+        disagreement: Gauge,
+    ) -> Self {
+        Self {
+            quorum,
+            adapters,
+            disagreement,
+        }
+    }
+
+    /// Whether at least `quorum` providers, including `eth_adapter`, agree
+    /// on the block `reorg_threshold` blocks below `head`. Disagreements
+    /// about more recent blocks are normal during reorgs and are not
+    /// checked
+    async fn check(
+        &self,
+        logger: &Logger,
+        eth_adapter: &EthereumAdapter,
+        head: &BlockPtr,
+    ) -> Result<bool, IngestorError> {
+        let number = (head.number - ENV_VARS.reorg_threshold).max(0);
+        let expected = eth_adapter
+            .block_hash_by_block_number(logger, number)
+            .compat()
+            .await?
+            .ok_or_else(|| anyhow!("provider does not have block #{}", number))?;
+
+        let others: Vec<_> = self
+            .adapters
+            .all()
+            .into_iter()
+            .filter(|adapter| adapter.provider() != eth_adapter.provider())
+            .collect();
+        let hashes = join_all(others.iter().map(|adapter| {
+            let hash = adapter.block_hash_by_block_number(logger, number).compat();
+            async move {
+                match tokio::time::timeout(ENV_VARS.json_rpc_timeout, hash).await {
+                    Ok(Ok(hash)) => hash,
+                    Ok(Err(_)) | Err(_) => None,
+                }
+            }
+        }))
+        .await;
+
+        let agreeing = 1 + hashes
+            .iter()
+            .filter(|hash| hash.as_ref() == Some(&expected))
+            .count();
+        if agreeing >= self.quorum {
+            self.disagreement.set(0.0);
+            return Ok(true);
+        }
+
+        let answers = others
+            .iter()
+            .zip(hashes)
+            .map(|(adapter, hash)| match hash {
+                Some(hash) => format!("{}: {:x}", adapter.provider(), hash),
+                None => format!("{}: no answer", adapter.provider()),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        error!(
+            logger,
+            "Not advancing the chain head because providers disagree about block #{}", number;
+            "head" => head,
+            "expected_hash" => format!("{:x}", expected),
+            "agreeing" => agreeing,
+            "quorum" => self.quorum,
+            "answers" => answers,
+        );
+        self.disagreement.set(1.0);
+        Ok(false)
+    }
+}
+
 pub struct BlockIngestor {
     logger: Logger,
     ancestor_count: i32,
-    eth_adapter: Arc<EthereumAdapter>,
+    /// The providers of the network. The ingestor uses the cheapest one
+    /// for each poll so that it follows changes to the providers
+    eth_adapters: Arc<EthereumNetworkAdapters>,
     chain_store: Arc<dyn ChainStore>,
     polling_interval: Duration,
+    head_quorum: Option<HeadQuorum>,
 }
 
 impl BlockIngestor {
     pub fn new(
         logger: Logger,
         ancestor_count: i32,
-        eth_adapter: Arc<EthereumAdapter>,
+        eth_adapters: Arc<EthereumNetworkAdapters>,
         chain_store: Arc<dyn ChainStore>,
         polling_interval: Duration,
+        head_quorum: Option<HeadQuorum>,
     ) -> Result<BlockIngestor, Error> {
         Ok(BlockIngestor {
             logger,
             ancestor_count,
-            eth_adapter,
+            eth_adapters,
             chain_store,
             polling_interval,
+            head_quorum,
         })
     }
 
@@ -91,13 +189,18 @@ impl BlockIngestor {
     async fn do_poll(&self) -> Result<(), IngestorError> {
         trace!(self.logger, "BlockIngestor::do_poll");
 
+        let eth_adapter = self
+            .eth_adapters
+            .cheapest()
+            .ok_or_else(|| anyhow!("there are no Ethereum providers for the network"))?;
+
         // Get chain head ptr from store
         let head_block_ptr_opt = self.chain_store.cheap_clone().chain_head_ptr().await?;
 
         // To check if there is a new block or not, fetch only the block header since that's cheaper
         // than the full block. This is worthwhile because most of the time there won't be a new
         // block, as we expect the poll interval to be much shorter than the block time.
-        let latest_block = self.latest_block().await?;
+        let latest_block = self.latest_block(&eth_adapter).await?;
 
         // If latest block matches head block in store, nothing needs to be done
         if Some(&latest_block) == head_block_ptr_opt.as_ref() {
@@ -137,11 +240,20 @@ impl BlockIngestor {
             }
         }
 
+        if let Some(head_quorum) = &self.head_quorum {
+            if !head_quorum
+                .check(&self.logger, &eth_adapter, &latest_block)
+                .await?
+            {
+                return Ok(());
+            }
+        }
+
         // Store latest block in block store.
         // Might be a no-op if latest block is one that we have seen.
         // ingest_blocks will return a (potentially incomplete) list of blocks that are
         // missing.
-        let mut missing_block_hash = self.ingest_block(&latest_block.hash).await?;
+        let mut missing_block_hash = self.ingest_block(&eth_adapter, &latest_block.hash).await?;
 
         // Repeatedly fetch missing parent blocks, and ingest them.
         // ingest_blocks will continue to tell us about more missing parent
@@ -162,29 +274,26 @@ impl BlockIngestor {
         //   iteration will have at most block number N-1.
         // - Therefore, the loop will iterate at most ancestor_count times.
         while let Some(hash) = missing_block_hash {
-            missing_block_hash = self.ingest_block(&hash).await?;
+            missing_block_hash = self.ingest_block(&eth_adapter, &hash).await?;
         }
         Ok(())
     }
 
     async fn ingest_block(
         &self,
+        eth_adapter: &EthereumAdapter,
         block_hash: &BlockHash,
     ) -> Result<Option<BlockHash>, IngestorError> {
         // TODO: H256::from_slice can panic
         let block_hash = H256::from_slice(block_hash.as_slice());
 
         // Get the fully populated block
-        let block = self
-            .eth_adapter
+        let block = eth_adapter
             .block_by_hash(&self.logger, block_hash)
             .compat()
             .await?
             .ok_or_else(|| IngestorError::BlockUnavailable(block_hash))?;
-        let ethereum_block = self
-            .eth_adapter
-            .load_full_block(&self.logger, block)
-            .await?;
+        let ethereum_block = eth_adapter.load_full_block(&self.logger, block).await?;
 
         // We need something that implements `Block` to store the block; the
         // store does not care whether the block is final or not
@@ -209,8 +318,8 @@ impl BlockIngestor {
             })
     }
 
-    async fn latest_block(&self) -> Result<BlockPtr, IngestorError> {
-        self.eth_adapter
+    async fn latest_block(&self, eth_adapter: &EthereumAdapter) -> Result<BlockPtr, IngestorError> {
+        eth_adapter
             .latest_block_header(&self.logger)
             .compat()
             .await
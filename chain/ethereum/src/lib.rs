@@ -1,14 +1,19 @@
 mod adapter;
+mod budget;
+mod call_cache;
 mod capabilities;
 pub mod codec;
 mod data_source;
 mod env;
 mod ethereum_adapter;
 mod ingestor;
+mod logs_range;
+mod prefetch;
 pub mod runtime;
 mod transport;
 
-pub use self::capabilities::NodeCapabilities;
+pub use self::budget::{RequestBudget, RequestLimits};
+pub use self::capabilities::{NodeCapabilities, ProbedCapabilities};
 pub use self::ethereum_adapter::EthereumAdapter;
 pub use self::runtime::RuntimeAdapter;
 pub use self::transport::Transport;
@@ -16,7 +21,7 @@ pub use env::ENV_VARS;
 
 // ETHDEP: These concrete types should probably not be exposed.
 pub use data_source::{DataSource, DataSourceTemplate, Mapping, MappingABI, TemplateSource};
-pub use trigger::MappingTrigger;
+pub use trigger::{EntityTrigger, EthereumBlockTriggerType, EthereumTrigger, MappingTrigger};
 
 pub mod chain;
 
@@ -28,8 +33,8 @@ pub use crate::adapter::{
     MockEthereumAdapter, ProviderEthRpcMetrics, SubgraphEthRpcMetrics, TriggerFilter,
 };
 pub use crate::chain::Chain;
-pub use crate::network::EthereumNetworks;
-pub use ingestor::BlockIngestor;
+pub use crate::network::{EthereumNetworkAdapters, EthereumNetworks};
+pub use ingestor::{BlockIngestor, HeadQuorum};
 
 #[cfg(test)]
 mod tests;
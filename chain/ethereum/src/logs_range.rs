@@ -0,0 +1,111 @@
+use graph::prelude::BlockNumber;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Responses with more logs than this make the range smaller
+const TARGET_LOGS: usize = 5_000;
+
+/// Responses that take longer than this make the range smaller
+const TARGET_DURATION: Duration = Duration::from_secs(10);
+
+/// How much a range that went through without trouble grows
+const GROWTH: BlockNumber = 2;
+
+/// How much a range that the provider refused or that timed out shrinks
+const SHRINK_ON_ERROR: BlockNumber = 10;
+
+struct State {
+    range: BlockNumber,
+    ceiling: BlockNumber,
+}
+
+/// Adjusts the block range of `eth_getLogs` requests to one provider to
+/// what that provider can answer. The range shrinks when a response has
+/// many logs, takes long, or fails because the request was too heavy, and
+/// grows again when responses are small and fast
+pub struct LogsRange {
+    state: Mutex<State>,
+}
+
+impl LogsRange {
+    pub fn new(initial: BlockNumber) -> Self {
+        Self {
+            state: Mutex::new(State {
+                range: initial.max(1),
+                ceiling: BlockNumber::MAX,
+            }),
+        }
+    }
+
+    /// The number of blocks that the next request should span
+    pub fn range(&self) -> BlockNumber {
+        self.state.lock().unwrap().range
+    }
+
+    /// Never use ranges larger than `ceiling`, for example because the
+    /// provider rejects them
+    pub fn set_ceiling(&self, ceiling: BlockNumber) -> BlockNumber {
+        let mut state = self.state.lock().unwrap();
+        state.ceiling = ceiling.max(1);
+        state.range = state.range.min(state.ceiling);
+        state.range
+    }
+
+    /// A request for `range` blocks returned `logs` logs in `duration`.
+    /// Returns the new range
+    pub fn succeeded(&self, range: BlockNumber, logs: usize, duration: Duration) -> BlockNumber {
+        let mut state = self.state.lock().unwrap();
+        if logs > TARGET_LOGS || duration > TARGET_DURATION {
+            let by_logs = TARGET_LOGS as f64 / logs.max(1) as f64;
+            let by_duration = TARGET_DURATION.as_secs_f64() / duration.as_secs_f64().max(0.001);
+            let shrunk = (range as f64 * by_logs.min(by_duration)) as BlockNumber;
+            state.range = state.range.min(shrunk.max(1));
+        } else if logs < TARGET_LOGS / 2 && duration < TARGET_DURATION / 2 && range >= state.range {
+            // Only requests for the full range say anything about whether
+            // a larger range would work; the last request for a block
+            // range is usually shorter
+            state.range = range.saturating_mul(GROWTH).min(state.ceiling);
+        }
+        state.range
+    }
+
+    /// A request for `range` blocks was too heavy for the provider or
+    /// timed out. Returns the new range
+    pub fn failed(&self, range: BlockNumber) -> BlockNumber {
+        let mut state = self.state.lock().unwrap();
+        state.range = state.range.min((range / SHRINK_ON_ERROR).max(1));
+        state.range
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_and_shrinks() {
+        let fast = Duration::from_millis(100);
+        let range = LogsRange::new(1_000);
+
+        // Small, fast responses grow the range up to the ceiling
+        assert_eq!(2_000, range.succeeded(1_000, 10, fast));
+        assert_eq!(2_000, range.set_ceiling(3_000));
+        assert_eq!(3_000, range.succeeded(2_000, 10, fast));
+        assert_eq!(3_000, range.succeeded(3_000, 10, fast));
+
+        // A short request at the end of a block range does not grow it
+        assert_eq!(3_000, range.succeeded(100, 10, fast));
+
+        // Large responses shrink the range in proportion
+        assert_eq!(1_500, range.succeeded(3_000, 2 * TARGET_LOGS, fast));
+
+        // So do slow ones
+        assert_eq!(750, range.succeeded(1_500, 10, 2 * TARGET_DURATION));
+
+        // Errors shrink it a lot, but never below one block
+        assert_eq!(75, range.failed(750));
+        assert_eq!(7, range.failed(75));
+        assert_eq!(1, range.failed(7));
+        assert_eq!(1, range.failed(1));
+    }
+}
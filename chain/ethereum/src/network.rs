@@ -2,7 +2,7 @@ use anyhow::{anyhow, Context};
 use graph::cheap_clone::CheapClone;
 use graph::prelude::rand::{self, seq::IteratorRandom};
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 pub use graph::impl_slog_value;
 use graph::prelude::Error;
@@ -17,24 +17,95 @@ pub struct EthereumNetworkAdapter {
     adapter: Arc<EthereumAdapter>,
 }
 
+/// The adapters for one network. Clones of this struct share the list of
+/// adapters so that providers can be added and removed while the node is
+/// running, and every chain that uses these adapters sees the change.
 #[derive(Clone)]
 pub struct EthereumNetworkAdapters {
-    pub adapters: Vec<EthereumNetworkAdapter>,
+    adapters: Arc<RwLock<Vec<EthereumNetworkAdapter>>>,
 }
 
 impl EthereumNetworkAdapters {
+    pub fn new(adapters: Vec<EthereumNetworkAdapter>) -> Self {
+        Self {
+            adapters: Arc::new(RwLock::new(adapters)),
+        }
+    }
+
+    /// A snapshot of the current adapters
+    pub fn adapters(&self) -> Vec<EthereumNetworkAdapter> {
+        self.adapters.read().unwrap().clone()
+    }
+
+    /// All adapters for this network, cheapest first
+    pub fn all(&self) -> Vec<Arc<EthereumAdapter>> {
+        self.adapters
+            .read()
+            .unwrap()
+            .iter()
+            .map(|adapter| adapter.adapter.cheap_clone())
+            .collect()
+    }
+
+    /// The labels of all providers for this network
+    pub fn providers(&self) -> Vec<String> {
+        self.adapters
+            .read()
+            .unwrap()
+            .iter()
+            .map(|adapter| adapter.adapter.provider().to_string())
+            .collect()
+    }
+
+    /// Add an adapter, keeping adapters sorted by their capabilities
+    pub fn insert(&self, capabilities: NodeCapabilities, adapter: Arc<EthereumAdapter>) {
+        let mut adapters = self.adapters.write().unwrap();
+        adapters.push(EthereumNetworkAdapter {
+            capabilities,
+            adapter,
+        });
+        adapters.sort_by_key(|adapter| adapter.capabilities);
+    }
+
+    /// Change the capabilities of the adapter for `provider`
+    pub fn set_capabilities(&self, provider: &str, capabilities: NodeCapabilities) {
+        let mut adapters = self.adapters.write().unwrap();
+        for adapter in adapters.iter_mut() {
+            if adapter.adapter.provider() == provider {
+                adapter.capabilities = capabilities;
+            }
+        }
+        adapters.sort_by_key(|adapter| adapter.capabilities);
+    }
+
     pub fn cheapest_with(
         &self,
         required_capabilities: &NodeCapabilities,
     ) -> Result<Arc<EthereumAdapter>, Error> {
-        let cheapest_sufficient_capability = self
-            .adapters
+        let adapters = self.adapters.read().unwrap();
+
+        // Prefer providers that have not used up their daily budget, but
+        // use the others if none of those has sufficient capabilities
+        let within_budget: Vec<_> = adapters
+            .iter()
+            .filter(|adapter| adapter.adapter.is_within_budget())
+            .collect();
+        let adapters = if within_budget
+            .iter()
+            .any(|adapter| &adapter.capabilities >= required_capabilities)
+        {
+            within_budget
+        } else {
+            adapters.iter().collect()
+        };
+
+        let cheapest_sufficient_capability = adapters
             .iter()
             .find(|adapter| &adapter.capabilities >= required_capabilities)
             .map(|adapter| &adapter.capabilities);
 
         // Select randomly from the cheapest adapters that have sufficent capabilities.
-        self.adapters
+        adapters
             .iter()
             .filter(|adapter| Some(&adapter.capabilities) == cheapest_sufficient_capability)
             .choose(&mut rand::thread_rng())
@@ -50,14 +121,18 @@ impl EthereumNetworkAdapters {
     pub fn cheapest(&self) -> Option<Arc<EthereumAdapter>> {
         // EthereumAdapters are sorted by their NodeCapabilities when the EthereumNetworks
         // struct is instantiated so they do not need to be sorted here
-        self.adapters
+        let adapters = self.adapters.read().unwrap();
+        adapters
             .iter()
-            .next()
+            .find(|adapter| adapter.adapter.is_within_budget())
+            .or_else(|| adapters.first())
             .map(|ethereum_network_adapter| ethereum_network_adapter.adapter.clone())
     }
 
-    pub fn remove(&mut self, provider: &str) {
+    pub fn remove(&self, provider: &str) {
         self.adapters
+            .write()
+            .unwrap()
             .retain(|adapter| adapter.adapter.provider() != provider);
     }
 }
@@ -83,15 +158,18 @@ impl EthereumNetworks {
         let network_adapters = self
             .networks
             .entry(name)
-            .or_insert(EthereumNetworkAdapters { adapters: vec![] });
-        network_adapters.adapters.push(EthereumNetworkAdapter {
-            capabilities,
-            adapter: adapter.clone(),
-        });
+            .or_insert_with(|| EthereumNetworkAdapters::new(vec![]));
+        network_adapters.insert(capabilities, adapter);
+    }
+
+    pub fn set_capabilities(&self, name: &str, provider: &str, capabilities: NodeCapabilities) {
+        if let Some(adapters) = self.networks.get(name) {
+            adapters.set_capabilities(provider, capabilities);
+        }
     }
 
-    pub fn remove(&mut self, name: &str, provider: &str) {
-        if let Some(adapters) = self.networks.get_mut(name) {
+    pub fn remove(&self, name: &str, provider: &str) {
+        if let Some(adapters) = self.networks.get(name) {
             adapters.remove(provider);
         }
     }
@@ -105,8 +183,8 @@ impl EthereumNetworks {
             .iter()
             .flat_map(|(network_name, network_adapters)| {
                 network_adapters
-                    .adapters
-                    .iter()
+                    .adapters()
+                    .into_iter()
                     .map(move |network_adapter| {
                         (
                             network_name.clone(),
@@ -122,6 +200,8 @@ impl EthereumNetworks {
         for adapters in self.networks.values_mut() {
             adapters
                 .adapters
+                .write()
+                .unwrap()
                 .sort_by_key(|adapter| adapter.capabilities)
         }
     }
@@ -0,0 +1,468 @@
+use anyhow::Error;
+use graph::blockchain::BlockPtr;
+use graph::prelude::{async_trait, ethabi::Address, futures03, EthereumCallCache, Logger};
+use graph::slog::{debug, trace};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::call_cache::PrefetchedCalls;
+use crate::data_source::PreparedCall;
+use crate::{DataSource, EthereumAdapter, EthereumTrigger};
+
+/// Makes the contract calls for prefetching
+#[async_trait]
+pub(crate) trait ContractCalls: Send + Sync {
+    /// Make `calls`, batching them through Multicall3 if possible
+    async fn multicall(
+        &self,
+        logger: &Logger,
+        calls: &[(Address, Vec<u8>)],
+        block_ptr: &BlockPtr,
+    ) -> Vec<Option<Vec<u8>>>;
+
+    /// Make `calls` with one `eth_call` each
+    async fn calls(
+        &self,
+        logger: &Logger,
+        calls: &[(Address, Vec<u8>)],
+        block_ptr: &BlockPtr,
+    ) -> Vec<Option<Vec<u8>>>;
+}
+
+#[async_trait]
+impl ContractCalls for EthereumAdapter {
+    async fn multicall(
+        &self,
+        logger: &Logger,
+        calls: &[(Address, Vec<u8>)],
+        block_ptr: &BlockPtr,
+    ) -> Vec<Option<Vec<u8>>> {
+        EthereumAdapter::multicall(self, logger, calls, block_ptr).await
+    }
+
+    async fn calls(
+        &self,
+        logger: &Logger,
+        calls: &[(Address, Vec<u8>)],
+        block_ptr: &BlockPtr,
+    ) -> Vec<Option<Vec<u8>>> {
+        EthereumAdapter::calls(self, logger, calls, block_ptr).await
+    }
+}
+
+/// Prefetch the declared calls for `blocks`, given with their triggers, in
+/// order. Failures are logged and do not stop prefetching for later blocks
+pub(crate) async fn prefetch_blocks(
+    logger: &Logger,
+    eth_adapter: &dyn ContractCalls,
+    call_cache: Arc<dyn EthereumCallCache>,
+    prefetched: &PrefetchedCalls,
+    data_sources: &[DataSource],
+    blocks: Vec<(BlockPtr, Vec<EthereumTrigger>)>,
+) {
+    let data_sources: Vec<_> = data_sources.iter().collect();
+    for (block_ptr, triggers) in blocks {
+        if let Err(e) = prefetch_declared_calls(
+            logger,
+            eth_adapter,
+            call_cache.clone(),
+            prefetched,
+            &data_sources,
+            block_ptr.clone(),
+            &triggers,
+        )
+        .await
+        {
+            debug!(logger, "Prefetching declared calls failed";
+                "block_number" => block_ptr.number,
+                "error" => format!("{:#}", e));
+        }
+    }
+}
+
+/// Make the calls that the event handlers of `data_sources` declare in the
+/// manifest for `triggers` at `block_ptr` and keep their results in
+/// `prefetched`, where `ethereum.call` finds them when the handlers run.
+/// Calls whose results are already in `call_cache` are not made again.
+/// Only calls to `pure` functions are batched through Multicall3; all
+/// other calls are made the same way a handler would make them. Returns
+/// the number of calls that were made
+pub(crate) async fn prefetch_declared_calls(
+    logger: &Logger,
+    eth_adapter: &dyn ContractCalls,
+    call_cache: Arc<dyn EthereumCallCache>,
+    prefetched: &PrefetchedCalls,
+    data_sources: &[&DataSource],
+    block_ptr: BlockPtr,
+    triggers: &[EthereumTrigger],
+) -> Result<usize, Error> {
+    let mut seen = HashSet::new();
+    let mut calls = Vec::new();
+    for trigger in triggers {
+        for ds in data_sources {
+            for call in ds.declared_calls(trigger, block_ptr.number, logger)? {
+                if seen.insert(call.clone()) {
+                    calls.push(call);
+                }
+            }
+        }
+    }
+    if calls.is_empty() {
+        return Ok(0);
+    }
+
+    let calls = {
+        let block_ptr = block_ptr.clone();
+        graph::spawn_blocking_allow_panic(move || uncached(call_cache.as_ref(), calls, block_ptr))
+            .await??
+    };
+    if calls.is_empty() {
+        return Ok(0);
+    }
+
+    let (pure, other): (Vec<_>, Vec<_>) = calls.into_iter().partition(|call| call.pure);
+    let pure: Vec<_> = pure
+        .into_iter()
+        .map(|call| (call.address, call.data))
+        .collect();
+    let other: Vec<_> = other
+        .into_iter()
+        .map(|call| (call.address, call.data))
+        .collect();
+    let (pure_results, other_results) = futures03::join!(
+        eth_adapter.multicall(logger, &pure, &block_ptr),
+        eth_adapter.calls(logger, &other, &block_ptr)
+    );
+    let made = pure.len() + other.len();
+    trace!(logger, "Prefetched declared calls";
+        "block_number" => block_ptr.number,
+        "calls" => made,
+        "succeeded" => pure_results
+            .iter()
+            .chain(other_results.iter())
+            .filter(|result| result.is_some())
+            .count());
+
+    // `ethereum.call` treats empty results as reverts; leave those to the
+    // handler
+    for ((address, data), result) in pure
+        .into_iter()
+        .zip(pure_results)
+        .chain(other.into_iter().zip(other_results))
+    {
+        if let Some(result) = result.filter(|result| !result.is_empty()) {
+            prefetched.insert(address, &data, &block_ptr, result);
+        }
+    }
+    Ok(made)
+}
+
+fn uncached(
+    call_cache: &dyn EthereumCallCache,
+    calls: Vec<PreparedCall>,
+    block_ptr: BlockPtr,
+) -> Result<Vec<PreparedCall>, Error> {
+    let mut uncached = Vec::with_capacity(calls.len());
+    for call in calls {
+        if call_cache
+            .get_call(call.address, &call.data, block_ptr.clone())?
+            .is_none()
+        {
+            uncached.push(call);
+        }
+    }
+    Ok(uncached)
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::data::subgraph::Source;
+    use graph::prelude::{
+        ethabi::{self, Contract},
+        tokio,
+        web3::types::{Bytes, Log, H256},
+        CachedEthereumCall, Link,
+    };
+    use graph::semver::Version;
+    use graph::slog::{o, Discard};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::call_cache::PrefetchingCallCache;
+    use crate::data_source::{DeclaredCall, Mapping, MappingABI, MappingEventHandler};
+
+    const ABI: &str = r#"[
+        {"type": "event", "name": "Transfer", "anonymous": false,
+         "inputs": [{"name": "to", "type": "address", "indexed": true}]},
+        {"type": "function", "name": "balanceOf", "stateMutability": "view",
+         "inputs": [{"name": "owner", "type": "address"}],
+         "outputs": [{"name": "", "type": "uint256"}]},
+        {"type": "function", "name": "decimals", "stateMutability": "pure",
+         "inputs": [], "outputs": [{"name": "", "type": "uint8"}]}
+    ]"#;
+
+    fn logger() -> Logger {
+        Logger::root(Discard, o!())
+    }
+
+    fn token() -> Address {
+        Address::from_low_u64_be(7)
+    }
+
+    fn data_source() -> DataSource {
+        let abi = Arc::new(MappingABI {
+            name: "Token".to_string(),
+            contract: Contract::load(ABI.as_bytes()).unwrap(),
+        });
+        let calls = vec![
+            DeclaredCall::parse("balance", "Token[event.address].balanceOf(event.params.to)")
+                .unwrap(),
+            DeclaredCall::parse("decimals", "Token[event.address].decimals()").unwrap(),
+        ];
+        DataSource {
+            kind: "ethereum/contract".to_string(),
+            network: Some("mainnet".to_string()),
+            name: "Token".to_string(),
+            source: Source {
+                address: Some(token()),
+                subgraph: None,
+                abi: "Token".to_string(),
+                start_block: 0,
+            },
+            mapping: Mapping {
+                kind: "ethereum/events".to_string(),
+                api_version: Version::new(0, 0, 6),
+                language: "wasm/assemblyscript".to_string(),
+                entities: vec![],
+                abis: vec![abi.clone()],
+                block_handlers: vec![],
+                call_handlers: vec![],
+                event_handlers: vec![MappingEventHandler {
+                    event: "Transfer(indexed address)".to_string(),
+                    topic0: None,
+                    handler: "handleTransfer".to_string(),
+                    receipt: false,
+                    calls,
+                }],
+                entity_handlers: vec![],
+                runtime: Arc::new(vec![]),
+                link: Link {
+                    link: "link".to_string(),
+                },
+            },
+            context: Arc::new(None),
+            creation_block: None,
+            contract_abi: abi,
+        }
+    }
+
+    fn block_ptr(number: u64) -> BlockPtr {
+        BlockPtr::from((H256::from_low_u64_be(number), number))
+    }
+
+    fn transfer(to: Address) -> EthereumTrigger {
+        let event = data_source()
+            .contract_abi
+            .contract
+            .event("Transfer")
+            .unwrap()
+            .clone();
+        EthereumTrigger::Log(
+            Arc::new(Log {
+                address: token(),
+                topics: vec![event.signature(), H256::from(to)],
+                data: Bytes::default(),
+                block_hash: None,
+                block_number: None,
+                transaction_hash: None,
+                transaction_index: None,
+                log_index: None,
+                transaction_log_index: None,
+                log_type: None,
+                removed: None,
+            }),
+            None,
+        )
+    }
+
+    /// Answers calls made through Multicall3 with `[1]` and all other
+    /// calls with `[2]`, and remembers how many calls it made each way
+    #[derive(Default)]
+    struct FakeCalls {
+        batched: Mutex<Vec<Vec<u8>>>,
+        single: Mutex<Vec<Vec<u8>>>,
+    }
+
+    #[async_trait]
+    impl ContractCalls for FakeCalls {
+        async fn multicall(
+            &self,
+            _logger: &Logger,
+            calls: &[(Address, Vec<u8>)],
+            _block_ptr: &BlockPtr,
+        ) -> Vec<Option<Vec<u8>>> {
+            let mut batched = self.batched.lock().unwrap();
+            calls
+                .iter()
+                .map(|(_, data)| {
+                    batched.push(data.clone());
+                    Some(vec![1])
+                })
+                .collect()
+        }
+
+        async fn calls(
+            &self,
+            _logger: &Logger,
+            calls: &[(Address, Vec<u8>)],
+            _block_ptr: &BlockPtr,
+        ) -> Vec<Option<Vec<u8>>> {
+            let mut single = self.single.lock().unwrap();
+            calls
+                .iter()
+                .map(|(_, data)| {
+                    single.push(data.clone());
+                    Some(vec![2])
+                })
+                .collect()
+        }
+    }
+
+    /// A call cache that fails for the blocks in `failing` and must never
+    /// be written to by prefetching
+    #[derive(Default)]
+    struct FakeCallCache {
+        calls: Mutex<HashMap<(Address, Vec<u8>, BlockPtr), Vec<u8>>>,
+        failing: Vec<BlockPtr>,
+    }
+
+    impl EthereumCallCache for FakeCallCache {
+        fn get_call(
+            &self,
+            contract_address: Address,
+            encoded_call: &[u8],
+            block: BlockPtr,
+        ) -> Result<Option<Vec<u8>>, Error> {
+            if self.failing.contains(&block) {
+                return Err(anyhow::anyhow!("call cache is down"));
+            }
+            Ok(self
+                .calls
+                .lock()
+                .unwrap()
+                .get(&(contract_address, encoded_call.to_vec(), block))
+                .cloned())
+        }
+
+        fn get_calls_in_block(&self, _block: BlockPtr) -> Result<Vec<CachedEthereumCall>, Error> {
+            Ok(vec![])
+        }
+
+        fn set_call(
+            &self,
+            _contract_address: Address,
+            _encoded_call: &[u8],
+            _block: BlockPtr,
+            _return_value: &[u8],
+        ) -> Result<(), Error> {
+            panic!("prefetching must not write to the call cache")
+        }
+    }
+
+    fn encoded(function: &str, args: &[ethabi::Token]) -> Vec<u8> {
+        data_source()
+            .contract_abi
+            .contract
+            .function(function)
+            .unwrap()
+            .encode_input(args)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn prefetches_declared_calls() {
+        let to = Address::from_low_u64_be(42);
+        let ds = data_source();
+        let calls = FakeCalls::default();
+        let prefetched = Arc::new(PrefetchedCalls::default());
+        let store = Arc::new(FakeCallCache::default());
+        let call_cache = PrefetchingCallCache::new(prefetched.clone(), store);
+
+        // Both events declare the same calls, which are only made once
+        let made = prefetch_declared_calls(
+            &logger(),
+            &calls,
+            call_cache.clone(),
+            &prefetched,
+            &[&ds],
+            block_ptr(1),
+            &[transfer(to), transfer(to)],
+        )
+        .await
+        .unwrap();
+        assert_eq!(2, made);
+
+        // Only the call to the `pure` function is batched
+        let balance = encoded("balanceOf", &[ethabi::Token::Address(to)]);
+        let decimals = encoded("decimals", &[]);
+        assert_eq!(vec![decimals.clone()], *calls.batched.lock().unwrap());
+        assert_eq!(vec![balance.clone()], *calls.single.lock().unwrap());
+
+        // Handlers find the results at the same block, but not at others
+        let get = |data: &[u8], block| call_cache.get_call(token(), data, block).unwrap();
+        assert_eq!(Some(vec![1]), get(&decimals, block_ptr(1)));
+        assert_eq!(Some(vec![2]), get(&balance, block_ptr(1)));
+        assert_eq!(None, get(&balance, block_ptr(2)));
+
+        // Calls with known results are not made again
+        let made = prefetch_declared_calls(
+            &logger(),
+            &calls,
+            call_cache.clone(),
+            &prefetched,
+            &[&ds],
+            block_ptr(1),
+            &[transfer(to)],
+        )
+        .await
+        .unwrap();
+        assert_eq!(0, made);
+    }
+
+    #[tokio::test]
+    async fn failed_prefetch_does_not_stop_prefetching() {
+        let to = Address::from_low_u64_be(42);
+        let calls = FakeCalls::default();
+        let prefetched = Arc::new(PrefetchedCalls::default());
+        let store = Arc::new(FakeCallCache {
+            failing: vec![block_ptr(1)],
+            ..Default::default()
+        });
+        let call_cache = PrefetchingCallCache::new(prefetched.clone(), store);
+
+        prefetch_blocks(
+            &logger(),
+            &calls,
+            call_cache.clone(),
+            &prefetched,
+            &[data_source()],
+            vec![
+                (block_ptr(1), vec![transfer(to)]),
+                (block_ptr(2), vec![transfer(to)]),
+            ],
+        )
+        .await;
+
+        let balance = encoded("balanceOf", &[ethabi::Token::Address(to)]);
+        assert_eq!(
+            Some(vec![2]),
+            call_cache
+                .get_call(token(), &balance, block_ptr(2))
+                .unwrap()
+        );
+        assert!(call_cache
+            .get_call(token(), &balance, block_ptr(1))
+            .is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use super::runtime_adapter::UnresolvedContractCall;
 use crate::trigger::{
-    EthereumBlockData, EthereumCallData, EthereumEventData, EthereumTransactionData,
+    EntityTriggerData, EthereumBlockData, EthereumCallData, EthereumEventData,
+    EthereumTransactionData,
 };
 use graph::{
     prelude::{
@@ -15,8 +16,8 @@ use graph::{
 };
 use graph_runtime_derive::AscType;
 use graph_runtime_wasm::asc_abi::class::{
-    Array, AscAddress, AscBigInt, AscEnum, AscH160, AscString, AscWrapped, EthereumValueKind,
-    Uint8Array,
+    Array, AscAddress, AscBigInt, AscEntity, AscEnum, AscH160, AscString, AscWrapped,
+    EthereumValueKind, Uint8Array,
 };
 use semver::Version;
 
@@ -300,6 +301,20 @@ impl AscIndexId for AscEthereumEvent<AscEthereumTransaction_0_0_6, AscEthereumBl
     const INDEX_ASC_TYPE_ID: IndexForAscTypeId = IndexForAscTypeId::EthereumEvent;
 }
 
+#[repr(C)]
+#[derive(AscType)]
+pub(crate) struct AscEntityTrigger {
+    pub source: AscPtr<AscString>,
+    pub entity_type: AscPtr<AscString>,
+    pub id: AscPtr<AscString>,
+    pub entity: AscPtr<AscEntity>,
+    pub block: AscPtr<AscEthereumBlock_0_0_6>,
+}
+
+impl AscIndexId for AscEntityTrigger {
+    const INDEX_ASC_TYPE_ID: IndexForAscTypeId = IndexForAscTypeId::EntityTrigger;
+}
+
 #[repr(C)]
 #[derive(AscType)]
 pub(crate) struct AscEthereumLog {
@@ -618,6 +633,26 @@ where
     }
 }
 
+impl ToAscObj<AscEntityTrigger> for EntityTriggerData {
+    fn to_asc_obj<H: AscHeap + ?Sized>(
+        &self,
+        heap: &mut H,
+        gas: &GasCounter,
+    ) -> Result<AscEntityTrigger, DeterministicHostError> {
+        Ok(AscEntityTrigger {
+            source: asc_new(heap, self.source.as_str(), gas)?,
+            entity_type: asc_new(heap, &self.entity_type, gas)?,
+            id: asc_new(heap, &self.id, gas)?,
+            entity: self
+                .entity
+                .as_ref()
+                .map(|entity| asc_new(heap, &entity.clone().sorted(), gas))
+                .unwrap_or(Ok(AscPtr::null()))?,
+            block: asc_new(heap, &self.block, gas)?,
+        })
+    }
+}
+
 impl ToAscObj<AscEthereumLog> for Log {
     fn to_asc_obj<H: AscHeap + ?Sized>(
         &self,
@@ -1,4 +1,4 @@
-pub use runtime_adapter::RuntimeAdapter;
+pub use runtime_adapter::{mock_ethereum_call, RuntimeAdapter, UnresolvedContractCall};
 
 pub mod abi;
 pub mod runtime_adapter;
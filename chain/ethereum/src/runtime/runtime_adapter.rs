@@ -1,8 +1,10 @@
 use std::{sync::Arc, time::Instant};
 
+use crate::call_cache::{InFlightCalls, PrefetchedCalls};
 use crate::data_source::MappingABI;
 use crate::{
-    capabilities::NodeCapabilities, network::EthereumNetworkAdapters, Chain, DataSource,
+    capabilities::NodeCapabilities, chain::BlockFinality, network::EthereumNetworkAdapters,
+    prefetch::prefetch_declared_calls, trigger::EthereumTrigger, Chain, DataSource,
     EthereumAdapter, EthereumAdapterTrait, EthereumContractCall, EthereumContractCallError,
 };
 use anyhow::{Context, Error};
@@ -10,9 +12,10 @@ use blockchain::HostFn;
 use graph::runtime::gas::Gas;
 use graph::runtime::{AscIndexId, IndexForAscTypeId};
 use graph::{
-    blockchain::{self, BlockPtr, HostFnCtx},
+    blockchain::{self, Block, BlockPtr, HostFnCtx},
     cheap_clone::CheapClone,
     prelude::{
+        async_trait,
         ethabi::{self, Address, Token},
         EthereumCallCache, Future01CompatExt,
     },
@@ -37,12 +40,16 @@ pub const ETHEREUM_CALL: Gas = Gas::new(5_000_000_000);
 pub struct RuntimeAdapter {
     pub(crate) eth_adapters: Arc<EthereumNetworkAdapters>,
     pub(crate) call_cache: Arc<dyn EthereumCallCache>,
+    pub(crate) prefetched_calls: Arc<PrefetchedCalls>,
+    pub(crate) in_flight_calls: Arc<InFlightCalls>,
 }
 
+#[async_trait]
 impl blockchain::RuntimeAdapter<Chain> for RuntimeAdapter {
     fn host_fns(&self, ds: &DataSource) -> Result<Vec<HostFn>, Error> {
         let abis = ds.mapping.abis.clone();
         let call_cache = self.call_cache.cheap_clone();
+        let in_flight_calls = self.in_flight_calls.cheap_clone();
         let eth_adapter = self
             .eth_adapters
             .cheapest_with(&NodeCapabilities {
@@ -54,42 +61,124 @@ impl blockchain::RuntimeAdapter<Chain> for RuntimeAdapter {
         let ethereum_call = HostFn {
             name: "ethereum.call",
             func: Arc::new(move |ctx, wasm_ptr| {
-                ethereum_call(&eth_adapter, call_cache.cheap_clone(), ctx, wasm_ptr, &abis)
-                    .map(|ptr| ptr.wasm_ptr())
+                ethereum_call(
+                    &eth_adapter,
+                    call_cache.cheap_clone(),
+                    in_flight_calls.cheap_clone(),
+                    ctx,
+                    wasm_ptr,
+                    &abis,
+                )
+                .map(|ptr| ptr.wasm_ptr())
             }),
         };
 
         Ok(vec![ethereum_call])
     }
+
+    /// Make the calls that event handlers declare in the manifest for all
+    /// events in `block` and keep their results in memory. The
+    /// block stream usually has done that already while fetching the block;
+    /// this catches calls it has not made yet
+    async fn prefetch(
+        &self,
+        logger: &Logger,
+        block: &Arc<BlockFinality>,
+        data_sources: &[&DataSource],
+        triggers: &[EthereumTrigger],
+    ) -> Result<(), Error> {
+        // Declared calls are made from mappings that use `ethereum.call`,
+        // which prefer archive nodes
+        let eth_adapter = self
+            .eth_adapters
+            .cheapest_with(&NodeCapabilities {
+                archive: true,
+                traces: false,
+            })
+            .or_else(|_| {
+                self.eth_adapters
+                    .cheapest_with(&NodeCapabilities::default())
+            })?;
+        prefetch_declared_calls(
+            logger,
+            eth_adapter.as_ref(),
+            self.call_cache.cheap_clone(),
+            &self.prefetched_calls,
+            data_sources,
+            block.ptr(),
+            triggers,
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+/// An `ethereum.call` host function that answers calls with `respond`
+/// instead of sending them to an Ethereum node, for running mappings in
+/// tests. `respond` returns `None` to make the call revert
+pub fn mock_ethereum_call(
+    respond: Arc<
+        dyn Fn(&UnresolvedContractCall) -> Result<Option<Vec<Token>>, HostExportError>
+            + Send
+            + Sync,
+    >,
+) -> HostFn {
+    HostFn {
+        name: "ethereum.call",
+        func: Arc::new(move |ctx, wasm_ptr| {
+            ctx.gas.consume_host_fn(ETHEREUM_CALL)?;
+            let call = unresolved_call(&ctx, wasm_ptr)?;
+            let result = respond(&call)?;
+            call_result(ctx, result).map(|ptr| ptr.wasm_ptr())
+        }),
+    }
 }
 
 /// function ethereum.call(call: SmartContractCall): Array<Token> | null
 fn ethereum_call(
     eth_adapter: &EthereumAdapter,
     call_cache: Arc<dyn EthereumCallCache>,
+    in_flight_calls: Arc<InFlightCalls>,
     ctx: HostFnCtx<'_>,
     wasm_ptr: u32,
     abis: &[Arc<MappingABI>],
 ) -> Result<AscEnumArray<EthereumValueKind>, HostExportError> {
     ctx.gas.consume_host_fn(ETHEREUM_CALL)?;
 
-    // For apiVersion >= 0.0.4 the call passed from the mapping includes the
-    // function signature; subgraphs using an apiVersion < 0.0.4 don't pass
-    // the signature along with the call.
-    let call: UnresolvedContractCall = if ctx.heap.api_version() >= Version::new(0, 0, 4) {
-        asc_get::<_, AscUnresolvedContractCall_0_0_4, _>(ctx.heap, wasm_ptr.into(), &ctx.gas)?
-    } else {
-        asc_get::<_, AscUnresolvedContractCall, _>(ctx.heap, wasm_ptr.into(), &ctx.gas)?
-    };
-
+    let call = unresolved_call(&ctx, wasm_ptr)?;
     let result = eth_call(
         eth_adapter,
         call_cache,
+        in_flight_calls,
         &ctx.logger,
         &ctx.block_ptr,
         call,
         abis,
     )?;
+    call_result(ctx, result)
+}
+
+/// Read the call that the mapping passed to `ethereum.call`
+fn unresolved_call(
+    ctx: &HostFnCtx<'_>,
+    wasm_ptr: u32,
+) -> Result<UnresolvedContractCall, HostExportError> {
+    // For apiVersion >= 0.0.4 the call passed from the mapping includes the
+    // function signature; subgraphs using an apiVersion < 0.0.4 don't pass
+    // the signature along with the call.
+    let call = if ctx.heap.api_version() >= Version::new(0, 0, 4) {
+        asc_get::<_, AscUnresolvedContractCall_0_0_4, _>(&*ctx.heap, wasm_ptr.into(), &ctx.gas)?
+    } else {
+        asc_get::<_, AscUnresolvedContractCall, _>(&*ctx.heap, wasm_ptr.into(), &ctx.gas)?
+    };
+    Ok(call)
+}
+
+/// Pass the result of a call back to the mapping; a reverted call is `null`
+fn call_result(
+    ctx: HostFnCtx<'_>,
+    result: Option<Vec<Token>>,
+) -> Result<AscEnumArray<EthereumValueKind>, HostExportError> {
     match result {
         Some(tokens) => Ok(asc_new(ctx.heap, tokens.as_slice(), &ctx.gas)?),
         None => Ok(AscPtr::null()),
@@ -100,6 +189,7 @@ fn ethereum_call(
 fn eth_call(
     eth_adapter: &EthereumAdapter,
     call_cache: Arc<dyn EthereumCallCache>,
+    in_flight_calls: Arc<InFlightCalls>,
     logger: &Logger,
     block_ptr: &BlockPtr,
     unresolved_call: UnresolvedContractCall,
@@ -169,7 +259,7 @@ fn eth_call(
     let logger1 = logger.clone();
     let call_cache = call_cache.clone();
     let result = match graph::block_on(
-            eth_adapter.contract_call(&logger1, call, call_cache).compat()
+            eth_adapter.contract_call(&logger1, call, call_cache, in_flight_calls).compat()
         ) {
             Ok(tokens) => Ok(Some(tokens)),
             Err(EthereumContractCallError::Revert(reason)) => {
@@ -4,13 +4,14 @@ use graph::{
     blockchain::{block_stream::BlockWithTriggers, BlockPtr},
     prelude::{
         web3::types::{Address, Bytes, Log, H160, H256, U64},
-        EthereumCall,
+        DeploymentHash, EntityKey, EntityOperation, EthereumCall,
     },
 };
 
 use crate::{
     chain::BlockFinality,
-    trigger::{EthereumBlockTriggerType, EthereumTrigger},
+    data_source::{CallArg, DeclaredCall},
+    trigger::{EntityTrigger, EthereumBlockTriggerType, EthereumTrigger},
 };
 
 #[test]
@@ -95,3 +96,78 @@ fn test_trigger_ordering() {
         vec![log1, log2, call1, log3, call2, call4, call3, block2, block1]
     );
 }
+
+#[test]
+fn test_entity_trigger_ordering() {
+    fn entity(source: &str, index: usize) -> EthereumTrigger {
+        let source = DeploymentHash::new(source).unwrap();
+        let key = EntityKey::data(source.clone(), "Token".to_string(), index.to_string());
+        EthereumTrigger::Entity(Arc::new(EntityTrigger {
+            source,
+            block: BlockPtr::from((H256::zero(), 1u64)),
+            index,
+            op: EntityOperation::Remove { key },
+        }))
+    }
+
+    let block = EthereumTrigger::Block(
+        BlockPtr::from((H256::zero(), 1u64)),
+        EthereumBlockTriggerType::Every,
+    );
+    let mut call = EthereumCall::default();
+    call.transaction_index = 1;
+    let call = EthereumTrigger::Call(Arc::new(call));
+
+    let a0 = entity("QmSourceA", 0);
+    let a1 = entity("QmSourceA", 1);
+    let b0 = entity("QmSourceB", 0);
+
+    // Entity triggers come after calls and before block triggers, grouped
+    // by source and in the order in which the source made the changes
+    let triggers = vec![
+        block.clone(),
+        b0.clone(),
+        a1.clone(),
+        call.clone(),
+        a0.clone(),
+    ];
+    let block_with_triggers =
+        BlockWithTriggers::<crate::Chain>::new(BlockFinality::Final(Default::default()), triggers);
+
+    assert_eq!(
+        block_with_triggers.trigger_data,
+        vec![call, a0, a1, b0, block]
+    );
+}
+
+#[test]
+fn parse_declared_calls() {
+    let call = DeclaredCall::parse(
+        "balance",
+        "ERC20[event.address].balanceOf(event.params.to, event.params.id)",
+    )
+    .unwrap();
+    assert_eq!("ERC20", call.contract);
+    assert_eq!(CallArg::Address, call.address);
+    assert_eq!("balanceOf", call.function);
+    assert_eq!(
+        vec![
+            CallArg::Param("to".to_string()),
+            CallArg::Param("id".to_string())
+        ],
+        call.args
+    );
+
+    let call = DeclaredCall::parse("supply", "ERC20[event.params.token].totalSupply()").unwrap();
+    assert_eq!(CallArg::Param("token".to_string()), call.address);
+    assert!(call.args.is_empty());
+
+    for expr in [
+        "ERC20.balanceOf(event.params.to)",
+        "ERC20[event.address].balanceOf(event.params.to",
+        "ERC20[event.address].balanceOf(0x1234)",
+        "ERC20[event.params.].balanceOf()",
+    ] {
+        assert!(DeclaredCall::parse("call", expr).is_err(), "{}", expr);
+    }
+}
@@ -8,12 +8,17 @@ use graph::prelude::*;
 use graph::url::Url;
 use std::future::Future;
 
+use crate::budget::RequestBudget;
+
 /// Abstraction over the different web3 transports.
 #[derive(Clone, Debug)]
 pub enum Transport {
     RPC(http::Http),
     IPC(ipc::Ipc),
     WS(ws::WebSocket),
+    /// A transport whose requests are subject to the rate limit and daily
+    /// budget of its provider
+    Limited(Box<Transport>, Arc<RequestBudget>),
 }
 
 impl Transport {
@@ -46,6 +51,18 @@ impl Transport {
             .unwrap();
         Transport::RPC(http::Http::with_client(client, rpc))
     }
+
+    /// Makes all requests sent through this transport wait for `budget`
+    pub fn with_budget(self, budget: Arc<RequestBudget>) -> Self {
+        Transport::Limited(Box::new(self), budget)
+    }
+
+    pub fn budget(&self) -> Option<&RequestBudget> {
+        match self {
+            Transport::Limited(_, budget) => Some(budget),
+            _ => None,
+        }
+    }
 }
 
 impl web3::Transport for Transport {
@@ -56,6 +73,9 @@ impl web3::Transport for Transport {
             Transport::RPC(http) => http.prepare(method, params),
             Transport::IPC(ipc) => ipc.prepare(method, params),
             Transport::WS(ws) => ws.prepare(method, params),
+            Transport::Limited(transport, _) => {
+                web3::Transport::prepare(transport.as_ref(), method, params)
+            }
         }
     }
 
@@ -64,6 +84,14 @@ impl web3::Transport for Transport {
             Transport::RPC(http) => Box::new(http.send(id, request)),
             Transport::IPC(ipc) => Box::new(ipc.send(id, request)),
             Transport::WS(ws) => Box::new(ws.send(id, request)),
+            Transport::Limited(transport, budget) => {
+                let transport = transport.as_ref().clone();
+                let budget = budget.cheap_clone();
+                Box::new(Box::pin(async move {
+                    budget.acquire(1).await;
+                    web3::Transport::send(&transport, id, request).await
+                }))
+            }
         }
     }
 }
@@ -83,6 +111,15 @@ impl web3::BatchTransport for Transport {
             Transport::RPC(http) => Box::new(http.send_batch(requests)),
             Transport::IPC(ipc) => Box::new(ipc.send_batch(requests)),
             Transport::WS(ws) => Box::new(ws.send_batch(requests)),
+            Transport::Limited(transport, budget) => {
+                let requests: Vec<_> = requests.into_iter().collect();
+                let transport = transport.as_ref().clone();
+                let budget = budget.cheap_clone();
+                Box::new(Box::pin(async move {
+                    budget.acquire(requests.len()).await;
+                    web3::BatchTransport::send_batch(&transport, requests).await
+                }))
+            }
         }
     }
 }
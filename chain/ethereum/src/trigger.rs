@@ -18,6 +18,7 @@ use graph::prelude::web3::types::TransactionReceipt;
 use graph::prelude::BlockNumber;
 use graph::prelude::BlockPtr;
 use graph::prelude::{CheapClone, EthereumCall};
+use graph::prelude::{DeploymentHash, Entity, EntityKey, EntityOperation};
 use graph::runtime::asc_new;
 use graph::runtime::gas::GasCounter;
 use graph::runtime::AscHeap;
@@ -28,6 +29,7 @@ use std::convert::TryFrom;
 use std::ops::Deref;
 use std::{cmp::Ordering, sync::Arc};
 
+use crate::runtime::abi::AscEntityTrigger;
 use crate::runtime::abi::AscEthereumBlock;
 use crate::runtime::abi::AscEthereumBlock_0_0_6;
 use crate::runtime::abi::AscEthereumCall;
@@ -59,6 +61,10 @@ pub enum MappingTrigger {
     Block {
         block: Arc<LightEthereumBlock>,
     },
+    Entity {
+        block: Arc<LightEthereumBlock>,
+        trigger: Arc<EntityTrigger>,
+    },
 }
 
 // Logging the block is too verbose, so this strips the block from the trigger for Debug.
@@ -78,6 +84,9 @@ impl std::fmt::Debug for MappingTrigger {
                 _outputs: Vec<LogParam>,
             },
             Block,
+            Entity {
+                _trigger: Arc<EntityTrigger>,
+            },
         }
 
         let trigger_without_block = match self {
@@ -105,6 +114,9 @@ impl std::fmt::Debug for MappingTrigger {
                 _outputs: outputs.clone(),
             },
             MappingTrigger::Block { block: _ } => MappingTriggerWithoutBlock::Block,
+            MappingTrigger::Entity { block: _, trigger } => MappingTriggerWithoutBlock::Entity {
+                _trigger: trigger.cheap_clone(),
+            },
         };
 
         write!(f, "{:?}", trigger_without_block)
@@ -209,6 +221,19 @@ impl blockchain::MappingTrigger for MappingTrigger {
                     asc_new::<AscEthereumBlock, _, _>(heap, &block, gas)?.erase()
                 }
             }
+            MappingTrigger::Entity { block, trigger } => {
+                let data = EntityTriggerData {
+                    source: trigger.source.clone(),
+                    entity_type: trigger.key().entity_type.to_string(),
+                    id: trigger.key().entity_id.clone(),
+                    entity: match &trigger.op {
+                        EntityOperation::Set { data, .. } => Some(data.clone()),
+                        EntityOperation::Remove { .. } => None,
+                    },
+                    block: EthereumBlockData::from(block.as_ref()),
+                };
+                asc_new::<AscEntityTrigger, _, _>(heap, &data, gas)?.erase()
+            }
         })
     }
 }
@@ -218,6 +243,7 @@ pub enum EthereumTrigger {
     Block(BlockPtr, EthereumBlockTriggerType),
     Call(Arc<EthereumCall>),
     Log(Arc<Log>, Option<TransactionReceipt>),
+    Entity(Arc<EntityTrigger>),
 }
 
 impl PartialEq for EthereumTrigger {
@@ -235,6 +261,10 @@ impl PartialEq for EthereumTrigger {
                     && a_receipt == b_receipt
             }
 
+            (Self::Entity(a), Self::Entity(b)) => {
+                a.source == b.source && a.block == b.block && a.index == b.index
+            }
+
             _ => false,
         }
     }
@@ -256,6 +286,7 @@ impl EthereumTrigger {
             EthereumTrigger::Log(log, _) => {
                 i32::try_from(log.block_number.unwrap().as_u64()).unwrap()
             }
+            EthereumTrigger::Entity(trigger) => trigger.block.number,
         }
     }
 
@@ -264,6 +295,7 @@ impl EthereumTrigger {
             EthereumTrigger::Block(block_ptr, _) => block_ptr.hash_as_h256(),
             EthereumTrigger::Call(call) => call.block_hash,
             EthereumTrigger::Log(log, _) => log.block_hash.unwrap(),
+            EthereumTrigger::Entity(trigger) => trigger.block.hash_as_h256(),
         }
     }
 }
@@ -278,6 +310,14 @@ impl Ord for EthereumTrigger {
             (Self::Block(..), _) => Ordering::Greater,
             (_, Self::Block(..)) => Ordering::Less,
 
+            // Entity triggers come after calls and events, in the order in
+            // which their source subgraph reported them
+            (Self::Entity(a), Self::Entity(b)) => {
+                a.source.cmp(&b.source).then(a.index.cmp(&b.index))
+            }
+            (Self::Entity(..), _) => Ordering::Greater,
+            (_, Self::Entity(..)) => Ordering::Less,
+
             // Calls are ordered by their tx indexes
             (Self::Call(a), Self::Call(b)) => a.transaction_index.cmp(&b.transaction_index),
 
@@ -320,6 +360,16 @@ impl TriggerData for EthereumTrigger {
             EthereumTrigger::Log(log, _) => log.transaction_hash,
             EthereumTrigger::Call(call) => call.transaction_hash,
             EthereumTrigger::Block(..) => None,
+            EthereumTrigger::Entity(trigger) => {
+                return format!(
+                    "block #{} ({}), {} `{}` of subgraph {}",
+                    self.block_number(),
+                    self.block_hash(),
+                    trigger.key().entity_type,
+                    trigger.key().entity_id,
+                    trigger.source
+                )
+            }
         };
 
         match transaction_id {
@@ -334,6 +384,35 @@ impl TriggerData for EthereumTrigger {
     }
 }
 
+/// A change to an entity of the subgraph `source` in `block`; data sources
+/// of kind `subgraph` handle these instead of data from the chain
+#[derive(Clone, Debug)]
+pub struct EntityTrigger {
+    pub source: DeploymentHash,
+    pub block: BlockPtr,
+    /// The position of this change among all the changes that `source`
+    /// made in `block`
+    pub index: usize,
+    pub op: EntityOperation,
+}
+
+impl EntityTrigger {
+    pub fn key(&self) -> &EntityKey {
+        self.op.key()
+    }
+}
+
+/// An entity change as it is passed to a mapping handler
+#[derive(Clone, Debug)]
+pub struct EntityTriggerData {
+    pub source: DeploymentHash,
+    pub entity_type: String,
+    pub id: String,
+    /// The entity after the change, or `None` if it was removed
+    pub entity: Option<Entity>,
+    pub block: EthereumBlockData,
+}
+
 /// Ethereum block data.
 #[derive(Clone, Debug, Default)]
 pub struct EthereumBlockData {
@@ -2,11 +2,12 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Duration;
 
-use graph::data::subgraph::SPEC_VERSION_0_0_4;
+use graph::data::subgraph::{Prune, SPEC_VERSION_0_0_4, SPEC_VERSION_0_0_6};
 use graph::prelude::{
     anyhow, async_trait, serde_yaml, tokio, DeploymentHash, Entity, Link, Logger, SubgraphManifest,
-    SubgraphManifestValidationError, UnvalidatedSubgraphManifest,
+    SubgraphManifestValidationError, UnvalidatedSubgraphManifest, BLOCK_NUMBER_MAX,
 };
+use graph::semver::Version;
 use graph::{
     blockchain::NodeCapabilities as _,
     components::{
@@ -65,6 +66,15 @@ impl LinkResolverTrait for TextResolver {
 }
 
 async fn resolve_manifest(text: &str) -> SubgraphManifest<graph_chain_ethereum::Chain> {
+    try_resolve_manifest(text, SPEC_VERSION_0_0_4)
+        .await
+        .expect("Parsing simple manifest works")
+}
+
+async fn try_resolve_manifest(
+    text: &str,
+    max_spec_version: Version,
+) -> Result<SubgraphManifest<graph_chain_ethereum::Chain>, anyhow::Error> {
     let mut resolver = TextResolver::default();
     let id = DeploymentHash::new("Qmmanifest").unwrap();
 
@@ -76,9 +86,7 @@ async fn resolve_manifest(text: &str) -> SubgraphManifest<graph_chain_ethereum::
     let resolver: Arc<dyn LinkResolverTrait> = Arc::new(resolver);
 
     let raw = serde_yaml::from_str(text).unwrap();
-    SubgraphManifest::resolve_from_raw(id, raw, &resolver, &LOGGER, SPEC_VERSION_0_0_4.clone())
-        .await
-        .expect("Parsing simple manifest works")
+    Ok(SubgraphManifest::resolve_from_raw(id, raw, &resolver, &LOGGER, max_spec_version).await?)
 }
 
 async fn resolve_unvalidated(text: &str) -> UnvalidatedSubgraphManifest<Chain> {
@@ -136,6 +144,69 @@ specVersion: 0.0.2
     assert_eq!(12345, graft.block);
 }
 
+#[tokio::test]
+async fn indexer_hints_manifest() {
+    const YAML_NO_HINTS: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+specVersion: 0.0.2
+";
+
+    fn yaml(spec_version: &str, prune: &str) -> String {
+        format!(
+            "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+indexerHints:
+  prune: {}
+specVersion: {}
+",
+            prune, spec_version
+        )
+    }
+
+    let manifest = resolve_manifest(YAML_NO_HINTS).await;
+    assert!(manifest.indexer_hints.is_none());
+    assert_eq!(BLOCK_NUMBER_MAX, manifest.history_blocks());
+
+    for (prune, expected) in [
+        ("auto", Prune::Auto),
+        ("never", Prune::Never),
+        ("100000", Prune::Blocks(100000)),
+    ] {
+        let manifest = try_resolve_manifest(&yaml("0.0.6", prune), SPEC_VERSION_0_0_6)
+            .await
+            .expect("manifest with indexer hints is valid");
+        let hints = manifest.indexer_hints.clone().expect("manifest has hints");
+        assert_eq!(Some(expected), hints.prune);
+    }
+
+    let manifest = try_resolve_manifest(&yaml("0.0.6", "100000"), SPEC_VERSION_0_0_6)
+        .await
+        .unwrap();
+    assert_eq!(100000, manifest.history_blocks());
+
+    // Invalid values and hints with older spec versions are rejected
+    for prune in ["sometimes", "0", "-10"] {
+        assert!(
+            try_resolve_manifest(&yaml("0.0.6", prune), SPEC_VERSION_0_0_6)
+                .await
+                .is_err(),
+            "prune: {} is invalid",
+            prune
+        );
+    }
+    assert!(
+        try_resolve_manifest(&yaml("0.0.5", "auto"), SPEC_VERSION_0_0_6)
+            .await
+            .is_err()
+    );
+}
+
 #[test]
 fn graft_invalid_manifest() {
     const YAML: &str = "
@@ -200,6 +271,83 @@ specVersion: 0.0.2
     })
 }
 
+#[test]
+fn graft_incompatible_manifest() {
+    const YAML: &str = "
+dataSources: []
+schema:
+  file:
+    /: /ipfs/Qmschema
+graft:
+  base: Qmincompatible
+  block: 0
+specVersion: 0.0.2
+";
+
+    fn graft_error(errors: Vec<SubgraphManifestValidationError>) -> String {
+        errors
+            .into_iter()
+            .find(|e| {
+                matches!(
+                    e,
+                    SubgraphManifestValidationError::GraftBaseInvalid(_)
+                        | SubgraphManifestValidationError::GraftIncompatible(_, _)
+                )
+            })
+            .expect("There must be a graft error")
+            .to_string()
+    }
+
+    test_store::run_test_sequentially(|store| async move {
+        let store = store.subgraph_store();
+        let subgraph = DeploymentHash::new("Qmincompatible").unwrap();
+        test_store::remove_subgraph(&subgraph);
+
+        //
+        // Validation against a base that does not exist fails
+        //
+        let unvalidated = resolve_unvalidated(YAML).await;
+        let msg = graft_error(
+            unvalidated
+                .validate(store.clone(), true)
+                .await
+                .expect_err("Validation must fail"),
+        );
+        assert_eq!(
+            "the graft base is invalid: failed to graft onto `Qmincompatible` \
+            since it does not exist on this installation",
+            msg
+        );
+
+        //
+        // Validation against a base with an incompatible schema fails and
+        // lists all incompatibilities
+        //
+        let deployment =
+            test_store::create_test_subgraph(&subgraph, "type Thing @entity { id: Bytes! }").await;
+        test_store::transact_entity_operations(
+            &store,
+            &deployment,
+            test_store::GENESIS_PTR.clone(),
+            vec![],
+        )
+        .expect("Can advance the base");
+
+        let unvalidated = resolve_unvalidated(YAML).await;
+        let msg = graft_error(
+            unvalidated
+                .validate(store, true)
+                .await
+                .expect_err("Validation must fail"),
+        );
+        assert_eq!(
+            "the schema can not be grafted onto `Qmincompatible`: \
+            [The attribute Thing.id has type ID!, but its type in the source is Bytes!]",
+            msg
+        );
+    })
+}
+
 #[tokio::test]
 async fn parse_call_handlers() {
     const YAML: &str = "
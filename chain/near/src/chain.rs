@@ -15,7 +15,7 @@ use graph::{
     },
     components::store::DeploymentLocator,
     firehose::{self as firehose, ForkStep},
-    prelude::{async_trait, o, BlockNumber, ChainStore, Error, Logger, LoggerFactory},
+    prelude::{async_trait, o, BlockNumber, ChainStore, Error, Logger, LoggerFactory, ENV_VARS},
 };
 use prost::Message;
 use std::sync::Arc;
@@ -109,7 +109,7 @@ impl Blockchain for Chain {
             .triggers_adapter(&deployment, &NodeCapabilities {}, unified_api_version)
             .expect(&format!("no adapter for network {}", self.name,));
 
-        let firehose_endpoint = match self.firehose_endpoints.random() {
+        let firehose_endpoint = match self.firehose_endpoints.endpoint() {
             Some(e) => e.clone(),
             None => return Err(anyhow::format_err!("no firehose endpoint available")),
         };
@@ -120,11 +120,11 @@ impl Blockchain for Chain {
             .new(o!("component" => "FirehoseBlockStream"));
 
         let firehose_mapper = Arc::new(FirehoseMapper {
-            endpoint: firehose_endpoint.cheap_clone(),
+            endpoint: firehose_endpoint,
         });
 
         Ok(Box::new(FirehoseBlockStream::new(
-            firehose_endpoint,
+            self.firehose_endpoints.cheap_clone(),
             subgraph_current_block,
             block_cursor,
             firehose_mapper,
@@ -155,7 +155,7 @@ impl Blockchain for Chain {
         logger: &Logger,
         number: BlockNumber,
     ) -> Result<BlockPtr, IngestorError> {
-        let firehose_endpoint = match self.firehose_endpoints.random() {
+        let firehose_endpoint = match self.firehose_endpoints.endpoint() {
             Some(e) => e.clone(),
             None => return Err(anyhow::format_err!("no firehose endpoint available").into()),
         };
@@ -166,6 +166,31 @@ impl Blockchain for Chain {
             .await
     }
 
+    async fn block_pointer_from_hash(
+        &self,
+        logger: &Logger,
+        hash: BlockHash,
+    ) -> Result<Option<BlockPtr>, IngestorError> {
+        let firehose_endpoint = match self.firehose_endpoints.endpoint() {
+            Some(e) => e.clone(),
+            None => return Err(anyhow::format_err!("no firehose endpoint available").into()),
+        };
+
+        let block = match firehose_endpoint
+            .block_for_hash::<codec::HeaderOnlyBlock>(
+                logger,
+                &hash,
+                ENV_VARS.graphql.block_hash_lookup_depth,
+            )
+            .await?
+        {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+        self.chain_store.upsert_light_blocks(&[block.as_ref()])?;
+        Ok(Some(block.ptr()))
+    }
+
     fn runtime_adapter(&self) -> Arc<Self::RuntimeAdapter> {
         Arc::new(RuntimeAdapter {})
     }
@@ -70,6 +70,10 @@ impl BlockchainBlock for Block {
     fn parent_ptr(&self) -> Option<BlockPtr> {
         self.parent_ptr()
     }
+
+    fn timestamp(&self) -> Option<u64> {
+        Some(self.header().timestamp_nanosec / 1_000_000_000)
+    }
 }
 
 impl HeaderOnlyBlock {
@@ -96,6 +100,10 @@ impl BlockchainBlock for HeaderOnlyBlock {
     fn parent_ptr(&self) -> Option<BlockPtr> {
         self.header().parent_ptr()
     }
+
+    fn timestamp(&self) -> Option<u64> {
+        Some(self.header().timestamp_nanosec / 1_000_000_000)
+    }
 }
 
 impl execution_outcome::Status {
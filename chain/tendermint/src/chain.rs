@@ -14,7 +14,7 @@ use graph::{
     },
     components::store::DeploymentLocator,
     firehose::{self, FirehoseEndpoint, FirehoseEndpoints, ForkStep},
-    prelude::{async_trait, o, BlockNumber, ChainStore, Error, Logger, LoggerFactory},
+    prelude::{async_trait, o, BlockNumber, ChainStore, Error, Logger, LoggerFactory, ENV_VARS},
 };
 use prost::Message;
 
@@ -104,7 +104,7 @@ impl Blockchain for Chain {
             .triggers_adapter(&deployment, &NodeCapabilities {}, unified_api_version)
             .unwrap_or_else(|_| panic!("no adapter for network {}", self.name));
 
-        let firehose_endpoint = match self.firehose_endpoints.random() {
+        let firehose_endpoint = match self.firehose_endpoints.endpoint() {
             Some(e) => e.clone(),
             None => return Err(anyhow!("no firehose endpoint available",)),
         };
@@ -115,11 +115,11 @@ impl Blockchain for Chain {
             .new(o!("component" => "FirehoseBlockStream"));
 
         let firehose_mapper = Arc::new(FirehoseMapper {
-            endpoint: firehose_endpoint.cheap_clone(),
+            endpoint: firehose_endpoint,
         });
 
         Ok(Box::new(FirehoseBlockStream::new(
-            firehose_endpoint,
+            self.firehose_endpoints.cheap_clone(),
             subgraph_current_block,
             block_cursor,
             firehose_mapper,
@@ -150,7 +150,7 @@ impl Blockchain for Chain {
         logger: &Logger,
         number: BlockNumber,
     ) -> Result<BlockPtr, IngestorError> {
-        let firehose_endpoint = match self.firehose_endpoints.random() {
+        let firehose_endpoint = match self.firehose_endpoints.endpoint() {
             Some(e) => e.clone(),
             None => return Err(anyhow!("no firehose endpoint available").into()),
         };
@@ -161,6 +161,31 @@ impl Blockchain for Chain {
             .map_err(Into::into)
     }
 
+    async fn block_pointer_from_hash(
+        &self,
+        logger: &Logger,
+        hash: BlockHash,
+    ) -> Result<Option<BlockPtr>, IngestorError> {
+        let firehose_endpoint = match self.firehose_endpoints.endpoint() {
+            Some(e) => e.clone(),
+            None => return Err(anyhow!("no firehose endpoint available").into()),
+        };
+
+        let block = match firehose_endpoint
+            .block_for_hash::<codec::EventList>(
+                logger,
+                &hash,
+                ENV_VARS.graphql.block_hash_lookup_depth,
+            )
+            .await?
+        {
+            Some(block) => block,
+            None => return Ok(None),
+        };
+        self.chain_store.upsert_light_blocks(&[block.as_ref()])?;
+        Ok(Some(block.ptr()))
+    }
+
     fn runtime_adapter(&self) -> Arc<Self::RuntimeAdapter> {
         Arc::new(RuntimeAdapter {})
     }
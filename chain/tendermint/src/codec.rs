@@ -89,6 +89,10 @@ impl BlockchainBlock for EventList {
     fn parent_ptr(&self) -> Option<BlockPtr> {
         self.parent_ptr()
     }
+
+    fn timestamp(&self) -> Option<u64> {
+        self.header().time.as_ref().map(|time| time.seconds as u64)
+    }
 }
 
 impl EventData {
@@ -141,4 +145,8 @@ impl BlockchainBlock for EventBlock {
     fn parent_ptr(&self) -> Option<BlockPtr> {
         self.parent_ptr()
     }
+
+    fn timestamp(&self) -> Option<u64> {
+        self.header().time.as_ref().map(|time| time.seconds as u64)
+    }
 }
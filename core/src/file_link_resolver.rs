@@ -0,0 +1,244 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_json::Value;
+
+use graph::prelude::{
+    anyhow, futures03::stream, hex, tiny_keccak, DeploymentHash, Error, JsonStreamValue,
+    JsonValueStream, Link, LinkResolver as LinkResolverTrait, Logger,
+};
+
+/// The name of the manifest in a subgraph directory
+const MANIFEST: &str = "subgraph.yaml";
+
+/// A link resolver that reads the files for a subgraph from a local
+/// directory instead of IPFS, for development and for installations that
+/// can not reach an IPFS node. The manifest in the directory can refer to
+/// other files with paths relative to the directory. Since the subgraph
+/// does not have an IPFS hash, its deployment hash is derived from the
+/// contents of the directory; it changes whenever any file in the
+/// directory changes and the resolver is `refresh`ed
+#[derive(Clone, Debug)]
+pub struct FileLinkResolver {
+    dir: PathBuf,
+    deployment: Arc<RwLock<DeploymentHash>>,
+}
+
+impl FileLinkResolver {
+    pub fn new(dir: impl AsRef<Path>) -> Result<Self, Error> {
+        let dir = dir
+            .as_ref()
+            .canonicalize()
+            .map_err(|e| anyhow!("can not open {}: {}", dir.as_ref().display(), e))?;
+        if !dir.join(MANIFEST).is_file() {
+            return Err(anyhow!(
+                "the directory {} does not contain a {}",
+                dir.display(),
+                MANIFEST
+            ));
+        }
+        let deployment = Arc::new(RwLock::new(Self::hash_dir(&dir)?));
+        Ok(Self { dir, deployment })
+    }
+
+    /// The deployment hash for the subgraph in the directory
+    pub fn deployment_hash(&self) -> DeploymentHash {
+        self.deployment.read().unwrap().clone()
+    }
+
+    /// Compute the deployment hash from the files in the directory again.
+    /// If it changed, return the new hash, which from now on refers to the
+    /// manifest in the directory
+    pub fn refresh(&self) -> Result<Option<DeploymentHash>, Error> {
+        let hash = Self::hash_dir(&self.dir)?;
+        let mut deployment = self.deployment.write().unwrap();
+        if *deployment == hash {
+            return Ok(None);
+        }
+        *deployment = hash.clone();
+        Ok(Some(hash))
+    }
+
+    /// The directory from which files are read
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Compute a deployment hash from the names and contents of all files
+    /// in `dir`
+    pub fn hash_dir(dir: &Path) -> Result<DeploymentHash, Error> {
+        fn collect(dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), Error> {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    collect(&path, files)?;
+                } else {
+                    files.push(path);
+                }
+            }
+            Ok(())
+        }
+
+        let mut files = Vec::new();
+        collect(dir, &mut files)?;
+        files.sort();
+
+        let mut data = Vec::new();
+        for file in files {
+            let name = file.strip_prefix(dir).unwrap_or(&file);
+            data.extend_from_slice(name.to_string_lossy().as_bytes());
+            data.push(0);
+            data.extend_from_slice(&fs::read(&file)?);
+        }
+        let hash = hex::encode(tiny_keccak::keccak256(&data));
+        DeploymentHash::new(format!("Local{}", &hash[..40]))
+            .map_err(|id| anyhow!("invalid deployment hash {}", id))
+    }
+
+    /// The path of the file that `link` refers to. The link for the
+    /// deployment hash refers to the manifest; all other links must be
+    /// paths inside the directory
+    fn path(&self, link: &Link) -> Result<PathBuf, Error> {
+        if link.link == self.deployment_hash().to_ipfs_link().link {
+            return Ok(self.dir.join(MANIFEST));
+        }
+        if link.link.starts_with("/ipfs/") {
+            return Err(anyhow!(
+                "can not resolve the IPFS link {} from local files",
+                link.link
+            ));
+        }
+
+        let path = link.link.trim_start_matches("file://");
+        let path = self
+            .dir
+            .join(path)
+            .canonicalize()
+            .map_err(|e| anyhow!("can not read {}: {}", path, e))?;
+        if !path.starts_with(&self.dir) {
+            return Err(anyhow!(
+                "{} is outside of the subgraph directory {}",
+                link.link,
+                self.dir.display()
+            ));
+        }
+        Ok(path)
+    }
+}
+
+#[async_trait]
+impl LinkResolverTrait for FileLinkResolver {
+    fn with_timeout(&self, _timeout: Duration) -> Box<dyn LinkResolverTrait> {
+        Box::new(self.clone())
+    }
+
+    fn with_retries(&self) -> Box<dyn LinkResolverTrait> {
+        Box::new(self.clone())
+    }
+
+    async fn cat(&self, _logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+        let path = self.path(link)?;
+        fs::read(&path).map_err(|e| anyhow!("can not read {}: {}", path.display(), e))
+    }
+
+    async fn json_stream(&self, logger: &Logger, link: &Link) -> Result<JsonValueStream, Error> {
+        let bytes = self.cat(logger, link).await?;
+        let text = String::from_utf8(bytes)?;
+
+        let values = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str::<Value>(line)
+                    .map(|value| JsonStreamValue { value, line: i + 1 })
+                    .map_err(|e| anyhow!("{} at line {}: '{}'", e, i + 1, line))
+            })
+            .collect::<Vec<_>>();
+        Ok(Box::pin(stream::iter(values)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use graph::prelude::{futures03::TryStreamExt, slog, tokio};
+
+    fn subgraph_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("file-link-resolver-{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("abis")).unwrap();
+        fs::write(dir.join(MANIFEST), "schema:\n  file: schema.graphql\n").unwrap();
+        fs::write(dir.join("schema.graphql"), "type Thing @entity { id: ID! }").unwrap();
+        fs::write(dir.join("abis/data.json"), "{\"a\": 1}\n\n{\"a\": 2}\n").unwrap();
+        dir
+    }
+
+    #[tokio::test]
+    async fn resolves_files_in_dir() {
+        let logger = Logger::root(slog::Discard, slog::o!());
+        let dir = subgraph_dir("resolve");
+        let resolver = FileLinkResolver::new(&dir).unwrap();
+
+        let manifest = resolver
+            .cat(&logger, &resolver.deployment_hash().to_ipfs_link())
+            .await
+            .unwrap();
+        assert_eq!(b"schema:\n  file: schema.graphql\n".to_vec(), manifest);
+
+        let schema = resolver
+            .cat(&logger, &Link::from("schema.graphql".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(b"type Thing @entity { id: ID! }".to_vec(), schema);
+
+        let values = resolver
+            .json_stream(&logger, &Link::from("file://abis/data.json".to_string()))
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let lines = values.iter().map(|value| value.line).collect::<Vec<_>>();
+        assert_eq!(vec![1, 3], lines);
+
+        // Links can not escape from the directory or go to IPFS
+        assert!(resolver
+            .cat(&logger, &Link::from("../outside".to_string()))
+            .await
+            .is_err());
+        assert!(resolver
+            .cat(&logger, &Link::from("/ipfs/QmOther".to_string()))
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn deployment_hash_follows_contents() {
+        let dir = subgraph_dir("hash");
+        let hash = FileLinkResolver::hash_dir(&dir).unwrap();
+        assert_eq!(hash, FileLinkResolver::hash_dir(&dir).unwrap());
+        assert!(hash.starts_with("Local"));
+
+        fs::write(dir.join("abis/data.json"), "{\"a\": 3}\n").unwrap();
+        assert_ne!(hash, FileLinkResolver::hash_dir(&dir).unwrap());
+    }
+
+    #[test]
+    fn refresh_picks_up_changes() {
+        let dir = subgraph_dir("refresh");
+        let resolver = FileLinkResolver::new(&dir).unwrap();
+        let hash = resolver.deployment_hash();
+        assert_eq!(None, resolver.refresh().unwrap());
+
+        fs::write(dir.join("schema.graphql"), "type Other @entity { id: ID! }").unwrap();
+        let changed = resolver.refresh().unwrap().expect("the hash changed");
+        assert_ne!(hash, changed);
+        assert_eq!(changed, resolver.deployment_hash());
+        assert_eq!(None, resolver.refresh().unwrap());
+    }
+}
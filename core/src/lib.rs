@@ -1,7 +1,13 @@
+mod file_link_resolver;
 mod link_resolver;
 mod metrics;
 mod subgraph;
 
+pub use crate::file_link_resolver::FileLinkResolver;
 pub use crate::link_resolver::LinkResolver;
 pub use crate::metrics::MetricsRegistry;
-pub use crate::subgraph::{SubgraphAssignmentProvider, SubgraphInstanceManager, SubgraphRegistrar};
+pub use crate::subgraph::{
+    block_triggers, replay_block, BlockReplay, BlockTriggers, IndexingShutdown, PoiChecker,
+    SubgraphAssignmentProvider, SubgraphInstanceManager, SubgraphRegistrar, TriggerHandlers,
+    TriggerReplay,
+};
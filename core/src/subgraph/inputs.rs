@@ -1,13 +1,21 @@
 use graph::components::store::WritableStore;
+use graph::prelude::tokio::sync::watch;
 use graph::{
     blockchain::Blockchain,
     components::store::{DeploymentLocator, SubgraphFork},
-    data::subgraph::{SubgraphFeature, UnifiedMappingApiVersion},
+    data::subgraph::{
+        retry::RetryPolicy,
+        schema::{DeploymentPriority, QuarantinedDataSource, SkippedBlock},
+        SubgraphFeature, UnifiedMappingApiVersion,
+    },
     prelude::BlockNumber,
 };
 use std::collections::BTreeSet;
 use std::sync::Arc;
 
+use crate::subgraph::load::LoadCounter;
+use crate::subgraph::scheduler::SyncScheduler;
+
 pub struct IndexingInputs<C: Blockchain> {
     pub deployment: DeploymentLocator,
     pub features: BTreeSet<SubgraphFeature>,
@@ -20,4 +28,16 @@ pub struct IndexingInputs<C: Blockchain> {
     pub templates: Arc<Vec<C::DataSourceTemplate>>,
     pub unified_api_version: UnifiedMappingApiVersion,
     pub static_filters: bool,
+    pub priority: DeploymentPriority,
+    pub sync_scheduler: Option<Arc<SyncScheduler>>,
+    pub load: Arc<LoadCounter>,
+    /// Blocks, or handlers in blocks, that operators told us to skip
+    pub skipped_blocks: Vec<SkippedBlock>,
+    /// Data sources that were quarantined before the deployment was
+    /// started; the runner takes them over
+    pub quarantined: Vec<QuarantinedDataSource>,
+    /// How to retry after non-deterministic errors
+    pub retry_policy: RetryPolicy,
+    /// Changes to `true` when the node shuts down
+    pub shutdown: watch::Receiver<bool>,
 }
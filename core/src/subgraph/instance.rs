@@ -10,6 +10,7 @@ use graph::{
         store::SubgraphFork,
         subgraph::{MappingError, SharedProofOfIndexing},
     },
+    data::subgraph::schema::{QuarantinedDataSource, SkippedBlock},
     prelude::ENV_VARS,
 };
 
@@ -82,7 +83,7 @@ where
             if let Some(sender) = self.module_cache.get(&module_hash) {
                 sender.clone()
             } else {
-                let sender = T::spawn_mapping(
+                let sender = self.host_builder.spawn_mapping(
                     module_bytes.to_owned(),
                     logger,
                     self.subgraph_id.clone(),
@@ -112,6 +113,8 @@ where
         causality_region: &str,
         debug_fork: &Option<Arc<dyn SubgraphFork>>,
         subgraph_metrics: &Arc<SubgraphInstanceMetrics>,
+        skipped: &[SkippedBlock],
+        quarantined: Option<&[QuarantinedDataSource]>,
     ) -> Result<BlockState<C>, MappingError> {
         Self::process_trigger_in_runtime_hosts(
             logger,
@@ -123,6 +126,8 @@ where
             causality_region,
             debug_fork,
             subgraph_metrics,
+            skipped,
+            quarantined,
         )
         .await
     }
@@ -137,6 +142,8 @@ where
         causality_region: &str,
         debug_fork: &Option<Arc<dyn SubgraphFork>>,
         subgraph_metrics: &Arc<SubgraphInstanceMetrics>,
+        skipped: &[SkippedBlock],
+        quarantined: Option<&[QuarantinedDataSource]>,
     ) -> Result<BlockState<C>, MappingError> {
         let error_count = state.deterministic_errors.len();
 
@@ -147,6 +154,20 @@ where
         }
 
         for host in hosts {
+            // Data sources that failed in this or an earlier block are not
+            // run anymore. `quarantined` is `None` unless the subgraph
+            // uses the `quarantineDataSources` feature
+            let data_source = host.data_source();
+            let (name, address) = (data_source.name(), data_source.address());
+            if quarantined.map_or(false, |quarantined| {
+                quarantined
+                    .iter()
+                    .chain(state.quarantined.iter())
+                    .any(|q| q.is(name, address))
+            }) {
+                continue;
+            }
+
             let mapping_trigger = match host.match_and_decode(trigger, block, logger)? {
                 // Trigger matches and was decoded as a mapping trigger.
                 Some(mapping_trigger) => mapping_trigger,
@@ -155,7 +176,16 @@ where
                 None => continue,
             };
 
+            // An operator told us not to run this handler for this block
+            if skipped
+                .iter()
+                .any(|skip| skip.skips(mapping_trigger.handler_name()))
+            {
+                continue;
+            }
+
             let start = Instant::now();
+            let host_error_count = state.deterministic_errors.len();
             state = host
                 .process_mapping_trigger(
                     logger,
@@ -168,6 +198,23 @@ where
                 .await?;
             let elapsed = start.elapsed().as_secs_f64();
             subgraph_metrics.observe_trigger_processing_duration(elapsed);
+
+            if quarantined.is_some() && state.deterministic_errors.len() > host_error_count {
+                let message = state
+                    .deterministic_errors
+                    .last()
+                    .map(|e| e.to_string())
+                    .unwrap_or_default();
+                warn!(logger, "Quarantining data source after a deterministic error";
+                              "data_source" => name,
+                              "error" => &message);
+                state.quarantined.push(QuarantinedDataSource {
+                    name: name.to_string(),
+                    address: address.map(|address| address.to_vec()),
+                    block_number: block.number(),
+                    message,
+                });
+            }
         }
 
         if let Some(proof_of_indexing) = proof_of_indexing {
@@ -235,4 +282,328 @@ where
     pub(crate) fn network(&self) -> &str {
         &self.network
     }
+
+    /// The data sources of all runtime hosts
+    pub(crate) fn data_sources(&self) -> Vec<&C::DataSource> {
+        self.hosts.iter().map(|host| host.data_source()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use graph::blockchain::mock::{
+        MockBlock, MockBlockchain, MockDataSource, MockDataSourceTemplate, MockMappingTrigger,
+        MockTriggerData,
+    };
+    use graph::blockchain::{BlockPtr, TriggerWithHandler};
+    use graph::components::store::{
+        EntityType, StoredDynamicDataSource, UnfailOutcome, WritableStore,
+    };
+    use graph::data::schema::Schema;
+    use graph::data::subgraph::schema::{MappingLog, SubgraphError, SubgraphHealth};
+    use graph::prelude::{tokio, EntityModification, StopwatchMetrics, StoreError};
+    use graph::util::lfu_cache::LfuCache;
+    use graph_mock::MockMetricsRegistry;
+
+    use super::*;
+
+    /// A store that the hosts below never touch
+    struct NoStore;
+
+    #[async_trait]
+    impl WritableStore for NoStore {
+        async fn block_ptr(&self) -> Option<BlockPtr> {
+            unreachable!()
+        }
+
+        async fn block_cursor(&self) -> Option<String> {
+            unreachable!()
+        }
+
+        async fn delete_block_cursor(&self) -> Result<(), StoreError> {
+            unreachable!()
+        }
+
+        async fn start_subgraph_deployment(&self, _: &Logger) -> Result<(), StoreError> {
+            unreachable!()
+        }
+
+        async fn revert_block_operations(
+            &self,
+            _: BlockPtr,
+            _: Option<&str>,
+        ) -> Result<(), StoreError> {
+            unreachable!()
+        }
+
+        async fn unfail_deterministic_error(
+            &self,
+            _: &BlockPtr,
+            _: &BlockPtr,
+        ) -> Result<UnfailOutcome, StoreError> {
+            unreachable!()
+        }
+
+        async fn unfail_non_deterministic_error(
+            &self,
+            _: &BlockPtr,
+        ) -> Result<UnfailOutcome, StoreError> {
+            unreachable!()
+        }
+
+        async fn fail_subgraph(&self, _: SubgraphError) -> Result<(), StoreError> {
+            unreachable!()
+        }
+
+        async fn supports_proof_of_indexing(&self) -> Result<bool, StoreError> {
+            unreachable!()
+        }
+
+        fn get(&self, _: &EntityKey) -> Result<Option<Entity>, StoreError> {
+            unreachable!()
+        }
+
+        async fn transact_block_operations(
+            &self,
+            _: BlockPtr,
+            _: Option<String>,
+            _: Vec<EntityModification>,
+            _: &StopwatchMetrics,
+            _: Vec<StoredDynamicDataSource>,
+            _: Vec<SubgraphError>,
+            _: Vec<MappingLog>,
+            _: Vec<QuarantinedDataSource>,
+            _: bool,
+        ) -> Result<(), StoreError> {
+            unreachable!()
+        }
+
+        fn get_many(
+            &self,
+            _: BTreeMap<&EntityType, Vec<&str>>,
+        ) -> Result<BTreeMap<EntityType, Vec<Entity>>, StoreError> {
+            unreachable!()
+        }
+
+        async fn deployment_synced(&self) -> Result<(), StoreError> {
+            unreachable!()
+        }
+
+        async fn promote_canary(&self) -> Result<(), StoreError> {
+            unreachable!()
+        }
+
+        async fn is_deployment_synced(&self) -> Result<bool, StoreError> {
+            unreachable!()
+        }
+
+        async fn unassign_subgraph(&self) -> Result<(), StoreError> {
+            unreachable!()
+        }
+
+        async fn load_dynamic_data_sources(
+            &self,
+        ) -> Result<Vec<StoredDynamicDataSource>, StoreError> {
+            unreachable!()
+        }
+
+        fn shard(&self) -> &str {
+            unreachable!()
+        }
+
+        async fn health(&self, _: &DeploymentHash) -> Result<SubgraphHealth, StoreError> {
+            unreachable!()
+        }
+
+        fn input_schema(&self) -> Arc<Schema> {
+            unreachable!()
+        }
+
+        async fn flush(&self) -> Result<(), StoreError> {
+            unreachable!()
+        }
+    }
+
+    /// A host whose handler matches every trigger, and fails with a
+    /// deterministic error if `fails` is set
+    struct TestHost {
+        data_source: MockDataSource,
+        fails: bool,
+        runs: AtomicUsize,
+    }
+
+    impl TestHost {
+        fn new(name: &str, fails: bool) -> Arc<Self> {
+            Arc::new(TestHost {
+                data_source: MockDataSource {
+                    name: name.to_string(),
+                    address: Some(name.as_bytes().to_vec()),
+                },
+                fails,
+                runs: AtomicUsize::new(0),
+            })
+        }
+
+        fn runs(&self) -> usize {
+            self.runs.load(Ordering::SeqCst)
+        }
+    }
+
+    impl PartialEq for TestHost {
+        fn eq(&self, other: &Self) -> bool {
+            self.data_source.name == other.data_source.name
+        }
+    }
+
+    #[async_trait]
+    impl RuntimeHost<MockBlockchain> for TestHost {
+        fn match_and_decode(
+            &self,
+            _: &MockTriggerData,
+            _: &Arc<MockBlock>,
+            _: &Logger,
+        ) -> Result<Option<TriggerWithHandler<MockBlockchain>>, Error> {
+            Ok(Some(TriggerWithHandler::new(
+                MockMappingTrigger {},
+                "handle".to_string(),
+            )))
+        }
+
+        async fn process_mapping_trigger(
+            &self,
+            _: &Logger,
+            block_ptr: BlockPtr,
+            _: TriggerWithHandler<MockBlockchain>,
+            mut state: BlockState<MockBlockchain>,
+            _: SharedProofOfIndexing,
+            _: &Option<Arc<dyn SubgraphFork>>,
+        ) -> Result<BlockState<MockBlockchain>, MappingError> {
+            self.runs.fetch_add(1, Ordering::SeqCst);
+            if self.fails {
+                state.deterministic_errors.push(SubgraphError {
+                    subgraph_id: DeploymentHash::new("test").unwrap(),
+                    message: format!("{} failed", self.data_source.name),
+                    block_ptr: Some(block_ptr),
+                    handler: Some("handle".to_string()),
+                    deterministic: true,
+                });
+            }
+            Ok(state)
+        }
+
+        fn creation_block_number(&self) -> Option<BlockNumber> {
+            None
+        }
+
+        fn data_source(&self) -> &MockDataSource {
+            &self.data_source
+        }
+    }
+
+    #[derive(Clone)]
+    struct TestHostBuilder;
+
+    impl RuntimeHostBuilder<MockBlockchain> for TestHostBuilder {
+        type Host = TestHost;
+        type Req = ();
+
+        fn build(
+            &self,
+            _: String,
+            _: DeploymentHash,
+            _: MockDataSource,
+            _: Arc<Vec<MockDataSourceTemplate>>,
+            _: Sender<()>,
+            _: Arc<HostMetrics>,
+        ) -> Result<TestHost, Error> {
+            unreachable!()
+        }
+
+        fn spawn_mapping(
+            &self,
+            _: Vec<u8>,
+            _: Logger,
+            _: DeploymentHash,
+            _: Arc<HostMetrics>,
+        ) -> Result<Sender<()>, Error> {
+            unreachable!()
+        }
+    }
+
+    /// Run the trigger of every block in `blocks` through `hosts`, and
+    /// return the final block state
+    async fn process(
+        hosts: &[Arc<TestHost>],
+        blocks: &[u64],
+        quarantine: bool,
+    ) -> BlockState<MockBlockchain> {
+        let logger = Logger::root(slog::Discard, o!());
+        let metrics = Arc::new(SubgraphInstanceMetrics::new(
+            Arc::new(MockMetricsRegistry::new()),
+            "test",
+        ));
+        let mut state = BlockState::new(Arc::new(NoStore), LfuCache::new());
+        let mut quarantined = Vec::new();
+        for number in blocks {
+            let block = Arc::new(MockBlock { number: *number });
+            state = SubgraphInstance::<MockBlockchain, TestHostBuilder>::process_trigger_in_runtime_hosts(
+                &logger,
+                hosts,
+                &block,
+                &MockTriggerData,
+                state,
+                &None,
+                "ethereum/mainnet",
+                &None,
+                &metrics,
+                &[],
+                quarantine.then(|| quarantined.as_slice()),
+            )
+            .await
+            .unwrap();
+            // Like the runner, remember quarantined data sources once a
+            // block has been processed
+            quarantined.extend(state.quarantined.drain(..));
+        }
+        state.quarantined = quarantined;
+        state
+    }
+
+    #[tokio::test]
+    async fn failing_data_source_is_quarantined() {
+        let hosts = vec![
+            TestHost::new("first", false),
+            TestHost::new("failing", true),
+            TestHost::new("last", false),
+        ];
+
+        let state = process(&hosts, &[1, 2, 3], true).await;
+
+        // The failing data source only ran once, all others kept running
+        assert_eq!(3, hosts[0].runs());
+        assert_eq!(1, hosts[1].runs());
+        assert_eq!(3, hosts[2].runs());
+
+        assert_eq!(1, state.deterministic_errors.len());
+        assert_eq!(1, state.quarantined.len());
+        let quarantined = &state.quarantined[0];
+        assert!(quarantined.is("failing", Some("failing".as_bytes())));
+        assert_eq!(1, quarantined.block_number);
+        assert!(quarantined.message.starts_with("failing failed"));
+    }
+
+    #[tokio::test]
+    async fn data_sources_are_not_quarantined_without_the_feature() {
+        let hosts = vec![TestHost::new("failing", true), TestHost::new("last", false)];
+
+        let state = process(&hosts, &[1, 2], false).await;
+
+        assert_eq!(2, hosts[0].runs());
+        assert_eq!(2, hosts[1].runs());
+        assert_eq!(2, state.deterministic_errors.len());
+        assert!(state.quarantined.is_empty());
+    }
 }
@@ -1,17 +1,23 @@
 use crate::subgraph::context::{IndexingContext, SharedInstanceKeepAliveMap};
 use crate::subgraph::inputs::IndexingInputs;
+use crate::subgraph::load::{take_loads, LoadCounter, SharedLoadCounters};
 use crate::subgraph::loader::load_dynamic_data_sources;
 use crate::subgraph::metrics::{
     RunnerMetrics, SubgraphInstanceManagerMetrics, SubgraphInstanceMetrics,
 };
 use crate::subgraph::runner::SubgraphRunner;
+use crate::subgraph::scheduler::SyncScheduler;
+use crate::subgraph::shutdown::IndexingShutdown;
 use crate::subgraph::SubgraphInstance;
 use graph::blockchain::block_stream::BlockStreamMetrics;
 use graph::blockchain::Blockchain;
 use graph::blockchain::NodeCapabilities;
 use graph::blockchain::{BlockchainKind, TriggerFilter};
+use graph::data::subgraph::retry::RetryPolicy;
 use graph::prelude::{SubgraphInstanceManager as SubgraphInstanceManagerTrait, *};
 use graph::{blockchain::BlockchainMap, components::store::DeploymentLocator};
+use std::collections::HashMap;
+use std::time::Instant;
 use tokio::task;
 
 pub struct SubgraphInstanceManager<S: SubgraphStore> {
@@ -23,6 +29,17 @@ pub struct SubgraphInstanceManager<S: SubgraphStore> {
     instances: SharedInstanceKeepAliveMap,
     link_resolver: Arc<dyn LinkResolver>,
     static_filters: bool,
+    /// Handler timeouts for individual deployments that override
+    /// `GRAPH_MAPPING_HANDLER_TIMEOUT`
+    handler_timeouts: Arc<HashMap<DeploymentHash, Duration>>,
+    /// Retry policies for individual deployments that override the
+    /// `GRAPH_SUBGRAPH_ERROR_RETRY_*` settings
+    retry_policies: Arc<HashMap<DeploymentHash, RetryPolicy>>,
+    /// Shares `GRAPH_SUBGRAPH_SYNC_CONCURRENCY` between syncing deployments
+    sync_scheduler: Option<Arc<SyncScheduler>>,
+    /// Counts the load of running deployments for `GRAPH_LOAD_REPORT_INTERVAL`
+    load_counters: SharedLoadCounters,
+    shutdown: IndexingShutdown,
 }
 
 #[async_trait]
@@ -89,6 +106,11 @@ impl<S: SubgraphStore> SubgraphInstanceManagerTrait for SubgraphInstanceManager<
         instances.remove(&loc.id);
 
         self.manager_metrics.subgraph_count.dec();
+
+        // Let the writer finish the blocks that are still queued and stop
+        if let Err(e) = self.subgraph_store.stop_subgraph(loc.id) {
+            error!(logger, "Failed to stop writing for subgraph"; "error" => e.to_string());
+        }
     }
 }
 
@@ -100,6 +122,8 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
         metrics_registry: Arc<dyn MetricsRegistry>,
         link_resolver: Arc<dyn LinkResolver>,
         static_filters: bool,
+        handler_timeouts: HashMap<DeploymentHash, Duration>,
+        retry_policies: HashMap<DeploymentHash, RetryPolicy>,
     ) -> Self {
         let logger = logger_factory.component_logger("SubgraphInstanceManager", None);
         let logger_factory = logger_factory.with_parent(logger.clone());
@@ -113,9 +137,43 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
             instances: SharedInstanceKeepAliveMap::default(),
             link_resolver,
             static_filters,
+            handler_timeouts: Arc::new(handler_timeouts),
+            retry_policies: Arc::new(retry_policies),
+            sync_scheduler: ENV_VARS
+                .subgraph_sync_concurrency
+                .map(|concurrency| Arc::new(SyncScheduler::new(concurrency))),
+            load_counters: SharedLoadCounters::default(),
+            shutdown: IndexingShutdown::new(),
         }
     }
 
+    /// A handle for stopping all deployments when the node shuts down
+    pub fn shutdown_handle(&self) -> IndexingShutdown {
+        self.shutdown.clone()
+    }
+
+    /// Periodically record the load of the deployments this node indexes
+    /// in the store so that deployments can be rebalanced between nodes
+    pub fn start_load_reporter(&self, node_id: NodeId, interval: Duration) {
+        let logger = self.logger_factory.component_logger("LoadReporter", None);
+        let store = self.subgraph_store.cheap_clone();
+        let networks: Vec<_> = self.chains.networks().cloned().collect();
+        let counters = self.load_counters.cheap_clone();
+
+        graph::spawn(async move {
+            let mut last_report = Instant::now();
+            loop {
+                tokio::time::sleep(interval).await;
+
+                let loads = take_loads(&counters, last_report.elapsed());
+                last_report = Instant::now();
+                if let Err(e) = store.report_load(&node_id, networks.clone(), loads) {
+                    warn!(logger, "Failed to report deployment load"; "error" => e.to_string());
+                }
+            }
+        });
+    }
+
     async fn start_subgraph_inner<C: Blockchain>(
         self: Arc<Self>,
         logger: Logger,
@@ -240,13 +298,51 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
             chain.runtime_adapter(),
             self.link_resolver.cheap_clone(),
             subgraph_store.ens_lookup(),
+        )
+        .with_timeout(
+            self.handler_timeouts
+                .get(&deployment.hash)
+                .cloned()
+                .or(ENV_VARS.mappings.timeout),
         );
 
+        // A priority set by an operator overrides the hint in the manifest
+        let priority = subgraph_store
+            .assignment_priority(&deployment)?
+            .or(manifest.priority)
+            .unwrap_or_default();
+
+        let skipped_blocks = subgraph_store.skipped_blocks(&deployment.hash)?;
+        if !skipped_blocks.is_empty() {
+            let blocks = skipped_blocks
+                .iter()
+                .map(|skip| skip.block_number.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            warn!(logger, "Skipping blocks as requested by an operator"; "blocks" => blocks);
+        }
+
+        let quarantined = subgraph_store.quarantined_data_sources(&deployment.hash)?;
+
+        let load = Arc::new(LoadCounter::default());
+        self.load_counters
+            .write()
+            .unwrap()
+            .insert(deployment.id, load.cheap_clone());
+        let load_counters = self.load_counters.cheap_clone();
+        let deployment_id = deployment.id;
+
         let features = manifest.features.clone();
         let unified_api_version = manifest.unified_mapping_api_version()?;
         let instance =
             SubgraphInstance::from_manifest(&logger, manifest, host_builder, host_metrics.clone())?;
 
+        let retry_policy = self
+            .retry_policies
+            .get(&deployment.hash)
+            .cloned()
+            .unwrap_or_else(RetryPolicy::from_env);
+
         let inputs = IndexingInputs {
             deployment: deployment.clone(),
             features,
@@ -259,6 +355,13 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
             templates,
             unified_api_version,
             static_filters: self.static_filters,
+            priority,
+            sync_scheduler: self.sync_scheduler.cheap_clone(),
+            load: load.cheap_clone(),
+            skipped_blocks,
+            quarantined,
+            retry_policy,
+            shutdown: self.shutdown.receiver(),
         };
 
         // The subgraph state tracks the state of the subgraph instance over time
@@ -287,7 +390,9 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
         // scheduling. It is also logical in terms of performance to run this with `unconstrained`,
         // it has a dedicated OS thread so the OS will handle the preemption. See
         // https://github.com/tokio-rs/tokio/issues/3493.
+        let running = self.shutdown.runner_started();
         graph::spawn_thread(deployment.to_string(), move || {
+            let _running = running;
             let runner = SubgraphRunner::new(inputs, ctx, logger.cheap_clone(), metrics);
             if let Err(e) = graph::block_on(task::unconstrained(runner.run())) {
                 error!(
@@ -297,6 +402,15 @@ impl<S: SubgraphStore> SubgraphInstanceManager<S> {
                 );
             }
             subgraph_metrics_unregister.unregister(registry);
+
+            // Stop counting the load unless the deployment was restarted
+            // with a new counter in the meantime
+            let mut load_counters = load_counters.write().unwrap();
+            if let Some(counter) = load_counters.get(&deployment_id) {
+                if Arc::ptr_eq(counter, &load) {
+                    load_counters.remove(&deployment_id);
+                }
+            }
         });
 
         Ok(())
@@ -0,0 +1,67 @@
+use graph::components::store::{DeploymentId, DeploymentLoad};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Counts the blocks a deployment processed and the time it spent writing
+/// them to the database since the last load report
+#[derive(Default)]
+pub struct LoadCounter {
+    blocks: AtomicU64,
+    db_micros: AtomicU64,
+}
+
+impl LoadCounter {
+    pub fn record_block(&self, db_time: Duration) {
+        self.blocks.fetch_add(1, Ordering::Relaxed);
+        self.db_micros
+            .fetch_add(db_time.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Turn what was counted since the last call into a load for a period
+    /// of length `elapsed` and start counting from zero again
+    fn take(&self, deployment: DeploymentId, elapsed: Duration) -> DeploymentLoad {
+        let blocks = self.blocks.swap(0, Ordering::Relaxed);
+        let db_time = Duration::from_micros(self.db_micros.swap(0, Ordering::Relaxed));
+        let minutes = elapsed.as_secs_f64().max(1.0) / 60.0;
+        DeploymentLoad {
+            deployment,
+            blocks_per_minute: blocks as f64 / minutes,
+            db_seconds_per_minute: db_time.as_secs_f64() / minutes,
+        }
+    }
+}
+
+pub type SharedLoadCounters = Arc<RwLock<HashMap<DeploymentId, Arc<LoadCounter>>>>;
+
+/// The load of all deployments in `counters` over the last `elapsed`
+pub fn take_loads(counters: &SharedLoadCounters, elapsed: Duration) -> Vec<DeploymentLoad> {
+    counters
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(id, counter)| counter.take(*id, elapsed))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_are_per_minute() {
+        let counter = LoadCounter::default();
+        counter.record_block(Duration::from_millis(500));
+        counter.record_block(Duration::from_millis(1500));
+
+        let load = counter.take(DeploymentId(1), Duration::from_secs(30));
+        assert_eq!(4.0, load.blocks_per_minute);
+        assert_eq!(4.0, load.db_seconds_per_minute);
+
+        // Taking the load starts counting from zero
+        let load = counter.take(DeploymentId(1), Duration::from_secs(30));
+        assert_eq!(0.0, load.blocks_per_minute);
+        assert_eq!(0.0, load.db_seconds_per_minute);
+    }
+}
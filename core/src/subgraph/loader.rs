@@ -3,7 +3,7 @@ use std::iter::FromIterator;
 use std::time::Instant;
 
 use graph::blockchain::{Blockchain, DataSource, DataSourceTemplate as _};
-use graph::components::store::WritableStore;
+use graph::components::store::{DeploymentLocator, WritableStore};
 use graph::prelude::*;
 
 pub async fn load_dynamic_data_sources<C: Blockchain>(
@@ -38,3 +38,31 @@ pub async fn load_dynamic_data_sources<C: Blockchain>(
 
     Ok(data_sources)
 }
+
+/// Resolve the manifest of `deployment` and add the dynamic data sources
+/// that were created up to and including block `last`
+pub(crate) async fn resolve_with_dynamic_data_sources<C: Blockchain>(
+    logger: &Logger,
+    store: Arc<dyn WritableStore>,
+    link_resolver: &Arc<dyn LinkResolver>,
+    deployment: &DeploymentLocator,
+    manifest: serde_yaml::Mapping,
+    last: BlockNumber,
+) -> Result<SubgraphManifest<C>, Error> {
+    let mut manifest = SubgraphManifest::<C>::resolve_from_raw(
+        deployment.hash.cheap_clone(),
+        manifest,
+        link_resolver,
+        logger,
+        ENV_VARS.max_spec_version.clone(),
+    )
+    .await?;
+    let data_sources =
+        load_dynamic_data_sources::<C>(store, logger.clone(), manifest.templates.clone()).await?;
+    manifest.data_sources.extend(
+        data_sources
+            .into_iter()
+            .filter(|ds| ds.creation_block().map_or(true, |block| block <= last)),
+    );
+    Ok(manifest)
+}
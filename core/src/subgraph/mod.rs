@@ -3,15 +3,25 @@ mod error;
 mod inputs;
 mod instance;
 mod instance_manager;
+mod load;
 mod loader;
 mod metrics;
+mod poi_check;
 mod provider;
 mod registrar;
+mod replay;
 mod runner;
+mod scheduler;
+mod shutdown;
 mod state;
 mod stream;
+mod triggers;
 
 pub use self::instance::SubgraphInstance;
 pub use self::instance_manager::SubgraphInstanceManager;
+pub use self::poi_check::PoiChecker;
 pub use self::provider::SubgraphAssignmentProvider;
 pub use self::registrar::SubgraphRegistrar;
+pub use self::replay::{replay_block, BlockReplay, TriggerReplay};
+pub use self::shutdown::IndexingShutdown;
+pub use self::triggers::{block_triggers, BlockTriggers, TriggerHandlers};
@@ -0,0 +1,247 @@
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::Duration;
+
+use graph::components::store::{DeploymentLocator, Store, SubgraphStore};
+use graph::prelude::{
+    anyhow::{anyhow, bail},
+    error, hex, info, reqwest, serde_json, tokio, warn,
+    web3::types::Address,
+    BlockPtr, CheapClone, Error, Logger, LoggerFactory, MetricsRegistry, NodeId,
+};
+use graph::prometheus::{CounterVec, GaugeVec};
+
+const STATUS_QUERY: &str = "query status($subgraph: String!) {
+  indexingStatuses(subgraphs: [$subgraph]) {
+    chains { latestBlock { hash number } }
+  }
+}";
+
+const POI_QUERY: &str =
+    "query poi($subgraph: String!, $number: Int!, $hash: Bytes!, $indexer: Bytes) {
+  proofOfIndexing(subgraph: $subgraph, blockNumber: $number, blockHash: $hash, indexer: $indexer)
+}";
+
+/// Regularly compares the proofs of indexing of the deployments that this
+/// node indexes with the ones that peer graph-nodes report for the same
+/// block. Proofs of indexing are computed for the zero indexer address so
+/// that they can be compared regardless of whether peers protect them with
+/// an access token
+pub struct PoiChecker<S: Store> {
+    logger: Logger,
+    store: Arc<S>,
+    node_id: NodeId,
+    peers: Vec<String>,
+    pause: bool,
+    client: reqwest::Client,
+    divergent: Box<GaugeVec>,
+    failures: Box<CounterVec>,
+}
+
+impl<S: Store> PoiChecker<S> {
+    pub fn new(
+        logger_factory: &LoggerFactory,
+        store: Arc<S>,
+        node_id: NodeId,
+        peers: Vec<String>,
+        pause: bool,
+        registry: Arc<impl MetricsRegistry>,
+    ) -> Self {
+        let divergent = registry
+            .new_gauge_vec(
+                "deployment_poi_divergent",
+                "Set to 1 when the proof of indexing of a deployment differs from that of a peer",
+                vec!["deployment".to_string(), "peer".to_string()],
+            )
+            .expect("Can register the deployment_poi_divergent gauge");
+        let failures = registry
+            .new_counter_vec(
+                "deployment_poi_check_failures",
+                "The number of times that comparing proofs of indexing with a peer failed",
+                vec!["peer".to_string()],
+            )
+            .expect("Can register the deployment_poi_check_failures counter");
+        PoiChecker {
+            logger: logger_factory.component_logger("PoiChecker", None),
+            store,
+            node_id,
+            peers,
+            pause,
+            client: reqwest::Client::new(),
+            divergent,
+            failures,
+        }
+    }
+
+    pub fn start(self, interval: Duration) {
+        info!(self.logger, "Comparing proofs of indexing with peers";
+                           "peers" => self.peers.join(", "),
+                           "interval_secs" => interval.as_secs());
+        graph::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                self.check_all().await;
+            }
+        });
+    }
+
+    async fn check_all(&self) {
+        let deployments = match self.store.subgraph_store().assignments(&self.node_id) {
+            Ok(deployments) => deployments,
+            Err(e) => {
+                warn!(self.logger, "Failed to load assigned deployments"; "error" => e.to_string());
+                return;
+            }
+        };
+        for deployment in &deployments {
+            for peer in &self.peers {
+                match self.check(deployment, peer).await {
+                    Ok(Some(block)) => {
+                        self.divergent
+                            .with_label_values(&[deployment.hash.as_str(), peer])
+                            .set(1.0);
+                        error!(self.logger, "Proof of indexing differs from that of a peer";
+                                            "deployment" => &deployment.hash,
+                                            "peer" => peer,
+                                            "block" => block.number);
+                        if self.pause {
+                            match self.store.subgraph_store().pause_subgraph(deployment) {
+                                Ok(()) => {
+                                    warn!(self.logger, "Paused deployment with divergent proof of indexing";
+                                                       "deployment" => &deployment.hash);
+                                }
+                                Err(e) => {
+                                    error!(self.logger, "Failed to pause deployment";
+                                                        "deployment" => &deployment.hash,
+                                                        "error" => e.to_string());
+                                }
+                            }
+                            break;
+                        }
+                    }
+                    Ok(None) => {
+                        self.divergent
+                            .with_label_values(&[deployment.hash.as_str(), peer])
+                            .set(0.0);
+                    }
+                    Err(e) => {
+                        self.failures.with_label_values(&[peer]).inc();
+                        warn!(self.logger, "Failed to compare proof of indexing with peer";
+                                           "deployment" => &deployment.hash,
+                                           "peer" => peer,
+                                           "error" => e.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Compare the proof of indexing of `deployment` with the one that
+    /// `peer` reports at the latest block that both have processed. Return
+    /// that block if they differ
+    async fn check(
+        &self,
+        deployment: &DeploymentLocator,
+        peer: &str,
+    ) -> Result<Option<BlockPtr>, Error> {
+        let head = match self
+            .store
+            .subgraph_store()
+            .least_block_ptr(&deployment.hash)
+            .await?
+        {
+            Some(head) => head,
+            None => return Ok(None),
+        };
+        let peer_head = match self.peer_head(deployment, peer).await? {
+            Some(peer_head) => peer_head,
+            None => return Ok(None),
+        };
+        let block = if peer_head.number < head.number {
+            peer_head
+        } else {
+            head
+        };
+
+        let indexer = Some(Address::zero());
+        let ours = match self
+            .store
+            .get_proof_of_indexing(&deployment.hash, &indexer, block.cheap_clone())
+            .await?
+        {
+            Some(poi) => format!("0x{}", hex::encode(&poi)),
+            None => return Ok(None),
+        };
+        let theirs = self
+            .query(
+                peer,
+                POI_QUERY,
+                serde_json::json!({
+                    "subgraph": deployment.hash.as_str(),
+                    "number": block.number,
+                    "hash": format!("0x{}", block.hash_hex()),
+                    "indexer": format!("{:?}", Address::zero()),
+                }),
+            )
+            .await?;
+        match theirs.get("proofOfIndexing").and_then(|poi| poi.as_str()) {
+            Some(theirs) if theirs != ours => Ok(Some(block)),
+            _ => Ok(None),
+        }
+    }
+
+    /// The latest block that `peer` has processed for `deployment`
+    async fn peer_head(
+        &self,
+        deployment: &DeploymentLocator,
+        peer: &str,
+    ) -> Result<Option<BlockPtr>, Error> {
+        let status = self
+            .query(
+                peer,
+                STATUS_QUERY,
+                serde_json::json!({ "subgraph": deployment.hash.as_str() }),
+            )
+            .await?;
+        let latest = match status.pointer("/indexingStatuses/0/chains/0/latestBlock") {
+            Some(latest) if !latest.is_null() => latest,
+            _ => return Ok(None),
+        };
+        let hash = latest.get("hash").and_then(|hash| hash.as_str());
+        let number = latest
+            .get("number")
+            .and_then(|number| number.as_str())
+            .and_then(|number| number.parse::<i64>().ok());
+        match (hash, number) {
+            (Some(hash), Some(number)) => BlockPtr::try_from((hash, number)).map(Some),
+            _ => bail!("invalid latest block {}", latest),
+        }
+    }
+
+    /// Run `query` against the index-node API of `peer` and return the
+    /// `data` of the response
+    async fn query(
+        &self,
+        peer: &str,
+        query: &str,
+        variables: serde_json::Value,
+    ) -> Result<serde_json::Value, Error> {
+        let body = serde_json::json!({ "query": query, "variables": variables });
+        let mut response: serde_json::Value = self
+            .client
+            .post(peer)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if let Some(errors) = response.get("errors") {
+            bail!("query returned errors: {}", errors);
+        }
+        response
+            .get_mut("data")
+            .map(serde_json::Value::take)
+            .ok_or_else(|| anyhow!("response has no data"))
+    }
+}
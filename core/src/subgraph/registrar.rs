@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashSet};
 use std::time::Instant;
 
 use async_trait::async_trait;
@@ -7,10 +7,12 @@ use graph::blockchain::BlockchainKind;
 use graph::blockchain::BlockchainMap;
 use graph::components::store::{DeploymentId, DeploymentLocator, SubscriptionManager};
 use graph::data::subgraph::schema::DeploymentCreate;
+use graph::data::subgraph::Graft;
 use graph::prelude::{
     CreateSubgraphResult, SubgraphAssignmentProvider as SubgraphAssignmentProviderTrait,
     SubgraphRegistrar as SubgraphRegistrarTrait, *,
 };
+use graph_runtime_wasm::ValidModule;
 
 pub struct SubgraphRegistrar<P, S, SM> {
     logger: Logger,
@@ -167,13 +169,13 @@ where
                     match operation {
                         EntityChangeOperation::Set => {
                             store
-                                .assigned_node(&deployment)
+                                .assignment_status(&deployment)
                                 .map_err(|e| {
                                     anyhow!("Failed to get subgraph assignment entity: {}", e)
                                 })
                                 .map(|assigned| -> Box<dyn Stream<Item = _, Error = _> + Send> {
-                                    if let Some(assigned) = assigned {
-                                        if assigned == node_id {
+                                    if let Some((assigned, paused)) = assigned {
+                                        if assigned == node_id && !paused {
                                             // Start subgraph on this node
                                             debug!(logger, "Deployment assignee is this node, broadcasting add event"; "assigned_to" => assigned, "node_id" => &node_id);
                                             Box::new(stream::once(Ok(AssignmentEvent::Add {
@@ -182,7 +184,7 @@ where
                                             })))
                                         } else {
                                             // Ensure it is removed from this node
-                                            debug!(logger, "Deployment assignee is not this node, broadcasting remove event"; "assigned_to" => assigned, "node_id" => &node_id);
+                                            debug!(logger, "Deployment assignee is not this node or deployment is paused, broadcasting remove event"; "assigned_to" => assigned, "paused" => paused, "node_id" => &node_id);
                                             Box::new(stream::once(Ok(AssignmentEvent::Remove {
                                                 deployment,
                                                 node_id: node_id.clone(),
@@ -241,6 +243,26 @@ where
                 })
             })
     }
+
+    /// Find the one deployment with `hash`
+    fn locate_deployment(
+        &self,
+        hash: &DeploymentHash,
+    ) -> Result<DeploymentLocator, SubgraphRegistrarError> {
+        let locations = self.store.locators(hash)?;
+        match locations.len() {
+            0 => Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string())),
+            1 => Ok(locations[0].clone()),
+            _ => Err(SubgraphRegistrarError::StoreError(
+                anyhow!(
+                    "there are {} different deployments with id {}",
+                    locations.len(),
+                    hash.as_str()
+                )
+                .into(),
+            )),
+        }
+    }
 }
 
 #[async_trait]
@@ -268,6 +290,8 @@ where
         node_id: NodeId,
         debug_fork: Option<DeploymentHash>,
         start_block: Option<BlockPtr>,
+        auto_graft: bool,
+        upgrade_in_place: bool,
     ) -> Result<(), SubgraphRegistrarError> {
         // We don't have a location for the subgraph yet; that will be
         // assigned when we deploy for real. For logging purposes, make up a
@@ -276,20 +300,7 @@ where
             .logger_factory
             .subgraph_logger(&DeploymentLocator::new(DeploymentId(0), hash.clone()));
 
-        let raw: serde_yaml::Mapping = {
-            let file_bytes = self
-                .resolver
-                .cat(&logger, &hash.to_ipfs_link())
-                .await
-                .map_err(|e| {
-                    SubgraphRegistrarError::ResolveError(
-                        SubgraphManifestResolveError::ResolveError(e),
-                    )
-                })?;
-
-            serde_yaml::from_slice(&file_bytes)
-                .map_err(|e| SubgraphRegistrarError::ResolveError(e.into()))?
-        };
+        let raw = resolve_raw_manifest(&logger, &self.resolver, &hash).await?;
 
         let kind = BlockchainKind::from_manifest(&raw).map_err(|e| {
             SubgraphRegistrarError::ResolveError(SubgraphManifestResolveError::ResolveError(e))
@@ -307,6 +318,8 @@ where
                     raw,
                     node_id,
                     debug_fork,
+                    auto_graft,
+                    upgrade_in_place,
                     self.version_switching_mode,
                     &self.resolver,
                 )
@@ -324,6 +337,8 @@ where
                     raw,
                     node_id,
                     debug_fork,
+                    auto_graft,
+                    upgrade_in_place,
                     self.version_switching_mode,
                     &self.resolver,
                 )
@@ -341,6 +356,8 @@ where
                     raw,
                     node_id,
                     debug_fork,
+                    auto_graft,
+                    upgrade_in_place,
                     self.version_switching_mode,
                     &self.resolver,
                 )
@@ -358,6 +375,64 @@ where
         Ok(())
     }
 
+    async fn validate_subgraph_version(
+        &self,
+        hash: DeploymentHash,
+    ) -> Result<Vec<String>, SubgraphRegistrarError> {
+        let logger = self
+            .logger_factory
+            .subgraph_logger(&DeploymentLocator::new(DeploymentId(0), hash.clone()));
+
+        let raw = match resolve_raw_manifest(&logger, &self.resolver, &hash).await {
+            Ok(raw) => raw,
+            Err(e) => return Ok(vec![e.to_string()]),
+        };
+        let kind = match BlockchainKind::from_manifest(&raw) {
+            Ok(kind) => kind,
+            Err(e) => return Ok(vec![e.to_string()]),
+        };
+
+        let problems = match kind {
+            BlockchainKind::Ethereum => {
+                validate_subgraph_version::<graph_chain_ethereum::Chain, _>(
+                    &logger,
+                    self.store.clone(),
+                    &self.chains,
+                    hash,
+                    raw,
+                    &self.resolver,
+                )
+                .await
+            }
+
+            BlockchainKind::Near => {
+                validate_subgraph_version::<graph_chain_near::Chain, _>(
+                    &logger,
+                    self.store.clone(),
+                    &self.chains,
+                    hash,
+                    raw,
+                    &self.resolver,
+                )
+                .await
+            }
+
+            BlockchainKind::Tendermint => {
+                validate_subgraph_version::<graph_chain_tendermint::Chain, _>(
+                    &logger,
+                    self.store.clone(),
+                    &self.chains,
+                    hash,
+                    raw,
+                    &self.resolver,
+                )
+                .await
+            }
+        };
+
+        Ok(problems)
+    }
+
     async fn remove_subgraph(&self, name: SubgraphName) -> Result<(), SubgraphRegistrarError> {
         self.store.clone().remove_subgraph(name.clone())?;
 
@@ -375,25 +450,67 @@ where
         hash: &DeploymentHash,
         node_id: &NodeId,
     ) -> Result<(), SubgraphRegistrarError> {
-        let locations = self.store.locators(hash)?;
-        let deployment = match locations.len() {
-            0 => return Err(SubgraphRegistrarError::DeploymentNotFound(hash.to_string())),
-            1 => locations[0].clone(),
-            _ => {
-                return Err(SubgraphRegistrarError::StoreError(
-                    anyhow!(
-                        "there are {} different deployments with id {}",
-                        locations.len(),
-                        hash.as_str()
-                    )
-                    .into(),
-                ))
-            }
-        };
+        let deployment = self.locate_deployment(hash)?;
         self.store.reassign_subgraph(&deployment, node_id)?;
 
         Ok(())
     }
+
+    /// Stop indexing a subgraph deployment without unassigning it, so that
+    /// it resumes on the same node
+    async fn pause_subgraph(&self, hash: &DeploymentHash) -> Result<(), SubgraphRegistrarError> {
+        let deployment = self.locate_deployment(hash)?;
+        self.store.pause_subgraph(&deployment)?;
+
+        debug!(self.logger, "Paused subgraph"; "subgraph_hash" => hash.to_string());
+
+        Ok(())
+    }
+
+    /// Resume indexing a subgraph deployment that was paused
+    async fn resume_subgraph(&self, hash: &DeploymentHash) -> Result<(), SubgraphRegistrarError> {
+        let deployment = self.locate_deployment(hash)?;
+        self.store.resume_subgraph(&deployment)?;
+
+        debug!(self.logger, "Resumed subgraph"; "subgraph_hash" => hash.to_string());
+
+        Ok(())
+    }
+
+    /// Stop indexing a subgraph deployment by removing its assignment
+    async fn unassign_subgraph(&self, hash: &DeploymentHash) -> Result<(), SubgraphRegistrarError> {
+        let deployment = self.locate_deployment(hash)?;
+        self.store.unassign_subgraph(&deployment)?;
+
+        debug!(self.logger, "Unassigned subgraph"; "subgraph_hash" => hash.to_string());
+
+        Ok(())
+    }
+
+    /// Revert a subgraph deployment to an earlier block. The deployment
+    /// must be paused or unassigned so that it is not indexed while we
+    /// rewind it
+    async fn rewind_subgraph(
+        &self,
+        hash: &DeploymentHash,
+        block_ptr_to: BlockPtr,
+    ) -> Result<(), SubgraphRegistrarError> {
+        let deployment = self.locate_deployment(hash)?;
+        if let Some((_, false)) = self.store.assignment_status(&deployment)? {
+            return Err(SubgraphRegistrarError::DeploymentNotPaused(
+                hash.to_string(),
+            ));
+        }
+        self.store
+            .rewind_subgraph(&deployment, block_ptr_to.clone())?;
+
+        debug!(self.logger, "Rewound subgraph";
+            "subgraph_hash" => hash.to_string(),
+            "block" => block_ptr_to.to_string(),
+        );
+
+        Ok(())
+    }
 }
 
 async fn handle_assignment_event(
@@ -508,6 +625,196 @@ async fn resolve_subgraph_chain_blocks(
     Ok((start_block_ptr, base_ptr))
 }
 
+/// Fetch the manifest for `hash` and parse it as YAML
+async fn resolve_raw_manifest(
+    logger: &Logger,
+    resolver: &Arc<dyn LinkResolver>,
+    hash: &DeploymentHash,
+) -> Result<serde_yaml::Mapping, SubgraphRegistrarError> {
+    let file_bytes = resolver
+        .cat(logger, &hash.to_ipfs_link())
+        .await
+        .map_err(|e| {
+            SubgraphRegistrarError::ResolveError(SubgraphManifestResolveError::ResolveError(e))
+        })?;
+
+    serde_yaml::from_slice(&file_bytes).map_err(|e| SubgraphRegistrarError::ResolveError(e.into()))
+}
+
+/// Check everything about the subgraph `deployment` that deploying it
+/// would check, and also that its mappings compile, and return all the
+/// problems found. Nothing about the subgraph is written to the store
+async fn validate_subgraph_version<C: Blockchain, S: SubgraphStore>(
+    logger: &Logger,
+    store: Arc<S>,
+    chains: &BlockchainMap,
+    deployment: DeploymentHash,
+    raw: serde_yaml::Mapping,
+    resolver: &Arc<dyn LinkResolver>,
+) -> Vec<String> {
+    let unvalidated = match UnvalidatedSubgraphManifest::<C>::resolve(
+        deployment,
+        raw,
+        resolver,
+        logger,
+        ENV_VARS.max_spec_version.clone(),
+    )
+    .await
+    {
+        Ok(unvalidated) => unvalidated,
+        Err(e) => return vec![e.to_string()],
+    };
+
+    let mut problems = Vec::new();
+    let manifest = unvalidated.as_manifest();
+
+    let networks: BTreeSet<_> = manifest
+        .data_sources
+        .iter()
+        .filter_map(|ds| ds.network().map(|network| network.to_string()))
+        .collect();
+    for network in networks {
+        if let Err(e) = chains.get::<C>(network) {
+            problems.push(SubgraphRegistrarError::NetworkNotSupported(e).to_string());
+        }
+    }
+
+    // Data sources and templates often share their mapping; only compile
+    // each distinct mapping once
+    let mut compiled = HashSet::new();
+    let mappings = manifest
+        .data_sources
+        .iter()
+        .map(|ds| (ds.name(), ds.runtime()))
+        .chain(manifest.templates.iter().map(|t| (t.name(), t.runtime())));
+    for (name, runtime) in mappings {
+        if !compiled.insert(runtime) {
+            continue;
+        }
+        if let Err(e) = ValidModule::new(runtime) {
+            problems.push(format!(
+                "the mapping for `{}` does not compile: {:#}",
+                name, e
+            ));
+        }
+    }
+
+    if let Err(errors) = unvalidated.validate(store, true).await {
+        problems.extend(errors.into_iter().map(|e| e.to_string()));
+    }
+    problems
+}
+
+/// The graft onto the head of the current version of `name` for a new
+/// version with `manifest`, or `None` if the new version can not be grafted
+/// onto it. Reasons for not grafting are logged
+async fn auto_graft<C: Blockchain, S: SubgraphStore>(
+    logger: &Logger,
+    store: &S,
+    name: &SubgraphName,
+    manifest: &SubgraphManifest<C>,
+) -> Result<Option<Graft>, SubgraphRegistrarError> {
+    if ENV_VARS.disable_grafts {
+        info!(
+            logger,
+            "Not grafting automatically since grafting is disabled"
+        );
+        return Ok(None);
+    }
+
+    let base = match store.current_deployment(name)? {
+        Some(base) if base != manifest.id => base,
+        _ => {
+            info!(logger, "Not grafting automatically since there is no other current version";
+                  "subgraph_name" => name.to_string());
+            return Ok(None);
+        }
+    };
+
+    let block = match store.least_block_ptr(&base).await? {
+        Some(ptr) => ptr.number,
+        None => {
+            info!(logger, "Not grafting automatically since the current version has not processed any blocks";
+                  "base" => base.to_string());
+            return Ok(None);
+        }
+    };
+
+    let errors = store.graft_incompatibilities(&base, &manifest.schema)?;
+    if !errors.is_empty() {
+        info!(logger, "Not grafting automatically since the schemas are incompatible";
+              "base" => base.to_string(),
+              "errors" => errors.join("; "));
+        return Ok(None);
+    }
+
+    Ok(Some(Graft { base, block }))
+}
+
+/// Upgrade the current version of `name` to the schema of `manifest`
+/// instead of deploying `manifest` as a new version that has to be indexed
+/// from scratch. That is only possible if the manifest `raw` only differs
+/// from the manifest of the current version in its schema, so that the
+/// mappings produce exactly the same data, and if the new schema only adds
+/// entity types and nullable attributes. The current version keeps its
+/// deployment hash
+async fn upgrade_in_place<C: Blockchain, S: SubgraphStore>(
+    logger: &Logger,
+    store: &S,
+    resolver: &Arc<dyn LinkResolver>,
+    name: &SubgraphName,
+    manifest: &SubgraphManifest<C>,
+    mut raw: serde_yaml::Mapping,
+) -> Result<(), SubgraphRegistrarError> {
+    fn impossible(msg: String) -> SubgraphRegistrarError {
+        SubgraphRegistrarError::UpgradeInPlaceImpossible(vec![msg])
+    }
+
+    // Everything in the manifest besides these keys must stay the same
+    fn strip(raw: &mut serde_yaml::Mapping) {
+        for key in &["schema", "description", "repository"] {
+            raw.remove(&serde_yaml::Value::from(*key));
+        }
+    }
+
+    let current = match store.current_deployment(name)? {
+        Some(current) => current,
+        None => {
+            return Err(impossible(format!(
+                "subgraph `{}` has no current version",
+                name
+            )))
+        }
+    };
+    if current == manifest.id {
+        return Err(impossible(format!(
+            "`{}` already is the current version of `{}`",
+            current, name
+        )));
+    }
+
+    let mut current_raw = resolve_raw_manifest(logger, resolver, &current).await?;
+    strip(&mut raw);
+    strip(&mut current_raw);
+    if raw != current_raw {
+        return Err(impossible(format!(
+            "the manifest of `{}` differs from the manifest of `{}` in more than its schema",
+            manifest.id, current
+        )));
+    }
+
+    let errors = store.upgrade_schema(&current, &manifest.schema)?;
+    if !errors.is_empty() {
+        return Err(SubgraphRegistrarError::UpgradeInPlaceImpossible(errors));
+    }
+
+    info!(logger, "Upgraded the current version in place";
+          "subgraph_name" => name.to_string(),
+          "deployment" => current.to_string(),
+          "schema_from" => manifest.id.to_string());
+    Ok(())
+}
+
 async fn create_subgraph_version<C: Blockchain, S: SubgraphStore>(
     logger: &Logger,
     store: Arc<S>,
@@ -518,12 +825,14 @@ async fn create_subgraph_version<C: Blockchain, S: SubgraphStore>(
     raw: serde_yaml::Mapping,
     node_id: NodeId,
     debug_fork: Option<DeploymentHash>,
+    auto_graft_requested: bool,
+    upgrade_in_place_requested: bool,
     version_switching_mode: SubgraphVersionSwitchingMode,
     resolver: &Arc<dyn LinkResolver>,
 ) -> Result<(), SubgraphRegistrarError> {
     let unvalidated = UnvalidatedSubgraphManifest::<C>::resolve(
         deployment,
-        raw,
+        raw.clone(),
         &resolver,
         &logger,
         ENV_VARS.max_spec_version.clone(),
@@ -531,7 +840,7 @@ async fn create_subgraph_version<C: Blockchain, S: SubgraphStore>(
     .map_err(SubgraphRegistrarError::ResolveError)
     .await?;
 
-    let manifest = unvalidated
+    let mut manifest = unvalidated
         .validate(store.cheap_clone(), true)
         .await
         .map_err(SubgraphRegistrarError::ManifestValidationError)?;
@@ -556,6 +865,15 @@ async fn create_subgraph_version<C: Blockchain, S: SubgraphStore>(
         return Err(SubgraphRegistrarError::NameNotFound(name.to_string()));
     }
 
+    if upgrade_in_place_requested {
+        return upgrade_in_place(&logger, store.as_ref(), resolver, &name, &manifest, raw).await;
+    }
+
+    // A graft in the manifest always takes precedence
+    if auto_graft_requested && manifest.graft.is_none() {
+        manifest.graft = auto_graft(&logger, store.as_ref(), &name, &manifest).await?;
+    }
+
     let (manifest_start_block, base_block) =
         resolve_subgraph_chain_blocks(&manifest, chain, &logger.clone()).await?;
 
@@ -0,0 +1,174 @@
+use std::sync::Arc;
+
+use graph::blockchain::{
+    Block, Blockchain, DataSourceTemplate as _, NodeCapabilities as _, TriggerData,
+    TriggerFilter as _, TriggersAdapter as _,
+};
+use graph::components::store::{DeploymentLocator, SubgraphStore};
+use graph::components::subgraph::{CausalityRegion, HostTrace, MappingError};
+use graph::data::subgraph::schema::{MappingLog, SubgraphError};
+use graph::data::subgraph::SubgraphFeature;
+use graph::prelude::{
+    anyhow::anyhow, info, serde_yaml, BlockNumber, BlockPtr, BlockState, CheapClone,
+    EntityModification, Error, HostMetrics, LinkResolver, Logger, MetricsRegistry,
+    StopwatchMetrics, ENV_VARS,
+};
+use graph::util::lfu_cache::LfuCache;
+
+use crate::subgraph::loader::resolve_with_dynamic_data_sources;
+use crate::subgraph::metrics::SubgraphInstanceMetrics;
+use crate::subgraph::SubgraphInstance;
+
+/// What happened when a trigger in a replayed block was processed
+pub struct TriggerReplay {
+    /// A description of the trigger
+    pub trigger: String,
+    /// The handlers and host functions that ran for the trigger
+    pub trace: Vec<HostTrace>,
+}
+
+/// The outcome of running the mappings of a deployment for one block
+pub struct BlockReplay {
+    pub block: BlockPtr,
+    pub triggers: Vec<TriggerReplay>,
+    /// The entity changes the block would have made
+    pub modifications: Vec<EntityModification>,
+    /// Messages that the mappings logged; they are only collected if
+    /// `GRAPH_MAPPING_LOG_LIMIT` is set
+    pub mapping_logs: Vec<MappingLog>,
+    pub deterministic_errors: Vec<SubgraphError>,
+    /// The names of the templates from which the block created data sources
+    pub created_data_sources: Vec<String>,
+}
+
+/// Run the mappings of `deployment` for the triggers in block `number`
+/// without writing anything to the store. The mappings see the entities
+/// as of the current head of the deployment, not as of the block before
+/// `number`, and the data sources that were created before `number`
+pub async fn replay_block<C: Blockchain, S: SubgraphStore>(
+    logger: &Logger,
+    subgraph_store: Arc<S>,
+    chain: Arc<C>,
+    link_resolver: Arc<dyn LinkResolver>,
+    registry: Arc<dyn MetricsRegistry>,
+    deployment: &DeploymentLocator,
+    manifest: serde_yaml::Mapping,
+    number: BlockNumber,
+) -> Result<BlockReplay, Error> {
+    let store = subgraph_store
+        .cheap_clone()
+        .writable(logger.clone(), deployment.id)
+        .await?;
+
+    let manifest = resolve_with_dynamic_data_sources::<C>(
+        logger,
+        store.clone(),
+        &link_resolver,
+        deployment,
+        manifest,
+        number - 1,
+    )
+    .await?;
+
+    let filter = C::TriggerFilter::from_data_sources(manifest.data_sources.iter());
+    let required_capabilities = C::NodeCapabilities::from_data_sources(&manifest.data_sources);
+    let triggers_adapter = chain.triggers_adapter(
+        deployment,
+        &required_capabilities,
+        manifest.unified_mapping_api_version()?,
+    )?;
+
+    let block = triggers_adapter
+        .scan_triggers(number, number, &filter)
+        .await?
+        .into_iter()
+        .find(|block| block.block.number() == number)
+        .ok_or_else(|| anyhow!("block {} is not available", number))?;
+    let triggers = block.trigger_data;
+    let block = Arc::new(block.block);
+    info!(logger, "Replaying block"; "block" => block.ptr().to_string(),
+                  "triggers" => triggers.len());
+
+    let debug_fork = subgraph_store.debug_fork(&deployment.hash, logger.clone())?;
+    let skipped: Vec<_> = subgraph_store
+        .skipped_blocks(&deployment.hash)?
+        .into_iter()
+        .filter(|skip| skip.block_number == number)
+        .collect();
+    let quarantined = subgraph_store.quarantined_data_sources(&deployment.hash)?;
+    let quarantine = manifest
+        .features
+        .contains(&SubgraphFeature::QuarantineDataSources);
+
+    let stopwatch = StopwatchMetrics::new(
+        logger.clone(),
+        deployment.hash.clone(),
+        registry.cheap_clone(),
+    );
+    let host_metrics = Arc::new(
+        HostMetrics::new(registry.cheap_clone(), deployment.hash.as_str(), stopwatch).with_trace(),
+    );
+    let subgraph_metrics = Arc::new(SubgraphInstanceMetrics::new(
+        registry,
+        deployment.hash.as_str(),
+    ));
+    let host_builder = graph_runtime_wasm::RuntimeHostBuilder::new(
+        chain.runtime_adapter(),
+        link_resolver,
+        subgraph_store.ens_lookup(),
+    )
+    .with_timeout(ENV_VARS.mappings.timeout);
+    let instance =
+        SubgraphInstance::from_manifest(logger, manifest, host_builder, host_metrics.clone())?;
+    let causality_region = CausalityRegion::from_network(instance.network());
+
+    let mut state = BlockState::new(store, LfuCache::new());
+    let mut replays = Vec::new();
+    for trigger in triggers {
+        state = instance
+            .process_trigger(
+                logger,
+                &block,
+                &trigger,
+                state,
+                &None,
+                &causality_region,
+                &debug_fork,
+                &subgraph_metrics,
+                &skipped,
+                quarantine.then(|| quarantined.as_slice()),
+            )
+            .await
+            .map_err(|e| match e {
+                MappingError::Unknown(e) | MappingError::PossibleReorg(e) => {
+                    e.context(format!("failed to process {}", trigger.error_context()))
+                }
+            })?;
+        replays.push(TriggerReplay {
+            trigger: trigger.error_context(),
+            trace: host_metrics.take_trace(),
+        });
+    }
+
+    let created_data_sources = state
+        .drain_created_data_sources()
+        .into_iter()
+        .map(|info| info.template.name().to_string())
+        .collect();
+    let BlockState {
+        entity_cache,
+        mapping_logs,
+        deterministic_errors,
+        ..
+    } = state;
+    let modifications = entity_cache.as_modifications()?.modifications;
+
+    Ok(BlockReplay {
+        block: block.ptr(),
+        triggers: replays,
+        modifications,
+        mapping_logs,
+        deterministic_errors,
+        created_data_sources,
+    })
+}
@@ -8,24 +8,26 @@ use crate::subgraph::SubgraphInstance;
 use atomic_refcell::AtomicRefCell;
 use fail::fail_point;
 use graph::blockchain::block_stream::{BlockStreamEvent, BlockWithTriggers};
-use graph::blockchain::{Block, Blockchain, DataSource, TriggerFilter as _, TriggersAdapter};
+use graph::blockchain::{
+    Block, Blockchain, DataSource, RuntimeAdapter as _, TriggerFilter as _, TriggersAdapter,
+};
 use graph::components::{
     store::ModificationsAndCache,
     subgraph::{CausalityRegion, MappingError, ProofOfIndexing, SharedProofOfIndexing},
 };
 use graph::data::store::scalar::Bytes;
 use graph::data::subgraph::{
-    schema::{SubgraphError, SubgraphHealth, POI_OBJECT},
+    retry::ErrorClass,
+    schema::{QuarantinedDataSource, SkippedBlock, SubgraphError, SubgraphHealth, POI_OBJECT},
     SubgraphFeature,
 };
 use graph::prelude::*;
-use graph::util::{backoff::ExponentialBackoff, lfu_cache::LfuCache};
+use graph::util::lfu_cache::LfuCache;
+use graph::util::otel::{self, Span};
 use std::convert::TryFrom;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-const MINUTE: Duration = Duration::from_secs(60);
-
 const SKIP_PTR_UPDATES_THRESHOLD: Duration = Duration::from_secs(60 * 5);
 
 pub struct SubgraphRunner<C: Blockchain, T: RuntimeHostBuilder<C>> {
@@ -42,26 +44,39 @@ where
     T: RuntimeHostBuilder<C>,
 {
     pub fn new(
-        inputs: IndexingInputs<C>,
+        mut inputs: IndexingInputs<C>,
         ctx: IndexingContext<T, C>,
         logger: Logger,
         metrics: RunnerMetrics,
     ) -> Self {
+        let quarantined = std::mem::take(&mut inputs.quarantined);
+        let backoff = inputs.retry_policy.backoff();
         Self {
             inputs: Arc::new(inputs),
             ctx,
             state: IndexingState {
                 should_try_unfail_non_deterministic: true,
                 synced: false,
+                promoted: false,
                 skip_ptr_updates_timer: Instant::now(),
-                backoff: ExponentialBackoff::new(MINUTE * 2, ENV_VARS.subgraph_error_retry_ceil),
+                backoff,
                 entity_lfu_cache: LfuCache::new(),
+                quarantined,
             },
             logger,
             metrics,
         }
     }
 
+    /// The quarantined data sources if the deployment uses the
+    /// `quarantineDataSources` feature, and `None` otherwise
+    fn quarantined(&self) -> Option<&[QuarantinedDataSource]> {
+        self.inputs
+            .features
+            .contains(&SubgraphFeature::QuarantineDataSources)
+            .then(|| self.state.quarantined.as_slice())
+    }
+
     pub async fn run(mut self) -> Result<(), Error> {
         // If a subgraph failed for deterministic reasons, before start indexing, we first
         // revert the deployment head. It should lead to the same result since the error was
@@ -79,32 +94,76 @@ where
                 // There's no point in calling it if we have no current or parent block
                 // pointers, because there would be: no block to revert to or to search
                 // errors from (first execution).
-                let _outcome = self
+                let outcome = self
                     .inputs
                     .store
-                    .unfail_deterministic_error(&current_ptr, &parent_ptr)?;
+                    .unfail_deterministic_error(&current_ptr, &parent_ptr)
+                    .await?;
+                if outcome == UnfailOutcome::Unfailed {
+                    self.state.revert_quarantined(parent_ptr.number);
+                }
             }
         }
 
+        // Blocks are only written once they have been processed completely;
+        // stopping between blocks therefore never leaves a block half done
+        let mut shutdown = self.inputs.shutdown.clone();
+
         loop {
             debug!(self.logger, "Starting or restarting subgraph");
 
             let block_stream_canceler = CancelGuard::new();
             let block_stream_cancel_handle = block_stream_canceler.handle();
 
-            let mut block_stream = new_block_stream(&self.inputs, &self.ctx.filter)
+            let is_firehose = self.inputs.chain.is_firehose_supported();
+            let mut block_stream = new_block_stream(&self.inputs, &self.ctx.filter, is_firehose)
                 .await?
                 .map_err(CancelableError::Error)
                 .cancelable(&block_stream_canceler, || Err(CancelableError::Cancel));
 
-            debug!(self.logger, "Starting block stream");
+            // Chains can switch between Firehose and RPC providers, e.g.,
+            // when all Firehose endpoints are down; check regularly whether
+            // the block stream needs to be replaced
+            let interval = ENV_VARS.firehose_health_check_interval;
+            let mut stream_kind_check =
+                tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+
+            debug!(self.logger, "Starting block stream"; "firehose" => is_firehose);
 
             // Process events from the stream as long as no restart is needed
             loop {
+                if *shutdown.borrow() {
+                    info!(self.logger, "Stopping subgraph for shutdown");
+                    self.inputs.store.flush().await?;
+                    return Ok(());
+                }
+
+                // Deployments that are still syncing take turns scanning
+                // and processing blocks, with preference for the ones with
+                // a higher priority. The permit is released after each
+                // block
+                let _permit = match &self.inputs.sync_scheduler {
+                    Some(scheduler) if !self.state.synced => {
+                        Some(scheduler.acquire(self.inputs.priority).await)
+                    }
+                    _ => None,
+                };
+
                 let event = {
                     let _section = self.metrics.stream.stopwatch.start_section("scan_blocks");
 
-                    block_stream.next().await
+                    tokio::select! {
+                        event = block_stream.next() => event,
+                        Ok(()) = shutdown.changed() => continue,
+                        _ = stream_kind_check.tick() => {
+                            if self.inputs.chain.is_firehose_supported() == is_firehose {
+                                continue;
+                            }
+                            info!(self.logger, "Restarting block stream to switch between Firehose and RPC";
+                                               "firehose" => !is_firehose);
+                            break;
+                        }
+                    }
                 };
 
                 // TODO: move cancel handle to the Context
@@ -114,7 +173,10 @@ where
                     .await?
                 {
                     Action::Continue => continue,
-                    Action::Stop => return Ok(()),
+                    Action::Stop => {
+                        self.inputs.store.flush().await?;
+                        return Ok(());
+                    }
                     Action::Restart => break,
                 };
             }
@@ -148,6 +210,21 @@ where
             );
         }
 
+        // Operators can tell us to skip blocks, or some handlers in them,
+        // that make the subgraph fail and can not be fixed otherwise
+        let skipped: Vec<SkippedBlock> = self
+            .inputs
+            .skipped_blocks
+            .iter()
+            .filter(|skip| skip.block_number == block_ptr.number)
+            .cloned()
+            .collect();
+        for skip in &skipped {
+            warn!(&logger, "Skipping handlers in this block as requested by an operator";
+                           "handler" => skip.handler.as_deref().unwrap_or("all"),
+                           "reason" => &skip.reason);
+        }
+
         let proof_of_indexing = if self.inputs.store.supports_proof_of_indexing().await? {
             Some(Arc::new(AtomicRefCell::new(ProofOfIndexing::new(
                 block_ptr.number,
@@ -162,7 +239,13 @@ where
         // Process events one after the other, passing in entity operations
         // collected previously to every new event being processed
         let mut block_state = match self
-            .process_triggers(&proof_of_indexing, &block, triggers, &causality_region)
+            .process_triggers(
+                &proof_of_indexing,
+                &block,
+                triggers,
+                &causality_region,
+                &skipped,
+            )
             .await
         {
             // Triggers processed with no errors or with only deterministic errors.
@@ -207,11 +290,13 @@ where
             let filter = C::TriggerFilter::from_data_sources(data_sources.iter());
 
             // Reprocess the triggers from this block that match the new data sources
+            let span = Span::new("triggers_in_block");
             let block_with_triggers = self
                 .inputs
                 .triggers_adapter
                 .triggers_in_block(&logger, block.as_ref().clone(), &filter)
                 .await?;
+            span.end();
 
             let triggers = block_with_triggers.trigger_data;
 
@@ -245,6 +330,8 @@ where
                     &causality_region,
                     &self.inputs.debug_fork,
                     &self.metrics.subgraph,
+                    &skipped,
+                    self.quarantined(),
                 )
                 .await
                 .map_err(|e| {
@@ -262,10 +349,13 @@ where
         }
 
         let has_errors = block_state.has_errors();
+        // When data sources are quarantined, deterministic errors only
+        // stop the data source that caused them
         let is_non_fatal_errors_active = self
             .inputs
             .features
-            .contains(&SubgraphFeature::NonFatalErrors);
+            .contains(&SubgraphFeature::NonFatalErrors)
+            || self.quarantined().is_some();
 
         // Apply entity operations and advance the stream
 
@@ -338,11 +428,23 @@ where
 
         let BlockState {
             deterministic_errors,
+            mapping_logs,
+            quarantined,
             ..
         } = block_state;
 
         let first_error = deterministic_errors.first().cloned();
 
+        // Far behind the chain head, writing entities with `COPY` is faster
+        let bulk = match ENV_VARS.store.bulk_write_distance {
+            Some(distance) => is_far_from_head(
+                &block_ptr,
+                self.inputs.chain.chain_store().cached_head_ptr().await?,
+                distance,
+            ),
+            None => false,
+        };
+
         store
             .transact_block_operations(
                 block_ptr,
@@ -351,8 +453,13 @@ where
                 &self.metrics.host.stopwatch,
                 data_sources,
                 deterministic_errors,
+                mapping_logs,
+                quarantined.clone(),
+                bulk,
             )
+            .await
             .context("Failed to transact block operations")?;
+        self.state.quarantined.extend(quarantined);
 
         // For subgraphs with `nonFatalErrors` feature disabled, we consider
         // any error as fatal.
@@ -366,17 +473,19 @@ where
             return Err(BlockProcessingError::Deterministic(first_error.unwrap()));
         }
 
-        let elapsed = start.elapsed().as_secs_f64();
+        let elapsed = start.elapsed();
+        self.inputs.load.record_block(elapsed);
         self.metrics
             .subgraph
             .block_ops_transaction_duration
-            .observe(elapsed);
+            .observe(elapsed.as_secs_f64());
 
         // To prevent a buggy pending version from replacing a current version, if errors are
         // present the subgraph will be unassigned.
         if has_errors && !ENV_VARS.disable_fail_fast && !store.is_deployment_synced().await? {
             store
                 .unassign_subgraph()
+                .await
                 .map_err(|e| BlockProcessingError::Unknown(e.into()))?;
 
             // Use `Canceled` to avoiding setting the subgraph health to failed, an error was
@@ -396,6 +505,7 @@ where
         block: &Arc<C::Block>,
         triggers: Vec<C::TriggerData>,
         causality_region: &str,
+        skipped: &[SkippedBlock],
     ) -> Result<BlockState<C>, MappingError> {
         let mut block_state = BlockState::new(
             self.inputs.store.clone(),
@@ -404,6 +514,21 @@ where
 
         use graph::blockchain::TriggerData;
 
+        if let Err(e) = self
+            .inputs
+            .chain
+            .runtime_adapter()
+            .prefetch(
+                &self.logger,
+                block,
+                &self.ctx.instance.data_sources(),
+                &triggers,
+            )
+            .await
+        {
+            warn!(self.logger, "Prefetching data for handlers failed"; "error" => e.to_string());
+        }
+
         for trigger in triggers {
             block_state = self
                 .ctx
@@ -417,6 +542,8 @@ where
                     causality_region,
                     &self.inputs.debug_fork,
                     &self.metrics.subgraph,
+                    skipped,
+                    self.quarantined(),
                 )
                 .await
                 .map_err(move |mut e| {
@@ -593,15 +720,27 @@ where
 
         let start = Instant::now();
 
-        let res = self
-            .process_block(&cancel_handle, block, cursor.into())
-            .await;
+        let mut span = Span::root("process_block", None)
+            .attr("deployment", &self.inputs.deployment.hash)
+            .attr("block.number", block_ptr.number)
+            .attr("block.hash", &block_ptr.hash)
+            .attr("block.trigger_count", block.trigger_count());
+        let res = otel::in_span(
+            &span,
+            self.process_block(&cancel_handle, block, cursor.into()),
+        )
+        .await;
+        if let Err(e) = &res {
+            span.set_error(e);
+        }
+        span.end();
 
         let elapsed = start.elapsed().as_secs_f64();
         self.metrics
             .subgraph
             .block_processing_duration
             .observe(elapsed);
+        self.metrics.host.observe_block_handler_triggers();
 
         match res {
             Ok(action) => {
@@ -615,7 +754,7 @@ where
                     // Updating the sync status is an one way operation.
                     // This state change exists: not synced -> synced
                     // This state change does NOT: synced -> not synced
-                    self.inputs.store.deployment_synced()?;
+                    self.inputs.store.deployment_synced().await?;
 
                     // Stop trying to update the sync status.
                     self.state.synced = true;
@@ -624,6 +763,26 @@ where
                     self.metrics.stream.stopwatch.disable();
                 }
 
+                // A canary replaces the current version once it is healthy
+                // and close enough to the chain head
+                if ENV_VARS.subgraph_version_switching_mode.is_canary()
+                    && !self.state.promoted
+                    && is_within_blocks_of_head(
+                        &block_ptr,
+                        self.inputs.chain.chain_store().cached_head_ptr().await?,
+                        ENV_VARS.canary_max_blocks_behind,
+                    )
+                    && self
+                        .inputs
+                        .store
+                        .health(&self.inputs.deployment.hash)
+                        .await?
+                        == SubgraphHealth::Healthy
+                {
+                    self.inputs.store.promote_canary().await?;
+                    self.state.promoted = true;
+                }
+
                 // Keep trying to unfail subgraph for everytime it advances block(s) until it's
                 // health is not Failed anymore.
                 if self.state.should_try_unfail_non_deterministic {
@@ -632,7 +791,8 @@ where
                     let outcome = self
                         .inputs
                         .store
-                        .unfail_non_deterministic_error(&block_ptr)?;
+                        .unfail_non_deterministic_error(&block_ptr)
+                        .await?;
 
                     if let UnfailOutcome::Unfailed = outcome {
                         // Stop trying to unfail.
@@ -707,6 +867,13 @@ where
                         return Err(err);
                     }
                     false => {
+                        let message = format!("{:#}", e).replace("\n", "\t");
+                        let class = ErrorClass::of(&message);
+                        let retry = self
+                            .inputs
+                            .retry_policy
+                            .should_retry(class, self.state.backoff.attempt);
+
                         // Shouldn't fail subgraph if it's already failed for non-deterministic
                         // reasons.
                         //
@@ -730,6 +897,13 @@ where
                                 .context("Failed to set subgraph status to `failed`")?;
                         }
 
+                        if !retry {
+                            error!(self.logger, "Subgraph failed with non-deterministic error and will not be retried: {}", message;
+                                "error_class" => class.to_string(),
+                                "attempts" => self.state.backoff.attempt);
+                            return Err(err);
+                        }
+
                         // Retry logic below:
 
                         // Cancel the stream for real.
@@ -739,8 +913,8 @@ where
                             .unwrap()
                             .remove(&self.inputs.deployment.id);
 
-                        let message = format!("{:#}", e).replace("\n", "\t");
                         error!(self.logger, "Subgraph failed with non-deterministic error: {}", message;
+                            "error_class" => class.to_string(),
                             "attempt" => self.state.backoff.attempt,
                             "retry_delay_s" => self.state.backoff.delay().as_secs());
 
@@ -778,6 +952,7 @@ where
             .inputs
             .store
             .revert_block_operations(revert_to_ptr, cursor.as_deref())
+            .await
         {
             error!(&self.logger, "Could not revert block. Retrying"; "error" => %e);
 
@@ -804,6 +979,7 @@ where
         // match any data sources.
         self.ctx.instance.revert_data_sources(subgraph_ptr.number);
         self.state.entity_lfu_cache = LfuCache::new();
+        self.state.revert_quarantined(revert_to_ptr.number);
 
         Ok(Action::Continue)
     }
@@ -877,7 +1053,26 @@ async fn update_proof_of_indexing(
 
 /// Checks if the Deployment BlockPtr is at least one block behind to the chain head.
 fn is_deployment_synced(deployment_head_ptr: &BlockPtr, chain_head_ptr: Option<BlockPtr>) -> bool {
-    matches!((deployment_head_ptr, &chain_head_ptr), (b1, Some(b2)) if b1.number >= (b2.number - 1))
+    is_within_blocks_of_head(deployment_head_ptr, chain_head_ptr, 1)
+}
+
+/// Checks if the Deployment BlockPtr is at most `blocks` blocks behind the chain head.
+fn is_within_blocks_of_head(
+    deployment_head_ptr: &BlockPtr,
+    chain_head_ptr: Option<BlockPtr>,
+    blocks: BlockNumber,
+) -> bool {
+    matches!((deployment_head_ptr, &chain_head_ptr), (b1, Some(b2)) if b1.number >= (b2.number - blocks))
+}
+
+/// Checks if the Deployment BlockPtr is more than `blocks` blocks behind the
+/// chain head. If the chain head is not known, it is not
+fn is_far_from_head(
+    deployment_head_ptr: &BlockPtr,
+    chain_head_ptr: Option<BlockPtr>,
+    blocks: BlockNumber,
+) -> bool {
+    matches!(chain_head_ptr, Some(head) if deployment_head_ptr.number < head.number - blocks)
 }
 
 #[test]
@@ -905,4 +1100,24 @@ fn test_is_deployment_synced() {
 
     assert!(is_deployment_synced(&block_1, Some(block_2.clone())));
     assert!(is_deployment_synced(&block_2, Some(block_2.clone())));
+
+    assert!(!is_within_blocks_of_head(&block_0, None, 10));
+    assert!(!is_within_blocks_of_head(
+        &block_0,
+        Some(block_2.clone()),
+        1
+    ));
+    assert!(is_within_blocks_of_head(&block_0, Some(block_2.clone()), 2));
+    assert!(is_within_blocks_of_head(
+        &block_1,
+        Some(block_2.clone()),
+        10
+    ));
+
+    // Far from the head, entities are written with `COPY`, close to the
+    // head and when the head is not known with `INSERT`
+    assert!(is_far_from_head(&block_0, Some(block_2.clone()), 1));
+    assert!(!is_far_from_head(&block_1, Some(block_2.clone()), 1));
+    assert!(!is_far_from_head(&block_2, Some(block_2.clone()), 1));
+    assert!(!is_far_from_head(&block_0, None, 1));
 }
@@ -0,0 +1,83 @@
+use graph::data::subgraph::schema::DeploymentPriority;
+use graph::prelude::tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use graph::prelude::CheapClone;
+use std::sync::Arc;
+
+/// Limits how many syncing deployments scan blocks and write them to the
+/// database at the same time. All deployments compete for the same
+/// permits, but deployments with priority `normal` may only hold three
+/// quarters, and deployments with priority `low` only a quarter of them,
+/// so that there are always permits left for deployments with priority
+/// `high`
+pub struct SyncScheduler {
+    permits: Arc<Semaphore>,
+    normal: Arc<Semaphore>,
+    low: Arc<Semaphore>,
+}
+
+/// Allows a deployment to scan and process blocks until it is dropped
+pub struct SyncPermit {
+    _class: Option<OwnedSemaphorePermit>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl SyncScheduler {
+    pub fn new(concurrency: usize) -> Self {
+        let concurrency = concurrency.max(1);
+        SyncScheduler {
+            permits: Arc::new(Semaphore::new(concurrency)),
+            normal: Arc::new(Semaphore::new((concurrency * 3 / 4).max(1))),
+            low: Arc::new(Semaphore::new((concurrency / 4).max(1))),
+        }
+    }
+
+    pub async fn acquire(&self, priority: DeploymentPriority) -> SyncPermit {
+        let class = match priority {
+            DeploymentPriority::High => None,
+            DeploymentPriority::Normal => Some(Self::acquire_from(&self.normal).await),
+            DeploymentPriority::Low => Some(Self::acquire_from(&self.low).await),
+        };
+        let permit = Self::acquire_from(&self.permits).await;
+        SyncPermit {
+            _class: class,
+            _permit: permit,
+        }
+    }
+
+    async fn acquire_from(semaphore: &Arc<Semaphore>) -> OwnedSemaphorePermit {
+        semaphore
+            .cheap_clone()
+            .acquire_owned()
+            .await
+            .expect("the sync scheduler semaphores are never closed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::prelude::tokio;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn reserves_permits_for_high_priority() {
+        let scheduler = SyncScheduler::new(4);
+
+        // Low priority deployments can only hold one of the four permits
+        let _low = scheduler.acquire(DeploymentPriority::Low).await;
+        assert_eq!(0, scheduler.low.available_permits());
+
+        // Normal priority deployments can hold three permits, but only two
+        // are left
+        let _normal1 = scheduler.acquire(DeploymentPriority::Normal).await;
+        let _normal2 = scheduler.acquire(DeploymentPriority::Normal).await;
+        assert_eq!(1, scheduler.normal.available_permits());
+        assert_eq!(1, scheduler.permits.available_permits());
+
+        let high = scheduler.acquire(DeploymentPriority::High).await;
+        assert_eq!(0, scheduler.permits.available_permits());
+
+        drop(high);
+        assert_eq!(1, scheduler.permits.available_permits());
+    }
+}
@@ -0,0 +1,87 @@
+use graph::prelude::tokio::{self, sync::watch};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often to check whether all runners have stopped
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Tells running deployments to stop once they have finished and written
+/// the block they are working on, and keeps track of how many of them are
+/// still running
+#[derive(Clone)]
+pub struct IndexingShutdown {
+    sender: Arc<watch::Sender<bool>>,
+    receiver: watch::Receiver<bool>,
+    running: Arc<AtomicUsize>,
+}
+
+impl IndexingShutdown {
+    pub(crate) fn new() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        IndexingShutdown {
+            sender: Arc::new(sender),
+            receiver,
+            running: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// A receiver that runners use to find out that they should stop
+    pub(crate) fn receiver(&self) -> watch::Receiver<bool> {
+        self.receiver.clone()
+    }
+
+    /// Count a runner as running until the returned guard is dropped
+    pub(crate) fn runner_started(&self) -> RunnerGuard {
+        self.running.fetch_add(1, Ordering::SeqCst);
+        RunnerGuard {
+            running: self.running.clone(),
+        }
+    }
+
+    /// Stop all runners at their next block boundary and wait at most
+    /// `timeout` for them to finish. Return how many runners were still
+    /// running when we stopped waiting
+    pub async fn shutdown(&self, timeout: Duration) -> usize {
+        // We hold a receiver ourselves, sending can therefore not fail
+        self.sender.send(true).ok();
+
+        let start = Instant::now();
+        loop {
+            let running = self.running.load(Ordering::SeqCst);
+            if running == 0 || start.elapsed() >= timeout {
+                return running;
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+pub(crate) struct RunnerGuard {
+    running: Arc<AtomicUsize>,
+}
+
+impl Drop for RunnerGuard {
+    fn drop(&mut self) {
+        self.running.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waits_for_runners() {
+        let shutdown = IndexingShutdown::new();
+        let receiver = shutdown.receiver();
+        let guard = shutdown.runner_started();
+
+        let timeout = Duration::from_millis(10);
+        assert_eq!(1, shutdown.shutdown(timeout).await);
+        assert!(*receiver.borrow());
+
+        drop(guard);
+        assert_eq!(0, shutdown.shutdown(timeout).await);
+    }
+}
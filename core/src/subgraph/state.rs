@@ -1,5 +1,6 @@
 use graph::{
-    prelude::{Entity, EntityKey},
+    data::subgraph::schema::QuarantinedDataSource,
+    prelude::{BlockNumber, Entity, EntityKey},
     util::{backoff::ExponentialBackoff, lfu_cache::LfuCache},
 };
 use std::time::Instant;
@@ -9,6 +10,8 @@ pub struct IndexingState {
     pub should_try_unfail_non_deterministic: bool,
     /// `false` -> `true` once it reaches chain head
     pub synced: bool,
+    /// `false` -> `true` once it was promoted as a canary
+    pub promoted: bool,
     /// Backoff used for the retry mechanism on non-deterministic errors
     pub backoff: ExponentialBackoff,
     /// Related to field above `backoff`
@@ -18,4 +21,72 @@ pub struct IndexingState {
     /// - Or the subgraph has triggers for the block
     pub skip_ptr_updates_timer: Instant,
     pub entity_lfu_cache: LfuCache<EntityKey, Option<Entity>>,
+    /// Data sources whose handlers are not run anymore because they failed
+    /// with a deterministic error
+    pub quarantined: Vec<QuarantinedDataSource>,
+}
+
+impl IndexingState {
+    /// Forget the data sources that were quarantined after `block` since
+    /// the blocks in which they failed are reverted
+    pub fn revert_quarantined(&mut self, block: BlockNumber) {
+        self.quarantined.retain(|q| q.block_number <= block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn quarantined(name: &str, block_number: BlockNumber) -> QuarantinedDataSource {
+        QuarantinedDataSource {
+            name: name.to_string(),
+            address: None,
+            block_number,
+            message: "failed".to_string(),
+        }
+    }
+
+    fn state(quarantined: Vec<QuarantinedDataSource>) -> IndexingState {
+        IndexingState {
+            should_try_unfail_non_deterministic: true,
+            synced: false,
+            promoted: false,
+            backoff: ExponentialBackoff::new(Duration::from_secs(1), Duration::from_secs(60)),
+            skip_ptr_updates_timer: Instant::now(),
+            entity_lfu_cache: LfuCache::new(),
+            quarantined,
+        }
+    }
+
+    fn names(state: &IndexingState) -> Vec<&str> {
+        state.quarantined.iter().map(|q| q.name.as_str()).collect()
+    }
+
+    #[test]
+    fn revert_keeps_data_sources_quarantined_before_the_block() {
+        // Reverting to block 5 undoes blocks 6 and later, and the data
+        // sources that failed in them run again
+        let mut state = state(vec![
+            quarantined("a", 3),
+            quarantined("b", 5),
+            quarantined("c", 6),
+            quarantined("d", 9),
+        ]);
+        state.revert_quarantined(5);
+        assert_eq!(vec!["a", "b"], names(&state));
+    }
+
+    #[test]
+    fn unfail_releases_data_source_that_failed_in_the_failed_block() {
+        // Unfailing a deterministic error at block 7 reverts to its parent
+        // block, which releases the data source that failed at block 7
+        let mut state = state(vec![quarantined("a", 4), quarantined("b", 7)]);
+        state.revert_quarantined(6);
+        assert_eq!(vec!["a"], names(&state));
+
+        state.revert_quarantined(0);
+        assert!(state.quarantined.is_empty());
+    }
 }
@@ -10,9 +10,8 @@ const BUFFERED_FIREHOSE_STREAM_SIZE: usize = 1;
 pub async fn new_block_stream<C: Blockchain>(
     inputs: &IndexingInputs<C>,
     filter: &C::TriggerFilter,
+    is_firehose: bool,
 ) -> Result<Box<dyn BlockStream<C>>, Error> {
-    let is_firehose = inputs.chain.is_firehose_supported();
-
     let buffer_size = match is_firehose {
         true => BUFFERED_FIREHOSE_STREAM_SIZE,
         false => BUFFERED_BLOCK_STREAM_SIZE,
@@ -0,0 +1,95 @@
+use std::sync::Arc;
+
+use graph::blockchain::{
+    Block, Blockchain, DataSource, NodeCapabilities as _, TriggerFilter as _, TriggersAdapter as _,
+};
+use graph::components::store::{DeploymentLocator, SubgraphStore};
+use graph::prelude::{
+    anyhow::ensure, serde_yaml, BlockNumber, BlockPtr, CheapClone, Error, LinkResolver, Logger,
+};
+
+use crate::subgraph::loader::resolve_with_dynamic_data_sources;
+
+/// A trigger and the handlers that it would run
+pub struct TriggerHandlers<C: Blockchain> {
+    pub trigger: C::TriggerData,
+    /// The names of the data sources and their handlers that match the
+    /// trigger, in the order in which they would run
+    pub handlers: Vec<(String, String)>,
+}
+
+/// The triggers in a block that match the filters of a deployment
+pub struct BlockTriggers<C: Blockchain> {
+    pub block: BlockPtr,
+    pub triggers: Vec<TriggerHandlers<C>>,
+}
+
+/// Find the triggers for `deployment` in the blocks from `from` to `to`
+/// and the handlers that they would run, without running any mappings.
+/// Blocks without triggers are left out
+pub async fn block_triggers<C: Blockchain, S: SubgraphStore>(
+    logger: &Logger,
+    subgraph_store: Arc<S>,
+    chain: Arc<C>,
+    link_resolver: Arc<dyn LinkResolver>,
+    deployment: &DeploymentLocator,
+    manifest: serde_yaml::Mapping,
+    from: BlockNumber,
+    to: BlockNumber,
+) -> Result<Vec<BlockTriggers<C>>, Error> {
+    ensure!(from <= to, "the block range {}..{} is empty", from, to);
+
+    let store = subgraph_store
+        .cheap_clone()
+        .writable(logger.clone(), deployment.id)
+        .await?;
+    let manifest = resolve_with_dynamic_data_sources::<C>(
+        logger,
+        store,
+        &link_resolver,
+        deployment,
+        manifest,
+        to,
+    )
+    .await?;
+
+    let filter = C::TriggerFilter::from_data_sources(manifest.data_sources.iter());
+    let required_capabilities = C::NodeCapabilities::from_data_sources(&manifest.data_sources);
+    let triggers_adapter = chain.triggers_adapter(
+        deployment,
+        &required_capabilities,
+        manifest.unified_mapping_api_version()?,
+    )?;
+
+    let mut blocks = Vec::new();
+    for block in triggers_adapter.scan_triggers(from, to, &filter).await? {
+        if block.trigger_data.is_empty() {
+            continue;
+        }
+        let number = block.block.number();
+        let triggers = block.trigger_data;
+        let block = Arc::new(block.block);
+
+        let mut matches = Vec::new();
+        for trigger in triggers {
+            let mut handlers = Vec::new();
+            // Data sources created in a block also process the triggers in
+            // that block
+            for ds in manifest
+                .data_sources
+                .iter()
+                .filter(|ds| ds.creation_block().map_or(true, |block| block <= number))
+            {
+                if let Some(matched) = ds.match_and_decode(&trigger, &block, logger)? {
+                    handlers.push((ds.name().to_string(), matched.handler_name().to_string()));
+                }
+            }
+            matches.push(TriggerHandlers { trigger, handlers });
+        }
+        blocks.push(BlockTriggers {
+            block: block.ptr(),
+            triggers: matches,
+        });
+    }
+    Ok(blocks)
+}
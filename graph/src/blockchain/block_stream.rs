@@ -153,6 +153,19 @@ pub trait TriggersAdapter<C: Blockchain>: Send + Sync {
 
     /// Get pointer to parent of `block`. This is called when reverting `block`.
     async fn parent_ptr(&self, block: &BlockPtr) -> Result<Option<BlockPtr>, Error>;
+
+    /// The highest block for which all triggers that match `filter` are
+    /// known. That is only limited when triggers come from something
+    /// other than the chain, like the entity changes of another subgraph
+    /// that might not have processed the chain head yet; `None` means
+    /// that triggers are known up to the chain head. A block number
+    /// before the genesis block means that no triggers are known yet
+    async fn triggers_known_up_to(
+        &self,
+        _filter: &C::TriggerFilter,
+    ) -> Result<Option<BlockNumber>, Error> {
+        Ok(None)
+    }
 }
 
 #[async_trait]
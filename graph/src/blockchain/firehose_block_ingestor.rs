@@ -3,7 +3,7 @@ use std::{marker::PhantomData, sync::Arc, time::Duration};
 use crate::{
     blockchain::Block as BlockchainBlock,
     components::store::ChainStore,
-    firehose::{self, decode_firehose_block, FirehoseEndpoint},
+    firehose::{self, decode_firehose_block, FirehoseEndpoint, FirehoseEndpoints},
     prelude::{error, info, Logger},
     util::backoff::ExponentialBackoff,
 };
@@ -17,7 +17,7 @@ where
     M: prost::Message + BlockchainBlock + Default + 'static,
 {
     chain_store: Arc<dyn ChainStore>,
-    endpoint: Arc<FirehoseEndpoint>,
+    endpoints: FirehoseEndpoints,
     logger: Logger,
 
     phantom: PhantomData<M>,
@@ -29,12 +29,12 @@ where
 {
     pub fn new(
         chain_store: Arc<dyn ChainStore>,
-        endpoint: Arc<FirehoseEndpoint>,
+        endpoints: FirehoseEndpoints,
         logger: Logger,
     ) -> FirehoseBlockIngestor<M> {
         FirehoseBlockIngestor {
             chain_store,
-            endpoint,
+            endpoints,
             logger,
             phantom: PhantomData {},
         }
@@ -48,13 +48,23 @@ where
             ExponentialBackoff::new(Duration::from_millis(250), Duration::from_secs(30));
 
         loop {
+            // Pick the endpoint again every time we connect so that we move
+            // away from endpoints that stopped working
+            let endpoint = match self.endpoints.endpoint() {
+                Some(endpoint) => endpoint,
+                None => {
+                    error!(self.logger, "No firehose endpoint available");
+                    backoff.sleep_async().await;
+                    continue;
+                }
+            };
+
             info!(
                 self.logger,
-                "Blockstream disconnected, connecting"; "endpoint uri" => format_args!("{}", self.endpoint), "cursor" => format_args!("{}", latest_cursor),
+                "Blockstream disconnected, connecting"; "endpoint uri" => format_args!("{}", endpoint), "provider" => &endpoint.provider, "cursor" => format_args!("{}", latest_cursor),
             );
 
-            let result = self
-                .endpoint
+            let result = endpoint
                 .clone()
                 .stream_blocks(firehose::Request {
                     // Starts at current HEAD block of the chain (viewed from Firehose side)
@@ -70,7 +80,7 @@ where
                     info!(self.logger, "Blockstream connected, consuming blocks");
 
                     // Consume the stream of blocks until an error is hit
-                    latest_cursor = self.process_blocks(latest_cursor, stream).await
+                    latest_cursor = self.process_blocks(&endpoint, latest_cursor, stream).await
                 }
                 Err(e) => {
                     error!(self.logger, "Unable to connect to endpoint: {:?}", e);
@@ -102,6 +112,7 @@ where
     /// upstream for future consumption.
     async fn process_blocks(
         &self,
+        endpoint: &FirehoseEndpoint,
         cursor: String,
         mut stream: Streaming<firehose::Response>,
     ) -> String {
@@ -139,6 +150,7 @@ where
                         self.logger,
                         "An error occurred while streaming blocks: {}", e
                     );
+                    endpoint.record_failure();
                     break;
                 }
             }
@@ -11,7 +11,7 @@ use crate::util::backoff::ExponentialBackoff;
 
 use super::block_stream::{BlockStream, BlockStreamEvent, FirehoseMapper};
 use super::Blockchain;
-use crate::{firehose, firehose::FirehoseEndpoint};
+use crate::{firehose, firehose::FirehoseEndpoints};
 
 pub struct FirehoseBlockStream<C: Blockchain> {
     stream: Pin<Box<dyn Stream<Item = Result<BlockStreamEvent<C>, Error>> + Send>>,
@@ -21,8 +21,11 @@ impl<C> FirehoseBlockStream<C>
 where
     C: Blockchain,
 {
+    /// Stream blocks from one of `endpoints`. When the stream breaks, the
+    /// stream reconnects to the endpoint that `FirehoseEndpoints::endpoint`
+    /// picks then
     pub fn new<F>(
-        endpoint: Arc<FirehoseEndpoint>,
+        endpoints: Arc<FirehoseEndpoints>,
         subgraph_current_block: Option<BlockPtr>,
         cursor: Option<String>,
         mapper: Arc<F>,
@@ -43,7 +46,7 @@ where
 
         FirehoseBlockStream {
             stream: Box::pin(stream_blocks(
-                endpoint,
+                endpoints,
                 cursor,
                 mapper,
                 adapter,
@@ -57,7 +60,7 @@ where
 }
 
 fn stream_blocks<C: Blockchain, F: FirehoseMapper<C>>(
-    endpoint: Arc<FirehoseEndpoint>,
+    endpoints: Arc<FirehoseEndpoints>,
     cursor: Option<String>,
     mapper: Arc<F>,
     adapter: Arc<C::TriggersAdapter>,
@@ -122,6 +125,15 @@ fn stream_blocks<C: Blockchain, F: FirehoseMapper<C>>(
 
     try_stream! {
         loop {
+            let endpoint = match endpoints.endpoint() {
+                Some(endpoint) => endpoint,
+                None => {
+                    error!(logger, "No firehose endpoint available");
+                    backoff.sleep_async().await;
+                    continue;
+                }
+            };
+
             info!(
                 &logger,
                 "Blockstream disconnected, connecting";
@@ -154,6 +166,10 @@ fn stream_blocks<C: Blockchain, F: FirehoseMapper<C>>(
                     let mut expected_stream_end = false;
 
                     for await response in stream {
+                        if response.is_err() {
+                            endpoint.record_failure();
+                        }
+
                         match process_firehose_response(
                             response,
                             &mut check_subgraph_continuity,
@@ -194,6 +210,7 @@ fn stream_blocks<C: Blockchain, F: FirehoseMapper<C>>(
                     }
 
                     if !expected_stream_end {
+                        endpoint.record_failure();
                         error!(logger, "Stream blocks complete unexpectedly, expecting stream to always stream blocks");
                     }
                 },
@@ -1,6 +1,6 @@
 use crate::{
     components::{link_resolver::LinkResolver, store::BlockNumber},
-    prelude::DataSourceTemplateInfo,
+    prelude::{web3::types::H256, DataSourceTemplateInfo},
     runtime::gas::GasCounter,
 };
 use anyhow::Error;
@@ -12,8 +12,8 @@ use std::{convert::TryFrom, sync::Arc};
 use super::{block_stream, HostFn, IngestorError, TriggerWithHandler};
 
 use super::{
-    block_stream::BlockWithTriggers, Block, BlockPtr, Blockchain, BlockchainKind, DataSource,
-    DataSourceTemplate, MappingTrigger, NodeCapabilities, RuntimeAdapter, TriggerData,
+    block_stream::BlockWithTriggers, Block, BlockHash, BlockPtr, Blockchain, BlockchainKind,
+    DataSource, DataSourceTemplate, MappingTrigger, NodeCapabilities, RuntimeAdapter, TriggerData,
     TriggerFilter, TriggersAdapter, UnresolvedDataSource, UnresolvedDataSourceTemplate,
 };
 
@@ -27,7 +27,7 @@ pub struct MockBlock {
 
 impl Block for MockBlock {
     fn ptr(&self) -> BlockPtr {
-        todo!()
+        BlockPtr::from((H256::from_low_u64_be(self.number), self.number))
     }
 
     fn parent_ptr(&self) -> Option<BlockPtr> {
@@ -35,12 +35,10 @@ impl Block for MockBlock {
     }
 }
 
-pub struct MockDataSource;
-
-impl Clone for MockDataSource {
-    fn clone(&self) -> Self {
-        todo!()
-    }
+#[derive(Clone, Default)]
+pub struct MockDataSource {
+    pub name: String,
+    pub address: Option<Vec<u8>>,
 }
 
 impl<C: Blockchain> TryFrom<DataSourceTemplateInfo<C>> for MockDataSource {
@@ -53,7 +51,7 @@ impl<C: Blockchain> TryFrom<DataSourceTemplateInfo<C>> for MockDataSource {
 
 impl<C: Blockchain> DataSource<C> for MockDataSource {
     fn address(&self) -> Option<&[u8]> {
-        todo!()
+        self.address.as_deref()
     }
 
     fn start_block(&self) -> crate::components::store::BlockNumber {
@@ -61,7 +59,7 @@ impl<C: Blockchain> DataSource<C> for MockDataSource {
     }
 
     fn name(&self) -> &str {
-        todo!()
+        &self.name
     }
 
     fn kind(&self) -> &str {
@@ -340,6 +338,14 @@ impl Blockchain for MockBlockchain {
         todo!()
     }
 
+    async fn block_pointer_from_hash(
+        &self,
+        _logger: &slog::Logger,
+        _hash: BlockHash,
+    ) -> Result<Option<BlockPtr>, IngestorError> {
+        todo!()
+    }
+
     fn runtime_adapter(&self) -> std::sync::Arc<Self::RuntimeAdapter> {
         todo!()
     }
@@ -14,7 +14,7 @@ use crate::{
     cheap_clone::CheapClone,
     components::store::{DeploymentLocator, StoredDynamicDataSource},
     data::subgraph::UnifiedMappingApiVersion,
-    prelude::DataSourceContext,
+    prelude::{DataSourceContext, DeploymentHash},
     runtime::{gas::GasCounter, AscHeap, AscPtr, DeterministicHostError, HostExportError},
 };
 use crate::{
@@ -26,6 +26,7 @@ use crate::{
 };
 use anyhow::{anyhow, Context, Error};
 use async_trait::async_trait;
+use lru_time_cache::LruCache;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use slog::Logger;
@@ -36,8 +37,10 @@ use std::{
     convert::TryFrom,
     fmt::{self, Debug},
     str::FromStr,
-    sync::Arc,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
+use tokio::sync::Semaphore;
 use web3::types::H256;
 
 pub use block_stream::{ChainHeadUpdateListener, ChainHeadUpdateStream, TriggersAdapter};
@@ -61,6 +64,12 @@ pub trait Block: Send + Sync {
         self.parent_ptr().map(|ptr| ptr.hash)
     }
 
+    /// The time at which the block was produced in seconds since the
+    /// epoch, if the chain records that
+    fn timestamp(&self) -> Option<u64> {
+        None
+    }
+
     /// The data that should be stored for this block in the `ChainStore`
     fn data(&self) -> Result<serde_json::Value, serde_json::Error> {
         Ok(serde_json::Value::Null)
@@ -131,6 +140,16 @@ pub trait Blockchain: Debug + Sized + Send + Sync + Unpin + 'static {
         number: BlockNumber,
     ) -> Result<BlockPtr, IngestorError>;
 
+    /// Ask the chain for the block with the given `hash`. This is used for
+    /// blocks that are not in the chain store; implementations should add
+    /// the block to the chain store when they find it. Returns `None` if
+    /// the chain does not know a block with that hash.
+    async fn block_pointer_from_hash(
+        &self,
+        logger: &Logger,
+        hash: BlockHash,
+    ) -> Result<Option<BlockPtr>, IngestorError>;
+
     fn runtime_adapter(&self) -> Arc<Self::RuntimeAdapter>;
 
     fn is_firehose_supported(&self) -> bool;
@@ -196,6 +215,13 @@ pub trait DataSource<C: Blockchain>:
     fn api_version(&self) -> semver::Version;
     fn runtime(&self) -> &[u8];
 
+    /// The deployment whose entity changes this data source handles, for
+    /// data sources that are fed by another subgraph rather than by the
+    /// chain directly
+    fn source_subgraph(&self) -> Option<&DeploymentHash> {
+        None
+    }
+
     /// Checks if `trigger` matches this data source, and if so decodes it into a `MappingTrigger`.
     /// A return of `Ok(None)` mean the trigger does not match.
     ///
@@ -295,14 +321,33 @@ impl CheapClone for HostFn {
     }
 }
 
+#[async_trait]
 pub trait RuntimeAdapter<C: Blockchain>: Send + Sync {
     fn host_fns(&self, ds: &C::DataSource) -> Result<Vec<HostFn>, Error>;
+
+    /// Called with the triggers of a block before any handler runs for them
+    /// so that chains can fetch data that the handlers of `data_sources`
+    /// are known to need in as few requests as possible. Errors are not
+    /// fatal since handlers fetch what they need themselves
+    async fn prefetch(
+        &self,
+        _logger: &Logger,
+        _block: &Arc<C::Block>,
+        _data_sources: &[&C::DataSource],
+        _triggers: &[C::TriggerData],
+    ) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 pub trait NodeCapabilities<C: Blockchain> {
     fn from_data_sources(data_sources: &[C::DataSource]) -> Self;
 }
 
+/// The `kind` of data sources that handle the entity changes of another
+/// subgraph instead of data from the chain
+pub const SUBGRAPH_DATA_SOURCE_KIND: &str = "subgraph";
+
 /// Blockchain technologies supported by Graph Node.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
@@ -345,25 +390,133 @@ impl BlockchainKind {
     pub fn from_manifest(manifest: &serde_yaml::Mapping) -> Result<Self, Error> {
         use serde_yaml::Value;
 
-        // The `kind` field of the first data source in the manifest.
+        // The `kind` field of the first data source in the manifest that
+        // is not fed by another subgraph.
         //
         // Split by `/` to, for example, read 'ethereum' in 'ethereum/contracts'.
-        manifest
+        let kinds: Vec<_> = manifest
             .get(&Value::String("dataSources".to_owned()))
             .and_then(|ds| ds.as_sequence())
-            .and_then(|ds| ds.first())
-            .and_then(|ds| ds.as_mapping())
-            .and_then(|ds| ds.get(&Value::String("kind".to_owned())))
-            .and_then(|kind| kind.as_str())
-            .and_then(|kind| kind.split('/').next())
-            .context("invalid manifest")
-            .and_then(BlockchainKind::from_str)
+            .filter(|ds| !ds.is_empty())
+            .context("invalid manifest")?
+            .iter()
+            .map(|ds| {
+                ds.as_mapping()
+                    .and_then(|ds| ds.get(&Value::String("kind".to_owned())))
+                    .and_then(|kind| kind.as_str())
+                    .and_then(|kind| kind.split('/').next())
+            })
+            .collect();
+
+        match kinds
+            .into_iter()
+            .find(|kind| *kind != Some(SUBGRAPH_DATA_SOURCE_KIND))
+        {
+            Some(kind) => kind
+                .context("invalid manifest")
+                .and_then(BlockchainKind::from_str),
+            // Only Ethereum supports subgraphs whose data sources are all
+            // fed by other subgraphs
+            None => Ok(BlockchainKind::Ethereum),
+        }
+    }
+}
+
+/// Resolves block hashes that are not in the chain store by asking the
+/// chain for the given network itself.
+#[async_trait]
+pub trait BlockHashResolver: Send + Sync {
+    async fn block_ptr_for_hash(
+        &self,
+        logger: &Logger,
+        network: &str,
+        hash: BlockHash,
+    ) -> Result<Option<BlockPtr>, Error>;
+}
+
+/// Wraps a `BlockHashResolver` so that queries for made-up hashes can not
+/// overwhelm the chain. The outcome of each lookup, including that the
+/// chain does not know a hash, is remembered for `ttl` in a cache of at
+/// most `capacity` entries, and at most `max_lookups` lookups run at the
+/// same time; lookups beyond that fail right away
+pub struct CachingBlockHashResolver {
+    inner: Arc<dyn BlockHashResolver>,
+    cache: Mutex<LruCache<(String, BlockHash), Option<BlockPtr>>>,
+    lookups: Semaphore,
+}
+
+impl CachingBlockHashResolver {
+    pub fn new(
+        inner: Arc<dyn BlockHashResolver>,
+        capacity: usize,
+        ttl: Duration,
+        max_lookups: usize,
+    ) -> Self {
+        CachingBlockHashResolver {
+            inner,
+            cache: Mutex::new(LruCache::with_expiry_duration_and_capacity(ttl, capacity)),
+            lookups: Semaphore::new(max_lookups),
+        }
+    }
+}
+
+#[async_trait]
+impl BlockHashResolver for CachingBlockHashResolver {
+    async fn block_ptr_for_hash(
+        &self,
+        logger: &Logger,
+        network: &str,
+        hash: BlockHash,
+    ) -> Result<Option<BlockPtr>, Error> {
+        let key = (network.to_string(), hash);
+        let cached = self.cache.lock().unwrap().get(&key).cloned();
+        if let Some(ptr) = cached {
+            return Ok(ptr);
+        }
+
+        let _permit = self.lookups.try_acquire().map_err(|_| {
+            anyhow!(
+                "too many lookups of unknown block hashes for {} in progress, try again later",
+                network
+            )
+        })?;
+        let ptr = self
+            .inner
+            .block_ptr_for_hash(logger, network, key.1.clone())
+            .await?;
+        self.cache.lock().unwrap().insert(key, ptr.clone());
+        Ok(ptr)
+    }
+}
+
+/// Object-safe access to `Blockchain::block_pointer_from_hash` so that we
+/// can resolve hashes without knowing the concrete type of a chain
+#[async_trait]
+trait ChainBlockHashResolver: Debug + Send + Sync {
+    async fn block_pointer_from_hash(
+        &self,
+        logger: &Logger,
+        hash: BlockHash,
+    ) -> Result<Option<BlockPtr>, IngestorError>;
+}
+
+#[async_trait]
+impl<C: Blockchain> ChainBlockHashResolver for C {
+    async fn block_pointer_from_hash(
+        &self,
+        logger: &Logger,
+        hash: BlockHash,
+    ) -> Result<Option<BlockPtr>, IngestorError> {
+        Blockchain::block_pointer_from_hash(self, logger, hash).await
     }
 }
 
 /// A collection of blockchains, keyed by `BlockchainKind` and network.
 #[derive(Default, Debug, Clone)]
-pub struct BlockchainMap(HashMap<(BlockchainKind, String), Arc<dyn Any + Send + Sync>>);
+pub struct BlockchainMap(
+    HashMap<(BlockchainKind, String), Arc<dyn Any + Send + Sync>>,
+    HashMap<String, Arc<dyn ChainBlockHashResolver>>,
+);
 
 impl BlockchainMap {
     pub fn new() -> Self {
@@ -371,6 +524,7 @@ impl BlockchainMap {
     }
 
     pub fn insert<C: Blockchain>(&mut self, network: String, chain: Arc<C>) {
+        self.1.insert(network.clone(), chain.cheap_clone());
         self.0.insert((C::KIND, network), chain);
     }
 
@@ -382,6 +536,29 @@ impl BlockchainMap {
             .downcast()
             .map_err(|_| anyhow!("unable to downcast, wrong type for blockchain {}", C::KIND))
     }
+
+    /// The names of all networks in this map
+    pub fn networks(&self) -> impl Iterator<Item = &String> {
+        self.1.keys()
+    }
+}
+
+#[async_trait]
+impl BlockHashResolver for BlockchainMap {
+    async fn block_ptr_for_hash(
+        &self,
+        logger: &Logger,
+        network: &str,
+        hash: BlockHash,
+    ) -> Result<Option<BlockPtr>, Error> {
+        match self.1.get(network) {
+            Some(chain) => chain
+                .block_pointer_from_hash(logger, hash)
+                .await
+                .map_err(Into::into),
+            None => Ok(None),
+        }
+    }
 }
 
 pub struct TriggerWithHandler<C: Blockchain> {
@@ -247,6 +247,10 @@ where
         // Make sure not to include genesis in the reorg threshold.
         let reorg_threshold = ctx.reorg_threshold.min(head_ptr.number);
 
+        // Triggers that come from another subgraph are only known for the
+        // blocks that subgraph has processed; we can't go past those
+        let known_up_to = self.adapter.triggers_known_up_to(&self.filter).await?;
+
         // Only continue if the subgraph block ptr is behind the head block ptr.
         // subgraph_ptr > head_ptr shouldn't happen, but if it does, it's safest to just stop.
         if let Some(ptr) = &subgraph_ptr {
@@ -348,6 +352,10 @@ where
             // prior to the reorg threshold. It isn't safe to go farther than the
             // reorg threshold due to race conditions.
             let to_limit = cmp::min(head_ptr.number - reorg_threshold, next_start_block - 1);
+            let to_limit = known_up_to.map_or(to_limit, |known| cmp::min(to_limit, known));
+            if to_limit < from {
+                return Ok(ReconciliationStep::Done);
+            }
 
             // Calculate the range size according to the target number of triggers,
             // respecting the global maximum and also not increasing too
@@ -417,6 +425,10 @@ where
                 return Ok(ReconciliationStep::Revert(parent));
             }
 
+            if known_up_to.map_or(false, |known| known <= subgraph_ptr.number) {
+                return Ok(ReconciliationStep::Done);
+            }
+
             // Precondition: subgraph_ptr.number < head_ptr.number
             // Walk back to one block short of subgraph_ptr.number
             let offset = head_ptr.number - subgraph_ptr.number - 1;
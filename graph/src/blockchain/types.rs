@@ -8,7 +8,7 @@ use web3::types::{Block, H256};
 use crate::{cheap_clone::CheapClone, components::store::BlockNumber};
 
 /// A simple marker for byte arrays that are really block hashes
-#[derive(Clone, Default, PartialEq, Eq, Hash)]
+#[derive(Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct BlockHash(pub Box<[u8]>);
 
 impl BlockHash {
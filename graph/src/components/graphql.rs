@@ -1,9 +1,9 @@
 use futures::prelude::*;
 
-use crate::data::query::{CacheStatus, Query, QueryTarget};
+use crate::data::query::{CacheStatus, IncrementalResponse, Query, QueryStats, QueryTarget};
 use crate::data::subscription::{Subscription, SubscriptionError, SubscriptionResult};
 use crate::data::{graphql::effort::LoadManager, query::QueryResults};
-use crate::prelude::DeploymentHash;
+use crate::prelude::{CheapClone, DeploymentHash};
 
 use async_trait::async_trait;
 use std::sync::Arc;
@@ -24,7 +24,41 @@ pub trait GraphQlRunner: Send + Sync + 'static {
     /// Runs a GraphQL query and returns its result.
     async fn run_query(self: Arc<Self>, query: Query, target: QueryTarget) -> QueryResults;
 
+    /// Runs a batch of GraphQL queries and returns their results in the
+    /// same order. Queries against the latest block are all run at the
+    /// same block. Overrides the global complexity limit for each query if
+    /// `max_complexity` is set.
+    async fn run_query_batch(
+        self: Arc<Self>,
+        queries: Vec<Query>,
+        target: QueryTarget,
+        max_complexity: Option<u64>,
+    ) -> Vec<QueryResults> {
+        let mut results = Vec::with_capacity(queries.len());
+        for query in queries {
+            let runner = self.cheap_clone();
+            let result = match max_complexity {
+                Some(_) => {
+                    runner
+                        .run_query_with_complexity(
+                            query,
+                            target.clone(),
+                            max_complexity,
+                            None,
+                            None,
+                            None,
+                        )
+                        .await
+                }
+                None => runner.run_query(query, target.clone()).await,
+            };
+            results.push(result);
+        }
+        results
+    }
+
     /// Runs a GraphqL query up to the given complexity. Overrides the global complexity limit.
+    /// Limits that are `None` fall back to the limits configured for the deployment.
     async fn run_query_with_complexity(
         self: Arc<Self>,
         query: Query,
@@ -35,6 +69,16 @@ pub trait GraphQlRunner: Send + Sync + 'static {
         max_skip: Option<u32>,
     ) -> QueryResults;
 
+    /// Runs a GraphQL query that may use `@defer` or `@stream`. If it does,
+    /// the response is delivered incrementally as a stream of payloads.
+    /// Overrides the global complexity limit if `max_complexity` is set.
+    async fn run_query_incremental(
+        self: Arc<Self>,
+        query: Query,
+        target: QueryTarget,
+        max_complexity: Option<u64>,
+    ) -> IncrementalResponse;
+
     /// Runs a GraphQL subscription and returns a stream of results.
     async fn run_subscription(
         self: Arc<Self>,
@@ -43,6 +87,9 @@ pub trait GraphQlRunner: Send + Sync + 'static {
     ) -> Result<SubscriptionResult, SubscriptionError>;
 
     fn load_manager(&self) -> Arc<LoadManager>;
+
+    /// The statistics for the queries that this runner has run
+    fn query_stats(&self) -> Arc<QueryStats>;
 }
 
 #[async_trait]
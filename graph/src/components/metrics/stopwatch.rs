@@ -1,4 +1,5 @@
 use crate::prelude::*;
+use crate::util::otel::Span;
 use std::sync::{atomic::AtomicBool, atomic::Ordering, Mutex};
 use std::time::Instant;
 
@@ -6,6 +7,8 @@ use std::time::Instant;
 pub struct Section {
     id: String,
     stopwatch: StopwatchMetrics,
+    // Sections are also traced, even when the stopwatch is disabled
+    _span: Span,
 }
 
 impl Section {
@@ -82,6 +85,7 @@ impl StopwatchMetrics {
 
         // If disabled, this will do nothing on drop.
         Section {
+            _span: Span::new(&id),
             id,
             stopwatch: self.clone(),
         }
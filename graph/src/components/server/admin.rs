@@ -1,6 +1,9 @@
 use std::io;
+use std::path::PathBuf;
 use std::sync::Arc;
 
+use crate::components::store::PersistedQueryStore;
+use crate::data::graphql::effort::LoadManager;
 use crate::prelude::Logger;
 use crate::prelude::NodeId;
 
@@ -13,7 +16,10 @@ pub trait JsonRpcServer<P> {
         http_port: u16,
         ws_port: u16,
         provider: Arc<P>,
+        persisted_queries: Arc<dyn PersistedQueryStore>,
+        load_manager: Arc<LoadManager>,
         node_id: NodeId,
+        unix_socket: Option<PathBuf>,
         logger: Logger,
     ) -> Result<Self::Server, io::Error>;
 }
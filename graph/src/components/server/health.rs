@@ -0,0 +1,107 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+/// The state of one component of a node, as reported by the readiness
+/// endpoint
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ComponentHealth {
+    pub ready: bool,
+    pub detail: String,
+}
+
+/// What the readiness endpoint reports: the node is ready once all its
+/// components are
+#[derive(Clone, Debug, Serialize)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub components: BTreeMap<String, ComponentHealth>,
+}
+
+/// Tracks whether the components of a node are ready to do their work.
+/// A node is alive as soon as it can answer requests, but it should only
+/// receive traffic once every registered component is ready
+#[derive(Debug, Default)]
+pub struct Readiness {
+    components: RwLock<BTreeMap<String, ComponentHealth>>,
+}
+
+impl Readiness {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `component` in a state that is not ready yet. Components need
+    /// to be registered before the readiness endpoint is served so that the
+    /// node does not look ready just because a component has not reported
+    /// anything yet
+    pub fn register(&self, component: &str) {
+        self.set(component, false, "starting");
+    }
+
+    pub fn set_ready(&self, component: &str, detail: impl Into<String>) {
+        self.set(component, true, detail);
+    }
+
+    pub fn set_not_ready(&self, component: &str, detail: impl Into<String>) {
+        self.set(component, false, detail);
+    }
+
+    fn set(&self, component: &str, ready: bool, detail: impl Into<String>) {
+        let health = ComponentHealth {
+            ready,
+            detail: detail.into(),
+        };
+        self.components
+            .write()
+            .unwrap()
+            .insert(component.to_string(), health);
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.components
+            .read()
+            .unwrap()
+            .values()
+            .all(|health| health.ready)
+    }
+
+    pub fn report(&self) -> ReadinessReport {
+        let components = self.components.read().unwrap().clone();
+        ReadinessReport {
+            ready: components.values().all(|health| health.ready),
+            components,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ready_once_all_components_are() {
+        let readiness = Readiness::new();
+        assert!(readiness.is_ready());
+
+        readiness.register("store");
+        readiness.register("providers");
+        assert!(!readiness.is_ready());
+
+        readiness.set_ready("store", "primary is reachable");
+        assert!(!readiness.is_ready());
+        readiness.set_ready("providers", "all providers validated");
+        assert!(readiness.is_ready());
+
+        readiness.set_not_ready("store", "primary is unreachable");
+        let report = readiness.report();
+        assert!(!report.ready);
+        assert_eq!(
+            Some(&ComponentHealth {
+                ready: false,
+                detail: "primary is unreachable".to_string()
+            }),
+            report.components.get("store")
+        );
+    }
+}
@@ -12,3 +12,6 @@ pub mod index_node;
 
 /// Components for the Prometheus metrics server.
 pub mod metrics;
+
+/// Component for tracking whether a node is ready to receive traffic.
+pub mod health;
@@ -160,6 +160,31 @@ pub enum EntityFilter {
     EndsWithNoCase(Attribute, Value),
     NotEndsWith(Attribute, Value),
     NotEndsWithNoCase(Attribute, Value),
+    /// Match the attribute against a SQL `like` pattern
+    Like(Attribute, Value),
+    LikeNoCase(Attribute, Value),
+    NotLike(Attribute, Value),
+    NotLikeNoCase(Attribute, Value),
+    /// Compare the attributes as a row with the values, i.e.,
+    /// `(attr1, attr2) > (value1, value2)`. None of the values may be null
+    RowGreaterThan(Vec<(Attribute, Value)>),
+    /// Like `RowGreaterThan`, but for `(attr1, attr2) < (value1, value2)`
+    RowLessThan(Vec<(Attribute, Value)>),
+    /// Matches entities that reference a child entity that matches the
+    /// child's filter
+    Child(Child),
+}
+
+/// A filter on the entities that an entity references. If `derived` is
+/// `false`, `attr` is the attribute of the parent that holds the id(s) of
+/// its children. If `derived` is `true`, `attr` is the attribute of the
+/// child that holds the id(s) of its parents
+#[derive(Clone, Debug, PartialEq)]
+pub struct Child {
+    pub attr: Attribute,
+    pub entity_type: EntityType,
+    pub filter: Box<EntityFilter>,
+    pub derived: bool,
 }
 
 // Define some convenience methods
@@ -211,6 +236,16 @@ pub enum EntityOrder {
     Ascending(String, ValueType),
     /// Order descending by the given attribute. Use `id` as a tie-breaker
     Descending(String, ValueType),
+    /// Order by several attributes in turn; each of the orders is either
+    /// `Ascending` or `Descending`. Use `id` as a tie-breaker, sorted in
+    /// the direction of the last attribute
+    Compound(Vec<EntityOrder>),
+    /// Order ascending by an attribute of the entity that the parent
+    /// references. Use `id` of the parent as a tie-breaker
+    ChildAscending(EntityOrderByChild),
+    /// Order descending by an attribute of the entity that the parent
+    /// references. Use `id` of the parent as a tie-breaker
+    ChildDescending(EntityOrderByChild),
     /// Order by the `id` of the entities
     Default,
     /// Do not order at all. This speeds up queries where we know that
@@ -218,6 +253,16 @@ pub enum EntityOrder {
     Unordered,
 }
 
+/// Order by `attribute` of the entity of type `entity_type` that the
+/// `join_attribute` of the entities we are ordering references
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntityOrderByChild {
+    pub join_attribute: Attribute,
+    pub entity_type: EntityType,
+    pub attribute: Attribute,
+    pub value_type: ValueType,
+}
+
 /// How many entities to return, how many to skip etc.
 #[derive(Clone, Debug, PartialEq)]
 pub struct EntityRange {
@@ -471,6 +516,75 @@ impl EntityQuery {
     }
 }
 
+/// The aggregate functions that can be computed over the entities that
+/// match an `EntityQuery`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+impl AggregateFunction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AggregateFunction::Count => "count",
+            AggregateFunction::Sum => "sum",
+            AggregateFunction::Avg => "avg",
+            AggregateFunction::Min => "min",
+            AggregateFunction::Max => "max",
+        }
+    }
+}
+
+impl std::str::FromStr for AggregateFunction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "count" => Ok(AggregateFunction::Count),
+            "sum" => Ok(AggregateFunction::Sum),
+            "avg" => Ok(AggregateFunction::Avg),
+            "min" => Ok(AggregateFunction::Min),
+            "max" => Ok(AggregateFunction::Max),
+            _ => Err(anyhow::anyhow!("unknown aggregate function `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for AggregateFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// An aggregate to compute over the entities matching a query. The
+/// `attribute` is only `None` for `AggregateFunction::Count`, which
+/// counts entities
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EntityAggregate {
+    pub function: AggregateFunction,
+    pub attribute: Option<Attribute>,
+}
+
+impl EntityAggregate {
+    pub fn count() -> Self {
+        EntityAggregate {
+            function: AggregateFunction::Count,
+            attribute: None,
+        }
+    }
+
+    pub fn new(function: AggregateFunction, attribute: Attribute) -> Self {
+        EntityAggregate {
+            function,
+            attribute: Some(attribute),
+        }
+    }
+}
+
 /// Operation types that lead to entity changes.
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
@@ -746,6 +860,15 @@ pub enum EntityOperation {
     Remove { key: EntityKey },
 }
 
+impl EntityOperation {
+    /// The key of the entity this operation changes
+    pub fn key(&self) -> &EntityKey {
+        match self {
+            EntityOperation::Set { key, .. } | EntityOperation::Remove { key } => key,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum UnfailOutcome {
     Noop,
@@ -813,6 +936,46 @@ impl Display for DeploymentLocator {
     }
 }
 
+/// How much work indexing a deployment caused during a recent period of
+/// time, as reported by the node that indexes it
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeploymentLoad {
+    pub deployment: DeploymentId,
+    /// How many blocks the deployment processed per minute
+    pub blocks_per_minute: f64,
+    /// How many seconds per minute the deployment spent writing to the
+    /// database
+    pub db_seconds_per_minute: f64,
+}
+
+/// Callbacks that report the progress of pruning the history of a
+/// deployment. All methods do nothing by default
+#[allow(unused_variables)]
+pub trait PruneReporter: Send + 'static {
+    /// Pruning starts and will remove all entity versions that are not
+    /// visible at `earliest_block` or later
+    fn start(&mut self, earliest_block: BlockNumber) {}
+
+    /// Pruning of `table` starts. The table has an estimated `versions`
+    /// entity versions and takes up `bytes` bytes on disk
+    fn start_table(&mut self, table: &str, versions: i64, bytes: i64) {}
+
+    /// A batch was removed from `table`; `deleted` is the number of
+    /// versions removed from the table so far
+    fn prune_batch(&mut self, table: &str, deleted: usize) {}
+
+    /// Pruning `table` is done after removing `deleted` versions
+    fn finish_table(&mut self, table: &str, deleted: usize) {}
+
+    /// Pruning is done after removing `deleted` versions in total
+    fn finish(&mut self, deleted: usize) {}
+}
+
+/// A `PruneReporter` that does not report anything
+pub struct NoopPruneReporter;
+
+impl PruneReporter for NoopPruneReporter {}
+
 // The type that the connection pool uses to track wait times for
 // connection checkouts
 pub type PoolWaitStats = Arc<RwLock<MovingStats>>;
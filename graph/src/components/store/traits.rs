@@ -30,6 +30,28 @@ pub trait EnsLookup: Send + Sync + 'static {
     fn find_name(&self, hash: &str) -> Result<Option<String>, StoreError>;
 }
 
+/// The registry of persisted queries. Clients can run a persisted query by
+/// sending its hash instead of its text, and when persisted queries are
+/// enforced, they are the only queries that clients may run
+pub trait PersistedQueryStore: Send + Sync + 'static {
+    /// Add `query` to the registry under `hash`. Registering a query that
+    /// is already persisted is not an error
+    fn register(&self, hash: &str, query: &str) -> Result<(), StoreError>;
+
+    /// Remove the query with `hash` from the registry. Return `false` if
+    /// there was no such query
+    fn remove(&self, hash: &str) -> Result<bool, StoreError>;
+
+    /// Look up the text of the query with `hash`. This only consults an
+    /// in-memory copy of the registry and never blocks, so that it can be
+    /// used while a request is being parsed
+    fn find(&self, hash: &str) -> Option<String>;
+
+    /// Reload the in-memory copy of the registry from the database to pick
+    /// up queries that were registered or removed through other nodes
+    fn refresh(&self) -> Result<(), StoreError>;
+}
+
 /// An entry point for all operations that require access to the node's storage
 /// layer. It provides access to a [`BlockStore`] and a [`SubgraphStore`].
 pub trait Store: Clone + StatusStore + Send + Sync + 'static {
@@ -49,6 +71,8 @@ pub trait Store: Clone + StatusStore + Send + Sync + 'static {
 pub trait SubgraphStore: Send + Sync + 'static {
     fn ens_lookup(&self) -> Arc<dyn EnsLookup>;
 
+    fn persisted_queries(&self) -> Arc<dyn PersistedQueryStore>;
+
     /// Check if the store is accepting queries for the specified subgraph.
     /// May return true even if the specified subgraph is not currently assigned to an indexing
     /// node, as the store will still accept queries.
@@ -86,14 +110,86 @@ pub trait SubgraphStore: Send + Sync + 'static {
         node_id: &NodeId,
     ) -> Result<(), StoreError>;
 
+    /// Stop indexing the subgraph with `id` until it is resumed, but keep
+    /// it assigned to its node. If there is no assignment for the given
+    /// deployment, report an error.
+    fn pause_subgraph(&self, deployment: &DeploymentLocator) -> Result<(), StoreError>;
+
+    /// Resume indexing the subgraph with `id` after it was paused. If
+    /// there is no assignment for the given deployment, report an error.
+    fn resume_subgraph(&self, deployment: &DeploymentLocator) -> Result<(), StoreError>;
+
+    /// Remove the assignment of the subgraph with `id` so that no node
+    /// indexes it anymore. Does nothing if the deployment is not assigned
+    fn unassign_subgraph(&self, deployment: &DeploymentLocator) -> Result<(), StoreError>;
+
+    /// Revert the data of the subgraph with `id` to `block_ptr_to`. The
+    /// deployment must not be indexed while it is rewound
+    fn rewind_subgraph(
+        &self,
+        deployment: &DeploymentLocator,
+        block_ptr_to: BlockPtr,
+    ) -> Result<(), StoreError>;
+
     fn assigned_node(&self, deployment: &DeploymentLocator) -> Result<Option<NodeId>, StoreError>;
 
+    /// The node that the deployment is assigned to, and whether it is
+    /// paused
+    fn assignment_status(
+        &self,
+        deployment: &DeploymentLocator,
+    ) -> Result<Option<(NodeId, bool)>, StoreError>;
+
+    /// The priority an operator set for syncing the deployment, if any
+    fn assignment_priority(
+        &self,
+        deployment: &DeploymentLocator,
+    ) -> Result<Option<DeploymentPriority>, StoreError>;
+
+    /// The deployments that are assigned to `node` and not paused
     fn assignments(&self, node: &NodeId) -> Result<Vec<DeploymentLocator>, StoreError>;
 
+    /// Record the load that the deployments indexed by `node` caused
+    /// recently, together with the `networks` that `node` can index.
+    /// Replaces the previous report for `node`
+    fn report_load(
+        &self,
+        node: &NodeId,
+        networks: Vec<String>,
+        loads: Vec<DeploymentLoad>,
+    ) -> Result<(), StoreError>;
+
     /// Return `true` if a subgraph `name` exists, regardless of whether the
     /// subgraph has any deployments attached to it
     fn subgraph_exists(&self, name: &SubgraphName) -> Result<bool, StoreError>;
 
+    /// Return the deployment that is the current version of the subgraph
+    /// `name`, or `None` if the subgraph has no current version
+    fn current_deployment(&self, name: &SubgraphName)
+        -> Result<Option<DeploymentHash>, StoreError>;
+
+    /// Return the reasons why a deployment with `schema` can not be
+    /// grafted onto `base`. An empty vector means that grafting is possible
+    fn graft_incompatibilities(
+        &self,
+        base: &DeploymentHash,
+        schema: &Schema,
+    ) -> Result<Vec<String>, StoreError>;
+
+    /// Return the earliest block for which the deployment `id` still has
+    /// all its data; history before that block has been pruned
+    fn earliest_block(&self, id: &DeploymentHash) -> Result<BlockNumber, StoreError>;
+
+    /// Change the schema of the deployment `id` to `schema` without
+    /// reindexing it, which is only possible if `schema` only adds entity
+    /// types and nullable attributes. Return the reasons why the schema
+    /// can not be changed; an empty vector means that it was changed
+    fn upgrade_schema(
+        &self,
+        id: &DeploymentHash,
+        schema: &Schema,
+    ) -> Result<Vec<String>, StoreError>;
+
     /// Returns a collection of all [`EntityModification`] items in relation to
     /// the given [`BlockNumber`]. No distinction is made between inserts and
     /// updates, which may be returned as either [`EntityModification::Insert`]
@@ -104,6 +200,45 @@ pub trait SubgraphStore: Send + Sync + 'static {
         block_number: BlockNumber,
     ) -> Result<Vec<EntityOperation>, StoreError>;
 
+    /// Return the changes to entities of the given `entity_types` for
+    /// every block in the range `[from, to]` in which any of them changed,
+    /// keyed by block number. Like for `entity_changes_in_block`, inserts
+    /// and updates are both reported as [`EntityOperation::Set`]
+    fn entity_changes_in_block_range(
+        &self,
+        subgraph_id: &DeploymentHash,
+        entity_types: &[EntityType],
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<BTreeMap<BlockNumber, Vec<EntityOperation>>, StoreError>;
+
+    /// The name of the network that the deployment `id` indexes
+    fn deployment_network(&self, id: &DeploymentHash) -> Result<String, StoreError>;
+
+    /// Return up to `first` messages that the mappings of `subgraph_id`
+    /// logged for `from_block` or later blocks with severity `level` or
+    /// higher, oldest first
+    fn mapping_logs(
+        &self,
+        subgraph_id: &DeploymentHash,
+        from_block: BlockNumber,
+        level: slog::Level,
+        first: usize,
+    ) -> Result<Vec<MappingLog>, StoreError>;
+
+    /// The blocks that operators told graph-node to skip, completely or
+    /// for some handlers, when indexing `subgraph_id`, ordered by block
+    /// number
+    fn skipped_blocks(&self, subgraph_id: &DeploymentHash)
+        -> Result<Vec<SkippedBlock>, StoreError>;
+
+    /// The data sources of `subgraph_id` whose handlers are not run
+    /// anymore because they failed with a deterministic error
+    fn quarantined_data_sources(
+        &self,
+        subgraph_id: &DeploymentHash,
+    ) -> Result<Vec<QuarantinedDataSource>, StoreError>;
+
     /// Return the GraphQL schema supplied by the user
     fn input_schema(&self, subgraph_id: &DeploymentHash) -> Result<Arc<Schema>, StoreError>;
 
@@ -129,6 +264,12 @@ pub trait SubgraphStore: Send + Sync + 'static {
         deployment: DeploymentId,
     ) -> Result<Arc<dyn WritableStore>, StoreError>;
 
+    /// The deployment `id` was stopped. Its `WritableStore` writes the
+    /// blocks that are still queued and then stops its background writer.
+    /// The next call to `writable` for the deployment waits for that and
+    /// creates a new `WritableStore`
+    fn stop_subgraph(&self, id: DeploymentId) -> Result<(), StoreError>;
+
     /// Return the minimum block pointer of all deployments with this `id`
     /// that we would use to query or copy from; in particular, this will
     /// ignore any instances of this deployment that are in the process of
@@ -162,7 +303,7 @@ pub trait WritableStore: Send + Sync + 'static {
     /// subgraph block pointer to `block_ptr_to`.
     ///
     /// `block_ptr_to` must point to the parent block of the subgraph block pointer.
-    fn revert_block_operations(
+    async fn revert_block_operations(
         &self,
         block_ptr_to: BlockPtr,
         firehose_cursor: Option<&str>,
@@ -170,7 +311,7 @@ pub trait WritableStore: Send + Sync + 'static {
 
     /// If a deterministic error happened, this function reverts the block operations from the
     /// current block to the previous block.
-    fn unfail_deterministic_error(
+    async fn unfail_deterministic_error(
         &self,
         current_ptr: &BlockPtr,
         parent_ptr: &BlockPtr,
@@ -178,7 +319,7 @@ pub trait WritableStore: Send + Sync + 'static {
 
     /// If a non-deterministic error happened and the current deployment head is past the error
     /// block range, this function unfails the subgraph and deletes the error.
-    fn unfail_non_deterministic_error(
+    async fn unfail_non_deterministic_error(
         &self,
         current_ptr: &BlockPtr,
     ) -> Result<UnfailOutcome, StoreError>;
@@ -195,7 +336,11 @@ pub trait WritableStore: Send + Sync + 'static {
     /// subgraph block pointer to `block_ptr_to`, and update the firehose cursor to `firehose_cursor`
     ///
     /// `block_ptr_to` must point to a child block of the current subgraph block pointer.
-    fn transact_block_operations(
+    ///
+    /// `bulk` is set when the deployment is far behind the chain head. The
+    /// store then writes entities with `COPY`, which is faster when many
+    /// entities are written
+    async fn transact_block_operations(
         &self,
         block_ptr_to: BlockPtr,
         firehose_cursor: Option<String>,
@@ -203,6 +348,9 @@ pub trait WritableStore: Send + Sync + 'static {
         stopwatch: &StopwatchMetrics,
         data_sources: Vec<StoredDynamicDataSource>,
         deterministic_errors: Vec<SubgraphError>,
+        mapping_logs: Vec<MappingLog>,
+        quarantined: Vec<QuarantinedDataSource>,
+        bulk: bool,
     ) -> Result<(), StoreError>;
 
     /// Look up multiple entities as of the latest block. Returns a map of
@@ -215,13 +363,19 @@ pub trait WritableStore: Send + Sync + 'static {
     /// The deployment `id` finished syncing, mark it as synced in the database
     /// and promote it to the current version in the subgraphs where it was the
     /// pending version so far
-    fn deployment_synced(&self) -> Result<(), StoreError>;
+    async fn deployment_synced(&self) -> Result<(), StoreError>;
+
+    /// The deployment is healthy and close enough to the chain head to
+    /// replace the current version of the subgraphs where it is the pending
+    /// version. Promote it, and retire the deployments it replaces after
+    /// `GRAPH_CANARY_RETIRE_AFTER`
+    async fn promote_canary(&self) -> Result<(), StoreError>;
 
     /// Return true if the deployment with the given id is fully synced,
     /// and return false otherwise. Errors from the store are passed back up
     async fn is_deployment_synced(&self) -> Result<bool, StoreError>;
 
-    fn unassign_subgraph(&self) -> Result<(), StoreError>;
+    async fn unassign_subgraph(&self) -> Result<(), StoreError>;
 
     /// Load the dynamic data sources for the given deployment
     async fn load_dynamic_data_sources(&self) -> Result<Vec<StoredDynamicDataSource>, StoreError>;
@@ -233,6 +387,11 @@ pub trait WritableStore: Send + Sync + 'static {
     async fn health(&self, id: &DeploymentHash) -> Result<SubgraphHealth, StoreError>;
 
     fn input_schema(&self) -> Arc<Schema>;
+
+    /// Wait until all blocks passed to `transact_block_operations` have
+    /// been written to the store. Return the error from writing a block if
+    /// that failed
+    async fn flush(&self) -> Result<(), StoreError>;
 }
 
 #[async_trait]
@@ -350,6 +509,15 @@ pub trait ChainStore: Send + Sync + 'static {
     /// Find the block with `block_hash` and return the network name and number
     fn block_number(&self, block_hash: H256) -> Result<Option<(String, BlockNumber)>, StoreError>;
 
+    /// Find the first block whose timestamp, in seconds since the epoch, is
+    /// at or after `timestamp` and return the network name and number. Only
+    /// cached blocks are considered, and it is an error if the cache does
+    /// not have the blocks needed to be sure which block is the first one
+    fn block_number_by_timestamp(
+        &self,
+        timestamp: u64,
+    ) -> Result<Option<(String, BlockNumber)>, StoreError>;
+
     /// Tries to retrieve all transactions receipts for a given block.
     async fn transaction_receipts_in_block(
         &self,
@@ -389,17 +557,45 @@ pub trait QueryStore: Send + Sync {
         query: EntityQuery,
     ) -> Result<Vec<BTreeMap<String, r::Value>>, QueryExecutionError>;
 
+    /// Fetch the entities that match `query` through a database cursor and
+    /// pass them to `sink` in batches of up to `batch_size` entities. All
+    /// entities in the range of `query` are fetched unless `sink` returns
+    /// `false` to stop early
+    fn stream_query_values(
+        &self,
+        query: EntityQuery,
+        batch_size: usize,
+        sink: &mut dyn FnMut(Vec<BTreeMap<String, r::Value>>) -> bool,
+    ) -> Result<(), QueryExecutionError>;
+
+    /// Compute `aggregates` over all entities that match `query`; the
+    /// order and range of `query` are ignored. The result contains one
+    /// value for each entry in `aggregates`, in the same order
+    fn aggregate_query_values(
+        &self,
+        query: EntityQuery,
+        aggregates: &[EntityAggregate],
+    ) -> Result<Vec<r::Value>, QueryExecutionError>;
+
     async fn is_deployment_synced(&self) -> Result<bool, Error>;
 
     async fn block_ptr(&self) -> Result<Option<BlockPtr>, StoreError>;
 
     fn block_number(&self, block_hash: H256) -> Result<Option<BlockNumber>, StoreError>;
 
+    /// Find the number of the first block whose timestamp, in seconds
+    /// since the epoch, is at or after `timestamp`
+    fn block_number_by_timestamp(&self, timestamp: u64) -> Result<Option<BlockNumber>, StoreError>;
+
     fn wait_stats(&self) -> PoolWaitStats;
 
     /// If `block` is `None`, assumes the latest block.
     async fn has_non_fatal_errors(&self, block: Option<BlockNumber>) -> Result<bool, StoreError>;
 
+    /// The numbers of the blocks up to and including `block` that operators
+    /// told graph-node to skip, completely or for some handlers
+    async fn skipped_blocks(&self, block: BlockNumber) -> Result<Vec<BlockNumber>, StoreError>;
+
     /// Find the current state for the subgraph deployment `id` and
     /// return details about it needed for executing queries
     async fn deployment_state(&self) -> Result<DeploymentState, QueryExecutionError>;
@@ -451,4 +647,15 @@ pub trait StatusStore: Send + Sync + 'static {
         indexer: &Option<Address>,
         block: BlockPtr,
     ) -> Result<Option<[u8; 32]>, StoreError>;
+
+    /// The proofs of indexing for all blocks from `from` to `to`, both
+    /// inclusive, that the deployment has processed. Blocks whose hash is
+    /// not known unambiguously from the block cache are left out
+    async fn get_proofs_of_indexing(
+        &self,
+        subgraph_id: &DeploymentHash,
+        indexer: &Option<Address>,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<Vec<(BlockPtr, [u8; 32])>, StoreError>;
 }
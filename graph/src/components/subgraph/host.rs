@@ -1,5 +1,6 @@
 use std::cmp::PartialEq;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 
 use anyhow::Error;
@@ -64,11 +65,34 @@ pub trait RuntimeHost<C: Blockchain>: Send + Sync + 'static {
     /// Block number in which this host was created.
     /// Returns `None` for static data sources.
     fn creation_block_number(&self) -> Option<BlockNumber>;
+
+    /// The data source whose mappings this host runs
+    fn data_source(&self) -> &C::DataSource;
+}
+
+/// Something that happened while running mappings. It is only recorded
+/// for metrics that were created with `HostMetrics::with_trace`
+#[derive(Clone, Debug)]
+pub enum HostTrace {
+    /// A handler finished running
+    Handler {
+        handler: String,
+        data_source: String,
+        gas_used: u64,
+        duration: Duration,
+    },
+    /// A mapping called a host function
+    HostFn { name: String, duration: Duration },
 }
 
 pub struct HostMetrics {
     handler_execution_time: Box<HistogramVec>,
+    handler_trigger_count: Box<HistogramVec>,
     host_fn_execution_time: Box<HistogramVec>,
+    /// The number of triggers each handler, identified by its name and the
+    /// name of its data source, processed in the current block
+    handler_triggers: Mutex<HashMap<(String, String), usize>>,
+    trace: Option<Mutex<Vec<HostTrace>>>,
     pub stopwatch: StopwatchMetrics,
 }
 
@@ -83,10 +107,19 @@ impl HostMetrics {
                 "deployment_handler_execution_time",
                 "Measures the execution time for handlers",
                 subgraph,
-                vec![String::from("handler")],
-                vec![0.1, 0.5, 1.0, 10.0, 100.0],
+                vec![String::from("handler"), String::from("data_source")],
+                vec![0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 10.0, 100.0],
             )
             .expect("failed to create `deployment_handler_execution_time` histogram");
+        let handler_trigger_count = registry
+            .new_deployment_histogram_vec(
+                "deployment_handler_trigger_count",
+                "Measures the number of triggers each handler processes in a block",
+                subgraph,
+                vec![String::from("handler"), String::from("data_source")],
+                vec![1.0, 5.0, 10.0, 50.0, 100.0, 500.0, 1000.0],
+            )
+            .expect("failed to create `deployment_handler_trigger_count` histogram");
         let host_fn_execution_time = registry
             .new_deployment_histogram_vec(
                 "deployment_host_fn_execution_time",
@@ -98,21 +131,83 @@ impl HostMetrics {
             .expect("failed to create `deployment_host_fn_execution_time` histogram");
         Self {
             handler_execution_time,
+            handler_trigger_count,
             host_fn_execution_time,
+            handler_triggers: Mutex::new(HashMap::new()),
+            trace: None,
             stopwatch,
         }
     }
 
-    pub fn observe_handler_execution_time(&self, duration: f64, handler: &str) {
+    /// Also keep a trace of the handlers and host functions that run
+    pub fn with_trace(self) -> Self {
+        Self {
+            trace: Some(Mutex::new(Vec::new())),
+            ..self
+        }
+    }
+
+    /// Return everything that was traced so far and clear the trace
+    pub fn take_trace(&self) -> Vec<HostTrace> {
+        match &self.trace {
+            Some(trace) => std::mem::take(&mut *trace.lock().unwrap()),
+            None => Vec::new(),
+        }
+    }
+
+    fn trace(&self, event: HostTrace) {
+        if let Some(trace) = &self.trace {
+            trace.lock().unwrap().push(event);
+        }
+    }
+
+    /// Record the gas that a handler used in the trace
+    pub fn trace_handler(
+        &self,
+        handler: &str,
+        data_source: &str,
+        gas_used: u64,
+        duration: Duration,
+    ) {
+        self.trace(HostTrace::Handler {
+            handler: handler.to_string(),
+            data_source: data_source.to_string(),
+            gas_used,
+            duration,
+        });
+    }
+
+    pub fn observe_handler_execution_time(&self, duration: f64, handler: &str, data_source: &str) {
         self.handler_execution_time
-            .with_label_values(&[handler][..])
+            .with_label_values(&[handler, data_source][..])
             .observe(duration);
+        *self
+            .handler_triggers
+            .lock()
+            .unwrap()
+            .entry((handler.to_string(), data_source.to_string()))
+            .or_insert(0) += 1;
+    }
+
+    /// Record how many triggers each handler processed in the block that
+    /// was just processed and reset the counts for the next block
+    pub fn observe_block_handler_triggers(&self) {
+        let triggers = std::mem::take(&mut *self.handler_triggers.lock().unwrap());
+        for ((handler, data_source), count) in triggers {
+            self.handler_trigger_count
+                .with_label_values(&[handler.as_str(), data_source.as_str()][..])
+                .observe(count as f64);
+        }
     }
 
     pub fn observe_host_fn_execution_time(&self, duration: f64, fn_name: &str) {
         self.host_fn_execution_time
             .with_label_values(&[fn_name][..])
             .observe(duration);
+        self.trace(HostTrace::HostFn {
+            name: fn_name.to_string(),
+            duration: Duration::from_secs_f64(duration),
+        });
     }
 
     pub fn time_host_fn_execution_region(
@@ -160,6 +255,7 @@ pub trait RuntimeHostBuilder<C: Blockchain>: Clone + Send + Sync + 'static {
     /// Spawn a mapping and return a channel for mapping requests. The sender should be able to be
     /// cached and shared among mappings that use the same wasm file.
     fn spawn_mapping(
+        &self,
         raw_module: Vec<u8>,
         logger: Logger,
         subgraph_id: DeploymentHash,
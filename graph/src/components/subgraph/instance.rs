@@ -1,7 +1,10 @@
 use crate::blockchain::Blockchain;
 use crate::prelude::*;
 use crate::util::lfu_cache::LfuCache;
-use crate::{components::store::WritableStore, data::subgraph::schema::SubgraphError};
+use crate::{
+    components::store::WritableStore,
+    data::subgraph::schema::{MappingLog, QuarantinedDataSource, SubgraphError},
+};
 
 #[derive(Clone, Debug)]
 pub struct DataSourceTemplateInfo<C: Blockchain> {
@@ -15,6 +18,10 @@ pub struct DataSourceTemplateInfo<C: Blockchain> {
 pub struct BlockState<C: Blockchain> {
     pub entity_cache: EntityCache,
     pub deterministic_errors: Vec<SubgraphError>,
+    pub mapping_logs: Vec<MappingLog>,
+    /// Data sources that failed in this block and whose handlers should
+    /// not be run anymore
+    pub quarantined: Vec<QuarantinedDataSource>,
     created_data_sources: Vec<DataSourceTemplateInfo<C>>,
 
     // Data sources created in the current handler.
@@ -32,6 +39,8 @@ impl<C: Blockchain> BlockState<C> {
         BlockState {
             entity_cache: EntityCache::with_current(store, lfu_cache),
             deterministic_errors: Vec::new(),
+            mapping_logs: Vec::new(),
+            quarantined: Vec::new(),
             created_data_sources: Vec::new(),
             handler_created_data_sources: Vec::new(),
             in_handler: false,
@@ -44,6 +53,8 @@ impl<C: Blockchain> BlockState<C> {
         let BlockState {
             entity_cache,
             deterministic_errors,
+            mapping_logs,
+            quarantined,
             created_data_sources,
             handler_created_data_sources,
             in_handler,
@@ -54,6 +65,8 @@ impl<C: Blockchain> BlockState<C> {
             false => created_data_sources.extend(other.created_data_sources),
         }
         deterministic_errors.extend(other.deterministic_errors);
+        mapping_logs.extend(other.mapping_logs);
+        quarantined.extend(other.quarantined);
         entity_cache.extend(other.entity_cache);
     }
 
@@ -93,6 +106,13 @@ impl<C: Blockchain> BlockState<C> {
         self.deterministic_errors.push(e);
     }
 
+    /// Remember a message that a mapping logged so that it can be stored
+    /// with the block. Messages are kept even if the handler fails since
+    /// they are most useful to find out why it failed
+    pub fn push_mapping_log(&mut self, log: MappingLog) {
+        self.mapping_logs.push(log);
+    }
+
     pub fn push_created_data_source(&mut self, ds: DataSourceTemplateInfo<C>) {
         assert!(self.in_handler);
         self.handler_created_data_sources.push(ds);
@@ -7,7 +7,7 @@ mod registrar;
 
 pub use crate::prelude::Entity;
 
-pub use self::host::{HostMetrics, MappingError, RuntimeHost, RuntimeHostBuilder};
+pub use self::host::{HostMetrics, HostTrace, MappingError, RuntimeHost, RuntimeHostBuilder};
 pub use self::instance::{BlockState, DataSourceTemplateInfo};
 pub use self::instance_manager::SubgraphInstanceManager;
 pub use self::proof_of_indexing::{
@@ -8,12 +8,20 @@ use crate::prelude::*;
 pub enum SubgraphVersionSwitchingMode {
     Instant,
     Synced,
+    /// Like `Synced`, but the new deployment becomes the current version
+    /// once it is healthy and close enough to the chain head, and the
+    /// deployment it replaces keeps indexing for a while longer
+    Canary,
 }
 
 impl SubgraphVersionSwitchingMode {
     pub fn parse(mode: &str) -> Self {
         Self::from_str(mode).unwrap()
     }
+
+    pub fn is_canary(&self) -> bool {
+        matches!(self, SubgraphVersionSwitchingMode::Canary)
+    }
 }
 
 impl FromStr for SubgraphVersionSwitchingMode {
@@ -23,6 +31,7 @@ impl FromStr for SubgraphVersionSwitchingMode {
         match s.to_ascii_lowercase().as_str() {
             "instant" => Ok(SubgraphVersionSwitchingMode::Instant),
             "synced" => Ok(SubgraphVersionSwitchingMode::Synced),
+            "canary" => Ok(SubgraphVersionSwitchingMode::Canary),
             _ => Err(format!("invalid version switching mode: {:?}", s)),
         }
     }
@@ -43,8 +52,18 @@ pub trait SubgraphRegistrar: Send + Sync + 'static {
         assignment_node_id: NodeId,
         debug_fork: Option<DeploymentHash>,
         start_block: Option<BlockPtr>,
+        auto_graft: bool,
+        upgrade_in_place: bool,
     ) -> Result<(), SubgraphRegistrarError>;
 
+    /// Check whether the subgraph `hash` could be deployed and return all
+    /// problems that would prevent that, without creating anything. An
+    /// empty vector means that no problems were found
+    async fn validate_subgraph_version(
+        &self,
+        hash: DeploymentHash,
+    ) -> Result<Vec<String>, SubgraphRegistrarError>;
+
     async fn remove_subgraph(&self, name: SubgraphName) -> Result<(), SubgraphRegistrarError>;
 
     async fn reassign_subgraph(
@@ -52,4 +71,16 @@ pub trait SubgraphRegistrar: Send + Sync + 'static {
         hash: &DeploymentHash,
         node_id: &NodeId,
     ) -> Result<(), SubgraphRegistrarError>;
+
+    async fn pause_subgraph(&self, hash: &DeploymentHash) -> Result<(), SubgraphRegistrarError>;
+
+    async fn resume_subgraph(&self, hash: &DeploymentHash) -> Result<(), SubgraphRegistrarError>;
+
+    async fn unassign_subgraph(&self, hash: &DeploymentHash) -> Result<(), SubgraphRegistrarError>;
+
+    async fn rewind_subgraph(
+        &self,
+        hash: &DeploymentHash,
+        block_ptr_to: BlockPtr,
+    ) -> Result<(), SubgraphRegistrarError>;
 }
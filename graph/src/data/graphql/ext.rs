@@ -10,6 +10,15 @@ use std::collections::{BTreeMap, HashMap};
 pub trait ObjectTypeExt {
     fn field(&self, name: &str) -> Option<&Field>;
     fn is_meta(&self) -> bool;
+    /// Whether this is an entity type declared with
+    /// `@entity(timeseries: true)`
+    fn is_timeseries(&self) -> bool;
+    /// Whether this type has an `@aggregation` directive
+    fn is_aggregation(&self) -> bool;
+    /// Whether the attribute `field` should be indexed. If any field of the
+    /// type has an `@index` directive, only those fields are indexed.
+    /// Otherwise, all fields without a `@noIndex` directive are indexed
+    fn is_indexed(&self, field: &Field) -> bool;
 }
 
 impl ObjectTypeExt for ObjectType {
@@ -20,6 +29,29 @@ impl ObjectTypeExt for ObjectType {
     fn is_meta(&self) -> bool {
         self.name == META_FIELD_TYPE
     }
+
+    fn is_timeseries(&self) -> bool {
+        self.find_directive("entity")
+            .and_then(|dir| dir.argument("timeseries"))
+            .map(|value| matches!(value, Value::Boolean(true)))
+            .unwrap_or(false)
+    }
+
+    fn is_aggregation(&self) -> bool {
+        self.find_directive("aggregation").is_some()
+    }
+
+    fn is_indexed(&self, field: &Field) -> bool {
+        if self
+            .fields
+            .iter()
+            .any(|field| field.find_directive("index").is_some())
+        {
+            field.find_directive("index").is_some()
+        } else {
+            field.find_directive("noIndex").is_none()
+        }
+    }
 }
 
 impl ObjectTypeExt for InterfaceType {
@@ -30,6 +62,18 @@ impl ObjectTypeExt for InterfaceType {
     fn is_meta(&self) -> bool {
         false
     }
+
+    fn is_timeseries(&self) -> bool {
+        false
+    }
+
+    fn is_aggregation(&self) -> bool {
+        false
+    }
+
+    fn is_indexed(&self, _field: &Field) -> bool {
+        true
+    }
 }
 
 pub trait DocumentExt {
@@ -51,6 +95,12 @@ pub trait DocumentExt {
 
     fn get_root_subscription_type(&self) -> Option<&ObjectType>;
 
+    /// The root `Mutation` type. Mutations are only enabled for schemas
+    /// that name their mutation type in an explicit `schema { .. }`
+    /// definition, so that an entity type called `Mutation` does not turn
+    /// into a mutation root
+    fn get_root_mutation_type(&self) -> Option<&ObjectType>;
+
     fn object_or_interface(&self, name: &str) -> Option<ObjectOrInterface<'_>>;
 
     fn get_named_type(&self, name: &str) -> Option<&TypeDefinition>;
@@ -171,6 +221,16 @@ impl DocumentExt for Document {
             .next()
     }
 
+    fn get_root_mutation_type(&self) -> Option<&ObjectType> {
+        self.definitions
+            .iter()
+            .find_map(|d| match d {
+                Definition::SchemaDefinition(schema) => schema.mutation.as_deref(),
+                _ => None,
+            })
+            .and_then(|name| self.get_object_type_definition(name))
+    }
+
     fn object_or_interface(&self, name: &str) -> Option<ObjectOrInterface<'_>> {
         match self.get_named_type(name) {
             Some(TypeDefinition::Object(t)) => Some(t.into()),
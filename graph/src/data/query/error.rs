@@ -2,6 +2,7 @@ use graphql_parser::Pos;
 use hex::FromHexError;
 use num_bigint;
 use serde::ser::*;
+use serde::Serialize;
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
@@ -10,7 +11,7 @@ use std::sync::Arc;
 
 use crate::data::graphql::SerializableValue;
 use crate::data::subgraph::*;
-use crate::prelude::q;
+use crate::prelude::{q, BlockNumber, BlockPtr};
 use crate::{components::store::StoreError, prelude::CacheWeight};
 
 #[derive(Debug)]
@@ -83,6 +84,9 @@ pub enum QueryExecutionError {
     SubgraphManifestResolveError(Arc<SubgraphManifestResolveError>),
     InvalidSubgraphManifest,
     ResultTooBig(usize, usize),
+    SubscriptionCursorTooOld(BlockNumber, BlockNumber), // (cursor, oldest block that can be replayed)
+    Unauthorized(String),
+    AdminOperationFailed(String),
 }
 
 impl QueryExecutionError {
@@ -139,7 +143,71 @@ impl QueryExecutionError {
             | SubgraphManifestResolveError(_)
             | InvalidSubgraphManifest
             | ValidationError(_, _)
-            | ResultTooBig(_, _) => false,
+            | ResultTooBig(_, _)
+            | SubscriptionCursorTooOld(_, _)
+            | Unauthorized(_)
+            | AdminOperationFailed(_) => false,
+        }
+    }
+
+    /// A stable code for the kind of error that clients can use to decide
+    /// how to handle it. It is sent in the `extensions` of the error
+    pub fn code(&self) -> &'static str {
+        use self::QueryExecutionError::*;
+        match self {
+            OperationNameRequired
+            | OperationNotFound(_)
+            | NoRootSubscriptionObjectType
+            | InvalidArgumentError(_, _, _)
+            | MissingArgumentError(_, _)
+            | ValidationError(_, _)
+            | InvalidVariableTypeError(_, _)
+            | MissingVariableError(_, _)
+            | UnknownField(_, _, _)
+            | EmptyQuery
+            | MultipleSubscriptionFields
+            | EmptySelectionSet(_)
+            | CyclicalFragment(_)
+            | UndefinedFragment(_) => "GRAPHQL_VALIDATION_FAILED",
+            OrderByNotSupportedError(_, _)
+            | OrderByNotSupportedForType(_)
+            | FilterNotSupportedError(_, _)
+            | RangeArgumentsError(_, _, _)
+            | InvalidFilterError
+            | EntityFieldError(_, _)
+            | ListTypesError(_, _)
+            | ListFilterError(_)
+            | ValueParseError(_, _)
+            | AttributeTypeError(_, _)
+            | EnumCoercionError(_, _, _, _, _)
+            | ScalarCoercionError(_, _, _, _)
+            | FulltextQueryRequiresFilter
+            | FulltextQueryInvalidSyntax(_) => "BAD_USER_INPUT",
+            NotSupported(_) | Unimplemented(_) => "NOT_SUPPORTED",
+            NonNullError(_, _)
+            | ListValueError(_, _)
+            | NamedTypeError(_)
+            | AbstractTypeError(_)
+            | ResolveEntitiesError(_)
+            | SubgraphDeploymentIdError(_)
+            | EntityParseError(_)
+            | AmbiguousDerivedFromResult(_, _, _, _)
+            | IncorrectPrefetchResult { .. } => "RESOLVE_FAILED",
+            StoreError(_) => "STORE_UNAVAILABLE",
+            Timeout => "TIMEOUT",
+            TooComplex(_, _) => "TOO_COMPLEX",
+            TooDeep(_) => "TOO_DEEP",
+            TooExpensive => "TOO_EXPENSIVE",
+            Throttled => "THROTTLED",
+            ResultTooBig(_, _) => "RESULT_TOO_BIG",
+            DeploymentReverted => "DEPLOYMENT_REVERTED",
+            SubscriptionCursorTooOld(_, _) => "CURSOR_TOO_OLD",
+            Unauthorized(_) => "UNAUTHORIZED",
+            AdminOperationFailed(_) => "ADMIN_OPERATION_FAILED",
+            SubgraphManifestResolveError(_) | InvalidSubgraphManifest => {
+                "INVALID_SUBGRAPH_MANIFEST"
+            }
+            Panic(_) | EventStreamError => "INTERNAL_SERVER_ERROR",
         }
     }
 }
@@ -287,6 +355,9 @@ impl fmt::Display for QueryExecutionError {
             SubgraphManifestResolveError(e) => write!(f, "failed to resolve subgraph manifest: {}", e),
             InvalidSubgraphManifest => write!(f, "invalid subgraph manifest file"),
             ResultTooBig(actual, limit) => write!(f, "the result size of {} is larger than the allowed limit of {}", actual, limit),
+            SubscriptionCursorTooOld(cursor, oldest) => write!(f, "the subscription can not be resumed from block {} since results are only replayed from block {}; results for the blocks in between were skipped", cursor, oldest),
+            Unauthorized(msg) => write!(f, "unauthorized: {}", msg),
+            AdminOperationFailed(msg) => write!(f, "{}", msg),
         }
     }
 }
@@ -344,6 +415,53 @@ impl QueryError {
             QueryError::IndexingError => false,
         }
     }
+
+    /// A stable code for the kind of error; see `QueryExecutionError::code`
+    pub fn code(&self) -> &'static str {
+        match self {
+            QueryError::EncodingError(_) | QueryError::ParseError(_) => "GRAPHQL_PARSE_FAILED",
+            QueryError::ExecutionError(err) => err.code(),
+            QueryError::IndexingError => "INDEXING_ERROR",
+        }
+    }
+
+    /// Serialize this error with the deployment and the block at which
+    /// the query that caused it was run in its `extensions`
+    pub fn with_context<'a>(
+        &'a self,
+        deployment: Option<&'a DeploymentHash>,
+        block: Option<&'a BlockPtr>,
+    ) -> QueryErrorWithContext<'a> {
+        QueryErrorWithContext {
+            error: self,
+            deployment,
+            block,
+        }
+    }
+}
+
+/// A `QueryError` together with information about the query that caused
+/// it for serialization
+pub struct QueryErrorWithContext<'a> {
+    error: &'a QueryError,
+    deployment: Option<&'a DeploymentHash>,
+    block: Option<&'a BlockPtr>,
+}
+
+/// The `extensions` of a serialized `QueryError`
+#[derive(Serialize)]
+struct ErrorExtensions<'a> {
+    code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deployment: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    block: Option<ErrorBlock>,
+}
+
+#[derive(Serialize)]
+struct ErrorBlock {
+    number: BlockNumber,
+    hash: String,
 }
 
 impl From<FromUtf8Error> for QueryError {
@@ -386,6 +504,15 @@ impl fmt::Display for QueryError {
 }
 
 impl Serialize for QueryError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.with_context(None, None).serialize(serializer)
+    }
+}
+
+impl Serialize for QueryErrorWithContext<'_> {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: Serializer,
@@ -395,21 +522,21 @@ impl Serialize for QueryError {
         let entry_count =
             if let QueryError::ExecutionError(QueryExecutionError::IncorrectPrefetchResult {
                 ..
-            }) = self
+            }) = self.error
             {
-                3
+                5
             } else {
-                1
+                2
             };
         let mut map = serializer.serialize_map(Some(entry_count))?;
 
-        let msg = match self {
+        let msg = match self.error {
             // Serialize parse errors with their location (line, column) to make it easier
             // for users to find where the errors are; this is likely to change as the
             // graphql_parser team makes improvements to their error reporting
             QueryError::ParseError(_) => {
                 // Split the inner message into (first line, rest)
-                let msg = format!("{}", self);
+                let msg = format!("{}", self.error);
                 let inner_msg = msg.replace("query parse error:", "");
                 let inner_msg = inner_msg.trim();
                 let parts: Vec<&str> = inner_msg.splitn(2, '\n').collect();
@@ -455,18 +582,27 @@ impl Serialize for QueryError {
                 location.insert("line", pos.line);
                 location.insert("column", pos.column);
                 map.serialize_entry("locations", &vec![location])?;
-                format!("{}", self)
+                format!("{}", self.error)
             }
             QueryError::ExecutionError(IncorrectPrefetchResult { slow, prefetch }) => {
                 map.serialize_entry("incorrectPrefetch", &true)?;
                 map.serialize_entry("single", &SerializableValue(slow))?;
                 map.serialize_entry("prefetch", &SerializableValue(prefetch))?;
-                format!("{}", self)
+                format!("{}", self.error)
             }
-            _ => format!("{}", self),
+            _ => format!("{}", self.error),
         };
 
         map.serialize_entry("message", msg.as_str())?;
+        let extensions = ErrorExtensions {
+            code: self.error.code(),
+            deployment: self.deployment.map(|deployment| deployment.as_str()),
+            block: self.block.map(|block| ErrorBlock {
+                number: block.number,
+                hash: block.hash.to_string(),
+            }),
+        };
+        map.serialize_entry("extensions", &extensions)?;
         map.end()
     }
 }
@@ -477,3 +613,39 @@ impl CacheWeight for QueryError {
         0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{QueryError, QueryExecutionError};
+    use crate::prelude::{BlockPtr, DeploymentHash};
+    use serde_json::json;
+
+    #[test]
+    fn errors_have_codes_in_extensions() {
+        let err = QueryError::from(QueryExecutionError::Timeout);
+        assert_eq!(
+            json!({
+                "message": "Query timed out",
+                "extensions": { "code": "TIMEOUT" }
+            }),
+            serde_json::to_value(&err).unwrap()
+        );
+
+        let deployment = DeploymentHash::new("QmTest").unwrap();
+        let block = BlockPtr::from((vec![1u8; 32], 7i32));
+        assert_eq!(
+            json!({
+                "message": "indexing_error",
+                "extensions": {
+                    "code": "INDEXING_ERROR",
+                    "deployment": "QmTest",
+                    "block": { "number": 7, "hash": format!("0x{}", "01".repeat(32)) }
+                }
+            }),
+            serde_json::to_value(
+                &QueryError::IndexingError.with_context(Some(&deployment), Some(&block))
+            )
+            .unwrap()
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use crate::prelude::{Deserialize, Serialize};
+
+/// Limits on the queries that can be run against a deployment. Limits that
+/// are not set fall back to the global limits from the environment
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct QueryLimits {
+    pub max_complexity: Option<u64>,
+    pub max_depth: Option<u8>,
+    pub max_first: Option<u32>,
+    pub max_skip: Option<u32>,
+}
+
+impl QueryLimits {
+    /// Use the limits from `other` for the limits that are not set in `self`
+    pub fn or(self, other: &QueryLimits) -> QueryLimits {
+        QueryLimits {
+            max_complexity: self.max_complexity.or(other.max_complexity),
+            max_depth: self.max_depth.or(other.max_depth),
+            max_first: self.max_first.or(other.max_first),
+            max_skip: self.max_skip.or(other.max_skip),
+        }
+    }
+
+    /// The stricter of the limits in `self` and `other`; limits that are
+    /// only set in one of them are used as is
+    pub fn min(self, other: &QueryLimits) -> QueryLimits {
+        fn min<T: Ord>(a: Option<T>, b: Option<T>) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            }
+        }
+
+        QueryLimits {
+            max_complexity: min(self.max_complexity, other.max_complexity),
+            max_depth: min(self.max_depth, other.max_depth),
+            max_first: min(self.max_first, other.max_first),
+            max_skip: min(self.max_skip, other.max_skip),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QueryLimits;
+
+    #[test]
+    fn min_uses_stricter_limits() {
+        let requested = QueryLimits {
+            max_complexity: Some(1000),
+            max_first: Some(50),
+            ..QueryLimits::default()
+        };
+        let deployment = QueryLimits {
+            max_complexity: Some(100),
+            max_first: Some(500),
+            max_skip: Some(10),
+            ..QueryLimits::default()
+        };
+        let limits = requested.min(&deployment);
+        assert_eq!(Some(100), limits.max_complexity);
+        assert_eq!(None, limits.max_depth);
+        assert_eq!(Some(50), limits.max_first);
+        assert_eq!(Some(10), limits.max_skip);
+    }
+}
@@ -1,9 +1,18 @@
 mod cache_status;
 mod error;
+mod limits;
+mod persisted;
 mod query;
 mod result;
+mod stats;
 
 pub use self::cache_status::CacheStatus;
-pub use self::error::{QueryError, QueryExecutionError};
+pub use self::error::{QueryError, QueryErrorWithContext, QueryExecutionError};
+pub use self::limits::QueryLimits;
+pub use self::persisted::persisted_query_hash;
 pub use self::query::{Query, QueryTarget, QueryVariables};
-pub use self::result::{QueryResult, QueryResults};
+pub use self::result::{
+    IncrementalPayload, IncrementalPayloadStream, IncrementalResponse, IncrementalResult,
+    QueryResult, QueryResults,
+};
+pub use self::stats::{entity_count, QueryShapeSummary, QueryStats, QueryStatsOrder};
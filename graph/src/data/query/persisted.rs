@@ -0,0 +1,21 @@
+use sha2::{Digest, Sha256};
+
+/// The hash under which the query with text `query` is persisted. Like
+/// Apollo's automatic persisted queries, this is the hex-encoded SHA-256
+/// of the query text, so that clients can compute it themselves
+pub fn persisted_query_hash(query: &str) -> String {
+    hex::encode(Sha256::digest(query.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::persisted_query_hash;
+
+    #[test]
+    fn hash_is_hex_sha256() {
+        assert_eq!(
+            persisted_query_hash("{ __typename }"),
+            "7f56e67dd21ab3f30d1ff8b7bed08893f0a0db86449836189b361dd1e56ddb4b"
+        );
+    }
+}
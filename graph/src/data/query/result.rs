@@ -1,6 +1,6 @@
 use super::error::{QueryError, QueryExecutionError};
 use crate::data::value::Object;
-use crate::prelude::{r, CacheWeight, DeploymentHash};
+use crate::prelude::{r, BlockPtr, CacheWeight, DeploymentHash};
 use http::header::{
     ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
     CONTENT_TYPE,
@@ -8,6 +8,7 @@ use http::header::{
 use serde::ser::*;
 use serde::Serialize;
 use std::convert::TryFrom;
+use std::pin::Pin;
 use std::sync::Arc;
 
 fn serialize_data<S>(data: &Option<Data>, serializer: S) -> Result<S::Ok, S::Error>
@@ -45,18 +46,47 @@ pub type Data = Object;
 /// A collection of query results that is serialized as a single result.
 pub struct QueryResults {
     results: Vec<Arc<QueryResult>>,
+    /// The complexity of the query that produced the results
+    complexity: u64,
 }
 
 impl QueryResults {
     pub fn empty() -> Self {
         QueryResults {
             results: Vec::new(),
+            complexity: 0,
         }
     }
 
     pub fn first(&self) -> Option<&Arc<QueryResult>> {
         self.results.first()
     }
+
+    /// The complexity of the query that produced these results, or 0 if
+    /// the query was never run
+    pub fn complexity(&self) -> u64 {
+        self.complexity
+    }
+
+    pub fn set_complexity(&mut self, complexity: u64) {
+        self.complexity = complexity;
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.results.iter().any(|result| result.has_errors())
+    }
+
+    /// The blocks at which the queries for the results were run, or `None`
+    /// if that is not known for all of them
+    pub fn block_ptrs(&self) -> Option<Vec<&BlockPtr>> {
+        if self.results.is_empty() {
+            return None;
+        }
+        self.results
+            .iter()
+            .map(|result| result.block_ptr.as_ref())
+            .collect()
+    }
 }
 
 impl Serialize for QueryResults {
@@ -96,8 +126,12 @@ impl Serialize for QueryResults {
             impl Serialize for SerError<'_> {
                 fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
                     let mut seq = serializer.serialize_seq(None)?;
-                    for err in self.0.results.iter().map(|r| &r.errors).flatten() {
-                        seq.serialize_element(err)?;
+                    for r in self.0.results.iter() {
+                        for err in &r.errors {
+                            seq.serialize_element(
+                                &err.with_context(r.deployment.as_ref(), r.block_ptr.as_ref()),
+                            )?;
+                        }
                     }
                     seq.end()
                 }
@@ -114,6 +148,7 @@ impl From<Data> for QueryResults {
     fn from(x: Data) -> Self {
         QueryResults {
             results: vec![Arc::new(x.into())],
+            complexity: 0,
         }
     }
 }
@@ -122,13 +157,17 @@ impl From<QueryResult> for QueryResults {
     fn from(x: QueryResult) -> Self {
         QueryResults {
             results: vec![Arc::new(x)],
+            complexity: 0,
         }
     }
 }
 
 impl From<Arc<QueryResult>> for QueryResults {
     fn from(x: Arc<QueryResult>) -> Self {
-        QueryResults { results: vec![x] }
+        QueryResults {
+            results: vec![x],
+            complexity: 0,
+        }
     }
 }
 
@@ -136,6 +175,7 @@ impl From<QueryExecutionError> for QueryResults {
     fn from(x: QueryExecutionError) -> Self {
         QueryResults {
             results: vec![Arc::new(x.into())],
+            complexity: 0,
         }
     }
 }
@@ -144,6 +184,7 @@ impl From<Vec<QueryExecutionError>> for QueryResults {
     fn from(x: Vec<QueryExecutionError>) -> Self {
         QueryResults {
             results: vec![Arc::new(x.into())],
+            complexity: 0,
         }
     }
 }
@@ -153,37 +194,47 @@ impl QueryResults {
         self.results.push(other);
     }
 
+    pub fn is_attestable(&self) -> bool {
+        self.results.iter().all(|r| r.is_attestable())
+    }
+
     pub fn as_http_response<T: From<String>>(&self) -> http::Response<T> {
-        let status_code = http::StatusCode::OK;
         let json =
             serde_json::to_string(self).expect("Failed to serialize GraphQL response to JSON");
+        Self::http_response(json, self.is_attestable())
+    }
+
+    /// The response to a batch of queries: a JSON array with the results
+    /// of each query in the batch
+    pub fn as_batch_http_response<T: From<String>>(batch: &[QueryResults]) -> http::Response<T> {
+        let json =
+            serde_json::to_string(batch).expect("Failed to serialize GraphQL response to JSON");
+        Self::http_response(json, batch.iter().all(|r| r.is_attestable()))
+    }
+
+    fn http_response<T: From<String>>(json: String, attestable: bool) -> http::Response<T> {
+        let status_code = http::StatusCode::OK;
         http::Response::builder()
             .status(status_code)
             .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
             .header(ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type, User-Agent")
             .header(ACCESS_CONTROL_ALLOW_METHODS, "GET, OPTIONS, POST")
             .header(CONTENT_TYPE, "application/json")
-            .header(
-                "Graph-Attestable",
-                self.results.iter().all(|r| r.is_attestable()).to_string(),
-            )
+            .header("Graph-Attestable", attestable.to_string())
             .body(T::from(json))
             .unwrap()
     }
 }
 
 /// The result of running a query, if successful.
-#[derive(Debug, Serialize)]
+#[derive(Debug)]
 pub struct QueryResult {
-    #[serde(
-        skip_serializing_if = "Option::is_none",
-        serialize_with = "serialize_data"
-    )]
     data: Option<Data>,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
     errors: Vec<QueryError>,
-    #[serde(skip_serializing)]
     pub deployment: Option<DeploymentHash>,
+    /// The block at which the query was run, if it was run against a
+    /// specific block
+    pub block_ptr: Option<BlockPtr>,
 }
 
 impl QueryResult {
@@ -192,6 +243,7 @@ impl QueryResult {
             data: Some(data),
             errors: Vec::new(),
             deployment: None,
+            block_ptr: None,
         }
     }
 
@@ -204,6 +256,7 @@ impl QueryResult {
             data: self.data.clone(),
             errors: self.errors.clone(),
             deployment: self.deployment.clone(),
+            block_ptr: self.block_ptr.clone(),
         }
     }
 
@@ -238,6 +291,49 @@ impl QueryResult {
     pub fn errors_mut(&mut self) -> &mut Vec<QueryError> {
         &mut self.errors
     }
+
+    pub fn data(&self) -> Option<&Data> {
+        self.data.as_ref()
+    }
+
+    pub fn errors(&self) -> &[QueryError] {
+        &self.errors
+    }
+}
+
+impl Serialize for QueryResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        struct SerData<'a>(&'a Option<Data>);
+
+        impl Serialize for SerData<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serialize_data(self.0, serializer)
+            }
+        }
+
+        // Errors carry the deployment and block of the query in their
+        // `extensions`
+        struct SerErrors<'a>(&'a QueryResult);
+
+        impl Serialize for SerErrors<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let result = self.0;
+                serializer.collect_seq(result.errors.iter().map(|err| {
+                    err.with_context(result.deployment.as_ref(), result.block_ptr.as_ref())
+                }))
+            }
+        }
+
+        let len = self.data.is_some() as usize + self.has_errors() as usize;
+        let mut state = serializer.serialize_struct("QueryResult", len)?;
+        if self.data.is_some() {
+            state.serialize_field("data", &SerData(&self.data))?;
+        }
+        if self.has_errors() {
+            state.serialize_field("errors", &SerErrors(self))?;
+        }
+        state.end()
+    }
 }
 
 impl From<QueryExecutionError> for QueryResult {
@@ -246,6 +342,7 @@ impl From<QueryExecutionError> for QueryResult {
             data: None,
             errors: vec![e.into()],
             deployment: None,
+            block_ptr: None,
         }
     }
 }
@@ -256,6 +353,7 @@ impl From<QueryError> for QueryResult {
             data: None,
             errors: vec![e],
             deployment: None,
+            block_ptr: None,
         }
     }
 }
@@ -266,6 +364,7 @@ impl From<Vec<QueryExecutionError>> for QueryResult {
             data: None,
             errors: e.into_iter().map(QueryError::from).collect(),
             deployment: None,
+            block_ptr: None,
         }
     }
 }
@@ -296,6 +395,157 @@ impl<V: Into<QueryResult>, E: Into<QueryResult>> From<Result<V, E>> for QueryRes
     }
 }
 
+/// The data for one `@defer` fragment or a batch of items for one `@stream`
+/// field, sent as part of a payload after the initial payload of an
+/// incrementally delivered response
+#[derive(Debug, Serialize)]
+pub struct IncrementalResult {
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_data"
+    )]
+    data: Option<Data>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    items: Option<Vec<r::Value>>,
+    path: Vec<r::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<QueryError>,
+}
+
+impl IncrementalResult {
+    /// The result for a deferred fragment of the object at `path`
+    pub fn data(path: Vec<r::Value>, label: Option<String>, data: Data) -> Self {
+        IncrementalResult {
+            data: Some(data),
+            items: None,
+            path,
+            label,
+            errors: Vec::new(),
+        }
+    }
+
+    /// A batch of items for a streamed list; `path` ends with the index of
+    /// the first item in `items`
+    pub fn items(path: Vec<r::Value>, label: Option<String>, items: Vec<r::Value>) -> Self {
+        IncrementalResult {
+            data: None,
+            items: Some(items),
+            path,
+            label,
+            errors: Vec::new(),
+        }
+    }
+
+    /// The errors that happened while resolving the deferred fragment or
+    /// streamed list at `path`
+    pub fn errors(path: Vec<r::Value>, label: Option<String>, errors: Vec<QueryError>) -> Self {
+        IncrementalResult {
+            data: None,
+            items: None,
+            path,
+            label,
+            errors,
+        }
+    }
+
+    pub fn has_errors(&self) -> bool {
+        !self.errors.is_empty()
+    }
+}
+
+/// One payload of the response to a query that uses `@defer` or `@stream`.
+/// The first payload has the data that was neither deferred nor streamed,
+/// and all later payloads deliver deferred fragments and streamed items
+#[derive(Debug)]
+pub enum IncrementalPayload {
+    Initial {
+        results: QueryResults,
+        has_next: bool,
+    },
+    Subsequent {
+        incremental: Vec<IncrementalResult>,
+        has_next: bool,
+    },
+}
+
+impl IncrementalPayload {
+    pub fn has_next(&self) -> bool {
+        match self {
+            IncrementalPayload::Initial { has_next, .. }
+            | IncrementalPayload::Subsequent { has_next, .. } => *has_next,
+        }
+    }
+}
+
+impl Serialize for IncrementalPayload {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Initial<'a> {
+            #[serde(flatten)]
+            results: &'a QueryResults,
+            has_next: bool,
+        }
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Subsequent<'a> {
+            #[serde(skip_serializing_if = "is_empty")]
+            incremental: &'a Vec<IncrementalResult>,
+            has_next: bool,
+        }
+
+        fn is_empty(incremental: &&Vec<IncrementalResult>) -> bool {
+            incremental.is_empty()
+        }
+
+        match self {
+            IncrementalPayload::Initial { results, has_next } => Initial {
+                results,
+                has_next: *has_next,
+            }
+            .serialize(serializer),
+            IncrementalPayload::Subsequent {
+                incremental,
+                has_next,
+            } => Subsequent {
+                incremental,
+                has_next: *has_next,
+            }
+            .serialize(serializer),
+        }
+    }
+}
+
+pub type IncrementalPayloadStream =
+    Pin<Box<dyn futures03::Stream<Item = IncrementalPayload> + Send>>;
+
+/// The response to a query that was run allowing incremental delivery
+pub enum IncrementalResponse {
+    /// The query did not use `@defer` or `@stream`, and the whole response
+    /// is available at once
+    Complete(QueryResults),
+    /// The query used `@defer` or `@stream`; the stream produces the
+    /// initial payload followed by the payloads with deferred and streamed
+    /// data. The last payload has `has_next` set to `false`
+    Incremental {
+        payloads: IncrementalPayloadStream,
+        complexity: u64,
+    },
+}
+
+impl IncrementalResponse {
+    /// The complexity of the query that produced this response
+    pub fn complexity(&self) -> u64 {
+        match self {
+            IncrementalResponse::Complete(results) => results.complexity(),
+            IncrementalResponse::Incremental { complexity, .. } => *complexity,
+        }
+    }
+}
+
 impl CacheWeight for QueryResult {
     fn indirect_weight(&self) -> usize {
         self.data.indirect_weight() + self.errors.indirect_weight()
@@ -326,3 +576,34 @@ fn multiple_data_items() {
     let actual = serde_json::to_string(&res).unwrap();
     assert_eq!(expected, actual)
 }
+
+#[test]
+fn incremental_payloads() {
+    use serde_json::json;
+
+    let mut map = Object::new();
+    map.insert("key".to_owned(), r::Value::String("value".to_owned()));
+    let initial = IncrementalPayload::Initial {
+        results: QueryResults::from(map.clone()),
+        has_next: true,
+    };
+    let expected = json!({"data": {"key": "value"}, "hasNext": true});
+    assert_eq!(expected, serde_json::to_value(&initial).unwrap());
+
+    let path = vec![r::Value::String("things".to_owned()), r::Value::Int(1)];
+    let subsequent = IncrementalPayload::Subsequent {
+        incremental: vec![
+            IncrementalResult::data(path.clone(), Some("more".to_owned()), map),
+            IncrementalResult::items(path, None, vec![r::Value::Int(7)]),
+        ],
+        has_next: false,
+    };
+    let expected = json!({
+        "incremental": [
+            {"data": {"key": "value"}, "path": ["things", 1], "label": "more"},
+            {"items": [7], "path": ["things", 1]}
+        ],
+        "hasNext": false
+    });
+    assert_eq!(expected, serde_json::to_value(&subsequent).unwrap());
+}
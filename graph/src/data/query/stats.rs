@@ -0,0 +1,275 @@
+//! Statistics about the queries that were run against each deployment,
+//! aggregated by the shape of the query, so that operators can find the
+//! kinds of queries that cause the most work for a deployment
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::data::value::Object;
+use crate::prelude::{r, DeploymentHash, ENV_VARS};
+
+/// Upper bounds (in milliseconds) of the buckets of the latency histogram
+/// that we keep for each query shape. Percentiles are estimated from the
+/// histogram and are therefore only as precise as these buckets
+const LATENCY_BUCKETS_MS: [u64; 16] = [
+    1,
+    2,
+    5,
+    10,
+    20,
+    50,
+    100,
+    200,
+    500,
+    1_000,
+    2_000,
+    5_000,
+    10_000,
+    30_000,
+    60_000,
+    u64::MAX,
+];
+
+/// How to rank query shapes when asking for the top shapes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueryStatsOrder {
+    TotalTime,
+    MeanTime,
+    MaxTime,
+    Count,
+    Rows,
+}
+
+/// The statistics for all queries of one shape
+#[derive(Clone, Debug)]
+struct ShapeStats {
+    /// The text of the first query with this shape that we saw
+    query: Arc<String>,
+    count: u64,
+    total_time: Duration,
+    max_time: Duration,
+    rows: u64,
+    buckets: [u64; LATENCY_BUCKETS_MS.len()],
+}
+
+impl ShapeStats {
+    fn new(query: Arc<String>) -> Self {
+        ShapeStats {
+            query,
+            count: 0,
+            total_time: Duration::ZERO,
+            max_time: Duration::ZERO,
+            rows: 0,
+            buckets: [0; LATENCY_BUCKETS_MS.len()],
+        }
+    }
+
+    fn add(&mut self, elapsed: Duration, rows: usize) {
+        let ms = elapsed.as_millis() as u64;
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| ms <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len() - 1);
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.total_time += elapsed;
+        self.max_time = self.max_time.max(elapsed);
+        self.rows += rows as u64;
+    }
+
+    fn mean_time(&self) -> Duration {
+        self.total_time / self.count.max(1) as u32
+    }
+
+    /// Estimate the latency below which fraction `p` of the queries fall
+    fn percentile(&self, p: f64) -> Duration {
+        let rank = (self.count as f64 * p).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let bound = Duration::from_millis(LATENCY_BUCKETS_MS[bucket]);
+                return bound.min(self.max_time);
+            }
+        }
+        self.max_time
+    }
+
+    fn summary(&self, shape_hash: u64) -> QueryShapeSummary {
+        QueryShapeSummary {
+            shape_hash,
+            query: self.query.clone(),
+            count: self.count,
+            total_time: self.total_time,
+            mean_time: self.mean_time(),
+            p50_time: self.percentile(0.5),
+            p90_time: self.percentile(0.9),
+            p99_time: self.percentile(0.99),
+            max_time: self.max_time,
+            rows: self.rows,
+        }
+    }
+}
+
+/// A snapshot of the statistics for one query shape
+#[derive(Clone, Debug)]
+pub struct QueryShapeSummary {
+    pub shape_hash: u64,
+    /// An example of a query with this shape
+    pub query: Arc<String>,
+    pub count: u64,
+    pub total_time: Duration,
+    pub mean_time: Duration,
+    pub p50_time: Duration,
+    pub p90_time: Duration,
+    pub p99_time: Duration,
+    pub max_time: Duration,
+    /// The total number of entities returned by all queries of this shape
+    pub rows: u64,
+}
+
+impl QueryShapeSummary {
+    fn key(&self, order: QueryStatsOrder) -> u128 {
+        match order {
+            QueryStatsOrder::TotalTime => self.total_time.as_micros(),
+            QueryStatsOrder::MeanTime => self.mean_time.as_micros(),
+            QueryStatsOrder::MaxTime => self.max_time.as_micros(),
+            QueryStatsOrder::Count => self.count as u128,
+            QueryStatsOrder::Rows => self.rows as u128,
+        }
+    }
+}
+
+/// Collects statistics for the queries against each deployment. At most
+/// `GRAPH_GRAPHQL_QUERY_STATS_MAX_SHAPES` shapes are tracked for each
+/// deployment; once that many shapes have been seen, the shape that has
+/// caused the least work is dropped to make room for a new one
+#[derive(Debug, Default)]
+pub struct QueryStats {
+    deployments: Mutex<HashMap<DeploymentHash, HashMap<u64, ShapeStats>>>,
+}
+
+impl QueryStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that a query with `shape_hash` against `deployment` took
+    /// `elapsed` and returned `rows` entities
+    pub fn record(
+        &self,
+        deployment: &DeploymentHash,
+        shape_hash: u64,
+        query: &Arc<String>,
+        elapsed: Duration,
+        rows: usize,
+    ) {
+        let max_shapes = ENV_VARS.graphql.query_stats_max_shapes;
+        if max_shapes == 0 {
+            return;
+        }
+
+        let mut deployments = self.deployments.lock().unwrap();
+        if !deployments.contains_key(deployment) {
+            deployments.insert(deployment.clone(), HashMap::new());
+        }
+        // Unwrap: we just made sure that there is an entry
+        let shapes = deployments.get_mut(deployment).unwrap();
+        if !shapes.contains_key(&shape_hash) && shapes.len() >= max_shapes {
+            let cheapest = shapes
+                .iter()
+                .min_by_key(|(_, stats)| stats.total_time)
+                .map(|(hash, _)| *hash);
+            if let Some(cheapest) = cheapest {
+                shapes.remove(&cheapest);
+            }
+        }
+        shapes
+            .entry(shape_hash)
+            .or_insert_with(|| ShapeStats::new(query.clone()))
+            .add(elapsed, rows);
+    }
+
+    /// The `first` query shapes for `deployment` that rank highest
+    /// according to `order`
+    pub fn top(
+        &self,
+        deployment: &DeploymentHash,
+        first: usize,
+        order: QueryStatsOrder,
+    ) -> Vec<QueryShapeSummary> {
+        let deployments = self.deployments.lock().unwrap();
+        let mut summaries: Vec<_> = deployments
+            .get(deployment)
+            .map(|shapes| {
+                shapes
+                    .iter()
+                    .map(|(hash, stats)| stats.summary(*hash))
+                    .collect()
+            })
+            .unwrap_or_default();
+        summaries.sort_by(|a, b| b.key(order).cmp(&a.key(order)));
+        summaries.truncate(first);
+        summaries
+    }
+
+    /// Forget all statistics for `deployment`
+    pub fn clear(&self, deployment: &DeploymentHash) {
+        self.deployments.lock().unwrap().remove(deployment);
+    }
+}
+
+/// The number of entities in the data of a query result, i.e., the number
+/// of objects below the root object
+pub fn entity_count(data: &Object) -> usize {
+    fn count(value: &r::Value) -> usize {
+        match value {
+            r::Value::Object(obj) => 1 + entity_count(obj),
+            r::Value::List(values) => values.iter().map(count).sum(),
+            _ => 0,
+        }
+    }
+
+    data.iter().map(|(_, value)| count(value)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{QueryStats, QueryStatsOrder};
+    use crate::prelude::DeploymentHash;
+
+    #[test]
+    fn ranks_shapes() {
+        let stats = QueryStats::new();
+        let id = DeploymentHash::new("QmStats").unwrap();
+        let query = Arc::new("{ things { id } }".to_string());
+
+        for ms in [1, 3, 8, 40] {
+            stats.record(&id, 1, &query, Duration::from_millis(ms), 10);
+        }
+        stats.record(&id, 2, &query, Duration::from_millis(300), 1);
+
+        let top = stats.top(&id, 10, QueryStatsOrder::TotalTime);
+        assert_eq!(
+            vec![2, 1],
+            top.iter().map(|s| s.shape_hash).collect::<Vec<_>>()
+        );
+
+        let top = stats.top(&id, 1, QueryStatsOrder::Count);
+        assert_eq!(1, top.len());
+        let shape = &top[0];
+        assert_eq!(1, shape.shape_hash);
+        assert_eq!(4, shape.count);
+        assert_eq!(40, shape.rows);
+        assert_eq!(Duration::from_millis(13), shape.mean_time);
+        assert_eq!(Duration::from_millis(5), shape.p50_time);
+        assert_eq!(Duration::from_millis(40), shape.p99_time);
+        assert_eq!(Duration::from_millis(40), shape.max_time);
+
+        stats.clear(&id);
+        assert!(stats.top(&id, 10, QueryStatsOrder::Count).is_empty());
+    }
+}
@@ -1,5 +1,5 @@
 use crate::cheap_clone::CheapClone;
-use crate::components::store::{EntityKey, EntityType, SubgraphStore};
+use crate::components::store::{AggregateFunction, EntityKey, EntityType, SubgraphStore};
 use crate::data::graphql::ext::{DirectiveExt, DirectiveFinder, DocumentExt, TypeExt, ValueExt};
 use crate::data::graphql::ObjectTypeExt;
 use crate::data::store::{self, ValueType};
@@ -35,6 +35,13 @@ pub const META_FIELD_NAME: &str = "_meta";
 
 pub const BLOCK_FIELD_TYPE: &str = "_Block_";
 
+/// The field that holds the time of a point in a timeseries, and the start
+/// of the interval a rollup covers for aggregations
+pub const TIMESTAMP_FIELD: &str = "timestamp";
+/// The field of aggregations that holds the name of the interval a rollup
+/// covers
+pub const INTERVAL_FIELD: &str = "interval";
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Strings(Vec<String>);
 
@@ -112,6 +119,12 @@ pub enum SchemaValidationError {
     FulltextIncludedFieldMissingRequiredProperty,
     #[error("Fulltext entity field, {0}, not found or not a string")]
     FulltextIncludedFieldInvalid(String),
+    #[error("Timeseries type `{0}` is invalid: {1}")]
+    InvalidTimeseries(String, String), // (type, reason)
+    #[error("Aggregation type `{0}` is invalid: {1}")]
+    InvalidAggregation(String, String), // (type, reason)
+    #[error("Field `{1}` in type `{0}` has invalid index directives: {2}")]
+    InvalidIndexDirective(String, String, String), // (type, field, reason)
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -260,6 +273,122 @@ impl From<&s::Directive> for FulltextDefinition {
         }
     }
 }
+
+/// The intervals for which an `@aggregation` type can compute rollups
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum AggregationInterval {
+    Hour,
+    Day,
+}
+
+impl AggregationInterval {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Hour => "hour",
+            Self::Day => "day",
+        }
+    }
+
+    /// The length of the interval in seconds
+    pub fn seconds(&self) -> i64 {
+        match self {
+            Self::Hour => 3600,
+            Self::Day => 86400,
+        }
+    }
+
+    /// The start of the interval that contains `timestamp`
+    pub fn start(&self, timestamp: i64) -> i64 {
+        timestamp - timestamp.rem_euclid(self.seconds())
+    }
+}
+
+impl TryFrom<&str> for AggregationInterval {
+    type Error = String;
+    fn try_from(interval: &str) -> Result<Self, Self::Error> {
+        match interval {
+            "hour" => Ok(Self::Hour),
+            "day" => Ok(Self::Day),
+            invalid => Err(format!(
+                "the interval `{}` is invalid. It must be one of: hour, day",
+                invalid
+            )),
+        }
+    }
+}
+
+/// An aggregate that an `@aggregation` type computes for `field` from the
+/// attribute `arg` of its source. `arg` is only `None` for counts
+#[derive(Clone, Debug, PartialEq)]
+pub struct AggregateDefinition {
+    pub field: String,
+    pub function: AggregateFunction,
+    pub arg: Option<String>,
+}
+
+/// A type with an `@aggregation` directive. Its entities are rollups of the
+/// timeseries `source`, one for each interval, start of the interval, and
+/// combination of the values of the `dimensions`
+#[derive(Clone, Debug, PartialEq)]
+pub struct AggregationDefinition {
+    pub entity_type: EntityType,
+    pub source: EntityType,
+    pub intervals: Vec<AggregationInterval>,
+    pub dimensions: Vec<String>,
+    pub aggregates: Vec<AggregateDefinition>,
+}
+
+impl From<&s::ObjectType> for AggregationDefinition {
+    // Assumes the input is an aggregation type that has already been
+    // validated because it makes liberal use of unwrap()
+    fn from(object_type: &s::ObjectType) -> Self {
+        let directive = object_type.find_directive("aggregation").unwrap();
+        let source = directive.argument("source").unwrap().as_str().unwrap();
+        let intervals = directive
+            .argument("intervals")
+            .unwrap()
+            .as_list()
+            .unwrap()
+            .iter()
+            .map(|interval| AggregationInterval::try_from(interval.as_str().unwrap()).unwrap())
+            .collect();
+
+        let mut dimensions = vec![];
+        let mut aggregates = vec![];
+        for field in &object_type.fields {
+            match field.find_directive("aggregate") {
+                Some(aggregate) => aggregates.push(AggregateDefinition {
+                    field: field.name.clone(),
+                    function: aggregate
+                        .argument("fn")
+                        .unwrap()
+                        .as_str()
+                        .unwrap()
+                        .parse()
+                        .unwrap(),
+                    arg: aggregate
+                        .argument("arg")
+                        .and_then(|arg| arg.as_str())
+                        .map(str::to_string),
+                }),
+                None => {
+                    if !["id", INTERVAL_FIELD, TIMESTAMP_FIELD].contains(&field.name.as_str()) {
+                        dimensions.push(field.name.clone());
+                    }
+                }
+            }
+        }
+
+        AggregationDefinition {
+            entity_type: EntityType::from(object_type),
+            source: EntityType::new(source.to_string()),
+            intervals,
+            dimensions,
+            aggregates,
+        }
+    }
+}
+
 #[derive(Debug, Error, PartialEq, Eq, Clone)]
 pub enum SchemaImportError {
     #[error("Schema for imported subgraph `{0}` was not found")]
@@ -361,6 +490,7 @@ pub struct ApiSchema {
     // Root types for the api schema.
     pub query_type: Arc<ObjectType>,
     pub subscription_type: Option<Arc<ObjectType>>,
+    pub mutation_type: Option<Arc<ObjectType>>,
     object_types: HashMap<String, Arc<ObjectType>>,
 }
 
@@ -384,6 +514,11 @@ impl ApiSchema {
             .get_root_subscription_type()
             .cloned()
             .map(Arc::new);
+        let mutation_type = api_schema
+            .document
+            .get_root_mutation_type()
+            .cloned()
+            .map(Arc::new);
 
         let object_types = HashMap::from_iter(
             api_schema
@@ -397,6 +532,7 @@ impl ApiSchema {
             schema: api_schema,
             query_type: Arc::new(query_type),
             subscription_type,
+            mutation_type,
             object_types,
         })
     }
@@ -863,6 +999,9 @@ impl Schema {
         errors.append(&mut self.validate_fields());
         errors.append(&mut self.validate_import_directives());
         errors.append(&mut self.validate_fulltext_directives());
+        errors.append(&mut self.validate_timeseries());
+        errors.append(&mut self.validate_aggregations());
+        errors.append(&mut self.validate_index_directives());
         errors.append(&mut self.validate_imported_types(schemas));
 
         if errors.is_empty() {
@@ -1163,6 +1302,181 @@ impl Schema {
         return vec![];
     }
 
+    fn validate_timeseries(&self) -> Vec<SchemaValidationError> {
+        self.document
+            .get_object_type_definitions()
+            .into_iter()
+            .filter(|object_type| object_type.is_timeseries())
+            .filter_map(|object_type| {
+                let timestamp = object_type.field(TIMESTAMP_FIELD);
+                match timestamp.map(|field| field.field_type.to_string()) {
+                    Some(field_type) if field_type == "Int!" => None,
+                    _ => Some(SchemaValidationError::InvalidTimeseries(
+                        object_type.name.clone(),
+                        format!("it must have a field `{}: Int!`", TIMESTAMP_FIELD),
+                    )),
+                }
+            })
+            .collect()
+    }
+
+    fn validate_index_directives(&self) -> Vec<SchemaValidationError> {
+        let mut errors = Vec::new();
+        for object_type in self.document.get_object_type_definitions() {
+            for field in &object_type.fields {
+                let index = field.find_directive("index").is_some();
+                let no_index = field.find_directive("noIndex").is_some();
+                let reason = if index && no_index {
+                    "it can not have both @index and @noIndex"
+                } else if (index || no_index) && field.is_derived() {
+                    "derived fields are not stored and can not be indexed"
+                } else {
+                    continue;
+                };
+                errors.push(SchemaValidationError::InvalidIndexDirective(
+                    object_type.name.clone(),
+                    field.name.clone(),
+                    reason.to_string(),
+                ));
+            }
+        }
+        errors
+    }
+
+    fn validate_aggregations(&self) -> Vec<SchemaValidationError> {
+        self.document
+            .get_object_type_definitions()
+            .into_iter()
+            .filter(|object_type| object_type.is_aggregation())
+            .filter_map(|object_type| {
+                self.validate_aggregation(object_type).err().map(|reason| {
+                    SchemaValidationError::InvalidAggregation(object_type.name.clone(), reason)
+                })
+            })
+            .collect()
+    }
+
+    fn validate_aggregation(&self, object_type: &ObjectType) -> Result<(), String> {
+        if object_type.find_directive("entity").is_some() {
+            return Err("it can not also have an @entity directive".to_string());
+        }
+        let directive = object_type.find_directive("aggregation").unwrap();
+
+        let intervals = directive
+            .argument("intervals")
+            .and_then(|intervals| intervals.as_list())
+            .filter(|intervals| !intervals.is_empty())
+            .ok_or_else(|| "@aggregation needs a non-empty list of `intervals`".to_string())?;
+        for interval in intervals {
+            let interval = interval
+                .as_str()
+                .ok_or_else(|| format!("the interval `{}` must be a string", interval))?;
+            AggregationInterval::try_from(interval)?;
+        }
+
+        let source = directive
+            .argument("source")
+            .and_then(|source| source.as_str())
+            .ok_or_else(|| "@aggregation needs a `source`".to_string())?;
+        let source = self
+            .document
+            .get_object_type_definition(source)
+            .filter(|source| source.is_timeseries())
+            .ok_or_else(|| {
+                format!(
+                    "the source `{}` must be a type with @entity(timeseries: true)",
+                    source
+                )
+            })?;
+
+        for (name, field_type) in [
+            ("id", "ID!"),
+            (INTERVAL_FIELD, "String!"),
+            (TIMESTAMP_FIELD, "Int!"),
+        ] {
+            match object_type.field(name) {
+                Some(field) if field.field_type.to_string() == field_type => {}
+                _ => return Err(format!("it must have a field `{}: {}`", name, field_type)),
+            }
+        }
+
+        for field in &object_type.fields {
+            if ["id", INTERVAL_FIELD, TIMESTAMP_FIELD].contains(&field.name.as_str()) {
+                continue;
+            }
+            if field.field_type.is_list() || field.is_derived() {
+                return Err(format!(
+                    "the field `{}` can not be a list or derived",
+                    field.name
+                ));
+            }
+            let aggregate = match field.find_directive("aggregate") {
+                Some(aggregate) => aggregate,
+                None => {
+                    // Fields without `@aggregate` are dimensions and are
+                    // copied from the source
+                    match source.field(&field.name) {
+                        Some(source_field) if source_field.field_type == field.field_type => {
+                            continue
+                        }
+                        _ => {
+                            return Err(format!(
+                                "the dimension `{}` must be a field of type {} in `{}`",
+                                field.name, field.field_type, source.name
+                            ))
+                        }
+                    }
+                }
+            };
+
+            let function = aggregate
+                .argument("fn")
+                .and_then(|function| function.as_str())
+                .ok_or_else(|| format!("@aggregate on `{}` needs a `fn`", field.name))
+                .and_then(|function| {
+                    AggregateFunction::from_str(function).map_err(|e| e.to_string())
+                })?;
+            let arg_type = match aggregate.argument("arg") {
+                None if function == AggregateFunction::Count => None,
+                None => {
+                    return Err(format!(
+                        "@aggregate on `{}` needs an `arg` for `{}`",
+                        field.name, function
+                    ))
+                }
+                Some(arg) => {
+                    let arg = arg.as_str().unwrap_or_default();
+                    let arg_type = source
+                        .field(arg)
+                        .filter(|source_field| !source_field.field_type.is_list())
+                        .map(|source_field| source_field.field_type.get_base_type())
+                        .filter(|arg_type| ["Int", "BigInt", "BigDecimal"].contains(arg_type))
+                        .ok_or_else(|| {
+                            format!(
+                                "the `arg` of @aggregate on `{}` must be a numeric field of `{}`",
+                                field.name, source.name
+                            )
+                        })?;
+                    Some(arg_type)
+                }
+            };
+            let expected_type = match (function, arg_type) {
+                (AggregateFunction::Count, _) => "Int",
+                (AggregateFunction::Avg, _) => "BigDecimal",
+                (AggregateFunction::Sum, Some("Int")) => "BigInt",
+                (_, Some(arg_type)) => arg_type,
+                (_, None) => unreachable!("only counts have no arg"),
+            };
+            if field.field_type.get_base_type() != expected_type {
+                return Err(format!(
+                    "the field `{}` must have type {} to hold the {} of its `arg`",
+                    field.name, expected_type, function
+                ));
+            }
+        }
+        Ok(())
+    }
+
     fn validate_import_directives(&self) -> Vec<SchemaValidationError> {
         self.subgraph_schema_object_type()
             .map_or(vec![], |subgraph_schema_type| {
@@ -1328,7 +1642,11 @@ impl Schema {
             .document
             .get_object_type_definitions()
             .iter()
-            .filter(|t| t.find_directive("entity").is_none() && !t.name.eq(SCHEMA_TYPE_NAME))
+            .filter(|t| {
+                t.find_directive("entity").is_none()
+                    && !t.is_aggregation()
+                    && !t.name.eq(SCHEMA_TYPE_NAME)
+            })
             .map(|t| t.name.to_owned())
             .collect::<Vec<_>>();
         if types_without_entity_directive.is_empty() {
@@ -1533,6 +1851,16 @@ impl Schema {
             .find(|object_type| object_type.name.eq(SCHEMA_TYPE_NAME))
     }
 
+    /// The definitions of all `@aggregation` types in the schema
+    pub fn aggregations(&self) -> Vec<AggregationDefinition> {
+        self.document
+            .get_object_type_definitions()
+            .into_iter()
+            .filter(|object_type| object_type.is_aggregation())
+            .map(AggregationDefinition::from)
+            .collect()
+    }
+
     pub fn entity_fulltext_definitions(
         entity: &str,
         document: &Document,
@@ -1935,3 +2263,145 @@ type Gravatar @entity {
 
     assert_eq!(schema.validate_fulltext_directives(), vec![]);
 }
+
+#[test]
+fn test_aggregation_validation() {
+    fn validate(stats: &str) -> Vec<SchemaValidationError> {
+        let schema = format!(
+            r#"
+type Trade @entity(timeseries: true) {{
+  id: ID!
+  timestamp: Int!
+  token: Bytes!
+  amount: BigDecimal!
+  size: Int!
+}}
+{}"#,
+            stats
+        );
+        let schema = Schema::parse(&schema, DeploymentHash::new("id1").unwrap())
+            .expect("Failed to parse schema");
+        schema.validate_aggregations()
+    }
+
+    const VALID: &str = r#"
+type TradeStats @aggregation(intervals: ["hour", "day"], source: "Trade") {
+  id: ID!
+  interval: String!
+  timestamp: Int!
+  token: Bytes!
+  count: Int! @aggregate(fn: "count")
+  volume: BigDecimal! @aggregate(fn: "sum", arg: "amount")
+  totalSize: BigInt! @aggregate(fn: "sum", arg: "size")
+  maxSize: Int! @aggregate(fn: "max", arg: "size")
+  avgSize: BigDecimal! @aggregate(fn: "avg", arg: "size")
+}"#;
+    assert_eq!(validate(VALID), vec![]);
+
+    let schema = Schema::parse(VALID, DeploymentHash::new("id1").unwrap()).unwrap();
+    let stats = schema.aggregations().pop().unwrap();
+    assert_eq!(
+        vec![AggregationInterval::Hour, AggregationInterval::Day],
+        stats.intervals
+    );
+    assert_eq!(vec!["token".to_string()], stats.dimensions);
+    assert_eq!(5, stats.aggregates.len());
+
+    fn assert_invalid(stats: &str, reason: &str) {
+        assert_eq!(
+            validate(stats),
+            vec![SchemaValidationError::InvalidAggregation(
+                "TradeStats".to_string(),
+                reason.to_string()
+            )]
+        );
+    }
+
+    assert_invalid(
+        r#"
+type TradeStats @aggregation(intervals: ["week"], source: "Trade") {
+  id: ID!
+  interval: String!
+  timestamp: Int!
+}"#,
+        "the interval `week` is invalid. It must be one of: hour, day",
+    );
+    assert_invalid(
+        r#"
+type TradeStats @aggregation(intervals: ["hour"], source: "Trade") {
+  id: ID!
+  timestamp: Int!
+}"#,
+        "it must have a field `interval: String!`",
+    );
+    assert_invalid(
+        r#"
+type TradeStats @aggregation(intervals: ["hour"], source: "Trade") {
+  id: ID!
+  interval: String!
+  timestamp: Int!
+  pool: Bytes!
+}"#,
+        "the dimension `pool` must be a field of type Bytes! in `Trade`",
+    );
+    assert_invalid(
+        r#"
+type TradeStats @aggregation(intervals: ["hour"], source: "Trade") {
+  id: ID!
+  interval: String!
+  timestamp: Int!
+  totalSize: Int! @aggregate(fn: "sum", arg: "size")
+}"#,
+        "the field `totalSize` must have type BigInt to hold the sum of its `arg`",
+    );
+}
+
+#[test]
+fn test_index_directive_validation() {
+    fn validate(fields: &str) -> Vec<SchemaValidationError> {
+        let schema = format!(
+            "type A @entity {{ id: ID!\n {} }}\ntype B @entity {{ id: ID!, a: A! }}",
+            fields
+        );
+        let schema = Schema::parse(&schema, DeploymentHash::new("id1").unwrap())
+            .expect("Failed to parse schema");
+        schema.validate_index_directives()
+    }
+
+    fn invalid(field: &str, reason: &str) -> Vec<SchemaValidationError> {
+        vec![SchemaValidationError::InvalidIndexDirective(
+            "A".to_string(),
+            field.to_string(),
+            reason.to_string(),
+        )]
+    }
+
+    assert_eq!(validate("name: String! @index, note: String"), vec![]);
+    assert_eq!(validate("name: String! @noIndex"), vec![]);
+    assert_eq!(
+        validate("name: String! @index @noIndex"),
+        invalid("name", "it can not have both @index and @noIndex")
+    );
+    assert_eq!(
+        validate("bs: [B!]! @derivedFrom(field: \"a\") @index"),
+        invalid("bs", "derived fields are not stored and can not be indexed")
+    );
+
+    let schema = Schema::parse(
+        "type A @entity { id: ID!, name: String! @index, note: String }
+         type C @entity { id: ID!, name: String!, note: String @noIndex }",
+        DeploymentHash::new("id1").unwrap(),
+    )
+    .unwrap();
+    let indexed = |typ: &str| -> Vec<String> {
+        let object_type = schema.document.get_object_type_definition(typ).unwrap();
+        object_type
+            .fields
+            .iter()
+            .filter(|field| object_type.is_indexed(field))
+            .map(|field| field.name.clone())
+            .collect()
+    };
+    assert_eq!(vec!["name".to_string()], indexed("A"));
+    assert_eq!(vec!["id".to_string(), "name".to_string()], indexed("C"));
+}
@@ -21,7 +21,7 @@ use std::{
 use strum::AsStaticRef as _;
 use strum_macros::AsStaticStr;
 
-use super::graphql::{ext::DirectiveFinder, DocumentExt as _, TypeExt as _};
+use super::graphql::{ext::DirectiveFinder, DocumentExt as _, ObjectTypeExt as _, TypeExt as _};
 
 /// Custom scalars in GraphQL.
 pub mod scalar;
@@ -110,6 +110,48 @@ impl<'de> de::Deserialize<'de> for NodeId {
     }
 }
 
+/// What a node does. Query nodes never index subgraphs and index nodes
+/// never serve GraphQL queries for subgraph data; combined nodes do both
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeRole {
+    Combined,
+    Query,
+    Index,
+}
+
+impl Default for NodeRole {
+    fn default() -> Self {
+        NodeRole::Combined
+    }
+}
+
+impl NodeRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NodeRole::Combined => "combined",
+            NodeRole::Query => "query",
+            NodeRole::Index => "index",
+        }
+    }
+
+    /// Whether nodes with this role index subgraphs and ingest blocks
+    pub fn indexes(&self) -> bool {
+        !matches!(self, NodeRole::Query)
+    }
+
+    /// Whether nodes with this role serve GraphQL queries for subgraph data
+    pub fn serves_queries(&self) -> bool {
+        !matches!(self, NodeRole::Index)
+    }
+}
+
+impl fmt::Display for NodeRole {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
 #[serde(tag = "type")]
 pub enum AssignmentEvent {
@@ -671,6 +713,15 @@ impl Entity {
                 )
             })?;
 
+        if object_type.is_aggregation() {
+            anyhow::bail!(
+                "Entity {}[{}]: entities of type `{}` are aggregations and can not be set",
+                key.entity_type,
+                key.entity_id,
+                key.entity_type
+            );
+        }
+
         for field in &object_type.fields {
             let is_derived = field.is_derived();
             match (self.get(&field.name), is_derived) {
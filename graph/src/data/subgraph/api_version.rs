@@ -28,6 +28,9 @@ pub const SPEC_VERSION_0_0_4: Version = Version::new(0, 0, 4);
 /// This version supports event handlers having access to transaction receipts.
 pub const SPEC_VERSION_0_0_5: Version = Version::new(0, 0, 5);
 
+/// This version supports indexer hints, like pruning of historical data.
+pub const SPEC_VERSION_0_0_6: Version = Version::new(0, 0, 6);
+
 pub const MIN_SPEC_VERSION: Version = Version::new(0, 0, 2);
 
 #[derive(Clone, PartialEq, Debug)]
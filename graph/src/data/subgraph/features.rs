@@ -11,7 +11,7 @@
 //! Feature validation is performed by the [`validate_subgraph_features`] function.
 
 use crate::{
-    blockchain::Blockchain,
+    blockchain::{Blockchain, DataSource},
     data::{graphql::DocumentExt, schema::Schema, subgraph::SubgraphManifest},
     prelude::{Deserialize, Serialize},
 };
@@ -33,6 +33,8 @@ pub enum SubgraphFeature {
     Grafting,
     FullTextSearch,
     IpfsOnEthereumContracts,
+    QuarantineDataSources,
+    SubgraphComposition,
 }
 
 impl fmt::Display for SubgraphFeature {
@@ -85,7 +87,9 @@ pub fn detect_features<C: Blockchain>(
 ) -> Result<BTreeSet<SubgraphFeature>, InvalidMapping> {
     let features = vec![
         detect_non_fatal_errors(manifest),
+        detect_quarantine_data_sources(manifest),
         detect_grafting(manifest),
+        detect_subgraph_composition(manifest),
         detect_full_text_search(&manifest.schema),
         detect_ipfs_on_ethereum_contracts(manifest)?,
     ]
@@ -105,10 +109,33 @@ fn detect_non_fatal_errors<C: Blockchain>(
     }
 }
 
+fn detect_quarantine_data_sources<C: Blockchain>(
+    manifest: &SubgraphManifest<C>,
+) -> Option<SubgraphFeature> {
+    if manifest
+        .features
+        .contains(&SubgraphFeature::QuarantineDataSources)
+    {
+        Some(SubgraphFeature::QuarantineDataSources)
+    } else {
+        None
+    }
+}
+
 fn detect_grafting<C: Blockchain>(manifest: &SubgraphManifest<C>) -> Option<SubgraphFeature> {
     manifest.graft.as_ref().map(|_| SubgraphFeature::Grafting)
 }
 
+fn detect_subgraph_composition<C: Blockchain>(
+    manifest: &SubgraphManifest<C>,
+) -> Option<SubgraphFeature> {
+    manifest
+        .data_sources
+        .iter()
+        .any(|ds| ds.source_subgraph().is_some())
+        .then(|| SubgraphFeature::SubgraphComposition)
+}
+
 fn detect_full_text_search(schema: &Schema) -> Option<SubgraphFeature> {
     match schema.document.get_fulltext_directives() {
         Ok(directives) => (!directives.is_empty()).then(|| SubgraphFeature::FullTextSearch),
@@ -146,17 +173,21 @@ fn detect_ipfs_on_ethereum_contracts<C: Blockchain>(
 mod tests {
     use super::*;
     use SubgraphFeature::*;
-    const VARIANTS: [SubgraphFeature; 4] = [
+    const VARIANTS: [SubgraphFeature; 6] = [
         NonFatalErrors,
         Grafting,
         FullTextSearch,
         IpfsOnEthereumContracts,
+        QuarantineDataSources,
+        SubgraphComposition,
     ];
-    const STRING: [&str; 4] = [
+    const STRING: [&str; 6] = [
         "nonFatalErrors",
         "grafting",
         "fullTextSearch",
         "ipfsOnEthereumContracts",
+        "quarantineDataSources",
+        "subgraphComposition",
     ];
 
     #[test]
@@ -6,6 +6,7 @@ pub mod api_version;
 pub use api_version::*;
 
 pub mod features;
+pub mod retry;
 pub mod status;
 
 pub use features::{SubgraphFeature, SubgraphFeatureValidationError};
@@ -27,7 +28,7 @@ use web3::types::Address;
 use crate::data::store::Entity;
 use crate::data::{
     schema::{Schema, SchemaImportError, SchemaValidationError},
-    subgraph::features::validate_subgraph_features,
+    subgraph::{features::validate_subgraph_features, schema::DeploymentPriority},
 };
 use crate::prelude::{r, CheapClone, ENV_VARS};
 use crate::{blockchain::DataSource, data::graphql::TryFromValue};
@@ -40,8 +41,9 @@ use crate::{
     },
 };
 
-use crate::prelude::{impl_slog_value, BlockNumber, Deserialize, Serialize};
+use crate::prelude::{impl_slog_value, BlockNumber, Deserialize, Serialize, BLOCK_NUMBER_MAX};
 
+use std::convert::TryFrom;
 use std::fmt;
 use std::ops::Deref;
 use std::str::FromStr;
@@ -255,6 +257,8 @@ pub enum SubgraphRegistrarError {
     DeploymentNotFound(String),
     #[error("deployment assignment unchanged: {0}")]
     DeploymentAssignmentUnchanged(String),
+    #[error("deployment must be paused or unassigned: {0}")]
+    DeploymentNotPaused(String),
     #[error("subgraph registrar internal query error: {0}")]
     QueryExecutionError(QueryExecutionError),
     #[error("subgraph registrar error with store: {0}")]
@@ -263,6 +267,8 @@ pub enum SubgraphRegistrarError {
     ManifestValidationError(Vec<SubgraphManifestValidationError>),
     #[error("subgraph deployment error: {0}")]
     SubgraphDeploymentError(StoreError),
+    #[error("subgraph can not be upgraded in place: {}", display_vector(.0))]
+    UpgradeInPlaceImpossible(Vec<String>),
     #[error("subgraph registrar error: {0}")]
     Unknown(anyhow::Error),
 }
@@ -343,12 +349,16 @@ pub enum SubgraphManifestValidationError {
     SchemaValidationError(Vec<SchemaValidationError>),
     #[error("the graft base is invalid: {0}")]
     GraftBaseInvalid(String),
+    #[error("the schema can not be grafted onto `{0}`: {}", display_vector(.1))]
+    GraftIncompatible(DeploymentHash, Vec<String>),
     #[error("subgraph must use a single apiVersion across its data sources. Found: {}", format_versions(.0))]
     DifferentApiVersions(BTreeSet<Version>),
     #[error(transparent)]
     FeatureValidationError(#[from] SubgraphFeatureValidationError),
     #[error("data source {0} is invalid: {1}")]
     DataSourceValidation(String, Error),
+    #[error("data source {0} can not use subgraph `{1}` as its source: {2}")]
+    SourceSubgraphInvalid(String, DeploymentHash, String),
 }
 
 #[derive(Error, Debug)]
@@ -372,13 +382,33 @@ impl From<serde_yaml::Error> for SubgraphManifestResolveError {
 /// Data source contexts are conveniently represented as entities.
 pub type DataSourceContext = Entity;
 
-/// IPLD link.
+/// IPLD link. Besides the IPLD form `{ "/": link }`, links can also be
+/// given as plain strings, which is what manifests that have not been
+/// uploaded to IPFS use to refer to files relative to the manifest
 #[derive(Clone, Debug, Default, Hash, Eq, PartialEq, Deserialize)]
+#[serde(from = "LinkValue")]
 pub struct Link {
-    #[serde(rename = "/")]
     pub link: String,
 }
 
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LinkValue {
+    Ipld {
+        #[serde(rename = "/")]
+        link: String,
+    },
+    Plain(String),
+}
+
+impl From<LinkValue> for Link {
+    fn from(value: LinkValue) -> Self {
+        match value {
+            LinkValue::Ipld { link } | LinkValue::Plain(link) => Self { link },
+        }
+    }
+}
+
 impl From<String> for Link {
     fn from(s: String) -> Self {
         Self { link: s }
@@ -411,6 +441,11 @@ pub struct Source {
     /// events with the given `abi`
     #[serde(default, deserialize_with = "deserialize_address")]
     pub address: Option<Address>,
+    /// The deployment whose entity changes a data source of kind
+    /// `subgraph` handles; such data sources have no contract and no `abi`
+    #[serde(default)]
+    pub subgraph: Option<DeploymentHash>,
+    #[serde(default)]
     pub abi: String,
     #[serde(rename = "startBlock", default)]
     pub start_block: BlockNumber,
@@ -441,9 +476,15 @@ pub struct Graft {
 }
 
 impl Graft {
+    /// Check that grafting a deployment with `schema` onto the base will
+    /// work: the base must exist on this installation, must have data for
+    /// the graft block, and its schema must be compatible with `schema`.
+    /// Checking this when the subgraph is deployed avoids finding out that
+    /// the graft is impossible only once the data is copied
     async fn validate<S: SubgraphStore>(
         &self,
         store: Arc<S>,
+        schema: &Schema,
     ) -> Vec<SubgraphManifestValidationError> {
         fn gbi(msg: String) -> Vec<SubgraphManifestValidationError> {
             vec![SubgraphManifestValidationError::GraftBaseInvalid(msg)]
@@ -456,21 +497,148 @@ impl Graft {
         // subgraph is started. We therefore check that any instance of the
         // base subgraph is suitable.
         match store.least_block_ptr(&self.base).await {
+            Err(StoreError::DeploymentNotFound(_)) => {
+                return gbi(format!(
+                    "failed to graft onto `{}` since it does not exist on this installation",
+                    self.base
+                ))
+            }
+            Err(e) => return gbi(e.to_string()),
+            Ok(None) => {
+                return gbi(format!(
+                    "failed to graft onto `{}` since it has not processed any blocks",
+                    self.base
+                ))
+            }
+            Ok(Some(ptr)) if ptr.number < self.block => {
+                return gbi(format!(
+                    "failed to graft onto `{}` at block {} since it has only processed block {}",
+                    self.base, self.block, ptr.number
+                ))
+            }
+            Ok(Some(_)) => { /* the base has processed the graft block */ }
+        }
+
+        let mut errors = match store.earliest_block(&self.base) {
             Err(e) => gbi(e.to_string()),
-            Ok(None) => gbi(format!(
-                "failed to graft onto `{}` since it has not processed any blocks",
-                self.base
+            Ok(earliest) if earliest > self.block => gbi(format!(
+                "failed to graft onto `{}` at block {} since it has pruned its history before block {}",
+                self.base, self.block, earliest
+            )),
+            Ok(_) => vec![],
+        };
+
+        match store.graft_incompatibilities(&self.base, schema) {
+            Err(e) => errors.extend(gbi(e.to_string())),
+            Ok(diff) if !diff.is_empty() => errors.push(
+                SubgraphManifestValidationError::GraftIncompatible(self.base.clone(), diff),
+            ),
+            Ok(_) => { /* the schemas are compatible */ }
+        }
+        errors
+    }
+}
+
+/// Check that the data source `ds` of the deployment `id` can use the
+/// deployment `source` as its source: `source` must exist on this
+/// installation, index the same network as `ds`, and still have the
+/// history for all blocks starting at the data source's start block
+fn validate_source_subgraph<C: Blockchain, S: SubgraphStore>(
+    store: &S,
+    id: &DeploymentHash,
+    ds: &C::DataSource,
+    source: &DeploymentHash,
+) -> Option<SubgraphManifestValidationError> {
+    let invalid = |msg: String| {
+        Some(SubgraphManifestValidationError::SourceSubgraphInvalid(
+            ds.name().to_owned(),
+            source.clone(),
+            msg,
+        ))
+    };
+
+    if source == id {
+        return invalid("a subgraph can not be its own source".to_owned());
+    }
+    match store.is_deployed(source) {
+        Err(e) => return invalid(e.to_string()),
+        Ok(false) => return invalid("it does not exist on this installation".to_owned()),
+        Ok(true) => { /* the source exists */ }
+    }
+    match store.deployment_network(source) {
+        Err(e) => return invalid(e.to_string()),
+        Ok(network) if Some(network.as_str()) != ds.network() => {
+            return invalid(format!("it indexes the different network `{}`", network))
+        }
+        Ok(_) => { /* the source indexes the same network */ }
+    }
+    match store.earliest_block(source) {
+        Err(e) => invalid(e.to_string()),
+        Ok(earliest) if earliest > ds.start_block() => invalid(format!(
+            "it has pruned its history before block {}, which is after the start block {}",
+            earliest,
+            ds.start_block()
+        )),
+        Ok(_) => None,
+    }
+}
+
+/// Hints from the subgraph author for how indexers should index the
+/// subgraph
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexerHints {
+    pub prune: Option<Prune>,
+}
+
+impl IndexerHints {
+    /// How many blocks of history of entity versions the deployment needs
+    /// to keep. Deployments never keep less history than
+    /// `GRAPH_MIN_HISTORY_BLOCKS` so that reverts are always possible
+    pub fn history_blocks(&self) -> BlockNumber {
+        let min = ENV_VARS.store.min_history_blocks;
+        match self.prune {
+            None | Some(Prune::Never) => BLOCK_NUMBER_MAX,
+            Some(Prune::Auto) => min,
+            Some(Prune::Blocks(blocks)) => blocks.max(min),
+        }
+    }
+}
+
+/// How much history a deployment keeps: with `auto`, only as much as is
+/// needed to handle reorgs, and with a number, that many blocks, so that
+/// queries can go back that far in time
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "PruneValue")]
+pub enum Prune {
+    Auto,
+    Never,
+    Blocks(BlockNumber),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PruneValue {
+    Keyword(String),
+    Blocks(BlockNumber),
+}
+
+impl TryFrom<PruneValue> for Prune {
+    type Error = String;
+
+    fn try_from(value: PruneValue) -> Result<Self, Self::Error> {
+        match value {
+            PruneValue::Keyword(kw) if kw == "auto" => Ok(Prune::Auto),
+            PruneValue::Keyword(kw) if kw == "never" => Ok(Prune::Never),
+            PruneValue::Blocks(blocks) if blocks > 0 => Ok(Prune::Blocks(blocks)),
+            PruneValue::Keyword(kw) => Err(format!(
+                "invalid value `{}` for `prune`, it must be `auto`, `never` or a number of blocks",
+                kw
+            )),
+            PruneValue::Blocks(blocks) => Err(format!(
+                "invalid value `{}` for `prune`, the number of blocks must be positive",
+                blocks
             )),
-            Ok(Some(ptr)) => {
-                if ptr.number < self.block {
-                    gbi(format!(
-                        "failed to graft onto `{}` at block {} since it has only processed block {}",
-                        self.base, self.block, ptr.number
-                    ))
-                } else {
-                    vec![]
-                }
-            }
         }
     }
 }
@@ -489,6 +657,10 @@ pub struct BaseSubgraphManifest<C, S, D, T> {
     pub graft: Option<Graft>,
     #[serde(default)]
     pub templates: Vec<T>,
+    /// A hint how urgently the deployment should be synced; an operator
+    /// can override it with `graphman priority`
+    pub priority: Option<DeploymentPriority>,
+    pub indexer_hints: Option<IndexerHints>,
     #[serde(skip_serializing, default)]
     pub chain: PhantomData<C>,
 }
@@ -513,6 +685,11 @@ pub type SubgraphManifest<C> = BaseSubgraphManifest<
 pub struct UnvalidatedSubgraphManifest<C: Blockchain>(SubgraphManifest<C>);
 
 impl<C: Blockchain> UnvalidatedSubgraphManifest<C> {
+    /// The manifest for checks that do not need it to be valid
+    pub fn as_manifest(&self) -> &SubgraphManifest<C> {
+        &self.0
+    }
+
     /// Entry point for resolving a subgraph definition.
     /// Right now the only supported links are of the form:
     /// `/ipfs/QmUmg7BZC1YP1ca66rRtWKxpXp77WgVHrnv263JtDuvs2k`
@@ -581,6 +758,17 @@ impl<C: Blockchain> UnvalidatedSubgraphManifest<C> {
                 ));
             });
 
+        for ds in &self.0.data_sources {
+            if let Some(source) = ds.source_subgraph() {
+                errors.extend(validate_source_subgraph::<C, _>(
+                    store.as_ref(),
+                    &self.0.id,
+                    ds,
+                    source,
+                ));
+            }
+        }
+
         if let Some(graft) = &self.0.graft {
             if ENV_VARS.disable_grafts {
                 errors.push(SubgraphManifestValidationError::GraftBaseInvalid(
@@ -588,7 +776,7 @@ impl<C: Blockchain> UnvalidatedSubgraphManifest<C> {
                 ));
             }
             if validate_graft_base {
-                errors.extend(graft.validate(store).await);
+                errors.extend(graft.validate(store, &self.0.schema).await);
             }
         }
 
@@ -666,6 +854,14 @@ impl<C: Blockchain> SubgraphManifest<C> {
             .chain(self.data_sources.iter().map(|source| source.runtime()))
     }
 
+    /// How many blocks of history the deployment needs to keep
+    pub fn history_blocks(&self) -> BlockNumber {
+        self.indexer_hints
+            .as_ref()
+            .map(IndexerHints::history_blocks)
+            .unwrap_or(BLOCK_NUMBER_MAX)
+    }
+
     pub fn unified_mapping_api_version(
         &self,
     ) -> Result<UnifiedMappingApiVersion, DifferentMappingApiVersions> {
@@ -690,6 +886,8 @@ impl<C: Blockchain> UnresolvedSubgraphManifest<C> {
             data_sources,
             graft,
             templates,
+            priority,
+            indexer_hints,
             chain,
         } = self;
 
@@ -703,6 +901,15 @@ impl<C: Blockchain> UnresolvedSubgraphManifest<C> {
             ));
         }
 
+        if indexer_hints.is_some() && spec_version < SPEC_VERSION_0_0_6 {
+            return Err(anyhow!(
+                "`indexerHints` are only supported with spec version {} or later, but subgraph `{}` uses `{}`",
+                SPEC_VERSION_0_0_6,
+                id,
+                spec_version
+            ));
+        }
+
         let (schema, data_sources, templates) = try_join3(
             schema.resolve(id.clone(), &resolver, logger),
             data_sources
@@ -739,6 +946,8 @@ impl<C: Blockchain> UnresolvedSubgraphManifest<C> {
             data_sources,
             graft,
             templates,
+            priority,
+            indexer_hints,
             chain,
         })
     }
@@ -762,6 +971,9 @@ pub struct DeploymentState {
     pub max_reorg_depth: u32,
     /// The number of the last block that the subgraph has processed
     pub latest_ethereum_block_number: BlockNumber,
+    /// The earliest block that queries can use. History before that block
+    /// has been pruned
+    pub earliest_block_number: BlockNumber,
 }
 
 impl DeploymentState {
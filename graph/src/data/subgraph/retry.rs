@@ -0,0 +1,189 @@
+//! How deployments that failed with a non-deterministic error are retried
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::env::ENV_VARS;
+use crate::util::backoff::ExponentialBackoff;
+
+/// The kinds of non-deterministic errors that a `RetryPolicy` can treat
+/// differently
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// A request, usually to a chain provider, timed out
+    Timeout,
+    /// IPFS was unavailable or did not return a file
+    Ipfs,
+    /// The database was unavailable or a query failed
+    Store,
+    /// Any other non-deterministic error
+    Other,
+}
+
+impl ErrorClass {
+    /// Classify a non-deterministic error by its message. By the time
+    /// errors reach the subgraph runner they are untyped, and the message
+    /// is all that is left to go by
+    pub fn of(message: &str) -> Self {
+        let message = message.to_lowercase();
+        let contains_any = |needles: &[&str]| needles.iter().any(|n| message.contains(n));
+
+        if message.contains("ipfs") {
+            ErrorClass::Ipfs
+        } else if contains_any(&["timed out", "timeout", "deadline has elapsed"]) {
+            ErrorClass::Timeout
+        } else if contains_any(&["store error", "database unavailable", "connection pool"]) {
+            ErrorClass::Store
+        } else {
+            ErrorClass::Other
+        }
+    }
+}
+
+impl FromStr for ErrorClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "timeout" => Ok(ErrorClass::Timeout),
+            "ipfs" => Ok(ErrorClass::Ipfs),
+            "store" => Ok(ErrorClass::Store),
+            "other" => Ok(ErrorClass::Other),
+            _ => Err(format!(
+                "unknown error class `{}`, expected one of timeout, ipfs, store, or other",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorClass::Timeout => write!(f, "timeout"),
+            ErrorClass::Ipfs => write!(f, "ipfs"),
+            ErrorClass::Store => write!(f, "store"),
+            ErrorClass::Other => write!(f, "other"),
+        }
+    }
+}
+
+/// The classes of errors that are retried. Parsed from `all`, `none`, or a
+/// comma-separated list of error classes like `timeout,ipfs`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RetryErrors {
+    All,
+    Only(Vec<ErrorClass>),
+}
+
+impl RetryErrors {
+    pub fn contains(&self, class: ErrorClass) -> bool {
+        match self {
+            RetryErrors::All => true,
+            RetryErrors::Only(classes) => classes.contains(&class),
+        }
+    }
+}
+
+impl FromStr for RetryErrors {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "all" => Ok(RetryErrors::All),
+            "none" | "" => Ok(RetryErrors::Only(vec![])),
+            s => s
+                .split(',')
+                .map(|class| class.trim().parse())
+                .collect::<Result<_, _>>()
+                .map(RetryErrors::Only),
+        }
+    }
+}
+
+/// How a deployment that failed with a non-deterministic error is retried.
+/// Retries are spaced out with an exponential backoff from `base` up to
+/// `ceiling`; once the deployment processes a block successfully, the
+/// attempts start counting from zero again
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// How many times to retry before giving up; `None` means to retry
+    /// forever
+    pub max_attempts: Option<u64>,
+    pub base: Duration,
+    pub ceiling: Duration,
+    /// Errors that are not in one of these classes are not retried
+    pub errors: RetryErrors,
+}
+
+impl RetryPolicy {
+    /// The policy set through the `GRAPH_SUBGRAPH_ERROR_RETRY_*`
+    /// environment variables
+    pub fn from_env() -> Self {
+        RetryPolicy {
+            max_attempts: ENV_VARS.subgraph_error_retry_max_attempts,
+            base: ENV_VARS.subgraph_error_retry_base,
+            ceiling: ENV_VARS.subgraph_error_retry_ceil,
+            errors: ENV_VARS.subgraph_error_retry_errors.clone(),
+        }
+    }
+
+    pub fn backoff(&self) -> ExponentialBackoff {
+        ExponentialBackoff::new(self.base, self.ceiling)
+    }
+
+    /// Whether to retry after an error of `class` when `attempts` retries
+    /// have already been made
+    pub fn should_retry(&self, class: ErrorClass, attempts: u64) -> bool {
+        self.errors.contains(class) && self.max_attempts.map_or(true, |max| attempts < max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify() {
+        assert_eq!(
+            ErrorClass::Ipfs,
+            ErrorClass::of("Failed to cat IPFS file: request timed out")
+        );
+        assert_eq!(
+            ErrorClass::Timeout,
+            ErrorClass::of("Ethereum node took too long to respond: operation timed out")
+        );
+        assert_eq!(
+            ErrorClass::Store,
+            ErrorClass::of("store error: database unavailable")
+        );
+        assert_eq!(ErrorClass::Other, ErrorClass::of("something went wrong"));
+    }
+
+    #[test]
+    fn parse_retry_errors() {
+        assert_eq!(Ok(RetryErrors::All), "all".parse());
+        assert_eq!(Ok(RetryErrors::Only(vec![])), "none".parse());
+        assert_eq!(
+            Ok(RetryErrors::Only(vec![
+                ErrorClass::Timeout,
+                ErrorClass::Ipfs
+            ])),
+            "timeout, ipfs".parse()
+        );
+        assert!("timeout,disk".parse::<RetryErrors>().is_err());
+    }
+
+    #[test]
+    fn should_retry() {
+        let policy = RetryPolicy {
+            max_attempts: Some(3),
+            base: Duration::from_secs(1),
+            ceiling: Duration::from_secs(10),
+            errors: RetryErrors::Only(vec![ErrorClass::Timeout]),
+        };
+        assert!(policy.should_retry(ErrorClass::Timeout, 2));
+        assert!(!policy.should_retry(ErrorClass::Timeout, 3));
+        assert!(!policy.should_retry(ErrorClass::Store, 0));
+    }
+}
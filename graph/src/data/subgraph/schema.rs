@@ -99,6 +99,52 @@ impl TryFromValue for SubgraphHealth {
     }
 }
 
+/// How urgently a deployment should be synced. While syncing, deployments
+/// with a higher priority get preference when block scans and database
+/// writes compete for resources
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeploymentPriority {
+    Low,
+    Normal,
+    High,
+}
+
+impl Default for DeploymentPriority {
+    fn default() -> Self {
+        DeploymentPriority::Normal
+    }
+}
+
+impl DeploymentPriority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeploymentPriority::Low => "low",
+            DeploymentPriority::Normal => "normal",
+            DeploymentPriority::High => "high",
+        }
+    }
+}
+
+impl FromStr for DeploymentPriority {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<DeploymentPriority, Error> {
+        match s {
+            "low" => Ok(DeploymentPriority::Low),
+            "normal" => Ok(DeploymentPriority::Normal),
+            "high" => Ok(DeploymentPriority::High),
+            _ => Err(anyhow!("failed to parse `{}` as DeploymentPriority", s)),
+        }
+    }
+}
+
+impl fmt::Display for DeploymentPriority {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
 /// The deployment data that is needed to create a deployment
 pub struct DeploymentCreate {
     pub manifest: SubgraphManifestEntity,
@@ -163,6 +209,8 @@ pub struct SubgraphManifestEntity {
     pub repository: Option<String>,
     pub features: Vec<String>,
     pub schema: String,
+    /// How many blocks of history of entity versions the deployment keeps
+    pub history_blocks: BlockNumber,
 }
 
 impl<'a, C: Blockchain> From<&'a super::SubgraphManifest<C>> for SubgraphManifestEntity {
@@ -173,6 +221,7 @@ impl<'a, C: Blockchain> From<&'a super::SubgraphManifest<C>> for SubgraphManifes
             repository: manifest.repository.clone(),
             features: manifest.features.iter().map(|f| f.to_string()).collect(),
             schema: manifest.schema.document.clone().to_string(),
+            history_blocks: manifest.history_blocks(),
         }
     }
 }
@@ -218,6 +267,60 @@ impl StableHash for SubgraphError {
     }
 }
 
+/// A message that a mapping logged with `log.info` etc. while processing
+/// `block_number`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MappingLog {
+    pub block_number: BlockNumber,
+    pub level: slog::Level,
+    pub data_source: String,
+    pub message: String,
+}
+
+/// A block that an operator told graph-node to skip when indexing a
+/// deployment, either completely or only for one handler. Skipping is a
+/// last resort for blocks that make a subgraph fail in ways that can only
+/// be fixed by deploying new code
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkippedBlock {
+    pub block_number: BlockNumber,
+    /// The handler that should not be run for the block; if this is
+    /// `None`, no triggers in the block are processed at all
+    pub handler: Option<String>,
+    /// Why the operator decided to skip the block
+    pub reason: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A data source whose handlers are not run anymore because one of them
+/// failed with a deterministic error at `block_number`. See
+/// `GRAPH_QUARANTINE_DATA_SOURCES`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuarantinedDataSource {
+    pub name: String,
+    pub address: Option<Vec<u8>>,
+    pub block_number: BlockNumber,
+    /// The error that caused the data source to be quarantined
+    pub message: String,
+}
+
+impl QuarantinedDataSource {
+    /// Whether this is the data source with `name` and `address`
+    pub fn is(&self, name: &str, address: Option<&[u8]>) -> bool {
+        self.name == name && self.address.as_deref() == address
+    }
+}
+
+impl SkippedBlock {
+    /// Whether `handler` should not be run for this block
+    pub fn skips(&self, handler: &str) -> bool {
+        match &self.handler {
+            None => true,
+            Some(skipped) => skipped == handler,
+        }
+    }
+}
+
 pub fn generate_entity_id() -> String {
     // Fast crypto RNG from operating system
     let mut rng = OsRng::default();
@@ -1,6 +1,6 @@
 //! Support for the indexing status API
 
-use super::schema::{SubgraphError, SubgraphHealth};
+use super::schema::{QuarantinedDataSource, SkippedBlock, SubgraphError, SubgraphHealth};
 use crate::components::store::DeploymentId;
 use crate::data::graphql::{object, IntoValue};
 use crate::prelude::{r, web3::types::H256, BlockPtr, Value};
@@ -99,6 +99,13 @@ pub struct Info {
     pub fatal_error: Option<SubgraphError>,
     pub non_fatal_errors: Vec<SubgraphError>,
 
+    /// Blocks that operators told graph-node to skip for this deployment
+    pub skipped_blocks: Vec<SkippedBlock>,
+
+    /// Data sources whose handlers are not run anymore because they failed
+    /// with a deterministic error
+    pub quarantined_data_sources: Vec<QuarantinedDataSource>,
+
     /// Indexing status on different chains involved in the subgraph's data sources.
     pub chains: Vec<ChainInfo>,
 
@@ -119,6 +126,8 @@ impl IntoValue for Info {
             health,
             node,
             non_fatal_errors,
+            skipped_blocks,
+            quarantined_data_sources,
             synced,
         } = self;
 
@@ -150,6 +159,30 @@ impl IntoValue for Info {
             .map(subgraph_error_to_value)
             .collect();
         let fatal_error_val = fatal_error.map_or(r::Value::Null, subgraph_error_to_value);
+        let skipped_blocks: Vec<_> = skipped_blocks
+            .into_iter()
+            .map(|skipped| {
+                object! {
+                    __typename: "SkippedBlock",
+                    blockNumber: skipped.block_number,
+                    handler: skipped.handler,
+                    reason: skipped.reason,
+                    createdAt: skipped.created_at.to_rfc3339(),
+                }
+            })
+            .collect();
+        let quarantined_data_sources: Vec<_> = quarantined_data_sources
+            .into_iter()
+            .map(|quarantined| {
+                object! {
+                    __typename: "QuarantinedDataSource",
+                    name: quarantined.name,
+                    address: quarantined.address.map(|address| r::Value::from(Value::Bytes(address.into()))),
+                    blockNumber: quarantined.block_number,
+                    message: quarantined.message,
+                }
+            })
+            .collect();
 
         object! {
             __typename: "SubgraphIndexingStatus",
@@ -158,6 +191,8 @@ impl IntoValue for Info {
             health: r::Value::from(health),
             fatalError: fatal_error_val,
             nonFatalErrors: non_fatal_errors,
+            skippedBlocks: skipped_blocks,
+            quarantinedDataSources: quarantined_data_sources,
             chains: chains.into_iter().map(|chain| chain.into_value()).collect::<Vec<_>>(),
             entityCount: format!("{}", entity_count),
             node: node,
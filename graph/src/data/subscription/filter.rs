@@ -0,0 +1,252 @@
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use crate::data::store::scalar::BigDecimal;
+use crate::data::value::Object;
+use crate::prelude::r;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Not,
+    Gt,
+    Lt,
+    Gte,
+    Lte,
+    In,
+    NotIn,
+    Contains,
+    NotContains,
+}
+
+impl Op {
+    /// Split the key of a filter into the field name and the operator,
+    /// using the same suffixes as the `where` argument of collection fields
+    fn parse(key: &str) -> (&str, Op) {
+        // Longer suffixes need to come first so that `_not_in` is not
+        // mistaken for `_in`
+        const SUFFIXES: [(&str, Op); 9] = [
+            ("_not_contains", Op::NotContains),
+            ("_contains", Op::Contains),
+            ("_not_in", Op::NotIn),
+            ("_not", Op::Not),
+            ("_gte", Op::Gte),
+            ("_lte", Op::Lte),
+            ("_in", Op::In),
+            ("_gt", Op::Gt),
+            ("_lt", Op::Lt),
+        ];
+        SUFFIXES
+            .iter()
+            .find_map(|(suffix, op)| {
+                key.strip_suffix(suffix)
+                    .filter(|field| !field.is_empty())
+                    .map(|field| (field, *op))
+            })
+            .unwrap_or((key, Op::Equal))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Condition {
+    field: String,
+    op: Op,
+    value: r::Value,
+}
+
+impl Condition {
+    fn matches(&self, entity: &Object) -> bool {
+        let actual = entity.get(&self.field).unwrap_or(&r::Value::Null);
+        match self.op {
+            Op::Equal => equal(actual, &self.value),
+            Op::Not => !equal(actual, &self.value),
+            Op::Gt => compare(actual, &self.value) == Some(Ordering::Greater),
+            Op::Lt => compare(actual, &self.value) == Some(Ordering::Less),
+            Op::Gte => matches!(
+                compare(actual, &self.value),
+                Some(Ordering::Greater | Ordering::Equal)
+            ),
+            Op::Lte => matches!(
+                compare(actual, &self.value),
+                Some(Ordering::Less | Ordering::Equal)
+            ),
+            Op::In => contains(&self.value, actual),
+            Op::NotIn => !contains(&self.value, actual),
+            Op::Contains => contains(actual, &self.value),
+            Op::NotContains => !contains(actual, &self.value),
+        }
+    }
+}
+
+fn decimal(value: &r::Value) -> Option<BigDecimal> {
+    match value {
+        r::Value::Int(i) => Some(BigDecimal::from(*i)),
+        // `BigInt` and `BigDecimal` values are sent to clients as strings
+        r::Value::String(s) => BigDecimal::from_str(s).ok(),
+        _ => None,
+    }
+}
+
+fn compare(a: &r::Value, b: &r::Value) -> Option<Ordering> {
+    match (a, b) {
+        (r::Value::Float(a), r::Value::Float(b)) => a.partial_cmp(b),
+        (r::Value::Float(a), r::Value::Int(b)) => a.partial_cmp(&(*b as f64)),
+        (r::Value::Int(a), r::Value::Float(b)) => (*a as f64).partial_cmp(b),
+        (r::Value::String(sa), r::Value::String(sb)) => match (decimal(a), decimal(b)) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            _ => Some(sa.cmp(sb)),
+        },
+        _ => match (decimal(a), decimal(b)) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            _ => None,
+        },
+    }
+}
+
+fn equal(a: &r::Value, b: &r::Value) -> bool {
+    a == b || compare(a, b) == Some(Ordering::Equal)
+}
+
+/// Check whether `haystack`, a list or a string, contains `needle`
+fn contains(haystack: &r::Value, needle: &r::Value) -> bool {
+    match (haystack, needle) {
+        (r::Value::List(values), needle) => values.iter().any(|value| equal(value, needle)),
+        (r::Value::String(s), r::Value::String(needle)) => s.contains(needle.as_str()),
+        _ => false,
+    }
+}
+
+/// A filter that a client sends along with a subscription. It uses the
+/// same syntax as the `where` argument of collection fields, but is
+/// applied by the server to the entities in the result of the
+/// subscription. Clients are only sent a new result when the entities that
+/// match the filter change
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubscriptionFilter {
+    conditions: Vec<Condition>,
+}
+
+impl SubscriptionFilter {
+    /// Parse a filter from a JSON object like `{ "name": "x", "count_gt": 5 }`
+    pub fn from_json(value: serde_json::Value) -> Result<Self, String> {
+        let map = match value {
+            serde_json::Value::Object(map) => map,
+            _ => return Err("the filter must be an object".to_string()),
+        };
+        let conditions = map
+            .into_iter()
+            .map(|(key, value)| {
+                let (field, op) = Op::parse(&key);
+                let value = r::Value::from(value);
+                if matches!(op, Op::In | Op::NotIn) && !matches!(value, r::Value::List(_)) {
+                    return Err(format!("the value for `{}` must be a list", key));
+                }
+                Ok(Condition {
+                    field: field.to_string(),
+                    op,
+                    value,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(SubscriptionFilter { conditions })
+    }
+
+    /// Return `true` if `entity` satisfies all conditions of the filter
+    pub fn matches(&self, entity: &Object) -> bool {
+        self.conditions.iter().all(|cond| cond.matches(entity))
+    }
+
+    /// Apply the filter to the root fields of `data`: entities in lists
+    /// that do not match the filter are removed, and single entities that
+    /// do not match are replaced with `null`
+    pub fn apply(&self, data: &Object) -> Object {
+        let filter = |value: &r::Value| match value {
+            r::Value::List(values) => r::Value::List(
+                values
+                    .iter()
+                    .filter(|value| match value {
+                        r::Value::Object(entity) => self.matches(entity),
+                        _ => true,
+                    })
+                    .cloned()
+                    .collect(),
+            ),
+            r::Value::Object(entity) if !self.matches(entity) => r::Value::Null,
+            value => value.clone(),
+        };
+        Object::from_iter(
+            data.iter()
+                .map(|(key, value)| (key.to_string(), filter(value))),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionFilter;
+    use crate::data::value::Object;
+    use crate::prelude::r;
+
+    fn entity(id: &str, count: i64, amount: &str) -> r::Value {
+        r::Value::Object(Object::from_iter(vec![
+            ("id".to_string(), r::Value::String(id.to_string())),
+            ("count".to_string(), r::Value::Int(count)),
+            ("amount".to_string(), r::Value::String(amount.to_string())),
+        ]))
+    }
+
+    fn ids(data: &Object) -> Vec<String> {
+        match data.get("things") {
+            Some(r::Value::List(values)) => values
+                .iter()
+                .map(|value| match value {
+                    r::Value::Object(obj) => match obj.get("id") {
+                        Some(r::Value::String(id)) => id.clone(),
+                        _ => panic!("entity without id"),
+                    },
+                    _ => panic!("not an entity"),
+                })
+                .collect(),
+            _ => panic!("no things"),
+        }
+    }
+
+    #[test]
+    fn filters_entities() {
+        let data = Object::from_iter(vec![(
+            "things".to_string(),
+            r::Value::List(vec![
+                entity("a", 1, "100"),
+                entity("b", 5, "20"),
+                entity("c", 10, "3"),
+            ]),
+        )]);
+
+        let filter = |json: serde_json::Value| {
+            let filter = SubscriptionFilter::from_json(json).unwrap();
+            ids(&filter.apply(&data))
+        };
+
+        assert_eq!(vec!["b"], filter(serde_json::json!({ "id": "b" })));
+        assert_eq!(
+            vec!["b", "c"],
+            filter(serde_json::json!({ "count_gte": 5 }))
+        );
+        // `BigInt` values are compared as numbers, not as strings
+        assert_eq!(
+            vec!["a", "b"],
+            filter(serde_json::json!({ "amount_gt": "10" }))
+        );
+        assert_eq!(
+            vec!["a", "c"],
+            filter(serde_json::json!({ "id_not_in": ["b"] }))
+        );
+        assert_eq!(
+            vec!["c"],
+            filter(serde_json::json!({ "id_in": ["a", "c"], "count_gt": 1 }))
+        );
+
+        assert!(SubscriptionFilter::from_json(serde_json::json!({ "id_in": "a" })).is_err());
+        assert!(SubscriptionFilter::from_json(serde_json::json!(["a"])).is_err());
+    }
+}
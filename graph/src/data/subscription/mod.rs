@@ -1,7 +1,9 @@
 mod error;
+mod filter;
 mod result;
 mod subscription;
 
 pub use self::error::SubscriptionError;
+pub use self::filter::SubscriptionFilter;
 pub use self::result::{QueryResultStream, SubscriptionResult};
 pub use self::subscription::Subscription;
@@ -1,11 +1,27 @@
-use crate::prelude::Query;
+use crate::prelude::{BlockPtr, Query};
+
+use super::SubscriptionFilter;
 
 /// A GraphQL subscription made by a client.
-///
-/// At the moment, this only contains the GraphQL query submitted as the
-/// subscription payload.
 #[derive(Clone, Debug)]
 pub struct Subscription {
     /// The GraphQL subscription query.
     pub query: Query,
+    /// A filter that is applied to the results of the subscription; when
+    /// it is set, results are only sent when they change.
+    pub filter: Option<SubscriptionFilter>,
+    /// The block of the last result that the client received before it
+    /// reconnected. Results for the blocks after it are sent before the
+    /// subscription continues with the latest block.
+    pub cursor: Option<BlockPtr>,
+}
+
+impl Subscription {
+    pub fn new(query: Query) -> Self {
+        Subscription {
+            query,
+            filter: None,
+            cursor: None,
+        }
+    }
 }
@@ -211,6 +211,8 @@ impl Value {
             ("BigInt", Value::Int(n)) => Ok(Value::String(n.to_string())),
             ("JSONObject", Value::Object(obj)) => Ok(Value::Object(obj)),
             ("Date", Value::String(obj)) => Ok(Value::String(obj)),
+            // Representations of entities for Apollo Federation
+            ("_Any", Value::Object(obj)) => Ok(Value::Object(obj)),
             (_, v) => Err(v),
         }
     }
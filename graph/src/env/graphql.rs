@@ -23,6 +23,37 @@ pub struct EnvVarsGraphQl {
     /// Set by the environment variable `GRAPH_GRAPHQL_MAX_COMPLEXITY`. No
     /// default value is provided.
     pub max_complexity: Option<u64>,
+    /// The complexity that is charged for computing the `totalCount` of a
+    /// connection or the aggregates of a `<types>Aggregate` field, which
+    /// need to scan all matching entities.
+    ///
+    /// Set by the environment variable `GRAPH_GRAPHQL_TOTAL_COUNT_COMPLEXITY`.
+    /// The default value is 1000.
+    pub total_count_complexity: u64,
+    /// The complexity that is charged for each entity that a collection
+    /// field can return, i.e., the weight of its `first` argument.
+    ///
+    /// Set by the environment variable `GRAPH_GRAPHQL_FIRST_COMPLEXITY_WEIGHT`.
+    /// The default value is 1.
+    pub first_complexity_weight: u64,
+    /// The complexity that is charged for each entity that a collection
+    /// field skips with its `skip` argument.
+    ///
+    /// Set by the environment variable `GRAPH_GRAPHQL_SKIP_COMPLEXITY_WEIGHT`.
+    /// The default value is 0.
+    pub skip_complexity_weight: u64,
+    /// The factor by which the complexity of a collection field is
+    /// multiplied when it is nested inside another collection field.
+    ///
+    /// Set by the environment variable
+    /// `GRAPH_GRAPHQL_NESTED_COLLECTION_COMPLEXITY_WEIGHT`. The default value
+    /// is 1.
+    pub nested_collection_complexity_weight: u64,
+    /// The complexity that is charged for each fulltext search.
+    ///
+    /// Set by the environment variable `GRAPH_GRAPHQL_FULLTEXT_COMPLEXITY`.
+    /// The default value is 0.
+    pub fulltext_complexity: u64,
     /// Set by the environment variable `GRAPH_GRAPHQL_MAX_DEPTH`. The default
     /// value is 255.
     pub max_depth: u8,
@@ -47,6 +78,139 @@ pub struct EnvVarsGraphQl {
     /// Set by the flag `GRAPH_GRAPHQL_MAX_OPERATIONS_PER_CONNECTION`. No
     /// default is provided.
     pub max_operations_per_connection: Option<usize>,
+    /// Set by the environment variable
+    /// `GRAPH_GRAPHQL_MAX_SUBSCRIPTION_REPLAY_BLOCKS`. How many blocks of
+    /// results a subscription that is resumed from a cursor replays at most
+    /// before continuing with the latest block. The default value is 100.
+    pub max_subscription_replay_blocks: i32,
+    /// The URL of a cache for query results that is shared between query
+    /// nodes, for example `redis://localhost:6379`.
+    ///
+    /// Set by the environment variable `GRAPH_QUERY_SHARED_CACHE_URL`. No
+    /// default is provided, and no shared cache is used if it is not set.
+    pub shared_cache_url: Option<String>,
+    /// How long results are kept in the shared query cache.
+    ///
+    /// Set by the environment variable `GRAPH_QUERY_SHARED_CACHE_TTL`
+    /// (expressed in seconds). The default value is 300s.
+    pub shared_cache_ttl: Duration,
+    /// How long we wait for the shared query cache before treating a
+    /// lookup as a miss.
+    ///
+    /// Set by the environment variable `GRAPH_QUERY_SHARED_CACHE_TIMEOUT`
+    /// (expressed in milliseconds). The default value is 100ms.
+    pub shared_cache_timeout: Duration,
+    /// How many blocks behind the chain head we search for a block that a
+    /// query references by hash when the block is not in the chain store
+    /// and the chain can only be reached through Firehose.
+    ///
+    /// Set by the environment variable `GRAPH_GRAPHQL_BLOCK_HASH_LOOKUP_DEPTH`.
+    /// The default value is 1000.
+    pub block_hash_lookup_depth: i32,
+    /// How many block hashes that were looked up through the chain, and
+    /// whether the chain knew them, to remember.
+    ///
+    /// Set by the environment variable `GRAPH_GRAPHQL_BLOCK_HASH_CACHE_SIZE`.
+    /// The default value is 10000.
+    pub block_hash_cache_size: usize,
+    /// How long to remember block hashes that were looked up through the
+    /// chain.
+    ///
+    /// Set by the environment variable `GRAPH_GRAPHQL_BLOCK_HASH_CACHE_TTL`
+    /// (expressed in seconds). The default value is 60s.
+    pub block_hash_cache_ttl: Duration,
+    /// How many block hashes can be looked up through the chain at the
+    /// same time. Queries that need more lookups fail.
+    ///
+    /// Set by the environment variable `GRAPH_GRAPHQL_MAX_BLOCK_HASH_LOOKUPS`.
+    /// The default value is 4.
+    pub max_block_hash_lookups: usize,
+    /// How many items of a list field with `@stream` to send in each
+    /// payload after the initial payload.
+    ///
+    /// Set by the environment variable `GRAPH_GRAPHQL_STREAM_BATCH_SIZE`.
+    /// The default value is 100.
+    pub stream_batch_size: u32,
+    /// How many entities of a list field with `@export` to fetch from the
+    /// database and send in each payload.
+    ///
+    /// Set by the environment variable `GRAPH_GRAPHQL_EXPORT_BATCH_SIZE`.
+    /// The default value is 1000.
+    pub export_batch_size: usize,
+    /// The largest value for `first` that a list field with `@export` can
+    /// use. Exports stream their entities and are therefore not bound by
+    /// `GRAPH_GRAPHQL_MAX_FIRST`.
+    ///
+    /// Set by the environment variable `GRAPH_GRAPHQL_EXPORT_MAX_ROWS`.
+    /// The default value is 1000000.
+    pub export_max_rows: u32,
+    /// How long the database cursor of an `@export` waits for the client
+    /// to accept the next payload before the export is aborted.
+    ///
+    /// Set by the environment variable `GRAPH_GRAPHQL_EXPORT_SEND_TIMEOUT`
+    /// (expressed in seconds). The default value is 30s.
+    pub export_send_timeout: Duration,
+    /// Set by the flag `GRAPH_GRAPHQL_ENABLE_EXPORTS`. Off by default.
+    pub enable_exports: bool,
+    /// Only allow queries that have been registered as persisted queries
+    /// through the admin API.
+    ///
+    /// Set by the flag `GRAPH_GRAPHQL_ENFORCE_PERSISTED_QUERIES`. Off by
+    /// default.
+    pub enforce_persisted_queries: bool,
+    /// How often the persisted queries are reloaded from the database.
+    /// Queries registered through another node are only seen after that.
+    ///
+    /// Set by the environment variable
+    /// `GRAPH_GRAPHQL_PERSISTED_QUERIES_REFRESH_INTERVAL` (expressed in
+    /// seconds). The default value is 60s.
+    pub persisted_queries_refresh_interval: Duration,
+    /// A JSON file that maps API keys to the limits for queries made with
+    /// them. When API keys are configured, every query must carry one.
+    ///
+    /// Set by the environment variable `GRAPH_GRAPHQL_API_KEYS_FILE`. No
+    /// default is provided.
+    pub api_keys_file: Option<String>,
+    /// The base URL of a service that looks up the limits for API keys.
+    /// Only used if `GRAPH_GRAPHQL_API_KEYS_FILE` is not set.
+    ///
+    /// Set by the environment variable `GRAPH_GRAPHQL_API_KEYS_URL`. No
+    /// default is provided.
+    pub api_keys_url: Option<String>,
+    /// How long the limits looked up from `GRAPH_GRAPHQL_API_KEYS_URL` are
+    /// cached.
+    ///
+    /// Set by the environment variable `GRAPH_GRAPHQL_API_KEYS_CACHE_TTL`
+    /// (expressed in seconds). The default value is 60s.
+    pub api_keys_cache_ttl: Duration,
+    /// How many API keys looked up from `GRAPH_GRAPHQL_API_KEYS_URL` are
+    /// cached at most.
+    ///
+    /// Set by the environment variable `GRAPH_GRAPHQL_API_KEYS_CACHE_SIZE`.
+    /// The default value is 10000.
+    pub api_keys_cache_size: usize,
+    /// How long CDNs and other caches may serve a query result without
+    /// asking the GraphQL HTTP server whether it is still current.
+    ///
+    /// Set by the environment variable `GRAPH_GRAPHQL_HTTP_CACHE_MAX_AGE`
+    /// (expressed in seconds). The default value is 0s.
+    pub http_cache_max_age: Duration,
+    /// Set by the flag `GRAPH_GRAPHQL_DISABLE_HTTP_COMPRESSION`. Off by
+    /// default.
+    pub disable_http_compression: bool,
+    /// Set by the environment variable `GRAPH_GRAPHQL_MAX_BATCH_SIZE`. The
+    /// maximum number of queries in one batch request. The default value
+    /// is 10.
+    pub max_batch_size: usize,
+    /// Set by the flag `GRAPH_GRAPHQL_CONCURRENT_BATCHES`. Run the queries
+    /// in a batch concurrently rather than one after the other. Off by
+    /// default.
+    pub concurrent_batches: bool,
+    /// Set by the environment variable
+    /// `GRAPH_GRAPHQL_QUERY_STATS_MAX_SHAPES`. The maximum number of query
+    /// shapes for which we keep statistics for each deployment. Setting
+    /// this to 0 turns off query statistics. The default value is 100.
+    pub query_stats_max_shapes: usize,
 }
 
 // This does not print any values avoid accidentally leaking any sensitive env vars
@@ -66,6 +230,11 @@ impl From<InnerGraphQl> for EnvVarsGraphQl {
             sql_statement_timeout: x.sql_statement_timeout_in_secs.map(Duration::from_secs),
             query_timeout: x.query_timeout_in_secs.map(Duration::from_secs),
             max_complexity: x.max_complexity.map(|x| x.0),
+            total_count_complexity: x.total_count_complexity,
+            first_complexity_weight: x.first_complexity_weight,
+            skip_complexity_weight: x.skip_complexity_weight,
+            nested_collection_complexity_weight: x.nested_collection_complexity_weight,
+            fulltext_complexity: x.fulltext_complexity,
             max_depth: x.max_depth.0,
             max_first: x.max_first,
             max_skip: x.max_skip.0,
@@ -73,6 +242,32 @@ impl From<InnerGraphQl> for EnvVarsGraphQl {
             warn_result_size: x.warn_result_size.0 .0,
             error_result_size: x.error_result_size.0 .0,
             max_operations_per_connection: x.max_operations_per_connection,
+            max_subscription_replay_blocks: x.max_subscription_replay_blocks,
+            shared_cache_url: x.shared_cache_url,
+            shared_cache_ttl: Duration::from_secs(x.shared_cache_ttl_in_secs),
+            shared_cache_timeout: Duration::from_millis(x.shared_cache_timeout_in_ms),
+            block_hash_lookup_depth: x.block_hash_lookup_depth,
+            block_hash_cache_size: x.block_hash_cache_size,
+            block_hash_cache_ttl: Duration::from_secs(x.block_hash_cache_ttl_in_secs),
+            max_block_hash_lookups: x.max_block_hash_lookups,
+            stream_batch_size: x.stream_batch_size,
+            export_batch_size: x.export_batch_size,
+            export_max_rows: x.export_max_rows,
+            export_send_timeout: Duration::from_secs(x.export_send_timeout_in_secs),
+            enable_exports: x.enable_exports.0,
+            enforce_persisted_queries: x.enforce_persisted_queries.0,
+            persisted_queries_refresh_interval: Duration::from_secs(
+                x.persisted_queries_refresh_interval_in_secs,
+            ),
+            api_keys_file: x.api_keys_file,
+            api_keys_url: x.api_keys_url,
+            api_keys_cache_ttl: Duration::from_secs(x.api_keys_cache_ttl_in_secs),
+            api_keys_cache_size: x.api_keys_cache_size,
+            http_cache_max_age: Duration::from_secs(x.http_cache_max_age_in_secs),
+            disable_http_compression: x.disable_http_compression.0,
+            max_batch_size: x.max_batch_size,
+            concurrent_batches: x.concurrent_batches.0,
+            query_stats_max_shapes: x.query_stats_max_shapes,
         }
     }
 }
@@ -90,6 +285,19 @@ pub struct InnerGraphQl {
     query_timeout_in_secs: Option<u64>,
     #[envconfig(from = "GRAPH_GRAPHQL_MAX_COMPLEXITY")]
     max_complexity: Option<NoUnderscores<u64>>,
+    #[envconfig(from = "GRAPH_GRAPHQL_TOTAL_COUNT_COMPLEXITY", default = "1000")]
+    total_count_complexity: u64,
+    #[envconfig(from = "GRAPH_GRAPHQL_FIRST_COMPLEXITY_WEIGHT", default = "1")]
+    first_complexity_weight: u64,
+    #[envconfig(from = "GRAPH_GRAPHQL_SKIP_COMPLEXITY_WEIGHT", default = "0")]
+    skip_complexity_weight: u64,
+    #[envconfig(
+        from = "GRAPH_GRAPHQL_NESTED_COLLECTION_COMPLEXITY_WEIGHT",
+        default = "1"
+    )]
+    nested_collection_complexity_weight: u64,
+    #[envconfig(from = "GRAPH_GRAPHQL_FULLTEXT_COMPLEXITY", default = "0")]
+    fulltext_complexity: u64,
     #[envconfig(from = "GRAPH_GRAPHQL_MAX_DEPTH", default = "")]
     max_depth: WithDefaultUsize<u8, { u8::MAX as usize }>,
     #[envconfig(from = "GRAPH_GRAPHQL_MAX_FIRST", default = "1000")]
@@ -104,4 +312,55 @@ pub struct InnerGraphQl {
     error_result_size: WithDefaultUsize<NoUnderscores<usize>, { usize::MAX }>,
     #[envconfig(from = "GRAPH_GRAPHQL_MAX_OPERATIONS_PER_CONNECTION")]
     max_operations_per_connection: Option<usize>,
+    #[envconfig(from = "GRAPH_GRAPHQL_MAX_SUBSCRIPTION_REPLAY_BLOCKS", default = "100")]
+    max_subscription_replay_blocks: i32,
+    #[envconfig(from = "GRAPH_QUERY_SHARED_CACHE_URL")]
+    shared_cache_url: Option<String>,
+    #[envconfig(from = "GRAPH_QUERY_SHARED_CACHE_TTL", default = "300")]
+    shared_cache_ttl_in_secs: u64,
+    #[envconfig(from = "GRAPH_QUERY_SHARED_CACHE_TIMEOUT", default = "100")]
+    shared_cache_timeout_in_ms: u64,
+    #[envconfig(from = "GRAPH_GRAPHQL_BLOCK_HASH_LOOKUP_DEPTH", default = "1000")]
+    block_hash_lookup_depth: i32,
+    #[envconfig(from = "GRAPH_GRAPHQL_BLOCK_HASH_CACHE_SIZE", default = "10000")]
+    block_hash_cache_size: usize,
+    #[envconfig(from = "GRAPH_GRAPHQL_BLOCK_HASH_CACHE_TTL", default = "60")]
+    block_hash_cache_ttl_in_secs: u64,
+    #[envconfig(from = "GRAPH_GRAPHQL_MAX_BLOCK_HASH_LOOKUPS", default = "4")]
+    max_block_hash_lookups: usize,
+    #[envconfig(from = "GRAPH_GRAPHQL_STREAM_BATCH_SIZE", default = "100")]
+    stream_batch_size: u32,
+    #[envconfig(from = "GRAPH_GRAPHQL_EXPORT_BATCH_SIZE", default = "1000")]
+    export_batch_size: usize,
+    #[envconfig(from = "GRAPH_GRAPHQL_EXPORT_MAX_ROWS", default = "1000000")]
+    export_max_rows: u32,
+    #[envconfig(from = "GRAPH_GRAPHQL_EXPORT_SEND_TIMEOUT", default = "30")]
+    export_send_timeout_in_secs: u64,
+    #[envconfig(from = "GRAPH_GRAPHQL_ENABLE_EXPORTS", default = "false")]
+    enable_exports: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_GRAPHQL_ENFORCE_PERSISTED_QUERIES", default = "false")]
+    enforce_persisted_queries: EnvVarBoolean,
+    #[envconfig(
+        from = "GRAPH_GRAPHQL_PERSISTED_QUERIES_REFRESH_INTERVAL",
+        default = "60"
+    )]
+    persisted_queries_refresh_interval_in_secs: u64,
+    #[envconfig(from = "GRAPH_GRAPHQL_API_KEYS_FILE")]
+    api_keys_file: Option<String>,
+    #[envconfig(from = "GRAPH_GRAPHQL_API_KEYS_URL")]
+    api_keys_url: Option<String>,
+    #[envconfig(from = "GRAPH_GRAPHQL_API_KEYS_CACHE_TTL", default = "60")]
+    api_keys_cache_ttl_in_secs: u64,
+    #[envconfig(from = "GRAPH_GRAPHQL_API_KEYS_CACHE_SIZE", default = "10000")]
+    api_keys_cache_size: usize,
+    #[envconfig(from = "GRAPH_GRAPHQL_HTTP_CACHE_MAX_AGE", default = "0")]
+    http_cache_max_age_in_secs: u64,
+    #[envconfig(from = "GRAPH_GRAPHQL_DISABLE_HTTP_COMPRESSION", default = "false")]
+    disable_http_compression: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_GRAPHQL_MAX_BATCH_SIZE", default = "10")]
+    max_batch_size: usize,
+    #[envconfig(from = "GRAPH_GRAPHQL_CONCURRENT_BATCHES", default = "false")]
+    concurrent_batches: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_GRAPHQL_QUERY_STATS_MAX_SHAPES", default = "100")]
+    query_stats_max_shapes: usize,
 }
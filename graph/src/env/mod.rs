@@ -6,7 +6,7 @@ use envconfig::Envconfig;
 use lazy_static::lazy_static;
 use semver::Version;
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
     env::VarError,
     fmt,
     str::FromStr,
@@ -18,7 +18,8 @@ use self::graphql::*;
 use self::mappings::*;
 use self::store::*;
 use crate::{
-    components::subgraph::SubgraphVersionSwitchingMode, runtime::gas::CONST_MAX_GAS_PER_HANDLER,
+    components::subgraph::SubgraphVersionSwitchingMode, data::subgraph::retry::RetryErrors,
+    prelude::BlockNumber, runtime::gas::CONST_MAX_GAS_PER_HANDLER,
 };
 
 pub static UNSAFE_CONFIG: AtomicBool = AtomicBool::new(false);
@@ -110,7 +111,7 @@ pub struct EnvVars {
     /// are enabled.
     pub allow_non_deterministic_fulltext_search: bool,
     /// Set by the environment variable `GRAPH_MAX_SPEC_VERSION`. The default
-    /// value is `0.0.4`.
+    /// value is `0.0.6`.
     pub max_spec_version: Version,
     /// Set by the flag `GRAPH_DISABLE_GRAFTS`.
     pub disable_grafts: bool,
@@ -127,6 +128,25 @@ pub struct EnvVars {
     /// Set by the environment variable
     /// `GRAPH_ELASTIC_SEARCH_MAX_RETRIES`. The default value is 5.
     pub elastic_search_max_retries: usize,
+    /// Set by the environment variable `GRAPH_OTLP_ENDPOINT`. The base URL
+    /// of an OpenTelemetry collector that accepts OTLP over HTTP, e.g.,
+    /// `http://localhost:4318`. Spans for queries and block processing are
+    /// sent to `<endpoint>/v1/traces`. No default value is provided, and
+    /// tracing is disabled.
+    pub otlp_endpoint: Option<String>,
+    /// Set by the environment variable `GRAPH_OTLP_SERVICE_NAME`. The
+    /// default value is `graph-node`.
+    pub otlp_service_name: String,
+    /// Set by the environment variable `GRAPH_OTLP_SAMPLE_RATIO`. The
+    /// fraction of queries and blocks that are traced. Traces that are
+    /// continued from a client's `traceparent` header follow the client's
+    /// sampling decision. The default value is 1.0.
+    pub otlp_sample_ratio: f64,
+    /// Set by the environment variable `GRAPH_ADMIN_TOKENS_FILE`. A JSON
+    /// file that maps bearer tokens to the JSON-RPC admin methods they may
+    /// call. No default value is provided, and the admin server accepts
+    /// all requests.
+    pub admin_tokens_file: Option<String>,
     /// If an instrumented lock is contended for longer than the specified
     /// duration, a warning will be logged.
     ///
@@ -152,14 +172,50 @@ pub struct EnvVars {
     pub log_poi_events: bool,
     /// Set by the environment variable `GRAPH_LOG`.
     pub log_levels: Option<String>,
+    /// Set by the environment variable `GRAPH_LOG_FORMAT`. How log lines
+    /// are written: `text` for human-readable lines, `json` for one JSON
+    /// object per line. The default value is `text`.
+    pub log_format: LogFormat,
     /// Set by the flag `EXPERIMENTAL_STATIC_FILTERS`. Off by default.
     pub experimental_static_filters: bool,
     /// Set by the environment variable
     /// `EXPERIMENTAL_SUBGRAPH_VERSION_SWITCHING_MODE`. The default value is
     /// `"instant"`.
     pub subgraph_version_switching_mode: SubgraphVersionSwitchingMode,
+    /// With the `canary` version switching mode, how many blocks behind the
+    /// chain head a healthy pending deployment may be when it becomes the
+    /// current version.
+    ///
+    /// Set by the environment variable `GRAPH_CANARY_MAX_BLOCKS_BEHIND`.
+    /// The default value is 10.
+    pub canary_max_blocks_behind: BlockNumber,
+    /// With the `canary` version switching mode, how long the deployment
+    /// that was replaced by a new version keeps indexing before it is
+    /// unassigned.
+    ///
+    /// Set by the environment variable `GRAPH_CANARY_RETIRE_AFTER`
+    /// (expressed in seconds). The default value is 3600s (1 hour).
+    pub canary_retire_after: Duration,
     /// Set by the flag `GRAPH_KILL_IF_UNRESPONSIVE`. Off by default.
     pub kill_if_unresponsive: bool,
+    /// How long to wait for in-flight GraphQL queries and for deployments
+    /// to finish the block they are working on when the node receives a
+    /// `SIGTERM`.
+    ///
+    /// Set by the environment variable `GRAPH_SHUTDOWN_TIMEOUT` (expressed
+    /// in seconds). The default value is 25 seconds.
+    pub shutdown_timeout: Duration,
+    /// The PEM file with the certificate chain that the GraphQL, WebSocket,
+    /// index node and metrics servers use for TLS. When the file changes,
+    /// the new certificate is used for new connections.
+    ///
+    /// Set by the environment variable `GRAPH_TLS_CERT_FILE`. The servers
+    /// do not use TLS by default.
+    pub tls_cert_file: Option<String>,
+    /// The PEM file with the private key for `tls_cert_file`.
+    ///
+    /// Set by the environment variable `GRAPH_TLS_KEY_FILE`.
+    pub tls_key_file: Option<String>,
     /// Guards public access to POIs in the `index-node`.
     ///
     /// Set by the environment variable `GRAPH_POI_ACCESS_TOKEN`. No default
@@ -168,6 +224,75 @@ pub struct EnvVars {
     /// Set by the environment variable `GRAPH_SUBGRAPH_MAX_DATA_SOURCES`. No
     /// default value is provided.
     pub subgraph_max_data_sources: Option<usize>,
+    /// How many syncing deployments may scan blocks and write them to the
+    /// database at the same time. Deployments with a higher priority get
+    /// preference for these slots.
+    ///
+    /// Set by the environment variable `GRAPH_SUBGRAPH_SYNC_CONCURRENCY`. No
+    /// default value is provided, and syncing deployments are not limited.
+    pub subgraph_sync_concurrency: Option<usize>,
+    /// How often index nodes record in the database how much load the
+    /// deployments they index cause. These reports are what automatic
+    /// rebalancing is based on.
+    ///
+    /// Set by the environment variable `GRAPH_LOAD_REPORT_INTERVAL` (expressed
+    /// in seconds). No default value is provided, and index nodes do not
+    /// report their load.
+    pub load_report_interval: Option<Duration>,
+    /// How often to move deployments from busy index nodes to ones with
+    /// less load.
+    ///
+    /// Set by the environment variable `GRAPH_REBALANCE_INTERVAL` (expressed
+    /// in seconds). No default value is provided, and deployments are never
+    /// moved automatically.
+    pub rebalance_interval: Option<Duration>,
+    /// Only log the moves that rebalancing would make instead of making
+    /// them.
+    ///
+    /// Set by the environment variable `GRAPH_REBALANCE_DRY_RUN`. The
+    /// default value is `false`.
+    pub rebalance_dry_run: bool,
+    /// The maximum number of deployments that one round of rebalancing
+    /// moves.
+    ///
+    /// Set by the environment variable `GRAPH_REBALANCE_MAX_MOVES`. The
+    /// default value is 5.
+    pub rebalance_max_moves: usize,
+    /// The index-node API endpoints of other graph-nodes, e.g.,
+    /// `http://indexer-2:8030/graphql`, whose proofs of indexing index
+    /// nodes regularly compare with their own for the deployments they
+    /// index.
+    ///
+    /// Set by the environment variable `GRAPH_POI_CHECK_PEERS` (comma
+    /// separated). No default value is provided, and proofs of indexing are
+    /// not compared.
+    pub poi_check_peers: Vec<String>,
+    /// How often to compare proofs of indexing with the
+    /// `GRAPH_POI_CHECK_PEERS`.
+    ///
+    /// Set by the environment variable `GRAPH_POI_CHECK_INTERVAL`
+    /// (expressed in seconds). The default value is 300s.
+    pub poi_check_interval: Duration,
+    /// Pause deployments whose proof of indexing differs from that of a
+    /// peer.
+    ///
+    /// Set by the environment variable `GRAPH_POI_CHECK_PAUSE`. The default
+    /// value is `false`.
+    pub poi_check_pause: bool,
+    /// How often nodes that do not ingest blocks for a chain try to take
+    /// over the lease for ingesting it. The node that holds the lease
+    /// checks that it still does five times as often.
+    ///
+    /// Set by the environment variable `GRAPH_INGESTOR_LEASE_INTERVAL`
+    /// (expressed in seconds). The default value is 30 seconds.
+    pub ingestor_lease_interval: Duration,
+    /// How often the health of all Firehose endpoints is checked. Endpoints
+    /// that fail the check are avoided until they pass it again.
+    ///
+    /// Set by the environment variable
+    /// `GRAPH_FIREHOSE_HEALTH_CHECK_INTERVAL` (expressed in seconds). The
+    /// default value is 30 seconds.
+    pub firehose_health_check_interval: Duration,
     /// Keep deterministic errors non-fatal even if the subgraph is pending.
     /// Used for testing Graph Node itself.
     ///
@@ -178,6 +303,24 @@ pub struct EnvVars {
     /// Set by the environment variable `GRAPH_SUBGRAPH_ERROR_RETRY_CEIL_SECS`
     /// (expressed in seconds). The default value is 1800s (30 minutes).
     pub subgraph_error_retry_ceil: Duration,
+    /// The delay before the first retry of a non-deterministic error.
+    ///
+    /// Set by the environment variable `GRAPH_SUBGRAPH_ERROR_RETRY_BASE_SECS`
+    /// (expressed in seconds). The default value is 120s (2 minutes).
+    pub subgraph_error_retry_base: Duration,
+    /// How many times a non-deterministic error is retried before the
+    /// deployment is left failed.
+    ///
+    /// Set by the environment variable
+    /// `GRAPH_SUBGRAPH_ERROR_RETRY_MAX_ATTEMPTS`. No default value is
+    /// provided, and errors are retried forever.
+    pub subgraph_error_retry_max_attempts: Option<u64>,
+    /// The classes of non-deterministic errors that are retried.
+    ///
+    /// Set by the environment variable `GRAPH_SUBGRAPH_ERROR_RETRY_ERRORS`
+    /// (`all`, `none`, or a comma-separated list of `timeout`, `ipfs`,
+    /// `store`, and `other`). The default is `all`.
+    pub subgraph_error_retry_errors: RetryErrors,
     /// Set by the environment variable `GRAPH_CACHED_SUBGRAPH_IDS` (comma
     /// separated). When the value of the variable is `*`, queries are cached
     /// for all subgraphs, which is the default
@@ -194,6 +337,26 @@ pub struct EnvVars {
     /// default value is set to whatever `GRAPH_QUERY_BLOCK_CACHE_SHARDS` is set
     /// to.
     pub query_lfu_cache_shards: u8,
+    /// Set by the environment variable `GRAPH_QUERY_EMPTY_RESULT_CACHE_SIZE`.
+    /// How many results that contain no data to cache for each cache
+    /// shard, independently of the other query caches. The default value
+    /// is 0, which turns this cache off.
+    pub query_empty_result_cache_size: usize,
+    /// Set by the environment variable `GRAPH_QUERY_CACHE_EVICTION`. What to
+    /// do when a result does not fit into the block cache for its block:
+    /// `none` rejects the result, `hits` evicts the entries with the fewest
+    /// hits to make room for it. The default value is `none`.
+    pub query_cache_eviction: QueryCacheEviction,
+    /// Set by the environment variable `GRAPH_QUERY_CACHE_DEPLOYMENT_QUOTA`.
+    /// The percentage of each block cache that the results for a single
+    /// deployment may use. The default value is 100, i.e., no quota.
+    pub query_cache_deployment_quota: usize,
+    /// Set by the environment variable `GRAPH_QUERY_CACHE_DEPLOYMENT_WEIGHTS`
+    /// (comma separated list of `<deployment hash>=<weight>`). The quota of
+    /// a deployment is multiplied by its weight, which defaults to 1. A
+    /// weight of 0 keeps the results for that deployment out of the block
+    /// cache.
+    pub query_cache_deployment_weights: HashMap<String, f64>,
     /// Experimental feature.
     ///
     /// Set by the flag `GRAPH_ENABLE_SELECT_BY_SPECIFIC_ATTRIBUTES`. Off by
@@ -248,6 +411,10 @@ impl EnvVars {
                 inner.elastic_search_flush_interval_in_secs,
             ),
             elastic_search_max_retries: inner.elastic_search_max_retries,
+            otlp_endpoint: inner.otlp_endpoint,
+            otlp_service_name: inner.otlp_service_name,
+            otlp_sample_ratio: inner.otlp_sample_ratio,
+            admin_tokens_file: inner.admin_tokens_file,
             lock_contention_log_threshold: Duration::from_millis(
                 inner.lock_contention_log_threshold_in_ms,
             ),
@@ -260,13 +427,44 @@ impl EnvVars {
             log_time_format: inner.log_time_format,
             log_poi_events: inner.log_poi_events.0,
             log_levels: inner.log_levels,
+            log_format: inner.log_format,
             experimental_static_filters: inner.experimental_static_filters.0,
             subgraph_version_switching_mode: inner.subgraph_version_switching_mode,
+            canary_max_blocks_behind: inner.canary_max_blocks_behind,
+            canary_retire_after: Duration::from_secs(inner.canary_retire_after_in_secs),
             kill_if_unresponsive: inner.kill_if_unresponsive.0,
+            shutdown_timeout: Duration::from_secs(inner.shutdown_timeout_in_secs),
+            tls_cert_file: inner.tls_cert_file,
+            tls_key_file: inner.tls_key_file,
             poi_access_token: inner.poi_access_token,
             subgraph_max_data_sources: inner.subgraph_max_data_sources,
+            subgraph_sync_concurrency: inner.subgraph_sync_concurrency,
+            load_report_interval: inner.load_report_interval_in_secs.map(Duration::from_secs),
+            rebalance_interval: inner.rebalance_interval_in_secs.map(Duration::from_secs),
+            rebalance_dry_run: inner.rebalance_dry_run.0,
+            rebalance_max_moves: inner.rebalance_max_moves,
+            poi_check_peers: inner
+                .poi_check_peers
+                .map(|peers| {
+                    peers
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|peer| !peer.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            poi_check_interval: Duration::from_secs(inner.poi_check_interval_in_secs),
+            poi_check_pause: inner.poi_check_pause.0,
+            ingestor_lease_interval: Duration::from_secs(inner.ingestor_lease_interval_in_secs),
+            firehose_health_check_interval: Duration::from_secs(
+                inner.firehose_health_check_interval_in_secs,
+            ),
             disable_fail_fast: inner.disable_fail_fast.0,
             subgraph_error_retry_ceil: Duration::from_secs(inner.subgraph_error_retry_ceil_in_secs),
+            subgraph_error_retry_base: Duration::from_secs(inner.subgraph_error_retry_base_in_secs),
+            subgraph_error_retry_max_attempts: inner.subgraph_error_retry_max_attempts,
+            subgraph_error_retry_errors: inner.subgraph_error_retry_errors,
             cached_subgraph_ids: if inner.cached_subgraph_ids == "*" {
                 CachedSubgraphIds::All
             } else {
@@ -282,6 +480,10 @@ impl EnvVars {
             query_lfu_cache_shards: inner
                 .query_lfu_cache_shards
                 .unwrap_or(inner.query_block_cache_shards),
+            query_empty_result_cache_size: inner.query_empty_result_cache_size,
+            query_cache_eviction: inner.query_cache_eviction,
+            query_cache_deployment_quota: inner.query_cache_deployment_quota,
+            query_cache_deployment_weights: inner.query_cache_deployment_weights.0,
             enable_select_by_specific_attributes: inner.enable_select_by_specific_attributes.0,
             log_trigger_data: inner.log_trigger_data.0,
             explorer_ttl: Duration::from_secs(inner.explorer_ttl_in_secs),
@@ -341,7 +543,7 @@ struct Inner {
         default = "false"
     )]
     allow_non_deterministic_fulltext_search: EnvVarBoolean,
-    #[envconfig(from = "GRAPH_MAX_SPEC_VERSION", default = "0.0.5")]
+    #[envconfig(from = "GRAPH_MAX_SPEC_VERSION", default = "0.0.6")]
     max_spec_version: Version,
     #[envconfig(from = "GRAPH_DISABLE_GRAFTS", default = "false")]
     disable_grafts: EnvVarBoolean,
@@ -353,6 +555,14 @@ struct Inner {
     elastic_search_flush_interval_in_secs: u64,
     #[envconfig(from = "GRAPH_ELASTIC_SEARCH_MAX_RETRIES", default = "5")]
     elastic_search_max_retries: usize,
+    #[envconfig(from = "GRAPH_OTLP_ENDPOINT")]
+    otlp_endpoint: Option<String>,
+    #[envconfig(from = "GRAPH_OTLP_SERVICE_NAME", default = "graph-node")]
+    otlp_service_name: String,
+    #[envconfig(from = "GRAPH_OTLP_SAMPLE_RATIO", default = "1.0")]
+    otlp_sample_ratio: f64,
+    #[envconfig(from = "GRAPH_ADMIN_TOKENS_FILE")]
+    admin_tokens_file: Option<String>,
     #[envconfig(from = "GRAPH_LOCK_CONTENTION_LOG_THRESHOLD_MS", default = "100")]
     lock_contention_log_threshold_in_ms: u64,
     #[envconfig(from = "GRAPH_MAX_GAS_PER_HANDLER", default = "")]
@@ -366,6 +576,8 @@ struct Inner {
     log_poi_events: EnvVarBoolean,
     #[envconfig(from = "GRAPH_LOG")]
     log_levels: Option<String>,
+    #[envconfig(from = "GRAPH_LOG_FORMAT", default = "text")]
+    log_format: LogFormat,
     #[envconfig(from = "EXPERIMENTAL_STATIC_FILTERS", default = "false")]
     experimental_static_filters: EnvVarBoolean,
     #[envconfig(
@@ -373,22 +585,66 @@ struct Inner {
         default = "instant"
     )]
     subgraph_version_switching_mode: SubgraphVersionSwitchingMode,
+    #[envconfig(from = "GRAPH_CANARY_MAX_BLOCKS_BEHIND", default = "10")]
+    canary_max_blocks_behind: BlockNumber,
+    #[envconfig(from = "GRAPH_CANARY_RETIRE_AFTER", default = "3600")]
+    canary_retire_after_in_secs: u64,
     #[envconfig(from = "GRAPH_KILL_IF_UNRESPONSIVE", default = "false")]
     kill_if_unresponsive: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_SHUTDOWN_TIMEOUT", default = "25")]
+    shutdown_timeout_in_secs: u64,
+    #[envconfig(from = "GRAPH_TLS_CERT_FILE")]
+    tls_cert_file: Option<String>,
+    #[envconfig(from = "GRAPH_TLS_KEY_FILE")]
+    tls_key_file: Option<String>,
     #[envconfig(from = "GRAPH_POI_ACCESS_TOKEN")]
     poi_access_token: Option<String>,
     #[envconfig(from = "GRAPH_SUBGRAPH_MAX_DATA_SOURCES")]
     subgraph_max_data_sources: Option<usize>,
+    #[envconfig(from = "GRAPH_SUBGRAPH_SYNC_CONCURRENCY")]
+    subgraph_sync_concurrency: Option<usize>,
+    #[envconfig(from = "GRAPH_LOAD_REPORT_INTERVAL")]
+    load_report_interval_in_secs: Option<u64>,
+    #[envconfig(from = "GRAPH_REBALANCE_INTERVAL")]
+    rebalance_interval_in_secs: Option<u64>,
+    #[envconfig(from = "GRAPH_REBALANCE_DRY_RUN", default = "false")]
+    rebalance_dry_run: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_REBALANCE_MAX_MOVES", default = "5")]
+    rebalance_max_moves: usize,
+    #[envconfig(from = "GRAPH_POI_CHECK_PEERS")]
+    poi_check_peers: Option<String>,
+    #[envconfig(from = "GRAPH_POI_CHECK_INTERVAL", default = "300")]
+    poi_check_interval_in_secs: u64,
+    #[envconfig(from = "GRAPH_POI_CHECK_PAUSE", default = "false")]
+    poi_check_pause: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_INGESTOR_LEASE_INTERVAL", default = "30")]
+    ingestor_lease_interval_in_secs: u64,
+    #[envconfig(from = "GRAPH_FIREHOSE_HEALTH_CHECK_INTERVAL", default = "30")]
+    firehose_health_check_interval_in_secs: u64,
     #[envconfig(from = "GRAPH_DISABLE_FAIL_FAST", default = "false")]
     disable_fail_fast: EnvVarBoolean,
     #[envconfig(from = "GRAPH_SUBGRAPH_ERROR_RETRY_CEIL_SECS", default = "1800")]
     subgraph_error_retry_ceil_in_secs: u64,
+    #[envconfig(from = "GRAPH_SUBGRAPH_ERROR_RETRY_BASE_SECS", default = "120")]
+    subgraph_error_retry_base_in_secs: u64,
+    #[envconfig(from = "GRAPH_SUBGRAPH_ERROR_RETRY_MAX_ATTEMPTS")]
+    subgraph_error_retry_max_attempts: Option<u64>,
+    #[envconfig(from = "GRAPH_SUBGRAPH_ERROR_RETRY_ERRORS", default = "all")]
+    subgraph_error_retry_errors: RetryErrors,
     #[envconfig(from = "GRAPH_CACHED_SUBGRAPH_IDS", default = "*")]
     cached_subgraph_ids: String,
     #[envconfig(from = "GRAPH_QUERY_BLOCK_CACHE_SHARDS", default = "128")]
     query_block_cache_shards: u8,
     #[envconfig(from = "GRAPH_QUERY_LFU_CACHE_SHARDS")]
     query_lfu_cache_shards: Option<u8>,
+    #[envconfig(from = "GRAPH_QUERY_EMPTY_RESULT_CACHE_SIZE", default = "0")]
+    query_empty_result_cache_size: usize,
+    #[envconfig(from = "GRAPH_QUERY_CACHE_EVICTION", default = "none")]
+    query_cache_eviction: QueryCacheEviction,
+    #[envconfig(from = "GRAPH_QUERY_CACHE_DEPLOYMENT_QUOTA", default = "100")]
+    query_cache_deployment_quota: usize,
+    #[envconfig(from = "GRAPH_QUERY_CACHE_DEPLOYMENT_WEIGHTS", default = "")]
+    query_cache_deployment_weights: DeploymentWeights,
     #[envconfig(from = "GRAPH_ENABLE_SELECT_BY_SPECIFIC_ATTRIBUTES", default = "false")]
     enable_select_by_specific_attributes: EnvVarBoolean,
     #[envconfig(from = "GRAPH_LOG_TRIGGER_DATA", default = "false")]
@@ -411,6 +667,74 @@ pub enum CachedSubgraphIds {
     Only(Vec<String>),
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueryCacheEviction {
+    /// Results that do not fit into the cache are not cached
+    None,
+    /// Evict the entries with the fewest hits to make room for new results
+    Hits,
+}
+
+impl FromStr for QueryCacheEviction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(QueryCacheEviction::None),
+            "hits" => Ok(QueryCacheEviction::Hits),
+            _ => Err(format!("invalid query cache eviction policy: {:?}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable log lines
+    Text,
+    /// One JSON object per log line
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("invalid log format: {:?}", s)),
+        }
+    }
+}
+
+/// A list of `<deployment hash>=<weight>` pairs, separated by commas
+#[derive(Clone, Debug, Default)]
+struct DeploymentWeights(HashMap<String, f64>);
+
+impl FromStr for DeploymentWeights {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (id, weight) = pair.split_once('=').ok_or_else(|| {
+                    format!("expected `<deployment>=<weight>` but got {:?}", pair)
+                })?;
+                let weight = weight
+                    .trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|weight| *weight >= 0.0)
+                    .ok_or_else(|| format!("invalid weight for deployment {}: {:?}", id, weight))?;
+                Ok((id.trim().to_string(), weight))
+            })
+            .collect::<Result<_, _>>()
+            .map(DeploymentWeights)
+    }
+}
+
 /// When reading [`bool`] values from environment variables, we must be able to
 /// parse many different ways to specify booleans:
 ///
@@ -78,6 +78,169 @@ pub struct EnvVarsStore {
     /// Set by the environment variable `GRAPH_REMOVE_UNUSED_INTERVAL`
     /// (expressed in minutes). The default value is 360 minutes.
     pub remove_unused_interval: chrono::Duration,
+    /// Whether to create trigram indexes for `String` attributes when a
+    /// subgraph is deployed. They speed up `_contains`, `_like`, `_ilike`
+    /// and the other pattern-matching filters, but make writes more
+    /// expensive.
+    ///
+    /// Set by the flag `GRAPH_STORE_TRIGRAM_INDEXES`. Disabled by default.
+    pub trigram_indexes: bool,
+    /// Where to publish entity changes. `nats://<host>:<port>/<subject>`
+    /// publishes to a NATS subject, and `http(s)://<host>/topics/<topic>`
+    /// to a Kafka topic through the Kafka REST proxy at that URL.
+    ///
+    /// Set by the environment variable `GRAPH_STORE_CHANGE_FEED_URL`. No
+    /// default value is provided, and entity changes are not published.
+    pub change_feed_url: Option<String>,
+    /// How many entity changes to buffer for the change feed while the
+    /// publisher is falling behind. Once the buffer is full, changes are
+    /// dropped.
+    ///
+    /// Set by the environment variable `GRAPH_STORE_CHANGE_FEED_BUFFER`. The
+    /// default value is 100000.
+    pub change_feed_buffer: usize,
+    /// Whether entity changes should also be recorded in an append-only
+    /// table `changes$` in each deployment's schema, which consumers of
+    /// logical replication like Debezium can stream from.
+    ///
+    /// Set by the environment variable `GRAPH_STORE_CDC_TABLES`. Off by
+    /// default.
+    pub cdc_tables: bool,
+    /// How many blocks of changes to keep in the `changes$` tables. Changes
+    /// for blocks further behind the deployment head than this are removed
+    /// periodically.
+    ///
+    /// Set by the environment variable `GRAPH_STORE_CDC_RETENTION_BLOCKS`.
+    /// No default value is provided, and changes are kept forever.
+    pub cdc_retention_blocks: Option<i32>,
+    /// How many blocks of `eth_call` results to keep in the call cache of
+    /// each chain. Calls made at blocks further behind the chain head than
+    /// this are removed periodically.
+    ///
+    /// Set by the environment variable `GRAPH_CALL_CACHE_RETENTION_BLOCKS`.
+    /// No default value is provided, and calls are kept forever.
+    pub call_cache_retention_blocks: Option<i32>,
+    /// How many calls to keep at most in the call cache of each chain. When
+    /// there are more, the calls made at the oldest blocks are removed
+    /// periodically.
+    ///
+    /// Set by the environment variable `GRAPH_CALL_CACHE_MAX_CALLS`. No
+    /// default value is provided, and the call cache is not limited in size.
+    pub call_cache_max_calls: Option<usize>,
+    /// HTTP(S) URLs that are notified of lifecycle events of deployments.
+    ///
+    /// Set by the environment variable `GRAPH_LIFECYCLE_WEBHOOK_URLS` as a
+    /// comma-separated list. No default value is provided, and no webhooks
+    /// are notified.
+    pub lifecycle_webhook_urls: Vec<String>,
+    /// Which lifecycle events to send to webhooks.
+    ///
+    /// Set by the environment variable `GRAPH_LIFECYCLE_WEBHOOK_EVENTS` as a
+    /// comma-separated list. The default value is
+    /// `healthy,failed,synced,reassigned`.
+    pub lifecycle_webhook_events: Vec<String>,
+    /// How many log messages from mappings are kept for each deployment so
+    /// that they can be queried through the index-node API. A value of 0
+    /// turns storing mapping logs off.
+    ///
+    /// Set by the environment variable `GRAPH_STORE_MAPPING_LOG_LIMIT`. The
+    /// default value is 1000.
+    pub mapping_log_limit: usize,
+    /// The smallest number of blocks of history that a deployment that
+    /// prunes its history keeps. Deployments that use the indexer hint
+    /// `prune: auto` keep exactly this many blocks. The value needs to be
+    /// larger than the reorg threshold so that reverts never need pruned
+    /// data.
+    ///
+    /// Set by the environment variable `GRAPH_MIN_HISTORY_BLOCKS`. The
+    /// default value is 500.
+    pub min_history_blocks: i32,
+    /// How many entity versions the pruner deletes from a table in one
+    /// statement. Each batch is its own transaction so that pruning never
+    /// holds locks for long.
+    ///
+    /// Set by the environment variable `GRAPH_STORE_PRUNE_BATCH_SIZE`. The
+    /// default value is 10000.
+    pub prune_batch_size: i64,
+    /// How often to check entity tables for dead tuples and vacuum or
+    /// analyze the ones that autovacuum has fallen behind on. A value of 0
+    /// turns these checks off.
+    ///
+    /// Set by the environment variable `GRAPH_STORE_VACUUM_INTERVAL`
+    /// (expressed in seconds). The default value is 300s.
+    pub vacuum_interval: Option<Duration>,
+    /// The share of dead tuples in an entity table above which the table
+    /// gets vacuumed.
+    ///
+    /// Set by the environment variable `GRAPH_STORE_VACUUM_DEAD_RATIO`. The
+    /// default value is 0.2.
+    pub vacuum_dead_ratio: f64,
+    /// Tables that had more rows inserted, updated or deleted per second
+    /// than this since the last check are left alone until they are
+    /// quieter.
+    ///
+    /// Set by the environment variable `GRAPH_STORE_VACUUM_MAX_WRITE_RATE`.
+    /// The default value is 100.
+    pub vacuum_max_write_rate: f64,
+    /// How many tables in each shard to vacuum or analyze at most for each
+    /// check.
+    ///
+    /// Set by the environment variable `GRAPH_STORE_VACUUM_MAX_TABLES`. The
+    /// default value is 5.
+    pub vacuum_max_tables: usize,
+    /// If set, the tables for immutable entity types of newly created
+    /// deployments are partitioned by block number, with each partition
+    /// holding the entities created in this many blocks. A value of 0
+    /// turns partitioning off.
+    ///
+    /// Set by the environment variable `GRAPH_STORE_PARTITION_BLOCKS`. The
+    /// default value is 0.
+    pub partition_blocks: Option<i32>,
+    /// Whether to leave out the indexes on entity attributes when creating
+    /// new deployments, and build them only once the deployment has caught
+    /// up with the chain head. This makes the initial sync faster since
+    /// writes do not have to maintain these indexes. Indexes are always
+    /// deferred when `GRAPH_STORE_BULK_WRITE_DISTANCE` is set.
+    ///
+    /// Set by the environment variable `GRAPH_STORE_DEFER_INDEXES`. Off by
+    /// default.
+    pub defer_indexes: bool,
+    /// When a deployment is more than this many blocks behind the chain
+    /// head, entities are written with `COPY` instead of `INSERT`, which
+    /// is faster for the large numbers of entities written during the
+    /// initial sync. A value of 0 always uses `INSERT`.
+    ///
+    /// Set by the environment variable `GRAPH_STORE_BULK_WRITE_DISTANCE`.
+    /// The default value is 0.
+    pub bulk_write_distance: Option<i32>,
+    /// How many connections for writing entities with `COPY` each
+    /// connection pool may open.
+    ///
+    /// Set by the environment variable `GRAPH_STORE_BULK_WRITE_POOL_SIZE`.
+    /// The default value is 5.
+    pub bulk_write_pool_size: u32,
+    /// How many processed blocks may wait to be written to the store while
+    /// indexing continues with the next block. A value of 0 writes each
+    /// block before processing the next one.
+    ///
+    /// Set by the environment variable `GRAPH_STORE_WRITE_QUEUE`. The
+    /// default value is 0.
+    pub write_queue_size: usize,
+    /// How many blocks a read replica's copy of a deployment may lag
+    /// behind the main database before queries for that deployment are
+    /// sent to the main database instead of the replica. When this is not
+    /// set, replicas are used regardless of how far behind they are.
+    ///
+    /// Set by the environment variable `GRAPH_STORE_REPLICA_MAX_LAG`. Not
+    /// set by default.
+    pub replica_max_lag: Option<i32>,
+    /// For connection pools whose size has a minimum and a maximum, how
+    /// long queries may wait on average for a database connection before
+    /// the number of queries that can run concurrently is increased.
+    ///
+    /// Set by the environment variable `GRAPH_STORE_POOL_GROW_WAIT`
+    /// (expressed in milliseconds). The default value is 20ms.
+    pub pool_grow_wait: Duration,
 
     // These should really be set through the configuration file, especially for
     // `GRAPH_STORE_CONNECTION_MIN_IDLE` and
@@ -129,6 +292,38 @@ impl From<InnerStore> for EnvVarsStore {
             remove_unused_interval: chrono::Duration::minutes(
                 x.remove_unused_interval_in_minutes as i64,
             ),
+            trigram_indexes: x.trigram_indexes.0,
+            change_feed_url: x.change_feed_url,
+            change_feed_buffer: x.change_feed_buffer,
+            cdc_tables: x.cdc_tables.0,
+            cdc_retention_blocks: x.cdc_retention_blocks,
+            call_cache_retention_blocks: x.call_cache_retention_blocks,
+            call_cache_max_calls: x.call_cache_max_calls,
+            lifecycle_webhook_urls: comma_list(&x.lifecycle_webhook_urls),
+            lifecycle_webhook_events: comma_list(&x.lifecycle_webhook_events),
+            mapping_log_limit: x.mapping_log_limit,
+            min_history_blocks: x.min_history_blocks,
+            prune_batch_size: x.prune_batch_size,
+            vacuum_interval: match x.vacuum_interval_in_secs {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            vacuum_dead_ratio: x.vacuum_dead_ratio,
+            vacuum_max_write_rate: x.vacuum_max_write_rate,
+            vacuum_max_tables: x.vacuum_max_tables,
+            defer_indexes: x.defer_indexes.0 || x.bulk_write_distance > 0,
+            bulk_write_distance: match x.bulk_write_distance {
+                0 => None,
+                distance => Some(distance),
+            },
+            bulk_write_pool_size: x.bulk_write_pool_size,
+            write_queue_size: x.write_queue_size,
+            replica_max_lag: x.replica_max_lag,
+            pool_grow_wait: Duration::from_millis(x.pool_grow_wait_in_millis),
+            partition_blocks: match x.partition_blocks {
+                0 => None,
+                blocks => Some(blocks),
+            },
             connection_timeout: Duration::from_millis(x.connection_timeout_in_millis),
             connection_min_idle: x.connection_min_idle,
             connection_idle_timeout: Duration::from_secs(x.connection_idle_timeout_in_secs),
@@ -164,6 +359,55 @@ pub struct InnerStore {
     connection_try_always: EnvVarBoolean,
     #[envconfig(from = "GRAPH_REMOVE_UNUSED_INTERVAL", default = "360")]
     remove_unused_interval_in_minutes: u64,
+    #[envconfig(from = "GRAPH_STORE_TRIGRAM_INDEXES", default = "false")]
+    trigram_indexes: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_STORE_CHANGE_FEED_URL")]
+    change_feed_url: Option<String>,
+    #[envconfig(from = "GRAPH_STORE_CHANGE_FEED_BUFFER", default = "100000")]
+    change_feed_buffer: usize,
+    #[envconfig(from = "GRAPH_STORE_CDC_TABLES", default = "false")]
+    cdc_tables: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_STORE_CDC_RETENTION_BLOCKS")]
+    cdc_retention_blocks: Option<i32>,
+    #[envconfig(from = "GRAPH_CALL_CACHE_RETENTION_BLOCKS")]
+    call_cache_retention_blocks: Option<i32>,
+    #[envconfig(from = "GRAPH_CALL_CACHE_MAX_CALLS")]
+    call_cache_max_calls: Option<usize>,
+    #[envconfig(from = "GRAPH_LIFECYCLE_WEBHOOK_URLS", default = "")]
+    lifecycle_webhook_urls: String,
+    #[envconfig(
+        from = "GRAPH_LIFECYCLE_WEBHOOK_EVENTS",
+        default = "healthy,failed,synced,reassigned"
+    )]
+    lifecycle_webhook_events: String,
+    #[envconfig(from = "GRAPH_STORE_MAPPING_LOG_LIMIT", default = "1000")]
+    mapping_log_limit: usize,
+    #[envconfig(from = "GRAPH_MIN_HISTORY_BLOCKS", default = "500")]
+    min_history_blocks: i32,
+    #[envconfig(from = "GRAPH_STORE_PRUNE_BATCH_SIZE", default = "10000")]
+    prune_batch_size: i64,
+    #[envconfig(from = "GRAPH_STORE_VACUUM_INTERVAL", default = "300")]
+    vacuum_interval_in_secs: u64,
+    #[envconfig(from = "GRAPH_STORE_VACUUM_DEAD_RATIO", default = "0.2")]
+    vacuum_dead_ratio: f64,
+    #[envconfig(from = "GRAPH_STORE_VACUUM_MAX_WRITE_RATE", default = "100")]
+    vacuum_max_write_rate: f64,
+    #[envconfig(from = "GRAPH_STORE_VACUUM_MAX_TABLES", default = "5")]
+    vacuum_max_tables: usize,
+    #[envconfig(from = "GRAPH_STORE_PARTITION_BLOCKS", default = "0")]
+    partition_blocks: i32,
+    #[envconfig(from = "GRAPH_STORE_DEFER_INDEXES", default = "false")]
+    defer_indexes: EnvVarBoolean,
+    #[envconfig(from = "GRAPH_STORE_BULK_WRITE_DISTANCE", default = "0")]
+    bulk_write_distance: i32,
+    #[envconfig(from = "GRAPH_STORE_BULK_WRITE_POOL_SIZE", default = "5")]
+    bulk_write_pool_size: u32,
+    #[envconfig(from = "GRAPH_STORE_WRITE_QUEUE", default = "0")]
+    write_queue_size: usize,
+    #[envconfig(from = "GRAPH_STORE_REPLICA_MAX_LAG")]
+    replica_max_lag: Option<i32>,
+    #[envconfig(from = "GRAPH_STORE_POOL_GROW_WAIT", default = "20")]
+    pool_grow_wait_in_millis: u64,
 
     // These should really be set through the configuration file, especially for
     // `GRAPH_STORE_CONNECTION_MIN_IDLE` and
@@ -176,3 +420,13 @@ pub struct InnerStore {
     #[envconfig(from = "GRAPH_STORE_CONNECTION_IDLE_TIMEOUT", default = "600")]
     connection_idle_timeout_in_secs: u64,
 }
+
+/// Split a comma-separated list, ignoring surrounding whitespace and empty
+/// entries
+fn comma_list(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
@@ -1,17 +1,22 @@
 use crate::{
     blockchain::Block as BlockchainBlock,
-    blockchain::BlockPtr,
+    blockchain::{BlockHash, BlockPtr},
     cheap_clone::CheapClone,
     components::store::BlockNumber,
     firehose::{decode_firehose_block, ForkStep},
-    prelude::{debug, info},
+    prelude::{debug, info, warn},
 };
 use anyhow::Context;
-use futures03::StreamExt;
+use futures03::{future::join_all, StreamExt};
 use http::uri::{Scheme, Uri};
-use rand::prelude::IteratorRandom;
+use rand::Rng;
 use slog::Logger;
-use std::{collections::BTreeMap, fmt::Display, sync::Arc};
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
 use tonic::{
     metadata::MetadataValue,
     transport::{Channel, ClientTlsConfig},
@@ -20,6 +25,55 @@ use tonic::{
 
 use super::codec as firehose;
 
+/// How much weight a new latency measurement gets in the moving average
+const LATENCY_WEIGHT: f64 = 0.2;
+
+/// An endpoint that failed `n` times in a row is not used for
+/// `min(5s * 2^(n-1), MAX_BACKOFF)`, unless no other endpoint is usable
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// An endpoint that failed this many times in a row counts as down until a
+/// request to it succeeds again
+const FAILURES_UNTIL_DOWN: u32 = 3;
+
+/// What we know about how well an endpoint has been working recently
+#[derive(Debug, Default)]
+struct EndpointHealth {
+    /// Exponentially weighted moving average of the time it took the
+    /// endpoint to answer requests, in milliseconds. `None` until the
+    /// first request succeeded
+    latency_ms: Option<f64>,
+    /// The number of requests that failed since the last one that
+    /// succeeded
+    failures: u32,
+    last_failure: Option<Instant>,
+}
+
+impl EndpointHealth {
+    /// The time after the last failure until which the endpoint should not
+    /// be used
+    fn retry_at(&self) -> Option<Instant> {
+        if self.failures == 0 {
+            return None;
+        }
+        let backoff = Duration::from_secs(5)
+            .checked_mul(1u32 << (self.failures - 1).min(16))
+            .map_or(MAX_BACKOFF, |backoff| backoff.min(MAX_BACKOFF));
+        self.last_failure.map(|last| last + backoff)
+    }
+}
+
+/// Settings for the gRPC connection to a Firehose endpoint
+#[derive(Clone, Debug, Default)]
+pub struct GrpcSettings {
+    /// How often to send HTTP/2 pings to keep the connection alive, for
+    /// example through load balancers that close idle connections
+    pub keepalive_interval: Option<Duration>,
+    /// How long to wait for the answer to a ping before the connection is
+    /// considered broken
+    pub keepalive_timeout: Option<Duration>,
+}
+
 #[derive(Clone, Debug)]
 pub struct FirehoseEndpoint {
     pub provider: String,
@@ -27,6 +81,7 @@ pub struct FirehoseEndpoint {
     pub token: Option<String>,
     pub filters_enabled: bool,
     channel: Channel,
+    health: Arc<Mutex<EndpointHealth>>,
     _logger: Logger,
 }
 
@@ -43,6 +98,7 @@ impl FirehoseEndpoint {
         url: S,
         token: Option<String>,
         filters_enabled: bool,
+        grpc: GrpcSettings,
     ) -> Result<Self, anyhow::Error> {
         let uri = url
             .as_ref()
@@ -56,6 +112,14 @@ impl FirehoseEndpoint {
                 .expect("TLS config on this host is invalid"),
             _ => panic!("invalid uri scheme for firehose endpoint"),
         };
+        let endpoint = match grpc.keepalive_interval {
+            Some(interval) => endpoint.http2_keep_alive_interval(interval),
+            None => endpoint,
+        };
+        let endpoint = match grpc.keepalive_timeout {
+            Some(timeout) => endpoint.keep_alive_timeout(timeout),
+            None => endpoint,
+        };
 
         let uri = endpoint.uri().to_string();
         let channel = endpoint.connect_lazy().with_context(|| {
@@ -71,11 +135,109 @@ impl FirehoseEndpoint {
             uri,
             channel,
             token,
+            health: Arc::new(Mutex::new(EndpointHealth::default())),
             _logger: logger,
             filters_enabled,
         })
     }
 
+    /// Record that the endpoint answered a request after `latency`
+    pub fn record_success(&self, latency: Duration) {
+        let mut health = self.health.lock().unwrap();
+        let latency = latency.as_secs_f64() * 1000.0;
+        health.latency_ms = Some(match health.latency_ms {
+            Some(avg) => avg + LATENCY_WEIGHT * (latency - avg),
+            None => latency,
+        });
+        health.failures = 0;
+    }
+
+    /// Record that a request to the endpoint failed. After repeated
+    /// failures, the endpoint is avoided for an increasing amount of time
+    pub fn record_failure(&self) {
+        let mut health = self.health.lock().unwrap();
+        health.failures += 1;
+        health.last_failure = Some(Instant::now());
+    }
+
+    /// Whether the endpoint should be used, i.e., whether it has not
+    /// failed recently
+    pub fn is_healthy(&self) -> bool {
+        self.health
+            .lock()
+            .unwrap()
+            .retry_at()
+            .map_or(true, |retry_at| retry_at <= Instant::now())
+    }
+
+    /// Check that the endpoint is working by asking it for the chain head
+    /// and waiting up to `timeout` for it. The outcome is recorded in the
+    /// health of the endpoint
+    pub async fn check(self: Arc<Self>, timeout: Duration) -> Result<(), anyhow::Error> {
+        let request = firehose::Request {
+            start_block_num: -1,
+            fork_steps: vec![ForkStep::StepNew as i32],
+            ..Default::default()
+        };
+        let check = async {
+            // `stream_blocks` records the outcome of connecting itself
+            let mut stream = self.cheap_clone().stream_blocks(request).await?;
+            let result = match stream.next().await {
+                Some(Ok(_)) => return Ok(()),
+                Some(Err(e)) => Err(anyhow::format_err!("firehose error {}", e)),
+                None => Err(anyhow::format_err!("firehose did not return any block")),
+            };
+            self.record_failure();
+            result
+        };
+        match tokio::time::timeout(timeout, check).await {
+            Ok(result) => result,
+            Err(_) => {
+                self.record_failure();
+                Err(anyhow::format_err!(
+                    "firehose did not return a block within {}s",
+                    timeout.as_secs()
+                ))
+            }
+        }
+    }
+
+    /// Send `request` to the endpoint and record in the health of the
+    /// endpoint how long it took for it to accept the request, or that it
+    /// failed
+    async fn blocks(
+        &self,
+        request: firehose::Request,
+    ) -> Result<tonic::Streaming<firehose::Response>, anyhow::Error> {
+        let token_metadata = match self.token.clone() {
+            Some(token) => Some(MetadataValue::from_str(token.as_str())?),
+            None => None,
+        };
+
+        let mut client = firehose::stream_client::StreamClient::with_interceptor(
+            self.channel.cheap_clone(),
+            move |mut r: Request<()>| {
+                if let Some(ref t) = token_metadata {
+                    r.metadata_mut().insert("authorization", t.clone());
+                }
+
+                Ok(r)
+            },
+        );
+
+        let start = Instant::now();
+        match client.blocks(request).await {
+            Ok(response) => {
+                self.record_success(start.elapsed());
+                Ok(response.into_inner())
+            }
+            Err(e) => {
+                self.record_failure();
+                Err(e.into())
+            }
+        }
+    }
+
     pub async fn genesis_block_ptr<M>(&self, logger: &Logger) -> Result<BlockPtr, anyhow::Error>
     where
         M: prost::Message + BlockchainBlock + Default + 'static,
@@ -97,22 +259,6 @@ impl FirehoseEndpoint {
     where
         M: prost::Message + BlockchainBlock + Default + 'static,
     {
-        let token_metadata = match self.token.clone() {
-            Some(token) => Some(MetadataValue::from_str(token.as_str())?),
-            None => None,
-        };
-
-        let mut client = firehose::stream_client::StreamClient::with_interceptor(
-            self.channel.cheap_clone(),
-            move |mut r: Request<()>| {
-                if let Some(ref t) = token_metadata {
-                    r.metadata_mut().insert("authorization", t.clone());
-                }
-
-                Ok(r)
-            },
-        );
-
         debug!(
             logger,
             "Connecting to firehose to retrieve block for number {}", number
@@ -130,7 +276,7 @@ impl FirehoseEndpoint {
         // That way, we either get the final block if the block is now in a final segment of the
         // chain (or probabilisticly if not finality concept exists for the chain). Or we get the
         // block that is in the longuest chain according to Firehose.
-        let response_stream = client
+        let mut block_stream = self
             .blocks(firehose::Request {
                 start_block_num: number as i64,
                 stop_block_num: number as u64,
@@ -139,8 +285,6 @@ impl FirehoseEndpoint {
             })
             .await?;
 
-        let mut block_stream = response_stream.into_inner();
-
         debug!(logger, "Retrieving block(s) from firehose");
 
         let mut latest_received_block: Option<BlockPtr> = None;
@@ -170,7 +314,10 @@ impl FirehoseEndpoint {
                         }
                     }
                 }
-                Err(e) => return Err(anyhow::format_err!("firehose error {}", e)),
+                Err(e) => {
+                    self.record_failure();
+                    return Err(anyhow::format_err!("firehose error {}", e));
+                }
             };
         }
 
@@ -182,52 +329,151 @@ impl FirehoseEndpoint {
         }
     }
 
-    pub async fn stream_blocks(
-        self: Arc<Self>,
-        request: firehose::Request,
-    ) -> Result<tonic::Streaming<firehose::Response>, anyhow::Error> {
-        let token_metadata = match self.token.clone() {
-            Some(token) => Some(MetadataValue::from_str(token.as_str())?),
-            None => None,
-        };
+    /// Look for the block with the given `hash` among the last `depth`
+    /// blocks of the chain. Firehose has no way to look blocks up by their
+    /// hash, and we therefore walk the most recent blocks, starting
+    /// `depth` blocks behind the chain head. Return `None` if none of these
+    /// blocks has the given hash.
+    pub async fn block_for_hash<M>(
+        &self,
+        logger: &Logger,
+        hash: &BlockHash,
+        depth: BlockNumber,
+    ) -> Result<Option<Arc<dyn BlockchainBlock>>, anyhow::Error>
+    where
+        M: prost::Message + BlockchainBlock + Default + 'static,
+    {
+        debug!(
+            logger,
+            "Connecting to firehose to look for block with hash {} in the last {} blocks",
+            hash,
+            depth
+        );
 
-        let mut client = firehose::stream_client::StreamClient::with_interceptor(
-            self.channel.cheap_clone(),
-            move |mut r: Request<()>| {
-                if let Some(ref t) = token_metadata {
-                    r.metadata_mut().insert("authorization", t.clone());
-                }
+        // A negative start block is resolved relative to the chain head.
+        // Since we do not set a stop block, the stream would go on forever
+        // and we stop it ourselves once we have seen `depth` blocks
+        let mut block_stream = self
+            .blocks(firehose::Request {
+                start_block_num: -(depth as i64),
+                fork_steps: vec![ForkStep::StepNew as i32],
+                ..Default::default()
+            })
+            .await?;
 
-                Ok(r)
-            },
-        );
+        let mut seen: BlockNumber = 0;
+        while let Some(message) = block_stream.next().await {
+            match message {
+                Ok(v) => {
+                    let block = decode_firehose_block::<M>(&v)?;
+                    if &block.hash() == hash {
+                        return Ok(Some(block));
+                    }
+                    seen += 1;
+                    if seen > depth {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    self.record_failure();
+                    return Err(anyhow::format_err!("firehose error {}", e));
+                }
+            };
+        }
 
-        let response_stream = client.blocks(request).await?;
-        let block_stream = response_stream.into_inner();
+        Ok(None)
+    }
 
-        Ok(block_stream)
+    pub async fn stream_blocks(
+        self: Arc<Self>,
+        request: firehose::Request,
+    ) -> Result<tonic::Streaming<firehose::Response>, anyhow::Error> {
+        self.blocks(request).await
     }
 }
+
+/// The endpoints for one network. Clones of this struct share the list of
+/// endpoints so that endpoints can be added and removed while the node is
+/// running.
 #[derive(Clone, Debug)]
-pub struct FirehoseEndpoints(Vec<Arc<FirehoseEndpoint>>);
+pub struct FirehoseEndpoints(Arc<RwLock<Vec<Arc<FirehoseEndpoint>>>>);
 
 impl FirehoseEndpoints {
     pub fn new() -> Self {
-        Self(vec![])
+        Self(Arc::new(RwLock::new(vec![])))
     }
 
     pub fn len(&self) -> usize {
-        self.0.len()
+        self.0.read().unwrap().len()
     }
 
-    pub fn random(&self) -> Option<&Arc<FirehoseEndpoint>> {
-        // Select from the matching adapters randomly
-        let mut rng = rand::thread_rng();
-        self.0.iter().choose(&mut rng)
+    /// Pick an endpoint to send a request to. Endpoints that failed
+    /// recently are skipped, and among the others, endpoints are picked
+    /// randomly with a probability that is inversely proportional to their
+    /// latency. If all endpoints failed recently, use the one that will be
+    /// retried first
+    pub fn endpoint(&self) -> Option<Arc<FirehoseEndpoint>> {
+        let endpoints = self.0.read().unwrap();
+
+        let healthy: Vec<_> = endpoints
+            .iter()
+            .filter(|endpoint| endpoint.is_healthy())
+            .collect();
+        if healthy.is_empty() {
+            return endpoints
+                .iter()
+                .min_by_key(|endpoint| endpoint.health.lock().unwrap().retry_at())
+                .cloned();
+        }
+
+        // Endpoints that have not been used yet get the weight of an
+        // endpoint with a latency of 1ms so that they get tried soon
+        let weights: Vec<f64> = healthy
+            .iter()
+            .map(|endpoint| {
+                let latency = endpoint.health.lock().unwrap().latency_ms.unwrap_or(0.0);
+                1.0 / latency.max(1.0)
+            })
+            .collect();
+        let mut pick = rand::thread_rng().gen::<f64>() * weights.iter().sum::<f64>();
+        for (endpoint, weight) in healthy.iter().zip(weights) {
+            if pick < weight {
+                return Some((*endpoint).clone());
+            }
+            pick -= weight;
+        }
+        healthy.last().map(|endpoint| (*endpoint).clone())
+    }
+
+    /// Whether all endpoints are down, i.e., have failed repeatedly and
+    /// not answered a request since. Health checks keep testing endpoints
+    /// that are down, and they stop being down as soon as one succeeds
+    pub fn is_down(&self) -> bool {
+        let endpoints = self.0.read().unwrap();
+        !endpoints.is_empty()
+            && endpoints
+                .iter()
+                .all(|endpoint| endpoint.health.lock().unwrap().failures >= FAILURES_UNTIL_DOWN)
     }
 
-    pub fn remove(&mut self, provider: &str) {
+    /// The labels of all providers for this network
+    pub fn providers(&self) -> Vec<String> {
         self.0
+            .read()
+            .unwrap()
+            .iter()
+            .map(|endpoint| endpoint.provider.clone())
+            .collect()
+    }
+
+    pub fn insert(&self, endpoint: Arc<FirehoseEndpoint>) {
+        self.0.write().unwrap().push(endpoint);
+    }
+
+    pub fn remove(&self, provider: &str) {
+        self.0
+            .write()
+            .unwrap()
             .retain(|network_endpoint| network_endpoint.provider != provider);
     }
 }
@@ -252,11 +498,11 @@ impl FirehoseNetworks {
             .entry(chain_id)
             .or_insert_with(FirehoseEndpoints::new);
 
-        endpoints.0.push(endpoint);
+        endpoints.insert(endpoint);
     }
 
-    pub fn remove(&mut self, chain_id: &str, provider: &str) {
-        if let Some(endpoints) = self.networks.get_mut(chain_id) {
+    pub fn remove(&self, chain_id: &str, provider: &str) {
+        if let Some(endpoints) = self.networks.get(chain_id) {
             endpoints.remove(provider);
         }
     }
@@ -271,9 +517,37 @@ impl FirehoseNetworks {
             .flat_map(|(chain_id, firehose_endpoints)| {
                 firehose_endpoints
                     .0
+                    .read()
+                    .unwrap()
                     .iter()
                     .map(move |endpoint| (chain_id.clone(), endpoint.clone()))
+                    .collect::<Vec<_>>()
             })
             .collect()
     }
+
+    /// Check the health of all endpoints every `interval` in the
+    /// background so that endpoints that stopped working are avoided, and
+    /// endpoints that work again are used again, even when there is no
+    /// traffic that would notice that
+    pub fn start_health_checks(&self, logger: Logger, interval: Duration) {
+        let networks = self.clone();
+        crate::spawn(async move {
+            loop {
+                let checks = networks.flatten().into_iter().map(|(chain_id, endpoint)| {
+                    let logger = logger.clone();
+                    async move {
+                        if let Err(e) = endpoint.cheap_clone().check(interval).await {
+                            warn!(logger, "Firehose endpoint failed health check";
+                                          "network_name" => chain_id,
+                                          "provider" => &endpoint.provider,
+                                          "error" => format!("{:#}", e));
+                        }
+                    }
+                });
+                join_all(checks).await;
+                tokio::time::sleep(interval).await;
+            }
+        });
+    }
 }
@@ -114,12 +114,13 @@ pub mod prelude {
     pub use crate::components::server::query::GraphQLServer;
     pub use crate::components::server::subscription::SubscriptionServer;
     pub use crate::components::store::{
-        AttributeNames, BlockNumber, CachedEthereumCall, ChainStore, ChildMultiplicity,
-        EntityCache, EntityChange, EntityChangeOperation, EntityCollection, EntityFilter,
-        EntityKey, EntityLink, EntityModification, EntityOperation, EntityOrder, EntityQuery,
-        EntityRange, EntityWindow, EthereumCallCache, ParentLink, PoolWaitStats, QueryStore,
-        QueryStoreManager, StoreError, StoreEvent, StoreEventStream, StoreEventStreamBox,
-        SubgraphStore, UnfailOutcome, WindowAttribute, BLOCK_NUMBER_MAX,
+        AggregateFunction, AttributeNames, BlockNumber, CachedEthereumCall, ChainStore,
+        ChildMultiplicity, EntityAggregate, EntityCache, EntityChange, EntityChangeOperation,
+        EntityCollection, EntityFilter, EntityKey, EntityLink, EntityModification, EntityOperation,
+        EntityOrder, EntityOrderByChild, EntityQuery, EntityRange, EntityWindow, EthereumCallCache,
+        ParentLink, PoolWaitStats, QueryStore, QueryStoreManager, StoreError, StoreEvent,
+        StoreEventStream, StoreEventStreamBox, SubgraphStore, UnfailOutcome, WindowAttribute,
+        BLOCK_NUMBER_MAX,
     };
     pub use crate::components::subgraph::{
         BlockState, DataSourceTemplateInfo, HostMetrics, RuntimeHost, RuntimeHostBuilder,
@@ -140,8 +141,8 @@ pub mod prelude {
     pub use crate::data::store::ethereum::*;
     pub use crate::data::store::scalar::{BigDecimal, BigInt, BigIntSign};
     pub use crate::data::store::{
-        AssignmentEvent, Attribute, Entity, NodeId, SubscriptionFilter, ToEntityId, ToEntityKey,
-        TryIntoEntity, Value, ValueType,
+        AssignmentEvent, Attribute, Entity, NodeId, NodeRole, SubscriptionFilter, ToEntityId,
+        ToEntityKey, TryIntoEntity, Value, ValueType,
     };
     pub use crate::data::subgraph::schema::SubgraphDeploymentEntity;
     pub use crate::data::subgraph::{
@@ -151,7 +152,7 @@ pub mod prelude {
         UnvalidatedSubgraphManifest,
     };
     pub use crate::data::subscription::{
-        QueryResultStream, Subscription, SubscriptionError, SubscriptionResult,
+        QueryResultStream, Subscription, SubscriptionError, SubscriptionFilter, SubscriptionResult,
     };
     pub use crate::ext::futures::{
         CancelGuard, CancelHandle, CancelToken, CancelableError, FutureExtension,
@@ -159,10 +160,13 @@ pub mod prelude {
     };
     pub use crate::impl_slog_value;
     pub use crate::log::codes::LogCode;
-    pub use crate::log::elastic::{elastic_logger, ElasticDrainConfig, ElasticLoggingConfig};
+    pub use crate::log::elastic::{
+        elastic_logger, ElasticDrainConfig, ElasticLogStore, ElasticLoggingConfig,
+    };
     pub use crate::log::factory::{
-        ComponentLoggerConfig, ElasticComponentLoggerConfig, LoggerFactory,
+        ComponentLoggerConfig, ElasticComponentLoggerConfig, LogSource, LogStore, LoggerFactory,
     };
+    pub use crate::log::loki::{loki_logger, LokiDrainConfig, LokiLogStore, LokiLoggingConfig};
     pub use crate::log::split::split_logger;
     pub use crate::util::cache_weight::CacheWeight;
     pub use crate::util::futures::{retry, TimeoutError};
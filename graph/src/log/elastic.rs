@@ -16,6 +16,8 @@ use serde_json::json;
 use slog::*;
 use slog_async;
 
+use crate::log::factory::{LogSource, LogStore};
+use crate::prelude::ENV_VARS;
 use crate::util::futures::retry;
 
 /// General configuration parameters for Elasticsearch logging.
@@ -384,3 +386,31 @@ pub fn elastic_logger(config: ElasticDrainConfig, error_logger: Logger) -> Logge
         .fuse();
     Logger::root(async_drain, o!())
 }
+
+/// Sends component and subgraph logs to Elasticsearch
+pub struct ElasticLogStore {
+    config: ElasticLoggingConfig,
+}
+
+impl ElasticLogStore {
+    pub fn new(config: ElasticLoggingConfig) -> Self {
+        ElasticLogStore { config }
+    }
+}
+
+impl LogStore for ElasticLogStore {
+    fn logger(&self, source: LogSource, error_logger: Logger) -> Logger {
+        elastic_logger(
+            ElasticDrainConfig {
+                general: self.config.clone(),
+                index: source.index,
+                document_type: String::from("log"),
+                custom_id_key: source.id_key,
+                custom_id_value: source.id,
+                flush_interval: ENV_VARS.elastic_search_flush_interval,
+                max_retries: ENV_VARS.elastic_search_max_retries,
+            },
+            error_logger,
+        )
+    }
+}
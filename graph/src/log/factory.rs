@@ -1,11 +1,12 @@
+use std::sync::Arc;
+
 use slog::*;
 
 use crate::components::store::DeploymentLocator;
-use crate::log::elastic::*;
 use crate::log::split::*;
-use crate::prelude::ENV_VARS;
 
-/// Configuration for component-specific logging to Elasticsearch.
+/// Configuration for component-specific logging to a log store. The index
+/// is the Elasticsearch index, or the value of the `index` label in Loki.
 pub struct ElasticComponentLoggerConfig {
     pub index: String,
 }
@@ -15,19 +16,39 @@ pub struct ComponentLoggerConfig {
     pub elastic: Option<ElasticComponentLoggerConfig>,
 }
 
+/// Identifies where the logs sent to a `LogStore` come from.
+#[derive(Clone, Debug)]
+pub struct LogSource {
+    /// The index, or group of sources, that the logs belong to, e.g.
+    /// `subgraph-logs`
+    pub index: String,
+    /// The name of the key that identifies the source, e.g. `subgraphId`
+    pub id_key: String,
+    /// The value that identifies the source, e.g. the deployment hash
+    pub id: String,
+}
+
+/// A service that stores logs so that they can be queried per component
+/// or subgraph, e.g. Elasticsearch or Loki.
+pub trait LogStore: Send + Sync + 'static {
+    /// Creates a logger that sends logs from `source` to the store. Errors
+    /// while sending logs are reported through `error_logger`.
+    fn logger(&self, source: LogSource, error_logger: Logger) -> Logger;
+}
+
 /// Factory for creating component and subgraph loggers.
 #[derive(Clone)]
 pub struct LoggerFactory {
     parent: Logger,
-    elastic_config: Option<ElasticLoggingConfig>,
+    log_store: Option<Arc<dyn LogStore>>,
 }
 
 impl LoggerFactory {
-    /// Creates a new factory using a parent logger and optional Elasticsearch configuration.
-    pub fn new(logger: Logger, elastic_config: Option<ElasticLoggingConfig>) -> Self {
+    /// Creates a new factory using a parent logger and an optional log store.
+    pub fn new(logger: Logger, log_store: Option<Arc<dyn LogStore>>) -> Self {
         Self {
             parent: logger,
-            elastic_config,
+            log_store,
         }
     }
 
@@ -35,11 +56,11 @@ impl LoggerFactory {
     pub fn with_parent(&self, parent: Logger) -> Self {
         Self {
             parent,
-            elastic_config: self.elastic_config.clone(),
+            log_store: self.log_store.clone(),
         }
     }
 
-    /// Creates a component-specific logger with optional Elasticsearch support.
+    /// Creates a component-specific logger with optional log store support.
     pub fn component_logger(
         &self,
         component: &str,
@@ -47,60 +68,41 @@ impl LoggerFactory {
     ) -> Logger {
         let term_logger = self.parent.new(o!("component" => component.to_string()));
 
-        match config {
-            None => term_logger,
-            Some(config) => match config.elastic {
-                None => term_logger,
-                Some(config) => self
-                    .elastic_config
-                    .clone()
-                    .map(|elastic_config| {
-                        split_logger(
-                            term_logger.clone(),
-                            elastic_logger(
-                                ElasticDrainConfig {
-                                    general: elastic_config,
-                                    index: config.index,
-                                    document_type: String::from("log"),
-                                    custom_id_key: String::from("componentId"),
-                                    custom_id_value: component.to_string(),
-                                    flush_interval: ENV_VARS.elastic_search_flush_interval,
-                                    max_retries: ENV_VARS.elastic_search_max_retries,
-                                },
-                                term_logger.clone(),
-                            ),
-                        )
-                    })
-                    .unwrap_or(term_logger),
-            },
+        match (config.and_then(|config| config.elastic), &self.log_store) {
+            (Some(config), Some(log_store)) => split_logger(
+                term_logger.clone(),
+                log_store.logger(
+                    LogSource {
+                        index: config.index,
+                        id_key: String::from("componentId"),
+                        id: component.to_string(),
+                    },
+                    term_logger,
+                ),
+            ),
+            _ => term_logger,
         }
     }
 
-    /// Creates a subgraph logger with Elasticsearch support.
+    /// Creates a subgraph logger with log store support.
     pub fn subgraph_logger(&self, loc: &DeploymentLocator) -> Logger {
         let term_logger = self
             .parent
             .new(o!("subgraph_id" => loc.hash.to_string(), "sgd" => loc.id.to_string()));
 
-        self.elastic_config
-            .clone()
-            .map(|elastic_config| {
-                split_logger(
-                    term_logger.clone(),
-                    elastic_logger(
-                        ElasticDrainConfig {
-                            general: elastic_config,
-                            index: String::from("subgraph-logs"),
-                            document_type: String::from("log"),
-                            custom_id_key: String::from("subgraphId"),
-                            custom_id_value: loc.hash.to_string(),
-                            flush_interval: ENV_VARS.elastic_search_flush_interval,
-                            max_retries: ENV_VARS.elastic_search_max_retries,
-                        },
-                        term_logger.clone(),
-                    ),
-                )
-            })
-            .unwrap_or(term_logger)
+        match &self.log_store {
+            Some(log_store) => split_logger(
+                term_logger.clone(),
+                log_store.logger(
+                    LogSource {
+                        index: String::from("subgraph-logs"),
+                        id_key: String::from("subgraphId"),
+                        id: loc.hash.to_string(),
+                    },
+                    term_logger,
+                ),
+            ),
+            None => term_logger,
+        }
     }
 }
@@ -0,0 +1,137 @@
+//! A drain that writes one JSON object per log line so that log pipelines
+//! can ingest the logs without parsing the text format
+use std::io;
+use std::sync::Mutex;
+
+use chrono::prelude::{SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use slog::*;
+
+use super::{HeaderSerializer, KeyValueSerializer};
+
+pub struct JsonFormat<W: io::Write> {
+    writer: Mutex<W>,
+}
+
+impl<W: io::Write> JsonFormat<W> {
+    pub fn new(writer: W) -> Self {
+        JsonFormat {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Critical => "critical",
+        Level::Error => "error",
+        Level::Warning => "warning",
+        Level::Info => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace",
+    }
+}
+
+/// Turn a log record into a JSON object. The subgraph ID is reported as
+/// `deployment`, the block number as `block`, and the component hierarchy
+/// as `component`; all other key/value pairs are reported as strings under
+/// their own key
+pub(super) fn to_json(record: &Record, values: &OwnedKVList) -> io::Result<Map<String, Value>> {
+    let mut serializer = KeyValueSerializer::new();
+    record.kv().serialize(record, &mut serializer)?;
+    let body_kvs = serializer.finish();
+
+    let mut serializer = HeaderSerializer::new();
+    values.serialize(record, &mut serializer)?;
+    let (subgraph_id, components, header_kvs) = serializer.finish();
+
+    let mut map = Map::new();
+    map.insert(
+        "timestamp".to_string(),
+        Value::from(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+    );
+    map.insert("level".to_string(), Value::from(level_name(record.level())));
+    map.insert("msg".to_string(), Value::from(record.msg().to_string()));
+    map.insert("module".to_string(), Value::from(record.module()));
+    if !components.is_empty() {
+        map.insert("component".to_string(), Value::from(components.join(" > ")));
+    }
+    if let Some(subgraph_id) = subgraph_id {
+        map.insert("deployment".to_string(), Value::from(subgraph_id));
+    }
+
+    // Values from the log statement take precedence over values from the
+    // logger
+    for (key, value) in header_kvs.into_iter().chain(body_kvs) {
+        let (key, value) = match key.as_str() {
+            "block_number" => (
+                "block".to_string(),
+                value
+                    .parse::<i64>()
+                    .map(Value::from)
+                    .unwrap_or_else(|_| Value::from(value)),
+            ),
+            _ => (key, Value::from(value)),
+        };
+        map.insert(key, value);
+    }
+    Ok(map)
+}
+
+impl<W: io::Write> Drain for JsonFormat<W> {
+    type Ok = ();
+    type Err = io::Error;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        let mut line = serde_json::to_vec(&to_json(record, values)?)?;
+        line.push(b'\n');
+        let mut writer = self.writer.lock().unwrap();
+        writer.write_all(&line)?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    use serde_json::Value;
+    use slog::*;
+
+    use super::JsonFormat;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_lines() {
+        let buffer = Buffer::default();
+        let logger = Logger::root(JsonFormat::new(buffer.clone()).fuse(), o!());
+        let logger = logger.new(o!("subgraph_id" => "QmLog", "component" => "SubgraphInstance"));
+        let logger = logger.new(o!("block_number" => "17", "block_hash" => "0xab"));
+        info!(logger, "Done processing trigger"; "handler" => "handleTransfer");
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(1, output.lines().count());
+        let json: Value = serde_json::from_str(output.trim_end()).unwrap();
+        assert_eq!("info", json["level"]);
+        assert_eq!("Done processing trigger", json["msg"]);
+        assert_eq!("QmLog", json["deployment"]);
+        assert_eq!("SubgraphInstance", json["component"]);
+        assert_eq!(17, json["block"]);
+        assert_eq!("0xab", json["block_hash"]);
+        assert_eq!("handleTransfer", json["handler"]);
+        assert!(json["module"].as_str().unwrap().contains("json"));
+    }
+}
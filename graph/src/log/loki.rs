@@ -0,0 +1,275 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures03::TryFutureExt;
+use http::header::CONTENT_TYPE;
+use reqwest::Client;
+use serde_json::json;
+use slog::*;
+use slog_async;
+
+use crate::log::factory::{LogSource, LogStore};
+use crate::prelude::ENV_VARS;
+use crate::util::futures::retry;
+
+/// General configuration parameters for logging to Grafana Loki.
+#[derive(Clone, Debug)]
+pub struct LokiLoggingConfig {
+    /// The Loki service to log to.
+    pub endpoint: String,
+    /// The Loki username.
+    pub username: Option<String>,
+    /// The Loki password (optional).
+    pub password: Option<String>,
+    /// The tenant to log to in a multi-tenant Loki (optional).
+    pub tenant_id: Option<String>,
+}
+
+/// Configuration for `LokiDrain`.
+#[derive(Clone, Debug)]
+pub struct LokiDrainConfig {
+    /// General Loki logging configuration.
+    pub general: LokiLoggingConfig,
+    /// The labels that identify the stream the logs are sent to.
+    pub labels: Vec<(String, String)>,
+    /// The batching interval.
+    pub flush_interval: Duration,
+    /// Maximum retry attempts.
+    pub max_retries: usize,
+}
+
+#[derive(Clone, Debug)]
+struct LokiLog {
+    level: Level,
+    /// Nanoseconds since the epoch, as Loki expects them
+    timestamp: String,
+    line: String,
+}
+
+/// An slog `Drain` for logging to Grafana Loki through its push API.
+///
+/// Logs are sent in batches to `<endpoint>/loki/api/v1/push`. Each source
+/// of logs becomes its own set of streams that are identified by the
+/// labels from the `LokiDrainConfig` and the log level. Each log line is
+/// a JSON object in the same format as with `GRAPH_LOG_FORMAT=json` so
+/// that it can be parsed with LogQL's `json` stage.
+pub struct LokiDrain {
+    config: LokiDrainConfig,
+    error_logger: Logger,
+    logs: Arc<Mutex<Vec<LokiLog>>>,
+}
+
+impl LokiDrain {
+    /// Creates a new `LokiDrain`.
+    pub fn new(config: LokiDrainConfig, error_logger: Logger) -> Self {
+        let drain = LokiDrain {
+            config,
+            error_logger,
+            logs: Arc::new(Mutex::new(vec![])),
+        };
+        drain.periodically_flush_logs();
+        drain
+    }
+
+    fn periodically_flush_logs(&self) {
+        let flush_logger = self.error_logger.clone();
+        let logs = self.logs.clone();
+        let config = self.config.clone();
+        let mut interval = tokio::time::interval(self.config.flush_interval);
+        let max_retries = self.config.max_retries;
+
+        crate::task_spawn::spawn(async move {
+            loop {
+                interval.tick().await;
+
+                let logs_to_send = std::mem::take(&mut *logs.lock().unwrap());
+
+                // Do nothing if there are no logs to flush
+                if logs_to_send.is_empty() {
+                    continue;
+                }
+
+                debug!(flush_logger, "Flushing {} logs to Loki", logs_to_send.len());
+
+                let body = push_body(&config.labels, &logs_to_send).to_string();
+
+                let url = format!(
+                    "{}/loki/api/v1/push",
+                    config.general.endpoint.trim_end_matches('/')
+                );
+                let mut request = Client::new()
+                    .post(url)
+                    .header(CONTENT_TYPE, "application/json");
+                if let Some(username) = &config.general.username {
+                    request = request.basic_auth(username, config.general.password.clone());
+                }
+                if let Some(tenant_id) = &config.general.tenant_id {
+                    request = request.header("X-Scope-OrgID", tenant_id);
+                }
+
+                let flush_logger = flush_logger.clone();
+                retry("send logs to Loki", &flush_logger)
+                    .limit(max_retries)
+                    .timeout_secs(30)
+                    .run(move || {
+                        request
+                            .try_clone()
+                            .unwrap() // Unwrap: Request body not yet set
+                            .body(body.clone())
+                            .send()
+                            .and_then(|response| async { response.error_for_status() })
+                            .map_ok(|_| ())
+                    })
+                    .await
+                    .unwrap_or_else(|e| {
+                        // Log if there was a problem sending the logs
+                        error!(flush_logger, "Failed to send logs to Loki: {}", e);
+                    })
+            }
+        });
+    }
+}
+
+fn level_label(level: Level) -> &'static str {
+    match level {
+        Level::Critical => "critical",
+        Level::Error => "error",
+        Level::Warning => "warning",
+        Level::Info => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace",
+    }
+}
+
+/// The body of a request to the push API. Logs are grouped into one stream
+/// per level, and the logs in each stream keep their order
+fn push_body(labels: &[(String, String)], logs: &[LokiLog]) -> serde_json::Value {
+    let mut streams: Vec<(Level, Vec<serde_json::Value>)> = Vec::new();
+    for log in logs {
+        let entry = json!([log.timestamp, log.line]);
+        match streams.iter_mut().find(|(level, _)| *level == log.level) {
+            Some((_, values)) => values.push(entry),
+            None => streams.push((log.level, vec![entry])),
+        }
+    }
+
+    let streams: Vec<_> = streams
+        .into_iter()
+        .map(|(level, values)| {
+            let mut stream: serde_json::Map<String, serde_json::Value> = labels
+                .iter()
+                .map(|(key, value)| (key.clone(), json!(value)))
+                .collect();
+            stream.insert("level".to_string(), json!(level_label(level)));
+            json!({ "stream": stream, "values": values })
+        })
+        .collect();
+    json!({ "streams": streams })
+}
+
+impl Drain for LokiDrain {
+    type Ok = ();
+    type Err = ();
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<Self::Ok, Self::Err> {
+        // Don't send `trace` logs to Loki.
+        if record.level() == Level::Trace {
+            return Ok(());
+        }
+
+        let line = match crate::log::json::to_json(record, values) {
+            Ok(map) => serde_json::Value::Object(map).to_string(),
+            Err(_) => format!("{}", record.msg()),
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+            .to_string();
+
+        // Push the log into the queue
+        let mut logs = self.logs.lock().unwrap();
+        logs.push(LokiLog {
+            level: record.level(),
+            timestamp,
+            line,
+        });
+
+        Ok(())
+    }
+}
+
+/// Creates a new asynchronous Loki logger.
+///
+/// Uses `error_logger` to print any Loki logging errors,
+/// so they don't go unnoticed.
+pub fn loki_logger(config: LokiDrainConfig, error_logger: Logger) -> Logger {
+    let loki_drain = LokiDrain::new(config, error_logger).fuse();
+    let async_drain = slog_async::Async::new(loki_drain)
+        .chan_size(20000)
+        .build()
+        .fuse();
+    Logger::root(async_drain, o!())
+}
+
+/// Sends component and subgraph logs to Loki
+pub struct LokiLogStore {
+    config: LokiLoggingConfig,
+}
+
+impl LokiLogStore {
+    pub fn new(config: LokiLoggingConfig) -> Self {
+        LokiLogStore { config }
+    }
+}
+
+impl LogStore for LokiLogStore {
+    fn logger(&self, source: LogSource, error_logger: Logger) -> Logger {
+        loki_logger(
+            LokiDrainConfig {
+                general: self.config.clone(),
+                labels: vec![
+                    ("job".to_string(), "graph-node".to_string()),
+                    ("index".to_string(), source.index),
+                    (source.id_key, source.id),
+                ],
+                flush_interval: ENV_VARS.elastic_search_flush_interval,
+                max_retries: ENV_VARS.elastic_search_max_retries,
+            },
+            error_logger,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use slog::Level;
+
+    use super::{push_body, LokiLog};
+
+    #[test]
+    fn push_request() {
+        let log = |level, line: &str| LokiLog {
+            level,
+            timestamp: "1".to_string(),
+            line: line.to_string(),
+        };
+        let logs = vec![
+            log(Level::Info, "a"),
+            log(Level::Error, "b"),
+            log(Level::Info, "c"),
+        ];
+        let labels = vec![("subgraphId".to_string(), "QmLoki".to_string())];
+
+        let body = push_body(&labels, &logs);
+        let streams = body["streams"].as_array().unwrap();
+        assert_eq!(2, streams.len());
+        assert_eq!("QmLoki", streams[0]["stream"]["subgraphId"]);
+        assert_eq!("info", streams[0]["stream"]["level"]);
+        assert_eq!(
+            serde_json::json!([["1", "a"], ["1", "c"]]),
+            streams[0]["values"]
+        );
+        assert_eq!("error", streams[1]["stream"]["level"]);
+    }
+}
@@ -24,17 +24,35 @@ use slog_envlogger;
 use slog_term::*;
 use std::{fmt, io, result};
 
+use crate::env::LogFormat;
 use crate::prelude::ENV_VARS;
 
 pub mod codes;
 pub mod elastic;
 pub mod factory;
+pub mod json;
+pub mod loki;
 pub mod split;
 
 pub fn logger(show_debug: bool) -> Logger {
-    let use_color = isatty::stdout_isatty();
-    let decorator = slog_term::TermDecorator::new().build();
-    let drain = CustomFormat::new(decorator, use_color).fuse();
+    match ENV_VARS.log_format {
+        LogFormat::Text => {
+            let use_color = isatty::stdout_isatty();
+            let decorator = slog_term::TermDecorator::new().build();
+            let drain = CustomFormat::new(decorator, use_color).fuse();
+            logger_with_drain(drain, show_debug)
+        }
+        LogFormat::Json => {
+            let drain = json::JsonFormat::new(io::stdout()).fuse();
+            logger_with_drain(drain, show_debug)
+        }
+    }
+}
+
+fn logger_with_drain<D>(drain: D, show_debug: bool) -> Logger
+where
+    D: Drain<Ok = (), Err = Never> + Send + 'static,
+{
     let drain = slog_envlogger::LogBuilder::new(drain)
         .filter(
             None,
@@ -307,6 +307,9 @@ pub enum IndexForAscTypeId {
     Log = 135,
     ArrayH256 = 136,
     ArrayLog = 137,
+
+    // Subgraph composition
+    EntityTrigger = 138,
 }
 
 impl ToAscObj<u32> for IndexForAscTypeId {
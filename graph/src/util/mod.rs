@@ -18,5 +18,15 @@ pub mod timed_rw_lock;
 
 pub mod jobs;
 
+/// Tracing of queries and block processing with OpenTelemetry
+pub mod otel;
+
 /// Increasingly longer sleeps to back off some repeated operation
 pub mod backoff;
+
+/// Accepting TLS connections with certificates that are reloaded when they change
+pub mod tls;
+
+/// Listening on unix sockets
+#[cfg(unix)]
+pub mod unix_socket;
@@ -0,0 +1,360 @@
+//! A small OpenTelemetry tracer. Spans are exported in batches to an OTLP
+//! collector over HTTP with JSON encoding when `GRAPH_OTLP_ENDPOINT` is
+//! set; otherwise, creating spans is a no-op.
+//!
+//! Traces are started explicitly with `Span::root`, e.g., for each query or
+//! block. Code that runs as part of that work creates child spans with
+//! `Span::new`, which finds its parent through `in_span` for async code and
+//! `with_context` for blocking code. `Span::new` does nothing if there is no
+//! parent, so that only work that belongs to a sampled trace is recorded.
+use std::cell::Cell;
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+use rand::Rng;
+use serde_json::json;
+use tokio::sync::mpsc;
+
+use crate::env::ENV_VARS;
+
+/// How many finished spans we buffer before we drop spans
+const BUFFER_SIZE: usize = 10_000;
+/// The most spans we send in one request
+const BATCH_SIZE: usize = 512;
+/// How long we wait for more spans before sending a batch
+const FLUSH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The identity of a span that is needed to create child spans
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpanContext {
+    trace_id: [u8; 16],
+    span_id: [u8; 8],
+}
+
+impl SpanContext {
+    /// Parse a W3C `traceparent` header. Returns the context of the remote
+    /// span and whether the remote side sampled the trace
+    pub fn from_traceparent(header: &str) -> Option<(SpanContext, bool)> {
+        let mut parts = header.trim().split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let span_id = parts.next()?;
+        let flags = parts.next()?;
+        if version != "00" || parts.next().is_some() {
+            return None;
+        }
+
+        let mut ctx = SpanContext {
+            trace_id: [0; 16],
+            span_id: [0; 8],
+        };
+        hex::decode_to_slice(trace_id, &mut ctx.trace_id).ok()?;
+        hex::decode_to_slice(span_id, &mut ctx.span_id).ok()?;
+        let mut flags_byte = [0u8; 1];
+        hex::decode_to_slice(flags, &mut flags_byte).ok()?;
+        if ctx.trace_id == [0; 16] || ctx.span_id == [0; 8] {
+            return None;
+        }
+        Some((ctx, flags_byte[0] & 0x01 == 0x01))
+    }
+
+    /// The W3C `traceparent` header for this context
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-01",
+            hex::encode(self.trace_id),
+            hex::encode(self.span_id)
+        )
+    }
+}
+
+tokio::task_local! {
+    static CURRENT: SpanContext;
+}
+
+thread_local! {
+    static CURRENT_SYNC: Cell<Option<SpanContext>> = Cell::new(None);
+}
+
+/// The context of the span that the current task or thread runs in
+pub fn current() -> Option<SpanContext> {
+    CURRENT
+        .try_with(|ctx| *ctx)
+        .ok()
+        .or_else(|| CURRENT_SYNC.with(|ctx| ctx.get()))
+}
+
+/// Run `fut` so that spans created by it become children of `span`
+pub async fn in_span<F: Future>(span: &Span, fut: F) -> F::Output {
+    match span.context() {
+        Some(ctx) => CURRENT.scope(ctx, fut).await,
+        None => fut.await,
+    }
+}
+
+/// Run the blocking function `f` so that spans created by it become
+/// children of `ctx`. This is needed for code that runs on a different
+/// thread than its caller, e.g., with `spawn_blocking`
+pub fn with_context<T>(ctx: Option<SpanContext>, f: impl FnOnce() -> T) -> T {
+    let prev = CURRENT_SYNC.with(|current| current.replace(ctx));
+    let res = f();
+    CURRENT_SYNC.with(|current| current.set(prev));
+    res
+}
+
+struct SpanData {
+    ctx: SpanContext,
+    parent: Option<[u8; 8]>,
+    name: String,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(&'static str, String)>,
+    error: Option<String>,
+}
+
+impl SpanData {
+    fn to_json(&self) -> serde_json::Value {
+        fn nanos(time: SystemTime) -> String {
+            time.duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos()
+                .to_string()
+        }
+
+        let attributes: Vec<_> = self
+            .attributes
+            .iter()
+            .map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } }))
+            .collect();
+        let status = match &self.error {
+            Some(message) => json!({ "code": 2, "message": message }),
+            None => json!({ "code": 1 }),
+        };
+        let mut span = json!({
+            "traceId": hex::encode(self.ctx.trace_id),
+            "spanId": hex::encode(self.ctx.span_id),
+            "name": self.name,
+            // SPAN_KIND_INTERNAL
+            "kind": 1,
+            "startTimeUnixNano": nanos(self.start),
+            "endTimeUnixNano": nanos(self.end),
+            "attributes": attributes,
+            "status": status,
+        });
+        if let Some(parent) = self.parent {
+            span["parentSpanId"] = json!(hex::encode(parent));
+        }
+        span
+    }
+}
+
+/// A span of work. The span ends and is exported when it is dropped
+#[must_use]
+pub struct Span {
+    data: Option<Box<SpanData>>,
+}
+
+impl Span {
+    fn start(name: &str, trace_id: [u8; 16], parent: Option<[u8; 8]>) -> Span {
+        let mut span_id = [0u8; 8];
+        while span_id == [0; 8] {
+            rand::thread_rng().fill(&mut span_id);
+        }
+        let now = SystemTime::now();
+        Span {
+            data: Some(Box::new(SpanData {
+                ctx: SpanContext { trace_id, span_id },
+                parent,
+                name: name.to_string(),
+                start: now,
+                end: now,
+                attributes: Vec::new(),
+                error: None,
+            })),
+        }
+    }
+
+    /// A span that records nothing
+    pub fn none() -> Span {
+        Span { data: None }
+    }
+
+    /// Start a new trace, or continue the trace of a client that sent
+    /// `traceparent`. Whether the trace is recorded depends on the
+    /// client's sampling decision or on `GRAPH_OTLP_SAMPLE_RATIO`
+    pub fn root(name: &str, traceparent: Option<&str>) -> Span {
+        if EXPORTER.is_none() {
+            return Span::none();
+        }
+        match traceparent.and_then(SpanContext::from_traceparent) {
+            Some((remote, true)) => Span::start(name, remote.trace_id, Some(remote.span_id)),
+            Some((_, false)) => Span::none(),
+            None => {
+                if rand::thread_rng().gen::<f64>() >= ENV_VARS.otlp_sample_ratio {
+                    return Span::none();
+                }
+                let mut trace_id = [0u8; 16];
+                while trace_id == [0; 16] {
+                    rand::thread_rng().fill(&mut trace_id);
+                }
+                Span::start(name, trace_id, None)
+            }
+        }
+    }
+
+    /// Start a span that is a child of the span that the current task or
+    /// thread runs in. If there is none, the span records nothing
+    pub fn new(name: &str) -> Span {
+        Span::child_of(current(), name)
+    }
+
+    /// Start a span that is a child of `parent`
+    pub fn child_of(parent: Option<SpanContext>, name: &str) -> Span {
+        match parent {
+            Some(parent) if EXPORTER.is_some() => {
+                Span::start(name, parent.trace_id, Some(parent.span_id))
+            }
+            _ => Span::none(),
+        }
+    }
+
+    /// The context of this span, or `None` if the span is not recorded
+    pub fn context(&self) -> Option<SpanContext> {
+        self.data.as_ref().map(|data| data.ctx)
+    }
+
+    /// Whether this span is recorded. Use this to avoid computing
+    /// expensive attributes
+    pub fn is_recording(&self) -> bool {
+        self.data.is_some()
+    }
+
+    /// Add an attribute to the span
+    pub fn attr(mut self, key: &'static str, value: impl fmt::Display) -> Self {
+        self.set_attr(key, value);
+        self
+    }
+
+    /// Add an attribute to the span
+    pub fn set_attr(&mut self, key: &'static str, value: impl fmt::Display) {
+        if let Some(data) = self.data.as_mut() {
+            data.attributes.push((key, value.to_string()));
+        }
+    }
+
+    /// Mark the span as failed with `error`
+    pub fn set_error(&mut self, error: impl fmt::Display) {
+        if let Some(data) = self.data.as_mut() {
+            data.error = Some(error.to_string());
+        }
+    }
+
+    /// A more readable `drop`
+    pub fn end(self) {}
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if let (Some(mut data), Some(exporter)) = (self.data.take(), EXPORTER.as_ref()) {
+            data.end = SystemTime::now();
+            // If the exporter can not keep up, we drop spans rather than
+            // slow down the work that is traced
+            let _ = exporter.sender.try_send(data);
+        }
+    }
+}
+
+struct Exporter {
+    sender: mpsc::Sender<Box<SpanData>>,
+}
+
+impl Exporter {
+    fn from_env() -> Option<Exporter> {
+        let endpoint = ENV_VARS.otlp_endpoint.as_ref()?;
+        let url = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+        let service_name = ENV_VARS.otlp_service_name.clone();
+        let (sender, receiver) = mpsc::channel(BUFFER_SIZE);
+
+        // Spans can be finished on threads that are not part of a tokio
+        // runtime, e.g., the threads that run mappings. The exporter
+        // therefore runs on its own thread with its own runtime
+        std::thread::Builder::new()
+            .name("otlp-exporter".to_string())
+            .spawn(move || {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .expect("failed to create runtime for the OTLP exporter");
+                runtime.block_on(Self::run(url, service_name, receiver));
+            })
+            .expect("failed to start the OTLP exporter");
+        Some(Exporter { sender })
+    }
+
+    async fn run(url: String, service_name: String, mut receiver: mpsc::Receiver<Box<SpanData>>) {
+        let client = reqwest::Client::new();
+        while let Some(span) = receiver.recv().await {
+            let mut batch = vec![span];
+            let deadline = tokio::time::Instant::now() + FLUSH_INTERVAL;
+            while batch.len() < BATCH_SIZE {
+                match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                    Ok(Some(span)) => batch.push(span),
+                    Ok(None) | Err(_) => break,
+                }
+            }
+
+            let spans: Vec<_> = batch.iter().map(|span| span.to_json()).collect();
+            let body = json!({
+                "resourceSpans": [{
+                    "resource": {
+                        "attributes": [
+                            { "key": "service.name", "value": { "stringValue": service_name } }
+                        ]
+                    },
+                    "scopeSpans": [{
+                        "scope": { "name": "graph-node" },
+                        "spans": spans,
+                    }]
+                }]
+            });
+            // There is no good place to report errors to since tracing is
+            // best-effort; if the collector is down, spans are lost
+            let _ = client.post(&url).json(&body).send().await;
+        }
+    }
+}
+
+lazy_static! {
+    static ref EXPORTER: Option<Exporter> = Exporter::from_env();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpanContext;
+
+    #[test]
+    fn traceparent() {
+        let header = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let (ctx, sampled) = SpanContext::from_traceparent(header).unwrap();
+        assert!(sampled);
+        assert_eq!(header, ctx.traceparent());
+
+        let (_, sampled) = SpanContext::from_traceparent(
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-00",
+        )
+        .unwrap();
+        assert!(!sampled);
+
+        assert!(SpanContext::from_traceparent("garbage").is_none());
+        assert!(SpanContext::from_traceparent(
+            "00-00000000000000000000000000000000-b7ad6b7169203331-01"
+        )
+        .is_none());
+        assert!(SpanContext::from_traceparent(
+            "01-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,200 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{anyhow, Context, Error};
+use futures03::stream::{self, Stream, StreamExt};
+use slog::{debug, info, o, warn, Logger};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::{internal::pemfile, NoClientAuth, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+use crate::env::EnvVars;
+
+/// How often to check whether the certificate or key files changed
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How many TLS handshakes may be in progress at the same time for one
+/// server
+const MAX_CONCURRENT_HANDSHAKES: usize = 64;
+
+struct Loaded {
+    checked_at: Instant,
+    modified: (SystemTime, SystemTime),
+    acceptor: TlsAcceptor,
+}
+
+/// The certificate and private key that the servers use to accept TLS
+/// connections. When the files change, for example because the
+/// certificate was rotated, the new certificate is used for all new
+/// connections
+pub struct TlsSettings {
+    logger: Logger,
+    cert: PathBuf,
+    key: PathBuf,
+    loaded: Mutex<Loaded>,
+}
+
+impl TlsSettings {
+    /// Load the certificate chain from the PEM file `cert` and the private
+    /// key from the PEM file `key`
+    pub fn new(logger: &Logger, cert: PathBuf, key: PathBuf) -> Result<Self, Error> {
+        let loaded = Self::load(&cert, &key)?;
+        Ok(TlsSettings {
+            logger: logger.new(o!("component" => "TlsSettings")),
+            cert,
+            key,
+            loaded: Mutex::new(loaded),
+        })
+    }
+
+    /// The TLS settings from `GRAPH_TLS_CERT_FILE` and `GRAPH_TLS_KEY_FILE`,
+    /// or `None` if the servers should not use TLS
+    pub fn from_env(logger: &Logger, env_vars: &EnvVars) -> Result<Option<Arc<Self>>, Error> {
+        match (&env_vars.tls_cert_file, &env_vars.tls_key_file) {
+            (Some(cert), Some(key)) => {
+                Self::new(logger, cert.into(), key.into()).map(|settings| Some(Arc::new(settings)))
+            }
+            (None, None) => Ok(None),
+            _ => Err(anyhow!(
+                "GRAPH_TLS_CERT_FILE and GRAPH_TLS_KEY_FILE must either both be set or both be unset"
+            )),
+        }
+    }
+
+    fn modified(cert: &Path, key: &Path) -> Result<(SystemTime, SystemTime), Error> {
+        let modified = |path: &Path| {
+            path.metadata()
+                .and_then(|metadata| metadata.modified())
+                .with_context(|| format!("can not read {}", path.display()))
+        };
+        Ok((modified(cert)?, modified(key)?))
+    }
+
+    fn load(cert_path: &Path, key_path: &Path) -> Result<Loaded, Error> {
+        let modified = Self::modified(cert_path, key_path)?;
+
+        let open = |path: &Path| {
+            File::open(path)
+                .map(BufReader::new)
+                .with_context(|| format!("can not open {}", path.display()))
+        };
+        let certs = pemfile::certs(&mut open(cert_path)?)
+            .map_err(|()| anyhow!("invalid certificate in {}", cert_path.display()))?;
+        if certs.is_empty() {
+            return Err(anyhow!("no certificate found in {}", cert_path.display()));
+        }
+        let mut keys = pemfile::pkcs8_private_keys(&mut open(key_path)?)
+            .map_err(|()| anyhow!("invalid private key in {}", key_path.display()))?;
+        if keys.is_empty() {
+            keys = pemfile::rsa_private_keys(&mut open(key_path)?)
+                .map_err(|()| anyhow!("invalid private key in {}", key_path.display()))?;
+        }
+        let key = keys
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("no private key found in {}", key_path.display()))?;
+
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config
+            .set_single_cert(certs, key)
+            .context("certificate and private key do not match")?;
+
+        Ok(Loaded {
+            checked_at: Instant::now(),
+            modified,
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+        })
+    }
+
+    /// The acceptor for new connections. If the certificate or key files
+    /// changed since they were last loaded, load them again. If they can
+    /// not be loaded, keep using the old ones
+    pub fn acceptor(&self) -> TlsAcceptor {
+        let mut loaded = self.loaded.lock().unwrap();
+        if loaded.checked_at.elapsed() < RELOAD_CHECK_INTERVAL {
+            return loaded.acceptor.clone();
+        }
+        loaded.checked_at = Instant::now();
+
+        let changed = match Self::modified(&self.cert, &self.key) {
+            Ok(modified) => modified != loaded.modified,
+            Err(e) => {
+                warn!(self.logger, "Failed to check TLS certificate for changes";
+                                   "error" => format!("{:#}", e));
+                false
+            }
+        };
+        if changed {
+            match Self::load(&self.cert, &self.key) {
+                Ok(new) => {
+                    info!(self.logger, "Reloaded TLS certificate";
+                                       "cert" => self.cert.display().to_string());
+                    *loaded = new;
+                }
+                Err(e) => {
+                    warn!(self.logger, "Failed to reload TLS certificate, keeping the old one";
+                                       "error" => format!("{:#}", e));
+                }
+            }
+        }
+        loaded.acceptor.clone()
+    }
+
+    /// Listen on `addr` and return the connections for which the TLS
+    /// handshake succeeded. Connections that fail the handshake are
+    /// dropped
+    pub fn incoming(
+        self: &Arc<Self>,
+        addr: SocketAddr,
+    ) -> io::Result<impl Stream<Item = io::Result<TlsStream<TcpStream>>> + Send> {
+        let listener = std::net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+
+        let settings = self.clone();
+        let logger = self.logger.clone();
+        let connections = stream::unfold(listener, |listener| async move {
+            let conn = listener.accept().await;
+            Some((conn, listener))
+        });
+        Ok(connections
+            .map(move |conn| {
+                let acceptor = settings.acceptor();
+                async move {
+                    let (conn, _) = conn?;
+                    acceptor.accept(conn).await
+                }
+            })
+            .buffer_unordered(MAX_CONCURRENT_HANDSHAKES)
+            .filter_map(move |conn| {
+                let conn = match conn {
+                    Ok(conn) => Some(Ok(conn)),
+                    Err(e) => {
+                        debug!(logger, "Failed to accept TLS connection"; "error" => e.to_string());
+                        None
+                    }
+                };
+                futures03::future::ready(conn)
+            }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_files_are_an_error() {
+        let res = TlsSettings::new(
+            &Logger::root(slog::Discard, o!()),
+            PathBuf::from("/nonexistent/cert.pem"),
+            PathBuf::from("/nonexistent/key.pem"),
+        );
+        assert!(res.is_err());
+    }
+}
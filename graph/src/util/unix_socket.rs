@@ -0,0 +1,57 @@
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+
+use futures03::stream::{self, Stream};
+use tokio::net::{UnixListener, UnixStream};
+
+/// Listen on the unix socket at `path`. A socket left behind by a previous
+/// run of the process is removed first; any other file at `path` is an
+/// error. Only the owner and the group of the process can connect to the
+/// socket
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)?,
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ))
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o660))?;
+    Ok(listener)
+}
+
+/// The connections that clients make to `listener`
+pub fn incoming(listener: UnixListener) -> impl Stream<Item = io::Result<UnixStream>> + Send {
+    stream::unfold(listener, |listener| async move {
+        let conn = listener.accept().await.map(|(conn, _)| conn);
+        Some((conn, listener))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn replaces_stale_sockets_only() {
+        let dir = std::env::temp_dir().join(format!("graph-unix-socket-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let socket = dir.join("graph.sock");
+        drop(bind(&socket).unwrap());
+        // The socket from before is still there and gets replaced
+        drop(bind(&socket).unwrap());
+
+        let file = dir.join("graph.txt");
+        std::fs::write(&file, "not a socket").unwrap();
+        assert!(bind(&file).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,6 +1,8 @@
 use async_trait::async_trait;
 use graph::blockchain::BlockPtr;
-use graph::data::subgraph::schema::{SubgraphError, SubgraphHealth};
+use graph::data::subgraph::schema::{
+    MappingLog, QuarantinedDataSource, SubgraphError, SubgraphHealth,
+};
 use graph::prelude::{Schema, StopwatchMetrics, StoreError, UnfailOutcome};
 use lazy_static::lazy_static;
 use slog::Logger;
@@ -62,11 +64,15 @@ impl WritableStore for MockStore {
         unimplemented!()
     }
 
-    fn revert_block_operations(&self, _: BlockPtr, _: Option<&str>) -> Result<(), StoreError> {
+    async fn revert_block_operations(
+        &self,
+        _: BlockPtr,
+        _: Option<&str>,
+    ) -> Result<(), StoreError> {
         unimplemented!()
     }
 
-    fn unfail_deterministic_error(
+    async fn unfail_deterministic_error(
         &self,
         _: &BlockPtr,
         _: &BlockPtr,
@@ -74,7 +80,10 @@ impl WritableStore for MockStore {
         unimplemented!()
     }
 
-    fn unfail_non_deterministic_error(&self, _: &BlockPtr) -> Result<UnfailOutcome, StoreError> {
+    async fn unfail_non_deterministic_error(
+        &self,
+        _: &BlockPtr,
+    ) -> Result<UnfailOutcome, StoreError> {
         unimplemented!()
     }
 
@@ -99,7 +108,7 @@ impl WritableStore for MockStore {
         }
     }
 
-    fn transact_block_operations(
+    async fn transact_block_operations(
         &self,
         _: BlockPtr,
         _: Option<String>,
@@ -107,6 +116,9 @@ impl WritableStore for MockStore {
         _: &StopwatchMetrics,
         _: Vec<StoredDynamicDataSource>,
         _: Vec<SubgraphError>,
+        _: Vec<MappingLog>,
+        _: Vec<QuarantinedDataSource>,
+        _: bool,
     ) -> Result<(), StoreError> {
         unimplemented!()
     }
@@ -122,7 +134,7 @@ impl WritableStore for MockStore {
         unimplemented!()
     }
 
-    fn unassign_subgraph(&self) -> Result<(), StoreError> {
+    async fn unassign_subgraph(&self) -> Result<(), StoreError> {
         unimplemented!()
     }
 
@@ -130,7 +142,11 @@ impl WritableStore for MockStore {
         unimplemented!()
     }
 
-    fn deployment_synced(&self) -> Result<(), StoreError> {
+    async fn deployment_synced(&self) -> Result<(), StoreError> {
+        unimplemented!()
+    }
+
+    async fn promote_canary(&self) -> Result<(), StoreError> {
         unimplemented!()
     }
 
@@ -145,6 +161,10 @@ impl WritableStore for MockStore {
     fn input_schema(&self) -> Arc<Schema> {
         SCHEMA.clone()
     }
+
+    async fn flush(&self) -> Result<(), StoreError> {
+        unimplemented!()
+    }
 }
 
 fn make_band(id: &'static str, data: Vec<(&str, Value)>) -> (EntityKey, Entity) {
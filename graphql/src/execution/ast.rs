@@ -9,6 +9,10 @@ use graphql_parser::Pos;
 
 use crate::schema::ast::ObjectType;
 
+pub const DEFER_DIRECTIVE: &str = "defer";
+pub const STREAM_DIRECTIVE: &str = "stream";
+pub const EXPORT_DIRECTIVE: &str = "export";
+
 /// A selection set is a table that maps object types to the fields that
 /// should be selected for objects of that type. The types are always
 /// concrete object types, never interface or union types. When a
@@ -119,6 +123,48 @@ impl SelectionSet {
         Ok(item.1.iter())
     }
 
+    /// Return `true` if any field in this selection set, or in any nested
+    /// selection set, uses an active `@defer`, `@stream`, or `@export`
+    /// directive
+    pub fn is_incremental(&self) -> bool {
+        self.items.iter().any(|(_, fields)| {
+            fields.iter().any(|field| {
+                field.defer_directive().is_some()
+                    || field.stream_directive().is_some()
+                    || field.export_directive().is_some()
+                    || field.selection_set.is_incremental()
+            })
+        })
+    }
+
+    /// Return a copy of this selection set that does not contain any of
+    /// the fields that are deferred with `@defer`, at any level
+    pub fn without_deferred(&self) -> SelectionSet {
+        let items = self
+            .items
+            .iter()
+            .map(|(obj_type, fields)| {
+                let fields = fields
+                    .iter()
+                    .filter(|field| field.defer_directive().is_none())
+                    .map(|field| Field {
+                        selection_set: field.selection_set.without_deferred(),
+                        ..field.clone()
+                    })
+                    .collect();
+                (obj_type.clone(), fields)
+            })
+            .collect();
+        SelectionSet { items }
+    }
+
+    /// Iterate mutably over all fields for all types
+    pub fn fields_mut(&mut self) -> impl Iterator<Item = &mut Field> {
+        self.items
+            .iter_mut()
+            .flat_map(|(_, fields)| fields.iter_mut())
+    }
+
     /// Append the field for all the sets' types
     pub fn push(&mut self, new_field: &Field) -> Result<(), QueryExecutionError> {
         for (_, fields) in &mut self.items {
@@ -210,6 +256,14 @@ impl Directive {
         }
     }
 
+    /// Return the value of the `label` argument, if there is one
+    pub fn label(&self) -> Option<String> {
+        match self.argument_value("label") {
+            Some(r::Value::String(label)) => Some(label.clone()),
+            _ => None,
+        }
+    }
+
     /// Return `true` if this directive says that we should not include the
     /// field it is attached to. That is the case if the directive is
     /// `include` and its `if` condition is `false`, or if it is `skip` and
@@ -251,6 +305,39 @@ impl Field {
             .map(|(_, v)| v)
     }
 
+    /// Set the argument `name` to `value`, adding it if the field does not
+    /// have that argument yet
+    pub fn set_argument(&mut self, name: &str, value: r::Value) {
+        match self.arguments.iter_mut().find(|(n, _)| n == name) {
+            Some((_, v)) => *v = value,
+            None => self.arguments.push((name.to_owned(), value)),
+        }
+    }
+
+    /// Return the `@defer` directive of the fragment through which this
+    /// field was selected if the directive's `if` condition is `true`
+    pub fn defer_directive(&self) -> Option<&Directive> {
+        self.directives
+            .iter()
+            .find(|dir| dir.name == DEFER_DIRECTIVE && dir.eval_if())
+    }
+
+    /// Return the `@stream` directive of this field if the directive's
+    /// `if` condition is `true`
+    pub fn stream_directive(&self) -> Option<&Directive> {
+        self.directives
+            .iter()
+            .find(|dir| dir.name == STREAM_DIRECTIVE && dir.eval_if())
+    }
+
+    /// Return the `@export` directive of this field if the directive's
+    /// `if` condition is `true`
+    pub fn export_directive(&self) -> Option<&Directive> {
+        self.directives
+            .iter()
+            .find(|dir| dir.name == EXPORT_DIRECTIVE && dir.eval_if())
+    }
+
     fn prepend_directives(&mut self, mut directives: Vec<Directive>) {
         // TODO: check that the new directives don't conflict with existing
         // directives
@@ -1,7 +1,10 @@
 use futures03::future::FutureExt;
 use futures03::future::Shared;
 use graph::{
-    prelude::{debug, futures03, BlockPtr, CheapClone, Logger, QueryResult},
+    env::QueryCacheEviction,
+    prelude::{
+        debug, futures03, r, BlockPtr, CheapClone, DeploymentHash, Logger, QueryResult, ENV_VARS,
+    },
     util::timed_rw_lock::TimedMutex,
 };
 use stable_hash::crypto::SetHasher;
@@ -82,46 +85,292 @@ impl<R: CheapClone> QueryCache<R> {
     }
 }
 
+/// How the block cache divides its space among deployments and what it does
+/// when a result does not fit
+#[derive(Debug)]
+pub struct CachePolicy {
+    pub eviction: QueryCacheEviction,
+    /// The percentage of a block cache that one deployment may use
+    pub deployment_quota: usize,
+    /// Multipliers for the quota of individual deployments
+    pub deployment_weights: HashMap<String, f64>,
+}
+
+impl CachePolicy {
+    pub fn from_env() -> Self {
+        CachePolicy {
+            eviction: ENV_VARS.query_cache_eviction,
+            deployment_quota: ENV_VARS.query_cache_deployment_quota,
+            deployment_weights: ENV_VARS.query_cache_deployment_weights.clone(),
+        }
+    }
+
+    fn weight_of(&self, deployment: &DeploymentHash) -> f64 {
+        self.deployment_weights
+            .get(deployment.as_str())
+            .copied()
+            .unwrap_or(1.0)
+    }
+
+    /// The most that results for `deployment` may take up in a block cache
+    /// of size `max_weight`
+    fn quota(&self, max_weight: usize, deployment: &DeploymentHash) -> usize {
+        let quota = max_weight as f64 * self.deployment_quota as f64 / 100.0;
+        (quota * self.weight_of(deployment)).min(max_weight as f64) as usize
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    result: Arc<QueryResult>,
+    weight: usize,
+    hits: AtomicU64,
+}
+
 #[derive(Debug)]
 struct CacheByBlock {
     block: BlockPtr,
     max_weight: usize,
     weight: usize,
+    weight_by_deployment: HashMap<DeploymentHash, usize>,
+    policy: Arc<CachePolicy>,
 
-    // The value is `(result, n_hits)`.
-    cache: HashMap<QueryHash, (Arc<QueryResult>, AtomicU64)>,
+    cache: HashMap<QueryHash, CacheEntry>,
     total_insert_time: Duration,
+    evictions: usize,
 }
 
 impl CacheByBlock {
-    fn new(block: BlockPtr, max_weight: usize) -> Self {
+    fn new(block: BlockPtr, max_weight: usize, policy: Arc<CachePolicy>) -> Self {
         CacheByBlock {
             block,
             max_weight,
             weight: 0,
+            weight_by_deployment: HashMap::new(),
+            policy,
             cache: HashMap::new(),
             total_insert_time: Duration::default(),
+            evictions: 0,
         }
     }
 
     fn get(&self, key: &QueryHash) -> Option<&Arc<QueryResult>> {
-        let (value, hit_count) = self.cache.get(key)?;
-        hit_count.fetch_add(1, Ordering::SeqCst);
-        Some(value)
+        let entry = self.cache.get(key)?;
+        entry.hits.fetch_add(1, Ordering::SeqCst);
+        Some(&entry.result)
+    }
+
+    fn deployment_weight(&self, deployment: Option<&DeploymentHash>) -> usize {
+        deployment
+            .and_then(|deployment| self.weight_by_deployment.get(deployment))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Evict entries until `needed` bytes have been freed, considering only
+    /// entries for `deployment` if it is given. Entries are evicted in
+    /// order of their hits, scaled by the weight of their deployment so that
+    /// results for deployments with a higher weight are kept longer.
+    /// Nothing is evicted if that would not free up enough space. Returns
+    /// `true` if enough space was freed
+    fn evict(&mut self, deployment: Option<&DeploymentHash>, needed: usize) -> bool {
+        if self.policy.eviction == QueryCacheEviction::None {
+            return false;
+        }
+
+        let policy = &self.policy;
+        let mut candidates: Vec<_> = self
+            .cache
+            .iter()
+            .filter(|(_, entry)| {
+                deployment.is_none() || entry.result.deployment.as_ref() == deployment
+            })
+            .map(|(key, entry)| {
+                let hits = entry.hits.load(Ordering::SeqCst) as f64;
+                let scale = entry
+                    .result
+                    .deployment
+                    .as_ref()
+                    .map_or(1.0, |deployment| policy.weight_of(deployment));
+                (hits * scale, entry.weight, *key)
+            })
+            .collect();
+        if candidates
+            .iter()
+            .map(|(_, weight, _)| weight)
+            .sum::<usize>()
+            < needed
+        {
+            return false;
+        }
+        candidates.sort_by(|(a, _, _), (b, _, _)| a.total_cmp(b));
+
+        let mut freed = 0;
+        for (_, _, key) in candidates {
+            if freed >= needed {
+                break;
+            }
+            // Unwrap: all candidates are in the cache
+            let entry = self.cache.remove(&key).unwrap();
+            freed += entry.weight;
+            self.weight -= entry.weight;
+            if let Some(deployment) = &entry.result.deployment {
+                if let Some(weight) = self.weight_by_deployment.get_mut(deployment) {
+                    *weight -= entry.weight;
+                }
+            }
+            self.evictions += 1;
+        }
+        true
     }
 
-    /// Returns `true` if the insert was successful or `false` if the cache was full.
+    /// Returns `true` if the insert was successful or `false` if the cache
+    /// or the quota of the result's deployment was full.
     fn insert(&mut self, key: QueryHash, value: Arc<QueryResult>, weight: usize) -> bool {
         // We never try to insert errors into this cache, and always resolve some value.
         assert!(!value.has_errors());
-        let fits_in_cache = self.weight + weight <= self.max_weight;
-        if fits_in_cache {
-            let start = Instant::now();
-            self.weight += weight;
-            self.cache.insert(key, (value, AtomicU64::new(0)));
-            self.total_insert_time += start.elapsed();
+        let deployment = value.deployment.clone();
+
+        if let Some(deployment) = &deployment {
+            let quota = self.policy.quota(self.max_weight, deployment);
+            let used = self.deployment_weight(Some(deployment));
+            if weight > quota
+                || (used + weight > quota && !self.evict(Some(deployment), used + weight - quota))
+            {
+                return false;
+            }
+        }
+        if weight > self.max_weight
+            || (self.weight + weight > self.max_weight
+                && !self.evict(None, self.weight + weight - self.max_weight))
+        {
+            return false;
+        }
+
+        let start = Instant::now();
+        self.weight += weight;
+        if let Some(deployment) = deployment {
+            *self.weight_by_deployment.entry(deployment).or_default() += weight;
+        }
+        self.cache.insert(
+            key,
+            CacheEntry {
+                result: value,
+                weight,
+                hits: AtomicU64::new(0),
+            },
+        );
+        self.total_insert_time += start.elapsed();
+        true
+    }
+}
+
+/// Cache for results that contain no data, which are the bulk of the
+/// results for frontends that poll for new data. Since such results are
+/// tiny, they are kept separately from the other caches. Entries are keyed
+/// by network and block, so that deployments on the same network that are
+/// at different blocks do not push each other's results out, and like the
+/// other caches, they are tied to the block hash so that a reorg can not
+/// resurrect a stale result. The cache holds at most `max_entries` results;
+/// when it is full, the results for the block we first cached a result for
+/// are evicted
+pub struct EmptyResultCache {
+    max_entries: usize,
+    /// The number of results in `by_network`
+    len: usize,
+    /// The blocks for which we have results, in the order in which we
+    /// cached the first result for them
+    blocks: VecDeque<(String, BlockPtr)>,
+    by_network: HashMap<String, HashMap<BlockPtr, HashMap<QueryHash, Arc<QueryResult>>>>,
+}
+
+impl EmptyResultCache {
+    pub fn new(max_entries: usize) -> Self {
+        EmptyResultCache {
+            max_entries,
+            len: 0,
+            blocks: VecDeque::new(),
+            by_network: HashMap::new(),
+        }
+    }
+
+    /// Return `true` if `result` has no errors and all the fields in its
+    /// data are `null` or empty lists
+    pub fn is_empty_result(result: &QueryResult) -> bool {
+        !result.has_errors()
+            && result.data().map_or(false, |data| {
+                data.iter().all(|(_, value)| match value {
+                    r::Value::Null => true,
+                    r::Value::List(values) => values.is_empty(),
+                    _ => false,
+                })
+            })
+    }
+
+    /// Returns `true` if the result was inserted
+    pub fn insert(
+        &mut self,
+        network: &str,
+        block_ptr: &BlockPtr,
+        key: QueryHash,
+        result: Arc<QueryResult>,
+    ) -> bool {
+        if self.max_entries == 0 {
+            return false;
+        }
+
+        let cached = self
+            .by_network
+            .get(network)
+            .and_then(|blocks| blocks.get(block_ptr))
+            .map_or(false, |cache| cache.contains_key(&key));
+        if cached {
+            return false;
+        }
+
+        while self.len >= self.max_entries {
+            self.evict_oldest_block();
+        }
+
+        let blocks = self.by_network.entry(network.to_string()).or_default();
+        if !blocks.contains_key(block_ptr) {
+            self.blocks
+                .push_back((network.to_string(), block_ptr.clone()));
+        }
+        blocks
+            .entry(block_ptr.clone())
+            .or_default()
+            .insert(key, result);
+        self.len += 1;
+        true
+    }
+
+    pub fn get(
+        &self,
+        network: &str,
+        block_ptr: &BlockPtr,
+        key: &QueryHash,
+    ) -> Option<Arc<QueryResult>> {
+        self.by_network
+            .get(network)
+            .and_then(|blocks| blocks.get(block_ptr))
+            .and_then(|cache| cache.get(key))
+            .map(CheapClone::cheap_clone)
+    }
+
+    fn evict_oldest_block(&mut self) {
+        let (network, block_ptr) = match self.blocks.pop_front() {
+            Some(oldest) => oldest,
+            None => return,
+        };
+        if let Some(blocks) = self.by_network.get_mut(&network) {
+            if let Some(cache) = blocks.remove(&block_ptr) {
+                self.len -= cache.len();
+            }
+            if blocks.is_empty() {
+                self.by_network.remove(&network);
+            }
         }
-        fits_in_cache
     }
 }
 
@@ -133,15 +382,17 @@ pub struct QueryBlockCache {
     cache_by_network: Vec<(String, VecDeque<CacheByBlock>)>,
     max_weight: usize,
     max_blocks: usize,
+    policy: Arc<CachePolicy>,
 }
 
 impl QueryBlockCache {
-    pub fn new(max_blocks: usize, shard: u8, max_weight: usize) -> Self {
+    pub fn new(max_blocks: usize, shard: u8, max_weight: usize, policy: Arc<CachePolicy>) -> Self {
         QueryBlockCache {
             shard,
             cache_by_network: Vec::new(),
             max_weight,
             max_blocks,
+            policy,
         }
     }
 
@@ -200,8 +451,8 @@ impl QueryBlockCache {
                 let insert_time_ms = block.total_insert_time.as_millis();
                 let mut dead_inserts = 0;
                 let mut total_hits = 0;
-                for (_, hits) in block.cache.values() {
-                    let hits = hits.load(Ordering::SeqCst);
+                for entry in block.cache.values() {
+                    let hits = entry.hits.load(Ordering::SeqCst);
                     total_hits += hits;
                     if hits == 0 {
                         dead_inserts += 1;
@@ -214,6 +465,7 @@ impl QueryBlockCache {
                     "entries" => n_entries,
                     "avg_hits" => format!("{0:.2}", (total_hits as f64) / (n_entries as f64)),
                     "dead_inserts" => dead_inserts,
+                    "evictions" => block.evictions,
                     "fill_ratio" => format!("{0:.2}", (block.weight as f64) / (block.max_weight as f64)),
                     "avg_insert_time_ms" => format!("{0:.2}", insert_time_ms as f64 / (n_entries as f64)),
                 )
@@ -221,7 +473,8 @@ impl QueryBlockCache {
         }
 
         // Create a new cache by block, insert this entry, and add it to the QUERY_CACHE.
-        let mut cache_by_block = CacheByBlock::new(block_ptr, self.max_weight);
+        let mut cache_by_block =
+            CacheByBlock::new(block_ptr, self.max_weight, self.policy.cheap_clone());
         let cache_insert = cache_by_block.insert(key, result, weight);
         cache.push_front(cache_by_block);
         cache_insert
@@ -249,3 +502,108 @@ impl QueryBlockCache {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use graph::data::value::Object;
+
+    use super::*;
+
+    fn result(value: r::Value) -> Arc<QueryResult> {
+        let data = Object::from_iter(vec![("things".to_string(), value)]);
+        Arc::new(QueryResult::new(data))
+    }
+
+    fn block(number: i32, hash: u8) -> BlockPtr {
+        BlockPtr::from((vec![hash; 32], number))
+    }
+
+    fn deployment_result(deployment: &DeploymentHash) -> Arc<QueryResult> {
+        let mut result = QueryResult::new(Object::from_iter(vec![(
+            "things".to_string(),
+            r::Value::Null,
+        )]));
+        result.deployment = Some(deployment.clone());
+        Arc::new(result)
+    }
+
+    #[test]
+    fn deployment_quotas() {
+        let chatty = DeploymentHash::new("QmChatty").unwrap();
+        let valued = DeploymentHash::new("QmValued").unwrap();
+        let policy = CachePolicy {
+            eviction: QueryCacheEviction::Hits,
+            deployment_quota: 50,
+            deployment_weights: HashMap::from_iter(vec![("QmValued".to_string(), 2.0)]),
+        };
+        let mut cache = CacheByBlock::new(block(1, 1), 100, Arc::new(policy));
+
+        // The chatty deployment can only use half the cache, and evicts its
+        // own entries with the fewest hits once it reaches its quota
+        assert!(cache.insert([1; 32], deployment_result(&chatty), 30));
+        assert!(cache.get(&[1; 32]).is_some());
+        assert!(cache.insert([2; 32], deployment_result(&chatty), 20));
+        assert!(cache.insert([3; 32], deployment_result(&chatty), 20));
+        assert!(cache.get(&[1; 32]).is_some());
+        assert!(cache.get(&[2; 32]).is_none());
+        assert!(!cache.insert([4; 32], deployment_result(&chatty), 60));
+        assert_eq!(50, cache.weight);
+
+        // The weighted deployment may use the whole cache, and pushes out
+        // the chatty deployment
+        assert!(cache.insert([5; 32], deployment_result(&valued), 80));
+        assert!(cache.get(&[3; 32]).is_none());
+        assert!(cache.get(&[1; 32]).is_none());
+        assert_eq!(80, cache.weight);
+        assert_eq!(0, cache.deployment_weight(Some(&chatty)));
+
+        // Without eviction, results that do not fit are rejected
+        let policy = CachePolicy {
+            eviction: QueryCacheEviction::None,
+            deployment_quota: 100,
+            deployment_weights: HashMap::from_iter(vec![("QmChatty".to_string(), 0.0)]),
+        };
+        let mut cache = CacheByBlock::new(block(1, 1), 100, Arc::new(policy));
+        assert!(!cache.insert([1; 32], deployment_result(&chatty), 1));
+        assert!(cache.insert([2; 32], deployment_result(&valued), 60));
+        assert!(!cache.insert([3; 32], deployment_result(&valued), 60));
+    }
+
+    #[test]
+    fn empty_results() {
+        assert!(EmptyResultCache::is_empty_result(&result(r::Value::Null)));
+        assert!(EmptyResultCache::is_empty_result(&result(r::Value::List(
+            vec![]
+        ))));
+        assert!(!EmptyResultCache::is_empty_result(&result(r::Value::List(
+            vec![r::Value::Null]
+        ))));
+    }
+
+    #[test]
+    fn empty_result_cache_by_block() {
+        let mut cache = EmptyResultCache::new(3);
+        let empty = result(r::Value::Null);
+
+        assert!(cache.insert("mainnet", &block(1, 1), [1; 32], empty.cheap_clone()));
+        assert!(cache.get("mainnet", &block(1, 1), &[1; 32]).is_some());
+        assert!(cache.get("goerli", &block(1, 1), &[1; 32]).is_none());
+        assert!(!cache.insert("mainnet", &block(1, 1), [1; 32], empty.cheap_clone()));
+
+        // Results for other blocks, including older ones and the blocks of
+        // a reorg, are kept side by side
+        assert!(cache.insert("mainnet", &block(1, 2), [2; 32], empty.cheap_clone()));
+        assert!(cache.insert("mainnet", &block(0, 1), [3; 32], empty.cheap_clone()));
+        assert!(cache.get("mainnet", &block(1, 1), &[1; 32]).is_some());
+        assert!(cache.get("mainnet", &block(1, 2), &[1; 32]).is_none());
+        assert!(cache.get("mainnet", &block(1, 2), &[2; 32]).is_some());
+        assert!(cache.get("mainnet", &block(0, 1), &[3; 32]).is_some());
+
+        // When the cache is full, the block that was cached first is evicted
+        assert!(cache.insert("goerli", &block(5, 1), [4; 32], empty));
+        assert!(cache.get("mainnet", &block(1, 1), &[1; 32]).is_none());
+        assert!(cache.get("mainnet", &block(1, 2), &[2; 32]).is_some());
+        assert!(cache.get("goerli", &block(5, 1), &[4; 32]).is_some());
+        assert_eq!(3, cache.len);
+    }
+}
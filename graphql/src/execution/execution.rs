@@ -1,4 +1,5 @@
-use super::cache::{QueryBlockCache, QueryCache};
+use super::cache::{CachePolicy, EmptyResultCache, QueryBlockCache, QueryCache};
+use super::shared_cache::{self, SharedQueryCache};
 use crossbeam::atomic::AtomicCell;
 use graph::{
     data::{schema::META_FIELD_NAME, value::Object},
@@ -17,11 +18,13 @@ use graph::data::query::CacheStatus;
 use graph::env::CachedSubgraphIds;
 use graph::prelude::*;
 use graph::util::lfu_cache::LfuCache;
+use graph::util::otel;
 
 use super::QueryHash;
 use crate::execution::ast as a;
 use crate::introspection::{is_introspection_field, INTROSPECTION_QUERY_TYPE};
 use crate::prelude::*;
+use crate::schema::api::SERVICE_FIELD_NAME;
 use crate::schema::ast as sast;
 
 lazy_static! {
@@ -33,13 +36,31 @@ lazy_static! {
 
             // The memory budget is evenly divided among blocks and their shards.
             let max_weight = ENV_VARS.mappings. query_cache_max_mem / (blocks * shards as usize);
+            let policy = Arc::new(CachePolicy::from_env());
             let mut caches = Vec::new();
             for i in 0..shards {
                 let id = format!("query_block_cache_{}", i);
-                caches.push(TimedMutex::new(QueryBlockCache::new(blocks, i, max_weight), id))
+                let cache = QueryBlockCache::new(blocks, i, max_weight, policy.cheap_clone());
+                caches.push(TimedMutex::new(cache, id))
             }
             caches
     };
+    // Cache that is shared with other query nodes, if one is configured
+    static ref SHARED_QUERY_CACHE: Option<Arc<dyn SharedQueryCache>> = {
+        ENV_VARS.graphql.shared_cache_url.as_ref().map(|url| {
+            shared_cache::shared_cache_from_url(url, ENV_VARS.graphql.shared_cache_ttl)
+                .expect("GRAPH_QUERY_SHARED_CACHE_URL is invalid")
+        })
+    };
+    // Cache for results without data, sharded like the block cache
+    static ref QUERY_EMPTY_RESULT_CACHE: Vec<TimedMutex<EmptyResultCache>> = {
+        (0..ENV_VARS.query_block_cache_shards)
+            .map(|i| {
+                let id = format!("query_empty_result_cache_{}", i);
+                TimedMutex::new(EmptyResultCache::new(ENV_VARS.query_empty_result_cache_size), id)
+            })
+            .collect()
+    };
     static ref QUERY_HERD_CACHE: QueryCache<Arc<QueryResult>> = QueryCache::new("query_herd_cache");
     static ref QUERY_LFU_CACHE: Vec<TimedMutex<LfuCache<QueryHash, WeightedResult>>> = {
         std::iter::repeat_with(|| TimedMutex::new(LfuCache::new(), "query_lfu_cache"))
@@ -196,7 +217,10 @@ pub(crate) fn execute_root_selection_set_uncached(
         // the data_set SelectionSet
         if is_introspection_field(&field.name) {
             intro_set.push(field)?
-        } else if field.name == META_FIELD_NAME || field.name == "__typename" {
+        } else if field.name == META_FIELD_NAME
+            || field.name == SERVICE_FIELD_NAME
+            || field.name == "__typename"
+        {
             meta_items.push(field)
         } else {
             data_set.push(field)?
@@ -238,6 +262,34 @@ pub(crate) async fn execute_root_selection_set<R: Resolver>(
     // and once for insert.
     let mut key: Option<QueryHash> = None;
 
+    // Identical queries for the same block that run concurrently are only
+    // executed once, whether or not their results end up in a cache.
+    // JSONB and metadata queries use `BLOCK_NUMBER_MAX`. Ignore this case for two reasons:
+    // - Metadata queries are not cacheable.
+    // - Caching `BLOCK_NUMBER_MAX` would make this cache think all other blocks are old.
+    let herd_key = match block_ptr.as_ref() {
+        Some(block_ptr) if R::CACHEABLE && block_ptr.number != BLOCK_NUMBER_MAX => {
+            Some(cache_key(&ctx, &selection_set, block_ptr))
+        }
+        _ => None,
+    };
+
+    // Results without data are cached separately and independently of
+    // whether results for this subgraph are cached otherwise
+    let check_empty_cache = ENV_VARS.query_empty_result_cache_size > 0;
+    if check_empty_cache {
+        if let (Some(cache_key), Some(block_ptr), Some(network)) =
+            (herd_key, block_ptr.as_ref(), &ctx.query.network)
+        {
+            let shard = (cache_key[0] as usize) % QUERY_EMPTY_RESULT_CACHE.len();
+            let cache = QUERY_EMPTY_RESULT_CACHE[shard].lock(&ctx.logger);
+            if let Some(result) = cache.get(network, block_ptr, &cache_key) {
+                ctx.cache_status.store(CacheStatus::Hit);
+                return result;
+            }
+        }
+    }
+
     let should_check_cache = R::CACHEABLE
         && match ENV_VARS.cached_subgraph_ids {
             CachedSubgraphIds::All => true,
@@ -247,56 +299,78 @@ pub(crate) async fn execute_root_selection_set<R: Resolver>(
         };
 
     if should_check_cache {
-        if let (Some(block_ptr), Some(network)) = (block_ptr.as_ref(), &ctx.query.network) {
-            // JSONB and metadata queries use `BLOCK_NUMBER_MAX`. Ignore this case for two reasons:
-            // - Metadata queries are not cacheable.
-            // - Caching `BLOCK_NUMBER_MAX` would make this cache think all other blocks are old.
-            if block_ptr.number != BLOCK_NUMBER_MAX {
-                // Calculate the hash outside of the lock
-                let cache_key = cache_key(&ctx, &selection_set, block_ptr);
-                let shard = (cache_key[0] as usize) % QUERY_BLOCK_CACHE.len();
-
-                // Check if the response is cached, first in the recent blocks cache,
-                // and then in the LfuCache for historical queries
-                // The blocks are used to delimit how long locks need to be held
-                {
-                    let cache = QUERY_BLOCK_CACHE[shard].lock(&ctx.logger);
-                    if let Some(result) = cache.get(network, block_ptr, &cache_key) {
-                        ctx.cache_status.store(CacheStatus::Hit);
-                        return result;
-                    }
+        if let (Some(cache_key), Some(block_ptr), Some(network)) =
+            (herd_key, block_ptr.as_ref(), &ctx.query.network)
+        {
+            let shard = (cache_key[0] as usize) % QUERY_BLOCK_CACHE.len();
+
+            // Check if the response is cached, first in the recent blocks cache,
+            // and then in the LfuCache for historical queries
+            // The blocks are used to delimit how long locks need to be held
+            {
+                let cache = QUERY_BLOCK_CACHE[shard].lock(&ctx.logger);
+                if let Some(result) = cache.get(network, block_ptr, &cache_key) {
+                    ctx.cache_status.store(CacheStatus::Hit);
+                    return result;
                 }
-                {
-                    let mut cache = QUERY_LFU_CACHE[shard].lock(&ctx.logger);
-                    if let Some(weighted) = cache.get(&cache_key) {
+            }
+            {
+                let mut cache = QUERY_LFU_CACHE[shard].lock(&ctx.logger);
+                if let Some(weighted) = cache.get(&cache_key) {
+                    ctx.cache_status.store(CacheStatus::Hit);
+                    return weighted.result.cheap_clone();
+                }
+            }
+            // Finally, check whether another query node already computed
+            // the result
+            if let Some(shared) = SHARED_QUERY_CACHE.as_ref() {
+                let lookup = shared_cache::with_timeout(
+                    ENV_VARS.graphql.shared_cache_timeout,
+                    shared.get(&cache_key),
+                )
+                .await;
+                match lookup {
+                    Ok(Some(mut result)) => {
+                        result.deployment = Some(ctx.query.schema.id().clone());
+                        result.block_ptr = Some(block_ptr.clone());
                         ctx.cache_status.store(CacheStatus::Hit);
-                        return weighted.result.cheap_clone();
+                        return Arc::new(result);
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        debug!(ctx.logger, "Shared query cache lookup failed";
+                                               "error" => e.to_string())
                     }
                 }
-                key = Some(cache_key);
             }
+            key = Some(cache_key);
         }
     }
 
     let execute_ctx = ctx.cheap_clone();
     let execute_selection_set = selection_set.cheap_clone();
     let execute_root_type = root_type.cheap_clone();
+    let execute_block_ptr = block_ptr.clone();
     let run_query = async move {
         let _permit = execute_ctx.resolver.query_permit().await;
 
         let logger = execute_ctx.logger.clone();
         let query_text = execute_ctx.query.query_text.cheap_clone();
         let variables_text = execute_ctx.query.variables_text.cheap_clone();
+        let span_ctx = otel::current();
         match graph::spawn_blocking_allow_panic(move || {
-            let mut query_res = QueryResult::from(execute_root_selection_set_uncached(
-                &execute_ctx,
-                &execute_selection_set,
-                &execute_root_type,
-            ));
+            let mut query_res = QueryResult::from(otel::with_context(span_ctx, || {
+                execute_root_selection_set_uncached(
+                    &execute_ctx,
+                    &execute_selection_set,
+                    &execute_root_type,
+                )
+            }));
 
             // Unwrap: In practice should never fail, but if it does we will catch the panic.
             execute_ctx.resolver.post_process(&mut query_res).unwrap();
             query_res.deployment = Some(execute_ctx.query.schema.id().clone());
+            query_res.block_ptr = execute_block_ptr;
             Arc::new(query_res)
         })
         .await
@@ -324,7 +398,7 @@ pub(crate) async fn execute_root_selection_set<R: Resolver>(
         }
     };
 
-    let (result, herd_hit) = if let Some(key) = key {
+    let (result, herd_hit) = if let Some(key) = herd_key {
         QUERY_HERD_CACHE
             .cached_query(key, run_query, &ctx.logger)
             .await
@@ -341,9 +415,48 @@ pub(crate) async fn execute_root_selection_set<R: Resolver>(
     // head can cause the legitimate cache to be thrown out.
     // It would be redundant to insert herd cache hits.
     let no_cache = herd_hit || result.has_errors();
+    if let (false, true, Some(cache_key), Some(block_ptr), Some(network)) = (
+        no_cache,
+        check_empty_cache,
+        herd_key,
+        block_ptr.as_ref(),
+        &ctx.query.network,
+    ) {
+        if EmptyResultCache::is_empty_result(&result) {
+            let shard = (cache_key[0] as usize) % QUERY_EMPTY_RESULT_CACHE.len();
+            let inserted = QUERY_EMPTY_RESULT_CACHE[shard].lock(&ctx.logger).insert(
+                network,
+                block_ptr,
+                cache_key,
+                result.cheap_clone(),
+            );
+            if inserted {
+                ctx.cache_status.store(CacheStatus::Insert);
+            }
+        }
+    }
     if let (false, Some(key), Some(block_ptr), Some(network)) =
         (no_cache, key, block_ptr, &ctx.query.network)
     {
+        // Storing the result in the shared cache happens in the background
+        // so that the response does not have to wait for it
+        if let Some(shared) = SHARED_QUERY_CACHE.as_ref() {
+            let shared = shared.cheap_clone();
+            let result = result.cheap_clone();
+            let logger = ctx.logger.cheap_clone();
+            graph::spawn(async move {
+                let insert = shared_cache::with_timeout(
+                    ENV_VARS.graphql.shared_cache_timeout,
+                    shared.insert(&key, &result),
+                )
+                .await;
+                if let Err(e) = insert {
+                    debug!(logger, "Failed to store result in shared query cache";
+                                   "error" => e.to_string())
+                }
+            });
+        }
+
         // Calculate the weight outside the lock.
         let weight = result.weight();
         let shard = (key[0] as usize) % QUERY_BLOCK_CACHE.len();
@@ -632,9 +745,10 @@ fn resolve_field_value_for_list_type(
                     .resolve_objects(field_value, field, field_definition, t.into())
                     .map_err(|e| vec![e]),
 
-                s::TypeDefinition::Union(_) => Err(vec![QueryExecutionError::Unimplemented(
-                    "unions".to_owned(),
-                )]),
+                s::TypeDefinition::Union(t) => ctx
+                    .resolver
+                    .resolve_union_objects(field_value, field, t)
+                    .map_err(|e| vec![e]),
 
                 s::TypeDefinition::InputObject(_) => {
                     unreachable!("input objects are never resolved")
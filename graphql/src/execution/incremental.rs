@@ -0,0 +1,439 @@
+use std::collections::BTreeMap;
+
+use graph::data::query::IncrementalResult;
+use graph::data::value::Object;
+use graph::prelude::{r, QueryError, QueryExecutionError};
+
+use crate::execution::ast as a;
+
+/// The fields that one or more `@defer` fragments with the same label
+/// select on the objects at `path`
+#[derive(Debug)]
+pub(crate) struct DeferredFragment {
+    /// The response keys of the fields leading from the root of the query
+    /// to the objects on which the fragment was spread. The path does not
+    /// contain list indices
+    path: Vec<String>,
+    label: Option<String>,
+    /// The response keys of the deferred fields
+    keys: Vec<String>,
+}
+
+/// A toplevel list field with `@stream`. The first `initial_count` items
+/// are part of the initial payload, and the remaining items up to `first`
+/// are sent in later payloads
+#[derive(Clone, Debug)]
+pub(crate) struct StreamedField {
+    field: a::Field,
+    label: Option<String>,
+    initial_count: u32,
+    first: u32,
+    skip: u32,
+}
+
+impl StreamedField {
+    pub fn response_key(&self) -> &str {
+        self.field.response_key()
+    }
+
+    pub fn initial_count(&self) -> u32 {
+        self.initial_count
+    }
+
+    /// Return `true` if there might be more items after the first
+    /// `delivered` items of the list
+    pub fn has_more(&self, delivered: u32) -> bool {
+        delivered < self.first
+    }
+
+    /// The number of items in the batch that starts after `delivered`
+    /// items
+    pub fn batch_len(&self, delivered: u32, batch_size: u32) -> u32 {
+        batch_size.min(self.first - delivered)
+    }
+
+    /// The selection set for fetching the next batch of up to `batch_size`
+    /// items after the first `delivered` items of the list
+    pub fn batch(
+        &self,
+        root: &a::SelectionSet,
+        delivered: u32,
+        batch_size: u32,
+    ) -> Result<a::SelectionSet, QueryExecutionError> {
+        let first = self.batch_len(delivered, batch_size);
+        let mut field = self.field.clone();
+        field.set_argument("first", r::Value::Int(first as i64));
+        field.set_argument("skip", r::Value::Int((self.skip + delivered) as i64));
+        let mut set = a::SelectionSet::empty_from(root);
+        set.push(&field)?;
+        Ok(set)
+    }
+
+    /// Turn the items of a batch that starts after `delivered` items into
+    /// the result for a payload
+    pub fn items(&self, delivered: u32, items: Vec<r::Value>) -> IncrementalResult {
+        IncrementalResult::items(self.path(delivered), self.label.clone(), items)
+    }
+
+    /// Report errors for the batch that starts after `delivered` items
+    pub fn errors(&self, delivered: u32, errors: Vec<QueryError>) -> IncrementalResult {
+        IncrementalResult::errors(self.path(delivered), self.label.clone(), errors)
+    }
+
+    fn path(&self, delivered: u32) -> Vec<r::Value> {
+        vec![
+            r::Value::String(self.response_key().to_owned()),
+            r::Value::Int(delivered as i64),
+        ]
+    }
+}
+
+/// A toplevel list field with `@export`. The entities that the field
+/// matches are read through a database cursor and sent in batches after
+/// the initial payload. Since exports do not hold all entities in memory,
+/// their `first` is bounded by `GRAPH_GRAPHQL_EXPORT_MAX_ROWS` instead of
+/// the limit for other list fields; `skip` and the complexity of the query
+/// are limited as usual. Since entities
+/// are sent as they come out of the database, only fields that are not
+/// objects can be selected for them
+#[derive(Clone, Debug)]
+pub(crate) struct ExportedField {
+    field: a::Field,
+    label: Option<String>,
+}
+
+impl ExportedField {
+    fn new(
+        field: &a::Field,
+        label: Option<String>,
+        enable_exports: bool,
+    ) -> Result<Self, QueryExecutionError> {
+        if !enable_exports {
+            return Err(QueryExecutionError::NotSupported(
+                "`@export` is not enabled on this node".to_owned(),
+            ));
+        }
+        for (_, mut fields) in field.selection_set.fields() {
+            if let Some(child) = fields.find(|f| !f.selection_set.is_empty()) {
+                return Err(QueryExecutionError::NotSupported(format!(
+                    "`{}` uses `@export` and can therefore not select the object field `{}`",
+                    field.response_key(),
+                    child.response_key()
+                )));
+            }
+        }
+        Ok(ExportedField {
+            field: field.clone(),
+            label,
+        })
+    }
+
+    pub fn field(&self) -> &a::Field {
+        &self.field
+    }
+
+    /// Turn an entity as it comes from the store into the object with the
+    /// fields that the query selected for it
+    fn object(&self, entity: BTreeMap<String, r::Value>) -> r::Value {
+        let typename = match entity.get("__typename") {
+            Some(r::Value::String(typename)) => typename.as_str(),
+            _ => "",
+        };
+        let object = self
+            .field
+            .selection_set
+            .fields()
+            .find(|(object_type, _)| object_type.name == typename)
+            .into_iter()
+            .flat_map(|(_, fields)| fields)
+            .map(|field| {
+                let value = entity.get(&field.name).cloned().unwrap_or(r::Value::Null);
+                (field.response_key().to_owned(), value)
+            })
+            .collect::<Object>();
+        r::Value::Object(object)
+    }
+
+    /// Turn the entities of a batch that starts after `delivered` entities
+    /// into the result for a payload
+    pub fn items(
+        &self,
+        delivered: usize,
+        entities: Vec<BTreeMap<String, r::Value>>,
+    ) -> IncrementalResult {
+        let items = entities
+            .into_iter()
+            .map(|entity| self.object(entity))
+            .collect();
+        IncrementalResult::items(self.path(delivered), self.label.clone(), items)
+    }
+
+    /// Report errors for the batch that starts after `delivered` entities
+    pub fn errors(&self, delivered: usize, errors: Vec<QueryError>) -> IncrementalResult {
+        IncrementalResult::errors(self.path(delivered), self.label.clone(), errors)
+    }
+
+    fn path(&self, delivered: usize) -> Vec<r::Value> {
+        vec![
+            r::Value::String(self.field.response_key().to_owned()),
+            r::Value::Int(delivered as i64),
+        ]
+    }
+}
+
+/// How to execute the toplevel fields that share a block constraint when
+/// the response is delivered incrementally
+#[derive(Debug)]
+pub(crate) struct IncrementalPlan {
+    /// The selection set for the initial payload; it does not contain any
+    /// deferred fields, and streamed fields only ask for their initial
+    /// items. Streamed fields with an `initialCount` of 0 are not part of
+    /// it at all since they are known to be empty
+    pub initial: a::SelectionSet,
+    /// The response keys of the streamed fields with an `initialCount` of 0
+    /// and of the exported fields
+    pub empty: Vec<String>,
+    /// The selection set that resolves all deferred fragments for the
+    /// objects that are part of the initial payload, and the fragments
+    /// that need to be extracted from its result
+    pub deferred: Option<(a::SelectionSet, Vec<DeferredFragment>)>,
+    pub streams: Vec<StreamedField>,
+    pub exports: Vec<ExportedField>,
+}
+
+impl IncrementalPlan {
+    /// Plan the execution of `root`, a selection set of toplevel fields.
+    /// Only toplevel list fields are streamed or exported; `@stream` and
+    /// `@export` on nested fields are ignored, and their items are sent
+    /// together with their parent. Using `@export` is an error unless
+    /// `enable_exports` is set
+    pub fn new(
+        root: &a::SelectionSet,
+        max_first: u32,
+        max_skip: u32,
+        enable_exports: bool,
+    ) -> Result<Self, QueryExecutionError> {
+        let mut limited = root.clone();
+        let mut streams = Vec::new();
+        let mut empty = Vec::new();
+        let mut exports = Vec::new();
+        for field in limited.fields_mut() {
+            // List fields have a `first` argument
+            let export_label = match field.export_directive() {
+                Some(dir) if field.argument_value("first").is_some() => Some(dir.label()),
+                _ => None,
+            };
+            if let Some(label) = export_label {
+                exports.push(ExportedField::new(field, label, enable_exports)?);
+                empty.push(field.response_key().to_owned());
+                continue;
+            }
+
+            let dir = match field.stream_directive() {
+                Some(dir) => dir,
+                None => continue,
+            };
+            let first = match field.argument_value("first") {
+                Some(r::Value::Int(n)) if *n > 0 && *n <= max_first as i64 => *n as u32,
+                Some(r::Value::Int(n)) => {
+                    return Err(QueryExecutionError::RangeArgumentsError(
+                        "first", max_first, *n,
+                    ))
+                }
+                // Not a list field
+                _ => continue,
+            };
+            let skip = match field.argument_value("skip") {
+                Some(r::Value::Int(n)) if *n >= 0 && *n <= max_skip as i64 => *n as u32,
+                Some(r::Value::Int(n)) => {
+                    return Err(QueryExecutionError::RangeArgumentsError(
+                        "skip", max_skip, *n,
+                    ))
+                }
+                _ => 0,
+            };
+            let initial_count = match dir.argument_value("initialCount") {
+                Some(r::Value::Int(n)) if *n >= 0 => (*n as u32).min(first),
+                Some(r::Value::Int(n)) => {
+                    return Err(QueryExecutionError::ValueParseError(
+                        "initialCount".to_owned(),
+                        format!("must not be negative but is {}", n),
+                    ))
+                }
+                _ => 0,
+            };
+            streams.push(StreamedField {
+                field: field.clone(),
+                label: dir.label(),
+                initial_count,
+                first,
+                skip,
+            });
+            if initial_count == 0 {
+                empty.push(field.response_key().to_owned());
+            }
+            field.set_argument("first", r::Value::Int(initial_count.max(1) as i64));
+        }
+
+        let fragments = deferred_fragments(&limited);
+        let deferred = if fragments.is_empty() {
+            None
+        } else {
+            let mut set = limited.clone();
+            remove_fields(&mut set, &empty)?;
+            Some((set, fragments))
+        };
+
+        let mut initial = limited.without_deferred();
+        remove_fields(&mut initial, &empty)?;
+
+        Ok(IncrementalPlan {
+            initial,
+            empty,
+            deferred,
+            streams,
+            exports,
+        })
+    }
+}
+
+/// Remove the toplevel fields with the given response keys from `set`
+fn remove_fields(set: &mut a::SelectionSet, keys: &[String]) -> Result<(), QueryExecutionError> {
+    if keys.is_empty() {
+        return Ok(());
+    }
+    let mut kept = a::SelectionSet::empty_from(set);
+    for (_, fields) in set.fields() {
+        for field in fields {
+            if !keys.iter().any(|key| key == field.response_key()) {
+                kept.push(field)?;
+            }
+        }
+    }
+    *set = kept;
+    Ok(())
+}
+
+/// Find all deferred fragments in `set`. Fields that are nested inside a
+/// deferred field are sent together with that field
+fn deferred_fragments(set: &a::SelectionSet) -> Vec<DeferredFragment> {
+    fn collect(set: &a::SelectionSet, path: &mut Vec<String>, frags: &mut Vec<DeferredFragment>) {
+        for (_, fields) in set.fields() {
+            for field in fields {
+                let key = field.response_key().to_owned();
+                match field.defer_directive() {
+                    Some(dir) => {
+                        let label = dir.label();
+                        match frags
+                            .iter_mut()
+                            .find(|frag| &frag.path == path && frag.label == label)
+                        {
+                            Some(frag) => {
+                                if !frag.keys.contains(&key) {
+                                    frag.keys.push(key)
+                                }
+                            }
+                            None => frags.push(DeferredFragment {
+                                path: path.clone(),
+                                label,
+                                keys: vec![key],
+                            }),
+                        }
+                    }
+                    None if !field.selection_set.is_empty() => {
+                        path.push(key);
+                        collect(&field.selection_set, path, frags);
+                        path.pop();
+                    }
+                    None => { /* leaf field that is not deferred */ }
+                }
+            }
+        }
+    }
+
+    let mut frags = Vec::new();
+    collect(set, &mut Vec::new(), &mut frags);
+    frags
+}
+
+/// Extract the data for each of the `fragments` from `data`, the result of
+/// executing the deferred selection set. There is one result for each
+/// object that the fragment was spread on
+pub(crate) fn deferred_results(
+    data: &Object,
+    fragments: &[DeferredFragment],
+) -> Vec<IncrementalResult> {
+    fn walk_object(
+        obj: &Object,
+        path: &[String],
+        current: &mut Vec<r::Value>,
+        frag: &DeferredFragment,
+        results: &mut Vec<IncrementalResult>,
+    ) {
+        match path.split_first() {
+            None => {
+                let data: Object = frag
+                    .keys
+                    .iter()
+                    .filter_map(|key| obj.get(key).map(|value| (key.clone(), value.clone())))
+                    .collect();
+                // Objects of a type that the fragment does not apply to do
+                // not have any of the deferred fields
+                if data.iter().next().is_some() {
+                    results.push(IncrementalResult::data(
+                        current.clone(),
+                        frag.label.clone(),
+                        data,
+                    ));
+                }
+            }
+            Some((key, rest)) => {
+                if let Some(value) = obj.get(key) {
+                    current.push(r::Value::String(key.clone()));
+                    walk(value, rest, current, frag, results);
+                    current.pop();
+                }
+            }
+        }
+    }
+
+    fn walk(
+        value: &r::Value,
+        path: &[String],
+        current: &mut Vec<r::Value>,
+        frag: &DeferredFragment,
+        results: &mut Vec<IncrementalResult>,
+    ) {
+        match value {
+            r::Value::Object(obj) => walk_object(obj, path, current, frag, results),
+            r::Value::List(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    current.push(r::Value::Int(i as i64));
+                    walk(value, path, current, frag, results);
+                    current.pop();
+                }
+            }
+            _ => { /* null or a scalar; nothing was deferred here */ }
+        }
+    }
+
+    let mut results = Vec::new();
+    for frag in fragments {
+        walk_object(data, &frag.path, &mut Vec::new(), frag, &mut results);
+    }
+    results
+}
+
+/// Report `errors` for all deferred fragments
+pub(crate) fn deferred_errors(
+    fragments: &[DeferredFragment],
+    errors: &[QueryError],
+) -> Vec<IncrementalResult> {
+    fragments
+        .iter()
+        .map(|frag| {
+            let path = frag.path.iter().cloned().map(r::Value::String).collect();
+            IncrementalResult::errors(path, frag.label.clone(), errors.to_vec())
+        })
+        .collect()
+}
@@ -1,9 +1,13 @@
 mod cache;
 /// Implementation of the GraphQL execution algorithm.
 mod execution;
+/// Splitting queries for incremental delivery with `@defer` and `@stream`
+pub(crate) mod incremental;
 mod query;
 /// Common trait for field resolvers used in the execution.
 mod resolver;
+/// Query result caches that are shared between query nodes
+mod shared_cache;
 
 /// Our representation of a query AST
 pub mod ast;
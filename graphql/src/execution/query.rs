@@ -11,7 +11,10 @@ use std::sync::Arc;
 use std::time::Instant;
 use std::{collections::hash_map::DefaultHasher, convert::TryFrom};
 
-use graph::data::graphql::{ext::TypeExt, ObjectOrInterface};
+use graph::data::graphql::{
+    ext::{DirectiveFinder, TypeExt},
+    ObjectOrInterface,
+};
 use graph::data::query::QueryExecutionError;
 use graph::data::query::{Query as GraphDataQuery, QueryVariables};
 use graph::data::schema::ApiSchema;
@@ -19,6 +22,7 @@ use graph::prelude::{info, o, q, r, s, BlockNumber, CheapClone, Logger, TryFromV
 
 use crate::execution::ast as a;
 use crate::query::{ast as qast, ext::BlockConstraint};
+use crate::schema::api::{AGGREGATE_DIRECTIVE, CONNECTION_DIRECTIVE};
 use crate::schema::ast::{self as sast};
 use crate::values::coercion;
 use crate::{execution::get_field, schema::api::ErrorPolicy};
@@ -69,6 +73,7 @@ pub enum ComplexityError {
 enum Kind {
     Query,
     Subscription,
+    Mutation,
 }
 
 /// Helper to log the fields in a `SelectionSet` without cloning. Writes
@@ -119,6 +124,8 @@ pub struct Query {
     pub selection_set: Arc<a::SelectionSet>,
     /// The ShapeHash of the original query
     pub shape_hash: u64,
+    /// The complexity of the query
+    pub complexity: u64,
 
     pub network: Option<String>,
 
@@ -185,10 +192,13 @@ impl Query {
             q::OperationDefinition::Subscription(q::Subscription { selection_set, .. }) => {
                 (Kind::Subscription, selection_set)
             }
-            q::OperationDefinition::Mutation(_) => {
-                return Err(vec![QueryExecutionError::NotSupported(
-                    "Mutations are not supported".to_owned(),
-                )])
+            q::OperationDefinition::Mutation(q::Mutation { selection_set, .. }) => {
+                if schema.mutation_type.is_none() {
+                    return Err(vec![QueryExecutionError::NotSupported(
+                        "Mutations are not supported".to_owned(),
+                    )]);
+                }
+                (Kind::Mutation, selection_set)
             }
         };
 
@@ -208,6 +218,7 @@ impl Query {
         let root_type = match kind {
             Kind::Query => schema.query_type.as_ref(),
             Kind::Subscription => schema.subscription_type.as_ref().unwrap(),
+            Kind::Mutation => schema.mutation_type.as_ref().unwrap(),
         };
         // Use an intermediate struct so we can modify the query before
         // enclosing it in an Arc
@@ -220,10 +231,8 @@ impl Query {
         };
 
         // It's important to check complexity first, so `validate_fields`
-        // doesn't risk a stack overflow from invalid queries. We don't
-        // really care about the resulting complexity, only that all the
-        // checks that `check_complexity` performs pass successfully
-        let _ = raw_query.check_complexity(max_complexity, max_depth)?;
+        // doesn't risk a stack overflow from invalid queries
+        let complexity = raw_query.check_complexity(max_complexity, max_depth)?;
         raw_query.validate_fields()?;
         let selection_set = raw_query.convert()?;
 
@@ -231,6 +240,7 @@ impl Query {
             schema,
             selection_set: Arc::new(selection_set),
             shape_hash: query.shape_hash,
+            complexity,
             kind,
             network,
             logger,
@@ -303,7 +313,7 @@ impl Query {
     pub fn is_query(&self) -> bool {
         match self.kind {
             Kind::Query => true,
-            Kind::Subscription => false,
+            Kind::Subscription | Kind::Mutation => false,
         }
     }
 
@@ -311,7 +321,15 @@ impl Query {
     pub fn is_subscription(&self) -> bool {
         match self.kind {
             Kind::Subscription => true,
-            Kind::Query => false,
+            Kind::Query | Kind::Mutation => false,
+        }
+    }
+
+    /// Return `true` if this is a mutation, not a query or a subscription
+    pub fn is_mutation(&self) -> bool {
+        match self.kind {
+            Kind::Mutation => true,
+            Kind::Query | Kind::Subscription => false,
         }
     }
 
@@ -468,9 +486,15 @@ impl<'s> RawQuery<'s> {
         max_depth: u8,
         depth: u8,
         visited_fragments: &'a HashSet<&'a str>,
+        connection_first: Option<u64>,
+        in_collection: bool,
     ) -> Result<u64, ComplexityError> {
         use ComplexityError::*;
 
+        // The `nodes` of a connection are limited by the `first` argument
+        // of the connection field
+        let in_connection = is_connection_type(ty);
+
         if depth >= max_depth {
             return Err(TooDeep);
         }
@@ -481,8 +505,14 @@ impl<'s> RawQuery<'s> {
             .try_fold(0, |total_complexity, selection| {
                 match selection {
                     q::Selection::Field(field) => {
-                        // Empty selection sets are the base case.
+                        // Empty selection sets are the base case. Counting
+                        // all entities for `totalCount` has a fixed cost.
                         if field.selection_set.items.is_empty() {
+                            if in_connection && field.name == "totalCount" {
+                                return Ok(
+                                    total_complexity + ENV_VARS.graphql.total_count_complexity
+                                );
+                            }
                             return Ok(total_complexity);
                         }
 
@@ -501,33 +531,91 @@ impl<'s> RawQuery<'s> {
                         }
                         .ok_or(Invalid)?;
 
+                        let field_type = self
+                            .schema
+                            .get_named_type(s_field.field_type.get_base_type())
+                            .ok_or(Invalid)?;
+                        let first =
+                            qast::get_argument_value(&field.arguments, "first").and_then(|arg| {
+                                match arg {
+                                    q::Value::Int(n) => Some(n.as_i64()? as u64),
+                                    _ => None,
+                                }
+                            });
+                        let skip =
+                            qast::get_argument_value(&field.arguments, "skip").and_then(|arg| {
+                                match arg {
+                                    q::Value::Int(n) => Some(n.as_i64()? as u64),
+                                    _ => None,
+                                }
+                            });
+                        let is_connection = is_connection_type(field_type);
+                        let is_collection =
+                            !is_connection && sast::is_list_or_non_null_list_field(&s_field);
                         let field_complexity = self.complexity_inner(
-                            self.schema
-                                .get_named_type(s_field.field_type.get_base_type())
-                                .ok_or(Invalid)?,
+                            field_type,
                             &field.selection_set,
                             max_depth,
                             depth + 1,
                             visited_fragments,
+                            if is_connection {
+                                first.or(Some(100))
+                            } else {
+                                None
+                            },
+                            in_collection || is_collection,
                         )?;
 
-                        // Non-collection queries pass through.
-                        if !sast::is_list_or_non_null_list_field(&s_field) {
-                            return Ok(total_complexity + field_complexity);
+                        // Fulltext searches have a fixed cost on top of the
+                        // entities they return
+                        let fulltext_complexity = if s_field.find_directive("fulltext").is_some() {
+                            ENV_VARS.graphql.fulltext_complexity
+                        } else {
+                            0
+                        };
+
+                        // Aggregates scan all matching entities, just like
+                        // the `totalCount` of a connection
+                        if s_field.find_directive(AGGREGATE_DIRECTIVE).is_some() {
+                            return Ok(total_complexity
+                                + field_complexity
+                                + ENV_VARS.graphql.total_count_complexity);
+                        }
+
+                        // Non-collection queries and connections pass through.
+                        if !is_collection {
+                            return Ok(total_complexity + field_complexity + fulltext_complexity);
                         }
 
-                        // For collection queries, check the `first` argument.
-                        let max_entities = qast::get_argument_value(&field.arguments, "first")
-                            .and_then(|arg| match arg {
-                                q::Value::Int(n) => Some(n.as_i64()? as u64),
-                                _ => None,
+                        // For collection queries, check the `first` and
+                        // `skip` arguments.
+                        let max_entities = match connection_first {
+                            Some(first) if in_connection => first,
+                            _ => first.unwrap_or(100),
+                        };
+                        let complexity = max_entities
+                            .checked_mul(ENV_VARS.graphql.first_complexity_weight)
+                            .and_then(|entities| {
+                                entities.checked_add(max_entities.checked_mul(field_complexity)?)
+                            })
+                            .and_then(|complexity| {
+                                complexity.checked_add(
+                                    skip.unwrap_or(0)
+                                        .checked_mul(ENV_VARS.graphql.skip_complexity_weight)?,
+                                )
                             })
-                            .unwrap_or(100);
-                        max_entities
-                            .checked_add(
-                                max_entities.checked_mul(field_complexity).ok_or(Overflow)?,
-                            )
-                            .ok_or(Overflow)
+                            .and_then(|complexity| complexity.checked_add(fulltext_complexity))
+                            .ok_or(Overflow)?;
+
+                        // Collections inside collections are run for each
+                        // entity of the outer collection
+                        if in_collection {
+                            complexity
+                                .checked_mul(ENV_VARS.graphql.nested_collection_complexity_weight)
+                                .ok_or(Overflow)
+                        } else {
+                            Ok(complexity)
+                        }
                     }
                     q::Selection::FragmentSpread(fragment) => {
                         let def = self.fragments.get(&fragment.fragment_name).unwrap();
@@ -545,6 +633,8 @@ impl<'s> RawQuery<'s> {
                             max_depth,
                             depth + 1,
                             &visited_fragments,
+                            connection_first,
+                            in_collection,
                         )
                     }
                     q::Selection::InlineFragment(fragment) => {
@@ -560,6 +650,8 @@ impl<'s> RawQuery<'s> {
                             max_depth,
                             depth + 1,
                             visited_fragments,
+                            connection_first,
+                            in_collection,
                         )
                     }
                 }
@@ -580,6 +672,8 @@ impl<'s> RawQuery<'s> {
             max_depth,
             0,
             &HashSet::new(),
+            None,
+            false,
         ) {
             Ok(complexity) => Ok(complexity),
             Err(ComplexityError::Invalid) => Ok(0),
@@ -697,6 +791,15 @@ impl<'s> RawQuery<'s> {
     }
 }
 
+/// Whether `ty` is one of the `<type>_connection` types that wrap the
+/// `nodes` of a `<types>Connection` field
+fn is_connection_type(ty: &s::TypeDefinition) -> bool {
+    match ty {
+        s::TypeDefinition::Object(t) => t.find_directive(CONNECTION_DIRECTIVE).is_some(),
+        _ => false,
+    }
+}
+
 struct Transform {
     schema: Arc<ApiSchema>,
     variables: HashMap<String, r::Value>,
@@ -947,12 +1050,10 @@ impl Transform {
                     }
                 }
                 q::Selection::FragmentSpread(spread) => {
-                    // TODO: we ignore the directives here (and so did the
-                    // old implementation), but that seems wrong
                     let q::FragmentSpread {
                         position: _,
                         fragment_name,
-                        directives: _,
+                        directives: spread_directives,
                     } = spread;
                     let frag = self.fragments.get(&fragment_name).unwrap();
                     if visited_fragments.insert(fragment_name) {
@@ -963,8 +1064,14 @@ impl Transform {
                             directives,
                             selection_set,
                         } = frag;
+                        // Directives on the spread, like `@defer`, apply to
+                        // all the fields of the fragment
+                        let directives = spread_directives
+                            .into_iter()
+                            .chain(directives.iter().cloned())
+                            .collect();
                         self.expand_fragment(
-                            directives.clone(),
+                            directives,
                             Some(type_condition),
                             type_set,
                             selection_set.clone(),
@@ -39,6 +39,17 @@ pub trait Resolver: Sized + Send + Sync + 'static {
         object_type: ObjectOrInterface<'_>,
     ) -> Result<r::Value, QueryExecutionError>;
 
+    /// Resolves a list of objects of a union type, `prefetched_objects` is
+    /// `Some` if the parent already calculated the value.
+    fn resolve_union_objects(
+        &self,
+        _prefetched_objects: Option<r::Value>,
+        _field: &a::Field,
+        _union_type: &s::UnionType,
+    ) -> Result<r::Value, QueryExecutionError> {
+        Err(QueryExecutionError::Unimplemented("unions".to_owned()))
+    }
+
     /// Resolves an enum value for a given enum type.
     fn resolve_enum_value(
         &self,
@@ -79,7 +79,7 @@ fn type_definition_object(
                 object_type_object(schema, type_objects, object_type)
             }
             s::TypeDefinition::Scalar(scalar_type) => scalar_type_object(scalar_type),
-            s::TypeDefinition::Union(union_type) => union_type_object(schema, union_type),
+            s::TypeDefinition::Union(union_type) => union_type_object(union_type),
         };
 
         type_objects.insert(type_name.to_owned(), type_object.clone());
@@ -213,21 +213,15 @@ fn scalar_type_object(scalar_type: &s::ScalarType) -> r::Value {
     }
 }
 
-fn union_type_object(schema: &Schema, union_type: &s::UnionType) -> r::Value {
+fn union_type_object(union_type: &s::UnionType) -> r::Value {
     object! {
         name: union_type.name.to_owned(),
         kind: r::Value::Enum(String::from("UNION")),
         description: union_type.description.clone(),
         possibleTypes:
-            schema.document.get_object_type_definitions()
+            union_type.types
                 .iter()
-                .filter(|object_type| {
-                    object_type
-                        .implements_interfaces
-                        .iter()
-                        .any(|implemented_name| implemented_name == &union_type.name)
-                })
-                .map(|object_type| r::Value::String(object_type.name.to_owned()))
+                .map(|name| r::Value::String(name.to_owned()))
                 .collect::<Vec<_>>(),
     }
 }
@@ -306,6 +300,7 @@ pub struct IntrospectionResolver {
     _logger: Logger,
     type_objects: TypeObjectsMap,
     directives: r::Value,
+    mutation_type: Option<String>,
 }
 
 impl IntrospectionResolver {
@@ -318,10 +313,16 @@ impl IntrospectionResolver {
         // Generate queryable objects for all directives in the schema
         let directives = schema_directive_objects(schema, &mut type_objects);
 
+        let mutation_type = schema
+            .document
+            .get_root_mutation_type()
+            .map(|mutation_type| mutation_type.name.clone());
+
         IntrospectionResolver {
             _logger: logger,
             type_objects,
             directives,
+            mutation_type,
         }
     }
 
@@ -335,7 +336,11 @@ impl IntrospectionResolver {
                 self.type_objects
                     .get(&String::from("Subscription"))
                     .cloned(),
-            mutationType: r::Value::Null,
+            mutationType:
+                self.mutation_type
+                    .as_ref()
+                    .and_then(|name| self.type_objects.get(name))
+                    .cloned(),
             types: self.type_objects.values().cloned().collect::<Vec<_>>(),
             directives: self.directives.clone(),
         }
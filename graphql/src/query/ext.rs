@@ -61,6 +61,9 @@ pub enum BlockConstraint {
     /// Execute the query on the latest block only if the the subgraph has progressed to or past the
     /// given block number.
     Min(BlockNumber),
+    /// Execute the query on the first block whose timestamp, in seconds
+    /// since the epoch, is at or after the given timestamp
+    Timestamp(u64),
     Latest,
 }
 
@@ -89,6 +92,10 @@ impl TryFromValue for BlockConstraint {
             Ok(BlockConstraint::Min(BlockNumber::try_from_value(
                 number_value,
             )?))
+        } else if let Some(timestamp_value) = map.get("timestamp_gte") {
+            Ok(BlockConstraint::Timestamp(u64::try_from_value(
+                timestamp_value,
+            )?))
         } else {
             Err(anyhow!("invalid `BlockConstraint`"))
         }
@@ -51,22 +51,30 @@ where
         cache_status: Default::default(),
     });
 
-    if !query.is_query() {
+    // Execute top-level `query { ... }`, `{ ... }` and `mutation { ... }`
+    // expressions
+    let root_type = if query.is_query() {
+        ctx.query.schema.query_type.cheap_clone()
+    } else if query.is_mutation() {
+        // `Query::new` only accepts mutations if the schema has a mutation type
+        ctx.query.schema.mutation_type.clone().unwrap()
+    } else {
         return Arc::new(
-            QueryExecutionError::NotSupported("Only queries are supported".to_string()).into(),
+            QueryExecutionError::NotSupported(
+                "Only queries and mutations are supported".to_string(),
+            )
+            .into(),
         );
-    }
+    };
     let selection_set = selection_set
         .map(Arc::new)
         .unwrap_or_else(|| query.selection_set.cheap_clone());
 
-    // Execute top-level `query { ... }` and `{ ... }` expressions.
-    let query_type = ctx.query.schema.query_type.cheap_clone().into();
     let start = Instant::now();
     let result = execute_root_selection_set(
         ctx.cheap_clone(),
         selection_set.cheap_clone(),
-        query_type,
+        root_type.into(),
         block_ptr.clone(),
     )
     .await;
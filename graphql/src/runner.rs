@@ -1,17 +1,36 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::sync::Arc;
 use std::time::Instant;
 
-use crate::prelude::{QueryExecutionOptions, StoreResolver, SubscriptionExecutionOptions};
+use crate::execution::ast as a;
+use crate::execution::incremental::{
+    deferred_errors, deferred_results, DeferredFragment, ExportedField, IncrementalPlan,
+    StreamedField,
+};
+use crate::prelude::{
+    BlockConstraint, QueryExecutionOptions, StoreResolver, SubscriptionExecutionOptions,
+};
 use crate::query::execute_query;
+use crate::store::export_query;
 use crate::subscription::execute_prepared_subscription;
-use graph::prelude::MetricsRegistry;
+use graph::data::query::{
+    entity_count, IncrementalPayload, IncrementalResponse, IncrementalResult, QueryLimits,
+    QueryResult, QueryStats,
+};
+use graph::data::value::Object;
+use graph::futures03::channel::mpsc;
+use graph::futures03::{future, stream, SinkExt, StreamExt};
+use graph::prelude::tokio::time::timeout;
+use graph::prelude::{r, BlockNumber, MetricsRegistry};
 use graph::prometheus::{Gauge, Histogram};
+use graph::util::otel::{self, Span};
 use graph::{
+    blockchain::{BlockHashResolver, CachingBlockHashResolver},
     components::store::SubscriptionManager,
     prelude::{
-        async_trait, o, CheapClone, DeploymentState, GraphQlRunner as GraphQlRunnerTrait, Logger,
-        Query, QueryExecutionError, Subscription, SubscriptionError, SubscriptionResult, ENV_VARS,
+        async_trait, o, CheapClone, DeploymentHash, DeploymentState,
+        GraphQlRunner as GraphQlRunnerTrait, Logger, Query, QueryExecutionError, Subscription,
+        SubscriptionError, SubscriptionResult, ENV_VARS,
     },
 };
 use graph::{data::graphql::effort::LoadManager, prelude::QueryStoreManager};
@@ -73,6 +92,10 @@ pub struct GraphQlRunner<S, SM> {
     subscription_manager: Arc<SM>,
     load_manager: Arc<LoadManager>,
     result_size: Arc<ResultSizeMetrics>,
+    block_hash_resolver: Option<Arc<dyn BlockHashResolver>>,
+    deployment_limits: Arc<HashMap<DeploymentHash, QueryLimits>>,
+    query_stats: Arc<QueryStats>,
+    enable_exports: bool,
 }
 
 #[cfg(debug_assertions)]
@@ -102,6 +125,56 @@ where
             subscription_manager,
             load_manager,
             result_size,
+            block_hash_resolver: None,
+            deployment_limits: Arc::new(HashMap::new()),
+            query_stats: Arc::new(QueryStats::new()),
+            enable_exports: ENV_VARS.graphql.enable_exports,
+        }
+    }
+
+    /// Use `resolver` to look up blocks that queries reference by hash
+    /// but that are not in the chain store. Lookups are cached and limited
+    /// as configured with `GRAPH_GRAPHQL_BLOCK_HASH_CACHE_SIZE`,
+    /// `GRAPH_GRAPHQL_BLOCK_HASH_CACHE_TTL`, and
+    /// `GRAPH_GRAPHQL_MAX_BLOCK_HASH_LOOKUPS`
+    pub fn with_block_hash_resolver(mut self, resolver: Arc<dyn BlockHashResolver>) -> Self {
+        self.block_hash_resolver = Some(Arc::new(CachingBlockHashResolver::new(
+            resolver,
+            ENV_VARS.graphql.block_hash_cache_size,
+            ENV_VARS.graphql.block_hash_cache_ttl,
+            ENV_VARS.graphql.max_block_hash_lookups,
+        )));
+        self
+    }
+
+    /// Use `limits` instead of the global limits for queries against the
+    /// deployments in it
+    pub fn with_deployment_limits(mut self, limits: HashMap<DeploymentHash, QueryLimits>) -> Self {
+        self.deployment_limits = Arc::new(limits);
+        self
+    }
+
+    /// Allow or forbid queries to stream list fields with `@export`,
+    /// overriding `GRAPH_GRAPHQL_ENABLE_EXPORTS`
+    pub fn with_exports(mut self, enable: bool) -> Self {
+        self.enable_exports = enable;
+        self
+    }
+
+    /// The limits for queries against deployment `id`. Where both
+    /// `requested`, for example the limits of an API key, and the limits
+    /// configured for the deployment set a limit, the stricter one is used.
+    /// Limits that neither sets fall back to the global limits
+    fn limits_for(&self, id: &DeploymentHash, requested: QueryLimits) -> QueryLimits {
+        let requested = match self.deployment_limits.get(id) {
+            Some(limits) => requested.min(limits),
+            None => requested,
+        };
+        QueryLimits {
+            max_complexity: requested.max_complexity.or(ENV_VARS.graphql.max_complexity),
+            max_depth: Some(requested.max_depth.unwrap_or(ENV_VARS.graphql.max_depth)),
+            max_first: Some(requested.max_first.unwrap_or(ENV_VARS.graphql.max_first)),
+            max_skip: Some(requested.max_skip.unwrap_or(ENV_VARS.graphql.max_skip)),
         }
     }
 
@@ -135,16 +208,22 @@ where
         Ok(())
     }
 
-    async fn execute(
+    /// Set up everything needed to execute `query` against `target`
+    /// and determine the limits for it
+    async fn prepare(
         &self,
         query: Query,
         target: QueryTarget,
-        max_complexity: Option<u64>,
-        max_depth: Option<u8>,
-        max_first: Option<u32>,
-        max_skip: Option<u32>,
-        result_size: Arc<ResultSizeMetrics>,
-    ) -> Result<QueryResults, QueryResults> {
+        limits: QueryLimits,
+    ) -> Result<
+        (
+            Arc<dyn QueryStore>,
+            DeploymentState,
+            Arc<crate::execution::Query>,
+            QueryLimits,
+        ),
+        QueryResults,
+    > {
         // We need to use the same `QueryStore` for the entire query to ensure
         // we have a consistent view if the world, even when replicas, which
         // are eventually consistent, are in use. If we run different parts
@@ -155,6 +234,24 @@ where
         // point, and everything needs to go through the `store` we are
         // setting up here
         let store = self.store.query_store(target, false).await?;
+        self.prepare_with_store(store, query, limits).await
+    }
+
+    /// Like `prepare`, but run `query` against `store`
+    async fn prepare_with_store(
+        &self,
+        store: Arc<dyn QueryStore>,
+        query: Query,
+        limits: QueryLimits,
+    ) -> Result<
+        (
+            Arc<dyn QueryStore>,
+            DeploymentState,
+            Arc<crate::execution::Query>,
+            QueryLimits,
+        ),
+        QueryResults,
+    > {
         let state = store.deployment_state().await?;
         let network = Some(store.network_name().to_string());
         let schema = store.api_schema()?;
@@ -167,14 +264,15 @@ where
             .clone()
             .unwrap_or(state);
 
-        let max_depth = max_depth.unwrap_or(ENV_VARS.graphql.max_depth);
+        let limits = self.limits_for(schema.id(), limits);
+        let span = Span::new("graphql_prepare").attr("deployment", schema.id());
         let query = crate::execution::Query::new(
             &self.logger,
             schema,
             network,
             query,
-            max_complexity,
-            max_depth,
+            limits.max_complexity,
+            limits.max_depth.unwrap_or(ENV_VARS.graphql.max_depth),
         )?;
         self.load_manager
             .decide(
@@ -183,45 +281,559 @@ where
                 query.query_text.as_ref(),
             )
             .to_result()?;
-        let by_block_constraint = query.block_constraint()?;
-        let mut max_block = 0;
-        let mut result: QueryResults = QueryResults::empty();
+        span.end();
+        Ok((store, state, query, limits))
+    }
 
-        // Note: This will always iterate at least once.
-        for (bc, (selection_set, error_policy)) in by_block_constraint {
-            let resolver = StoreResolver::at_block(
-                &self.logger,
-                store.cheap_clone(),
-                self.subscription_manager.cheap_clone(),
-                bc,
-                error_policy,
-                query.schema.id().clone(),
-                result_size.cheap_clone(),
-            )
-            .await?;
-            max_block = max_block.max(resolver.block_number());
-            let query_res = execute_query(
-                query.clone(),
+    async fn locate_block(
+        &self,
+        store: &Arc<dyn QueryStore>,
+        query: &crate::execution::Query,
+        bc: BlockConstraint,
+        error_policy: ErrorPolicy,
+        result_size: &Arc<ResultSizeMetrics>,
+    ) -> Result<StoreResolver, QueryExecutionError> {
+        StoreResolver::at_block(
+            &self.logger,
+            store.cheap_clone(),
+            self.subscription_manager.cheap_clone(),
+            bc,
+            error_policy,
+            query.schema.id().clone(),
+            result_size.cheap_clone(),
+            self.block_hash_resolver.clone(),
+        )
+        .await
+    }
+
+    /// Execute `selection_set` at the block that `resolver` is pinned to
+    async fn execute_selection_set(
+        &self,
+        query: &Arc<crate::execution::Query>,
+        selection_set: a::SelectionSet,
+        resolver: StoreResolver,
+        max_first: u32,
+        max_skip: u32,
+    ) -> Arc<QueryResult> {
+        let start = Instant::now();
+        let span = Span::new("graphql_execute")
+            .attr("deployment", query.schema.id())
+            .attr("block.number", resolver.block_number());
+        let result = otel::in_span(
+            &span,
+            execute_query(
+                query.cheap_clone(),
                 Some(selection_set),
                 resolver.block_ptr.clone(),
                 QueryExecutionOptions {
                     resolver,
                     deadline: ENV_VARS.graphql.query_timeout.map(|t| Instant::now() + t),
-                    max_first: max_first.unwrap_or(ENV_VARS.graphql.max_first),
-                    max_skip: max_skip.unwrap_or(ENV_VARS.graphql.max_skip),
+                    max_first,
+                    max_skip,
                     load_manager: self.load_manager.clone(),
                 },
-            )
-            .await;
+            ),
+        )
+        .await;
+        span.end();
+        self.query_stats.record(
+            query.schema.id(),
+            query.shape_hash,
+            &query.query_text,
+            start.elapsed(),
+            result.data().map(entity_count).unwrap_or(0),
+        );
+        result
+    }
+
+    async fn execute(
+        &self,
+        query: Query,
+        target: QueryTarget,
+        limits: QueryLimits,
+        result_size: Arc<ResultSizeMetrics>,
+    ) -> Result<QueryResults, QueryResults> {
+        let (store, state, query, limits) = self.prepare(query, target, limits).await?;
+        self.execute_prepared(
+            store,
+            state,
+            query,
+            limits.max_first.unwrap_or(ENV_VARS.graphql.max_first),
+            limits.max_skip.unwrap_or(ENV_VARS.graphql.max_skip),
+            None,
+            result_size,
+        )
+        .await
+    }
+
+    /// Execute all `queries` against `target`. Queries that ask for the
+    /// latest block are all run at the block that was the latest when the
+    /// batch started so that their results are consistent with each other
+    async fn execute_batch(
+        self: Arc<Self>,
+        queries: Vec<Query>,
+        target: QueryTarget,
+        max_complexity: Option<u64>,
+    ) -> Result<Vec<QueryResults>, QueryResults> {
+        let store = self.store.query_store(target, false).await?;
+        let latest = store.deployment_state().await?.latest_ethereum_block_number;
+
+        let limits = QueryLimits {
+            max_complexity,
+            ..QueryLimits::default()
+        };
+        let run = |query: Query| {
+            let runner = self.cheap_clone();
+            let store = store.cheap_clone();
+            let limits = limits.clone();
+            async move {
+                let result_size = runner.result_size.cheap_clone();
+                let (store, state, query, limits) =
+                    runner.prepare_with_store(store, query, limits).await?;
+                runner
+                    .execute_prepared(
+                        store,
+                        state,
+                        query,
+                        limits.max_first.unwrap_or(ENV_VARS.graphql.max_first),
+                        limits.max_skip.unwrap_or(ENV_VARS.graphql.max_skip),
+                        Some(latest),
+                        result_size,
+                    )
+                    .await
+            }
+        };
+
+        let results = if ENV_VARS.graphql.concurrent_batches {
+            future::join_all(queries.into_iter().map(run)).await
+        } else {
+            let mut results = Vec::with_capacity(queries.len());
+            for query in queries {
+                results.push(run(query).await);
+            }
+            results
+        };
+        Ok(results
+            .into_iter()
+            .map(|result| result.unwrap_or_else(|e| e))
+            .collect())
+    }
+
+    async fn execute_prepared(
+        &self,
+        store: Arc<dyn QueryStore>,
+        state: DeploymentState,
+        query: Arc<crate::execution::Query>,
+        max_first: u32,
+        max_skip: u32,
+        pinned_latest: Option<BlockNumber>,
+        result_size: Arc<ResultSizeMetrics>,
+    ) -> Result<QueryResults, QueryResults> {
+        let by_block_constraint = query.block_constraint()?;
+        let mut max_block = 0;
+        let mut result: QueryResults = QueryResults::empty();
+
+        // Note: This will always iterate at least once.
+        for (bc, (selection_set, error_policy)) in by_block_constraint {
+            let bc = match (bc, pinned_latest) {
+                (BlockConstraint::Latest, Some(number)) => BlockConstraint::Number(number),
+                (bc, _) => bc,
+            };
+            let resolver = self
+                .locate_block(&store, &query, bc, error_policy, &result_size)
+                .await?;
+            max_block = max_block.max(resolver.block_number());
+            let query_res = self
+                .execute_selection_set(&query, selection_set, resolver, max_first, max_skip)
+                .await;
             result.append(query_res);
         }
 
         query.log_execution(max_block);
+        result.set_complexity(query.complexity);
         self.deployment_changed(store.as_ref(), state, max_block as u64)
             .await
             .map_err(QueryResults::from)
             .map(|()| result)
     }
+
+    /// Execute `query`, delivering the data for deferred fragments and
+    /// the items of streamed fields in payloads after the initial payload
+    /// if the query uses `@defer` or `@stream`. All payloads are computed
+    /// against the same blocks as the initial payload
+    async fn execute_incremental(
+        self: Arc<Self>,
+        query: Query,
+        target: QueryTarget,
+        max_complexity: Option<u64>,
+        result_size: Arc<ResultSizeMetrics>,
+    ) -> Result<IncrementalResponse, QueryResults> {
+        let limits = QueryLimits {
+            max_complexity,
+            ..QueryLimits::default()
+        };
+        let (store, state, query, limits) = self.prepare(query, target, limits).await?;
+        let max_first = limits.max_first.unwrap_or(ENV_VARS.graphql.max_first);
+        let max_skip = limits.max_skip.unwrap_or(ENV_VARS.graphql.max_skip);
+        let complexity = query.complexity;
+
+        if !query.selection_set.is_incremental() {
+            return self
+                .execute_prepared(store, state, query, max_first, max_skip, None, result_size)
+                .await
+                .map(IncrementalResponse::Complete);
+        }
+
+        let mut results = QueryResults::empty();
+        let mut has_data = false;
+        let mut deferred_steps = Vec::new();
+        let mut stream_steps = Vec::new();
+        let mut export_steps = Vec::new();
+        let mut max_block = 0;
+        for (bc, (selection_set, error_policy)) in query.block_constraint()? {
+            let resolver = self
+                .locate_block(&store, &query, bc, error_policy, &result_size)
+                .await?;
+            max_block = max_block.max(resolver.block_number());
+            let plan =
+                IncrementalPlan::new(&selection_set, max_first, max_skip, self.enable_exports)?;
+
+            let initial = if plan.initial.is_empty() {
+                None
+            } else {
+                let res = self
+                    .execute_selection_set(
+                        &query,
+                        plan.initial,
+                        resolver.cheap_clone(),
+                        max_first,
+                        max_skip,
+                    )
+                    .await;
+                has_data = true;
+                results.append(res.cheap_clone());
+                Some(res)
+            };
+            if !plan.empty.is_empty() {
+                let empty = plan
+                    .empty
+                    .into_iter()
+                    .map(|key| (key, r::Value::List(vec![])))
+                    .collect::<Object>();
+                has_data = true;
+                results.append(Arc::new(QueryResult::new(empty)));
+            }
+
+            if let Some((selection_set, fragments)) = plan.deferred {
+                deferred_steps.push(IncrementalStep::Deferred {
+                    resolver: resolver.cheap_clone(),
+                    selection_set,
+                    fragments,
+                });
+            }
+            for field in plan.streams {
+                // Only stream the remaining items if the initial payload
+                // had all the items it asked for
+                let delivered = match initial.as_ref() {
+                    Some(res) if res.has_errors() => continue,
+                    Some(res) => match res.data().and_then(|data| data.get(field.response_key())) {
+                        Some(r::Value::List(items)) => items.len() as u32,
+                        _ => 0,
+                    },
+                    None => 0,
+                };
+                if delivered == field.initial_count() && field.has_more(delivered) {
+                    stream_steps.push(IncrementalStep::Stream {
+                        resolver: resolver.cheap_clone(),
+                        field,
+                        delivered,
+                    });
+                }
+            }
+            for field in plan.exports {
+                export_steps.push(IncrementalStep::Export {
+                    resolver: resolver.cheap_clone(),
+                    field,
+                });
+            }
+        }
+        if !has_data {
+            results.append(Arc::new(QueryResult::new(Object::new())));
+        }
+        query.log_execution(max_block);
+        self.deployment_changed(store.as_ref(), state.clone(), max_block as u64)
+            .await
+            .map_err(QueryResults::from)?;
+
+        let steps: VecDeque<_> = deferred_steps
+            .into_iter()
+            .chain(stream_steps)
+            .chain(export_steps)
+            .collect();
+        let initial = IncrementalPayload::Initial {
+            results,
+            has_next: !steps.is_empty(),
+        };
+        let state = IncrementalState {
+            runner: self,
+            store,
+            state,
+            query,
+            steps,
+            max_block,
+            max_first,
+            max_skip,
+        };
+        let rest = stream::unfold(state, |mut state| async move {
+            let step = state.steps.pop_front()?;
+            let incremental = state.run(step).await;
+            let payload = IncrementalPayload::Subsequent {
+                incremental,
+                has_next: !state.steps.is_empty(),
+            };
+            Some((payload, state))
+        });
+        Ok(IncrementalResponse::Incremental {
+            payloads: Box::pin(stream::once(future::ready(initial)).chain(rest)),
+            complexity,
+        })
+    }
+}
+
+/// Work that remains to be done after the initial payload of an
+/// incrementally delivered response has been sent
+enum IncrementalStep {
+    /// Resolve the `fragments` by executing `selection_set`
+    Deferred {
+        resolver: StoreResolver,
+        selection_set: a::SelectionSet,
+        fragments: Vec<DeferredFragment>,
+    },
+    /// Fetch the next batch of items of a streamed field after the first
+    /// `delivered` items
+    Stream {
+        resolver: StoreResolver,
+        field: StreamedField,
+        delivered: u32,
+    },
+    /// Start reading all entities for an exported field from the store
+    Export {
+        resolver: StoreResolver,
+        field: ExportedField,
+    },
+    /// Send the next batch of entities of an exported field after the
+    /// first `delivered` entities
+    ExportBatch {
+        field: ExportedField,
+        batches: ExportBatches,
+        delivered: usize,
+    },
+}
+
+/// A batch of entities for an exported field as it is read from the store
+type ExportBatch = Result<Vec<BTreeMap<String, r::Value>>, QueryExecutionError>;
+
+/// The batches of entities for an exported field as they are read from
+/// the store
+type ExportBatches = mpsc::Receiver<ExportBatch>;
+
+struct IncrementalState<S, SM> {
+    runner: Arc<GraphQlRunner<S, SM>>,
+    store: Arc<dyn QueryStore>,
+    state: DeploymentState,
+    query: Arc<crate::execution::Query>,
+    steps: VecDeque<IncrementalStep>,
+    max_block: BlockNumber,
+    max_first: u32,
+    max_skip: u32,
+}
+
+impl<S, SM> IncrementalState<S, SM>
+where
+    S: QueryStoreManager,
+    SM: SubscriptionManager,
+{
+    /// Perform `step` and return the results for the next payload. Adds
+    /// any follow-up work to `self.steps`
+    async fn run(&mut self, step: IncrementalStep) -> Vec<IncrementalResult> {
+        let mut results = match step {
+            IncrementalStep::Deferred {
+                resolver,
+                selection_set,
+                fragments,
+            } => {
+                let res = self
+                    .runner
+                    .execute_selection_set(
+                        &self.query,
+                        selection_set,
+                        resolver,
+                        self.max_first,
+                        self.max_skip,
+                    )
+                    .await;
+                if res.has_errors() {
+                    deferred_errors(&fragments, res.errors())
+                } else {
+                    res.data()
+                        .map(|data| deferred_results(data, &fragments))
+                        .unwrap_or_default()
+                }
+            }
+            IncrementalStep::Stream {
+                resolver,
+                field,
+                delivered,
+            } => {
+                let batch_size = ENV_VARS.graphql.stream_batch_size.max(1);
+                let requested = field.batch_len(delivered, batch_size);
+                let selection_set =
+                    match field.batch(&self.query.selection_set, delivered, batch_size) {
+                        Ok(selection_set) => selection_set,
+                        Err(e) => return vec![field.errors(delivered, vec![e.into()])],
+                    };
+                // Batches skip over the items that were already delivered,
+                // which can take them past `max_skip`
+                let res = self
+                    .runner
+                    .execute_selection_set(
+                        &self.query,
+                        selection_set,
+                        resolver.cheap_clone(),
+                        self.max_first,
+                        self.max_skip.saturating_add(self.max_first),
+                    )
+                    .await;
+                if res.has_errors() {
+                    vec![field.errors(delivered, res.errors().to_vec())]
+                } else {
+                    let items = match res.data().and_then(|data| data.get(field.response_key())) {
+                        Some(r::Value::List(items)) => items.clone(),
+                        _ => vec![],
+                    };
+                    let count = items.len() as u32;
+                    let result = field.items(delivered, items);
+                    if count == requested && field.has_more(delivered + count) {
+                        self.steps.push_front(IncrementalStep::Stream {
+                            resolver,
+                            field,
+                            delivered: delivered + count,
+                        });
+                    }
+                    if count > 0 {
+                        vec![result]
+                    } else {
+                        vec![]
+                    }
+                }
+            }
+            IncrementalStep::Export { resolver, field } => {
+                match self.export(&resolver, &field).await {
+                    Ok(batches) => self.next_export_batch(field, batches, 0).await,
+                    Err(e) => vec![field.errors(0, vec![e.into()])],
+                }
+            }
+            IncrementalStep::ExportBatch {
+                field,
+                batches,
+                delivered,
+            } => self.next_export_batch(field, batches, delivered).await,
+        };
+
+        if self.steps.is_empty() {
+            // Same check as for complete responses, but since the payloads
+            // have been computed over a longer time, we do it again once
+            // we are done
+            if let Err(e) = self
+                .runner
+                .deployment_changed(
+                    self.store.as_ref(),
+                    self.state.clone(),
+                    self.max_block as u64,
+                )
+                .await
+            {
+                results.push(IncrementalResult::errors(vec![], None, vec![e.into()]));
+            }
+        }
+        results
+    }
+
+    /// Start reading the entities for the exported `field` from the store
+    /// in the background. The store reads at most one batch ahead of what
+    /// has been sent so that a slow client does not cause entities to pile
+    /// up in memory. If the client does not accept a batch within
+    /// `GRAPH_GRAPHQL_EXPORT_SEND_TIMEOUT`, the export is aborted so that
+    /// the query permit and the database connection are released. A
+    /// successful export ends with an empty batch
+    async fn export(
+        &self,
+        resolver: &StoreResolver,
+        field: &ExportedField,
+    ) -> Result<ExportBatches, QueryExecutionError> {
+        let query = export_query(
+            self.runner.logger.clone(),
+            &self.query.schema,
+            field.field(),
+            resolver.block_number(),
+            self.query.query_id.clone(),
+            self.max_skip,
+        )?;
+        let store = self.store.cheap_clone();
+        let permit = store.query_permit().await;
+        let (mut sender, batches) = mpsc::channel(1);
+        graph::spawn_blocking_allow_panic(move || {
+            let _permit = permit;
+            let mut send = |batch: ExportBatch| {
+                let send = sender.send(batch);
+                matches!(
+                    graph::block_on(timeout(ENV_VARS.graphql.export_send_timeout, send)),
+                    Ok(Ok(()))
+                )
+            };
+            let mut aborted = false;
+            let mut sink = |batch: Vec<BTreeMap<String, r::Value>>| {
+                aborted = !send(Ok(batch));
+                !aborted
+            };
+            let res =
+                store.stream_query_values(query, ENV_VARS.graphql.export_batch_size, &mut sink);
+            if !aborted {
+                match res {
+                    Ok(()) => send(Ok(vec![])),
+                    Err(e) => send(Err(e)),
+                };
+            }
+        });
+        Ok(batches)
+    }
+
+    /// Turn the next batch of entities of an exported field into the
+    /// results for a payload, and schedule sending the batch after it
+    async fn next_export_batch(
+        &mut self,
+        field: ExportedField,
+        mut batches: ExportBatches,
+        delivered: usize,
+    ) -> Vec<IncrementalResult> {
+        match batches.next().await {
+            Some(Ok(entities)) if entities.is_empty() => vec![],
+            Some(Ok(entities)) => {
+                let count = entities.len();
+                let result = field.items(delivered, entities);
+                self.steps.push_front(IncrementalStep::ExportBatch {
+                    field,
+                    batches,
+                    delivered: delivered + count,
+                });
+                vec![result]
+            }
+            Some(Err(e)) => vec![field.errors(delivered, vec![e.into()])],
+            // The export stopped without reaching its end because we did
+            // not accept its batches fast enough
+            None => vec![field.errors(delivered, vec![QueryExecutionError::Timeout.into()])],
+        }
+    }
 }
 
 #[async_trait]
@@ -231,15 +843,8 @@ where
     SM: SubscriptionManager,
 {
     async fn run_query(self: Arc<Self>, query: Query, target: QueryTarget) -> QueryResults {
-        self.run_query_with_complexity(
-            query,
-            target,
-            ENV_VARS.graphql.max_complexity,
-            Some(ENV_VARS.graphql.max_depth),
-            Some(ENV_VARS.graphql.max_first),
-            Some(ENV_VARS.graphql.max_skip),
-        )
-        .await
+        self.run_query_with_complexity(query, target, None, None, None, None)
+            .await
     }
 
     async fn run_query_with_complexity(
@@ -251,17 +856,48 @@ where
         max_first: Option<u32>,
         max_skip: Option<u32>,
     ) -> QueryResults {
-        self.execute(
-            query,
-            target,
+        let limits = QueryLimits {
             max_complexity,
             max_depth,
             max_first,
             max_skip,
-            self.result_size.cheap_clone(),
-        )
-        .await
-        .unwrap_or_else(|e| e)
+        };
+        self.execute(query, target, limits, self.result_size.cheap_clone())
+            .await
+            .unwrap_or_else(|e| e)
+    }
+
+    async fn run_query_batch(
+        self: Arc<Self>,
+        queries: Vec<Query>,
+        target: QueryTarget,
+        max_complexity: Option<u64>,
+    ) -> Vec<QueryResults> {
+        let len = queries.len();
+        self.execute_batch(queries, target, max_complexity)
+            .await
+            .unwrap_or_else(|e| {
+                // We could not even start running the queries; report the
+                // same error for each of them
+                (0..len)
+                    .map(|_| match e.first() {
+                        Some(result) => QueryResults::from(result.cheap_clone()),
+                        None => QueryResults::empty(),
+                    })
+                    .collect()
+            })
+    }
+
+    async fn run_query_incremental(
+        self: Arc<Self>,
+        query: Query,
+        target: QueryTarget,
+        max_complexity: Option<u64>,
+    ) -> IncrementalResponse {
+        let result_size = self.result_size.cheap_clone();
+        self.execute_incremental(query, target, max_complexity, result_size)
+            .await
+            .unwrap_or_else(IncrementalResponse::Complete)
     }
 
     async fn run_subscription(
@@ -272,14 +908,21 @@ where
         let store = self.store.query_store(target, true).await?;
         let schema = store.api_schema()?;
         let network = store.network_name().to_string();
+        let limits = self.limits_for(schema.id(), QueryLimits::default());
+        let max_depth = limits.max_depth.unwrap_or(ENV_VARS.graphql.max_depth);
 
+        let Subscription {
+            query,
+            filter,
+            cursor,
+        } = subscription;
         let query = crate::execution::Query::new(
             &self.logger,
             schema,
             Some(network),
-            subscription.query,
-            ENV_VARS.graphql.max_complexity,
-            ENV_VARS.graphql.max_depth,
+            query,
+            limits.max_complexity,
+            max_depth,
         )?;
 
         if let Err(err) = self
@@ -296,16 +939,19 @@ where
 
         execute_prepared_subscription(
             query,
+            filter,
+            cursor,
             SubscriptionExecutionOptions {
                 logger: self.logger.clone(),
                 store,
                 subscription_manager: self.subscription_manager.cheap_clone(),
                 timeout: ENV_VARS.graphql.query_timeout,
-                max_complexity: ENV_VARS.graphql.max_complexity,
-                max_depth: ENV_VARS.graphql.max_depth,
-                max_first: ENV_VARS.graphql.max_first,
-                max_skip: ENV_VARS.graphql.max_skip,
+                max_complexity: limits.max_complexity,
+                max_depth,
+                max_first: limits.max_first.unwrap_or(ENV_VARS.graphql.max_first),
+                max_skip: limits.max_skip.unwrap_or(ENV_VARS.graphql.max_skip),
                 result_size: self.result_size.clone(),
+                block_hash_resolver: self.block_hash_resolver.clone(),
             },
         )
     }
@@ -313,4 +959,8 @@ where
     fn load_manager(&self) -> Arc<LoadManager> {
         self.load_manager.clone()
     }
+
+    fn query_stats(&self) -> Arc<QueryStats> {
+        self.query_stats.clone()
+    }
 }
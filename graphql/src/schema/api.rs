@@ -7,7 +7,9 @@ use lazy_static::lazy_static;
 use crate::schema::ast;
 
 use graph::data::{
-    graphql::ext::{DirectiveExt, DocumentExt, ValueExt},
+    graphql::ext::{
+        DirectiveExt, DocumentExt, ObjectTypeExt, TypeDefinitionExt, TypeExt, ValueExt,
+    },
     schema::{META_FIELD_NAME, META_FIELD_TYPE, SCHEMA_TYPE_NAME},
 };
 use graph::prelude::s::{Value, *};
@@ -27,6 +29,36 @@ pub enum APISchemaError {
 const BLOCK_HEIGHT: &str = "Block_height";
 const ERROR_POLICY_TYPE: &str = "_SubgraphErrorPolicy_";
 
+/// The directive that marks the `<types>Aggregate` fields on the `Query`
+/// type. Its `entity` argument is the name of the aggregated entity type
+pub(crate) const AGGREGATE_DIRECTIVE: &str = "aggregate";
+
+/// The directive that marks the `<type>_connection` types. Its `entity`
+/// argument is the name of the entity type in `nodes`
+pub(crate) const CONNECTION_DIRECTIVE: &str = "connection";
+
+/// The scalar types of fields for which we generate `sum`, `avg`, `min`,
+/// and `max` aggregates
+const NUMERIC_TYPES: [&str; 3] = ["Int", "BigInt", "BigDecimal"];
+
+/// The fields of `*_filter` types that combine a list of filters
+pub(crate) const LOGICAL_FILTER_OPS: [&str; 2] = ["and", "or"];
+
+/// The aggregate functions other than `count`
+const AGGREGATE_FUNCTIONS: [&str; 4] = ["sum", "avg", "min", "max"];
+
+/// The `Query` field with which Apollo Federation gateways get the SDL of
+/// the subgraph
+pub(crate) const SERVICE_FIELD_NAME: &str = "_service";
+pub(crate) const SERVICE_TYPE: &str = "_Service";
+
+/// The `Query` field with which Apollo Federation gateways look up
+/// entities by their key, and the union of all types it can return
+pub(crate) const ENTITIES_FIELD_NAME: &str = "_entities";
+pub(crate) const ENTITY_UNION_TYPE: &str = "_Entity";
+const ANY_TYPE: &str = "_Any";
+const KEY_DIRECTIVE: &str = "key";
+
 #[derive(Debug, PartialEq, Eq, Copy, Clone)]
 pub enum ErrorPolicy {
     Allow,
@@ -85,6 +117,7 @@ pub fn api_schema(input_schema: &Document) -> Result<Document, APISchemaError> {
     add_types_for_object_types(&mut schema, &object_types)?;
     add_types_for_interface_types(&mut schema, &interface_types)?;
     add_field_arguments(&mut schema, input_schema)?;
+    add_federation_types(&mut schema, &object_types);
     add_query_type(&mut schema, &object_types, &interface_types)?;
     add_subscription_type(&mut schema, &object_types, &interface_types)?;
 
@@ -115,38 +148,222 @@ fn add_meta_field_type(schema: &mut Document) {
         .extend(META_FIELD_SCHEMA.definitions.iter().cloned());
 }
 
+/// The object types that Apollo Federation gateways can look up by their
+/// `id`. Timeseries and aggregations are left out since their rows can
+/// not be identified by an `id` alone
+fn federated_types<'a>(object_types: &[&'a ObjectType]) -> Vec<&'a ObjectType> {
+    object_types
+        .iter()
+        .filter(|t| t.name != SCHEMA_TYPE_NAME && !t.is_timeseries() && !t.is_aggregation())
+        .copied()
+        .collect()
+}
+
+/// Adds the types and directives that Apollo Federation expects, marks
+/// entity types with `@key(fields: "id")`, and adds the `_Entity` union of
+/// all of them
+fn add_federation_types(schema: &mut Document, object_types: &[&ObjectType]) {
+    lazy_static! {
+        static ref FEDERATION_SCHEMA: Document = {
+            let schema = include_str!("federation.graphql");
+            parse_schema(schema).expect("the schema `federation.graphql` is invalid")
+        };
+    }
+
+    schema
+        .definitions
+        .extend(FEDERATION_SCHEMA.definitions.iter().cloned());
+
+    let federated: Vec<String> = federated_types(object_types)
+        .into_iter()
+        .map(|t| t.name.clone())
+        .collect();
+    for def in schema.definitions.iter_mut() {
+        if let Definition::TypeDefinition(TypeDefinition::Object(t)) = def {
+            if federated.contains(&t.name) {
+                t.directives.push(Directive {
+                    position: Pos::default(),
+                    name: KEY_DIRECTIVE.to_string(),
+                    arguments: vec![("fields".to_string(), Value::String("id".to_string()))],
+                });
+            }
+        }
+    }
+
+    // An empty union is not valid GraphQL
+    if !federated.is_empty() {
+        let typedef = TypeDefinition::Union(UnionType {
+            position: Pos::default(),
+            description: None,
+            name: ENTITY_UNION_TYPE.to_string(),
+            directives: vec![],
+            types: federated,
+        });
+        schema.definitions.push(Definition::TypeDefinition(typedef));
+    }
+}
+
 fn add_types_for_object_types(
     schema: &mut Document,
     object_types: &[&ObjectType],
 ) -> Result<(), APISchemaError> {
     for object_type in object_types {
         if !object_type.name.eq(SCHEMA_TYPE_NAME) {
-            add_order_by_type(schema, &object_type.name, &object_type.fields)?;
+            add_order_by_type(schema, &object_type.name, &object_type.fields, true)?;
             add_filter_type(schema, &object_type.name, &object_type.fields)?;
+            add_aggregate_types(schema, object_type)?;
+            add_connection_type(schema, &object_type.name)?;
         }
     }
     Ok(())
 }
 
+/// Adds a `<type_name>_aggregate` type for the given object type to the
+/// schema. Its `count` is a `BigInt` since the number of entities is not
+/// bounded by an `Int`. If the object type has numeric fields, also adds
+/// the types `<type_name>_<function>_fields` that hold the aggregates of
+/// these fields for each of the functions in `AGGREGATE_FUNCTIONS`
+fn add_aggregate_types(
+    schema: &mut Document,
+    object_type: &ObjectType,
+) -> Result<(), APISchemaError> {
+    let numeric_fields: Vec<_> = object_type
+        .fields
+        .iter()
+        .filter(|field| !field.field_type.is_list())
+        .map(|field| (field.name.as_str(), field.field_type.get_base_type()))
+        .filter(|(_, base_type)| NUMERIC_TYPES.contains(base_type))
+        .collect();
+
+    let mut fields = vec![object_field(
+        "count",
+        Type::NonNullType(Box::new(Type::NamedType("BigInt".to_string()))),
+    )];
+    if !numeric_fields.is_empty() {
+        for function in AGGREGATE_FUNCTIONS {
+            let fields_type = format!("{}_{}_fields", object_type.name, function);
+            let aggregated_fields = numeric_fields
+                .iter()
+                .map(|(name, base_type)| {
+                    let value_type = aggregate_value_type(function, base_type);
+                    object_field(name, Type::NamedType(value_type.to_string()))
+                })
+                .collect();
+            add_object_type(schema, fields_type.clone(), aggregated_fields, vec![])?;
+            fields.push(object_field(function, Type::NamedType(fields_type)));
+        }
+    }
+    add_object_type(
+        schema,
+        format!("{}_aggregate", object_type.name),
+        fields,
+        vec![],
+    )
+}
+
+/// Adds a `<type_name>_connection` type to the schema that wraps a page of
+/// entities in `nodes` together with the `totalCount` of all entities that
+/// match the filter, and the `endCursor` of the page
+fn add_connection_type(schema: &mut Document, type_name: &str) -> Result<(), APISchemaError> {
+    let fields = vec![
+        object_field(
+            "totalCount",
+            Type::NonNullType(Box::new(Type::NamedType("Int".to_string()))),
+        ),
+        object_field(
+            "nodes",
+            Type::NonNullType(Box::new(Type::ListType(Box::new(Type::NonNullType(
+                Box::new(Type::NamedType(type_name.to_owned())),
+            ))))),
+        ),
+        object_field("endCursor", Type::NamedType("String".to_string())),
+    ];
+    let directive = Directive {
+        position: Pos::default(),
+        name: CONNECTION_DIRECTIVE.to_string(),
+        arguments: vec![("entity".to_string(), Value::String(type_name.to_string()))],
+    };
+    add_object_type(
+        schema,
+        format!("{}_connection", type_name),
+        fields,
+        vec![directive],
+    )
+}
+
+/// The type of the result of applying the aggregate `function` to a field
+/// of type `base_type`. Averages are always `BigDecimal`, and sums of `Int`
+/// are `BigInt` since they can easily overflow an `Int`
+fn aggregate_value_type<'a>(function: &str, base_type: &'a str) -> &'a str {
+    match (function, base_type) {
+        ("avg", _) => "BigDecimal",
+        ("sum", "Int") => "BigInt",
+        (_, base_type) => base_type,
+    }
+}
+
+fn object_field(name: &str, field_type: Type) -> Field {
+    Field {
+        position: Pos::default(),
+        description: None,
+        name: name.to_owned(),
+        arguments: vec![],
+        field_type,
+        directives: vec![],
+    }
+}
+
+fn add_object_type(
+    schema: &mut Document,
+    type_name: String,
+    fields: Vec<Field>,
+    directives: Vec<Directive>,
+) -> Result<(), APISchemaError> {
+    if schema.get_named_type(&type_name).is_some() {
+        return Err(APISchemaError::TypeExists(type_name));
+    }
+    let typedef = TypeDefinition::Object(ObjectType {
+        position: Pos::default(),
+        description: None,
+        name: type_name,
+        implements_interfaces: vec![],
+        directives,
+        fields,
+    });
+    schema.definitions.push(Definition::TypeDefinition(typedef));
+    Ok(())
+}
+
 /// Adds `*_orderBy` and `*_filter` enum types for the given interfaces to the schema.
 fn add_types_for_interface_types(
     schema: &mut Document,
     interface_types: &[&InterfaceType],
 ) -> Result<(), APISchemaError> {
     for interface_type in interface_types {
-        add_order_by_type(schema, &interface_type.name, &interface_type.fields)?;
+        add_order_by_type(schema, &interface_type.name, &interface_type.fields, false)?;
         add_filter_type(schema, &interface_type.name, &interface_type.fields)?;
     }
     Ok(())
 }
 
-/// Adds a `<type_name>_orderBy` enum type for the given fields to the schema.
+/// Adds a `<type_name>_orderBy` enum type for the given fields to the
+/// schema, and a `<type_name>_order` input type that pairs one of them with
+/// a direction for ordering by several fields. If `nested` is `true`, the
+/// enum also has `<field>__<child field>` values for ordering by the fields
+/// of the entities that `fields` reference
 fn add_order_by_type(
     schema: &mut Document,
     type_name: &str,
     fields: &[Field],
+    nested: bool,
 ) -> Result<(), APISchemaError> {
+    add_order_type(schema, type_name)?;
+
     let type_name = format!("{}_orderBy", type_name);
+    let mut names: Vec<String> = fields.iter().map(|field| field.name.to_owned()).collect();
+    if nested {
+        names.extend(child_order_by_names(schema, fields));
+    }
 
     match schema.get_named_type(&type_name) {
         None => {
@@ -155,13 +372,12 @@ fn add_order_by_type(
                 description: None,
                 name: type_name,
                 directives: vec![],
-                values: fields
-                    .iter()
-                    .map(|field| &field.name)
+                values: names
+                    .into_iter()
                     .map(|name| EnumValue {
                         position: Pos::default(),
                         description: None,
-                        name: name.to_owned(),
+                        name,
                         directives: vec![],
                     })
                     .collect(),
@@ -174,6 +390,69 @@ fn add_order_by_type(
     Ok(())
 }
 
+/// The `<field>__<child field>` names for ordering by the fields of the
+/// object that a field references. We only allow this for fields that
+/// reference a single object that is not derived, and for child fields that
+/// are neither lists nor references themselves
+fn child_order_by_names(schema: &Document, fields: &[Field]) -> Vec<String> {
+    let is_scalar = |field: &Field| {
+        !ast::is_list(&field.field_type)
+            && ast::get_derived_from_directive(field).is_none()
+            && !matches!(
+                schema.get_named_type(&ast::get_field_name(&field.field_type)),
+                Some(TypeDefinition::Object(_)) | Some(TypeDefinition::Interface(_))
+            )
+    };
+
+    let mut names = vec![];
+    for field in fields {
+        if ast::is_list(&field.field_type) || ast::get_derived_from_directive(field).is_some() {
+            continue;
+        }
+        if let Some(TypeDefinition::Object(child)) =
+            schema.get_named_type(&ast::get_field_name(&field.field_type))
+        {
+            names.extend(
+                child
+                    .fields
+                    .iter()
+                    .filter(|child_field| is_scalar(child_field))
+                    .map(|child_field| format!("{}__{}", field.name, child_field.name)),
+            );
+        }
+    }
+    names
+}
+
+/// Adds the `<type_name>_order` input type with fields
+/// `field: <type_name>_orderBy!` and `direction: OrderDirection`
+fn add_order_type(schema: &mut Document, type_name: &str) -> Result<(), APISchemaError> {
+    let order_type_name = format!("{}_order", type_name);
+    if schema.get_named_type(&order_type_name).is_some() {
+        return Err(APISchemaError::TypeExists(order_type_name));
+    }
+    let typedef = TypeDefinition::InputObject(InputObjectType {
+        position: Pos::default(),
+        description: None,
+        name: order_type_name,
+        directives: vec![],
+        fields: vec![
+            input_value(
+                "field",
+                "",
+                Type::NonNullType(Box::new(Type::NamedType(format!("{}_orderBy", type_name)))),
+            ),
+            input_value(
+                "direction",
+                "",
+                Type::NamedType("OrderDirection".to_string()),
+            ),
+        ],
+    });
+    schema.definitions.push(Definition::TypeDefinition(typedef));
+    Ok(())
+}
+
 /// Adds a `<type_name>_filter` enum type for the given fields to the schema.
 fn add_filter_type(
     schema: &mut Document,
@@ -183,12 +462,24 @@ fn add_filter_type(
     let filter_type_name = format!("{}_filter", type_name);
     match schema.get_named_type(&filter_type_name) {
         None => {
+            let mut input_values = field_input_values(schema, fields)?;
+            // Combine filters with `and: [..]` and `or: [..]`, unless the
+            // type has fields with these names
+            for op in LOGICAL_FILTER_OPS {
+                if !fields.iter().any(|field| field.name == op) {
+                    input_values.push(input_value(
+                        op,
+                        "",
+                        Type::ListType(Box::new(Type::NamedType(filter_type_name.clone()))),
+                    ));
+                }
+            }
             let typedef = TypeDefinition::InputObject(InputObjectType {
                 position: Pos::default(),
                 description: None,
                 name: filter_type_name,
                 directives: vec![],
-                fields: field_input_values(schema, fields)?,
+                fields: input_values,
             });
             let def = Definition::TypeDefinition(typedef);
             schema.definitions.push(def);
@@ -226,7 +517,7 @@ fn field_filter_input_values(
                 TypeDefinition::Object(_) | TypeDefinition::Interface(_) => {
                     // Only add `where` filter fields for object and interface fields
                     // if they are not @derivedFrom
-                    if ast::get_derived_from_directive(field).is_some() {
+                    let mut input_values = if ast::get_derived_from_directive(field).is_some() {
                         vec![]
                     } else {
                         // We allow filtering with `where: { other: "some-id" }` and
@@ -238,7 +529,9 @@ fn field_filter_input_values(
                             field,
                             &ScalarType::new(String::from("String")),
                         )
-                    }
+                    };
+                    input_values.extend(field_child_filter_input_value(field, named_type));
+                    input_values
                 }
                 TypeDefinition::Scalar(ref t) => field_scalar_filter_input_values(schema, field, t),
                 TypeDefinition::Enum(ref t) => field_enum_filter_input_values(schema, field, t),
@@ -286,6 +579,10 @@ fn field_scalar_filter_input_values(
             "ends_with_nocase",
             "not_ends_with",
             "not_ends_with_nocase",
+            "like",
+            "ilike",
+            "not_like",
+            "not_ilike",
         ],
         _ => vec!["", "not"],
     }
@@ -336,7 +633,7 @@ fn field_list_filter_input_values(
         let input_field_type = match typedef {
             TypeDefinition::Interface(_) | TypeDefinition::Object(_) => {
                 if ast::get_derived_from_directive(field).is_some() {
-                    return None;
+                    return field_child_filter_input_value(field, typedef).map(|iv| vec![iv]);
                 } else {
                     Type::NamedType("String".into())
                 }
@@ -365,11 +662,26 @@ fn field_list_filter_input_values(
                     )))),
                 )
             })
+            .chain(field_child_filter_input_value(field, typedef))
             .collect(),
         )
     })
 }
 
+/// Generates the `<field>_: <Child>_filter` input value that filters on the
+/// fields of the entities a field references. Filtering through fields of
+/// interface type is not supported
+fn field_child_filter_input_value(field: &Field, typedef: &TypeDefinition) -> Option<InputValue> {
+    match typedef {
+        TypeDefinition::Object(t) => Some(input_value(
+            &format!("{}_", field.name),
+            "",
+            Type::NamedType(format!("{}_filter", t.name)),
+        )),
+        _ => None,
+    }
+}
+
 /// Generates a `*_filter` input value for the given field name, suffix and value type.
 fn input_value(name: &str, suffix: &'static str, value_type: Type) -> InputValue {
     InputValue {
@@ -405,6 +717,24 @@ fn add_query_type(
         .chain(interface_types.iter().map(|t| t.name.as_str()))
         .flat_map(query_fields_for_type)
         .collect::<Vec<Field>>();
+    // Aggregates and connections are only available for object types; we
+    // skip them when their name clashes with the name of another query field
+    let mut aggregate_fields = object_types
+        .iter()
+        .map(|t| t.name.as_str())
+        .filter(|name| !name.eq(&SCHEMA_TYPE_NAME))
+        .map(aggregate_query_field)
+        .filter(|field| !fields.iter().any(|f| f.name == field.name))
+        .collect();
+    fields.append(&mut aggregate_fields);
+    let mut connection_fields = object_types
+        .iter()
+        .map(|t| t.name.as_str())
+        .filter(|name| !name.eq(&SCHEMA_TYPE_NAME))
+        .map(connection_query_field)
+        .filter(|field| !fields.iter().any(|f| f.name == field.name))
+        .collect();
+    fields.append(&mut connection_fields);
     let mut fulltext_fields = schema
         .get_fulltext_directives()
         .map_err(|_| APISchemaError::FulltextSearchNonDeterministic)?
@@ -413,6 +743,10 @@ fn add_query_type(
         .collect();
     fields.append(&mut fulltext_fields);
     fields.push(meta_field());
+    fields.push(service_field());
+    if schema.get_named_type(ENTITY_UNION_TYPE).is_some() {
+        fields.push(entities_field());
+    }
 
     let typedef = TypeDefinition::Object(ObjectType {
         position: Pos::default(),
@@ -523,9 +857,12 @@ fn block_argument() -> InputValue {
             "The block at which the query should be executed. \
              Can either be a `{ hash: Bytes }` value containing a block hash, \
              a `{ number: Int }` containing the block number, \
-             or a `{ number_gte: Int }` containing the minimum block number. \
+             a `{ number_gte: Int }` containing the minimum block number, \
+             or a `{ timestamp_gte: Int }` containing a timestamp in seconds since the epoch. \
              In the case of `number_gte`, the query will be executed on the latest block only if \
              the subgraph has progressed to or past the minimum block number. \
+             In the case of `timestamp_gte`, the query will be executed on the first block \
+             whose timestamp is at or after the given timestamp. \
              Defaults to the latest block when omitted."
                 .to_owned(),
         ),
@@ -592,6 +929,66 @@ fn query_fields_for_type(type_name: &str) -> Vec<Field> {
     ]
 }
 
+/// Generates the `<types>Aggregate` query field for an object type
+fn aggregate_query_field(type_name: &str) -> Field {
+    Field {
+        position: Pos::default(),
+        description: None,
+        name: format!("{}Aggregate", type_name.to_plural().to_camel_case()),
+        arguments: vec![
+            input_value(
+                "where",
+                "",
+                Type::NamedType(format!("{}_filter", type_name)),
+            ),
+            block_argument(),
+            subgraph_error_argument(),
+        ],
+        field_type: Type::NonNullType(Box::new(Type::NamedType(format!(
+            "{}_aggregate",
+            type_name
+        )))),
+        directives: vec![Directive {
+            position: Pos::default(),
+            name: AGGREGATE_DIRECTIVE.to_string(),
+            arguments: vec![("entity".to_string(), Value::String(type_name.to_string()))],
+        }],
+    }
+}
+
+/// Generates the `<types>Connection` query field for an object type. It
+/// takes the same arguments as the `<types>` collection field, and an
+/// `after` cursor for keyset pagination
+fn connection_query_field(type_name: &str) -> Field {
+    let mut arguments = collection_arguments_for_named_type(type_name);
+    arguments.push(InputValue {
+        position: Pos::default(),
+        description: Some(
+            "Only return entities after the one with the given cursor. The cursor \
+             must come from the `endCursor` of a query with the same `orderBy` or `order`."
+                .to_owned(),
+        ),
+        name: "after".to_string(),
+        value_type: Type::NamedType("String".to_string()),
+        default_value: None,
+        directives: vec![],
+    });
+    arguments.push(block_argument());
+    arguments.push(subgraph_error_argument());
+
+    Field {
+        position: Pos::default(),
+        description: None,
+        name: format!("{}Connection", type_name.to_plural().to_camel_case()),
+        arguments,
+        field_type: Type::NonNullType(Box::new(Type::NamedType(format!(
+            "{}_connection",
+            type_name
+        )))),
+        directives: vec![],
+    }
+}
+
 fn meta_field() -> Field {
     lazy_static! {
         static ref META_FIELD: Field = Field {
@@ -616,6 +1013,92 @@ fn meta_field() -> Field {
     META_FIELD.clone()
 }
 
+/// Generates the `_service` field for Apollo Federation
+fn service_field() -> Field {
+    Field {
+        position: Pos::default(),
+        description: None,
+        name: SERVICE_FIELD_NAME.to_string(),
+        arguments: vec![],
+        field_type: Type::NonNullType(Box::new(Type::NamedType(SERVICE_TYPE.to_string()))),
+        directives: vec![],
+    }
+}
+
+/// Generates the `_entities(representations: [_Any!]!): [_Entity]!` field
+/// for Apollo Federation
+fn entities_field() -> Field {
+    Field {
+        position: Pos::default(),
+        description: None,
+        name: ENTITIES_FIELD_NAME.to_string(),
+        arguments: vec![InputValue {
+            position: Pos::default(),
+            description: None,
+            name: "representations".to_string(),
+            value_type: Type::NonNullType(Box::new(Type::ListType(Box::new(Type::NonNullType(
+                Box::new(Type::NamedType(ANY_TYPE.to_string())),
+            ))))),
+            default_value: None,
+            directives: vec![],
+        }],
+        field_type: Type::NonNullType(Box::new(Type::ListType(Box::new(Type::NamedType(
+            ENTITY_UNION_TYPE.to_string(),
+        ))))),
+        directives: vec![],
+    }
+}
+
+/// Print the SDL that Apollo Federation gateways compose the supergraph
+/// from. It is the API schema without the federation and introspection
+/// types and fields, and without any directives other than `@key` since
+/// gateways do not know about the directives that graph-node uses
+/// internally
+pub fn federation_sdl(schema: &Document) -> String {
+    fn keep_key(directives: &mut Vec<Directive>) {
+        directives.retain(|dir| dir.name == KEY_DIRECTIVE)
+    }
+
+    let mut schema = schema.clone();
+    schema.definitions.retain(|def| match def {
+        Definition::TypeDefinition(def) => {
+            !def.name().starts_with("__")
+                && ![ANY_TYPE, "_FieldSet", SERVICE_TYPE, ENTITY_UNION_TYPE].contains(&def.name())
+        }
+        Definition::DirectiveDefinition(_) => false,
+        Definition::SchemaDefinition(_) | Definition::TypeExtension(_) => true,
+    });
+    for def in schema.definitions.iter_mut() {
+        match def {
+            Definition::TypeDefinition(TypeDefinition::Object(t)) => {
+                keep_key(&mut t.directives);
+                t.fields.retain(|field| {
+                    !field.name.starts_with("__")
+                        && field.name != SERVICE_FIELD_NAME
+                        && field.name != ENTITIES_FIELD_NAME
+                });
+                for field in t.fields.iter_mut() {
+                    keep_key(&mut field.directives);
+                }
+            }
+            Definition::TypeDefinition(TypeDefinition::Interface(t)) => {
+                keep_key(&mut t.directives);
+                for field in t.fields.iter_mut() {
+                    keep_key(&mut field.directives);
+                }
+            }
+            Definition::TypeDefinition(TypeDefinition::Scalar(t)) => keep_key(&mut t.directives),
+            Definition::TypeDefinition(TypeDefinition::Enum(t)) => keep_key(&mut t.directives),
+            Definition::TypeDefinition(TypeDefinition::Union(t)) => keep_key(&mut t.directives),
+            Definition::TypeDefinition(TypeDefinition::InputObject(t)) => {
+                keep_key(&mut t.directives)
+            }
+            Definition::SchemaDefinition(_) | Definition::TypeExtension(_) => {}
+        }
+    }
+    schema.to_string()
+}
+
 /// Generates arguments for collection queries of a named type (e.g. User).
 fn collection_arguments_for_named_type(type_name: &str) -> Vec<InputValue> {
     // `first` and `skip` should be non-nullable, but the Apollo graphql client
@@ -640,6 +1123,20 @@ fn collection_arguments_for_named_type(type_name: &str) -> Vec<InputValue> {
             "",
             Type::NamedType("OrderDirection".to_string()),
         ),
+        InputValue {
+            position: Pos::default(),
+            description: Some(
+                "Order by several fields in turn. When given, `orderBy` and \
+                 `orderDirection` are ignored."
+                    .to_owned(),
+            ),
+            name: "order".to_string(),
+            value_type: Type::ListType(Box::new(Type::NonNullType(Box::new(Type::NamedType(
+                format!("{}_order", type_name),
+            ))))),
+            default_value: None,
+            directives: vec![],
+        },
         input_value(
             &"where".to_string(),
             "",
@@ -725,10 +1222,16 @@ fn add_field_arguments(
 
 #[cfg(test)]
 mod tests {
-    use graph::data::graphql::DocumentExt;
+    use graph::data::graphql::{
+        ext::{DirectiveExt, DirectiveFinder},
+        DocumentExt,
+    };
     use graphql_parser::schema::*;
 
-    use super::api_schema;
+    use super::{
+        api_schema, federation_sdl, CONNECTION_DIRECTIVE, ENTITIES_FIELD_NAME, ENTITY_UNION_TYPE,
+        KEY_DIRECTIVE, SERVICE_FIELD_NAME, SERVICE_TYPE,
+    };
     use crate::schema::ast;
 
     #[test]
@@ -811,6 +1314,56 @@ mod tests {
         assert_eq!(values, ["id", "name"]);
     }
 
+    #[test]
+    fn api_schema_contains_child_fields_in_order_by_enum() {
+        let input_schema = parse_schema(
+            r#"
+              type Pet {
+                  id: ID!
+                  name: String!
+                  owners: [User!]!
+                  favoriteOf: User
+              }
+
+              type User {
+                  id: ID!
+                  pets: [Pet!]!
+                  favoritePet: Pet
+                  leastFavoritePet: Pet @derivedFrom(field: "favoriteOf")
+              }
+            "#,
+        )
+        .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derived API schema");
+
+        let user_order_by = schema
+            .get_named_type("User_orderBy")
+            .expect("User_orderBy type is missing in derived API schema");
+
+        let enum_type = match user_order_by {
+            TypeDefinition::Enum(t) => Some(t),
+            _ => None,
+        }
+        .expect("User_orderBy type is not an enum");
+
+        let values: Vec<&str> = enum_type
+            .values
+            .iter()
+            .map(|value| value.name.as_str())
+            .collect();
+        assert_eq!(
+            values,
+            [
+                "id",
+                "pets",
+                "favoritePet",
+                "leastFavoritePet",
+                "favoritePet__id",
+                "favoritePet__name"
+            ]
+        );
+    }
+
     #[test]
     fn api_schema_contains_object_type_filter_enum() {
         let input_schema = parse_schema(
@@ -888,6 +1441,10 @@ mod tests {
                 "name_ends_with_nocase",
                 "name_not_ends_with",
                 "name_not_ends_with_nocase",
+                "name_like",
+                "name_ilike",
+                "name_not_like",
+                "name_not_ilike",
                 "favoritePetNames",
                 "favoritePetNames_not",
                 "favoritePetNames_contains",
@@ -900,6 +1457,7 @@ mod tests {
                 "pets_contains_nocase",
                 "pets_not_contains",
                 "pets_not_contains_nocase",
+                "pets_",
                 "favoriteFurType",
                 "favoriteFurType_not",
                 "favoriteFurType_in",
@@ -924,6 +1482,15 @@ mod tests {
                 "favoritePet_ends_with_nocase",
                 "favoritePet_not_ends_with",
                 "favoritePet_not_ends_with_nocase",
+                "favoritePet_like",
+                "favoritePet_ilike",
+                "favoritePet_not_like",
+                "favoritePet_not_ilike",
+                "favoritePet_",
+                "leastFavoritePet_",
+                "mostFavoritePets_",
+                "and",
+                "or",
             ]
             .iter()
             .map(ToString::to_string)
@@ -991,6 +1558,7 @@ mod tests {
                 "first",
                 "orderBy",
                 "orderDirection",
+                "order",
                 "where",
                 "block",
                 "subgraphError",
@@ -1025,6 +1593,139 @@ mod tests {
         );
     }
 
+    #[test]
+    fn api_schema_contains_aggregate_fields_on_query_type() {
+        let input_schema = parse_schema(
+            "type Token { id: ID!, name: String!, decimals: Int!, volume: BigDecimal! } \
+             type User { id: ID!, name: String! }",
+        )
+        .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let query_type = schema
+            .get_named_type("Query")
+            .expect("Query type is missing in derived API schema");
+
+        let tokens_aggregate_field = match query_type {
+            TypeDefinition::Object(t) => ast::get_field(t, &"tokensAggregate".to_string()),
+            _ => None,
+        }
+        .expect("\"tokensAggregate\" field is missing on Query type");
+
+        assert_eq!(
+            tokens_aggregate_field.field_type,
+            Type::NonNullType(Box::new(Type::NamedType("Token_aggregate".to_string())))
+        );
+        assert_eq!(
+            tokens_aggregate_field
+                .arguments
+                .iter()
+                .map(|input_value| input_value.name.to_owned())
+                .collect::<Vec<String>>(),
+            vec![
+                "where".to_string(),
+                "block".to_string(),
+                "subgraphError".to_string()
+            ],
+        );
+
+        let field_names = |type_name: &str| match schema.get_named_type(type_name) {
+            Some(TypeDefinition::Object(t)) => t
+                .fields
+                .iter()
+                .map(|field| field.name.to_owned())
+                .collect::<Vec<String>>(),
+            _ => panic!("{} type is missing in derived API schema", type_name),
+        };
+        assert_eq!(
+            field_names("Token_aggregate"),
+            vec!["count", "sum", "avg", "min", "max"]
+        );
+        let token_aggregate = match schema.get_named_type("Token_aggregate") {
+            Some(TypeDefinition::Object(t)) => t,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            ast::get_field(token_aggregate, "count")
+                .expect("\"count\" field is missing on Token_aggregate")
+                .field_type,
+            Type::NonNullType(Box::new(Type::NamedType("BigInt".to_string())))
+        );
+        assert_eq!(field_names("Token_sum_fields"), vec!["decimals", "volume"]);
+        assert_eq!(field_names("User_aggregate"), vec!["count"]);
+        assert!(schema.get_named_type("User_sum_fields").is_none());
+
+        let token_sum_fields = match schema.get_named_type("Token_sum_fields") {
+            Some(TypeDefinition::Object(t)) => t,
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            ast::get_field(token_sum_fields, "decimals")
+                .expect("\"decimals\" field is missing on Token_sum_fields")
+                .field_type,
+            Type::NamedType("BigInt".to_string())
+        );
+    }
+
+    #[test]
+    fn api_schema_contains_connection_fields_on_query_type() {
+        let input_schema = parse_schema("type User { id: ID!, name: String! }")
+            .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let query_type = schema
+            .get_named_type("Query")
+            .expect("Query type is missing in derived API schema");
+
+        let users_connection_field = match query_type {
+            TypeDefinition::Object(t) => ast::get_field(t, &"usersConnection".to_string()),
+            _ => None,
+        }
+        .expect("\"usersConnection\" field is missing on Query type");
+
+        assert_eq!(
+            users_connection_field.field_type,
+            Type::NonNullType(Box::new(Type::NamedType("User_connection".to_string())))
+        );
+        assert_eq!(
+            users_connection_field
+                .arguments
+                .iter()
+                .map(|input_value| input_value.name.to_owned())
+                .collect::<Vec<String>>(),
+            [
+                "skip",
+                "first",
+                "orderBy",
+                "orderDirection",
+                "order",
+                "where",
+                "after",
+                "block",
+                "subgraphError",
+            ]
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>()
+        );
+
+        let user_connection = match schema.get_named_type("User_connection") {
+            Some(TypeDefinition::Object(t)) => t,
+            _ => panic!("User_connection type is missing in derived API schema"),
+        };
+        assert_eq!(
+            user_connection
+                .fields
+                .iter()
+                .map(|field| field.name.to_owned())
+                .collect::<Vec<String>>(),
+            vec!["totalCount", "nodes", "endCursor"]
+        );
+        assert!(user_connection
+            .find_directive(CONNECTION_DIRECTIVE)
+            .is_some());
+    }
+
     #[test]
     fn api_schema_contains_interface_fields_on_query_type() {
         let input_schema = parse_schema(
@@ -1088,6 +1789,7 @@ mod tests {
                 "first",
                 "orderBy",
                 "orderDirection",
+                "order",
                 "where",
                 "block",
                 "subgraphError"
@@ -1135,4 +1837,47 @@ type Gravatar @entity {
         }
         .expect("\"metadata\" field is missing on Query type");
     }
+
+    #[test]
+    fn api_schema_contains_federation_fields() {
+        let input_schema = parse_schema(
+            "type User @entity { id: ID!, name: String! } \
+             type Point @entity(timeseries: true) { id: Int8!, timestamp: Int8!, x: Int! }",
+        )
+        .expect("Failed to parse input schema");
+        let schema = api_schema(&input_schema).expect("Failed to derive API schema");
+
+        let query_type = match schema.get_named_type("Query") {
+            Some(TypeDefinition::Object(t)) => t,
+            _ => panic!("Query type is missing in derived API schema"),
+        };
+        assert_eq!(
+            ast::get_field(query_type, SERVICE_FIELD_NAME)
+                .expect("\"_service\" field is missing on Query type")
+                .field_type,
+            Type::NonNullType(Box::new(Type::NamedType(SERVICE_TYPE.to_string())))
+        );
+        assert!(ast::get_field(query_type, ENTITIES_FIELD_NAME).is_some());
+
+        match schema.get_named_type(ENTITY_UNION_TYPE) {
+            Some(TypeDefinition::Union(t)) => assert_eq!(t.types, vec!["User".to_string()]),
+            _ => panic!("_Entity union is missing in derived API schema"),
+        }
+        match schema.get_named_type("User") {
+            Some(TypeDefinition::Object(t)) => {
+                let key = t.find_directive(KEY_DIRECTIVE).expect("User has no @key");
+                assert_eq!(
+                    key.argument("fields"),
+                    Some(&Value::String("id".to_string()))
+                );
+            }
+            _ => panic!("User type is missing in derived API schema"),
+        }
+
+        let sdl = federation_sdl(&schema);
+        assert!(sdl.contains("type User @key(fields: \"id\")"));
+        assert!(!sdl.contains("@entity"));
+        assert!(!sdl.contains(ENTITY_UNION_TYPE));
+        assert!(!sdl.contains(SERVICE_FIELD_NAME));
+    }
 }
@@ -32,6 +32,10 @@ pub(crate) enum FilterOp {
     EndsWithNoCase,
     NotEndsWith,
     NotEndsWithNoCase,
+    Like,
+    LikeNoCase,
+    NotLike,
+    NotLikeNoCase,
     Equal,
 }
 
@@ -65,6 +69,10 @@ pub(crate) fn parse_field_as_filter(key: &str) -> (String, FilterOp) {
         }
         k if k.ends_with("_ends_with") => ("_ends_with", FilterOp::EndsWith),
         k if k.ends_with("_ends_with_nocase") => ("_ends_with_nocase", FilterOp::EndsWithNoCase),
+        k if k.ends_with("_not_like") => ("_not_like", FilterOp::NotLike),
+        k if k.ends_with("_not_ilike") => ("_not_ilike", FilterOp::NotLikeNoCase),
+        k if k.ends_with("_like") => ("_like", FilterOp::Like),
+        k if k.ends_with("_ilike") => ("_ilike", FilterOp::LikeNoCase),
         _ => ("", FilterOp::Equal),
     };
 
@@ -327,9 +335,10 @@ pub fn is_entity_type(schema: &s::Document, t: &s::Type) -> bool {
 
 pub fn is_entity_type_definition(type_def: &s::TypeDefinition) -> bool {
     match type_def {
-        // Entity types are obvious
+        // Entity types are obvious, and aggregations are stored as entities
         s::TypeDefinition::Object(object_type) => {
             get_object_type_directive(object_type, String::from("entity")).is_some()
+                || get_object_type_directive(object_type, String::from("aggregation")).is_some()
         }
 
         // For now, we'll assume that only entities can implement interfaces;
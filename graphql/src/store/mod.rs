@@ -2,5 +2,6 @@ mod prefetch;
 mod query;
 mod resolver;
 
+pub(crate) use self::prefetch::export_query;
 pub use self::query::parse_subgraph_id;
 pub use self::resolver::StoreResolver;
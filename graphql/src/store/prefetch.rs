@@ -16,17 +16,18 @@ use graph::{components::store::EntityType, data::graphql::*};
 use graph::{
     data::graphql::ext::DirectiveFinder,
     prelude::{
-        s, ApiSchema, AttributeNames, BlockNumber, ChildMultiplicity, EntityCollection,
-        EntityFilter, EntityLink, EntityOrder, EntityWindow, Logger, ParentLink,
-        QueryExecutionError, QueryStore, StoreError, Value as StoreValue, WindowAttribute,
-        ENV_VARS,
+        s, AggregateFunction, ApiSchema, AttributeNames, BlockNumber, ChildMultiplicity,
+        EntityAggregate, EntityCollection, EntityFilter, EntityLink, EntityOrder, EntityQuery,
+        EntityWindow, Logger, ParentLink, QueryExecutionError, QueryStore, StoreError,
+        Value as StoreValue, WindowAttribute, ENV_VARS,
     },
 };
 
 use crate::execution::{ast as a, ExecutionContext, Resolver};
 use crate::runner::ResultSizeMetrics;
+use crate::schema::api::{AGGREGATE_DIRECTIVE, CONNECTION_DIRECTIVE, ENTITY_UNION_TYPE};
 use crate::schema::ast as sast;
-use crate::store::query::build_query;
+use crate::store::query::{build_aggregate_query, build_order, build_query, Cursor};
 use crate::store::StoreResolver;
 
 lazy_static! {
@@ -547,9 +548,45 @@ fn execute_selection_set<'a>(
             let field_type = object_type
                 .field(&field.name)
                 .expect("field names are valid");
+            if field_type.field_type.get_base_type() == ENTITY_UNION_TYPE {
+                match execute_entities_field(resolver, ctx, field) {
+                    Ok(children) => {
+                        Join::perform(&mut parents, children, field.response_key());
+                        let weight = parents.iter().map(|parent| parent.weight()).sum::<usize>();
+                        check_result_size(&ctx.logger, weight)?;
+                    }
+                    Err(mut e) => errors.append(&mut e),
+                }
+                continue;
+            }
+            if let Some(directive) = field_type.find_directive(AGGREGATE_DIRECTIVE) {
+                match execute_aggregate_field(resolver, ctx, directive, field) {
+                    Ok(node) => Join::perform(&mut parents, vec![node], field.response_key()),
+                    Err(e) => errors.push(e),
+                }
+                continue;
+            }
             let child_type = schema
                 .object_or_interface(field_type.field_type.get_base_type())
                 .expect("we only collect fields that are objects or interfaces");
+            if let Some(directive) = child_type.directives().find_directive(CONNECTION_DIRECTIVE) {
+                match execute_connection_field(
+                    resolver,
+                    ctx,
+                    &parents,
+                    object_type,
+                    directive,
+                    field,
+                ) {
+                    Ok(node) => {
+                        Join::perform(&mut parents, vec![node], field.response_key());
+                        let weight = parents.iter().map(|parent| parent.weight()).sum::<usize>();
+                        check_result_size(&ctx.logger, weight)?;
+                    }
+                    Err(mut e) => errors.append(&mut e),
+                }
+                continue;
+            }
 
             let join = Join::new(
                 ctx.query.schema.as_ref(),
@@ -625,7 +662,7 @@ fn execute_field(
         join,
         field,
         multiplicity,
-        ctx.query.schema.types_for_interface(),
+        ctx.query.schema.as_ref(),
         resolver.block_number(),
         ctx.max_first,
         ctx.max_skip,
@@ -635,6 +672,264 @@ fn execute_field(
     .map_err(|e| vec![e])
 }
 
+/// Compute the aggregates that `field`, one of the `<types>Aggregate`
+/// fields on the `Query` type, asks for with a single database query. The
+/// result is one node that holds `count` directly and the aggregates of
+/// individual attributes like `sum { volume }` as a nested object under
+/// the `prefetch:` key of their response key, just like any other child
+fn execute_aggregate_field(
+    resolver: &StoreResolver,
+    ctx: &ExecutionContext<impl Resolver>,
+    directive: &s::Directive,
+    field: &a::Field,
+) -> Result<Node, QueryExecutionError> {
+    let schema = &ctx.query.schema;
+    let entity_type = match directive.argument("entity") {
+        Some(s::Value::String(name)) => schema.object_or_interface(name),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        constraint_violation!(
+            "the aggregate field `{}` does not reference an entity type",
+            field.name
+        )
+    })?;
+
+    // Collect the aggregates we need to compute together with the position
+    // of their value in the result. We always compute `count` since it is
+    // cheap and saves us from checking whether it was selected
+    let mut aggregates = vec![EntityAggregate::count()];
+    let mut attributes: Vec<(&a::Field, Vec<(&a::Field, usize)>)> = Vec::new();
+    for (_, fields) in field.selection_set.fields() {
+        for child in fields {
+            let function = match child.name.parse::<AggregateFunction>() {
+                Ok(AggregateFunction::Count) | Err(_) => continue,
+                Ok(function) => function,
+            };
+            let mut values = Vec::new();
+            for (_, attrs) in child.selection_set.fields() {
+                for attr in attrs.filter(|attr| !attr.name.starts_with("__")) {
+                    values.push((attr, aggregates.len()));
+                    aggregates.push(EntityAggregate::new(function, attr.name.clone()));
+                }
+            }
+            attributes.push((child, values));
+        }
+    }
+
+    let mut query = build_aggregate_query(
+        entity_type,
+        resolver.block_number(),
+        field,
+        schema.document(),
+    )?;
+    query.query_id = Some(ctx.query.query_id.clone());
+    query.logger = Some(ctx.logger.clone());
+    let mut values = resolver.store.aggregate_query_values(query, &aggregates)?;
+
+    let mut entity = BTreeMap::new();
+    entity.insert(
+        "__typename".to_string(),
+        r::Value::String(format!("{}_aggregate", entity_type.name())),
+    );
+    // The store returns counts as `Int`, but the `count` of an aggregate
+    // is a `BigInt`
+    let count = match std::mem::replace(&mut values[0], r::Value::Null) {
+        r::Value::Int(count) => r::Value::String(count.to_string()),
+        count => count,
+    };
+    entity.insert("count".to_string(), count);
+    for (child, attrs) in attributes {
+        let mut object = BTreeMap::new();
+        object.insert(
+            "__typename".to_string(),
+            r::Value::String(format!("{}_{}_fields", entity_type.name(), child.name)),
+        );
+        for (attr, pos) in attrs {
+            object.insert(attr.name.clone(), values[pos].clone());
+        }
+        entity.insert(
+            format!("prefetch:{}", child.response_key()),
+            r::Value::List(vec![r::Value::object(object)]),
+        );
+    }
+    Ok(Node::from(entity))
+}
+
+/// Execute `field`, one of the `<types>Connection` fields on the `Query`
+/// type, and return a single node for the connection. The `totalCount` is
+/// computed with a `count(*)` that uses the same filter as the `nodes`,
+/// and is only computed if it was selected. The `nodes` are fetched just
+/// like for the corresponding `<types>` field
+fn execute_connection_field(
+    resolver: &StoreResolver,
+    ctx: &ExecutionContext<impl Resolver>,
+    parents: &[&mut Node],
+    parent_type: &s::ObjectType,
+    directive: &s::Directive,
+    field: &a::Field,
+) -> Result<Node, Vec<QueryExecutionError>> {
+    let schema = &ctx.query.schema;
+    let entity_type = match directive.argument("entity") {
+        Some(s::Value::String(name)) => schema.object_or_interface(name),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        vec![constraint_violation!(
+            "the connection field `{}` does not reference an entity type",
+            field.name
+        )
+        .into()]
+    })?;
+
+    let mut total_count = false;
+    let mut end_cursor = false;
+    let mut nodes_fields = Vec::new();
+    for (_, fields) in field.selection_set.fields() {
+        for child in fields {
+            match child.name.as_str() {
+                "totalCount" => total_count = true,
+                "endCursor" => end_cursor = true,
+                "nodes" => nodes_fields.push(child),
+                _ => { /* only `__typename` */ }
+            }
+        }
+    }
+
+    let mut entity = BTreeMap::new();
+    entity.insert(
+        "__typename".to_string(),
+        r::Value::String(format!("{}_connection", entity_type.name())),
+    );
+    if total_count {
+        let mut query = build_aggregate_query(
+            entity_type,
+            resolver.block_number(),
+            field,
+            schema.document(),
+        )
+        .map_err(|e| vec![e])?;
+        query.query_id = Some(ctx.query.query_id.clone());
+        query.logger = Some(ctx.logger.clone());
+        let count = resolver
+            .store
+            .aggregate_query_values(query, &[EntityAggregate::count()])
+            .map_err(|e| vec![e])?
+            .pop()
+            .unwrap_or(r::Value::Null);
+        entity.insert("totalCount".to_string(), count);
+    }
+
+    // All `nodes` fields use the arguments of the connection field and
+    // therefore the same entities
+    let children = if end_cursor || !nodes_fields.is_empty() {
+        let join = Join::new(schema.as_ref(), parent_type, entity_type, &field.name);
+        fetch(
+            ctx.logger.clone(),
+            resolver.store.as_ref(),
+            parents,
+            &join,
+            field,
+            ChildMultiplicity::Many,
+            schema.as_ref(),
+            resolver.block_number(),
+            ctx.max_first,
+            ctx.max_skip,
+            ctx.query.query_id.clone(),
+            SelectedAttributes::default(),
+        )
+        .map_err(|e| vec![e])?
+    } else {
+        vec![]
+    };
+    if end_cursor {
+        let order = build_order(entity_type, field, schema.document()).map_err(|e| vec![e])?;
+        let cursor = children
+            .last()
+            .and_then(|child| Cursor::for_entity(&order, &child.entity))
+            .map(|cursor| r::Value::String(cursor.encode()))
+            .unwrap_or(r::Value::Null);
+        entity.insert("endCursor".to_string(), cursor);
+    }
+    let mut node = Node::from(entity);
+
+    for nodes_field in nodes_fields {
+        let children =
+            execute_selection_set(resolver, ctx, children.clone(), &nodes_field.selection_set)?;
+        Join::perform(&mut [&mut node], children, nodes_field.response_key());
+    }
+    Ok(node)
+}
+
+/// Look up the entities for the `representations` that Apollo Federation
+/// gateways pass to the `_entities` field, with one query per entity type.
+/// The entities are not in any particular order; `StoreResolver` puts them
+/// in the order of the representations
+fn execute_entities_field(
+    resolver: &StoreResolver,
+    ctx: &ExecutionContext<impl Resolver>,
+    field: &a::Field,
+) -> Result<Vec<Node>, Vec<QueryExecutionError>> {
+    let schema = &ctx.query.schema;
+    let entity_types = match schema.get_named_type(ENTITY_UNION_TYPE) {
+        Some(s::TypeDefinition::Union(union)) => &union.types,
+        _ => {
+            return Err(vec![constraint_violation!(
+                "the `_Entity` union is missing"
+            )
+            .into()])
+        }
+    };
+
+    let mut ids: BTreeMap<&str, Vec<StoreValue>> = BTreeMap::new();
+    let representations = match field.argument_value("representations") {
+        Some(r::Value::List(representations)) => representations.as_slice(),
+        _ => &[],
+    };
+    for representation in representations {
+        let (typename, id) = match representation {
+            r::Value::Object(obj) => (obj.get("__typename"), obj.get("id")),
+            _ => (None, None),
+        };
+        match (typename, id) {
+            (Some(r::Value::String(typename)), Some(r::Value::String(id)))
+                if entity_types.contains(typename) =>
+            {
+                ids.entry(typename.as_str())
+                    .or_default()
+                    .push(StoreValue::from(id.to_owned()))
+            }
+            _ => {
+                return Err(vec![QueryExecutionError::InvalidArgumentError(
+                    field.position,
+                    "representations".to_owned(),
+                    representation.clone().into(),
+                )])
+            }
+        }
+    }
+
+    let mut children = Vec::new();
+    for (typename, ids) in ids {
+        let first = ids.len() as u32;
+        let collection = EntityCollection::All(vec![(
+            EntityType::new(typename.to_owned()),
+            AttributeNames::All,
+        )]);
+        let mut query = EntityQuery::new(schema.id().clone(), resolver.block_number(), collection)
+            .filter(EntityFilter::In(ARG_ID.to_owned(), ids))
+            .first(first);
+        query.query_id = Some(ctx.query.query_id.clone());
+        query.logger = Some(ctx.logger.clone());
+        let entities = resolver
+            .store
+            .find_query_values(query)
+            .map_err(|e| vec![e])?;
+        children.extend(entities.into_iter().map(Node::from));
+    }
+    execute_selection_set(resolver, ctx, children, &field.selection_set)
+}
+
 /// Query child entities for `parents` from the store. The `join` indicates
 /// in which child field to look for the parent's id/join field. When
 /// `is_single` is `true`, there is at most one child per parent.
@@ -645,7 +940,7 @@ fn fetch(
     join: &Join<'_>,
     field: &a::Field,
     multiplicity: ChildMultiplicity,
-    types_for_interface: &BTreeMap<EntityType, Vec<s::ObjectType>>,
+    schema: &ApiSchema,
     block: BlockNumber,
     max_first: u32,
     max_skip: u32,
@@ -656,7 +951,8 @@ fn fetch(
         join.child_type,
         block,
         field,
-        types_for_interface,
+        schema.document(),
+        schema.types_for_interface(),
         max_first,
         max_skip,
         selected_attrs,
@@ -691,6 +987,51 @@ fn fetch(
         .map(|entities| entities.into_iter().map(|entity| entity.into()).collect())
 }
 
+/// Build the query for the toplevel list `field` that is exported with
+/// `@export`. Its `skip` is checked against `max_skip` like for any other
+/// list field, but its `first` can go up to `GRAPH_GRAPHQL_EXPORT_MAX_ROWS`
+/// since the entities are streamed from the database
+pub(crate) fn export_query(
+    logger: Logger,
+    schema: &ApiSchema,
+    field: &a::Field,
+    block: BlockNumber,
+    query_id: String,
+    max_skip: u32,
+) -> Result<EntityQuery, QueryExecutionError> {
+    let field_type = sast::get_field(schema.query_type.as_ref(), &field.name).ok_or_else(|| {
+        QueryExecutionError::UnknownField(
+            field.position,
+            schema.query_type.name.clone(),
+            field.name.clone(),
+        )
+    })?;
+    let entity_type = schema
+        .object_or_interface(field_type.field_type.get_base_type())
+        .ok_or_else(|| {
+            constraint_violation!("the exported field `{}` is not an entity list", field.name)
+        })?;
+
+    let selected_attrs = if !ENV_VARS.enable_select_by_specific_attributes {
+        SelectedAttributes(BTreeMap::new())
+    } else {
+        SelectedAttributes::for_field(field).map_err(|mut errors| errors.remove(0))?
+    };
+    let mut query = build_query(
+        entity_type,
+        block,
+        field,
+        schema.document(),
+        schema.types_for_interface(),
+        ENV_VARS.graphql.export_max_rows,
+        max_skip,
+        selected_attrs,
+    )?;
+    query.query_id = Some(query_id);
+    query.logger = Some(logger);
+    Ok(query)
+}
+
 #[derive(Debug, Default, Clone)]
 pub(crate) struct SelectedAttributes(BTreeMap<String, AttributeNames>);
 
@@ -723,12 +1064,17 @@ impl SelectedAttributes {
         }
         // We need to also select the `orderBy` field if there is one.
         // Because of how the API Schema is set up, `orderBy` can only have
-        // an enum value
+        // an enum value. For `orderBy: <field>__<child field>`, we need the
+        // field that references the child
         match field.argument_value("orderBy") {
             None => { /* nothing to do */ }
             Some(r::Value::Enum(e)) => {
+                let attr = match e.split_once("__") {
+                    Some((attr, _)) if !attr.is_empty() => attr,
+                    _ => e.as_str(),
+                };
                 for columns in map.values_mut() {
-                    columns.add_str(e);
+                    columns.add_str(attr);
                 }
             }
             Some(v) => {
@@ -739,6 +1085,18 @@ impl SelectedAttributes {
                 .into()]);
             }
         }
+        // The same goes for the fields in `order`
+        if let Some(r::Value::List(keys)) = field.argument_value("order") {
+            for key in keys {
+                if let r::Value::Object(key) = key {
+                    if let Some(r::Value::Enum(e)) = key.get("field") {
+                        for columns in map.values_mut() {
+                            columns.add_str(e);
+                        }
+                    }
+                }
+            }
+        }
         Ok(SelectedAttributes(map))
     }
 
@@ -1,11 +1,13 @@
 use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
 use std::mem::discriminant;
 
+use graph::components::store::{Child, EntityType};
+use graph::data::graphql::{DocumentExt, ObjectOrInterface};
 use graph::data::value::Object;
 use graph::prelude::*;
-use graph::{components::store::EntityType, data::graphql::ObjectOrInterface};
 
 use crate::execution::ast as a;
+use crate::schema::api::LOGICAL_FILTER_OPS;
 use crate::schema::ast as sast;
 
 use super::prefetch::SelectedAttributes;
@@ -23,6 +25,7 @@ pub(crate) fn build_query<'a>(
     entity: impl Into<ObjectOrInterface<'a>>,
     block: BlockNumber,
     field: &a::Field,
+    schema: &s::Document,
     types_for_interface: &'a BTreeMap<EntityType, Vec<s::ObjectType>>,
     max_first: u32,
     max_skip: u32,
@@ -45,22 +48,289 @@ pub(crate) fn build_query<'a>(
     });
     let mut query = EntityQuery::new(parse_subgraph_id(entity)?, block, entity_types)
         .range(build_range(field, max_first, max_skip)?);
-    if let Some(filter) = build_filter(entity, field)? {
+    let order = build_order(entity, field, schema)?;
+    let filter = build_filter(entity, field, schema)?;
+    let filter = match build_after(entity, field, &order)? {
+        Some(after) => Some(after.and_maybe(filter)),
+        None => filter,
+    };
+    if let Some(filter) = filter {
         query = query.filter(filter);
     }
-    let order = match (
-        build_order_by(entity, field)?,
-        build_order_direction(field)?,
-    ) {
-        (Some((attr, value_type)), OrderDirection::Ascending) => {
-            EntityOrder::Ascending(attr, value_type)
+    query = query.order(order);
+    Ok(query)
+}
+
+/// An opaque cursor for keyset pagination. It consists of the attributes
+/// the query is ordered by together with their direction, the values of
+/// these attributes and the `id` of the last entity on a page. Clients see
+/// it as hex-encoded JSON
+#[derive(Debug, PartialEq)]
+pub(crate) struct Cursor {
+    /// The attributes the query is ordered by, and whether each of them is
+    /// sorted descending
+    order_by: Vec<(String, bool)>,
+    values: Vec<r::Value>,
+    id: r::Value,
+}
+
+impl Cursor {
+    /// The cursor for `entity` when entities are ordered by `order`
+    pub(crate) fn for_entity(
+        order: &EntityOrder,
+        entity: &BTreeMap<String, r::Value>,
+    ) -> Option<Self> {
+        let id = entity.get("id")?.clone();
+        let order_by: Vec<_> = order_keys(order)?
+            .into_iter()
+            .map(|(attr, descending)| (attr.to_owned(), descending))
+            .collect();
+        let values = order_by
+            .iter()
+            .map(|(attr, _)| entity.get(attr).cloned().unwrap_or(r::Value::Null))
+            .collect();
+        Some(Cursor {
+            order_by,
+            values,
+            id,
+        })
+    }
+
+    pub(crate) fn encode(&self) -> String {
+        let order_by: Vec<_> = self
+            .order_by
+            .iter()
+            .map(|(attr, descending)| {
+                let direction = if *descending { "desc" } else { "asc" };
+                serde_json::json!([attr, direction])
+            })
+            .collect();
+        let json = serde_json::json!([order_by, self.values, self.id]);
+        hex::encode(json.to_string())
+    }
+
+    fn decode(cursor: &str) -> Option<Self> {
+        let json = hex::decode(cursor).ok()?;
+        let json: serde_json::Value = serde_json::from_slice(&json).ok()?;
+        match json {
+            serde_json::Value::Array(mut parts) if parts.len() == 3 => {
+                let id = r::Value::from(parts.pop()?);
+                let values: Vec<_> = match parts.pop()? {
+                    serde_json::Value::Array(values) => {
+                        values.into_iter().map(r::Value::from).collect()
+                    }
+                    _ => return None,
+                };
+                let order_by = match parts.pop()? {
+                    serde_json::Value::Array(keys) => keys
+                        .iter()
+                        .map(Self::decode_key)
+                        .collect::<Option<Vec<_>>>()?,
+                    _ => return None,
+                };
+                if order_by.len() != values.len() {
+                    return None;
+                }
+                Some(Cursor {
+                    order_by,
+                    values,
+                    id,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Decode one `[attr, direction]` pair of the attributes the query is
+    /// ordered by
+    fn decode_key(key: &serde_json::Value) -> Option<(String, bool)> {
+        match key.as_array()?.as_slice() {
+            [attr, direction] => {
+                let attr = attr.as_str()?.to_owned();
+                match direction.as_str()? {
+                    "asc" => Some((attr, false)),
+                    "desc" => Some((attr, true)),
+                    _ => None,
+                }
+            }
+            _ => None,
         }
-        (Some((attr, value_type)), OrderDirection::Descending) => {
-            EntityOrder::Descending(attr, value_type)
+    }
+}
+
+/// The attributes that `order` sorts by, and whether each of them is
+/// sorted descending. Returns `None` if the order uses attributes of other
+/// entities
+fn order_keys(order: &EntityOrder) -> Option<Vec<(&str, bool)>> {
+    match order {
+        EntityOrder::Ascending(attr, _) => Some(vec![(attr.as_str(), false)]),
+        EntityOrder::Descending(attr, _) => Some(vec![(attr.as_str(), true)]),
+        EntityOrder::Compound(orders) => orders
+            .iter()
+            .map(order_keys)
+            .collect::<Option<Vec<_>>>()
+            .map(|keys| keys.into_iter().flatten().collect()),
+        EntityOrder::ChildAscending(_) | EntityOrder::ChildDescending(_) => None,
+        EntityOrder::Default | EntityOrder::Unordered => Some(vec![]),
+    }
+}
+
+/// Parses the `after` argument into a filter that only matches entities
+/// that come after the cursor in `order`. Since entities are ordered by
+/// `order` and then by `id`, that is a row comparison on both attributes
+/// that Postgres can answer with an index on them. When ordering by
+/// several attributes, possibly in different directions, we compare them
+/// one after the other instead.
+///
+/// Nulls come last when ordering ascending and first when ordering
+/// descending, and need to be handled separately since they do not
+/// compare with anything
+fn build_after(
+    entity: ObjectOrInterface,
+    field: &a::Field,
+    order: &EntityOrder,
+) -> Result<Option<EntityFilter>, QueryExecutionError> {
+    let cursor = match field.argument_value("after") {
+        Some(r::Value::String(cursor)) => cursor,
+        None | Some(r::Value::Null) => return Ok(None),
+        Some(_) => return Err(QueryExecutionError::InvalidFilterError),
+    };
+    let invalid = || {
+        QueryExecutionError::InvalidArgumentError(
+            field.position,
+            "after".to_string(),
+            q::Value::String(cursor.clone()),
+        )
+    };
+
+    if let EntityOrder::Unordered = order {
+        return Ok(None);
+    }
+    let keys = order_keys(order).ok_or_else(|| {
+        QueryExecutionError::NotSupported(
+            "`after` when ordering by a field of a related entity".to_string(),
+        )
+    })?;
+    // A cursor can only be used with the order it was made for, including
+    // the direction of each attribute
+    let cursor = Cursor::decode(cursor).ok_or_else(invalid)?;
+    if !cursor
+        .order_by
+        .iter()
+        .map(|(attr, descending)| (attr.as_str(), *descending))
+        .eq(keys.iter().copied())
+    {
+        return Err(invalid());
+    }
+
+    let store_value = |attr: &str, value: &r::Value| -> Result<Value, QueryExecutionError> {
+        let field = sast::get_field(entity, attr).ok_or_else(|| {
+            QueryExecutionError::EntityFieldError(entity.name().to_owned(), attr.to_owned())
+        })?;
+        Value::from_query_value(value, &field.field_type)
+    };
+    let id = store_value("id", &cursor.id)?;
+    let id_after = |descending| match descending {
+        false => EntityFilter::GreaterThan("id".to_string(), id.clone()),
+        true => EntityFilter::LessThan("id".to_string(), id.clone()),
+    };
+
+    let (attr, descending) = match keys.as_slice() {
+        [] => return Ok(Some(id_after(false))),
+        [(attr, descending)] if *attr == "id" => return Ok(Some(id_after(*descending))),
+        [(attr, descending)] => (attr.to_string(), *descending),
+        _ => return build_compound_after(&keys, &cursor.values, store_value, id_after),
+    };
+    let value = store_value(&attr, &cursor.values[0])?;
+    let filter = match (value, descending) {
+        (Value::Null, false) => EntityFilter::And(vec![
+            EntityFilter::Equal(attr.clone(), Value::Null),
+            id_after(false),
+        ]),
+        (Value::Null, true) => EntityFilter::Or(vec![
+            EntityFilter::Not(attr.clone(), Value::Null),
+            EntityFilter::And(vec![
+                EntityFilter::Equal(attr.clone(), Value::Null),
+                id_after(true),
+            ]),
+        ]),
+        (value, false) => EntityFilter::Or(vec![
+            EntityFilter::RowGreaterThan(vec![
+                (attr.clone(), value),
+                ("id".to_string(), id.clone()),
+            ]),
+            EntityFilter::Equal(attr.clone(), Value::Null),
+        ]),
+        (value, true) => {
+            EntityFilter::RowLessThan(vec![(attr.clone(), value), ("id".to_string(), id.clone())])
         }
-        (None, _) => EntityOrder::Default,
     };
-    query = query.order(order);
+    Ok(Some(filter))
+}
+
+/// The `after` filter for an order by several attributes `a1, .., an` and
+/// then `id`. An entity comes after the cursor if for some `i` it has the
+/// same values as the cursor for `a1, .., a(i-1)` and comes after it for
+/// `ai`, or if it has the same values for all of them and comes after it
+/// for `id`, which is sorted in the direction of `an`
+fn build_compound_after(
+    keys: &[(&str, bool)],
+    values: &[r::Value],
+    store_value: impl Fn(&str, &r::Value) -> Result<Value, QueryExecutionError>,
+    id_after: impl Fn(bool) -> EntityFilter,
+) -> Result<Option<EntityFilter>, QueryExecutionError> {
+    let mut equal = vec![];
+    let mut after = vec![];
+    for ((attr, descending), value) in keys.iter().zip(values) {
+        let attr = attr.to_string();
+        let value = store_value(&attr, value)?;
+        let key_after = match (&value, descending) {
+            (Value::Null, false) => EntityFilter::Or(vec![]),
+            (Value::Null, true) => EntityFilter::Not(attr.clone(), Value::Null),
+            (value, false) => EntityFilter::Or(vec![
+                EntityFilter::GreaterThan(attr.clone(), value.clone()),
+                EntityFilter::Equal(attr.clone(), Value::Null),
+            ]),
+            (value, true) => EntityFilter::LessThan(attr.clone(), value.clone()),
+        };
+        let mut filters = equal.clone();
+        filters.push(key_after);
+        after.push(EntityFilter::And(filters));
+        equal.push(EntityFilter::Equal(attr, value));
+    }
+    let descending = keys
+        .last()
+        .map(|(_, descending)| *descending)
+        .unwrap_or(false);
+    equal.push(id_after(descending));
+    after.push(EntityFilter::And(equal));
+    Ok(Some(EntityFilter::Or(after)))
+}
+
+/// Builds the EntityQuery for an aggregate field from its GraphQL
+/// arguments. Since all matching entities are aggregated, the query has no
+/// order and its range is ignored
+pub(crate) fn build_aggregate_query<'a>(
+    entity: impl Into<ObjectOrInterface<'a>>,
+    block: BlockNumber,
+    field: &a::Field,
+    schema: &s::Document,
+) -> Result<EntityQuery, QueryExecutionError> {
+    let entity = entity.into();
+    let entity_types = EntityCollection::All(match &entity {
+        ObjectOrInterface::Object(object) => vec![((*object).into(), AttributeNames::All)],
+        ObjectOrInterface::Interface(_) => {
+            return Err(QueryExecutionError::NotSupported(format!(
+                "aggregates over the interface `{}`",
+                entity.name()
+            )))
+        }
+    });
+    let mut query = EntityQuery::new(parse_subgraph_id(entity)?, block, entity_types)
+        .order(EntityOrder::Unordered);
+    if let Some(filter) = build_filter(entity, field, schema)? {
+        query = query.filter(filter);
+    }
     Ok(query)
 }
 
@@ -110,9 +380,10 @@ fn build_range(
 fn build_filter(
     entity: ObjectOrInterface,
     field: &a::Field,
+    schema: &s::Document,
 ) -> Result<Option<EntityFilter>, QueryExecutionError> {
     match field.argument_value("where") {
-        Some(r::Value::Object(object)) => build_filter_from_object(entity, object),
+        Some(r::Value::Object(object)) => build_filter_from_object(entity, object, schema),
         Some(r::Value::Null) => Ok(None),
         None => match field.argument_value("text") {
             Some(r::Value::Object(filter)) => build_fulltext_filter_from_object(filter),
@@ -145,6 +416,7 @@ fn build_fulltext_filter_from_object(
 fn build_filter_from_object(
     entity: ObjectOrInterface,
     object: &Object,
+    schema: &s::Document,
 ) -> Result<Option<EntityFilter>, QueryExecutionError> {
     Ok(Some(EntityFilter::And({
         object
@@ -152,6 +424,21 @@ fn build_filter_from_object(
             .map(|(key, value)| {
                 use self::sast::FilterOp::*;
 
+                if LOGICAL_FILTER_OPS.contains(&key.as_str())
+                    && sast::get_field(entity, key).is_none()
+                {
+                    return build_logical_filter(entity, key, value, schema);
+                }
+
+                if let Some(field_name) = key
+                    .strip_suffix('_')
+                    .filter(|_| sast::get_field(entity, key).is_none())
+                {
+                    if let Some(field) = sast::get_field(entity, field_name) {
+                        return build_child_filter(entity, field, value, schema);
+                    }
+                }
+
                 let (field_name, op) = sast::parse_field_as_filter(key);
 
                 let field = sast::get_field(entity, &field_name).ok_or_else(|| {
@@ -186,6 +473,10 @@ fn build_filter_from_object(
                     EndsWithNoCase => EntityFilter::EndsWithNoCase(field_name, store_value),
                     NotEndsWith => EntityFilter::NotEndsWith(field_name, store_value),
                     NotEndsWithNoCase => EntityFilter::NotEndsWithNoCase(field_name, store_value),
+                    Like => EntityFilter::Like(field_name, store_value),
+                    LikeNoCase => EntityFilter::LikeNoCase(field_name, store_value),
+                    NotLike => EntityFilter::NotLike(field_name, store_value),
+                    NotLikeNoCase => EntityFilter::NotLikeNoCase(field_name, store_value),
                     Equal => EntityFilter::Equal(field_name, store_value),
                 })
             })
@@ -193,6 +484,75 @@ fn build_filter_from_object(
     })))
 }
 
+/// Builds the filter for `and: [..]` and `or: [..]` where `value` is a list
+/// of filter objects
+fn build_logical_filter(
+    entity: ObjectOrInterface,
+    op: &str,
+    value: &r::Value,
+    schema: &s::Document,
+) -> Result<EntityFilter, QueryExecutionError> {
+    let filters = match value {
+        r::Value::List(filters) => filters
+            .iter()
+            .map(|filter| match filter {
+                r::Value::Object(object) => build_filter_from_object(entity, object, schema)
+                    .map(|filter| filter.unwrap_or_else(|| EntityFilter::And(vec![]))),
+                _ => Err(QueryExecutionError::InvalidFilterError),
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        r::Value::Null => return Ok(EntityFilter::And(vec![])),
+        _ => return Err(QueryExecutionError::InvalidFilterError),
+    };
+    Ok(match op {
+        "and" => EntityFilter::And(filters),
+        _ => EntityFilter::Or(filters),
+    })
+}
+
+/// Builds the filter for `<field>_: { .. }` which matches entities whose
+/// children, i.e., the entities that `field` references, match the nested
+/// filter
+fn build_child_filter(
+    entity: ObjectOrInterface,
+    field: &s::Field,
+    value: &r::Value,
+    schema: &s::Document,
+) -> Result<EntityFilter, QueryExecutionError> {
+    let object = match value {
+        r::Value::Object(object) => object,
+        _ => return Err(QueryExecutionError::InvalidFilterError),
+    };
+    let child_type_name = sast::get_field_name(&field.field_type);
+    let child_type = match schema.object_or_interface(&child_type_name) {
+        Some(child_type @ ObjectOrInterface::Object(_)) => child_type,
+        Some(ObjectOrInterface::Interface(_)) => {
+            return Err(QueryExecutionError::NotSupported(format!(
+                "filtering on the fields of the interface `{}`",
+                child_type_name
+            )))
+        }
+        None => {
+            return Err(QueryExecutionError::EntityFieldError(
+                entity.name().to_owned(),
+                format!("{}_", field.name),
+            ))
+        }
+    };
+    let (attr, derived) = match sast::get_derived_from_field(child_type, field) {
+        Some(derived_from) => (derived_from.name.clone(), true),
+        None => (field.name.clone(), false),
+    };
+    let filter = build_filter_from_object(child_type, object, schema)?
+        .unwrap_or_else(|| EntityFilter::And(vec![]));
+    Ok(EntityFilter::Child(Child {
+        attr,
+        entity_type: EntityType::new(child_type_name),
+        filter: Box::new(filter),
+        derived,
+    }))
+}
+
 /// Parses a list of GraphQL values into a vector of entity field values.
 fn list_values(value: Value, filter_type: &str) -> Result<Vec<Value>, QueryExecutionError> {
     match value {
@@ -221,25 +581,147 @@ fn list_values(value: Value, filter_type: &str) -> Result<Vec<Value>, QueryExecu
     }
 }
 
+/// Parses GraphQL arguments into the EntityOrder for a query
+pub(crate) fn build_order(
+    entity: ObjectOrInterface,
+    field: &a::Field,
+    schema: &s::Document,
+) -> Result<EntityOrder, QueryExecutionError> {
+    if let Some(order) = build_compound_order(entity, field)? {
+        return Ok(order);
+    }
+    if let Some(child) = build_child_order_by(entity, field, schema)? {
+        return Ok(match build_order_direction(field)? {
+            OrderDirection::Ascending => EntityOrder::ChildAscending(child),
+            OrderDirection::Descending => EntityOrder::ChildDescending(child),
+        });
+    }
+    Ok(
+        match (
+            build_order_by(entity, field)?,
+            build_order_direction(field)?,
+        ) {
+            (Some((attr, value_type)), OrderDirection::Ascending) => {
+                EntityOrder::Ascending(attr, value_type)
+            }
+            (Some((attr, value_type)), OrderDirection::Descending) => {
+                EntityOrder::Descending(attr, value_type)
+            }
+            (None, _) => EntityOrder::Default,
+        },
+    )
+}
+
+/// Parses the `order: [{ field, direction }]` argument, if present. Since
+/// entities are always ordered by `id` last, keys after `id` are ignored
+fn build_compound_order(
+    entity: ObjectOrInterface,
+    field: &a::Field,
+) -> Result<Option<EntityOrder>, QueryExecutionError> {
+    let keys = match field.argument_value("order") {
+        Some(r::Value::List(keys)) => keys,
+        _ => return Ok(None),
+    };
+    let mut orders = vec![];
+    for key in keys {
+        let (name, direction) = match key {
+            r::Value::Object(key) => (key.get("field"), key.get("direction")),
+            _ => return Err(QueryExecutionError::InvalidFilterError),
+        };
+        let (attr, value_type) = match name {
+            Some(r::Value::Enum(name)) => order_by_attribute(entity, name)?,
+            _ => return Err(QueryExecutionError::InvalidFilterError),
+        };
+        let is_id = attr == "id";
+        orders.push(match direction {
+            None | Some(r::Value::Null) => EntityOrder::Ascending(attr, value_type),
+            Some(r::Value::Enum(direction)) if direction == "asc" => {
+                EntityOrder::Ascending(attr, value_type)
+            }
+            Some(r::Value::Enum(direction)) if direction == "desc" => {
+                EntityOrder::Descending(attr, value_type)
+            }
+            Some(direction) => {
+                return Err(QueryExecutionError::InvalidArgumentError(
+                    field.position,
+                    "order".to_string(),
+                    direction.clone().into(),
+                ))
+            }
+        });
+        if is_id {
+            break;
+        }
+    }
+    Ok(match orders.len() {
+        0 => None,
+        1 => orders.pop(),
+        _ => Some(EntityOrder::Compound(orders)),
+    })
+}
+
+/// The attribute and value type for ordering by the field `name`
+fn order_by_attribute(
+    entity: ObjectOrInterface,
+    name: &str,
+) -> Result<(String, ValueType), QueryExecutionError> {
+    let field = sast::get_field(entity, name).ok_or_else(|| {
+        QueryExecutionError::EntityFieldError(entity.name().to_owned(), name.to_owned())
+    })?;
+    sast::get_field_value_type(&field.field_type)
+        .map(|value_type| (name.to_owned(), value_type))
+        .map_err(|_| {
+            QueryExecutionError::OrderByNotSupportedError(entity.name().to_owned(), name.to_owned())
+        })
+}
+
+/// Parses `orderBy: <field>__<child field>` into the attribute of the
+/// child to order by. Returns `None` if `orderBy` does not have that form
+fn build_child_order_by(
+    entity: ObjectOrInterface,
+    field: &a::Field,
+    schema: &s::Document,
+) -> Result<Option<EntityOrderByChild>, QueryExecutionError> {
+    let name = match field.argument_value("orderBy") {
+        Some(r::Value::Enum(name)) if sast::get_field(entity, name).is_none() => name,
+        _ => return Ok(None),
+    };
+    let (join_attribute, child_attribute) = match name.split_once("__") {
+        Some(parts) => parts,
+        None => return Ok(None),
+    };
+    let not_supported =
+        || QueryExecutionError::OrderByNotSupportedError(entity.name().to_owned(), name.clone());
+
+    let join_field = sast::get_field(entity, join_attribute).ok_or_else(|| {
+        QueryExecutionError::EntityFieldError(entity.name().to_owned(), join_attribute.to_owned())
+    })?;
+    if sast::is_list(&join_field.field_type)
+        || sast::get_derived_from_directive(join_field).is_some()
+    {
+        return Err(not_supported());
+    }
+    let child_type_name = sast::get_field_name(&join_field.field_type);
+    let child_type = match schema.object_or_interface(&child_type_name) {
+        Some(child_type @ ObjectOrInterface::Object(_)) => child_type,
+        _ => return Err(not_supported()),
+    };
+    let (attribute, value_type) = order_by_attribute(child_type, child_attribute)?;
+    Ok(Some(EntityOrderByChild {
+        join_attribute: join_attribute.to_owned(),
+        entity_type: EntityType::new(child_type_name),
+        attribute,
+        value_type,
+    }))
+}
+
 /// Parses GraphQL arguments into an field name to order by, if present.
 fn build_order_by(
     entity: ObjectOrInterface,
     field: &a::Field,
 ) -> Result<Option<(String, ValueType)>, QueryExecutionError> {
     match field.argument_value("orderBy") {
-        Some(r::Value::Enum(name)) => {
-            let field = sast::get_field(entity, name).ok_or_else(|| {
-                QueryExecutionError::EntityFieldError(entity.name().to_owned(), name.clone())
-            })?;
-            sast::get_field_value_type(&field.field_type)
-                .map(|value_type| Some((name.to_owned(), value_type)))
-                .map_err(|_| {
-                    QueryExecutionError::OrderByNotSupportedError(
-                        entity.name().to_owned(),
-                        name.clone(),
-                    )
-                })
-        }
+        Some(r::Value::Enum(name)) => order_by_attribute(entity, name).map(Some),
         _ => match field.argument_value("text") {
             Some(r::Value::Object(filter)) => build_fulltext_order_by_from_object(filter),
             None => Ok(None),
@@ -350,8 +832,8 @@ mod tests {
         components::store::EntityType,
         data::value::Object,
         prelude::{
-            r, AttributeNames, EntityCollection, EntityFilter, EntityRange, Value, ValueType,
-            BLOCK_NUMBER_MAX,
+            r, AttributeNames, EntityCollection, EntityFilter, EntityRange, QueryExecutionError,
+            Value, ValueType, BLOCK_NUMBER_MAX,
         },
         prelude::{
             s::{self, Directive, Field, InputValue, ObjectType, Type, Value as SchemaValue},
@@ -361,7 +843,7 @@ mod tests {
     use graphql_parser::Pos;
     use std::{collections::BTreeMap, iter::FromIterator, sync::Arc};
 
-    use super::{a, build_query};
+    use super::{a, build_query, Cursor};
 
     fn default_object() -> ObjectType {
         let subgraph_id_argument = (
@@ -426,6 +908,12 @@ mod tests {
         }
     }
 
+    fn empty_schema() -> s::Document {
+        s::Document {
+            definitions: vec![],
+        }
+    }
+
     fn default_field() -> a::Field {
         let arguments = vec![
             ("first".to_string(), r::Value::Int(100.into())),
@@ -463,6 +951,7 @@ mod tests {
                 &object("Entity1"),
                 BLOCK_NUMBER_MAX,
                 &default_field(),
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX,
@@ -477,6 +966,7 @@ mod tests {
                 &object("Entity2"),
                 BLOCK_NUMBER_MAX,
                 &default_field(),
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX,
@@ -495,6 +985,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &default_field(),
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX,
@@ -514,6 +1005,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &field,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX,
@@ -530,6 +1022,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &field,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX,
@@ -541,6 +1034,218 @@ mod tests {
         );
     }
 
+    #[test]
+    fn build_query_rejects_unknown_order_directions() {
+        let order = |direction: &str| {
+            let key = BTreeMap::from_iter(vec![
+                ("field".to_string(), r::Value::Enum("name".to_string())),
+                (
+                    "direction".to_string(),
+                    r::Value::Enum(direction.to_string()),
+                ),
+            ]);
+            default_field_with("order", r::Value::List(vec![r::Value::object(key)]))
+        };
+        let build = |field: &a::Field| {
+            build_query(
+                &default_object(),
+                BLOCK_NUMBER_MAX,
+                field,
+                &empty_schema(),
+                &BTreeMap::new(),
+                std::u32::MAX,
+                std::u32::MAX,
+                Default::default(),
+            )
+        };
+
+        assert_eq!(
+            build(&order("desc")).unwrap().order,
+            EntityOrder::Descending("name".to_string(), ValueType::String)
+        );
+        assert!(matches!(
+            build(&order("sideways")),
+            Err(QueryExecutionError::InvalidArgumentError(_, name, _)) if name == "order"
+        ));
+    }
+
+    #[test]
+    fn build_query_parses_after_cursor_into_row_comparison() {
+        let mut object = default_object();
+        object.fields.push(field(
+            "id",
+            Type::NonNullType(Box::new(Type::NamedType("ID".to_string()))),
+        ));
+        let entity = BTreeMap::from_iter(vec![
+            ("id".to_string(), r::Value::String("e1".to_string())),
+            ("name".to_string(), r::Value::String("bob".to_string())),
+        ]);
+        let cursor = Cursor::for_entity(
+            &EntityOrder::Descending("name".to_string(), ValueType::String),
+            &entity,
+        )
+        .unwrap()
+        .encode();
+
+        let field = default_field_with_vec(vec![
+            ("orderBy", r::Value::Enum("name".to_string())),
+            ("orderDirection", r::Value::Enum("desc".to_string())),
+            ("after", r::Value::String(cursor.clone())),
+        ]);
+        assert_eq!(
+            build_query(
+                &object,
+                BLOCK_NUMBER_MAX,
+                &field,
+                &empty_schema(),
+                &BTreeMap::new(),
+                std::u32::MAX,
+                std::u32::MAX,
+                Default::default()
+            )
+            .unwrap()
+            .filter,
+            Some(EntityFilter::RowLessThan(vec![
+                ("name".to_string(), Value::String("bob".to_string())),
+                ("id".to_string(), Value::String("e1".to_string())),
+            ]))
+        );
+
+        // A cursor can only be used with the order it was made for
+        let field = default_field_with("after", r::Value::String(cursor.clone()));
+        assert!(build_query(
+            &object,
+            BLOCK_NUMBER_MAX,
+            &field,
+            &empty_schema(),
+            &BTreeMap::new(),
+            std::u32::MAX,
+            std::u32::MAX,
+            Default::default()
+        )
+        .is_err());
+
+        // That includes the direction of the order
+        let field = default_field_with_vec(vec![
+            ("orderBy", r::Value::Enum("name".to_string())),
+            ("orderDirection", r::Value::Enum("asc".to_string())),
+            ("after", r::Value::String(cursor)),
+        ]);
+        assert!(build_query(
+            &object,
+            BLOCK_NUMBER_MAX,
+            &field,
+            &empty_schema(),
+            &BTreeMap::new(),
+            std::u32::MAX,
+            std::u32::MAX,
+            Default::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn build_query_parses_order_into_compound_order() {
+        let key = |name: &str, direction: &str| {
+            r::Value::Object(Object::from_iter(vec![
+                ("field".to_string(), r::Value::Enum(name.to_string())),
+                (
+                    "direction".to_string(),
+                    r::Value::Enum(direction.to_string()),
+                ),
+            ]))
+        };
+        let mut object = default_object();
+        object.fields.push(field(
+            "id",
+            Type::NonNullType(Box::new(Type::NamedType("ID".to_string()))),
+        ));
+        let build = |field: &a::Field| {
+            build_query(
+                &object,
+                BLOCK_NUMBER_MAX,
+                field,
+                &empty_schema(),
+                &BTreeMap::new(),
+                std::u32::MAX,
+                std::u32::MAX,
+                Default::default(),
+            )
+            .unwrap()
+        };
+
+        // Keys after `id` are ignored
+        let order = r::Value::List(vec![
+            key("name", "desc"),
+            key("email", "asc"),
+            key("id", "asc"),
+            key("name", "asc"),
+        ]);
+        let field = default_field_with_vec(vec![
+            ("orderBy", r::Value::Enum("email".to_string())),
+            ("order", order),
+        ]);
+        let compound = EntityOrder::Compound(vec![
+            EntityOrder::Descending("name".to_string(), ValueType::String),
+            EntityOrder::Ascending("email".to_string(), ValueType::String),
+            EntityOrder::Ascending("id".to_string(), ValueType::String),
+        ]);
+        assert_eq!(build(&field).order, compound);
+
+        // An `after` cursor compares the keys one after the other
+        let entity = BTreeMap::from_iter(vec![
+            ("id".to_string(), r::Value::String("e1".to_string())),
+            ("name".to_string(), r::Value::String("bob".to_string())),
+            ("email".to_string(), r::Value::Null),
+        ]);
+        let cursor = Cursor::for_entity(&compound, &entity).unwrap().encode();
+        let order = r::Value::List(vec![key("name", "desc"), key("email", "asc")]);
+        let field =
+            default_field_with_vec(vec![("order", order), ("after", r::Value::String(cursor))]);
+        // The cursor was made for a different order
+        assert!(build_query(
+            &object,
+            BLOCK_NUMBER_MAX,
+            &field,
+            &empty_schema(),
+            &BTreeMap::new(),
+            std::u32::MAX,
+            std::u32::MAX,
+            Default::default()
+        )
+        .is_err());
+
+        let cursor = Cursor::for_entity(
+            &EntityOrder::Compound(vec![
+                EntityOrder::Descending("name".to_string(), ValueType::String),
+                EntityOrder::Ascending("email".to_string(), ValueType::String),
+            ]),
+            &entity,
+        )
+        .unwrap()
+        .encode();
+        let order = r::Value::List(vec![key("name", "desc"), key("email", "asc")]);
+        let field =
+            default_field_with_vec(vec![("order", order), ("after", r::Value::String(cursor))]);
+        let name = || Value::String("bob".to_string());
+        let id = || Value::String("e1".to_string());
+        assert_eq!(
+            build(&field).filter,
+            Some(EntityFilter::Or(vec![
+                EntityFilter::And(vec![EntityFilter::LessThan("name".to_string(), name())]),
+                EntityFilter::And(vec![
+                    EntityFilter::Equal("name".to_string(), name()),
+                    EntityFilter::Or(vec![]),
+                ]),
+                EntityFilter::And(vec![
+                    EntityFilter::Equal("name".to_string(), name()),
+                    EntityFilter::Equal("email".to_string(), Value::Null),
+                    EntityFilter::GreaterThan("id".to_string(), id()),
+                ]),
+            ]))
+        );
+    }
+
     #[test]
     fn build_query_ignores_order_by_from_non_enum_values() {
         let field = default_field_with("orderBy", r::Value::String("name".to_string()));
@@ -549,6 +1254,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &field,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX,
@@ -565,6 +1271,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &field,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX,
@@ -587,6 +1294,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &field,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX,
@@ -606,6 +1314,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &field,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX,
@@ -628,6 +1337,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &field,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX,
@@ -648,6 +1358,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &field,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX,
@@ -666,6 +1377,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &default_field(),
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX,
@@ -687,6 +1399,7 @@ mod tests {
                 &default_object(),
                 BLOCK_NUMBER_MAX,
                 &field,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX,
@@ -718,6 +1431,7 @@ mod tests {
                 },
                 BLOCK_NUMBER_MAX,
                 &query_field,
+                &empty_schema(),
                 &BTreeMap::new(),
                 std::u32::MAX,
                 std::u32::MAX,
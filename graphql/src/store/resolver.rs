@@ -1,7 +1,8 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::result;
 use std::sync::Arc;
 
+use graph::blockchain::BlockHashResolver;
 use graph::data::value::Object;
 use graph::data::{
     graphql::{object, ObjectOrInterface},
@@ -13,6 +14,7 @@ use graph::{components::store::*, data::schema::BLOCK_FIELD_TYPE};
 use crate::execution::ast as a;
 use crate::query::ext::BlockConstraint;
 use crate::runner::ResultSizeMetrics;
+use crate::schema::api::{federation_sdl, ENTITY_UNION_TYPE, SERVICE_TYPE};
 use crate::schema::ast as sast;
 use crate::{prelude::*, schema::api::ErrorPolicy};
 
@@ -28,6 +30,7 @@ pub struct StoreResolver {
     pub(crate) block_ptr: Option<BlockPtr>,
     deployment: DeploymentHash,
     has_non_fatal_errors: bool,
+    skipped_blocks: Vec<BlockNumber>,
     error_policy: ErrorPolicy,
     result_size: Arc<ResultSizeMetrics>,
 }
@@ -55,6 +58,7 @@ impl StoreResolver {
 
             // Checking for non-fatal errors does not work with subscriptions.
             has_non_fatal_errors: false,
+            skipped_blocks: vec![],
             error_policy: ErrorPolicy::Deny,
             result_size,
         }
@@ -73,14 +77,39 @@ impl StoreResolver {
         error_policy: ErrorPolicy,
         deployment: DeploymentHash,
         result_size: Arc<ResultSizeMetrics>,
+        block_hash_resolver: Option<Arc<dyn BlockHashResolver>>,
     ) -> Result<Self, QueryExecutionError> {
         let store_clone = store.cheap_clone();
         let deployment2 = deployment.clone();
-        let block_ptr = Self::locate_block(store_clone.as_ref(), bc, deployment2.clone()).await?;
+        let time_travel = !matches!(bc, BlockConstraint::Latest);
+        let block_ptr = Self::locate_block(
+            logger,
+            store_clone.as_ref(),
+            bc,
+            deployment2.clone(),
+            block_hash_resolver,
+        )
+        .await?;
+
+        if time_travel {
+            // History before the earliest block might have been pruned
+            let earliest = store.deployment_state().await?.earliest_block_number;
+            if block_ptr.number < earliest {
+                return Err(QueryExecutionError::ValueParseError(
+                    "block.number".to_owned(),
+                    format!(
+                        "subgraph {} only has data starting at block number {} \
+                            and data for block number {} is therefore not available",
+                        deployment, earliest, block_ptr.number
+                    ),
+                ));
+            }
+        }
 
         let has_non_fatal_errors = store
             .has_non_fatal_errors(Some(block_ptr.block_number()))
             .await?;
+        let skipped_blocks = store.skipped_blocks(block_ptr.block_number()).await?;
 
         let resolver = StoreResolver {
             logger: logger.new(o!("component" => "StoreResolver")),
@@ -89,6 +118,7 @@ impl StoreResolver {
             block_ptr: Some(block_ptr),
             deployment,
             has_non_fatal_errors,
+            skipped_blocks,
             error_policy,
             result_size,
         };
@@ -103,9 +133,11 @@ impl StoreResolver {
     }
 
     async fn locate_block(
+        logger: &Logger,
         store: &dyn QueryStore,
         bc: BlockConstraint,
         subgraph: DeploymentHash,
+        block_hash_resolver: Option<Arc<dyn BlockHashResolver>>,
     ) -> Result<BlockPtr, QueryExecutionError> {
         fn check_ptr(
             subgraph: DeploymentHash,
@@ -127,19 +159,26 @@ impl StoreResolver {
         }
         match bc {
             BlockConstraint::Hash(hash) => {
-                store
-                    .block_number(hash)
-                    .map_err(Into::into)
-                    .and_then(|number| {
-                        number
-                            .ok_or_else(|| {
-                                QueryExecutionError::ValueParseError(
-                                    "block.hash".to_owned(),
-                                    "no block with that hash found".to_owned(),
-                                )
-                            })
-                            .map(|number| BlockPtr::from((hash, number as u64)))
+                let number = match (store.block_number(hash)?, block_hash_resolver) {
+                    (Some(number), _) => Some(number),
+                    // The block is not in the chain store; ask the chain
+                    // about it. The chain adds the block to the chain store
+                    // so that we will find it there next time
+                    (None, Some(resolver)) => resolver
+                        .block_ptr_for_hash(logger, store.network_name(), hash.into())
+                        .await
+                        .map_err(|e| QueryExecutionError::StoreError(e.into()))?
+                        .map(|ptr| ptr.number),
+                    (None, None) => None,
+                };
+                number
+                    .ok_or_else(|| {
+                        QueryExecutionError::ValueParseError(
+                            "block.hash".to_owned(),
+                            "no block with that hash found".to_owned(),
+                        )
                     })
+                    .map(|number| BlockPtr::from((hash, number as u64)))
             }
             BlockConstraint::Number(number) => {
                 store.block_ptr().await.map_err(Into::into).and_then(|ptr| {
@@ -158,6 +197,21 @@ impl StoreResolver {
                 .await
                 .map_err(Into::into)
                 .and_then(|ptr| check_ptr(subgraph, ptr, number)),
+            BlockConstraint::Timestamp(timestamp) => {
+                let number = store.block_number_by_timestamp(timestamp)?.ok_or_else(|| {
+                    QueryExecutionError::ValueParseError(
+                        "block.timestamp_gte".to_owned(),
+                        "no block with a timestamp at or after that time found".to_owned(),
+                    )
+                })?;
+                store.block_ptr().await.map_err(Into::into).and_then(|ptr| {
+                    check_ptr(subgraph, ptr, number)?;
+                    // Like for `BlockConstraint::Number`, we do not know
+                    // the hash of the block
+                    // See 7a7b9708-adb7-4fc2-acec-88680cb07ec1
+                    Ok(BlockPtr::from((web3::types::H256::zero(), number as u64)))
+                })
+            }
             BlockConstraint::Latest => {
                 store.block_ptr().await.map_err(Into::into).map(|ptr| {
                     ptr.expect("we should have already checked that the subgraph exists")
@@ -211,6 +265,15 @@ impl StoreResolver {
                 "hasIndexingErrors".to_string(),
                 r::Value::Boolean(self.has_non_fatal_errors),
             );
+            map.insert(
+                "skippedBlocks".to_string(),
+                r::Value::List(
+                    self.skipped_blocks
+                        .iter()
+                        .map(|number| r::Value::Int((*number).into()))
+                        .collect(),
+                ),
+            );
             map.insert(
                 "__typename".to_string(),
                 r::Value::String(META_FIELD_TYPE.to_string()),
@@ -263,6 +326,13 @@ impl Resolver for StoreResolver {
         field_definition: &s::Field,
         object_type: ObjectOrInterface<'_>,
     ) -> Result<r::Value, QueryExecutionError> {
+        if object_type.name() == SERVICE_TYPE {
+            let sdl = federation_sdl(self.store.api_schema()?.document());
+            return Ok(object! {
+                sdl: sdl,
+                __typename: SERVICE_TYPE
+            });
+        }
         let (prefetched_object, meta) = self.handle_meta(prefetched_object, &object_type)?;
         if let Some(meta) = meta {
             return Ok(meta);
@@ -292,6 +362,24 @@ impl Resolver for StoreResolver {
         }
     }
 
+    fn resolve_union_objects(
+        &self,
+        prefetched_objects: Option<r::Value>,
+        field: &a::Field,
+        union_type: &s::UnionType,
+    ) -> Result<r::Value, QueryExecutionError> {
+        if union_type.name != ENTITY_UNION_TYPE {
+            return Err(QueryExecutionError::Unimplemented("unions".to_owned()));
+        }
+        match prefetched_objects {
+            Some(r::Value::List(entities)) => Ok(entities_for_representations(field, entities)),
+            _ => Err(QueryExecutionError::ResolveEntitiesError(format!(
+                "internal error resolving {}: expected prefetched result, but found nothing",
+                &field.name,
+            ))),
+        }
+    }
+
     fn resolve_field_stream(
         &self,
         schema: &ApiSchema,
@@ -331,3 +419,38 @@ impl Resolver for StoreResolver {
         Ok(())
     }
 }
+
+/// Arrange the `entities` that prefetch found for the `_entities` field in
+/// the order of its `representations` argument, as Apollo Federation
+/// requires. Representations for which no entity exists resolve to `null`
+fn entities_for_representations(field: &a::Field, entities: Vec<r::Value>) -> r::Value {
+    fn key(obj: &r::Value) -> Option<(&str, &str)> {
+        match obj {
+            r::Value::Object(obj) => match (obj.get("__typename"), obj.get("id")) {
+                (Some(r::Value::String(typename)), Some(r::Value::String(id))) => {
+                    Some((typename.as_str(), id.as_str()))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    let entities: HashMap<_, _> = entities
+        .iter()
+        .filter_map(|entity| key(entity).map(|key| (key, entity)))
+        .collect();
+    let values = match field.argument_value("representations") {
+        Some(r::Value::List(representations)) => representations
+            .iter()
+            .map(|representation| {
+                key(representation)
+                    .and_then(|key| entities.get(&key))
+                    .map(|entity| (*entity).clone())
+                    .unwrap_or(r::Value::Null)
+            })
+            .collect(),
+        _ => vec![],
+    };
+    r::Value::List(values)
+}
@@ -1,7 +1,9 @@
 use std::result::Result;
 use std::time::{Duration, Instant};
 
+use graph::blockchain::BlockHashResolver;
 use graph::components::store::UnitStream;
+use graph::data::value::Object;
 use graph::{components::store::SubscriptionManager, prelude::*};
 
 use crate::runner::ResultSizeMetrics;
@@ -38,6 +40,10 @@ pub struct SubscriptionExecutionOptions {
     pub max_skip: u32,
 
     pub result_size: Arc<ResultSizeMetrics>,
+
+    /// Looks up blocks that are referenced by hash but that are not in the
+    /// chain store.
+    pub block_hash_resolver: Option<Arc<dyn BlockHashResolver>>,
 }
 
 pub fn execute_subscription(
@@ -53,11 +59,13 @@ pub fn execute_subscription(
         options.max_complexity,
         options.max_depth,
     )?;
-    execute_prepared_subscription(query, options)
+    execute_prepared_subscription(query, subscription.filter, subscription.cursor, options)
 }
 
 pub(crate) fn execute_prepared_subscription(
     query: Arc<crate::execution::Query>,
+    filter: Option<SubscriptionFilter>,
+    cursor: Option<BlockPtr>,
     options: SubscriptionExecutionOptions,
 ) -> Result<SubscriptionResult, SubscriptionError> {
     if !query.is_subscription() {
@@ -73,7 +81,8 @@ pub(crate) fn execute_prepared_subscription(
     );
 
     let source_stream = create_source_event_stream(query.clone(), &options)?;
-    let response_stream = map_source_to_response_stream(query, options, source_stream);
+    let response_stream =
+        map_source_to_response_stream(query, filter, cursor, options, source_stream);
     Ok(response_stream)
 }
 
@@ -133,6 +142,8 @@ fn resolve_field_stream(
 
 fn map_source_to_response_stream(
     query: Arc<crate::execution::Query>,
+    filter: Option<SubscriptionFilter>,
+    cursor: Option<BlockPtr>,
     options: SubscriptionExecutionOptions,
     source_stream: UnitStream,
 ) -> QueryResultStream {
@@ -153,44 +164,149 @@ fn map_source_to_response_stream(
         max_first,
         max_skip,
         result_size,
+        block_hash_resolver,
     } = options;
 
-    trigger_stream
-        .chain(source_stream)
-        .then(move |()| {
-            execute_subscription_event(
+    let mut changes = ResultChanges::new(filter, cursor.is_some());
+
+    // Before we follow the latest block, replay the results for the blocks
+    // the client missed since it received the result for `cursor`
+    let replay_store = store.cheap_clone();
+    let replay_stream =
+        futures03::stream::once(async move { replay_blocks(replay_store.as_ref(), cursor).await })
+            .map(futures03::stream::iter)
+            .flatten();
+
+    replay_stream
+        .chain(
+            trigger_stream
+                .chain(source_stream)
+                .map(|()| Ok(BlockConstraint::Latest)),
+        )
+        .then(move |block| match block {
+            Ok(block) => execute_subscription_event(
                 logger.clone(),
                 store.clone(),
                 subscription_manager.cheap_clone(),
                 query.clone(),
+                block,
                 timeout,
                 max_first,
                 max_skip,
                 result_size.cheap_clone(),
+                block_hash_resolver.clone(),
             )
-            .boxed()
+            .boxed(),
+            Err(e) => futures03::future::ready(Arc::new(QueryResult::from(e))).boxed(),
         })
+        .filter_map(move |result| futures03::future::ready(changes.next(result)))
         .boxed()
 }
 
+/// The blocks for which a client that resumes a subscription from `cursor`
+/// needs to be sent results, i.e., all blocks after the cursor up to the
+/// latest block of the deployment. At most
+/// `GRAPH_GRAPHQL_MAX_SUBSCRIPTION_REPLAY_BLOCKS` blocks are replayed; if
+/// the cursor is older than that, the client is sent an error first
+async fn replay_blocks(
+    store: &dyn QueryStore,
+    cursor: Option<BlockPtr>,
+) -> Vec<Result<BlockConstraint, QueryExecutionError>> {
+    let cursor = match cursor {
+        Some(cursor) => cursor,
+        None => return vec![],
+    };
+    let latest = match store.block_ptr().await {
+        Ok(Some(latest)) => latest.number,
+        Ok(None) => return vec![],
+        Err(e) => return vec![Err(e.into())],
+    };
+    // If we do not know the block of the cursor, the chain was reorganized
+    // since the client received its result, and the client needs to be
+    // sent the result for that block number again
+    let first = match store.block_number(cursor.hash_as_h256()) {
+        Ok(Some(_)) => cursor.number + 1,
+        Ok(None) => cursor.number,
+        Err(e) => return vec![Err(e.into())],
+    };
+
+    let oldest = latest - ENV_VARS.graphql.max_subscription_replay_blocks + 1;
+    let mut blocks = Vec::new();
+    if first < oldest {
+        blocks.push(Err(QueryExecutionError::SubscriptionCursorTooOld(
+            cursor.number,
+            oldest,
+        )));
+    }
+    blocks.extend((first.max(oldest)..=latest).map(|number| Ok(BlockConstraint::Number(number))));
+    blocks
+}
+
+/// Decides which results of a subscription are sent to the client. When
+/// the client passed a filter or a cursor, the filter is applied to each
+/// result and a result is only sent if it differs from the previous one.
+/// Otherwise, all results are sent as they are
+struct ResultChanges {
+    filter: Option<SubscriptionFilter>,
+    only_changes: bool,
+    last: Option<Object>,
+}
+
+impl ResultChanges {
+    fn new(filter: Option<SubscriptionFilter>, resumed: bool) -> Self {
+        let only_changes = filter.is_some() || resumed;
+        ResultChanges {
+            filter,
+            only_changes,
+            last: None,
+        }
+    }
+
+    fn next(&mut self, result: Arc<QueryResult>) -> Option<Arc<QueryResult>> {
+        if !self.only_changes || result.has_errors() {
+            return Some(result);
+        }
+        let data = match (&self.filter, result.data()) {
+            (Some(filter), Some(data)) => filter.apply(data),
+            (None, Some(data)) => data.clone(),
+            (_, None) => return Some(result),
+        };
+        if self.last.as_ref() == Some(&data) {
+            return None;
+        }
+        self.last = Some(data.clone());
+
+        if self.filter.is_none() {
+            return Some(result);
+        }
+        let mut filtered = QueryResult::new(data);
+        filtered.deployment = result.deployment.clone();
+        filtered.block_ptr = result.block_ptr.clone();
+        Some(Arc::new(filtered))
+    }
+}
+
 async fn execute_subscription_event(
     logger: Logger,
     store: Arc<dyn QueryStore>,
     subscription_manager: Arc<dyn SubscriptionManager>,
     query: Arc<crate::execution::Query>,
+    block: BlockConstraint,
     timeout: Option<Duration>,
     max_first: u32,
     max_skip: u32,
     result_size: Arc<ResultSizeMetrics>,
+    block_hash_resolver: Option<Arc<dyn BlockHashResolver>>,
 ) -> Arc<QueryResult> {
     let resolver = match StoreResolver::at_block(
         &logger,
         store,
         subscription_manager,
-        BlockConstraint::Latest,
+        block,
         ErrorPolicy::Deny,
         query.schema.id().clone(),
         result_size,
+        block_hash_resolver,
     )
     .await
     {
@@ -17,7 +17,7 @@ use graph::{
     data::graphql::{object, object_value},
     data::subgraph::schema::SubgraphError,
     data::{
-        query::{QueryResults, QueryTarget},
+        query::{QueryLimits, QueryResults, QueryStatsOrder, QueryTarget},
         subgraph::SubgraphFeature,
     },
     prelude::{
@@ -25,7 +25,7 @@ use graph::{
         EntityKey, EntityOperation, FutureExtension, GraphQlRunner as _, Logger, NodeId, Query,
         QueryError, QueryExecutionError, QueryResult, QueryStoreManager, QueryVariables, Schema,
         SubgraphManifest, SubgraphName, SubgraphStore, SubgraphVersionSwitchingMode, Subscription,
-        SubscriptionError, Value,
+        SubscriptionError, Value, ENV_VARS,
     },
     semver::Version,
 };
@@ -867,6 +867,147 @@ fn query_complexity() {
     })
 }
 
+#[test]
+fn query_limits_per_deployment() {
+    run_test_sequentially(|store| async move {
+        let deployment = setup(store.as_ref());
+        let query = || {
+            Query::new(
+                graphql_parser::parse_query(
+                    "query {
+                    musicians(orderBy: id) {
+                        name
+                        bands(first: 100, orderBy: id) {
+                            name
+                            members(first: 100, orderBy: id) {
+                                name
+                            }
+                        }
+                    }
+                }",
+                )
+                .unwrap()
+                .into_static(),
+                None,
+            )
+        };
+        let limits = QueryLimits {
+            max_complexity: Some(1_010_099),
+            ..QueryLimits::default()
+        };
+        let runner = Arc::new(
+            GraphQlRunner::new(
+                &*LOGGER,
+                STORE.clone(),
+                SUBSCRIPTION_MANAGER.clone(),
+                LOAD_MANAGER.clone(),
+                METRICS_REGISTRY.clone(),
+            )
+            .with_deployment_limits(HashMap::from([(deployment.hash.clone(), limits)])),
+        );
+        let target = || QueryTarget::Deployment(deployment.hash.clone());
+
+        // The limit for the deployment applies when the caller does not
+        // ask for a specific limit
+        let result = first_result(runner.clone().run_query(query(), target()).await).await;
+        match result.to_result().unwrap_err()[0] {
+            QueryError::ExecutionError(QueryExecutionError::TooComplex(1_010_100, 1_010_099)) => (),
+            _ => panic!("did not apply the complexity limit for the deployment"),
+        };
+
+        // Limits that the caller asks for take precedence
+        let result = first_result(
+            runner
+                .run_query_with_complexity(query(), target(), Some(1_010_100), None, None, None)
+                .await,
+        )
+        .await;
+        assert!(!result.has_errors());
+    })
+}
+
+#[test]
+fn query_batch_runs_at_same_block() {
+    run_test_sequentially(|store| async move {
+        let deployment = setup(store.as_ref());
+        let query = |text: &str| {
+            Query::new(
+                graphql_parser::parse_query(text).unwrap().into_static(),
+                None,
+            )
+        };
+        let queries = vec![
+            query("query { musicians(orderBy: id) { id } }"),
+            query("query { musicians(orderBy: id, first: 1) { id name } }"),
+            query("query { bands(orderBy: id) { id } }"),
+        ];
+        let runner = Arc::new(GraphQlRunner::new(
+            &*LOGGER,
+            STORE.clone(),
+            SUBSCRIPTION_MANAGER.clone(),
+            LOAD_MANAGER.clone(),
+            METRICS_REGISTRY.clone(),
+        ));
+        let target = QueryTarget::Deployment(deployment.hash.clone());
+
+        let results = runner.run_query_batch(queries, target, None).await;
+        assert_eq!(3, results.len());
+
+        let mut results: Vec<_> = results
+            .iter()
+            .map(|result| result.first().unwrap().duplicate())
+            .collect();
+        for result in &results {
+            assert!(!result.has_errors());
+            assert!(result.block_ptr.is_some());
+            assert_eq!(results[0].block_ptr, result.block_ptr);
+        }
+
+        // Results are in the order of the queries in the batch
+        let exp = object! { musicians: vec![object! { id: "m1", name: "John" }] };
+        assert_eq!(extract_data!(results.remove(1)), Some(exp));
+        let exp = object! { bands: vec![object! { id: "b1" }, object! { id: "b2" }] };
+        assert_eq!(extract_data!(results.remove(1)), Some(exp));
+    })
+}
+
+#[test]
+fn records_query_stats() {
+    run_test_sequentially(|store| async move {
+        let deployment = setup(store.as_ref());
+        let runner = Arc::new(GraphQlRunner::new(
+            &*LOGGER,
+            STORE.clone(),
+            SUBSCRIPTION_MANAGER.clone(),
+            LOAD_MANAGER.clone(),
+            METRICS_REGISTRY.clone(),
+        ));
+        let target = QueryTarget::Deployment(deployment.hash.clone());
+
+        // Both queries have the same shape
+        for id in ["m1", "m2"] {
+            let text = format!(
+                "query {{ musician(id: \"{}\") {{ id bands {{ id }} }} }}",
+                id
+            );
+            let query = Query::new(
+                graphql_parser::parse_query(&text).unwrap().into_static(),
+                None,
+            );
+            let result = first_result(runner.clone().run_query(query, target.clone()).await).await;
+            assert!(!result.has_errors());
+        }
+
+        let stats = runner
+            .query_stats()
+            .top(&deployment.hash, 10, QueryStatsOrder::Count);
+        assert_eq!(1, stats.len());
+        assert_eq!(2, stats[0].count);
+        // John is in two bands and Lisa in one
+        assert_eq!(5, stats[0].rows);
+    })
+}
+
 #[test]
 fn query_complexity_subscriptions() {
     run_test_sequentially(|store| async move {
@@ -907,13 +1048,14 @@ fn query_complexity_subscriptions() {
             max_first: std::u32::MAX,
             max_skip: std::u32::MAX,
             result_size: result_size_metrics(),
+            block_hash_resolver: None,
         };
         let schema = STORE.subgraph_store().api_schema(&deployment.hash).unwrap();
 
         // This query is exactly at the maximum complexity.
         // FIXME: Not collecting the stream because that will hang the test.
         let _ignore_stream =
-            execute_subscription(Subscription { query }, schema.clone(), options).unwrap();
+            execute_subscription(Subscription::new(query), schema.clone(), options).unwrap();
 
         let query = Query::new(
             graphql_parser::parse_query(
@@ -956,9 +1098,10 @@ fn query_complexity_subscriptions() {
             max_first: std::u32::MAX,
             max_skip: std::u32::MAX,
             result_size: result_size_metrics(),
+            block_hash_resolver: None,
         };
 
-        let result = execute_subscription(Subscription { query }, schema, options);
+        let result = execute_subscription(Subscription::new(query), schema, options);
 
         match result {
             Err(SubscriptionError::GraphQLError(e)) => match &e[0] {
@@ -1341,10 +1484,11 @@ fn subscription_gets_result_even_without_events() {
             max_first: std::u32::MAX,
             max_skip: std::u32::MAX,
             result_size: result_size_metrics(),
+            block_hash_resolver: None,
         };
         // Execute the subscription and expect at least one result to be
         // available in the result stream
-        let stream = execute_subscription(Subscription { query }, schema, options).unwrap();
+        let stream = execute_subscription(Subscription::new(query), schema, options).unwrap();
         let results: Vec<_> = stream
             .take(1)
             .collect()
@@ -1819,9 +1963,23 @@ fn query_at_block_with_vars() {
             check_musicians_at(&deployment.hash, query, var, expected, qid).await;
         }
 
+        async fn musicians_at_timestamp_gte(
+            deployment: &DeploymentLocator,
+            timestamp: i32,
+            expected: Result<Vec<&str>, &str>,
+            qid: &str,
+        ) {
+            let query = "query by_ts($timestamp: Int!) { musicians(block: { timestamp_gte: $timestamp }) { id } }";
+            let var = Some(("timestamp", r::Value::Int(timestamp.into())));
+
+            check_musicians_at(&deployment.hash, query, var, expected, qid).await;
+        }
+
         const BLOCK_NOT_INDEXED: &str = "subgraph graphqlTestsQuery has only indexed \
          up to block number 1 and data for block number 7000 is therefore not yet available";
         const BLOCK_HASH_NOT_FOUND: &str = "no block with that hash found";
+        const BLOCK_TIMESTAMP_NOT_FOUND: &str =
+            "no block with a timestamp at or after that time found";
 
         let deployment = setup(store.as_ref());
         musicians_at_nr(&deployment, 7000, Err(BLOCK_NOT_INDEXED), "n7000").await;
@@ -1848,6 +2006,88 @@ fn query_at_block_with_vars() {
         )
         .await;
         musicians_at_hash(&deployment, &BLOCK_THREE, Err(BLOCK_HASH_NOT_FOUND), "h3").await;
+
+        // Test blocks have a timestamp of ten times their number
+        musicians_at_timestamp_gte(&deployment, 0, Ok(vec!["m1", "m2"]), "ts0").await;
+        musicians_at_timestamp_gte(&deployment, 5, Ok(vec!["m1", "m2", "m3", "m4"]), "ts5").await;
+        musicians_at_timestamp_gte(&deployment, 21, Err(BLOCK_TIMESTAMP_NOT_FOUND), "ts21").await;
+    })
+}
+
+#[test]
+fn query_at_block_hash_resolved_by_chain() {
+    run_test_sequentially(|store| async move {
+        use graph::blockchain::{BlockHash, BlockHashResolver};
+        use graph::prelude::{async_trait, Error};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use test_store::block_store::BLOCK_THREE;
+
+        /// Pretend that the chain knows about `BLOCK_THREE` even though it
+        /// is not in the chain store, and count how often it is asked
+        #[derive(Default)]
+        struct BlockThreeResolver {
+            lookups: AtomicUsize,
+        }
+
+        #[async_trait]
+        impl BlockHashResolver for BlockThreeResolver {
+            async fn block_ptr_for_hash(
+                &self,
+                _logger: &Logger,
+                _network: &str,
+                hash: BlockHash,
+            ) -> Result<Option<BlockPtr>, Error> {
+                self.lookups.fetch_add(1, Ordering::SeqCst);
+                let ptr = BLOCK_THREE.block_ptr();
+                Ok(Some(ptr).filter(|ptr| ptr.hash == hash))
+            }
+        }
+
+        let deployment = setup(store.as_ref());
+        let chain = Arc::new(BlockThreeResolver::default());
+        let runner = Arc::new(
+            GraphQlRunner::new(
+                &*LOGGER,
+                STORE.clone(),
+                SUBSCRIPTION_MANAGER.clone(),
+                LOAD_MANAGER.clone(),
+                METRICS_REGISTRY.clone(),
+            )
+            .with_block_hash_resolver(chain.clone()),
+        );
+        let run = |hash: &str| {
+            let query = format!(
+                "query {{ musicians(block: {{ hash: \"0x{}\" }}) {{ id }} }}",
+                hash
+            );
+            let query = graphql_parser::parse_query(&query)
+                .expect("invalid test query")
+                .into_static();
+            runner.clone().run_query(
+                Query::new(query, None),
+                QueryTarget::Deployment(deployment.hash.clone()),
+            )
+        };
+
+        let result = run(&BLOCK_THREE.hash).await.first().unwrap().duplicate();
+
+        let ids: Vec<_> = vec!["m1", "m2", "m3", "m4"]
+            .into_iter()
+            .map(|id| object_value(vec![("id", r::Value::String(String::from(id)))]))
+            .collect();
+        let expected = Some(object_value(vec![("musicians", r::Value::List(ids))]));
+        assert_eq!(extract_data!(result), expected);
+
+        // The chain is only asked once about a hash, even if it does not
+        // know it
+        let unknown = "deadbeef".repeat(8);
+        for _ in 0..3 {
+            let result = run(&unknown).await.first().unwrap().duplicate();
+            assert!(result.has_errors());
+        }
+        let result = run(&BLOCK_THREE.hash).await.first().unwrap().duplicate();
+        assert!(!result.has_errors());
+        assert_eq!(2, chain.lookups.load(Ordering::SeqCst));
     })
 }
 
@@ -2006,6 +2246,16 @@ fn non_fatal_errors() {
             .await
             .unwrap();
 
+        // The errors identify the deployment and block of the query
+        let extensions = json!({
+            "code": "INDEXING_ERROR",
+            "deployment": deployment.hash.as_str(),
+            "block": {
+                "number": BLOCK_TWO.block_ptr().number,
+                "hash": BLOCK_TWO.block_ptr().hash.to_string(),
+            }
+        });
+
         // `subgraphError` is implicitly `deny`, data is omitted.
         let query = "query { musician(id: \"m1\") { id } }";
         let query = graphql_parser::parse_query(query).unwrap().into_static();
@@ -2013,7 +2263,8 @@ fn non_fatal_errors() {
         let expected = json!({
             "errors": [
                 {
-                    "message": "indexing_error"
+                    "message": "indexing_error",
+                    "extensions": extensions,
                 }
             ]
         });
@@ -2037,7 +2288,8 @@ fn non_fatal_errors() {
             },
             "errors": [
                 {
-                    "message": "indexing_error"
+                    "message": "indexing_error",
+                    "extensions": extensions,
                 }
             ]
         });
@@ -2055,7 +2307,8 @@ fn non_fatal_errors() {
             },
             "errors": [
                 {
-                    "message": "indexing_error"
+                    "message": "indexing_error",
+                    "extensions": extensions,
                 }
             ]
         });
@@ -2095,3 +2348,602 @@ fn can_query_root_typename() {
         assert_eq!(extract_data!(result), Some(exp));
     })
 }
+
+#[test]
+fn can_query_aggregates() {
+    run_test_sequentially(|store| async move {
+        let query = "
+        query {
+            songStatsAggregate {
+                count
+                sum { played }
+                avg { played }
+                min { played }
+                max { played }
+            }
+            b1: musiciansAggregate(where: { mainBand: \"b1\" }) { count }
+        }";
+        let query = graphql_parser::parse_query(query)
+            .expect("invalid test query")
+            .into_static();
+        let deployment = setup(store.as_ref());
+        let result = execute_query_document(&deployment.hash, query).await;
+        let exp = object! {
+            songStatsAggregate: object! {
+                count: "2",
+                sum: object! { played: "25" },
+                avg: object! { played: "12.5" },
+                min: object! { played: 10 },
+                max: object! { played: 15 },
+            },
+            b1: object! { count: "2" }
+        };
+        assert_eq!(extract_data!(result), Some(exp));
+    })
+}
+
+#[test]
+fn aggregates_have_a_fixed_complexity() {
+    run_test_sequentially(|store| async move {
+        let deployment = setup(store.as_ref());
+        let query = || {
+            Query::new(
+                graphql_parser::parse_query(
+                    "query { songStatsAggregate { count sum { played } max { played } } }",
+                )
+                .unwrap()
+                .into_static(),
+                None,
+            )
+        };
+
+        // Computing the aggregates costs as much as counting entities for
+        // `totalCount`, no matter how many aggregates are selected
+        let result = first_result(
+            execute_subgraph_query_with_complexity(
+                query(),
+                deployment.hash.clone().into(),
+                Some(ENV_VARS.graphql.total_count_complexity),
+            )
+            .await,
+        )
+        .await;
+        assert!(!result.has_errors());
+
+        let result = first_result(
+            execute_subgraph_query_with_complexity(
+                query(),
+                deployment.hash.into(),
+                Some(ENV_VARS.graphql.total_count_complexity - 1),
+            )
+            .await,
+        )
+        .await;
+        match result.to_result().unwrap_err()[0] {
+            QueryError::ExecutionError(QueryExecutionError::TooComplex(complexity, _)) => {
+                assert_eq!(ENV_VARS.graphql.total_count_complexity, complexity)
+            }
+            _ => panic!("did not catch complexity"),
+        };
+    })
+}
+
+#[test]
+fn can_query_connection_with_total_count() {
+    run_test_sequentially(|store| async move {
+        let query = "
+        query {
+            musiciansConnection(first: 1, orderBy: id, where: { mainBand: \"b1\" }) {
+                totalCount
+                nodes { id }
+            }
+        }";
+        let query = graphql_parser::parse_query(query)
+            .expect("invalid test query")
+            .into_static();
+        let deployment = setup(store.as_ref());
+        let result = execute_query_document(&deployment.hash, query).await;
+        let exp = object! {
+            musiciansConnection: object! {
+                totalCount: 2,
+                nodes: vec![object! { id: "m1" }],
+            }
+        };
+        assert_eq!(extract_data!(result), Some(exp));
+    })
+}
+
+#[test]
+fn can_paginate_connection_with_cursor() {
+    run_test_sequentially(|store| async move {
+        let deployment = setup(store.as_ref());
+
+        let query = "
+        query {
+            musiciansConnection(first: 2, orderBy: name, orderDirection: desc) {
+                nodes { name }
+                endCursor
+            }
+        }";
+        let query = graphql_parser::parse_query(query)
+            .expect("invalid test query")
+            .into_static();
+        let result = execute_query_document(&deployment.hash, query).await;
+        let data = extract_data!(result).unwrap();
+        let connection = match &data {
+            r::Value::Object(data) => data.get("musiciansConnection").unwrap(),
+            _ => panic!("expected an object but got {:?}", data),
+        };
+        let (nodes, cursor) = match connection {
+            r::Value::Object(connection) => (
+                connection.get("nodes").unwrap().clone(),
+                connection.get("endCursor").unwrap().clone(),
+            ),
+            _ => panic!("expected an object but got {:?}", connection),
+        };
+        assert_eq!(
+            nodes,
+            r::Value::List(vec![object! { name: "Valerie" }, object! { name: "Tom" }])
+        );
+        let cursor = match cursor {
+            r::Value::String(cursor) => cursor,
+            _ => panic!("expected a cursor but got {:?}", cursor),
+        };
+
+        let query = format!(
+            "
+        query {{
+            musiciansConnection(first: 2, orderBy: name, orderDirection: desc, after: \"{}\") {{
+                nodes {{ name }}
+            }}
+        }}",
+            cursor
+        );
+        let query = graphql_parser::parse_query(&query)
+            .expect("invalid test query")
+            .into_static();
+        let result = execute_query_document(&deployment.hash, query).await;
+        let exp = object! {
+            musiciansConnection: object! {
+                nodes: vec![object! { name: "Lisa" }, object! { name: "John" }],
+            }
+        };
+        assert_eq!(extract_data!(result), Some(exp));
+    })
+}
+
+#[test]
+fn can_order_and_paginate_by_several_fields() {
+    run_test_sequentially(|store| async move {
+        let deployment = setup(store.as_ref());
+
+        let query = "
+        query {
+            musicians(order: [{ field: name, direction: desc }, { field: id }]) {
+                name
+            }
+            musiciansConnection(first: 2, order: [{ field: name, direction: desc }, { field: id }]) {
+                nodes { name }
+                endCursor
+            }
+        }";
+        let query = graphql_parser::parse_query(query)
+            .expect("invalid test query")
+            .into_static();
+        let result = execute_query_document(&deployment.hash, query).await;
+        let data = extract_data!(result).unwrap();
+        let (musicians, connection) = match &data {
+            r::Value::Object(data) => (
+                data.get("musicians").unwrap(),
+                data.get("musiciansConnection").unwrap(),
+            ),
+            _ => panic!("expected an object but got {:?}", data),
+        };
+        assert_eq!(
+            musicians,
+            &r::Value::List(vec![
+                object! { name: "Valerie" },
+                object! { name: "Tom" },
+                object! { name: "Lisa" },
+                object! { name: "John" }
+            ])
+        );
+        let cursor = match connection {
+            r::Value::Object(connection) => connection.get("endCursor").unwrap().clone(),
+            _ => panic!("expected an object but got {:?}", connection),
+        };
+        let cursor = match cursor {
+            r::Value::String(cursor) => cursor,
+            _ => panic!("expected a cursor but got {:?}", cursor),
+        };
+
+        let query = format!(
+            "
+        query {{
+            musiciansConnection(first: 2, order: [{{ field: name, direction: desc }}, {{ field: id }}], after: \"{}\") {{
+                nodes {{ name }}
+            }}
+        }}",
+            cursor
+        );
+        let query = graphql_parser::parse_query(&query)
+            .expect("invalid test query")
+            .into_static();
+        let result = execute_query_document(&deployment.hash, query).await;
+        let exp = object! {
+            musiciansConnection: object! {
+                nodes: vec![object! { name: "Lisa" }, object! { name: "John" }],
+            }
+        };
+        assert_eq!(extract_data!(result), Some(exp));
+    })
+}
+
+#[test]
+fn can_use_logical_filter_operators() {
+    run_test_sequentially(|store| async move {
+        let query = "
+        query {
+            musicians(orderBy: id, where: {
+                or: [
+                    { name: \"John\" },
+                    { and: [{ name_starts_with: \"T\" }, { mainBand: \"b2\" }] }
+                ]
+            }) {
+                id
+            }
+        }";
+        let query = graphql_parser::parse_query(query)
+            .expect("invalid test query")
+            .into_static();
+        let deployment = setup(store.as_ref());
+        let result = execute_query_document(&deployment.hash, query).await;
+        let exp = object! {
+            musicians: vec![object! { id: "m1" }, object! { id: "m3" }]
+        };
+        assert_eq!(extract_data!(result), Some(exp));
+    })
+}
+
+#[test]
+fn can_use_like_and_ilike_filters() {
+    run_test_sequentially(|store| async move {
+        let query = "
+        query {
+            like: musicians(orderBy: id, where: { name_like: \"T_m\" }) {
+                id
+            }
+            ilike: musicians(orderBy: id, where: { name_ilike: \"%O%\" }) {
+                id
+            }
+            notIlike: musicians(orderBy: id, where: { name_not_ilike: \"%O%\" }) {
+                id
+            }
+        }";
+        let query = graphql_parser::parse_query(query)
+            .expect("invalid test query")
+            .into_static();
+        let deployment = setup(store.as_ref());
+        let result = execute_query_document(&deployment.hash, query).await;
+        let exp = object! {
+            like: vec![object! { id: "m3" }],
+            ilike: vec![object! { id: "m1" }, object! { id: "m3" }],
+            notIlike: vec![object! { id: "m2" }, object! { id: "m4" }],
+        };
+        assert_eq!(extract_data!(result), Some(exp));
+    })
+}
+
+#[test]
+fn can_filter_on_fields_of_related_entities() {
+    run_test_sequentially(|store| async move {
+        let query = "
+        query {
+            byMainBand: musicians(orderBy: id, where: { mainBand_: { name: \"The Amateurs\" } }) {
+                id
+            }
+            byWrittenSongs: musicians(orderBy: id, where: { writtenSongs_: { title_starts_with: \"Rock\" } }) {
+                id
+            }
+            byMembers: bands(orderBy: id, where: { members_: { name: \"Lisa\" } }) {
+                id
+            }
+            byOriginalSongs: bands(orderBy: id, where: { originalSongs_: { title: \"Pop Tune\" } }) {
+                id
+            }
+        }";
+        let query = graphql_parser::parse_query(query)
+            .expect("invalid test query")
+            .into_static();
+        let deployment = setup(store.as_ref());
+        let result = execute_query_document(&deployment.hash, query).await;
+        let exp = object! {
+            byMainBand: vec![object! { id: "m3" }],
+            byWrittenSongs: vec![object! { id: "m2" }],
+            byMembers: vec![object! { id: "b1" }],
+            byOriginalSongs: vec![object! { id: "b2" }],
+        };
+        assert_eq!(extract_data!(result), Some(exp));
+    })
+}
+
+#[test]
+fn can_order_by_fields_of_related_entities() {
+    run_test_sequentially(|store| async move {
+        let query = "
+        query {
+            asc: musicians(orderBy: mainBand__name) {
+                id
+            }
+            desc: musicians(orderBy: mainBand__name, orderDirection: desc) {
+                id
+            }
+        }";
+        let query = graphql_parser::parse_query(query)
+            .expect("invalid test query")
+            .into_static();
+        let deployment = setup(store.as_ref());
+        let result = execute_query_document(&deployment.hash, query).await;
+        let exp = object! {
+            asc: vec![
+                object! { id: "m3" },
+                object! { id: "m1" },
+                object! { id: "m2" },
+                object! { id: "m4" },
+            ],
+            desc: vec![
+                object! { id: "m4" },
+                object! { id: "m2" },
+                object! { id: "m1" },
+                object! { id: "m3" },
+            ],
+        };
+        assert_eq!(extract_data!(result), Some(exp));
+    })
+}
+
+#[test]
+fn can_defer_fragments_and_stream_lists() {
+    run_test_sequentially(|store| async move {
+        use graph::data::query::IncrementalResponse;
+
+        const QUERY: &str = "
+        query {
+            musicians(first: 4, orderBy: id) @stream(initialCount: 1) {
+                id
+                ... @defer(label: \"names\") {
+                    name
+                }
+            }
+        }";
+        let query = graphql_parser::parse_query(QUERY)
+            .expect("invalid test query")
+            .into_static();
+        let deployment = setup(store.as_ref());
+
+        let runner = Arc::new(GraphQlRunner::new(
+            &*LOGGER,
+            STORE.clone(),
+            SUBSCRIPTION_MANAGER.clone(),
+            LOAD_MANAGER.clone(),
+            METRICS_REGISTRY.clone(),
+        ));
+        let response = runner
+            .run_query_incremental(
+                Query::new(query, None),
+                QueryTarget::Deployment(deployment.hash.clone()),
+                None,
+            )
+            .await;
+        let payloads = match response {
+            IncrementalResponse::Incremental { payloads, .. } => payloads,
+            IncrementalResponse::Complete(_) => panic!("expected an incremental response"),
+        };
+        let payloads: Vec<_> = payloads
+            .map(|payload| serde_json::to_value(&payload).unwrap())
+            .collect()
+            .await;
+
+        let exp = vec![
+            serde_json::json!({
+                "data": { "musicians": [{ "id": "m1" }] },
+                "hasNext": true
+            }),
+            serde_json::json!({
+                "incremental": [{
+                    "data": { "name": "John" },
+                    "path": ["musicians", 0],
+                    "label": "names"
+                }],
+                "hasNext": true
+            }),
+            serde_json::json!({
+                "incremental": [{
+                    "items": [
+                        { "id": "m2", "name": "Lisa" },
+                        { "id": "m3", "name": "Tom" },
+                        { "id": "m4", "name": "Valerie" }
+                    ],
+                    "path": ["musicians", 1]
+                }],
+                "hasNext": false
+            }),
+        ];
+        assert_eq!(exp, payloads);
+    })
+}
+
+#[test]
+fn can_export_lists() {
+    run_test_sequentially(|store| async move {
+        use graph::data::query::IncrementalResponse;
+
+        let deployment = setup(store.as_ref());
+        let runner = |enable_exports: bool| {
+            Arc::new(
+                GraphQlRunner::new(
+                    &*LOGGER,
+                    STORE.clone(),
+                    SUBSCRIPTION_MANAGER.clone(),
+                    LOAD_MANAGER.clone(),
+                    METRICS_REGISTRY.clone(),
+                )
+                .with_exports(enable_exports),
+            )
+        };
+        let run = |query: &str, enable_exports: bool| {
+            let query = graphql_parser::parse_query(query)
+                .expect("invalid test query")
+                .into_static();
+            runner(enable_exports).run_query_incremental(
+                Query::new(query, None),
+                QueryTarget::Deployment(deployment.hash.clone()),
+                None,
+            )
+        };
+        let collect = |response: IncrementalResponse| async move {
+            match response {
+                IncrementalResponse::Incremental { payloads, .. } => {
+                    payloads
+                        .map(|payload| serde_json::to_value(&payload).unwrap())
+                        .collect::<Vec<_>>()
+                        .await
+                }
+                IncrementalResponse::Complete(_) => panic!("expected an incremental response"),
+            }
+        };
+
+        let response = run(
+            "
+        query {
+            musicians(orderBy: id, where: { id_not: \"m3\" }) @export {
+                __typename
+                id
+                nick: name
+            }
+        }",
+            true,
+        )
+        .await;
+        let payloads = collect(response).await;
+
+        let exp = vec![
+            serde_json::json!({
+                "data": { "musicians": [] },
+                "hasNext": true
+            }),
+            serde_json::json!({
+                "incremental": [{
+                    "items": [
+                        { "__typename": "Musician", "id": "m1", "nick": "John" },
+                        { "__typename": "Musician", "id": "m2", "nick": "Lisa" },
+                        { "__typename": "Musician", "id": "m4", "nick": "Valerie" }
+                    ],
+                    "path": ["musicians", 0]
+                }],
+                "hasNext": true
+            }),
+            serde_json::json!({ "hasNext": false }),
+        ];
+        assert_eq!(exp, payloads);
+
+        // Exports respect `first` and `skip`
+        let response = run(
+            "query { musicians(first: 2, skip: 1, orderBy: id) @export { id } }",
+            true,
+        )
+        .await;
+        let payloads = collect(response).await;
+        assert_eq!(
+            serde_json::json!([{ "id": "m2" }, { "id": "m3" }]),
+            payloads[1]["incremental"][0]["items"]
+        );
+
+        // Exports are not bound by the limit for `first` of other list
+        // fields, only by `GRAPH_GRAPHQL_EXPORT_MAX_ROWS`
+        let response = run("query { musicians(first: 1000000) @export { id } }", true).await;
+        let payloads = collect(response).await;
+        assert!(payloads[1]["incremental"][0]["items"].is_array());
+
+        let response = run("query { musicians(first: 1000001) @export { id } }", true).await;
+        let payloads = collect(response).await;
+        assert!(payloads[1]["incremental"][0]["errors"].is_array());
+
+        // Exported entities are sent without resolving their children
+        let response = run("query { musicians @export { id mainBand { id } } }", true).await;
+        match response {
+            IncrementalResponse::Complete(result) => {
+                assert!(result.first().unwrap().has_errors())
+            }
+            IncrementalResponse::Incremental { .. } => panic!("expected an error"),
+        }
+
+        // Exports need to be enabled
+        let response = run("query { musicians @export { id } }", false).await;
+        match response {
+            IncrementalResponse::Complete(result) => {
+                assert!(result.first().unwrap().has_errors())
+            }
+            IncrementalResponse::Incremental { .. } => panic!("expected an error"),
+        }
+    })
+}
+
+#[test]
+fn can_look_up_federation_entities() {
+    run_test_sequentially(|store| async move {
+        const QUERY: &str = "
+        query($representations: [_Any!]!) {
+            _entities(representations: $representations) {
+                __typename
+                ... on Musician { name mainBand { id } }
+                ... on Band { name }
+            }
+            _service { sdl }
+        }";
+        let query = graphql_parser::parse_query(QUERY)
+            .expect("invalid test query")
+            .into_static();
+        let representation = |typename: &str, id: &str| {
+            object! { __typename: typename.to_owned(), id: id.to_owned() }
+        };
+        let representations = r::Value::List(vec![
+            representation("Musician", "m2"),
+            representation("Band", "b1"),
+            representation("Musician", "m99"),
+            representation("Musician", "m1"),
+        ]);
+        let variables = QueryVariables::new(HashMap::from_iter(vec![(
+            String::from("representations"),
+            representations,
+        )]));
+        let deployment = setup(store.as_ref());
+        let result =
+            execute_query_document_with_variables(&deployment.hash, query, Some(variables)).await;
+        let data = extract_data!(result).unwrap();
+
+        let exp = r::Value::List(vec![
+            object! { __typename: "Musician", name: "Lisa", mainBand: object! { id: "b1" } },
+            object! { __typename: "Band", name: "The Musicians" },
+            r::Value::Null,
+            object! { __typename: "Musician", name: "John", mainBand: object! { id: "b1" } },
+        ]);
+        let data = match data {
+            r::Value::Object(data) => data,
+            _ => panic!("expected an object"),
+        };
+        assert_eq!(data.get("_entities"), Some(&exp));
+
+        let sdl = match data.get("_service") {
+            Some(r::Value::Object(service)) => service.get("sdl").cloned(),
+            _ => None,
+        };
+        match sdl {
+            Some(r::Value::String(sdl)) => {
+                assert!(sdl.contains("type Musician @key(fields: \"id\")"));
+                assert!(!sdl.contains("_entities"));
+            }
+            _ => panic!("expected the SDL as a string but got {:?}", sdl),
+        }
+    })
+}
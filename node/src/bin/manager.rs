@@ -1,4 +1,6 @@
-use std::{collections::HashMap, env, num::ParseIntError, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap, env, num::ParseIntError, path::PathBuf, sync::Arc, time::Duration,
+};
 
 use config::PoolSize;
 use git_testament::{git_testament, render_testament};
@@ -10,7 +12,7 @@ use structopt::StructOpt;
 
 use graph::{
     log::logger,
-    prelude::{info, o, slog, tokio, Logger, NodeId, ENV_VARS},
+    prelude::{info, o, slog, tokio, BlockNumber, Logger, NodeId, ENV_VARS},
     url::Url,
 };
 use graph_node::{
@@ -19,8 +21,8 @@ use graph_node::{
     MetricsContext,
 };
 use graph_store_postgres::{
-    connection_pool::ConnectionPool, BlockStore, Shard, Store, SubgraphStore, SubscriptionManager,
-    PRIMARY_SHARD,
+    connection_pool::ConnectionPool, query_store::QueryExplanations, BlockStore, Shard, Store,
+    SubgraphStore, SubscriptionManager, PRIMARY_SHARD,
 };
 
 use graph_node::config::{self, Config as Cfg};
@@ -143,6 +145,138 @@ pub enum Command {
         /// The deployment (see `help info`)
         deployment: DeploymentSearch,
     },
+    /// Set the priority with which a deployment is synced
+    ///
+    /// The priority takes effect the next time the deployment is started
+    /// and overrides the priority from the subgraph manifest. Use `clear`
+    /// to go back to the priority from the manifest
+    Priority {
+        /// The deployment (see `help info`)
+        deployment: DeploymentSearch,
+        /// One of `high`, `normal`, `low`, or `clear`
+        priority: String,
+    },
+    /// Remove the history of a deployment that is older than a number of
+    /// blocks
+    ///
+    /// Entity versions that are not visible at the last `history` blocks
+    /// of the deployment are removed, and queries for earlier blocks fail
+    /// from now on. Progress and an estimate of the reclaimed space are
+    /// reported for each table. Pruning is done in small batches, and if it
+    /// is interrupted, running the command again continues where it left
+    /// off
+    Prune {
+        /// The deployment (see `help info`)
+        deployment: DeploymentSearch,
+        /// How many blocks of history to keep
+        #[structopt(long)]
+        history: i32,
+    },
+    /// Write a backup of a deployment into a directory
+    ///
+    /// The backup contains the deployment's schema, all its entity
+    /// versions, its head block and its dynamic data sources. It is taken
+    /// in one transaction, so that the deployment can keep indexing while
+    /// the backup is written. Use `restore` to load it into a database
+    Backup {
+        /// The deployment (see `help info`)
+        deployment: DeploymentSearch,
+        /// The directory for the backup; it must be empty or not exist
+        dir: PathBuf,
+    },
+    /// Restore a deployment from a backup made with `backup`
+    ///
+    /// The deployment is created in `shard` and assigned to `node`, and
+    /// continues indexing from the block at which the backup was taken.
+    /// The deployment must not exist yet
+    Restore {
+        /// Make the deployment the current version of this subgraph
+        #[structopt(long)]
+        name: Option<String>,
+        /// The directory that contains the backup
+        dir: PathBuf,
+        /// The name of the database shard into which to restore
+        shard: String,
+        /// The name of the node that should index the deployment
+        node: String,
+    },
+    /// Export the entities of a deployment as of a block
+    ///
+    /// Writes one file `<EntityType>.jsonl` per entity type into the local
+    /// directory `dir` with the entities that were visible at `block`, one
+    /// entity per line. The snapshot is read in one transaction, so that
+    /// the deployment can keep indexing while it is written. Only JSON
+    /// Lines on the local filesystem are supported; exporting to Parquet
+    /// or directly to S3 or GCS is not
+    Export {
+        /// The block at which to take the snapshot; defaults to the
+        /// current head of the deployment
+        #[structopt(long, short)]
+        block: Option<BlockNumber>,
+        /// The deployment (see `help info`)
+        deployment: DeploymentSearch,
+        /// The local directory for the snapshot, as a path or a `file:`
+        /// URL; it must be empty or not exist
+        dir: String,
+    },
+    /// Export the rows of one entity table as CSV or JSONL
+    ///
+    /// Writes every version of the entities of type `entity` whose block
+    /// range overlaps the blocks from `--from` to `--to`, including their
+    /// `vid` and block range. Rows are streamed from the database, so that
+    /// tables of any size can be exported
+    ExportTable {
+        /// The output format, `csv` or `jsonl`
+        #[structopt(long, short, default_value = "csv")]
+        format: String,
+        /// Only export versions that are visible at or after this block
+        #[structopt(long)]
+        from: Option<BlockNumber>,
+        /// Only export versions that are visible at or before this block
+        #[structopt(long)]
+        to: Option<BlockNumber>,
+        /// The deployment (see `help info`)
+        deployment: DeploymentSearch,
+        /// The entity type whose table to export
+        entity: String,
+        /// The file to write to; defaults to stdout
+        output: Option<PathBuf>,
+    },
+    /// Compute checksums over the entities of a deployment at a block
+    ///
+    /// Prints a checksum for each entity type that only depends on the
+    /// entities visible at the block. Write them to a file with `--output`
+    /// and compare them with checksums from another installation with
+    /// `--compare` to verify that a copied or restored deployment has
+    /// exactly the same data as the original
+    Checksum {
+        /// The block at which to compute checksums; defaults to the block
+        /// of the checksums given with `--compare`, or the current head of
+        /// the deployment
+        #[structopt(long, short)]
+        block: Option<BlockNumber>,
+        /// Write the checksums to this file
+        #[structopt(long, short)]
+        output: Option<PathBuf>,
+        /// Compare with the checksums in this file and fail if they differ
+        #[structopt(long, short)]
+        compare: Option<PathBuf>,
+        /// The deployment (see `help info`)
+        deployment: DeploymentSearch,
+    },
+    /// Show how deployments would be moved between index nodes to spread
+    /// the load that index nodes report evenly
+    ///
+    /// Only index nodes that set `GRAPH_LOAD_REPORT_INTERVAL` report their
+    /// load. Without `--apply`, this only prints the planned moves
+    Rebalance {
+        /// Reassign the deployments as planned
+        #[structopt(long)]
+        apply: bool,
+        /// The maximum number of deployments to move
+        #[structopt(long, short, default_value = "5")]
+        max_moves: usize,
+    },
     /// Rewind a subgraph to a specific block
     Rewind {
         /// Force rewinding even if the block hash is not found in the local
@@ -158,12 +292,48 @@ pub enum Command {
         )]
         sleep: Duration,
         /// The block hash of the target block
-        block_hash: String,
+        #[structopt(long)]
+        block_hash: Option<String>,
         /// The block number of the target block
-        block_number: i32,
+        #[structopt(long)]
+        block_number: Option<i32>,
+        /// Rewind each deployment to the latest block at or before
+        /// `--block-number` for which it has a proof of indexing
+        #[structopt(long)]
+        to_nearest_poi: bool,
+        /// Allow rewinding grafted deployments to a block before their
+        /// graft point, copying data from the graft base again if needed
+        #[structopt(long)]
+        past_graft: bool,
         /// The deployments to rewind (see `help info`)
         deployments: Vec<DeploymentSearch>,
     },
+    /// Clear the fatal error of failed deployments
+    ///
+    /// Deployments are paused while their error is cleared. Without
+    /// `--rewind-to-divergence`, the head of each deployment must be
+    /// before the block at which it failed, which is usually the case for
+    /// non-deterministic errors
+    Unfail {
+        /// Rewind each deployment to the block before the one at which it
+        /// failed, and clear the error in the same step
+        #[structopt(long)]
+        rewind_to_divergence: bool,
+        /// Only unfail deployments whose error is not deterministic and
+        /// skip all others. Without deployments, consider all deployments
+        #[structopt(long)]
+        auto: bool,
+        /// Sleep for this many seconds after pausing subgraphs
+        #[structopt(
+            long,
+            short,
+            default_value = "10",
+            parse(try_from_str = parse_duration_in_secs)
+        )]
+        sleep: Duration,
+        /// The deployments to unfail (see `help info`)
+        deployments: Vec<DeploymentSearch>,
+    },
     /// Deploy and run an arbitrary subgraph up to a certain block, although it can surpass it by a few blocks, it's not exact (use for dev and testing purposes) -- WARNING: WILL RUN MIGRATIONS ON THE DB, DO NOT USE IN PRODUCTION
     ///
     /// Also worth noting that the deployed subgraph will be removed at the end.
@@ -179,6 +349,55 @@ pub enum Command {
 
         /// Prometheus push gateway endpoint.
         prometheus_host: Option<String>,
+
+        /// Read the subgraph from the files in this directory instead of
+        /// IPFS. The directory must contain a `subgraph.yaml`, and
+        /// `subgraph` is then only the name of the subgraph
+        #[structopt(long, parse(from_os_str))]
+        from_dir: Option<PathBuf>,
+    },
+    /// Run the unit tests for the mappings of a subgraph
+    ///
+    /// The subgraph is read from a directory with the output of `graph
+    /// build`. The tests for data source `<name>` are the exported
+    /// functions whose name starts with `test` in `tests/<name>.wasm`.
+    /// The entities, contract call results and IPFS files that the tests
+    /// can see are set up in `tests/<name>.json`
+    Test {
+        /// The directory with the built subgraph
+        #[structopt(parse(from_os_str))]
+        dir: PathBuf,
+        /// Only run tests whose name contains this string
+        filter: Option<String>,
+    },
+    /// Run the mappings of a deployment for one block without writing
+    /// anything
+    ///
+    /// Print the entity changes that the block would make, the handlers
+    /// and host functions like `ethereum.call` that ran together with the
+    /// gas they used, and the errors that happened. The mappings see the
+    /// entities as of the current block of the deployment. Logs from the
+    /// mappings are printed as they happen; set `GRAPH_LOG=trace` to also
+    /// see the arguments of each `ethereum.call`
+    RunBlock {
+        /// The deployment (see `help info`)
+        deployment: DeploymentSearch,
+        /// The block to run
+        block: BlockNumber,
+    },
+    /// List the handlers that would run for the blocks in a range
+    ///
+    /// Uses the filters of the deployment to find the events, calls and
+    /// blocks that it is interested in, and prints the handlers that each
+    /// of them matches without running any mappings. Blocks without
+    /// triggers are not listed
+    Triggers {
+        /// The deployment (see `help info`)
+        deployment: DeploymentSearch,
+        /// The first block of the range
+        from: BlockNumber,
+        /// The last block of the range; defaults to `from`
+        to: Option<BlockNumber>,
     },
     /// Check and interrogate the configuration
     ///
@@ -189,6 +408,10 @@ pub enum Command {
     Listen(ListenCommand),
     /// Manage deployment copies and grafts
     Copy(CopyCommand),
+    /// Compare proofs of indexing between index nodes
+    Poi(PoiCommand),
+    /// Skip blocks, or handlers in blocks, that a deployment can not process
+    Skip(SkipCommand),
     /// Run a GraphQL query
     Query {
         /// The subgraph to query
@@ -199,6 +422,10 @@ pub enum Command {
         query: String,
         /// The variables in the form `key=value`
         vars: Vec<String>,
+        /// Print the SQL of every query that is sent to the database
+        /// together with the output of `explain (analyze, buffers)` for it
+        #[structopt(long)]
+        explain: bool,
     },
     /// Get information about chains and manipulate them
     Chain(ChainCommand),
@@ -314,6 +541,27 @@ pub enum CopyCommand {
         /// The name of the database shard that holds the copy
         shard: String,
     },
+    /// Switch over from a deployment to its copy in another shard
+    ///
+    /// Once the copy has finished copying data and has caught up with the
+    /// deployment it was copied from, make the copy the active deployment
+    /// and stop indexing the original in one transaction. The original
+    /// keeps indexing until then, so that there is no downtime. The
+    /// original becomes unused and can be removed right away with
+    /// `--remove-source`, or later with `graphman unused`
+    Switch {
+        /// Move the copy to this node instead of leaving it on the node
+        /// that it was copied on
+        #[structopt(long)]
+        node: Option<String>,
+        /// Remove the original deployment after switching over
+        #[structopt(long)]
+        remove_source: bool,
+        /// The IPFS hash of the deployment
+        deployment: String,
+        /// The name of the database shard that holds the copy
+        shard: String,
+    },
     /// List all currently running copy and graft operations
     List,
     /// Print the progress of a copy operation
@@ -323,6 +571,72 @@ pub enum CopyCommand {
     },
 }
 
+#[derive(Clone, Debug, StructOpt)]
+pub enum PoiCommand {
+    /// Find the first block at which the proofs of indexing of a deployment
+    /// differ between two index nodes
+    ///
+    /// Queries the index-node APIs at `first` and `second`, e.g.,
+    /// `http://localhost:8030/graphql`, and bisects the block range using
+    /// their `proofsOfIndexing` call. Since proofs of indexing are
+    /// cumulative, they differ at every block after the first block at
+    /// which they differ
+    Diff {
+        /// The first block to compare
+        #[structopt(long, default_value = "0")]
+        from: BlockNumber,
+        /// The last block to compare
+        #[structopt(long)]
+        to: BlockNumber,
+        /// The indexer address for which to compute proofs of indexing
+        #[structopt(long)]
+        indexer: Option<String>,
+        /// The IPFS hash of the deployment
+        deployment: String,
+        /// The URL of the index-node API of the first index node
+        first: String,
+        /// The URL of the index-node API of the second index node
+        second: String,
+    },
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub enum SkipCommand {
+    /// Do not process a block, or only one handler in it, for a deployment
+    ///
+    /// Use this as a last resort when a single block makes a deployment
+    /// fail and that can not be fixed otherwise. The skip is recorded with
+    /// the reason and shows up in the indexing status and in `_meta`. It
+    /// takes effect when the deployment is restarted
+    Add {
+        /// Only skip this handler instead of the whole block
+        #[structopt(long)]
+        handler: Option<String>,
+        /// Why the block is skipped
+        #[structopt(long)]
+        reason: String,
+        /// The deployment (see `help info`)
+        deployment: DeploymentSearch,
+        /// The number of the block to skip
+        block: BlockNumber,
+    },
+    /// Process a skipped block, or a skipped handler in it, again
+    Remove {
+        /// Only stop skipping this handler
+        #[structopt(long)]
+        handler: Option<String>,
+        /// The deployment (see `help info`)
+        deployment: DeploymentSearch,
+        /// The number of the skipped block
+        block: BlockNumber,
+    },
+    /// List the blocks that a deployment skips
+    List {
+        /// The deployment (see `help info`)
+        deployment: DeploymentSearch,
+    },
+}
+
 #[derive(Clone, Debug, StructOpt)]
 pub enum ChainCommand {
     /// List all chains that are in the database
@@ -346,6 +660,28 @@ pub enum ChainCommand {
     /// There must be no deployments using that chain. If there are, the
     /// subgraphs and/or deployments using the chain must first be removed
     Remove { name: String },
+    /// Manage the cache of `eth_call` results of a chain
+    CallCache {
+        /// The name of the chain
+        chain_name: String,
+        #[structopt(subcommand)]
+        method: CallCacheCommand,
+    },
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub enum CallCacheCommand {
+    /// Remove the calls that were made at blocks before a given block
+    ///
+    /// Calls are removed in batches, and it is safe to run this while the
+    /// chain is in use; handlers simply make calls again that are no
+    /// longer cached. To remove old calls periodically, set
+    /// `GRAPH_CALL_CACHE_RETENTION_BLOCKS` or `GRAPH_CALL_CACHE_MAX_CALLS`
+    Prune {
+        /// Remove calls made at blocks before this block number
+        #[structopt(long)]
+        before: BlockNumber,
+    },
 }
 
 #[derive(Clone, Debug, StructOpt)]
@@ -443,6 +779,50 @@ pub enum IndexCommand {
         #[structopt(empty_values = false)]
         index_name: String,
     },
+
+    /// Show how far building the attribute indexes that a deployment
+    /// deferred has progressed
+    ///
+    /// Deployments that were created with `GRAPH_STORE_DEFER_INDEXES` only
+    /// get their attribute indexes once they have caught up with the chain
+    /// head. This lists the indexes that are still missing and the ones
+    /// that are being built right now
+    Deferred {
+        /// The deployment (see `help info`).
+        #[structopt(empty_values = false)]
+        deployment: DeploymentSearch,
+    },
+
+    /// Suggest indexes for attributes that queries use a lot
+    ///
+    /// Every node counts how often queries filter or sort by each attribute
+    /// of a deployment. This lists the attributes that were used at least
+    /// `--min-uses` times but that are not the first column of any index.
+    /// The counts are written to the database about once a minute.
+    Suggest {
+        /// The deployment (see `help info`).
+        #[structopt(empty_values = false)]
+        deployment: DeploymentSearch,
+        /// Only suggest attributes that were used at least this often
+        #[structopt(long, short, default_value = "100")]
+        min_uses: i64,
+        /// Create the suggested indexes concurrently
+        #[structopt(long)]
+        create: bool,
+    },
+
+    /// Build the attribute indexes that a deployment deferred right away
+    ///
+    /// The indexes are built concurrently, without waiting for the
+    /// deployment to catch up with the chain head. Use `index deferred` to
+    /// watch the progress from another shell.
+    ///
+    /// This command may be time-consuming.
+    BuildDeferred {
+        /// The deployment (see `help info`).
+        #[structopt(empty_values = false)]
+        deployment: DeploymentSearch,
+    },
 }
 
 impl From<Opt> for config::Opt {
@@ -607,11 +987,17 @@ impl Context {
         (store.block_store(), primary.clone())
     }
 
-    fn graphql_runner(self) -> Arc<GraphQlRunner<Store, PanicSubscriptionManager>> {
+    fn graphql_runner(
+        self,
+        explanations: Option<Arc<QueryExplanations>>,
+    ) -> Arc<GraphQlRunner<Store, PanicSubscriptionManager>> {
         let logger = self.logger.clone();
         let registry = self.registry.clone();
 
-        let store = self.store();
+        let store = match explanations {
+            Some(explanations) => Arc::new(self.store().with_explanations(explanations)),
+            None => self.store(),
+        };
 
         let subscription_manager = Arc::new(PanicSubscriptionManager);
         let load_manager = Arc::new(LoadManager::new(&logger, vec![], registry.clone()));
@@ -626,10 +1012,24 @@ impl Context {
     }
 }
 
-#[tokio::main]
-async fn main() {
+fn main() {
     let opt = Opt::from_args();
 
+    // Export settings from the configuration file before anything reads
+    // `ENV_VARS`, and before the runtime starts any threads
+    if let Err(e) = Cfg::export_env_vars(&opt.config) {
+        eprintln!("configuration error: {}", e);
+        std::process::exit(1);
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("failed to start the tokio runtime")
+        .block_on(run(opt))
+}
+
+async fn run(opt: Opt) {
     let version_label = opt.version_label.clone();
     // Set up logger
     let logger = match ENV_VARS.log_levels {
@@ -755,11 +1155,81 @@ async fn main() {
         Reassign { deployment, node } => {
             commands::assign::reassign(ctx.primary_pool(), &deployment, node)
         }
+        Priority {
+            deployment,
+            priority,
+        } => commands::assign::priority(ctx.primary_pool(), &deployment, priority),
+        Prune {
+            deployment,
+            history,
+        } => {
+            let (store, primary_pool) = ctx.store_and_primary();
+            commands::prune::run(store.subgraph_store(), primary_pool, deployment, history).await
+        }
+        Backup { deployment, dir } => {
+            let (store, primary_pool) = ctx.store_and_primary();
+            commands::backup::backup(store.subgraph_store(), primary_pool, deployment, dir)
+        }
+        Restore {
+            name,
+            dir,
+            shard,
+            node,
+        } => commands::backup::restore(ctx.subgraph_store(), dir, shard, node, name),
+        Export {
+            block,
+            deployment,
+            dir,
+        } => {
+            let (store, primary_pool) = ctx.store_and_primary();
+            commands::backup::export(store.subgraph_store(), primary_pool, deployment, block, dir)
+        }
+        ExportTable {
+            format,
+            from,
+            to,
+            deployment,
+            entity,
+            output,
+        } => {
+            let (store, primary_pool) = ctx.store_and_primary();
+            commands::backup::export_table(
+                store.subgraph_store(),
+                primary_pool,
+                deployment,
+                entity,
+                from,
+                to,
+                format,
+                output,
+            )
+        }
+        Checksum {
+            block,
+            output,
+            compare,
+            deployment,
+        } => {
+            let (store, primary_pool) = ctx.store_and_primary();
+            commands::checksum::run(
+                store.subgraph_store(),
+                primary_pool,
+                deployment,
+                block,
+                output,
+                compare,
+            )
+        }
+        Rebalance { apply, max_moves } => {
+            commands::assign::rebalance(ctx.subgraph_store(), max_moves, apply)
+        }
         Rewind {
             force,
             sleep,
             block_hash,
             block_number,
+            to_nearest_poi,
+            past_graft,
             deployments,
         } => {
             let (store, primary) = ctx.store_and_primary();
@@ -769,15 +1239,34 @@ async fn main() {
                 deployments,
                 block_hash,
                 block_number,
+                to_nearest_poi,
+                past_graft,
                 force,
                 sleep,
             )
         }
+        Unfail {
+            rewind_to_divergence,
+            auto,
+            sleep,
+            deployments,
+        } => {
+            let (store, primary) = ctx.store_and_primary();
+            commands::unfail::run(
+                primary,
+                store,
+                deployments,
+                rewind_to_divergence,
+                auto,
+                sleep,
+            )
+        }
         Run {
             network_name,
             subgraph,
             stop_block,
             prometheus_host,
+            from_dir,
         } => {
             let logger = ctx.logger.clone();
             let config = ctx.config();
@@ -803,6 +1292,51 @@ async fn main() {
                 node_id,
                 subgraph,
                 stop_block,
+                from_dir,
+            )
+            .await
+        }
+        Test { dir, filter } => commands::test::run(ctx.logger.clone(), dir, filter).await,
+        Triggers {
+            deployment,
+            from,
+            to,
+        } => {
+            let logger = ctx.logger.clone();
+            let config = ctx.config();
+            let registry = ctx.metrics_registry();
+            let node_id = ctx.node_id();
+            let ipfs_url = ctx.ipfs_url.clone();
+            let store_builder = ctx.store_builder().await;
+            commands::triggers::run(
+                logger,
+                store_builder,
+                config,
+                registry,
+                node_id,
+                ipfs_url,
+                deployment,
+                from,
+                to,
+            )
+            .await
+        }
+        RunBlock { deployment, block } => {
+            let logger = ctx.logger.clone();
+            let config = ctx.config();
+            let registry = ctx.metrics_registry();
+            let node_id = ctx.node_id();
+            let ipfs_url = ctx.ipfs_url.clone();
+            let store_builder = ctx.store_builder().await;
+            commands::run_block::run(
+                logger,
+                store_builder,
+                config,
+                registry,
+                node_id,
+                ipfs_url,
+                deployment,
+                block,
             )
             .await
         }
@@ -834,15 +1368,64 @@ async fn main() {
                 Activate { deployment, shard } => {
                     commands::copy::activate(ctx.subgraph_store(), deployment, shard)
                 }
+                Switch {
+                    node,
+                    remove_source,
+                    deployment,
+                    shard,
+                } => commands::copy::switch(
+                    ctx.subgraph_store(),
+                    deployment,
+                    shard,
+                    node,
+                    remove_source,
+                ),
                 List => commands::copy::list(ctx.pools()),
                 Status { dst } => commands::copy::status(ctx.pools(), &dst),
             }
         }
+        Poi(cmd) => {
+            use PoiCommand::*;
+            match cmd {
+                Diff {
+                    from,
+                    to,
+                    indexer,
+                    deployment,
+                    first,
+                    second,
+                } => commands::poi::diff(deployment, first, second, from, to, indexer).await,
+            }
+        }
+        Skip(cmd) => {
+            use SkipCommand::*;
+            let (store, primary) = ctx.store_and_primary();
+            let store = store.subgraph_store();
+            match cmd {
+                Add {
+                    handler,
+                    reason,
+                    deployment,
+                    block,
+                } => commands::skip::add(store, primary, deployment, block, handler, reason),
+                Remove {
+                    handler,
+                    deployment,
+                    block,
+                } => commands::skip::remove(store, primary, deployment, block, handler),
+                List { deployment } => commands::skip::list(store, primary, deployment),
+            }
+        }
         Query {
             target,
             query,
             vars,
-        } => commands::query::run(ctx.graphql_runner(), target, query, vars).await,
+            explain,
+        } => {
+            let explanations = explain.then(|| Arc::new(QueryExplanations::default()));
+            let runner = ctx.graphql_runner(explanations.clone());
+            commands::query::run(runner, target, query, vars, explanations).await
+        }
         Chain(cmd) => {
             use ChainCommand::*;
             match cmd {
@@ -862,6 +1445,12 @@ async fn main() {
                     let (block_store, primary) = ctx.block_store_and_primary_pool();
                     commands::chain::remove(primary, block_store, name)
                 }
+                CallCache { chain_name, method } => match method {
+                    CallCacheCommand::Prune { before } => {
+                        let (block_store, _) = ctx.block_store_and_primary_pool();
+                        commands::chain::prune_call_cache(block_store, chain_name, before)
+                    }
+                },
             }
         }
         Stats(cmd) => {
@@ -914,6 +1503,26 @@ async fn main() {
                     commands::index::drop(subgraph_store, primary_pool, deployment, &index_name)
                         .await
                 }
+                Suggest {
+                    deployment,
+                    min_uses,
+                    create,
+                } => {
+                    commands::index::suggest(
+                        subgraph_store,
+                        primary_pool,
+                        deployment,
+                        min_uses,
+                        create,
+                    )
+                    .await
+                }
+                Deferred { deployment } => {
+                    commands::index::deferred(subgraph_store, primary_pool, deployment).await
+                }
+                BuildDeferred { deployment } => {
+                    commands::index::build_deferred(subgraph_store, primary_pool, deployment).await
+                }
             }
         }
     };
@@ -1,5 +1,5 @@
 use crate::config::{Config, ProviderDetails};
-use ethereum::{EthereumNetworks, ProviderEthRpcMetrics};
+use ethereum::{EthereumNetworks, ProviderEthRpcMetrics, RequestBudget};
 use futures::future::join_all;
 use futures::TryFutureExt;
 use graph::anyhow::Error;
@@ -9,7 +9,7 @@ use graph::firehose::{FirehoseEndpoint, FirehoseNetworks};
 use graph::ipfs_client::IpfsClient;
 use graph::prelude::{anyhow, tokio};
 use graph::prelude::{prost, MetricsRegistry as MetricsRegistryTrait};
-use graph::slog::{debug, error, info, o, Logger};
+use graph::slog::{debug, error, info, o, warn, Logger};
 use graph::url::Url;
 use graph::util::security::SafeDisplay;
 use graph_chain_ethereum::{self as ethereum, EthereumAdapterTrait, Transport};
@@ -34,7 +34,7 @@ enum ProviderNetworkStatus {
 /// How long we will hold up node startup to get the net version and genesis
 /// hash from the client. If we can't get it within that time, we'll try and
 /// continue regardless.
-const NET_VERSION_WAIT_TIME: Duration = Duration::from_secs(30);
+pub(crate) const NET_VERSION_WAIT_TIME: Duration = Duration::from_secs(30);
 
 pub fn create_ipfs_clients(logger: &Logger, ipfs_addresses: &Vec<String>) -> Vec<IpfsClient> {
     // Parse the IPFS URL from the `--ipfs` command line argument
@@ -135,6 +135,14 @@ pub async fn create_ethereum_networks(
                     Ipc => Transport::new_ipc(&web3.url).await,
                     Ws => Transport::new_ws(&web3.url).await,
                 };
+                let transport = match web3.request_limits() {
+                    Some(limits) => transport.with_budget(Arc::new(RequestBudget::new(
+                        provider.label.clone(),
+                        limits,
+                        eth_rpc_metrics.clone(),
+                    ))),
+                    None => transport,
+                };
 
                 let supports_eip_1898 = !web3.features.contains("no_eip1898");
 
@@ -190,6 +198,7 @@ pub async fn create_firehose_networks(
                     &firehose.url,
                     firehose.token.clone(),
                     firehose.filters_enabled(),
+                    firehose.grpc_settings(),
                 )
                 .await?;
 
@@ -214,7 +223,7 @@ pub async fn create_firehose_networks(
 /// version for it.
 pub async fn connect_ethereum_networks(
     logger: &Logger,
-    mut eth_networks: EthereumNetworks,
+    eth_networks: EthereumNetworks,
 ) -> (EthereumNetworks, Vec<(String, Vec<ChainIdentifier>)>) {
     // This has one entry for each provider, and therefore multiple entries
     // for each network
@@ -280,9 +289,61 @@ pub async fn connect_ethereum_networks(
                 networks
             });
     let idents: Vec<_> = idents.into_iter().collect();
+
+    if ethereum::ENV_VARS.probe_providers {
+        probe_ethereum_networks(logger, &eth_networks).await;
+    }
+
     (eth_networks, idents)
 }
 
+/// Probe all providers in `eth_networks` for their capabilities and use
+/// what probing found out instead of the `features` in the configuration.
+/// Probing a provider stops after `NET_VERSION_WAIT_TIME`, and providers
+/// whose probes time out keep their configured capabilities
+async fn probe_ethereum_networks(logger: &Logger, eth_networks: &EthereumNetworks) {
+    join_all(
+        eth_networks
+            .flatten()
+            .into_iter()
+            .map(|(network, capabilities, eth_adapter)| async move {
+                let logger = logger.new(o!("provider" => eth_adapter.provider().to_string()));
+                let probed = match tokio::time::timeout(
+                    NET_VERSION_WAIT_TIME,
+                    eth_adapter.probe_capabilities(&logger),
+                )
+                .await
+                {
+                    Ok(probed) => probed,
+                    Err(_) => {
+                        warn!(logger, "Probing provider timed out, using configured capabilities";
+                                      "capabilities" => &capabilities);
+                        return;
+                    }
+                };
+
+                let detected = probed.apply(capabilities);
+                info!(
+                    logger,
+                    "Probed provider";
+                    "capabilities" => &detected,
+                    "state_depth" => probed.state_depth,
+                    "max_logs_range" => probed.max_logs_range,
+                );
+                if detected != capabilities {
+                    warn!(
+                        logger,
+                        "Provider capabilities differ from its configured features, using the probed ones";
+                        "configured" => &capabilities,
+                        "probed" => &detected,
+                    );
+                    eth_networks.set_capabilities(&network, eth_adapter.provider(), detected);
+                }
+            }),
+    )
+    .await;
+}
+
 /// Try to connect to all the providers in `firehose_networks` and get their net
 /// version and genesis block. Return the same `eth_networks` and the
 /// retrieved net identifiers grouped by network name. Remove all providers
@@ -293,7 +354,7 @@ pub async fn connect_ethereum_networks(
 /// version for it.
 pub async fn connect_firehose_networks<M>(
     logger: &Logger,
-    mut firehose_networks: FirehoseNetworks,
+    firehose_networks: FirehoseNetworks,
 ) -> (FirehoseNetworks, Vec<(String, Vec<ChainIdentifier>)>)
 where
     M: prost::Message + BlockchainBlock + Default + 'static,
@@ -458,7 +519,7 @@ mod test {
             .networks
             .get("goerli")
             .unwrap()
-            .adapters
+            .adapters()
             .iter()
             .next()
             .unwrap()
@@ -467,7 +528,7 @@ mod test {
             .networks
             .get("mainnet")
             .unwrap()
-            .adapters
+            .adapters()
             .iter()
             .next()
             .unwrap()
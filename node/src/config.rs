@@ -1,6 +1,10 @@
 use graph::{
     anyhow::Error,
     blockchain::BlockchainKind,
+    data::query::QueryLimits,
+    data::subgraph::retry::{RetryErrors, RetryPolicy},
+    env::QueryCacheEviction,
+    firehose::GrpcSettings,
     prelude::{
         anyhow::{anyhow, bail, Context, Result},
         info,
@@ -8,8 +12,9 @@ use graph::{
             de::{self, value, SeqAccess, Visitor},
             Deserialize, Deserializer, Serialize,
         },
-        serde_json, Logger, NodeId, StoreError,
+        serde_json, DeploymentHash, Logger, NodeId, NodeRole, StoreError,
     },
+    semver::Version,
 };
 use graph_chain_ethereum::{self as ethereum, NodeCapabilities};
 use graph_store_postgres::{DeploymentPlacer, Shard as ShardName, PRIMARY_SHARD};
@@ -18,8 +23,9 @@ use http::{HeaderMap, Uri};
 use regex::Regex;
 use std::fs::read_to_string;
 use std::{
-    collections::{BTreeMap, BTreeSet},
+    collections::{BTreeMap, BTreeSet, HashMap},
     fmt,
+    time::Duration,
 };
 use url::Url;
 
@@ -69,6 +75,10 @@ pub struct Config {
     pub stores: BTreeMap<String, Shard>,
     pub chains: ChainSection,
     pub deployment: Deployment,
+    #[serde(default)]
+    pub mappings: MappingsSection,
+    #[serde(default)]
+    pub query: QuerySection,
 }
 
 fn validate_name(s: &str) -> Result<()> {
@@ -110,7 +120,12 @@ impl Config {
         for (key, shard) in self.stores.iter_mut() {
             shard.validate(&key)?;
         }
+        if let Some(general) = &self.general {
+            general.validate()?;
+        }
         self.deployment.validate()?;
+        self.mappings.validate()?;
+        self.query.validate()?;
 
         // Check that deployment rules only reference existing stores and chains
         for (i, rule) in self.deployment.rules.iter().enumerate() {
@@ -172,9 +187,38 @@ impl Config {
             stores,
             chains,
             deployment,
+            mappings: MappingsSection::default(),
+            query: QuerySection::default(),
         })
     }
 
+    /// Read the configuration file at `path` and export the settings from
+    /// its `[mappings]` and `[query]` sections as environment variables.
+    /// Variables that are already set in the environment are left alone so
+    /// that the environment takes precedence over the configuration file.
+    ///
+    /// Since `ENV_VARS` is only read once, this must be called before
+    /// anything accesses `ENV_VARS`. Changing the environment is only safe
+    /// while the process has a single thread, so it also has to be called
+    /// before the tokio runtime is started
+    pub fn export_env_vars(path: &str) -> Result<()> {
+        let config = read_to_string(path)?;
+        let config: EnvSections = toml::from_str(&config)?;
+        config.mappings.validate()?;
+        config.query.validate()?;
+        for (name, value) in config
+            .mappings
+            .env_vars()
+            .into_iter()
+            .chain(config.query.env_vars())
+        {
+            if std::env::var_os(name).is_none() {
+                std::env::set_var(name, value);
+            }
+        }
+        Ok(())
+    }
+
     /// Genrate a JSON representation of the config.
     pub fn to_json(&self) -> Result<String> {
         // It would be nice to produce a TOML representation, but that runs
@@ -191,14 +235,21 @@ impl Config {
             .expect("a validated config has a primary store")
     }
 
-    pub fn query_only(&self, node: &NodeId) -> bool {
-        self.general
-            .as_ref()
-            .map(|g| match g.query.find(node.as_str()) {
-                None => false,
-                Some(m) => m.as_str() == node.as_str(),
-            })
-            .unwrap_or(false)
+    /// The role of `node`. A role listed for `node` in `general.roles`
+    /// takes precedence over `general.query`; nodes that are not mentioned
+    /// anywhere are combined nodes
+    pub fn node_role(&self, node: &NodeId) -> NodeRole {
+        let general = match &self.general {
+            Some(general) => general,
+            None => return NodeRole::Combined,
+        };
+        if let Some(role) = general.roles.get(node.as_str()) {
+            return *role;
+        }
+        match general.query.find(node.as_str()) {
+            Some(m) if m.as_str() == node.as_str() => NodeRole::Query,
+            _ => NodeRole::Combined,
+        }
     }
 }
 
@@ -206,6 +257,605 @@ impl Config {
 pub struct GeneralSection {
     #[serde(with = "serde_regex", default = "no_name")]
     query: Regex,
+    /// Explicit roles for individual nodes, keyed by node id
+    #[serde(default)]
+    roles: BTreeMap<String, NodeRole>,
+}
+
+impl GeneralSection {
+    fn validate(&self) -> Result<()> {
+        for node in self.roles.keys() {
+            NodeId::new(node)
+                .map_err(|()| anyhow!("invalid node id `{}` in general.roles", node))?;
+        }
+        Ok(())
+    }
+}
+
+/// The parts of the configuration file that mirror environment variables.
+/// They are read separately from the rest of the configuration since they
+/// need to be applied before anything else happens
+#[derive(Deserialize)]
+struct EnvSections {
+    #[serde(default)]
+    mappings: MappingsSection,
+    #[serde(default)]
+    query: QuerySection,
+}
+
+/// Turn the `Some` values in `vars` into a list of environment variables
+/// and their values
+fn set_env_vars(vars: Vec<(&'static str, Option<String>)>) -> Vec<(&'static str, String)> {
+    vars.into_iter()
+        .filter_map(|(name, value)| value.map(|value| (name, value)))
+        .collect()
+}
+
+/// Settings that affect how mappings are run. Each setting mirrors an
+/// environment variable; if that variable is set, it takes precedence over
+/// the value from the configuration file
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MappingsSection {
+    /// Per-deployment overrides for `GRAPH_MAPPING_HANDLER_TIMEOUT`,
+    /// mapping the deployment hash to the timeout in seconds
+    #[serde(default, rename = "handler_timeout")]
+    handler_timeouts: BTreeMap<String, u64>,
+    /// Per-deployment overrides for the `GRAPH_SUBGRAPH_ERROR_RETRY_*`
+    /// settings, keyed by the deployment hash
+    #[serde(default, rename = "retry")]
+    retry_policies: BTreeMap<String, RetryOverride>,
+    /// `GRAPH_SUBGRAPH_ERROR_RETRY_BASE_SECS`
+    error_retry_base: Option<u64>,
+    /// `GRAPH_SUBGRAPH_ERROR_RETRY_CEIL_SECS`
+    error_retry_ceiling: Option<u64>,
+    /// `GRAPH_SUBGRAPH_ERROR_RETRY_MAX_ATTEMPTS`
+    error_retry_max_attempts: Option<u64>,
+    /// `GRAPH_SUBGRAPH_ERROR_RETRY_ERRORS`
+    error_retry_errors: Option<String>,
+    /// `GRAPH_ENTITY_CACHE_SIZE`, in kilobytes
+    entity_cache_size: Option<usize>,
+    /// `GRAPH_RUNTIME_MAX_STACK_SIZE`, in bytes
+    max_stack_size: Option<usize>,
+    /// `GRAPH_MAPPING_HANDLER_TIMEOUT`, in seconds
+    default_handler_timeout: Option<u64>,
+    /// `GRAPH_MAX_GAS_PER_HANDLER`
+    max_gas_per_handler: Option<u64>,
+    /// `GRAPH_MAX_API_VERSION`
+    max_api_version: Option<String>,
+    /// `GRAPH_MAX_SPEC_VERSION`
+    max_spec_version: Option<String>,
+    /// `GRAPH_SUBGRAPH_MAX_DATA_SOURCES`
+    max_data_sources: Option<usize>,
+    /// `GRAPH_DISABLE_GRAFTS`
+    disable_grafts: Option<bool>,
+    /// `GRAPH_DISABLE_FAIL_FAST`
+    disable_fail_fast: Option<bool>,
+    /// `GRAPH_LOG_TRIGGER_DATA`
+    log_trigger_data: Option<bool>,
+    /// `GRAPH_IPFS_TIMEOUT`, in seconds
+    ipfs_timeout: Option<u64>,
+    /// `GRAPH_MAX_IPFS_CACHE_FILE_SIZE`, in bytes
+    max_ipfs_cache_file_size: Option<usize>,
+    /// `GRAPH_MAX_IPFS_CACHE_SIZE`
+    max_ipfs_cache_size: Option<u64>,
+    /// `GRAPH_MAX_IPFS_MAP_FILE_SIZE`, in bytes
+    max_ipfs_map_file_size: Option<usize>,
+    /// `GRAPH_MAX_IPFS_FILE_BYTES`
+    max_ipfs_file_bytes: Option<usize>,
+    /// `GRAPH_ALLOW_NON_DETERMINISTIC_IPFS`
+    allow_non_deterministic_ipfs: Option<bool>,
+}
+
+impl MappingsSection {
+    fn validate(&self) -> Result<()> {
+        for (hash, timeout) in &self.handler_timeouts {
+            DeploymentHash::new(hash.as_str()).map_err(|hash| {
+                anyhow!(
+                    "invalid deployment hash `{}` in mappings.handler_timeout",
+                    hash
+                )
+            })?;
+            if *timeout == 0 {
+                return Err(anyhow!(
+                    "the handler timeout for deployment `{}` must be bigger than 0",
+                    hash
+                ));
+            }
+        }
+        for (hash, retry) in &self.retry_policies {
+            DeploymentHash::new(hash.as_str())
+                .map_err(|hash| anyhow!("invalid deployment hash `{}` in mappings.retry", hash))?;
+            retry
+                .validate()
+                .with_context(|| format!("invalid retry policy for deployment `{}`", hash))?;
+        }
+        if let Some(errors) = &self.error_retry_errors {
+            errors
+                .parse::<RetryErrors>()
+                .map_err(|e| anyhow!("invalid mappings.error_retry_errors: {}", e))?;
+        }
+        for (name, version) in [
+            ("max_api_version", &self.max_api_version),
+            ("max_spec_version", &self.max_spec_version),
+        ] {
+            if let Some(version) = version {
+                Version::parse(version)
+                    .map_err(|e| anyhow!("invalid mappings.{} `{}`: {}", name, version, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn env_vars(&self) -> Vec<(&'static str, String)> {
+        fn s<T: ToString>(value: &Option<T>) -> Option<String> {
+            value.as_ref().map(ToString::to_string)
+        }
+
+        set_env_vars(vec![
+            (
+                "GRAPH_SUBGRAPH_ERROR_RETRY_BASE_SECS",
+                s(&self.error_retry_base),
+            ),
+            (
+                "GRAPH_SUBGRAPH_ERROR_RETRY_CEIL_SECS",
+                s(&self.error_retry_ceiling),
+            ),
+            (
+                "GRAPH_SUBGRAPH_ERROR_RETRY_MAX_ATTEMPTS",
+                s(&self.error_retry_max_attempts),
+            ),
+            (
+                "GRAPH_SUBGRAPH_ERROR_RETRY_ERRORS",
+                s(&self.error_retry_errors),
+            ),
+            ("GRAPH_ENTITY_CACHE_SIZE", s(&self.entity_cache_size)),
+            ("GRAPH_RUNTIME_MAX_STACK_SIZE", s(&self.max_stack_size)),
+            (
+                "GRAPH_MAPPING_HANDLER_TIMEOUT",
+                s(&self.default_handler_timeout),
+            ),
+            ("GRAPH_MAX_GAS_PER_HANDLER", s(&self.max_gas_per_handler)),
+            ("GRAPH_MAX_API_VERSION", s(&self.max_api_version)),
+            ("GRAPH_MAX_SPEC_VERSION", s(&self.max_spec_version)),
+            ("GRAPH_SUBGRAPH_MAX_DATA_SOURCES", s(&self.max_data_sources)),
+            ("GRAPH_DISABLE_GRAFTS", s(&self.disable_grafts)),
+            ("GRAPH_DISABLE_FAIL_FAST", s(&self.disable_fail_fast)),
+            ("GRAPH_LOG_TRIGGER_DATA", s(&self.log_trigger_data)),
+            ("GRAPH_IPFS_TIMEOUT", s(&self.ipfs_timeout)),
+            (
+                "GRAPH_MAX_IPFS_CACHE_FILE_SIZE",
+                s(&self.max_ipfs_cache_file_size),
+            ),
+            ("GRAPH_MAX_IPFS_CACHE_SIZE", s(&self.max_ipfs_cache_size)),
+            (
+                "GRAPH_MAX_IPFS_MAP_FILE_SIZE",
+                s(&self.max_ipfs_map_file_size),
+            ),
+            ("GRAPH_MAX_IPFS_FILE_BYTES", s(&self.max_ipfs_file_bytes)),
+            (
+                "GRAPH_ALLOW_NON_DETERMINISTIC_IPFS",
+                s(&self.allow_non_deterministic_ipfs),
+            ),
+        ])
+    }
+
+    /// The handler timeouts that should be used instead of the global
+    /// `GRAPH_MAPPING_HANDLER_TIMEOUT` for specific deployments
+    pub fn handler_timeouts(&self) -> HashMap<DeploymentHash, Duration> {
+        self.handler_timeouts
+            .iter()
+            .filter_map(|(hash, timeout)| {
+                DeploymentHash::new(hash.as_str())
+                    .ok()
+                    .map(|hash| (hash, Duration::from_secs(*timeout)))
+            })
+            .collect()
+    }
+
+    /// The retry policies that should be used instead of the one set by
+    /// the `GRAPH_SUBGRAPH_ERROR_RETRY_*` variables for specific
+    /// deployments
+    pub fn retry_policies(&self) -> HashMap<DeploymentHash, RetryPolicy> {
+        self.retry_policies
+            .iter()
+            .filter_map(|(hash, retry)| {
+                DeploymentHash::new(hash.as_str())
+                    .ok()
+                    .map(|hash| (hash, retry.apply(RetryPolicy::from_env())))
+            })
+            .collect()
+    }
+}
+
+/// Overrides for the retry policy of one deployment. Settings that are not
+/// given are taken from the `GRAPH_SUBGRAPH_ERROR_RETRY_*` variables
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RetryOverride {
+    max_attempts: Option<u64>,
+    /// The delay before the first retry, in seconds
+    base: Option<u64>,
+    /// The longest delay between retries, in seconds
+    ceiling: Option<u64>,
+    errors: Option<String>,
+}
+
+impl RetryOverride {
+    fn validate(&self) -> Result<()> {
+        if let Some(errors) = &self.errors {
+            errors
+                .parse::<RetryErrors>()
+                .map_err(|e| anyhow!("{}", e))?;
+        }
+        if let (Some(base), Some(ceiling)) = (self.base, self.ceiling) {
+            if base > ceiling {
+                bail!(
+                    "base ({}s) must not be bigger than ceiling ({}s)",
+                    base,
+                    ceiling
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&self, mut policy: RetryPolicy) -> RetryPolicy {
+        if let Some(max_attempts) = self.max_attempts {
+            policy.max_attempts = Some(max_attempts);
+        }
+        if let Some(base) = self.base {
+            policy.base = Duration::from_secs(base);
+        }
+        if let Some(ceiling) = self.ceiling {
+            policy.ceiling = Duration::from_secs(ceiling);
+        }
+        if let Some(errors) = self.errors.as_ref().and_then(|errors| errors.parse().ok()) {
+            policy.errors = errors;
+        }
+        policy
+    }
+}
+
+/// Settings for GraphQL queries. Each setting mirrors an environment
+/// variable; if that variable is set, it takes precedence over the value
+/// from the configuration file
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuerySection {
+    /// Per-deployment overrides for `GRAPH_GRAPHQL_MAX_COMPLEXITY`,
+    /// `GRAPH_GRAPHQL_MAX_DEPTH`, `GRAPH_GRAPHQL_MAX_FIRST` and
+    /// `GRAPH_GRAPHQL_MAX_SKIP`, keyed by the deployment hash
+    #[serde(default, rename = "deployment")]
+    deployment_limits: BTreeMap<String, QueryLimits>,
+    /// `GRAPH_GRAPHQL_QUERY_TIMEOUT`, in seconds
+    timeout: Option<u64>,
+    /// `GRAPH_SQL_STATEMENT_TIMEOUT`, in seconds
+    sql_statement_timeout: Option<u64>,
+    /// `GRAPH_GRAPHQL_MAX_COMPLEXITY`
+    max_complexity: Option<u64>,
+    /// `GRAPH_GRAPHQL_MAX_DEPTH`
+    max_depth: Option<u8>,
+    /// `GRAPH_GRAPHQL_MAX_FIRST`
+    max_first: Option<u32>,
+    /// `GRAPH_GRAPHQL_MAX_SKIP`
+    max_skip: Option<u32>,
+    /// `GRAPH_GRAPHQL_WARN_RESULT_SIZE`, in bytes
+    warn_result_size: Option<usize>,
+    /// `GRAPH_GRAPHQL_ERROR_RESULT_SIZE`, in bytes
+    error_result_size: Option<usize>,
+    /// `GRAPH_GRAPHQL_MAX_OPERATIONS_PER_CONNECTION`
+    max_operations_per_connection: Option<usize>,
+    /// `GRAPH_GRAPHQL_FIRST_COMPLEXITY_WEIGHT`
+    first_complexity_weight: Option<u64>,
+    /// `GRAPH_GRAPHQL_SKIP_COMPLEXITY_WEIGHT`
+    skip_complexity_weight: Option<u64>,
+    /// `GRAPH_GRAPHQL_NESTED_COLLECTION_COMPLEXITY_WEIGHT`
+    nested_collection_complexity_weight: Option<u64>,
+    /// `GRAPH_GRAPHQL_FULLTEXT_COMPLEXITY`
+    fulltext_complexity: Option<u64>,
+    /// `GRAPH_GRAPHQL_TOTAL_COUNT_COMPLEXITY`
+    total_count_complexity: Option<u64>,
+    /// `ENABLE_GRAPHQL_VALIDATIONS`
+    enable_validations: Option<bool>,
+    /// `GRAPHQL_ALLOW_DEPLOYMENT_CHANGE`
+    allow_deployment_change: Option<bool>,
+    /// `GRAPH_GRAPHQL_MAX_BATCH_SIZE`
+    max_batch_size: Option<usize>,
+    /// `GRAPH_GRAPHQL_CONCURRENT_BATCHES`
+    concurrent_batches: Option<bool>,
+    /// `GRAPH_GRAPHQL_ENFORCE_PERSISTED_QUERIES`
+    enforce_persisted_queries: Option<bool>,
+    /// `GRAPH_GRAPHQL_PERSISTED_QUERIES_REFRESH_INTERVAL`, in seconds
+    persisted_queries_refresh_interval: Option<u64>,
+    /// `SUBSCRIPTION_THROTTLE_INTERVAL`, in milliseconds
+    subscription_throttle_interval: Option<u64>,
+    /// `GRAPH_GRAPHQL_MAX_SUBSCRIPTION_REPLAY_BLOCKS`
+    max_subscription_replay_blocks: Option<i32>,
+    /// `GRAPH_GRAPHQL_BLOCK_HASH_LOOKUP_DEPTH`
+    block_hash_lookup_depth: Option<i32>,
+    /// `GRAPH_GRAPHQL_BLOCK_HASH_CACHE_SIZE`
+    block_hash_cache_size: Option<usize>,
+    /// `GRAPH_GRAPHQL_BLOCK_HASH_CACHE_TTL`, in seconds
+    block_hash_cache_ttl: Option<u64>,
+    /// `GRAPH_GRAPHQL_MAX_BLOCK_HASH_LOOKUPS`
+    max_block_hash_lookups: Option<usize>,
+    /// `GRAPH_GRAPHQL_STREAM_BATCH_SIZE`
+    stream_batch_size: Option<u32>,
+    /// `GRAPH_GRAPHQL_ENABLE_EXPORTS`
+    enable_exports: Option<bool>,
+    /// `GRAPH_GRAPHQL_EXPORT_BATCH_SIZE`
+    export_batch_size: Option<usize>,
+    /// `GRAPH_GRAPHQL_EXPORT_MAX_ROWS`
+    export_max_rows: Option<u32>,
+    /// `GRAPH_GRAPHQL_EXPORT_SEND_TIMEOUT`, in seconds
+    export_send_timeout: Option<u64>,
+    /// `GRAPH_GRAPHQL_HTTP_CACHE_MAX_AGE`, in seconds
+    http_cache_max_age: Option<u64>,
+    /// `GRAPH_GRAPHQL_DISABLE_HTTP_COMPRESSION`
+    disable_http_compression: Option<bool>,
+    /// `GRAPH_GRAPHQL_QUERY_STATS_MAX_SHAPES`
+    stats_max_shapes: Option<usize>,
+    /// `GRAPH_GRAPHQL_API_KEYS_FILE`
+    api_keys_file: Option<String>,
+    /// `GRAPH_GRAPHQL_API_KEYS_URL`
+    api_keys_url: Option<String>,
+    /// `GRAPH_GRAPHQL_API_KEYS_CACHE_TTL`, in seconds
+    api_keys_cache_ttl: Option<u64>,
+    /// `GRAPH_GRAPHQL_API_KEYS_CACHE_SIZE`
+    api_keys_cache_size: Option<usize>,
+    /// `GRAPH_QUERY_CACHE_BLOCKS`
+    cache_blocks: Option<usize>,
+    /// `GRAPH_QUERY_CACHE_MAX_MEM`, in megabytes
+    cache_max_mem: Option<usize>,
+    /// `GRAPH_QUERY_CACHE_STALE_PERIOD`
+    cache_stale_period: Option<u64>,
+    /// `GRAPH_QUERY_BLOCK_CACHE_SHARDS`
+    cache_block_shards: Option<u8>,
+    /// `GRAPH_QUERY_LFU_CACHE_SHARDS`
+    cache_lfu_shards: Option<u8>,
+    /// `GRAPH_QUERY_EMPTY_RESULT_CACHE_SIZE`
+    empty_result_cache_size: Option<usize>,
+    /// `GRAPH_QUERY_CACHE_EVICTION`
+    cache_eviction: Option<String>,
+    /// `GRAPH_QUERY_CACHE_DEPLOYMENT_QUOTA`, in percent
+    cache_deployment_quota: Option<usize>,
+    /// `GRAPH_QUERY_CACHE_DEPLOYMENT_WEIGHTS`, keyed by the deployment hash
+    #[serde(default)]
+    cache_deployment_weights: BTreeMap<String, f64>,
+    /// `GRAPH_CACHED_SUBGRAPH_IDS`
+    cached_subgraph_ids: Option<Vec<String>>,
+    /// `GRAPH_QUERY_SHARED_CACHE_URL`
+    shared_cache_url: Option<String>,
+    /// `GRAPH_QUERY_SHARED_CACHE_TTL`, in seconds
+    shared_cache_ttl: Option<u64>,
+    /// `GRAPH_QUERY_SHARED_CACHE_TIMEOUT`, in milliseconds
+    shared_cache_timeout: Option<u64>,
+    /// `GRAPH_LOAD_THRESHOLD`, in milliseconds
+    load_threshold: Option<u64>,
+    /// `GRAPH_LOAD_JAIL_THRESHOLD`
+    load_jail_threshold: Option<f64>,
+    /// `GRAPH_LOAD_SIMULATE`
+    load_simulate: Option<bool>,
+    /// `GRAPH_LOAD_WINDOW_SIZE`, in seconds
+    load_window_size: Option<u64>,
+    /// `GRAPH_LOAD_BIN_SIZE`, in seconds
+    load_bin_size: Option<u64>,
+}
+
+impl QuerySection {
+    fn validate(&self) -> Result<()> {
+        for hash in self.deployment_limits.keys() {
+            DeploymentHash::new(hash.as_str()).map_err(|hash| {
+                anyhow!("invalid deployment hash `{}` in query.deployment", hash)
+            })?;
+        }
+        for (hash, weight) in &self.cache_deployment_weights {
+            DeploymentHash::new(hash.as_str()).map_err(|hash| {
+                anyhow!(
+                    "invalid deployment hash `{}` in query.cache_deployment_weights",
+                    hash
+                )
+            })?;
+            if weight.is_nan() || *weight < 0.0 {
+                bail!(
+                    "the query cache weight for deployment `{}` must not be negative",
+                    hash
+                );
+            }
+        }
+        if let Some(eviction) = &self.cache_eviction {
+            eviction
+                .parse::<QueryCacheEviction>()
+                .map_err(|e| anyhow!("invalid query.cache_eviction: {}", e))?;
+        }
+        Ok(())
+    }
+
+    fn env_vars(&self) -> Vec<(&'static str, String)> {
+        fn s<T: ToString>(value: &Option<T>) -> Option<String> {
+            value.as_ref().map(ToString::to_string)
+        }
+
+        set_env_vars(vec![
+            ("GRAPH_GRAPHQL_QUERY_TIMEOUT", s(&self.timeout)),
+            (
+                "GRAPH_SQL_STATEMENT_TIMEOUT",
+                s(&self.sql_statement_timeout),
+            ),
+            ("GRAPH_GRAPHQL_MAX_COMPLEXITY", s(&self.max_complexity)),
+            ("GRAPH_GRAPHQL_MAX_DEPTH", s(&self.max_depth)),
+            ("GRAPH_GRAPHQL_MAX_FIRST", s(&self.max_first)),
+            ("GRAPH_GRAPHQL_MAX_SKIP", s(&self.max_skip)),
+            ("GRAPH_GRAPHQL_WARN_RESULT_SIZE", s(&self.warn_result_size)),
+            (
+                "GRAPH_GRAPHQL_ERROR_RESULT_SIZE",
+                s(&self.error_result_size),
+            ),
+            (
+                "GRAPH_GRAPHQL_MAX_OPERATIONS_PER_CONNECTION",
+                s(&self.max_operations_per_connection),
+            ),
+            (
+                "GRAPH_GRAPHQL_FIRST_COMPLEXITY_WEIGHT",
+                s(&self.first_complexity_weight),
+            ),
+            (
+                "GRAPH_GRAPHQL_SKIP_COMPLEXITY_WEIGHT",
+                s(&self.skip_complexity_weight),
+            ),
+            (
+                "GRAPH_GRAPHQL_NESTED_COLLECTION_COMPLEXITY_WEIGHT",
+                s(&self.nested_collection_complexity_weight),
+            ),
+            (
+                "GRAPH_GRAPHQL_FULLTEXT_COMPLEXITY",
+                s(&self.fulltext_complexity),
+            ),
+            (
+                "GRAPH_GRAPHQL_TOTAL_COUNT_COMPLEXITY",
+                s(&self.total_count_complexity),
+            ),
+            ("ENABLE_GRAPHQL_VALIDATIONS", s(&self.enable_validations)),
+            (
+                "GRAPHQL_ALLOW_DEPLOYMENT_CHANGE",
+                s(&self.allow_deployment_change),
+            ),
+            ("GRAPH_GRAPHQL_MAX_BATCH_SIZE", s(&self.max_batch_size)),
+            (
+                "GRAPH_GRAPHQL_CONCURRENT_BATCHES",
+                s(&self.concurrent_batches),
+            ),
+            (
+                "GRAPH_GRAPHQL_ENFORCE_PERSISTED_QUERIES",
+                s(&self.enforce_persisted_queries),
+            ),
+            (
+                "GRAPH_GRAPHQL_PERSISTED_QUERIES_REFRESH_INTERVAL",
+                s(&self.persisted_queries_refresh_interval),
+            ),
+            (
+                "SUBSCRIPTION_THROTTLE_INTERVAL",
+                s(&self.subscription_throttle_interval),
+            ),
+            (
+                "GRAPH_GRAPHQL_MAX_SUBSCRIPTION_REPLAY_BLOCKS",
+                s(&self.max_subscription_replay_blocks),
+            ),
+            (
+                "GRAPH_GRAPHQL_BLOCK_HASH_LOOKUP_DEPTH",
+                s(&self.block_hash_lookup_depth),
+            ),
+            (
+                "GRAPH_GRAPHQL_BLOCK_HASH_CACHE_SIZE",
+                s(&self.block_hash_cache_size),
+            ),
+            (
+                "GRAPH_GRAPHQL_BLOCK_HASH_CACHE_TTL",
+                s(&self.block_hash_cache_ttl),
+            ),
+            (
+                "GRAPH_GRAPHQL_MAX_BLOCK_HASH_LOOKUPS",
+                s(&self.max_block_hash_lookups),
+            ),
+            (
+                "GRAPH_GRAPHQL_STREAM_BATCH_SIZE",
+                s(&self.stream_batch_size),
+            ),
+            ("GRAPH_GRAPHQL_ENABLE_EXPORTS", s(&self.enable_exports)),
+            (
+                "GRAPH_GRAPHQL_EXPORT_BATCH_SIZE",
+                s(&self.export_batch_size),
+            ),
+            ("GRAPH_GRAPHQL_EXPORT_MAX_ROWS", s(&self.export_max_rows)),
+            (
+                "GRAPH_GRAPHQL_EXPORT_SEND_TIMEOUT",
+                s(&self.export_send_timeout),
+            ),
+            (
+                "GRAPH_GRAPHQL_HTTP_CACHE_MAX_AGE",
+                s(&self.http_cache_max_age),
+            ),
+            (
+                "GRAPH_GRAPHQL_DISABLE_HTTP_COMPRESSION",
+                s(&self.disable_http_compression),
+            ),
+            (
+                "GRAPH_GRAPHQL_QUERY_STATS_MAX_SHAPES",
+                s(&self.stats_max_shapes),
+            ),
+            ("GRAPH_GRAPHQL_API_KEYS_FILE", s(&self.api_keys_file)),
+            ("GRAPH_GRAPHQL_API_KEYS_URL", s(&self.api_keys_url)),
+            (
+                "GRAPH_GRAPHQL_API_KEYS_CACHE_TTL",
+                s(&self.api_keys_cache_ttl),
+            ),
+            (
+                "GRAPH_GRAPHQL_API_KEYS_CACHE_SIZE",
+                s(&self.api_keys_cache_size),
+            ),
+            ("GRAPH_QUERY_CACHE_BLOCKS", s(&self.cache_blocks)),
+            ("GRAPH_QUERY_CACHE_MAX_MEM", s(&self.cache_max_mem)),
+            (
+                "GRAPH_QUERY_CACHE_STALE_PERIOD",
+                s(&self.cache_stale_period),
+            ),
+            (
+                "GRAPH_QUERY_BLOCK_CACHE_SHARDS",
+                s(&self.cache_block_shards),
+            ),
+            ("GRAPH_QUERY_LFU_CACHE_SHARDS", s(&self.cache_lfu_shards)),
+            (
+                "GRAPH_QUERY_EMPTY_RESULT_CACHE_SIZE",
+                s(&self.empty_result_cache_size),
+            ),
+            ("GRAPH_QUERY_CACHE_EVICTION", s(&self.cache_eviction)),
+            (
+                "GRAPH_QUERY_CACHE_DEPLOYMENT_QUOTA",
+                s(&self.cache_deployment_quota),
+            ),
+            (
+                "GRAPH_QUERY_CACHE_DEPLOYMENT_WEIGHTS",
+                Some(&self.cache_deployment_weights)
+                    .filter(|weights| !weights.is_empty())
+                    .map(|weights| {
+                        weights
+                            .iter()
+                            .map(|(hash, weight)| format!("{}={}", hash, weight))
+                            .collect::<Vec<_>>()
+                            .join(",")
+                    }),
+            ),
+            (
+                "GRAPH_CACHED_SUBGRAPH_IDS",
+                self.cached_subgraph_ids.as_ref().map(|ids| ids.join(",")),
+            ),
+            ("GRAPH_QUERY_SHARED_CACHE_URL", s(&self.shared_cache_url)),
+            ("GRAPH_QUERY_SHARED_CACHE_TTL", s(&self.shared_cache_ttl)),
+            (
+                "GRAPH_QUERY_SHARED_CACHE_TIMEOUT",
+                s(&self.shared_cache_timeout),
+            ),
+            ("GRAPH_LOAD_THRESHOLD", s(&self.load_threshold)),
+            ("GRAPH_LOAD_JAIL_THRESHOLD", s(&self.load_jail_threshold)),
+            ("GRAPH_LOAD_SIMULATE", s(&self.load_simulate)),
+            ("GRAPH_LOAD_WINDOW_SIZE", s(&self.load_window_size)),
+            ("GRAPH_LOAD_BIN_SIZE", s(&self.load_bin_size)),
+        ])
+    }
+
+    /// The limits that should be used instead of the global query limits
+    /// for specific deployments
+    pub fn deployment_limits(&self) -> HashMap<DeploymentHash, QueryLimits> {
+        self.deployment_limits
+            .iter()
+            .filter_map(|(hash, limits)| {
+                DeploymentHash::new(hash.as_str())
+                    .ok()
+                    .map(|hash| (hash, limits.clone()))
+            })
+            .collect()
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -280,6 +930,7 @@ impl Shard {
 pub enum PoolSize {
     None,
     Fixed(u32),
+    Bounds(PoolSizeBounds),
     Rule(Vec<PoolSizeRule>),
 }
 
@@ -297,32 +948,67 @@ impl PoolSize {
     fn validate(&self, connection: &str) -> Result<()> {
         use PoolSize::*;
 
-        let pool_size = match self {
+        let (min_size, pool_size) = match self {
             None => bail!("missing pool size for {}", connection),
-            Fixed(s) => *s,
-            Rule(rules) => rules.iter().map(|rule| rule.size).min().unwrap_or(0u32),
+            Fixed(s) => (*s, *s),
+            Bounds(bounds) => (bounds.min, bounds.max),
+            Rule(rules) => (
+                rules.iter().map(|rule| rule.min()).min().unwrap_or(0u32),
+                rules.iter().map(|rule| rule.size).min().unwrap_or(0u32),
+            ),
         };
 
         if pool_size < 2 {
-            Err(anyhow!(
+            return Err(anyhow!(
                 "connection pool size must be at least 2, but is {} for {}",
                 pool_size,
                 connection
-            ))
-        } else {
-            Ok(())
+            ));
+        }
+        if let Rule(rules) = self {
+            if let Some(rule) = rules.iter().find(|rule| rule.min() > rule.size) {
+                return Err(anyhow!(
+                    "the minimum connection pool size {} is bigger than the size {} for {}",
+                    rule.min(),
+                    rule.size,
+                    connection
+                ));
+            }
+        } else if min_size > pool_size {
+            return Err(anyhow!(
+                "the minimum connection pool size {} is bigger than the maximum {} for {}",
+                min_size,
+                pool_size,
+                connection
+            ));
+        }
+        if min_size < 1 {
+            return Err(anyhow!(
+                "the minimum connection pool size must be at least 1 for {}",
+                connection
+            ));
         }
+        Ok(())
     }
 
+    /// The maximum number of connections the pool for `name` may open on
+    /// `node`
     pub fn size_for(&self, node: &NodeId, name: &str) -> Result<u32> {
+        self.bounds_for(node, name).map(|(_, max)| max)
+    }
+
+    /// The minimum and maximum size of the pool for `name` on `node`. For
+    /// pools with a fixed size, both are the same
+    pub fn bounds_for(&self, node: &NodeId, name: &str) -> Result<(u32, u32)> {
         use PoolSize::*;
         match self {
             None => unreachable!("validation ensures we have a pool size"),
-            Fixed(s) => Ok(*s),
+            Fixed(s) => Ok((*s, *s)),
+            Bounds(bounds) => Ok((bounds.min, bounds.max)),
             Rule(rules) => rules
                 .iter()
                 .find(|rule| rule.matches(node.as_str()))
-                .map(|rule| rule.size)
+                .map(|rule| (rule.min(), rule.size))
                 .ok_or_else(|| {
                     anyhow!(
                         "no rule matches node id `{}` for the pool of shard {}",
@@ -334,11 +1020,19 @@ impl PoolSize {
     }
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PoolSizeBounds {
+    min: u32,
+    max: u32,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PoolSizeRule {
     #[serde(with = "serde_regex", default = "any_name")]
     node: Regex,
     size: u32,
+    #[serde(default)]
+    min: Option<u32>,
 }
 
 impl PoolSizeRule {
@@ -348,6 +1042,10 @@ impl PoolSizeRule {
             Some(m) => m.as_str() == name,
         }
     }
+
+    fn min(&self) -> u32 {
+        self.min.unwrap_or(self.size)
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -457,6 +1155,7 @@ impl ChainSection {
                         url: url.to_string(),
                         features,
                         headers: Default::default(),
+                        limits: Default::default(),
                     }),
                 };
                 let entry = chains.entry(name.to_string()).or_insert_with(|| Chain {
@@ -537,12 +1236,34 @@ pub struct FirehoseProvider {
     pub token: Option<String>,
     #[serde(default)]
     pub features: BTreeSet<String>,
+    #[serde(default)]
+    pub grpc: FirehoseGrpc,
 }
 
 impl FirehoseProvider {
     pub fn filters_enabled(&self) -> bool {
         self.features.contains(FIREHOSE_FILTER_FEATURE)
     }
+
+    pub fn grpc_settings(&self) -> GrpcSettings {
+        GrpcSettings {
+            keepalive_interval: self.grpc.keepalive_interval.map(Duration::from_secs),
+            keepalive_timeout: self.grpc.keepalive_timeout.map(Duration::from_secs),
+        }
+    }
+}
+
+/// Settings for the gRPC connection to a Firehose provider. Compressing
+/// messages and limiting their size are not supported by the version of
+/// `tonic` that we use, and are therefore not accepted here
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FirehoseGrpc {
+    /// Send HTTP/2 pings every this many seconds
+    pub keepalive_interval: Option<u64>,
+    /// Close the connection if a ping is not answered within this many
+    /// seconds
+    pub keepalive_timeout: Option<u64>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
@@ -559,6 +1280,9 @@ pub struct Web3Provider {
         deserialize_with = "deserialize_http_headers"
     )]
     pub headers: HeaderMap,
+
+    #[serde(default)]
+    pub limits: Web3Limits,
 }
 
 impl Web3Provider {
@@ -568,6 +1292,32 @@ impl Web3Provider {
             traces: self.features.contains("traces"),
         }
     }
+
+    /// The limits for requests to this provider, or `None` if requests are
+    /// not limited
+    pub fn request_limits(&self) -> Option<ethereum::RequestLimits> {
+        if self.limits == Web3Limits::default() {
+            return None;
+        }
+        Some(ethereum::RequestLimits {
+            requests_per_second: self.limits.requests_per_second,
+            burst: self.limits.burst,
+            daily_requests: self.limits.daily_requests,
+        })
+    }
+}
+
+/// Limits on the requests that are sent to a JSON-RPC provider
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Web3Limits {
+    /// Send at most this many requests per second on average
+    pub requests_per_second: Option<f64>,
+    /// How many requests may be sent at once, on top of the average rate
+    pub burst: Option<u32>,
+    /// Prefer other providers once this many requests were sent during the
+    /// current UTC day
+    pub daily_requests: Option<u64>,
 }
 
 const PROVIDER_FEATURES: [&str; 3] = ["traces", "archive", "no_eip1898"];
@@ -607,6 +1357,21 @@ impl Provider {
                         FIREHOSE_PROVIDER_FEATURES
                     ));
                 }
+
+                if firehose.grpc.keepalive_interval == Some(0) {
+                    return Err(anyhow!(
+                        "the gRPC keepalive_interval for firehose provider {} must be positive",
+                        label
+                    ));
+                }
+                if firehose.grpc.keepalive_timeout.is_some()
+                    && firehose.grpc.keepalive_interval.is_none()
+                {
+                    return Err(anyhow!(
+                        "the gRPC keepalive_timeout for firehose provider {} requires a keepalive_interval",
+                        label
+                    ));
+                }
             }
 
             ProviderDetails::Web3(ref mut web3) => {
@@ -632,6 +1397,21 @@ impl Provider {
                         e
                     )
                 })?;
+
+                if let Some(rate) = web3.limits.requests_per_second {
+                    if rate.is_nan() || rate <= 0.0 {
+                        return Err(anyhow!(
+                            "the requests_per_second for provider {} must be positive",
+                            label
+                        ));
+                    }
+                }
+                if web3.limits.burst.is_some() && web3.limits.requests_per_second.is_none() {
+                    return Err(anyhow!(
+                        "the burst for provider {} requires requests_per_second",
+                        label
+                    ));
+                }
             }
         }
 
@@ -727,6 +1507,7 @@ impl<'de> Deserialize<'de> for Provider {
                         features: features
                             .ok_or_else(|| serde::de::Error::missing_field("features"))?,
                         headers: headers.unwrap_or_else(|| HeaderMap::new()),
+                        limits: Default::default(),
                     }),
                 };
 
@@ -1023,13 +1804,18 @@ where
 mod tests {
 
     use super::{
-        Chain, Config, FirehoseProvider, Provider, ProviderDetails, Transport, Web3Provider,
+        Chain, Config, FirehoseGrpc, FirehoseProvider, MappingsSection, Provider, ProviderDetails,
+        QuerySection, Shard, Transport, Web3Provider,
     };
     use graph::blockchain::BlockchainKind;
+    use graph::data::query::QueryLimits;
+    use graph::data::subgraph::retry::{ErrorClass, RetryErrors};
+    use graph::prelude::{DeploymentHash, NodeId, NodeRole};
     use http::{HeaderMap, HeaderValue};
     use std::collections::BTreeSet;
     use std::fs::read_to_string;
     use std::path::{Path, PathBuf};
+    use std::time::Duration;
 
     #[test]
     fn it_works_on_standard_config() {
@@ -1047,6 +1833,221 @@ mod tests {
         assert_eq!(3, actual.deployment.rules.len());
     }
 
+    #[test]
+    fn it_works_on_node_roles() {
+        let content = read_resource_as_string("full_config.toml");
+        let mut config: Config = toml::from_str(&content).unwrap();
+        let general = config.general.as_mut().unwrap();
+        general
+            .roles
+            .insert("query_node_7".to_string(), NodeRole::Index);
+        general
+            .roles
+            .insert("index_node_1".to_string(), NodeRole::Index);
+
+        let role = |node: &str| config.node_role(&NodeId::new(node).unwrap());
+        assert_eq!(NodeRole::Query, role("query_node_1"));
+        assert_eq!(NodeRole::Index, role("query_node_7"));
+        assert_eq!(NodeRole::Index, role("index_node_1"));
+        assert_eq!(NodeRole::Combined, role("index_node_2"));
+    }
+
+    #[test]
+    fn it_works_on_pool_size_bounds() {
+        let node = NodeId::new("index_node_1").unwrap();
+
+        let mut shard: Shard = toml::from_str(
+            r#"
+            connection = "postgresql://localhost/graph"
+            pool_size = { min = 2, max = 10 }
+        "#,
+        )
+        .unwrap();
+        shard.validate("primary").unwrap();
+        assert_eq!(
+            (2, 10),
+            shard.pool_size.bounds_for(&node, "primary").unwrap()
+        );
+        assert_eq!(10, shard.pool_size.size_for(&node, "primary").unwrap());
+
+        let mut shard: Shard = toml::from_str(
+            r#"
+            connection = "postgresql://localhost/graph"
+            pool_size = [
+              { node = "index_node_.*", size = 20, min = 5 },
+              { node = "query_node_.*", size = 30 }
+            ]
+        "#,
+        )
+        .unwrap();
+        shard.validate("primary").unwrap();
+        assert_eq!(
+            (5, 20),
+            shard.pool_size.bounds_for(&node, "primary").unwrap()
+        );
+        let query_node = NodeId::new("query_node_1").unwrap();
+        assert_eq!(
+            (30, 30),
+            shard.pool_size.bounds_for(&query_node, "primary").unwrap()
+        );
+
+        let mut shard: Shard = toml::from_str(
+            r#"
+            connection = "postgresql://localhost/graph"
+            pool_size = { min = 12, max = 10 }
+        "#,
+        )
+        .unwrap();
+        assert!(shard.validate("primary").is_err());
+    }
+
+    #[test]
+    fn it_works_on_handler_timeout_overrides() {
+        let actual: MappingsSection = toml::from_str(
+            r#"
+            [handler_timeout]
+            QmXYZ = 120
+        "#,
+        )
+        .unwrap();
+        actual.validate().unwrap();
+
+        let timeouts = actual.handler_timeouts();
+        assert_eq!(1, timeouts.len());
+        assert_eq!(
+            Some(&std::time::Duration::from_secs(120)),
+            timeouts.get(&DeploymentHash::new("QmXYZ").unwrap())
+        );
+
+        let invalid: MappingsSection = toml::from_str(
+            r#"
+            [handler_timeout]
+            QmXYZ = 0
+        "#,
+        )
+        .unwrap();
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn it_works_on_retry_policy_overrides() {
+        let actual: MappingsSection = toml::from_str(
+            r#"
+            [retry.QmXYZ]
+            max_attempts = 5
+            errors = "timeout,ipfs"
+        "#,
+        )
+        .unwrap();
+        actual.validate().unwrap();
+
+        let policies = actual.retry_policies();
+        let policy = policies
+            .get(&DeploymentHash::new("QmXYZ").unwrap())
+            .unwrap();
+        assert_eq!(Some(5), policy.max_attempts);
+        assert_eq!(
+            RetryErrors::Only(vec![ErrorClass::Timeout, ErrorClass::Ipfs]),
+            policy.errors
+        );
+
+        let invalid: MappingsSection = toml::from_str(
+            r#"
+            [retry.QmXYZ]
+            errors = "timeout,disk"
+        "#,
+        )
+        .unwrap();
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn it_works_on_query_limit_overrides() {
+        let actual: QuerySection = toml::from_str(
+            r#"
+            max_complexity = 1000
+            [deployment.QmXYZ]
+            max_complexity = 5000
+            max_first = 10
+        "#,
+        )
+        .unwrap();
+        actual.validate().unwrap();
+
+        let limits = actual.deployment_limits();
+        assert_eq!(1, limits.len());
+        assert_eq!(
+            Some(&QueryLimits {
+                max_complexity: Some(5000),
+                max_depth: None,
+                max_first: Some(10),
+                max_skip: None,
+            }),
+            limits.get(&DeploymentHash::new("QmXYZ").unwrap())
+        );
+
+        let invalid: QuerySection = toml::from_str(
+            r#"
+            [deployment."not a hash"]
+            max_first = 10
+        "#,
+        )
+        .unwrap();
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn it_works_on_env_sections() {
+        let mappings: MappingsSection = toml::from_str(
+            r#"
+            entity_cache_size = 20000
+            ipfs_timeout = 60
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                ("GRAPH_ENTITY_CACHE_SIZE", "20000".to_string()),
+                ("GRAPH_IPFS_TIMEOUT", "60".to_string())
+            ],
+            mappings.env_vars()
+        );
+
+        let query: QuerySection = toml::from_str(
+            r#"
+            timeout = 10
+            max_first = 500
+            cache_blocks = 4
+            cached_subgraph_ids = ["QmXYZ", "QmABC"]
+
+            [cache_deployment_weights]
+            QmXYZ = 2.5
+        "#,
+        )
+        .unwrap();
+        query.validate().unwrap();
+        assert_eq!(
+            vec![
+                ("GRAPH_GRAPHQL_QUERY_TIMEOUT", "10".to_string()),
+                ("GRAPH_GRAPHQL_MAX_FIRST", "500".to_string()),
+                ("GRAPH_QUERY_CACHE_BLOCKS", "4".to_string()),
+                (
+                    "GRAPH_QUERY_CACHE_DEPLOYMENT_WEIGHTS",
+                    "QmXYZ=2.5".to_string()
+                ),
+                ("GRAPH_CACHED_SUBGRAPH_IDS", "QmXYZ,QmABC".to_string())
+            ],
+            query.env_vars()
+        );
+
+        // The query cache is configured in `[query]`, not `[mappings]`
+        let misplaced = toml::from_str::<MappingsSection>("query_cache_blocks = 4");
+        assert!(misplaced.is_err());
+
+        let invalid: QuerySection = toml::from_str(r#"cache_eviction = "oldest""#).unwrap();
+        assert!(invalid.validate().is_err());
+    }
+
     #[test]
     fn it_works_on_chain_without_protocol() {
         let actual = toml::from_str(
@@ -1108,6 +2109,7 @@ mod tests {
                     url: "http://localhost:8545".to_owned(),
                     features: BTreeSet::new(),
                     headers: HeaderMap::new(),
+                    limits: Web3Limits::default(),
                 }),
             },
             actual
@@ -1133,6 +2135,7 @@ mod tests {
                     url: "http://localhost:8545".to_owned(),
                     features: BTreeSet::new(),
                     headers: HeaderMap::new(),
+                    limits: Web3Limits::default(),
                 }),
             },
             actual
@@ -1197,6 +2200,7 @@ mod tests {
                     url: "http://localhost:8545".to_owned(),
                     features,
                     headers,
+                    limits: Web3Limits::default(),
                 }),
             },
             actual
@@ -1221,6 +2225,7 @@ mod tests {
                     url: "http://localhost:8545".to_owned(),
                     features: BTreeSet::new(),
                     headers: HeaderMap::new(),
+                    limits: Web3Limits::default(),
                 }),
             },
             actual
@@ -1258,6 +2263,7 @@ mod tests {
                     url: "http://localhost:9000".to_owned(),
                     token: None,
                     features: BTreeSet::new(),
+                    grpc: FirehoseGrpc::default(),
                 }),
             },
             actual
@@ -1281,6 +2287,7 @@ mod tests {
                     url: "http://localhost:9000".to_owned(),
                     token: None,
                     features: BTreeSet::new(),
+                    grpc: FirehoseGrpc::default(),
                 }),
             },
             actual
@@ -1307,6 +2314,91 @@ mod tests {
         }
     }
 
+    #[test]
+    fn it_works_on_firehose_provider_with_grpc_settings() {
+        let mut actual = toml::from_str::<Provider>(
+            r#"
+                label = "firehose"
+                details = { type = "firehose", url = "http://localhost:9000", grpc = { keepalive_interval = 30, keepalive_timeout = 10 } }
+            "#,
+        )
+        .unwrap();
+        actual.validate().unwrap();
+
+        match actual.details {
+            ProviderDetails::Firehose(firehose) => {
+                let grpc = firehose.grpc_settings();
+                assert_eq!(Some(Duration::from_secs(30)), grpc.keepalive_interval);
+                assert_eq!(Some(Duration::from_secs(10)), grpc.keepalive_timeout);
+            }
+            ProviderDetails::Web3(_) => panic!("expected a firehose provider"),
+        }
+
+        let actual = toml::from_str::<Provider>(
+            r#"
+                label = "firehose"
+                details = { type = "firehose", url = "http://localhost:9000", grpc = { compression = "gzip" } }
+            "#,
+        );
+        assert!(actual.is_err());
+
+        let actual = toml::from_str::<Provider>(
+            r#"
+                label = "firehose"
+                details = { type = "firehose", url = "http://localhost:9000", grpc = { keepalive_timeout = 10 } }
+            "#,
+        )
+        .unwrap()
+        .validate();
+        assert!(actual.is_err());
+    }
+
+    #[test]
+    fn it_works_on_web3_provider_with_limits() {
+        let mut actual = toml::from_str::<Provider>(
+            r#"
+                label = "peering"
+                details = { type = "web3", url = "http://localhost:8545", features = [], limits = { requests_per_second = 25, burst = 50, daily_requests = 100000 } }
+            "#,
+        )
+        .unwrap();
+        actual.validate().unwrap();
+
+        match actual.details {
+            ProviderDetails::Web3(web3) => assert_eq!(
+                Some(ethereum::RequestLimits {
+                    requests_per_second: Some(25.0),
+                    burst: Some(50),
+                    daily_requests: Some(100_000),
+                }),
+                web3.request_limits()
+            ),
+            ProviderDetails::Firehose(_) => panic!("expected a web3 provider"),
+        }
+
+        let actual = toml::from_str::<Provider>(
+            r#"
+                label = "peering"
+                details = { type = "web3", url = "http://localhost:8545", features = [] }
+            "#,
+        )
+        .unwrap();
+        match actual.details {
+            ProviderDetails::Web3(web3) => assert_eq!(None, web3.request_limits()),
+            ProviderDetails::Firehose(_) => panic!("expected a web3 provider"),
+        }
+
+        let actual = toml::from_str::<Provider>(
+            r#"
+                label = "peering"
+                details = { type = "web3", url = "http://localhost:8545", features = [], limits = { burst = 50 } }
+            "#,
+        )
+        .unwrap()
+        .validate();
+        assert!(actual.is_err());
+    }
+
     fn read_resource_as_string<P: AsRef<Path>>(path: P) -> String {
         let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         d.push("resources/tests");
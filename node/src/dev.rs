@@ -0,0 +1,102 @@
+//! Development mode: run a subgraph from a local build directory and
+//! redeploy it whenever the files in the directory change
+use std::sync::Arc;
+use std::time::Duration;
+
+use graph::prelude::{
+    anyhow, error, info, o, tokio, warn, DeploymentHash, Logger, NodeId, SubgraphName,
+    SubgraphRegistrar, SubgraphRegistrarError, SubgraphStore as _,
+};
+use graph_core::FileLinkResolver;
+use graph_store_postgres::SubgraphStore;
+
+/// How often to check whether the files in the directory changed
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Deploy the subgraph in the directory of `resolver` under `name`, and
+/// deploy it again whenever the files in the directory change. Since the
+/// deployment hash is derived from the files, every change leads to a new
+/// deployment that indexes from the start block. When the files change
+/// back to an earlier state, the deployment for that state is removed
+/// first so that it, too, starts over at the start block. The registrar
+/// must switch versions instantly so that the previous deployment stops
+/// indexing right away.
+pub async fn watch(
+    logger: Logger,
+    resolver: Arc<FileLinkResolver>,
+    registrar: Arc<dyn SubgraphRegistrar>,
+    store: Arc<SubgraphStore>,
+    name: SubgraphName,
+    node_id: NodeId,
+) {
+    let logger = logger.new(o!("component" => "DevMode"));
+    info!(logger, "Watching {} for changes", resolver.dir().display();
+          "subgraph" => name.as_str());
+
+    match registrar.create_subgraph(name.clone()).await {
+        Ok(_) | Err(SubgraphRegistrarError::NameExists(_)) => {}
+        Err(e) => {
+            error!(logger, "Failed to create subgraph {}: {}", name, e);
+            return;
+        }
+    }
+
+    let mut hash = resolver.deployment_hash();
+    let mut current = None;
+    loop {
+        if current.as_ref() != Some(&hash) {
+            match deploy(&logger, &registrar, &store, &name, &node_id, &hash).await {
+                Ok(()) => info!(logger, "Deployed {}", hash; "subgraph" => name.as_str()),
+                Err(e) => error!(logger, "Failed to deploy {}: {}", hash, e;
+                                 "subgraph" => name.as_str()),
+            }
+            // Do not retry a broken deployment until the files change
+            current = Some(hash.clone());
+        }
+
+        tokio::time::sleep(POLL_INTERVAL).await;
+        match resolver.refresh() {
+            Ok(Some(changed)) => {
+                info!(logger, "Files in {} changed", resolver.dir().display();
+                      "deployment" => changed.as_str());
+                hash = changed;
+            }
+            Ok(None) => { /* nothing changed */ }
+            // The directory might be in the middle of being rebuilt
+            Err(e) => warn!(logger, "Failed to read {}: {}", resolver.dir().display(), e),
+        }
+    }
+}
+
+async fn deploy(
+    logger: &Logger,
+    registrar: &Arc<dyn SubgraphRegistrar>,
+    store: &Arc<SubgraphStore>,
+    name: &SubgraphName,
+    node_id: &NodeId,
+    hash: &DeploymentHash,
+) -> Result<(), anyhow::Error> {
+    let in_use = store.current_deployment(name)?.as_ref() == Some(hash);
+    if !in_use {
+        for locator in store.locators(hash)? {
+            info!(
+                logger,
+                "Removing earlier deployment {} to start it over", locator
+            );
+            store.remove_deployment(locator.id)?;
+        }
+    }
+
+    registrar
+        .create_subgraph_version(
+            name.clone(),
+            hash.clone(),
+            node_id.clone(),
+            None,
+            None,
+            false,
+            false,
+        )
+        .await?;
+    Ok(())
+}
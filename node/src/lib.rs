@@ -8,7 +8,9 @@ extern crate diesel;
 
 pub mod chain;
 pub mod config;
+pub mod dev;
 pub mod opt;
+pub mod provider_manager;
 pub mod store_builder;
 
 pub mod manager;
@@ -2,7 +2,8 @@ use ethereum::{BlockIngestor as EthereumBlockIngestor, EthereumAdapterTrait, Eth
 use git_testament::{git_testament, render_testament};
 use graph::blockchain::firehose_block_ingestor::FirehoseBlockIngestor;
 use graph::blockchain::{Block as BlockchainBlock, Blockchain, BlockchainKind, BlockchainMap};
-use graph::components::store::BlockStore;
+use graph::components::server::health::Readiness;
+use graph::components::store::{BlockStore, PersistedQueryStore};
 use graph::data::graphql::effort::LoadManager;
 use graph::env::EnvVars;
 use graph::firehose::{FirehoseEndpoints, FirehoseNetworks};
@@ -10,12 +11,14 @@ use graph::log::logger;
 use graph::prelude::{IndexNodeServer as _, JsonRpcServer as _, *};
 use graph::prometheus::Registry;
 use graph::url::Url;
+use graph::util::tls::TlsSettings;
 use graph_chain_ethereum as ethereum;
 use graph_chain_near::{self as near, HeaderOnlyBlock as NearFirehoseHeaderOnlyBlock};
 use graph_chain_tendermint::{self as tendermint, EventList as TendermintFirehoseEventList};
 use graph_core::{
-    LinkResolver, MetricsRegistry, SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider,
-    SubgraphInstanceManager, SubgraphRegistrar as IpfsSubgraphRegistrar,
+    FileLinkResolver, IndexingShutdown, LinkResolver, MetricsRegistry, PoiChecker,
+    SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider, SubgraphInstanceManager,
+    SubgraphRegistrar as IpfsSubgraphRegistrar,
 };
 use graph_graphql::prelude::GraphQlRunner;
 use graph_node::chain::{
@@ -24,16 +27,20 @@ use graph_node::chain::{
 };
 use graph_node::config::Config;
 use graph_node::opt;
+use graph_node::provider_manager::ProviderManager;
 use graph_node::store_builder::StoreBuilder;
-use graph_server_http::GraphQLServer as GraphQLQueryServer;
+use graph_server_http::{ApiKeyAuth, GraphQLServer as GraphQLQueryServer};
 use graph_server_index_node::IndexNodeServer;
 use graph_server_json_rpc::JsonRpcServer;
 use graph_server_metrics::PrometheusMetricsServer;
 use graph_server_websocket::SubscriptionServer as GraphQLSubscriptionServer;
-use graph_store_postgres::{register_jobs as register_store_jobs, ChainHeadUpdateListener, Store};
+use graph_store_postgres::{
+    connection_pool::ConnectionPool, register_jobs as register_store_jobs,
+    BlockStore as DieselBlockStore, ChainHeadUpdateListener, Store,
+};
 use std::collections::BTreeMap;
 use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::atomic;
 use std::time::Duration;
 use std::{collections::HashMap, env};
@@ -42,6 +49,15 @@ use tokio::sync::mpsc;
 
 git_testament!(TESTAMENT);
 
+/// The components that the readiness endpoint reports on
+const READY_STORE: &str = "store";
+const READY_PROVIDERS: &str = "providers";
+const READY_DEPLOYMENTS: &str = "deployments";
+const READY_SHUTDOWN: &str = "shutdown";
+
+/// How often to check that the primary database is reachable
+const STORE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
 fn read_expensive_queries() -> Result<Vec<Arc<q::Document>>, std::io::Error> {
     // A file with a list of expensive queries, one query per line
     // Attempts to run these queries will return a
@@ -71,16 +87,34 @@ fn read_expensive_queries() -> Result<Vec<Arc<q::Document>>, std::io::Error> {
     Ok(queries)
 }
 
-#[tokio::main]
-async fn main() {
+fn main() {
+    let opt = opt::Opt::from_args();
+
+    // Settings from the `[mappings]` and `[query]` sections of the
+    // configuration file are passed on as environment variables. That has to
+    // happen before anything reads `ENV_VARS`, and while the process still
+    // only has one thread, i.e., before the runtime is started
+    if let Some(config) = &opt.config {
+        if let Err(e) = Config::export_env_vars(config) {
+            eprintln!("configuration error: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("failed to start the tokio runtime")
+        .block_on(run(opt))
+}
+
+async fn run(opt: opt::Opt) {
     env_logger::init();
 
     // Allow configuring fail points on debug builds. Used for integration tests.
     #[cfg(debug_assertions)]
     std::mem::forget(fail::FailScenario::setup());
 
-    let opt = opt::Opt::from_args();
-
     // Set up logger
     let logger = logger(opt.debug);
 
@@ -121,10 +155,26 @@ async fn main() {
 
     let node_id =
         NodeId::new(opt.node_id.clone()).expect("Node ID must contain only a-z, A-Z, 0-9, and '_'");
-    let query_only = config.query_only(&node_id);
+    let node_role = config.node_role(&node_id);
+    let query_only = !node_role.indexes();
+    info!(logger, "Starting node"; "node_id" => &node_id, "role" => node_role.as_str());
 
     // Obtain subgraph related command-line arguments
     let subgraph = opt.subgraph.clone();
+    if subgraph.is_some() && query_only {
+        eprintln!(
+            "configuration error: query node {} can not index --subgraph",
+            node_id
+        );
+        std::process::exit(1);
+    }
+    if opt.dev_dir.is_some() && query_only {
+        eprintln!(
+            "configuration error: query node {} can not index --dev-dir",
+            node_id
+        );
+        std::process::exit(1);
+    }
 
     // Obtain ports to use for the GraphQL server(s)
     let http_port = opt.http_port;
@@ -164,28 +214,48 @@ async fn main() {
 
     info!(logger, "Starting up");
 
-    // Optionally, identify the Elasticsearch logging configuration
-    let elastic_config = opt
-        .elasticsearch_url
-        .clone()
-        .map(|endpoint| ElasticLoggingConfig {
+    // Optionally, identify where to store component and subgraph logs;
+    // either Elasticsearch or Loki
+    let log_store: Option<Arc<dyn LogStore>> = match (&opt.elasticsearch_url, &opt.loki_url) {
+        (Some(endpoint), _) => Some(Arc::new(ElasticLogStore::new(ElasticLoggingConfig {
             endpoint: endpoint.clone(),
             username: opt.elasticsearch_user.clone(),
             password: opt.elasticsearch_password.clone(),
-        });
+        }))),
+        (None, Some(endpoint)) => Some(Arc::new(LokiLogStore::new(LokiLoggingConfig {
+            endpoint: endpoint.clone(),
+            username: opt.loki_user.clone(),
+            password: opt.loki_password.clone(),
+            tenant_id: opt.loki_tenant.clone(),
+        }))),
+        (None, None) => None,
+    };
 
     // Create a component and subgraph logger factory
-    let logger_factory = LoggerFactory::new(logger.clone(), elastic_config);
-
-    // Try to create IPFS clients for each URL specified in `--ipfs`
-    let ipfs_clients: Vec<_> = create_ipfs_clients(&logger, &opt.ipfs);
+    let logger_factory = LoggerFactory::new(logger.clone(), log_store);
 
-    // Convert the clients into a link resolver. Since we want to get past
-    // possible temporary DNS failures, make the resolver retry
-    let link_resolver = Arc::new(LinkResolver::new(
-        ipfs_clients,
-        Arc::new(EnvVars::default()),
-    ));
+    // In development mode, all subgraph files are read from the local
+    // directory instead of IPFS
+    let dev_resolver = opt.dev_dir.as_ref().map(|dir| {
+        Arc::new(FileLinkResolver::new(dir).unwrap_or_else(|e| {
+            eprintln!("configuration error: invalid --dev-dir: {}", e);
+            std::process::exit(1);
+        }))
+    });
+    let link_resolver: Arc<dyn graph::prelude::LinkResolver> = match &dev_resolver {
+        Some(resolver) => resolver.clone(),
+        None => {
+            // Try to create IPFS clients for each URL specified in `--ipfs`
+            let ipfs_clients: Vec<_> = create_ipfs_clients(&logger, &opt.ipfs);
+
+            // Convert the clients into a link resolver. Since we want to get past
+            // possible temporary DNS failures, make the resolver retry
+            Arc::new(LinkResolver::new(
+                ipfs_clients,
+                Arc::new(EnvVars::default()),
+            ))
+        }
+    };
 
     // Set up Prometheus registry
     let prometheus_registry = Arc::new(Registry::new());
@@ -193,8 +263,30 @@ async fn main() {
         logger.clone(),
         prometheus_registry.clone(),
     ));
-    let mut metrics_server =
-        PrometheusMetricsServer::new(&logger_factory, prometheus_registry.clone());
+
+    // The metrics server also answers liveness and readiness probes. It
+    // starts right away so that the node is alive while it validates
+    // providers and sets up the store, but it is only ready once all these
+    // components are
+    let readiness = Arc::new(Readiness::new());
+    readiness.register(READY_STORE);
+    readiness.register(READY_PROVIDERS);
+    readiness.register(READY_DEPLOYMENTS);
+    let tls = TlsSettings::from_env(&logger, &ENV_VARS).expect("Invalid TLS configuration");
+    let mut metrics_server = PrometheusMetricsServer::new(
+        &logger_factory,
+        prometheus_registry.clone(),
+        readiness.clone(),
+    );
+    if let Some(tls) = &tls {
+        metrics_server = metrics_server.with_tls(tls.cheap_clone());
+    }
+    graph::spawn(
+        metrics_server
+            .serve(metrics_port)
+            .expect("Failed to start metrics server")
+            .compat(),
+    );
 
     // Ethereum clients; query nodes ignore all ethereum clients and never
     // connect to them directly
@@ -233,6 +325,7 @@ async fn main() {
         let subscription_manager = store_builder.subscription_manager();
         let chain_head_update_listener = store_builder.chain_head_update_listener();
         let primary_pool = store_builder.primary_pool();
+        check_store_readiness(logger.clone(), primary_pool.clone(), readiness.clone());
 
         // To support the ethereum block ingestor, ethereum networks are referenced both by the
         // `blockchain_map` and `ethereum_chains`. Future chains should be referred to only in
@@ -264,7 +357,20 @@ async fn main() {
             .into_iter()
             .chain(near_idents)
             .chain(tendermint_idents)
-            .collect();
+            .collect::<Vec<_>>();
+
+        if query_only {
+            readiness.set_ready(READY_PROVIDERS, "query nodes do not use providers");
+        } else {
+            let networks: Vec<_> = network_identifiers
+                .iter()
+                .map(|(network, _)| network.as_str())
+                .collect();
+            readiness.set_ready(
+                READY_PROVIDERS,
+                format!("validated providers for {}", networks.join(", ")),
+            );
+        }
 
         let network_store = store_builder.network_store(network_identifiers);
 
@@ -296,6 +402,39 @@ async fn main() {
             &logger_factory,
         );
 
+        if !query_only {
+            let health_check_logger = logger.new(o!("component" => "FirehoseHealthCheck"));
+            let interval = ENV_VARS.firehose_health_check_interval;
+            if let Some(networks) = firehose_networks_by_kind.get(&BlockchainKind::Ethereum) {
+                networks.start_health_checks(health_check_logger.clone(), interval);
+            }
+            near_networks.start_health_checks(health_check_logger.clone(), interval);
+            tendermint_networks.start_health_checks(health_check_logger, interval);
+
+            if let Some(config_file) = &opt.config {
+                let mut firehose_networks = BTreeMap::new();
+                if let Some(networks) = firehose_networks_by_kind.get(&BlockchainKind::Ethereum) {
+                    firehose_networks.insert(BlockchainKind::Ethereum, networks.clone());
+                }
+                firehose_networks.insert(BlockchainKind::Near, near_networks.clone());
+                firehose_networks.insert(BlockchainKind::Tendermint, tendermint_networks.clone());
+                let provider_manager = ProviderManager::new(
+                    logger.new(o!("component" => "ProviderManager")),
+                    metrics_registry.clone(),
+                    network_store.block_store(),
+                    eth_networks.clone(),
+                    firehose_networks,
+                    &config,
+                );
+                start_provider_reloader(
+                    logger.clone(),
+                    opt.clone(),
+                    config_file.clone(),
+                    provider_manager,
+                );
+            }
+        }
+
         let blockchain_map = Arc::new(blockchain_map);
 
         let load_manager = Arc::new(LoadManager::new(
@@ -303,21 +442,44 @@ async fn main() {
             expensive_queries,
             metrics_registry.clone(),
         ));
-        let graphql_runner = Arc::new(GraphQlRunner::new(
-            &logger,
-            network_store.clone(),
-            subscription_manager.clone(),
-            load_manager,
-            metrics_registry.clone(),
-        ));
+        let graphql_runner = Arc::new(
+            GraphQlRunner::new(
+                &logger,
+                network_store.clone(),
+                subscription_manager.clone(),
+                load_manager.clone(),
+                metrics_registry.clone(),
+            )
+            .with_block_hash_resolver(blockchain_map.cheap_clone())
+            .with_deployment_limits(config.query.deployment_limits()),
+        );
+        let persisted_queries = network_store.subgraph_store().persisted_queries();
+        if let Err(e) = persisted_queries.refresh() {
+            error!(logger, "Failed to load persisted queries"; "error" => e.to_string());
+        }
+        refresh_persisted_queries(logger.clone(), persisted_queries.clone());
+        let api_key_auth = ApiKeyAuth::from_env(graphql_metrics_registry.clone())
+            .expect("Failed to load API keys");
         let mut graphql_server = GraphQLQueryServer::new(
             &logger_factory,
             graphql_metrics_registry,
             graphql_runner.clone(),
             node_id.clone(),
-        );
-        let subscription_server =
-            GraphQLSubscriptionServer::new(&logger, graphql_runner.clone(), network_store.clone());
+        )
+        .with_persisted_queries(persisted_queries.clone());
+        let query_shutdown = Arc::new(tokio::sync::Notify::new());
+        graphql_server = graphql_server.with_graceful_shutdown({
+            let query_shutdown = query_shutdown.cheap_clone();
+            async move { query_shutdown.notified().await }
+        });
+        let mut subscription_server =
+            GraphQLSubscriptionServer::new(&logger, graphql_runner.clone(), network_store.clone())
+                .with_persisted_queries(persisted_queries.clone());
+        if let Some(api_key_auth) = api_key_auth {
+            let api_key_auth = Arc::new(api_key_auth);
+            graphql_server = graphql_server.with_api_key_auth(api_key_auth.cheap_clone());
+            subscription_server = subscription_server.with_api_key_auth(api_key_auth);
+        }
 
         let mut index_node_server = IndexNodeServer::new(
             &logger_factory,
@@ -325,8 +487,19 @@ async fn main() {
             graphql_runner.clone(),
             network_store.clone(),
             link_resolver.clone(),
+            node_id.clone(),
+            node_role,
         );
 
+        if let Some(http_socket) = &opt.http_socket {
+            graphql_server = graphql_server.with_unix_socket(PathBuf::from(http_socket));
+        }
+        if let Some(tls) = &tls {
+            graphql_server = graphql_server.with_tls(tls.cheap_clone());
+            subscription_server = subscription_server.with_tls(tls.cheap_clone());
+            index_node_server = index_node_server.with_tls(tls.cheap_clone());
+        }
+
         if !opt.disable_block_ingestor {
             if ethereum_chains.len() > 0 {
                 let block_polling_interval = Duration::from_millis(opt.ethereum_polling_interval);
@@ -334,6 +507,7 @@ async fn main() {
                 start_block_ingestor(
                     &logger,
                     &logger_factory,
+                    &network_store,
                     block_polling_interval,
                     ethereum_chains,
                 );
@@ -369,7 +543,26 @@ async fn main() {
             metrics_registry.clone(),
             link_resolver.clone(),
             static_filters,
+            config.mappings.handler_timeouts(),
+            config.mappings.retry_policies(),
         );
+        let indexing_shutdown = subgraph_instance_manager.shutdown_handle();
+        if node_role.indexes() {
+            if let Some(interval) = ENV_VARS.load_report_interval {
+                subgraph_instance_manager.start_load_reporter(node_id.clone(), interval);
+            }
+            if !ENV_VARS.poi_check_peers.is_empty() {
+                PoiChecker::new(
+                    &logger_factory,
+                    network_store.clone(),
+                    node_id.clone(),
+                    ENV_VARS.poi_check_peers.clone(),
+                    ENV_VARS.poi_check_pause,
+                    metrics_registry.clone(),
+                )
+                .start(ENV_VARS.poi_check_interval);
+            }
+        }
 
         // Create IPFS-based subgraph provider
         let subgraph_provider = IpfsSubgraphAssignmentProvider::new(
@@ -378,8 +571,12 @@ async fn main() {
             subgraph_instance_manager,
         );
 
-        // Check version switching mode environment variable
-        let version_switching_mode = ENV_VARS.subgraph_version_switching_mode;
+        // Check version switching mode environment variable. Development
+        // mode needs to replace the running deployment right away
+        let version_switching_mode = match dev_resolver {
+            Some(_) => SubgraphVersionSwitchingMode::Instant,
+            None => ENV_VARS.subgraph_version_switching_mode,
+        };
 
         // Create named subgraph provider for resolving subgraph name->ID mappings
         let subgraph_registrar = Arc::new(IpfsSubgraphRegistrar::new(
@@ -392,12 +589,21 @@ async fn main() {
             node_id.clone(),
             version_switching_mode,
         ));
-        graph::spawn(
-            subgraph_registrar
-                .start()
-                .map_err(|e| panic!("failed to initialize subgraph provider {}", e))
-                .compat(),
-        );
+        // Query nodes never start the subgraphs assigned to them
+        if node_role.indexes() {
+            let readiness = readiness.clone();
+            graph::spawn(
+                subgraph_registrar
+                    .start()
+                    .map(move |()| {
+                        readiness.set_ready(READY_DEPLOYMENTS, "started assigned deployments")
+                    })
+                    .map_err(|e| panic!("failed to initialize subgraph provider {}", e))
+                    .compat(),
+            );
+        } else {
+            readiness.set_ready(READY_DEPLOYMENTS, "query nodes do not index deployments");
+        }
 
         // Start admin JSON-RPC server.
         let json_rpc_server = JsonRpcServer::serve(
@@ -405,7 +611,10 @@ async fn main() {
             http_port,
             ws_port,
             subgraph_registrar.clone(),
+            persisted_queries,
+            load_manager,
             node_id.clone(),
+            opt.admin_socket.clone().map(PathBuf::from),
             logger.clone(),
         )
         .expect("failed to start JSON-RPC admin server");
@@ -413,6 +622,20 @@ async fn main() {
         // Let the server run forever.
         std::mem::forget(json_rpc_server);
 
+        // Run the subgraph from --dev-dir and redeploy it on changes
+        if let Some(resolver) = dev_resolver {
+            let name = SubgraphName::new(opt.dev_name.clone())
+                .expect("Subgraph name must contain only a-z, A-Z, 0-9, '-' and '_'");
+            graph::spawn(graph_node::dev::watch(
+                logger.clone(),
+                resolver,
+                subgraph_registrar.clone(),
+                network_store.subgraph_store(),
+                name,
+                node_id.clone(),
+            ));
+        }
+
         // Add the CLI subgraph with a REST request to the admin server.
         if let Some(subgraph) = subgraph {
             let (name, hash) = if subgraph.contains(':') {
@@ -454,6 +677,8 @@ async fn main() {
                             node_id,
                             debug_fork,
                             start_block,
+                            false,
+                            false,
                         )
                         .await
                 }
@@ -461,16 +686,22 @@ async fn main() {
             );
         }
 
-        // Serve GraphQL queries over HTTP
-        graph::spawn(
-            graphql_server
-                .serve(http_port, ws_port)
-                .expect("Failed to start GraphQL query server")
-                .compat(),
-        );
+        let query_server = if node_role.serves_queries() {
+            // Serve GraphQL queries over HTTP
+            let query_server = graph::spawn(
+                graphql_server
+                    .serve(http_port, ws_port)
+                    .expect("Failed to start GraphQL query server")
+                    .compat(),
+            );
 
-        // Serve GraphQL subscriptions over WebSockets
-        graph::spawn(subscription_server.serve(ws_port));
+            // Serve GraphQL subscriptions over WebSockets
+            graph::spawn(subscription_server.serve(ws_port));
+            Some(query_server)
+        } else {
+            info!(logger, "Not serving GraphQL queries on index node");
+            None
+        };
 
         // Run the index node server
         graph::spawn(
@@ -480,11 +711,12 @@ async fn main() {
                 .compat(),
         );
 
-        graph::spawn(
-            metrics_server
-                .serve(metrics_port)
-                .expect("Failed to start metrics server")
-                .compat(),
+        start_shutdown_handler(
+            logger.clone(),
+            readiness,
+            query_shutdown,
+            query_server,
+            indexing_shutdown,
         );
     };
 
@@ -527,6 +759,118 @@ async fn main() {
     futures::future::pending::<()>().await;
 }
 
+/// Reload the provider configuration from `config_file` whenever the
+/// process receives a `SIGHUP`, and add or remove providers accordingly
+#[cfg(unix)]
+fn start_provider_reloader(
+    logger: Logger,
+    opt: opt::Opt,
+    config_file: String,
+    provider_manager: ProviderManager,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    graph::spawn(async move {
+        let mut hangups = match signal(SignalKind::hangup()) {
+            Ok(hangups) => hangups,
+            Err(e) => {
+                error!(logger, "Failed to listen for SIGHUP, providers can not be reloaded";
+                               "error" => e.to_string());
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            info!(logger, "Received SIGHUP, reloading providers"; "config" => &config_file);
+            let config = match Config::load(&logger, &opt.clone().into()) {
+                Ok(config) => config,
+                Err(e) => {
+                    error!(logger, "Invalid configuration, keeping current providers";
+                                   "error" => format!("{:#}", e));
+                    continue;
+                }
+            };
+            if let Err(e) = provider_manager.reload(&config).await {
+                error!(logger, "Failed to reload providers"; "error" => format!("{:#}", e));
+            }
+        }
+    });
+}
+
+/// When the process receives a `SIGTERM`, stop accepting GraphQL
+/// connections and finish in-flight queries, and stop all deployments
+/// once they have written the block they are working on. Exit once both
+/// are done or `GRAPH_SHUTDOWN_TIMEOUT` has passed
+#[cfg(unix)]
+fn start_shutdown_handler(
+    logger: Logger,
+    readiness: Arc<Readiness>,
+    query_shutdown: Arc<tokio::sync::Notify>,
+    query_server: Option<tokio::task::JoinHandle<Result<(), ()>>>,
+    indexing_shutdown: IndexingShutdown,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    graph::spawn(async move {
+        let mut terminations = match signal(SignalKind::terminate()) {
+            Ok(terminations) => terminations,
+            Err(e) => {
+                error!(logger, "Failed to listen for SIGTERM, shutdown will not be graceful";
+                               "error" => e.to_string());
+                return;
+            }
+        };
+        if terminations.recv().await.is_none() {
+            return;
+        }
+
+        let timeout = ENV_VARS.shutdown_timeout;
+        info!(logger, "Received SIGTERM, shutting down";
+                      "timeout_s" => timeout.as_secs());
+        readiness.set_not_ready(READY_SHUTDOWN, "node is shutting down");
+
+        let drain_queries = async {
+            query_shutdown.notify_one();
+            if let Some(query_server) = query_server {
+                if tokio::time::timeout(timeout, query_server).await.is_err() {
+                    warn!(logger, "Timed out waiting for in-flight GraphQL queries");
+                }
+            }
+        };
+        let stop_indexing = async {
+            let running = indexing_shutdown.shutdown(timeout).await;
+            if running > 0 {
+                warn!(logger, "Timed out waiting for deployments to finish their block";
+                              "running" => running);
+            }
+        };
+        futures::join!(drain_queries, stop_indexing);
+
+        info!(logger, "Shutdown complete");
+        std::process::exit(0);
+    });
+}
+
+#[cfg(not(unix))]
+fn start_shutdown_handler(
+    logger: Logger,
+    _readiness: Arc<Readiness>,
+    _query_shutdown: Arc<tokio::sync::Notify>,
+    _query_server: Option<tokio::task::JoinHandle<Result<(), ()>>>,
+    _indexing_shutdown: IndexingShutdown,
+) {
+    warn!(logger, "Graceful shutdown is only supported on Unix");
+}
+
+#[cfg(not(unix))]
+fn start_provider_reloader(
+    logger: Logger,
+    _opt: opt::Opt,
+    _config_file: String,
+    _provider_manager: ProviderManager,
+) {
+    warn!(logger, "Reloading providers is only supported on Unix");
+}
+
 /// Return the hashmap of ethereum chains and also add them to `blockchain_map`.
 fn ethereum_networks_as_chains(
     blockchain_map: &mut BlockchainMap,
@@ -569,6 +913,7 @@ fn ethereum_networks_as_chains(
                 registry.clone(),
                 chain_store.cheap_clone(),
                 chain_store,
+                store.subgraph_store(),
                 firehose_endpoints.map_or_else(|| FirehoseEndpoints::new(), |v| v.clone()),
                 eth_adapters.clone(),
                 chain_head_update_listener.clone(),
@@ -685,6 +1030,7 @@ fn near_networks_as_chains(
 fn start_block_ingestor(
     logger: &Logger,
     logger_factory: &LoggerFactory,
+    store: &Store,
     block_polling_interval: Duration,
     chains: HashMap<String, Arc<ethereum::Chain>>,
 ) {
@@ -715,32 +1061,43 @@ fn start_block_ingestor(
                 "network_name" => &network_name
             );
 
-            let eth_adapter = chain.cheapest_adapter();
-                let logger = logger_factory
-                    .component_logger(
-                        "BlockIngestor",
-                        Some(ComponentLoggerConfig {
-                            elastic: Some(ElasticComponentLoggerConfig {
-                                index: String::from("block-ingestor-logs"),
-                            }),
-                        }),
-                    )
-                    .new(o!("provider" => eth_adapter.provider().to_string()));
+            let logger = logger_factory.component_logger(
+                "BlockIngestor",
+                Some(ComponentLoggerConfig {
+                    elastic: Some(ElasticComponentLoggerConfig {
+                        index: String::from("block-ingestor-logs"),
+                    }),
+                }),
+            );
 
-            // The block ingestor must be configured to keep at least REORG_THRESHOLD ancestors,
-            // because the json-rpc BlockStream expects blocks after the reorg threshold to be
-            // present in the DB.
-            let block_ingestor = EthereumBlockIngestor::new(
-                logger,
-                ethereum::ENV_VARS.reorg_threshold,
-                eth_adapter,
-                chain.chain_store(),
-                block_polling_interval,
-            )
-            .expect("failed to create Ethereum block ingestor");
+            let chain_store = chain.chain_store();
+            let make_ingestor = {
+                let logger = logger.clone();
+                let chain = chain.clone();
+                move || {
+                    // The block ingestor must be configured to keep at least REORG_THRESHOLD ancestors,
+                    // because the json-rpc BlockStream expects blocks after the reorg threshold to be
+                    // present in the DB.
+                    let block_ingestor = EthereumBlockIngestor::new(
+                        logger.clone(),
+                        ethereum::ENV_VARS.reorg_threshold,
+                        chain.eth_adapters(),
+                        chain_store.clone(),
+                        block_polling_interval,
+                        chain.head_quorum(),
+                    )
+                    .expect("failed to create Ethereum block ingestor");
+                    block_ingestor.into_polling_stream()
+                }
+            };
 
             // Run the Ethereum block ingestor in the background
-            graph::spawn(block_ingestor.into_polling_stream());
+            graph::spawn(run_with_ingestor_lease(
+                logger,
+                store.block_store(),
+                network_name.clone(),
+                make_ingestor,
+            ));
         });
 }
 
@@ -779,21 +1136,24 @@ fn start_firehose_block_ingestor<C, M>(
                 "network_name" => &network_name
             );
 
-            let endpoint = chain
-                .firehose_endpoints
-                .random()
-                .expect("One Firehose endpoint should exist at that execution point");
-
             match store.block_store().chain_store(network_name.as_ref()) {
                 Some(s) => {
-                    let block_ingestor = FirehoseBlockIngestor::<M>::new(
-                        s,
-                        endpoint.clone(),
-                        logger.new(o!("component" => "FirehoseBlockIngestor", "provider" => endpoint.provider.clone())),
-                    );
+                    let logger = logger.new(o!("component" => "FirehoseBlockIngestor"));
+                    let make_ingestor = {
+                        let logger = logger.clone();
+                        let endpoints = chain.firehose_endpoints.clone();
+                        move || {
+                            FirehoseBlockIngestor::<M>::new(s.clone(), endpoints.clone(), logger.clone()).run()
+                        }
+                    };
 
                     // Run the Firehose block ingestor in the background
-                    graph::spawn(block_ingestor.run());
+                    graph::spawn(run_with_ingestor_lease(
+                        logger,
+                        store.block_store(),
+                        network_name.clone(),
+                        make_ingestor,
+                    ));
                 },
                 None => {
                     error!(logger, "Not starting firehose block ingestor (no chain store available)"; "network_name" => &network_name);
@@ -801,3 +1161,106 @@ fn start_firehose_block_ingestor<C, M>(
             }
         });
 }
+
+/// Run the block ingestor that `make_ingestor` creates for `network_name`
+/// only while this node holds the ingestor lease for the network so that
+/// only one node ingests blocks for it. Nodes without the lease keep trying
+/// to get it, and take over when the node holding it dies
+async fn run_with_ingestor_lease<F, Fut>(
+    logger: Logger,
+    block_store: Arc<DieselBlockStore>,
+    network_name: String,
+    make_ingestor: F,
+) where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = ()> + Send + 'static,
+{
+    let interval = ENV_VARS.ingestor_lease_interval;
+    // The node holding the lease checks it more often than other nodes try
+    // to take it over so that it stops ingesting before another node starts
+    let check_interval = interval / 5;
+    loop {
+        // Getting and checking the lease block on the database
+        let lease = {
+            let block_store = block_store.cheap_clone();
+            let network_name = network_name.clone();
+            graph::spawn_blocking_allow_panic(move || block_store.try_ingestor_lease(&network_name))
+                .await
+                .map_err(Error::from)
+                .and_then(|lease| lease.map_err(Error::from))
+        };
+        let mut lease = match lease {
+            Ok(Some(lease)) => lease,
+            Ok(None) => {
+                debug!(logger, "Another node ingests blocks for network"; "network_name" => &network_name);
+                tokio::time::sleep(interval).await;
+                continue;
+            }
+            Err(e) => {
+                warn!(logger, "Failed to get block ingestor lease";
+                              "network_name" => &network_name,
+                              "error" => e.to_string());
+                tokio::time::sleep(interval).await;
+                continue;
+            }
+        };
+
+        info!(logger, "Got block ingestor lease, starting block ingestor"; "network_name" => &network_name);
+        let ingestor = graph::spawn(make_ingestor());
+        loop {
+            tokio::time::sleep(check_interval).await;
+            // A check that takes too long counts as failed since the
+            // connection that holds the lock might be gone
+            let check = graph::spawn_blocking_allow_panic(move || {
+                let held = lease.is_held();
+                (lease, held)
+            });
+            match tokio::time::timeout(check_interval, check).await {
+                Ok(Ok((held_lease, true))) => lease = held_lease,
+                _ => break,
+            }
+        }
+
+        // Another node may take over as soon as we lost the lease
+        ingestor.abort();
+        warn!(logger, "Lost block ingestor lease, stopping block ingestor"; "network_name" => &network_name);
+    }
+}
+
+/// Periodically reload the persisted queries so that queries that were
+/// registered or removed through other nodes are picked up
+fn refresh_persisted_queries(logger: Logger, persisted_queries: Arc<dyn PersistedQueryStore>) {
+    let interval = ENV_VARS.graphql.persisted_queries_refresh_interval;
+    graph::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+            let persisted_queries = persisted_queries.cheap_clone();
+            match graph::spawn_blocking_allow_panic(move || persisted_queries.refresh()).await {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) => {
+                    warn!(logger, "Failed to refresh persisted queries"; "error" => e.to_string())
+                }
+                Err(e) => {
+                    warn!(logger, "Failed to refresh persisted queries"; "error" => e.to_string())
+                }
+            }
+        }
+    });
+}
+
+/// Periodically check that the primary database is reachable and report
+/// the result as the readiness of the store
+fn check_store_readiness(logger: Logger, primary_pool: ConnectionPool, readiness: Arc<Readiness>) {
+    std::thread::spawn(move || loop {
+        if primary_pool.check() {
+            readiness.set_ready(READY_STORE, "primary database is reachable");
+        } else {
+            warn!(
+                logger,
+                "Primary database is not reachable, node is not ready"
+            );
+            readiness.set_not_ready(READY_STORE, "primary database is not reachable");
+        }
+        std::thread::sleep(STORE_CHECK_INTERVAL);
+    });
+}
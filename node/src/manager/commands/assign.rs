@@ -1,5 +1,11 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use graph::data::subgraph::schema::DeploymentPriority;
 use graph::prelude::{anyhow::anyhow, Error, NodeId};
-use graph_store_postgres::{command_support::catalog, connection_pool::ConnectionPool};
+use graph_store_postgres::{
+    command_support::catalog, connection_pool::ConnectionPool, SubgraphStore,
+};
 
 use crate::manager::deployment::DeploymentSearch;
 
@@ -50,3 +56,60 @@ pub fn reassign(
 
     Ok(())
 }
+
+pub fn priority(
+    primary: ConnectionPool,
+    search: &DeploymentSearch,
+    priority: String,
+) -> Result<(), Error> {
+    let priority = match priority.as_str() {
+        "clear" => None,
+        _ => Some(DeploymentPriority::from_str(&priority)?),
+    };
+    let locator = search.locate_unique(&primary)?;
+
+    let conn = primary.get()?;
+    let conn = catalog::Connection::new(conn);
+
+    let site = conn
+        .locate_site(locator.clone())?
+        .ok_or_else(|| anyhow!("failed to locate site for {locator}"))?;
+    if !conn.set_priority(&site, priority)? {
+        return Err(anyhow!("deployment {locator} is not assigned to any node"));
+    }
+    match priority {
+        Some(priority) => println!("set priority of {locator} to {priority}"),
+        None => println!("cleared priority of {locator}"),
+    }
+    println!("the priority takes effect the next time the deployment is started");
+
+    Ok(())
+}
+
+pub fn rebalance(store: Arc<SubgraphStore>, max_moves: usize, apply: bool) -> Result<(), Error> {
+    let moves = store.rebalance(max_moves, apply)?;
+    if moves.is_empty() {
+        println!("the load is balanced, no deployments need to move");
+        return Ok(());
+    }
+
+    println!(
+        "{:<46} | {:<20} | {:<20} | {:>6}",
+        "deployment", "from", "to", "load"
+    );
+    println!("{:-<46}-+-{:-<20}-+-{:-<20}-+-{:->6}", "", "", "", "");
+    for mv in &moves {
+        println!(
+            "{:<46} | {:<20} | {:<20} | {:>5.1}%",
+            mv.hash.to_string(),
+            mv.from.to_string(),
+            mv.to.to_string(),
+            mv.share * 100.0
+        );
+    }
+    if !apply {
+        println!("\nthis was a dry run; use `--apply` to reassign the deployments");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use graph::prelude::{anyhow::anyhow, BlockNumber, Error, NodeId, SubgraphName};
+use graph::url::Url;
+use graph_store_postgres::{connection_pool::ConnectionPool, ExportFormat, Shard, SubgraphStore};
+
+use crate::manager::deployment::DeploymentSearch;
+
+pub fn backup(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    deployment: DeploymentSearch,
+    dir: PathBuf,
+) -> Result<(), Error> {
+    let deployment = deployment.locate_unique(&primary)?;
+
+    println!("Backing up {} into {}", deployment, dir.display());
+    let metadata = store.backup(&deployment, &dir)?;
+
+    for (table, rows) in &metadata.tables {
+        println!("{:>30} | {:>10} rows", table, rows);
+    }
+    println!(
+        "{:>30} | {:>10} rows",
+        "dynamic data sources", metadata.dynamic_data_sources
+    );
+    match &metadata.head {
+        Some(head) => println!("Backed up {} at block {}", deployment, head.number),
+        None => println!(
+            "Backed up {}, which has not processed any blocks",
+            deployment
+        ),
+    }
+    Ok(())
+}
+
+pub fn export(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    deployment: DeploymentSearch,
+    block: Option<BlockNumber>,
+    dir: String,
+) -> Result<(), Error> {
+    let dir = local_dir(&dir)?;
+    let deployment = deployment.locate_unique(&primary)?;
+
+    println!("Exporting {} into {}", deployment, dir.display());
+    let (block, counts) = store.export(&deployment, block, &dir)?;
+
+    for (entity_type, count) in &counts {
+        println!("{:>30} | {:>10} entities", entity_type, count);
+    }
+    println!("Exported {} at block {}", deployment, block);
+    Ok(())
+}
+
+/// The local directory for the export destination `dest`, which is either
+/// a path or a `file:` URL. Snapshots can not be written to object stores
+/// like S3 or GCS; catch attempts to do that before we create a directory
+/// called `s3:` or similar
+fn local_dir(dest: &str) -> Result<PathBuf, Error> {
+    match Url::parse(dest) {
+        Ok(url) if url.scheme() == "file" => url
+            .to_file_path()
+            .map_err(|()| anyhow!("`{}` is not a local path", dest)),
+        // Windows paths like `C:\dir` parse as URLs with a one letter scheme
+        Ok(url) if url.scheme().len() > 1 => Err(anyhow!(
+            "can not export to {}: exporting to `{}` URLs is not implemented, \
+             snapshots can only be written to a local directory",
+            dest,
+            url.scheme()
+        )),
+        _ => Ok(PathBuf::from(dest)),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn export_table(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    deployment: DeploymentSearch,
+    entity: String,
+    from: Option<BlockNumber>,
+    to: Option<BlockNumber>,
+    format: String,
+    output: Option<PathBuf>,
+) -> Result<(), Error> {
+    let format: ExportFormat = format.parse()?;
+    let deployment = deployment.locate_unique(&primary)?;
+
+    let mut writer: Box<dyn Write> = match &output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    };
+    let count = store.export_table(&deployment, &entity, from, to, format, &mut writer)?;
+    // Don't mix the summary into the data when writing to stdout
+    if let Some(path) = output {
+        println!(
+            "Exported {} rows of {} in {} into {}",
+            count,
+            entity,
+            deployment,
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+pub fn restore(
+    store: Arc<SubgraphStore>,
+    dir: PathBuf,
+    shard: String,
+    node: String,
+    name: Option<String>,
+) -> Result<(), Error> {
+    let shard = Shard::new(shard)?;
+    let node = NodeId::new(node.clone()).map_err(|()| anyhow!("invalid node id `{}`", node))?;
+    let name = name
+        .map(|name| {
+            SubgraphName::new(name.clone())
+                .map_err(|()| anyhow!("invalid subgraph name `{}`", name))
+        })
+        .transpose()?;
+
+    println!(
+        "Restoring the backup in {} into shard {}",
+        dir.display(),
+        shard
+    );
+    let deployment = store.restore(&dir, shard, node.clone(), name)?;
+    println!("Restored {} and assigned it to {}", deployment, node);
+    Ok(())
+}
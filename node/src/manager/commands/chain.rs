@@ -129,3 +129,26 @@ pub fn remove(primary: ConnectionPool, store: Arc<BlockStore>, name: String) ->
 
     Ok(())
 }
+
+pub fn prune_call_cache(
+    store: Arc<BlockStore>,
+    chain_name: String,
+    before: BlockNumber,
+) -> Result<(), Error> {
+    let chain_store = store
+        .chain_store(&chain_name)
+        .ok_or_else(|| anyhow!("unknown chain: {}", chain_name))?;
+    if !chain_store.call_cache_is_prunable() {
+        bail!(
+            "the call cache of chain {} is shared with other chains and can not be pruned",
+            chain_name
+        );
+    }
+
+    let removed = chain_store.prune_call_cache(before)?;
+    println!(
+        "removed {} calls made before block {} from the call cache of {}",
+        removed, before, chain_name
+    );
+    Ok(())
+}
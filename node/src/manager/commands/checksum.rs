@@ -0,0 +1,68 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use graph::prelude::{anyhow::anyhow, serde_json, BlockNumber, Error};
+use graph_store_postgres::{connection_pool::ConnectionPool, Checksums, SubgraphStore};
+
+use crate::manager::deployment::DeploymentSearch;
+
+pub fn run(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    deployment: DeploymentSearch,
+    block: Option<BlockNumber>,
+    output: Option<PathBuf>,
+    compare: Option<PathBuf>,
+) -> Result<(), Error> {
+    let deployment = deployment.locate_unique(&primary)?;
+
+    // Compute the checksums at the same block as the ones we compare with
+    let expected = compare
+        .map(|path| -> Result<Checksums, Error> {
+            let reader = BufReader::new(File::open(&path)?);
+            serde_json::from_reader(reader)
+                .map_err(|e| anyhow!("invalid checksum file {}: {}", path.display(), e))
+        })
+        .transpose()?;
+    let block = match (&expected, block) {
+        (Some(expected), None) => Some(expected.block),
+        (_, block) => block,
+    };
+
+    let checksums = store.checksums(&deployment, block)?;
+
+    for (entity_type, checksum) in &checksums.tables {
+        println!(
+            "{:>30} | {:>10} entities | {}",
+            entity_type, checksum.entities, checksum.checksum
+        );
+    }
+    println!("Checksums for {} at block {}", deployment, checksums.block);
+
+    if let Some(path) = output {
+        let writer = BufWriter::new(File::create(&path)?);
+        serde_json::to_writer_pretty(writer, &checksums)?;
+        println!("Wrote checksums to {}", path.display());
+    }
+
+    if let Some(expected) = expected {
+        let diffs = checksums.diff(&expected);
+        if !diffs.is_empty() {
+            for diff in &diffs {
+                println!("  {}", diff);
+            }
+            return Err(anyhow!(
+                "the data of {} differs from that of {}",
+                checksums.deployment,
+                expected.deployment
+            ));
+        }
+        println!(
+            "The data of {} is the same as that of {}",
+            checksums.deployment, expected.deployment
+        );
+    }
+    Ok(())
+}
@@ -145,6 +145,44 @@ pub fn activate(store: Arc<SubgraphStore>, deployment: String, shard: String) ->
     Ok(())
 }
 
+pub fn switch(
+    store: Arc<SubgraphStore>,
+    deployment: String,
+    shard: String,
+    node: Option<String>,
+    remove_source: bool,
+) -> Result<(), Error> {
+    let shard = Shard::new(shard)?;
+    let deployment =
+        DeploymentHash::new(deployment).map_err(|s| anyhow!("illegal deployment hash `{}`", s))?;
+    let dst = store
+        .locate_in_shard(&deployment, shard.clone())?
+        .ok_or_else(|| {
+            anyhow!(
+                "could not find a copy for {} in shard {}",
+                deployment,
+                shard
+            )
+        })?;
+    let node = node
+        .map(|node| NodeId::new(node.clone()).map_err(|()| anyhow!("invalid node id `{}`", node)))
+        .transpose()?;
+
+    let src = store.switch_to_copy(&dst, node)?;
+    println!("switched from {} to copy {}", src, dst);
+
+    if remove_source {
+        store.remove_deployment(src.id.into())?;
+        println!("removed {}", src);
+    } else {
+        println!(
+            "{} is now unused and will be removed by `graphman unused record` and `graphman unused remove`",
+            src
+        );
+    }
+    Ok(())
+}
+
 pub fn list(pools: HashMap<Shard, ConnectionPool>) -> Result<(), Error> {
     use catalog::active_copies as ac;
     use catalog::deployment_schemas as ds;
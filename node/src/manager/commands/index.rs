@@ -68,3 +68,116 @@ pub async fn drop(
     println!("Dropped index {index_name}");
     Ok(())
 }
+
+pub async fn deferred(
+    store: Arc<SubgraphStore>,
+    pool: ConnectionPool,
+    search: DeploymentSearch,
+) -> Result<(), anyhow::Error> {
+    let deployment_locator = search.locate_unique(&pool)?;
+    let status = store.deferred_index_status(&deployment_locator).await?;
+
+    if !status.deferred {
+        println!("All attribute indexes of {deployment_locator} have been built");
+        return Ok(());
+    }
+    println!(
+        "{} of {} attribute indexes built; the deployment is {}",
+        status.total - status.missing.len(),
+        status.total,
+        if status.synced {
+            "synced and the remaining indexes will be built in the background"
+        } else {
+            "still syncing"
+        }
+    );
+    for index in &status.missing {
+        match status.builds.iter().find(|build| &build.name == index) {
+            Some(build) if build.blocks_total > 0 => println!(
+                "{index}: {} ({:.1}%)",
+                build.phase,
+                build.blocks_done as f64 * 100.0 / build.blocks_total as f64
+            ),
+            Some(build) => println!("{index}: {}", build.phase),
+            None => println!("{index}: missing"),
+        }
+    }
+    Ok(())
+}
+
+pub async fn build_deferred(
+    store: Arc<SubgraphStore>,
+    pool: ConnectionPool,
+    search: DeploymentSearch,
+) -> Result<(), anyhow::Error> {
+    let deployment_locator = search.locate_unique(&pool)?;
+    println!("Building deferred attribute indexes. Please wait.");
+    if store
+        .build_deferred_indexes_for(&deployment_locator)
+        .await?
+    {
+        println!("All attribute indexes of {deployment_locator} have been built");
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "some indexes could not be built; please retry"
+        ))
+    }
+}
+
+pub async fn suggest(
+    store: Arc<SubgraphStore>,
+    pool: ConnectionPool,
+    search: DeploymentSearch,
+    min_uses: i64,
+    create: bool,
+) -> Result<(), anyhow::Error> {
+    let deployment_locator = search.locate_unique(&pool)?;
+    let suggestions = store
+        .index_suggestions(&deployment_locator, min_uses)
+        .await?;
+    if suggestions.is_empty() {
+        println!("No missing indexes for {deployment_locator}");
+        return Ok(());
+    }
+
+    println!(
+        "{:<30} | {:<30} | {:>10} | {:>10}",
+        "entity", "attribute", "filters", "orders"
+    );
+    println!("{:-<30}-+-{:-<30}-+-{:->10}-+-{:->10}", "", "", "", "");
+    for suggestion in &suggestions {
+        println!(
+            "{:<30} | {:<30} | {:>10} | {:>10}",
+            suggestion.entity_type,
+            suggestion.attribute,
+            suggestion.usage.filters,
+            suggestion.usage.orders
+        );
+    }
+
+    if !create {
+        return Ok(());
+    }
+    for suggestion in &suggestions {
+        println!(
+            "Creating index on {}.{}. Please wait.",
+            suggestion.entity_type, suggestion.attribute
+        );
+        match store
+            .create_suggested_index(
+                &deployment_locator,
+                &suggestion.entity_type,
+                &suggestion.attribute,
+            )
+            .await
+        {
+            Ok(index_name) => println!("Created index {index_name}"),
+            Err(StoreError::Canceled) => {
+                eprintln!("Index creation attempt failed. Please retry.")
+            }
+            Err(other) => return Err(anyhow::anyhow!(other)),
+        }
+    }
+    Ok(())
+}
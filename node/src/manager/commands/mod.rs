@@ -1,15 +1,24 @@
 pub mod assign;
+pub mod backup;
 pub mod chain;
+pub mod checksum;
 pub mod config;
 pub mod copy;
 pub mod create;
 pub mod index;
 pub mod info;
 pub mod listen;
+pub mod poi;
+pub mod prune;
 pub mod query;
 pub mod remove;
 pub mod rewind;
 pub mod run;
+pub mod run_block;
+pub mod skip;
 pub mod stats;
+pub mod test;
+pub mod triggers;
 pub mod txn_speed;
+pub mod unfail;
 pub mod unused_deployments;
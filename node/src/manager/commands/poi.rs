@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+
+use graph::prelude::{
+    anyhow::{anyhow, bail},
+    reqwest, serde_json, BlockNumber, Error,
+};
+
+/// The most blocks that the index-node API returns proofs of indexing for
+/// in one call
+const MAX_BLOCKS: BlockNumber = 1000;
+
+const POIS_QUERY: &str = "query pois($subgraph: String!, $from: Int!, $to: Int!, $indexer: Bytes) {
+  proofsOfIndexing(subgraph: $subgraph, fromBlock: $from, toBlock: $to, indexer: $indexer) {
+    block { number }
+    proofOfIndexing
+  }
+}";
+
+struct IndexNode {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl IndexNode {
+    /// The proofs of indexing by block number that this index node reports
+    /// for the blocks from `from` to `to`
+    async fn pois(
+        &self,
+        deployment: &str,
+        indexer: &Option<String>,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<BTreeMap<BlockNumber, String>, Error> {
+        let body = serde_json::json!({
+            "query": POIS_QUERY,
+            "variables": {
+                "subgraph": deployment,
+                "from": from,
+                "to": to,
+                "indexer": indexer,
+            }
+        });
+        let response: serde_json::Value = self
+            .client
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if let Some(errors) = response.get("errors") {
+            bail!("{} returned errors: {}", self.url, errors);
+        }
+        let pois = response
+            .pointer("/data/proofsOfIndexing")
+            .and_then(|pois| pois.as_array())
+            .ok_or_else(|| anyhow!("{} returned an invalid response: {}", self.url, response))?;
+        pois.iter()
+            .map(|poi| {
+                let number = poi.pointer("/block/number").and_then(|n| n.as_i64());
+                let poi = poi.get("proofOfIndexing").and_then(|poi| poi.as_str());
+                match (number, poi) {
+                    (Some(number), Some(poi)) => Ok((number as BlockNumber, poi.to_string())),
+                    _ => Err(anyhow!(
+                        "{} returned an invalid proof of indexing",
+                        self.url
+                    )),
+                }
+            })
+            .collect()
+    }
+}
+
+/// The blocks from `from` to `to` for which both index nodes know the
+/// proof of indexing, and whether their proofs of indexing are the same
+async fn compare(
+    nodes: &[IndexNode; 2],
+    deployment: &str,
+    indexer: &Option<String>,
+    from: BlockNumber,
+    to: BlockNumber,
+) -> Result<Vec<(BlockNumber, bool)>, Error> {
+    let first = nodes[0].pois(deployment, indexer, from, to).await?;
+    let second = nodes[1].pois(deployment, indexer, from, to).await?;
+    Ok(first
+        .into_iter()
+        .filter_map(|(number, poi)| second.get(&number).map(|other| (number, &poi == other)))
+        .collect())
+}
+
+pub async fn diff(
+    deployment: String,
+    first: String,
+    second: String,
+    from: BlockNumber,
+    to: BlockNumber,
+    indexer: Option<String>,
+) -> Result<(), Error> {
+    if from < 0 || to < from {
+        bail!("invalid block range {} to {}", from, to);
+    }
+    let client = reqwest::Client::new();
+    let nodes = [
+        IndexNode {
+            client: client.clone(),
+            url: first,
+        },
+        IndexNode {
+            client,
+            url: second,
+        },
+    ];
+
+    // Proofs of indexing are cumulative: once they differ at a block, they
+    // differ at all later blocks, too, and if they are the same at a block,
+    // they are the same at all earlier blocks. We keep the invariant that
+    // the first block at which they differ, if it is earlier than `found`,
+    // lies between `lo` and `hi`, and look at chunks of blocks in the
+    // middle of that range. If a chunk contains no block that both nodes
+    // know, we can't tell which half to continue with and fall back to
+    // scanning from `lo`
+    let (mut lo, mut hi) = (from, to);
+    let mut found = None;
+    let mut scan = false;
+    while lo <= hi {
+        let start = if scan || hi - lo < MAX_BLOCKS {
+            lo
+        } else {
+            lo + (hi - lo) / 2
+        };
+        let end = (start + MAX_BLOCKS - 1).min(hi);
+        let blocks = compare(&nodes, &deployment, &indexer, start, end).await?;
+
+        match blocks.iter().position(|(_, same)| !same) {
+            Some(pos) => {
+                found = Some(blocks[pos].0);
+                hi = blocks[pos].0 - 1;
+                if pos > 0 {
+                    lo = lo.max(blocks[pos - 1].0 + 1);
+                }
+            }
+            None => match blocks.last() {
+                Some((number, _)) => lo = number + 1,
+                None if start == lo => lo = end + 1,
+                None => scan = true,
+            },
+        }
+    }
+
+    match found {
+        Some(block) => println!(
+            "The proofs of indexing for {} first differ at block {}",
+            deployment, block
+        ),
+        None => println!(
+            "The proofs of indexing for {} are the same for all blocks from {} to {} that both nodes know",
+            deployment, from, to
+        ),
+    }
+    Ok(())
+}
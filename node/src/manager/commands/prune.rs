@@ -0,0 +1,126 @@
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Instant;
+
+use graph::components::store::PruneReporter;
+use graph::prelude::{anyhow, BlockNumber, ENV_VARS};
+use graph_store_postgres::{connection_pool::ConnectionPool, SubgraphStore};
+
+use crate::manager::deployment::DeploymentSearch;
+
+struct Progress {
+    start: Instant,
+    table_start: Instant,
+    /// The estimated number of versions and bytes of the table that is
+    /// being pruned
+    versions: i64,
+    bytes: i64,
+    /// The estimated number of bytes that pruning freed up so far
+    reclaimed: f64,
+}
+
+impl Progress {
+    fn new() -> Self {
+        Progress {
+            start: Instant::now(),
+            table_start: Instant::now(),
+            versions: 0,
+            bytes: 0,
+            reclaimed: 0.0,
+        }
+    }
+
+    fn reclaimed(&self, deleted: usize) -> f64 {
+        if self.versions <= 0 {
+            return 0.0;
+        }
+        let ratio = (deleted as f64 / self.versions as f64).min(1.0);
+        self.bytes as f64 * ratio
+    }
+}
+
+fn print_size(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "kB", "MB", "GB", "TB"];
+    let mut size = bytes;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", size, UNITS[unit])
+}
+
+impl PruneReporter for Progress {
+    fn start(&mut self, earliest_block: BlockNumber) {
+        println!("Removing history before block {}", earliest_block);
+        println!(
+            "{:^30} | {:^10} | {:^12} | {:^10} | {:^8}",
+            "table", "versions", "removed", "reclaimed", "time"
+        );
+        println!(
+            "{:-^30}-+-{:-^10}-+-{:-^12}-+-{:-^10}-+-{:-^8}",
+            "", "", "", "", ""
+        );
+    }
+
+    fn start_table(&mut self, _table: &str, versions: i64, bytes: i64) {
+        self.table_start = Instant::now();
+        self.versions = versions;
+        self.bytes = bytes;
+    }
+
+    fn prune_batch(&mut self, table: &str, deleted: usize) {
+        print!(
+            "\r{:<30} | {:>10} | {:>12} | {:>10} | {:>7}s",
+            table,
+            self.versions,
+            deleted,
+            print_size(self.reclaimed(deleted)),
+            self.table_start.elapsed().as_secs()
+        );
+        std::io::stdout().flush().ok();
+    }
+
+    fn finish_table(&mut self, table: &str, deleted: usize) {
+        self.prune_batch(table, deleted);
+        println!();
+        self.reclaimed += self.reclaimed(deleted);
+    }
+
+    fn finish(&mut self, deleted: usize) {
+        println!(
+            "Removed {} versions in {}s, reclaiming about {}",
+            deleted,
+            self.start.elapsed().as_secs(),
+            print_size(self.reclaimed)
+        );
+        println!(
+            "The space can be reused for new data; run `vacuum full` on the tables \
+             to return it to the operating system"
+        );
+    }
+}
+
+pub async fn run(
+    store: Arc<SubgraphStore>,
+    primary_pool: ConnectionPool,
+    search: DeploymentSearch,
+    history: BlockNumber,
+) -> Result<(), anyhow::Error> {
+    let min_history = ENV_VARS.store.min_history_blocks;
+    if history < min_history {
+        return Err(anyhow::anyhow!(
+            "the deployment needs to keep at least {} blocks of history \
+             (GRAPH_MIN_HISTORY_BLOCKS) so that reverts remain possible",
+            min_history
+        ));
+    }
+
+    let locator = search.locate_unique(&primary_pool)?;
+    println!("Pruning {} to {} blocks of history", locator, history);
+
+    store
+        .prune(Box::new(Progress::new()), &locator, history)
+        .await?;
+    Ok(())
+}
@@ -10,6 +10,7 @@ use graph::{
     },
 };
 use graph_graphql::prelude::GraphQlRunner;
+use graph_store_postgres::query_store::QueryExplanations;
 use graph_store_postgres::Store;
 
 use crate::manager::PanicSubscriptionManager;
@@ -19,6 +20,7 @@ pub async fn run(
     target: String,
     query: String,
     vars: Vec<String>,
+    explanations: Option<Arc<QueryExplanations>>,
 ) -> Result<(), anyhow::Error> {
     let target = if target.starts_with("Qm") {
         let id =
@@ -58,5 +60,14 @@ pub async fn run(
     let json = serde_json::to_string(&res)?;
     println!("{}", json);
 
+    if let Some(explanations) = explanations {
+        for (i, explained) in explanations.take().into_iter().enumerate() {
+            println!("\n-- query {}\n{}\n", i + 1, explained.sql);
+            for line in explained.plan {
+                println!("{}", line);
+            }
+        }
+    }
+
     Ok(())
 }
@@ -5,23 +5,17 @@ use std::{collections::HashSet, convert::TryFrom};
 
 use graph::anyhow::bail;
 use graph::components::store::{BlockStore as _, ChainStore as _};
-use graph::prelude::{anyhow, BlockNumber, BlockPtr, NodeId, SubgraphStore};
-use graph_store_postgres::BlockStore;
+use graph::prelude::{anyhow, BlockNumber, BlockPtr, NodeId, SubgraphStore as _};
 use graph_store_postgres::{connection_pool::ConnectionPool, Store};
+use graph_store_postgres::{BlockStore, ChainStore, SubgraphStore};
 
 use crate::manager::deployment::{Deployment, DeploymentSearch};
 
-fn block_ptr(
+fn chain_store(
     store: Arc<BlockStore>,
     searches: &[DeploymentSearch],
     deployments: &[Deployment],
-    hash: &str,
-    number: BlockNumber,
-    force: bool,
-) -> Result<BlockPtr, anyhow::Error> {
-    let block_ptr_to = BlockPtr::try_from((hash, number as i64))
-        .map_err(|e| anyhow!("error converting to block pointer: {}", e))?;
-
+) -> Result<Arc<ChainStore>, anyhow::Error> {
     let chains = deployments.iter().map(|d| &d.chain).collect::<HashSet<_>>();
     if chains.len() > 1 {
         let names = searches
@@ -32,10 +26,21 @@ fn block_ptr(
         bail!("the deployments matching `{names}` are on different chains");
     }
     let chain = chains.iter().next().unwrap();
-    let chain_store = match store.chain_store(chain) {
+    match store.chain_store(chain) {
         None => bail!("can not find chain store for {}", chain),
-        Some(store) => store,
-    };
+        Some(store) => Ok(store),
+    }
+}
+
+fn block_ptr(
+    chain_store: &ChainStore,
+    hash: &str,
+    number: BlockNumber,
+    force: bool,
+) -> Result<BlockPtr, anyhow::Error> {
+    let block_ptr_to = BlockPtr::try_from((hash, number as i64))
+        .map_err(|e| anyhow!("error converting to block pointer: {}", e))?;
+
     if let Some((_, number)) = chain_store.block_number(block_ptr_to.hash_as_h256())? {
         if number != block_ptr_to.number {
             bail!(
@@ -49,7 +54,7 @@ fn block_ptr(
             bail!(
                 "the chain {} does not have a block with hash {} \
                    (run with --force to avoid this error)",
-                chain,
+                chain_store.chain,
                 block_ptr_to.hash
             );
         }
@@ -57,44 +62,62 @@ fn block_ptr(
     Ok(block_ptr_to)
 }
 
-pub fn run(
-    primary: ConnectionPool,
-    store: Arc<Store>,
-    searches: Vec<DeploymentSearch>,
-    block_hash: String,
-    block_number: BlockNumber,
-    force: bool,
-    sleep: Duration,
-) -> Result<(), anyhow::Error> {
-    const PAUSED: &str = "paused_";
-
-    let subgraph_store = store.subgraph_store();
-    let block_store = store.block_store();
+/// The latest block at or before `number` for which `deployment` has a
+/// proof of indexing
+fn nearest_poi_block_ptr(
+    subgraph_store: &SubgraphStore,
+    chain_store: &ChainStore,
+    deployment: &Deployment,
+    number: BlockNumber,
+) -> Result<BlockPtr, anyhow::Error> {
+    let loc = deployment.locator();
+    let number = match subgraph_store.nearest_poi_block(&loc.hash, number)? {
+        Some(number) => number,
+        None => bail!(
+            "deployment {} has no proof of indexing at or before block {}",
+            loc,
+            number
+        ),
+    };
+    block_ptr_by_number(chain_store, number)
+}
 
-    let deployments = searches
-        .iter()
-        .map(|search| search.lookup(&primary))
-        .collect::<Result<Vec<_>, _>>()?
-        .into_iter()
-        .flatten()
-        .collect::<Vec<_>>();
-    if deployments.is_empty() {
-        println!("nothing to do");
-        return Ok(());
+/// The pointer to the block with `number`, as long as `chain_store` has
+/// exactly one such block
+pub(super) fn block_ptr_by_number(
+    chain_store: &ChainStore,
+    number: BlockNumber,
+) -> Result<BlockPtr, anyhow::Error> {
+    let hashes = chain_store.block_hashes_by_block_number(number)?;
+    match hashes.as_slice() {
+        [hash] => Ok(BlockPtr::from((*hash, number))),
+        [] => bail!(
+            "the chain {} does not have a block with number {}",
+            chain_store.chain,
+            number
+        ),
+        _ => bail!(
+            "the chain {} has {} blocks with number {}; \
+             use `graphman chain check-blocks` to remove the wrong ones",
+            chain_store.chain,
+            hashes.len(),
+            number
+        ),
     }
+}
 
-    let block_ptr_to = block_ptr(
-        block_store,
-        &searches,
-        &deployments,
-        &block_hash,
-        block_number,
-        force,
-    )?;
+const PAUSED: &str = "paused_";
 
+/// Pause all `deployments` that are assigned to an index node by moving
+/// them to a `paused_` node, and give the index nodes `sleep` to stop them
+pub(super) fn pause(
+    subgraph_store: &SubgraphStore,
+    deployments: &[Deployment],
+    sleep: Duration,
+) -> Result<(), anyhow::Error> {
     println!("Pausing deployments");
     let mut paused = false;
-    for deployment in &deployments {
+    for deployment in deployments {
         if let Some(node) = &deployment.node_id {
             if !node.starts_with(PAUSED) {
                 let loc = deployment.locator();
@@ -114,15 +137,17 @@ pub fn run(
         thread::sleep(sleep);
     }
 
-    println!("\nRewinding deployments");
-    for deployment in &deployments {
-        let loc = deployment.locator();
-        subgraph_store.rewind(loc.hash.clone(), block_ptr_to.clone())?;
-        println!("  ... rewound {}", loc);
-    }
+    Ok(())
+}
 
+/// Move `deployments` back to the index nodes they were assigned to before
+/// they were paused with `pause`
+pub(super) fn resume(
+    subgraph_store: &SubgraphStore,
+    deployments: &[Deployment],
+) -> Result<(), anyhow::Error> {
     println!("Resuming deployments");
-    for deployment in &deployments {
+    for deployment in deployments {
         if let Some(node) = &deployment.node_id {
             let loc = deployment.locator();
             let node = NodeId::new(node.clone()).expect("node id is valid");
@@ -131,3 +156,61 @@ pub fn run(
     }
     Ok(())
 }
+
+#[allow(clippy::too_many_arguments)]
+pub fn run(
+    primary: ConnectionPool,
+    store: Arc<Store>,
+    searches: Vec<DeploymentSearch>,
+    block_hash: Option<String>,
+    block_number: Option<BlockNumber>,
+    to_nearest_poi: bool,
+    past_graft: bool,
+    force: bool,
+    sleep: Duration,
+) -> Result<(), anyhow::Error> {
+    let subgraph_store = store.subgraph_store();
+    let block_store = store.block_store();
+
+    let deployments = searches
+        .iter()
+        .map(|search| search.lookup(&primary))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    if deployments.is_empty() {
+        println!("nothing to do");
+        return Ok(());
+    }
+
+    let chain_store = chain_store(block_store, &searches, &deployments)?;
+    let targets = match (block_hash, block_number, to_nearest_poi) {
+        (None, Some(number), true) => deployments
+            .iter()
+            .map(|deployment| {
+                nearest_poi_block_ptr(&subgraph_store, &chain_store, deployment, number)
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        (Some(_), _, true) => bail!("--block-hash can not be used with --to-nearest-poi"),
+        (None, None, true) => bail!("--to-nearest-poi requires --block-number"),
+        (Some(hash), Some(number), false) => {
+            let block_ptr_to = block_ptr(&chain_store, &hash, number, force)?;
+            vec![block_ptr_to; deployments.len()]
+        }
+        (_, _, false) => bail!("both --block-hash and --block-number are required"),
+    };
+
+    pause(&subgraph_store, &deployments, sleep)?;
+
+    println!("\nRewinding deployments");
+    for (deployment, block_ptr_to) in deployments.iter().zip(targets) {
+        let loc = deployment.locator();
+        let number = block_ptr_to.number;
+        subgraph_store.rewind(loc.hash.clone(), block_ptr_to, past_graft)?;
+        println!("  ... rewound {} to block {}", loc, number);
+    }
+
+    resume(&subgraph_store, &deployments)?;
+    Ok(())
+}
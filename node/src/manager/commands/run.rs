@@ -1,4 +1,5 @@
 use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -6,7 +7,7 @@ use crate::config::{Config, ProviderDetails};
 use crate::manager::PanicSubscriptionManager;
 use crate::store_builder::StoreBuilder;
 use crate::MetricsContext;
-use ethereum::{EthereumNetworks, ProviderEthRpcMetrics};
+use ethereum::{EthereumNetworks, ProviderEthRpcMetrics, RequestBudget};
 use futures::future::join_all;
 use futures::TryFutureExt;
 use graph::anyhow::{bail, format_err, Error};
@@ -16,18 +17,20 @@ use graph::components::store::{BlockStore as _, DeploymentLocator};
 use graph::env::EnvVars;
 use graph::firehose::{FirehoseEndpoint, FirehoseEndpoints, FirehoseNetworks};
 use graph::ipfs_client::IpfsClient;
-use graph::prelude::MetricsRegistry as MetricsRegistryTrait;
 use graph::prelude::{
     anyhow, tokio, BlockNumber, DeploymentHash, LoggerFactory, NodeId, SubgraphAssignmentProvider,
     SubgraphName, SubgraphRegistrar, SubgraphStore, SubgraphVersionSwitchingMode, ENV_VARS,
 };
+use graph::prelude::{LinkResolver as LinkResolverTrait, MetricsRegistry as MetricsRegistryTrait};
 use graph::slog::{debug, error, info, o, Logger};
 use graph::util::security::SafeDisplay;
 use graph_chain_ethereum::{self as ethereum, EthereumAdapterTrait, Transport};
 use graph_core::{
-    LinkResolver, MetricsRegistry, SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider,
-    SubgraphInstanceManager, SubgraphRegistrar as IpfsSubgraphRegistrar,
+    FileLinkResolver, LinkResolver, MetricsRegistry,
+    SubgraphAssignmentProvider as IpfsSubgraphAssignmentProvider, SubgraphInstanceManager,
+    SubgraphRegistrar as IpfsSubgraphRegistrar,
 };
+use graph_store_postgres::Store;
 use url::Url;
 
 fn locate(store: &dyn SubgraphStore, hash: &str) -> Result<DeploymentLocator, anyhow::Error> {
@@ -49,6 +52,7 @@ pub async fn run(
     node_id: NodeId,
     subgraph: String,
     stop_block: BlockNumber,
+    from_dir: Option<PathBuf>,
 ) -> Result<(), anyhow::Error> {
     println!(
         "Run command: starting subgraph => {}, stop_block = {}",
@@ -58,68 +62,38 @@ pub async fn run(
     let metrics_registry = metrics_ctx.registry.clone();
     let logger_factory = LoggerFactory::new(logger.clone(), None);
 
-    // FIXME: Hard-coded IPFS config, take it from config file instead?
-    let ipfs_clients: Vec<_> = create_ipfs_clients(&logger, &ipfs_url);
-
-    // Convert the clients into a link resolver. Since we want to get past
-    // possible temporary DNS failures, make the resolver retry
-    let link_resolver = Arc::new(LinkResolver::new(
-        ipfs_clients,
-        Arc::new(EnvVars::default()),
-    ));
-
-    let eth_networks = create_ethereum_networks(logger.clone(), metrics_registry.clone(), &config)
-        .await
-        .expect("Failed to parse Ethereum networks");
-    let firehose_networks_by_kind =
-        create_firehose_networks(logger.clone(), metrics_registry.clone(), &config)
-            .await
-            .expect("Failed to parse Firehose endpoints");
-    let firehose_networks = firehose_networks_by_kind.get(&BlockchainKind::Ethereum);
-    let firehose_endpoints = firehose_networks.and_then(|v| v.networks.get(&network_name));
-
-    let eth_adapters = match eth_networks.networks.get(&network_name) {
-        Some(adapters) => adapters.clone(),
+    // When the subgraph is read from a local directory, `subgraph` is just
+    // its name, and the deployment hash is derived from the files
+    let (link_resolver, subgraph): (Arc<dyn LinkResolverTrait>, String) = match from_dir {
+        Some(dir) => {
+            let resolver = FileLinkResolver::new(&dir)?;
+            let hash = resolver.deployment_hash();
+            info!(&logger, "Reading subgraph from {}", resolver.dir().display();
+                  "deployment" => hash.as_str());
+            let subgraph = format!("{}:{}", subgraph, hash);
+            (Arc::new(resolver), subgraph)
+        }
         None => {
-            return Err(format_err!(
-                "No ethereum adapters found, but required in this state of graphman run command"
-            ))
+            // FIXME: Hard-coded IPFS config, take it from config file instead?
+            let ipfs_clients: Vec<_> = create_ipfs_clients(&logger, &ipfs_url);
+
+            // Convert the clients into a link resolver. Since we want to get past
+            // possible temporary DNS failures, make the resolver retry
+            let resolver = LinkResolver::new(ipfs_clients, Arc::new(EnvVars::default()));
+            (Arc::new(resolver), subgraph)
         }
     };
 
-    let (_, ethereum_idents) = connect_ethereum_networks(&logger, eth_networks).await;
-    // let (near_networks, near_idents) = connect_firehose_networks::<NearFirehoseHeaderOnlyBlock>(
-    //     &logger,
-    //     firehose_networks_by_kind
-    //         .remove(&BlockchainKind::Near)
-    //         .unwrap_or_else(|| FirehoseNetworks::new()),
-    // )
-    // .await;
-
-    let chain_head_update_listener = store_builder.chain_head_update_listener();
-    let network_identifiers = ethereum_idents.into_iter().collect();
-    let network_store = store_builder.network_store(network_identifiers);
-
-    let subgraph_store = network_store.subgraph_store();
-    let chain_store = network_store
-        .block_store()
-        .chain_store(network_name.as_ref())
-        .expect(format!("No chain store for {}", &network_name).as_ref());
-
-    let chain = ethereum::Chain::new(
-        logger_factory.clone(),
-        network_name.clone(),
-        node_id.clone(),
+    let (chain, network_store) = create_ethereum_chain(
+        &logger,
+        store_builder,
+        &network_name,
+        &config,
         metrics_registry.clone(),
-        chain_store.cheap_clone(),
-        chain_store,
-        firehose_endpoints.map_or_else(|| FirehoseEndpoints::new(), |v| v.clone()),
-        eth_adapters,
-        chain_head_update_listener,
-        ethereum::ENV_VARS.reorg_threshold,
-        // We assume the tested chain is always ingestible for now
-        true,
-    );
+        &node_id,
+    )
+    .await?;
+    let subgraph_store = network_store.subgraph_store();
 
     let mut blockchain_map = BlockchainMap::new();
     blockchain_map.insert(network_name.clone(), Arc::new(chain));
@@ -134,6 +108,8 @@ pub async fn run(
         metrics_registry.clone(),
         link_resolver.cheap_clone(),
         static_filters,
+        config.mappings.handler_timeouts(),
+        config.mappings.retry_policies(),
     );
 
     // Create IPFS-based subgraph provider
@@ -188,6 +164,8 @@ pub async fn run(
         node_id.clone(),
         None,
         None,
+        false,
+        false,
     )
     .await?;
 
@@ -262,7 +240,10 @@ enum ProviderNetworkStatus {
 /// continue regardless.
 const NET_VERSION_WAIT_TIME: Duration = Duration::from_secs(30);
 
-fn create_ipfs_clients(logger: &Logger, ipfs_addresses: &Vec<String>) -> Vec<IpfsClient> {
+pub(crate) fn create_ipfs_clients(
+    logger: &Logger,
+    ipfs_addresses: &Vec<String>,
+) -> Vec<IpfsClient> {
     // Parse the IPFS URL from the `--ipfs` command line argument
     let ipfs_addresses: Vec<_> = ipfs_addresses
         .iter()
@@ -329,6 +310,75 @@ fn create_ipfs_clients(logger: &Logger, ipfs_addresses: &Vec<String>) -> Vec<Ipf
         .collect()
 }
 
+/// Connect to the providers for the Ethereum network `network_name` and
+/// set up the chain for it together with the store for all networks that
+/// we could connect to
+pub(crate) async fn create_ethereum_chain(
+    logger: &Logger,
+    store_builder: StoreBuilder,
+    network_name: &str,
+    config: &Config,
+    metrics_registry: Arc<MetricsRegistry>,
+    node_id: &NodeId,
+) -> Result<(ethereum::Chain, Arc<Store>), anyhow::Error> {
+    let logger_factory = LoggerFactory::new(logger.clone(), None);
+
+    let eth_networks = create_ethereum_networks(logger.clone(), metrics_registry.clone(), config)
+        .await
+        .expect("Failed to parse Ethereum networks");
+    let firehose_networks_by_kind =
+        create_firehose_networks(logger.clone(), metrics_registry.clone(), config)
+            .await
+            .expect("Failed to parse Firehose endpoints");
+    let firehose_networks = firehose_networks_by_kind.get(&BlockchainKind::Ethereum);
+    let firehose_endpoints = firehose_networks.and_then(|v| v.networks.get(network_name));
+
+    let eth_adapters = match eth_networks.networks.get(network_name) {
+        Some(adapters) => adapters.clone(),
+        None => {
+            return Err(format_err!(
+                "No ethereum adapters found, but required in this state of graphman run command"
+            ))
+        }
+    };
+
+    let (_, ethereum_idents) = connect_ethereum_networks(logger, eth_networks).await;
+    // let (near_networks, near_idents) = connect_firehose_networks::<NearFirehoseHeaderOnlyBlock>(
+    //     &logger,
+    //     firehose_networks_by_kind
+    //         .remove(&BlockchainKind::Near)
+    //         .unwrap_or_else(|| FirehoseNetworks::new()),
+    // )
+    // .await;
+
+    let chain_head_update_listener = store_builder.chain_head_update_listener();
+    let network_identifiers = ethereum_idents.into_iter().collect();
+    let network_store = store_builder.network_store(network_identifiers);
+
+    let chain_store = network_store
+        .block_store()
+        .chain_store(network_name)
+        .expect(format!("No chain store for {}", network_name).as_ref());
+
+    let chain = ethereum::Chain::new(
+        logger_factory,
+        network_name.to_string(),
+        node_id.clone(),
+        metrics_registry,
+        chain_store.cheap_clone(),
+        chain_store,
+        network_store.subgraph_store(),
+        firehose_endpoints.map_or_else(|| FirehoseEndpoints::new(), |v| v.clone()),
+        eth_adapters,
+        chain_head_update_listener,
+        ethereum::ENV_VARS.reorg_threshold,
+        // We assume the tested chain is always ingestible for now
+        true,
+    );
+
+    Ok((chain, network_store))
+}
+
 /// Parses an Ethereum connection string and returns the network name and Ethereum adapter.
 async fn create_ethereum_networks(
     logger: Logger,
@@ -361,6 +411,14 @@ async fn create_ethereum_networks(
                     Ipc => Transport::new_ipc(&web3.url).await,
                     Ws => Transport::new_ws(&web3.url).await,
                 };
+                let transport = match web3.request_limits() {
+                    Some(limits) => transport.with_budget(Arc::new(RequestBudget::new(
+                        provider.label.clone(),
+                        limits,
+                        eth_rpc_metrics.clone(),
+                    ))),
+                    None => transport,
+                };
 
                 let supports_eip_1898 = !web3.features.contains("no_eip1898");
 
@@ -416,6 +474,7 @@ async fn create_firehose_networks(
                     &firehose.url,
                     firehose.token.clone(),
                     firehose.filters_enabled(),
+                    firehose.grpc_settings(),
                 )
                 .await?;
 
@@ -440,7 +499,7 @@ async fn create_firehose_networks(
 /// version for it.
 async fn connect_ethereum_networks(
     logger: &Logger,
-    mut eth_networks: EthereumNetworks,
+    eth_networks: EthereumNetworks,
 ) -> (EthereumNetworks, Vec<(String, Vec<ChainIdentifier>)>) {
     // This has one entry for each provider, and therefore multiple entries
     // for each network
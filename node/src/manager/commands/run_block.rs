@@ -0,0 +1,209 @@
+use std::sync::Arc;
+
+use graph::anyhow::bail;
+use graph::components::store::{DeploymentLocator, EntityModification};
+use graph::components::subgraph::HostTrace;
+use graph::env::EnvVars;
+use graph::prelude::{
+    anyhow, serde_yaml, BlockNumber, Entity, LinkResolver as LinkResolverTrait, Logger, NodeId,
+    SubgraphStore as _,
+};
+use graph_chain_ethereum::Chain;
+use graph_core::{replay_block, BlockReplay, LinkResolver, MetricsRegistry};
+use graph_store_postgres::SubgraphStore;
+
+use crate::config::Config;
+use crate::manager::commands::run::{create_ethereum_chain, create_ipfs_clients};
+use crate::manager::deployment::DeploymentSearch;
+use crate::store_builder::StoreBuilder;
+
+fn print_entity(entity: Entity) {
+    for (attr, value) in entity.sorted() {
+        println!("      {}: {}", attr, value);
+    }
+}
+
+fn print_replay(replay: BlockReplay) {
+    println!("block {}", replay.block);
+
+    for trigger in replay.triggers {
+        println!("\ntrigger {}", trigger.trigger);
+        if trigger.trace.is_empty() {
+            println!("  no handler ran");
+        }
+        for event in trigger.trace {
+            match event {
+                HostTrace::Handler {
+                    handler,
+                    data_source,
+                    gas_used,
+                    duration,
+                } => println!(
+                    "  handler {}.{}: gas {}, {}ms",
+                    data_source,
+                    handler,
+                    gas_used,
+                    duration.as_millis()
+                ),
+                HostTrace::HostFn { name, duration } => {
+                    println!("    {}: {}ms", name, duration.as_millis())
+                }
+            }
+        }
+    }
+
+    println!("\nentity changes:");
+    if replay.modifications.is_empty() {
+        println!("  none");
+    }
+    for modification in replay.modifications {
+        match modification {
+            EntityModification::Insert { key, data } => {
+                println!("  insert {}[{}]", key.entity_type, key.entity_id);
+                print_entity(data);
+            }
+            EntityModification::Overwrite { key, data } => {
+                println!("  overwrite {}[{}]", key.entity_type, key.entity_id);
+                print_entity(data);
+            }
+            EntityModification::Remove { key } => {
+                println!("  remove {}[{}]", key.entity_type, key.entity_id)
+            }
+        }
+    }
+
+    if !replay.created_data_sources.is_empty() {
+        println!("\ncreated data sources:");
+        for template in replay.created_data_sources {
+            println!("  {}", template);
+        }
+    }
+
+    if !replay.mapping_logs.is_empty() {
+        println!("\nlogs:");
+        for log in replay.mapping_logs {
+            println!("  {} {}: {}", log.level, log.data_source, log.message);
+        }
+    }
+
+    if !replay.deterministic_errors.is_empty() {
+        println!("\nerrors:");
+        for error in replay.deterministic_errors {
+            println!("  {}", error);
+        }
+    }
+}
+
+/// Everything that is needed to work with the mappings of a deployment
+/// outside of indexing
+pub(crate) struct MappingEnv {
+    pub locator: DeploymentLocator,
+    pub chain: Arc<Chain>,
+    pub subgraph_store: Arc<SubgraphStore>,
+    pub link_resolver: Arc<dyn LinkResolverTrait>,
+    /// The raw manifest of the deployment
+    pub manifest: serde_yaml::Mapping,
+}
+
+impl MappingEnv {
+    pub(crate) async fn new(
+        logger: &Logger,
+        store_builder: StoreBuilder,
+        config: &Config,
+        registry: Arc<MetricsRegistry>,
+        node_id: &NodeId,
+        ipfs_url: &Vec<String>,
+        search: &DeploymentSearch,
+    ) -> Result<Self, anyhow::Error> {
+        let primary = store_builder.primary_pool();
+        let deployments = search.lookup(&primary)?;
+        let deployment = match deployments.first() {
+            Some(deployment) => deployment,
+            None => bail!("no deployment matches {}", search),
+        };
+        if deployments.iter().any(|other| other.id != deployment.id) {
+            bail!("there are multiple deployments for {}", search);
+        }
+        let locator = deployment.locator();
+
+        let (chain, network_store) = create_ethereum_chain(
+            logger,
+            store_builder,
+            &deployment.chain,
+            config,
+            registry,
+            node_id,
+        )
+        .await?;
+
+        let link_resolver: Arc<dyn LinkResolverTrait> = Arc::new(LinkResolver::new(
+            create_ipfs_clients(logger, ipfs_url),
+            Arc::new(EnvVars::default()),
+        ));
+        let manifest = link_resolver
+            .cat(logger, &locator.hash.to_ipfs_link())
+            .await?;
+        let manifest = serde_yaml::from_slice(&manifest)?;
+
+        Ok(Self {
+            locator,
+            chain: Arc::new(chain),
+            subgraph_store: network_store.subgraph_store(),
+            link_resolver,
+            manifest,
+        })
+    }
+}
+
+pub async fn run(
+    logger: Logger,
+    store_builder: StoreBuilder,
+    config: Config,
+    registry: Arc<MetricsRegistry>,
+    node_id: NodeId,
+    ipfs_url: Vec<String>,
+    search: DeploymentSearch,
+    block: BlockNumber,
+) -> Result<(), anyhow::Error> {
+    let env = MappingEnv::new(
+        &logger,
+        store_builder,
+        &config,
+        registry.clone(),
+        &node_id,
+        &ipfs_url,
+        &search,
+    )
+    .await?;
+
+    match env
+        .subgraph_store
+        .least_block_ptr(&env.locator.hash)
+        .await?
+    {
+        Some(head) if head.number < block => println!(
+            "warning: {} is only at block {}; data sources created after that are missing",
+            env.locator, head.number
+        ),
+        Some(head) if head.number != block - 1 => println!(
+            "warning: mappings see entities as of block {}, not as of block {}",
+            head.number,
+            block - 1
+        ),
+        _ => (),
+    }
+
+    let replay = replay_block(
+        &logger,
+        env.subgraph_store,
+        env.chain,
+        env.link_resolver,
+        registry,
+        &env.locator,
+        env.manifest,
+        block,
+    )
+    .await?;
+    print_replay(replay);
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use graph::prelude::{BlockNumber, Error, SubgraphStore as _};
+use graph_store_postgres::{connection_pool::ConnectionPool, SubgraphStore};
+
+use crate::manager::deployment::DeploymentSearch;
+
+pub fn add(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    deployment: DeploymentSearch,
+    block: BlockNumber,
+    handler: Option<String>,
+    reason: String,
+) -> Result<(), Error> {
+    let deployment = deployment.locate_unique(&primary)?;
+    store.skip_block(&deployment.hash, block, handler.as_deref(), &reason)?;
+    match handler {
+        Some(handler) => println!(
+            "{} will not run handler {} for block {}",
+            deployment, handler, block
+        ),
+        None => println!("{} will skip block {}", deployment, block),
+    }
+    println!("Restart the deployment for this to take effect");
+    Ok(())
+}
+
+pub fn remove(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    deployment: DeploymentSearch,
+    block: BlockNumber,
+    handler: Option<String>,
+) -> Result<(), Error> {
+    let deployment = deployment.locate_unique(&primary)?;
+    let count = store.unskip_block(&deployment.hash, block, handler.as_deref())?;
+    if count == 0 {
+        println!("{} was not skipping block {}", deployment, block);
+    } else {
+        println!("{} will process block {} again", deployment, block);
+        println!("Restart the deployment for this to take effect");
+    }
+    Ok(())
+}
+
+pub fn list(
+    store: Arc<SubgraphStore>,
+    primary: ConnectionPool,
+    deployment: DeploymentSearch,
+) -> Result<(), Error> {
+    let deployment = deployment.locate_unique(&primary)?;
+    let skipped = store.skipped_blocks(&deployment.hash)?;
+    if skipped.is_empty() {
+        println!("{} does not skip any blocks", deployment);
+        return Ok(());
+    }
+    println!(
+        "{:>10} | {:<20} | {:<25} | reason",
+        "block", "handler", "created at"
+    );
+    for skip in skipped {
+        println!(
+            "{:>10} | {:<20} | {:<25} | {}",
+            skip.block_number,
+            skip.handler.as_deref().unwrap_or("(all)"),
+            skip.created_at.format("%Y-%m-%d %H:%M:%S %Z"),
+            skip.reason
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,115 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use graph::anyhow::bail;
+use graph::prelude::{
+    anyhow, serde_json, serde_yaml, tokio, LinkResolver, Logger, SubgraphManifest, ENV_VARS,
+};
+use graph_chain_ethereum::Chain;
+use graph_core::FileLinkResolver;
+use graph_runtime_test::harness::{Fixture, TestHarness};
+use graph_runtime_wasm::ValidModule;
+
+/// The directory inside the subgraph directory that contains the tests
+const TESTS_DIR: &str = "tests";
+
+fn read_fixture(path: &Path) -> Result<Fixture, anyhow::Error> {
+    match fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map_err(|e| anyhow!("invalid fixture {}: {}", path.display(), e)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Fixture::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub async fn run(
+    logger: Logger,
+    dir: PathBuf,
+    filter: Option<String>,
+) -> Result<(), anyhow::Error> {
+    let resolver = FileLinkResolver::new(&dir)?;
+    let hash = resolver.deployment_hash();
+    let resolver: Arc<dyn LinkResolver> = Arc::new(resolver);
+
+    let raw = resolver.cat(&logger, &hash.to_ipfs_link()).await?;
+    let raw: serde_yaml::Mapping = serde_yaml::from_slice(&raw)?;
+    let manifest = SubgraphManifest::<Chain>::resolve_from_raw(
+        hash.clone(),
+        raw,
+        &resolver,
+        &logger,
+        ENV_VARS.max_spec_version.clone(),
+    )
+    .await?;
+    let schema = Arc::new(manifest.schema.clone());
+
+    let mut passed = 0;
+    let mut failed = 0;
+    for data_source in &manifest.data_sources {
+        let name = data_source.name.clone();
+        let tests_dir = dir.join(TESTS_DIR);
+        let wasm = tests_dir.join(format!("{}.wasm", name));
+        if !wasm.is_file() {
+            println!("no tests for data source {}", name);
+            continue;
+        }
+        let fixture = read_fixture(&tests_dir.join(format!("{}.json", name)))?;
+        let module = Arc::new(ValidModule::new(&fs::read(&wasm)?)?);
+
+        let tests = TestHarness::tests(&module)
+            .into_iter()
+            .filter(|test| match &filter {
+                Some(filter) => test.contains(filter.as_str()),
+                None => true,
+            })
+            .collect::<Vec<_>>();
+        println!("running {} tests for data source {}", tests.len(), name);
+
+        let harness = TestHarness::new(
+            logger.clone(),
+            hash.clone(),
+            data_source.clone(),
+            manifest.templates.clone(),
+            schema.clone(),
+        )
+        .with_fixture(fixture);
+
+        // Mappings run on a thread that may block, as during indexing
+        let results = tokio::task::spawn_blocking(move || {
+            tests
+                .into_iter()
+                .map(|test| {
+                    let result = harness.run(module.clone(), &test);
+                    (test, result)
+                })
+                .collect::<Vec<_>>()
+        })
+        .await?;
+
+        for (test, result) in results {
+            match result {
+                Ok(store) => {
+                    println!(
+                        "test {}::{} ... ok ({} entities)",
+                        name,
+                        test,
+                        store.entities().len()
+                    );
+                    passed += 1;
+                }
+                Err(e) => {
+                    println!("test {}::{} ... FAILED\n    {:#}", name, test, e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    println!("\ntest result: {} passed; {} failed", passed, failed);
+    if failed > 0 {
+        bail!("{} of {} tests failed", failed, passed + failed);
+    }
+    Ok(())
+}
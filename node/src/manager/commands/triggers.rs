@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use graph::prelude::{anyhow, web3::types::H256, BlockNumber, Logger, NodeId};
+use graph_chain_ethereum::{EthereumBlockTriggerType, EthereumTrigger};
+use graph_core::{block_triggers, MetricsRegistry};
+
+use crate::config::Config;
+use crate::manager::commands::run_block::MappingEnv;
+use crate::manager::deployment::DeploymentSearch;
+use crate::store_builder::StoreBuilder;
+
+fn describe(trigger: &EthereumTrigger) -> String {
+    fn tx(hash: Option<H256>) -> String {
+        hash.map(|hash| format!(", transaction 0x{:x}", hash))
+            .unwrap_or_default()
+    }
+
+    match trigger {
+        EthereumTrigger::Log(log, _) => format!(
+            "event 0x{:x} from 0x{:x}{}",
+            log.topics.first().cloned().unwrap_or_default(),
+            log.address,
+            tx(log.transaction_hash)
+        ),
+        EthereumTrigger::Call(call) => format!(
+            "call to 0x{:x} from 0x{:x}{}",
+            call.to,
+            call.from,
+            tx(call.transaction_hash)
+        ),
+        EthereumTrigger::Block(_, EthereumBlockTriggerType::Every) => "block".to_string(),
+        EthereumTrigger::Block(_, EthereumBlockTriggerType::WithCallTo(address)) => {
+            format!("block with a call to 0x{:x}", address)
+        }
+        EthereumTrigger::Entity(trigger) => format!(
+            "change to {} `{}` in subgraph {}",
+            trigger.key().entity_type,
+            trigger.key().entity_id,
+            trigger.source
+        ),
+    }
+}
+
+pub async fn run(
+    logger: Logger,
+    store_builder: StoreBuilder,
+    config: Config,
+    registry: Arc<MetricsRegistry>,
+    node_id: NodeId,
+    ipfs_url: Vec<String>,
+    search: DeploymentSearch,
+    from: BlockNumber,
+    to: Option<BlockNumber>,
+) -> Result<(), anyhow::Error> {
+    let env = MappingEnv::new(
+        &logger,
+        store_builder,
+        &config,
+        registry,
+        &node_id,
+        &ipfs_url,
+        &search,
+    )
+    .await?;
+
+    let blocks = block_triggers(
+        &logger,
+        env.subgraph_store,
+        env.chain,
+        env.link_resolver,
+        &env.locator,
+        env.manifest,
+        from,
+        to.unwrap_or(from),
+    )
+    .await?;
+
+    if blocks.is_empty() {
+        println!("no triggers for {} in these blocks", env.locator);
+    }
+    for block in blocks {
+        println!("block {}", block.block);
+        for trigger in block.triggers {
+            println!("  {}", describe(&trigger.trigger));
+            if trigger.handlers.is_empty() {
+                println!("    no handler matches");
+            }
+            for (data_source, handler) in trigger.handlers {
+                println!("    {}.{}", data_source, handler);
+            }
+        }
+    }
+    Ok(())
+}
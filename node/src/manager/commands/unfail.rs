@@ -0,0 +1,139 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use graph::anyhow::bail;
+use graph::components::store::{BlockStore as _, StatusStore};
+use graph::data::subgraph::{schema::SubgraphError, status};
+use graph::prelude::{anyhow, BlockPtr};
+use graph_store_postgres::{connection_pool::ConnectionPool, BlockStore, Store};
+
+use crate::manager::commands::rewind::{block_ptr_by_number, pause, resume};
+use crate::manager::deployment::{Deployment, DeploymentSearch};
+
+/// The block to which to rewind `deployment` so that it processes the
+/// block at which it failed with `error` again
+fn divergence_block_ptr(
+    block_store: &BlockStore,
+    deployment: &Deployment,
+    error: &SubgraphError,
+) -> Result<BlockPtr, anyhow::Error> {
+    let number = match &error.block_ptr {
+        Some(ptr) => ptr.number,
+        None => bail!(
+            "the fatal error of {} does not have a block; use `graphman rewind` instead",
+            deployment.locator()
+        ),
+    };
+    let chain_store = match block_store.chain_store(&deployment.chain) {
+        None => bail!("can not find chain store for {}", deployment.chain),
+        Some(store) => store,
+    };
+    block_ptr_by_number(&chain_store, number - 1)
+}
+
+pub fn run(
+    primary: ConnectionPool,
+    store: Arc<Store>,
+    searches: Vec<DeploymentSearch>,
+    rewind_to_divergence: bool,
+    auto: bool,
+    sleep: Duration,
+) -> Result<(), anyhow::Error> {
+    let subgraph_store = store.subgraph_store();
+    let block_store = store.block_store();
+
+    let searches = match (searches.is_empty(), auto) {
+        // A search for the empty name matches every deployment
+        (true, true) => vec![DeploymentSearch::Name {
+            name: String::new(),
+        }],
+        (true, false) => bail!("no deployments given; use --auto to consider all deployments"),
+        (false, _) => searches,
+    };
+
+    // The same deployment can be found under several names or versions
+    let mut seen = HashSet::new();
+    let deployments = searches
+        .iter()
+        .map(|search| search.lookup(&primary))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .filter(|deployment| seen.insert(deployment.id))
+        .collect::<Vec<_>>();
+
+    let ids = deployments.iter().map(|d| d.locator().id).collect();
+    let statuses = store.status(status::Filter::DeploymentIds(ids))?;
+
+    let mut failed = Vec::new();
+    let mut errors = Vec::new();
+    for deployment in deployments {
+        let error = statuses
+            .iter()
+            .find(|status| status.id.0 == deployment.id)
+            .and_then(|status| status.fatal_error.clone());
+        match error {
+            None if !auto => println!("{} has not failed", deployment.locator()),
+            None => { /* nothing to do */ }
+            Some(error) if auto && error.deterministic => println!(
+                "skipping {} since it failed with a deterministic error: {}",
+                deployment.locator(),
+                error
+            ),
+            Some(error) => {
+                failed.push(deployment);
+                errors.push(error);
+            }
+        }
+    }
+    if failed.is_empty() {
+        println!("nothing to do");
+        return Ok(());
+    }
+
+    let targets = failed
+        .iter()
+        .zip(errors.iter())
+        .map(|(deployment, error)| {
+            if rewind_to_divergence {
+                divergence_block_ptr(&block_store, deployment, error).map(Some)
+            } else {
+                Ok(None)
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    pause(&subgraph_store, &failed, sleep)?;
+
+    // Keep going when one deployment can not be unfailed so that all of
+    // them are resumed
+    println!("\nUnfailing deployments");
+    let mut failures = 0;
+    for ((deployment, error), target) in failed.iter().zip(errors).zip(targets) {
+        let loc = deployment.locator();
+        let number = target.as_ref().map(|ptr| ptr.number);
+        match (subgraph_store.unfail(&loc.hash, target), number) {
+            (Ok(()), Some(number)) => {
+                println!("  ... unfailed {} and rewound it to block {}", loc, number)
+            }
+            (Ok(()), None) => println!("  ... unfailed {}", loc),
+            (Err(e), _) => {
+                println!("  ... failed to unfail {}: {}", loc, e);
+                failures += 1;
+            }
+        }
+        println!("      the error was: {}", error);
+    }
+
+    resume(&subgraph_store, &failed)?;
+
+    if failures > 0 {
+        bail!(
+            "{} of {} deployments were not unfailed",
+            failures,
+            failed.len()
+        );
+    }
+    Ok(())
+}
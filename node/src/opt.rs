@@ -142,6 +142,18 @@ pub struct Opt {
         help = "Port for the Prometheus metrics server"
     )]
     pub metrics_port: u16,
+    #[structopt(
+        long,
+        value_name = "PATH",
+        help = "Unix socket on which the GraphQL HTTP server listens in addition to its port"
+    )]
+    pub http_socket: Option<String>,
+    #[structopt(
+        long,
+        value_name = "PATH",
+        help = "Unix socket on which the JSON-RPC admin server listens in addition to its port"
+    )]
+    pub admin_socket: Option<String>,
     #[structopt(
         long,
         default_value = "default",
@@ -175,6 +187,36 @@ pub struct Opt {
         help = "Password to use for Elasticsearch logging"
     )]
     pub elasticsearch_password: Option<String>,
+    #[structopt(
+        long,
+        value_name = "URL",
+        env = "LOKI_URL",
+        conflicts_with = "elasticsearch_url",
+        help = "Grafana Loki service to write subgraph logs to"
+    )]
+    pub loki_url: Option<String>,
+    #[structopt(
+        long,
+        value_name = "USER",
+        env = "LOKI_USER",
+        help = "User to use for Loki logging"
+    )]
+    pub loki_user: Option<String>,
+    #[structopt(
+        long,
+        value_name = "PASSWORD",
+        env = "LOKI_PASSWORD",
+        hide_env_values = true,
+        help = "Password to use for Loki logging"
+    )]
+    pub loki_password: Option<String>,
+    #[structopt(
+        long,
+        value_name = "TENANT",
+        env = "LOKI_TENANT",
+        help = "Tenant to send logs to in a multi-tenant Loki"
+    )]
+    pub loki_tenant: Option<String>,
     #[structopt(
         long,
         value_name = "MILLISECONDS",
@@ -213,6 +255,23 @@ pub struct Opt {
 
     #[structopt(long, value_name = "URL", help = "Base URL for forking subgraphs")]
     pub fork_base: Option<String>,
+
+    #[structopt(
+        long,
+        value_name = "DIR",
+        env = "GRAPH_DEV_DIR",
+        conflicts_with = "subgraph",
+        help = "directory with a built subgraph to run in development mode; \
+                it is redeployed whenever its files change"
+    )]
+    pub dev_dir: Option<String>,
+    #[structopt(
+        long,
+        value_name = "NAME",
+        default_value = "dev",
+        help = "name under which the subgraph from --dev-dir is deployed"
+    )]
+    pub dev_name: String,
 }
 
 impl From<Opt> for config::Opt {
@@ -0,0 +1,317 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+
+use ethereum::{EthereumAdapterTrait, EthereumNetworks};
+use graph::blockchain::{BlockchainKind, ChainIdentifier};
+use graph::firehose::{FirehoseEndpoint, FirehoseNetworks};
+use graph::prelude::{anyhow, tokio};
+use graph::slog::{error, info, o, warn, Logger};
+use graph_chain_ethereum as ethereum;
+use graph_chain_near as near;
+use graph_chain_tendermint as tendermint;
+use graph_core::MetricsRegistry;
+use graph_store_postgres::BlockStore;
+
+use crate::chain::{create_ethereum_networks, create_firehose_networks, NET_VERSION_WAIT_TIME};
+use crate::config::{Config, Provider, ProviderDetails};
+
+/// Keeps track of the Ethereum and Firehose providers that the chains of
+/// this node use, and allows changing them while the node is running. The
+/// networks in here share their lists of providers with the chains, so that
+/// adding or removing a provider here immediately affects the chains.
+///
+/// Only providers for networks that were configured when the node started
+/// can be changed; adding a new network requires a restart. New providers
+/// are only used if they serve the chain that the block store has for
+/// their network.
+pub struct ProviderManager {
+    logger: Logger,
+    registry: Arc<MetricsRegistry>,
+    block_store: Arc<BlockStore>,
+    eth_networks: EthereumNetworks,
+    firehose_networks: BTreeMap<BlockchainKind, FirehoseNetworks>,
+    /// The configuration of the providers that we currently use, by
+    /// network. A provider whose configuration changed under the same
+    /// label gets replaced on reload
+    applied: Mutex<BTreeMap<String, Vec<Provider>>>,
+}
+
+impl ProviderManager {
+    /// Create a manager for the providers in `eth_networks` and
+    /// `firehose_networks`, which must have been created from `config`
+    pub fn new(
+        logger: Logger,
+        registry: Arc<MetricsRegistry>,
+        block_store: Arc<BlockStore>,
+        eth_networks: EthereumNetworks,
+        firehose_networks: BTreeMap<BlockchainKind, FirehoseNetworks>,
+        config: &Config,
+    ) -> Self {
+        let manager = Self {
+            logger,
+            registry,
+            block_store,
+            eth_networks,
+            firehose_networks,
+            applied: Mutex::new(BTreeMap::new()),
+        };
+        manager.remember_applied(config);
+        manager
+    }
+
+    /// Remember the configuration of those providers in `config` that we
+    /// are actually using
+    fn remember_applied(&self, config: &Config) {
+        let current = self.current_providers();
+        let applied = config
+            .chains
+            .chains
+            .iter()
+            .filter_map(|(name, chain)| {
+                let labels = current.get(name)?;
+                let providers = chain
+                    .providers
+                    .iter()
+                    .filter(|provider| labels.contains(&provider.label))
+                    .cloned()
+                    .collect();
+                Some((name.clone(), providers))
+            })
+            .collect();
+        *self.applied.lock().unwrap() = applied;
+    }
+
+    /// Whether we use `provider` for `network` with exactly this
+    /// configuration
+    fn is_applied(&self, network: &str, provider: &Provider) -> bool {
+        self.applied
+            .lock()
+            .unwrap()
+            .get(network)
+            .map_or(false, |providers| providers.contains(provider))
+    }
+
+    /// The labels of all providers that we currently use, grouped by network
+    fn current_providers(&self) -> BTreeMap<String, BTreeSet<String>> {
+        let mut providers: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for (name, adapters) in &self.eth_networks.networks {
+            providers
+                .entry(name.clone())
+                .or_default()
+                .extend(adapters.providers());
+        }
+        for networks in self.firehose_networks.values() {
+            for (name, endpoints) in &networks.networks {
+                providers
+                    .entry(name.clone())
+                    .or_default()
+                    .extend(endpoints.providers());
+            }
+        }
+        providers
+    }
+
+    fn is_known_network(&self, name: &str) -> bool {
+        self.eth_networks.networks.contains_key(name)
+            || self
+                .firehose_networks
+                .values()
+                .any(|networks| networks.networks.contains_key(name))
+    }
+
+    /// Check that a provider for `network` that reported `ident` serves
+    /// the same chain as the block store. Firehose providers do not have a
+    /// net version, and only their genesis block is checked
+    fn check_chain_identifier(
+        &self,
+        network: &str,
+        ident: &ChainIdentifier,
+        check_net_version: bool,
+    ) -> Result<(), anyhow::Error> {
+        let expected = self
+            .block_store
+            .chain_identifier(network)?
+            .ok_or_else(|| anyhow!("the block store has no chain for network {}", network))?;
+        if expected.genesis_block_hash != ident.genesis_block_hash {
+            return Err(anyhow!(
+                "the provider has genesis block {} but network {} has genesis block {}",
+                ident.genesis_block_hash,
+                network,
+                expected.genesis_block_hash
+            ));
+        }
+        if check_net_version && expected.net_version != ident.net_version {
+            return Err(anyhow!(
+                "the provider has net version {} but network {} has net version {}",
+                ident.net_version,
+                network,
+                expected.net_version
+            ));
+        }
+        Ok(())
+    }
+
+    async fn firehose_identifier(
+        &self,
+        kind: BlockchainKind,
+        endpoint: &FirehoseEndpoint,
+    ) -> Result<ChainIdentifier, anyhow::Error> {
+        let logger = self.logger.new(o!("provider" => endpoint.provider.clone()));
+        let genesis = match kind {
+            BlockchainKind::Ethereum => {
+                tokio::time::timeout(
+                    NET_VERSION_WAIT_TIME,
+                    endpoint.genesis_block_ptr::<ethereum::codec::HeaderOnlyBlock>(&logger),
+                )
+                .await
+            }
+            BlockchainKind::Near => {
+                tokio::time::timeout(
+                    NET_VERSION_WAIT_TIME,
+                    endpoint.genesis_block_ptr::<near::HeaderOnlyBlock>(&logger),
+                )
+                .await
+            }
+            BlockchainKind::Tendermint => {
+                tokio::time::timeout(
+                    NET_VERSION_WAIT_TIME,
+                    endpoint.genesis_block_ptr::<tendermint::EventList>(&logger),
+                )
+                .await
+            }
+        };
+        Ok(ChainIdentifier {
+            net_version: "0".to_string(),
+            genesis_block_hash: genesis??.hash,
+        })
+    }
+
+    /// Make the providers we use match the ones in `config`: providers that
+    /// are not mentioned in `config` anymore are removed, and providers
+    /// that are new in `config` are connected to and added if they serve
+    /// the right chain. A provider whose configuration changed, for example
+    /// because its URL or credentials were rotated, is removed and then
+    /// added again with its new configuration.
+    pub async fn reload(&self, config: &Config) -> Result<(), anyhow::Error> {
+        let current = self.current_providers();
+
+        // Remove providers that are not in the configuration anymore or
+        // whose configuration changed
+        for (name, labels) in &current {
+            let unchanged: BTreeSet<_> = config
+                .chains
+                .chains
+                .get(name)
+                .map(|chain| {
+                    chain
+                        .providers
+                        .iter()
+                        .filter(|provider| self.is_applied(name, provider))
+                        .map(|provider| provider.label.clone())
+                        .collect()
+                })
+                .unwrap_or_default();
+            for label in labels.difference(&unchanged) {
+                info!(self.logger, "Removing provider";
+                                   "network" => name, "provider" => label);
+                self.eth_networks.remove(name, label);
+                for networks in self.firehose_networks.values() {
+                    if let Some(endpoints) = networks.networks.get(name) {
+                        endpoints.remove(label);
+                    }
+                }
+            }
+        }
+
+        // Only keep providers in the configuration that we do not use yet
+        // with the same configuration so that we only connect to those
+        let mut added = config.clone();
+        added.chains.chains.retain(|name, chain| {
+            if !self.is_known_network(name) {
+                if !chain.providers.is_empty() {
+                    warn!(self.logger, "Ignoring providers for unknown network; adding a network requires a restart";
+                                       "network" => name);
+                }
+                return false;
+            }
+            chain
+                .providers
+                .retain(|provider| !self.is_applied(name, provider));
+            !chain.providers.is_empty()
+        });
+
+        let eth_networks =
+            create_ethereum_networks(self.logger.clone(), self.registry.clone(), &added).await?;
+        for (name, capabilities, adapter) in eth_networks.flatten() {
+            match self.eth_networks.networks.get(&name) {
+                Some(adapters) => {
+                    let ident =
+                        tokio::time::timeout(NET_VERSION_WAIT_TIME, adapter.net_identifiers())
+                            .await
+                            .map_err(anyhow::Error::from)
+                            .and_then(|ident| ident)
+                            .and_then(|ident| self.check_chain_identifier(&name, &ident, true));
+                    if let Err(e) = ident {
+                        error!(self.logger, "Not using new provider";
+                                            "network" => &name, "provider" => adapter.provider(),
+                                            "error" => format!("{:#}", e));
+                        continue;
+                    }
+                    info!(self.logger, "Adding provider";
+                                       "network" => &name, "provider" => adapter.provider());
+                    adapters.insert(capabilities, adapter);
+                }
+                None => {
+                    warn!(self.logger, "Ignoring Ethereum provider for a network that was not configured as an Ethereum network at startup";
+                                           "network" => &name, "provider" => adapter.provider())
+                }
+            }
+        }
+
+        let firehose_networks =
+            create_firehose_networks(self.logger.clone(), self.registry.clone(), &added).await?;
+        for (kind, networks) in firehose_networks {
+            for (name, endpoint) in networks.flatten() {
+                match self
+                    .firehose_networks
+                    .get(&kind)
+                    .and_then(|networks| networks.networks.get(&name))
+                {
+                    Some(endpoints) => {
+                        let ident = self
+                            .firehose_identifier(kind, &endpoint)
+                            .await
+                            .and_then(|ident| self.check_chain_identifier(&name, &ident, false));
+                        if let Err(e) = ident {
+                            error!(self.logger, "Not using new firehose provider";
+                                                "network" => &name, "provider" => &endpoint.provider,
+                                                "error" => format!("{:#}", e));
+                            continue;
+                        }
+                        info!(self.logger, "Adding firehose provider";
+                                           "network" => &name, "provider" => &endpoint.provider);
+                        endpoints.insert(endpoint);
+                    }
+                    None => {
+                        warn!(self.logger, "Ignoring firehose provider for a network that had no firehose providers at startup";
+                                               "network" => &name, "provider" => &endpoint.provider)
+                    }
+                }
+            }
+        }
+
+        for (name, chain) in &config.chains.chains {
+            let has_web3 = chain
+                .providers
+                .iter()
+                .any(|p| matches!(p.details, ProviderDetails::Web3(_)));
+            if self.eth_networks.networks.contains_key(name) && !has_web3 {
+                warn!(self.logger, "Network has no Ethereum RPC providers left"; "network" => name);
+            }
+        }
+
+        self.remember_applied(config);
+
+        Ok(())
+    }
+}
@@ -144,6 +144,7 @@ impl StoreBuilder {
             Arc::new(config.deployment.clone()),
             notification_sender,
             fork_base,
+            registry,
         ));
 
         (store, pools)
@@ -193,7 +194,7 @@ impl StoreBuilder {
         servers: Arc<Vec<ForeignServer>>,
     ) -> ConnectionPool {
         let logger = logger.new(o!("pool" => "main"));
-        let pool_size = shard.pool_size.size_for(node, name).expect(&format!(
+        let (min_pool_size, pool_size) = shard.pool_size.bounds_for(node, name).expect(&format!(
             "cannot determine the pool size for store {}",
             name
         ));
@@ -206,12 +207,14 @@ impl StoreBuilder {
             "Connecting to Postgres";
             "url" => SafeDisplay(shard.connection.as_str()),
             "conn_pool_size" => pool_size,
+            "min_conn_pool_size" => min_pool_size,
             "weight" => shard.weight
         );
         ConnectionPool::create(
             name,
             PoolName::Main,
             shard.connection.to_owned(),
+            min_pool_size,
             pool_size,
             Some(fdw_pool_size),
             &logger,
@@ -245,14 +248,16 @@ impl StoreBuilder {
                         "weight" => replica.weight
                     );
                     weights.push(replica.weight);
-                    let pool_size = replica.pool_size.size_for(node, name).expect(&format!(
-                        "we can determine the pool size for replica {}",
-                        name
-                    ));
+                    let (min_pool_size, pool_size) =
+                        replica.pool_size.bounds_for(node, name).expect(&format!(
+                            "we can determine the pool size for replica {}",
+                            name
+                        ));
                     ConnectionPool::create(
                         name,
                         PoolName::Replica(pool),
                         replica.connection.clone(),
+                        min_pool_size,
                         pool_size,
                         None,
                         &logger,
@@ -43,6 +43,7 @@ fn mock_host_exports(
             event_handlers: vec![],
             call_handlers: vec![],
             block_handlers: vec![],
+            entity_handlers: vec![],
             link: Link {
                 link: "link".to_owned(),
             },
@@ -123,6 +124,7 @@ pub fn mock_data_source(path: &str, api_version: Version) -> DataSource {
         network: Some(String::from("mainnet")),
         source: Source {
             address: Some(Address::from_str("0123123123012312312301231231230123123123").unwrap()),
+            subgraph: None,
             abi: String::from("123123"),
             start_block: 0,
         },
@@ -135,6 +137,7 @@ pub fn mock_data_source(path: &str, api_version: Version) -> DataSource {
             event_handlers: vec![],
             call_handlers: vec![],
             block_handlers: vec![],
+            entity_handlers: vec![],
             link: Link {
                 link: "link".to_owned(),
             },
@@ -0,0 +1,558 @@
+//! A harness for unit tests of mappings. Tests are compiled to a WASM
+//! module like the mappings themselves and run in the same runtime that
+//! indexes subgraphs. Instead of a database, Ethereum node and IPFS, they
+//! use an in-memory store, mocked `ethereum.call` results and mocked IPFS
+//! files that are set up with a [`Fixture`]
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+use ethabi::token::{LenientTokenizer, Tokenizer};
+use ethabi::{Function, Token};
+use graph::blockchain::BlockPtr;
+use graph::components::store::{
+    EnsLookup, EntityType, StoredDynamicDataSource, UnfailOutcome, WritableStore,
+};
+use graph::data::graphql::{DocumentExt, ObjectTypeExt};
+use graph::data::schema::Schema;
+use graph::data::subgraph::schema::{
+    MappingLog, QuarantinedDataSource, SubgraphError, SubgraphHealth,
+};
+use graph::prelude::{
+    anyhow, async_trait, ethabi, futures03, futures03::stream, r, serde_json, web3::types::Address,
+    Arc, BlockState, DeploymentHash, Deserialize, Duration, Entity, EntityKey, EntityModification,
+    Error, HostMetrics, JsonStreamValue, JsonValueStream, Link, LinkResolver, Logger,
+    StopwatchMetrics, StoreError, Value, ENV_VARS,
+};
+use graph::runtime::HostExportError;
+use graph_chain_ethereum::runtime::{mock_ethereum_call, UnresolvedContractCall};
+use graph_chain_ethereum::{Chain, DataSource, DataSourceTemplate, MappingABI};
+use graph_mock::MockMetricsRegistry;
+use graph_runtime_wasm::{
+    ExperimentalFeatures, HostExports, MappingContext, ValidModule, WasmInstance,
+};
+
+/// The prefix of the names of exported functions that are tests
+const TEST_PREFIX: &str = "test";
+
+/// The entities, contract calls and IPFS files that a test can see. In
+/// JSON, entities are listed by entity type and their attributes are
+/// converted to the types in the schema; arguments and results of calls
+/// are converted to the types in the ABI of the contract
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Fixture {
+    #[serde(default)]
+    pub entities: BTreeMap<String, Vec<serde_json::Map<String, serde_json::Value>>>,
+    #[serde(default)]
+    pub calls: Vec<MockCall>,
+    #[serde(default)]
+    pub files: HashMap<String, String>,
+}
+
+/// The result of an `ethereum.call`
+#[derive(Clone, Debug, Deserialize)]
+pub struct MockCall {
+    /// The name of the ABI of the contract
+    pub contract: String,
+    pub address: Address,
+    /// Either the name of the function or its full signature, e.g.,
+    /// `balanceOf(address):(uint256)`
+    pub function: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// The values the call returns; the call reverts if this is `null`
+    pub result: Option<Vec<String>>,
+}
+
+/// A mocked call with arguments and results in the form the runtime uses
+struct ResolvedCall {
+    contract: String,
+    address: Address,
+    function: Function,
+    args: Vec<Token>,
+    result: Option<Vec<Token>>,
+}
+
+impl ResolvedCall {
+    fn resolve(call: &MockCall, abis: &[Arc<MappingABI>]) -> Result<Self, Error> {
+        let abi = abis
+            .iter()
+            .find(|abi| abi.name == call.contract)
+            .ok_or_else(|| anyhow!("there is no ABI for contract {}", call.contract))?;
+        let function = abi
+            .contract
+            .functions()
+            .find(|f| f.name == call.function || f.signature() == call.function)
+            .ok_or_else(|| {
+                anyhow!(
+                    "contract {} has no function {}",
+                    call.contract,
+                    call.function
+                )
+            })?
+            .clone();
+
+        fn tokenize(
+            params: &[ethabi::Param],
+            values: &[String],
+            what: &str,
+        ) -> Result<Vec<Token>, Error> {
+            if params.len() != values.len() {
+                return Err(anyhow!(
+                    "expected {} {} but got {}",
+                    params.len(),
+                    what,
+                    values.len()
+                ));
+            }
+            params
+                .iter()
+                .zip(values)
+                .map(|(param, value)| {
+                    LenientTokenizer::tokenize(&param.kind, value)
+                        .map_err(|e| anyhow!("invalid {} `{}`: {}", param.kind, value, e))
+                })
+                .collect()
+        }
+
+        let args = tokenize(&function.inputs, &call.args, "arguments")?;
+        let result = call
+            .result
+            .as_ref()
+            .map(|result| tokenize(&function.outputs, result, "results"))
+            .transpose()?;
+
+        Ok(Self {
+            contract: call.contract.clone(),
+            address: call.address,
+            function,
+            args,
+            result,
+        })
+    }
+
+    fn matches(&self, call: &UnresolvedContractCall) -> bool {
+        let signature = call
+            .function_signature
+            .as_ref()
+            .map(|sig| sig == &self.function.signature())
+            .unwrap_or(call.function_name == self.function.name);
+        self.contract == call.contract_name
+            && self.address == call.contract_address
+            && signature
+            && self.args == call.function_args
+    }
+}
+
+/// An in-memory store. It holds the entities from the fixture and
+/// applies the changes of every block that is transacted
+pub struct MockStore {
+    deployment: DeploymentHash,
+    schema: Arc<Schema>,
+    entities: Mutex<BTreeMap<(EntityType, String), Entity>>,
+}
+
+impl MockStore {
+    pub fn new(deployment: DeploymentHash, schema: Arc<Schema>) -> Self {
+        Self {
+            deployment,
+            schema,
+            entities: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Add the entities from `fixture`, converting their attributes to the
+    /// types declared in the schema
+    pub fn load(&self, fixture: &Fixture) -> Result<(), Error> {
+        for (entity_type, entities) in &fixture.entities {
+            let object_type = self
+                .schema
+                .document
+                .get_object_type_definition(entity_type)
+                .ok_or_else(|| anyhow!("entity type {} is not in the schema", entity_type))?;
+            for json in entities {
+                let mut entity = Entity::new();
+                for (name, value) in json {
+                    let field = object_type.field(name).ok_or_else(|| {
+                        anyhow!("entity type {} has no attribute {}", entity_type, name)
+                    })?;
+                    let value =
+                        Value::from_query_value(&r::Value::from(value.clone()), &field.field_type)
+                            .map_err(|e| anyhow!("{}.{}: {}", entity_type, name, e))?;
+                    entity.insert(name.clone(), value);
+                }
+                let id = entity.id()?;
+                self.entities
+                    .lock()
+                    .unwrap()
+                    .insert((EntityType::new(entity_type.clone()), id), entity);
+            }
+        }
+        Ok(())
+    }
+
+    /// All entities in the store, sorted by entity type and id
+    pub fn entities(&self) -> Vec<(EntityKey, Entity)> {
+        self.entities
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((entity_type, id), entity)| {
+                let key = EntityKey {
+                    subgraph_id: self.deployment.clone(),
+                    entity_type: entity_type.clone(),
+                    entity_id: id.clone(),
+                };
+                (key, entity.clone())
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl WritableStore for MockStore {
+    async fn block_ptr(&self) -> Option<BlockPtr> {
+        None
+    }
+
+    async fn block_cursor(&self) -> Option<String> {
+        None
+    }
+
+    async fn delete_block_cursor(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    async fn start_subgraph_deployment(&self, _logger: &Logger) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    async fn revert_block_operations(
+        &self,
+        _block_ptr_to: BlockPtr,
+        _firehose_cursor: Option<&str>,
+    ) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    async fn unfail_deterministic_error(
+        &self,
+        _current_ptr: &BlockPtr,
+        _parent_ptr: &BlockPtr,
+    ) -> Result<UnfailOutcome, StoreError> {
+        Ok(UnfailOutcome::Noop)
+    }
+
+    async fn unfail_non_deterministic_error(
+        &self,
+        _current_ptr: &BlockPtr,
+    ) -> Result<UnfailOutcome, StoreError> {
+        Ok(UnfailOutcome::Noop)
+    }
+
+    async fn fail_subgraph(&self, _error: SubgraphError) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    async fn supports_proof_of_indexing(&self) -> Result<bool, StoreError> {
+        Ok(false)
+    }
+
+    fn get(&self, key: &EntityKey) -> Result<Option<Entity>, StoreError> {
+        let entities = self.entities.lock().unwrap();
+        Ok(entities
+            .get(&(key.entity_type.clone(), key.entity_id.clone()))
+            .cloned())
+    }
+
+    async fn transact_block_operations(
+        &self,
+        _block_ptr_to: BlockPtr,
+        _firehose_cursor: Option<String>,
+        mods: Vec<EntityModification>,
+        _stopwatch: &StopwatchMetrics,
+        _data_sources: Vec<StoredDynamicDataSource>,
+        _deterministic_errors: Vec<SubgraphError>,
+        _mapping_logs: Vec<MappingLog>,
+        _quarantined: Vec<QuarantinedDataSource>,
+        _bulk: bool,
+    ) -> Result<(), StoreError> {
+        let mut entities = self.entities.lock().unwrap();
+        for m in mods {
+            match m {
+                EntityModification::Insert { key, data }
+                | EntityModification::Overwrite { key, data } => {
+                    entities.insert((key.entity_type, key.entity_id), data);
+                }
+                EntityModification::Remove { key } => {
+                    entities.remove(&(key.entity_type, key.entity_id));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn get_many(
+        &self,
+        ids_for_type: BTreeMap<&EntityType, Vec<&str>>,
+    ) -> Result<BTreeMap<EntityType, Vec<Entity>>, StoreError> {
+        let entities = self.entities.lock().unwrap();
+        let mut found = BTreeMap::new();
+        for (entity_type, ids) in ids_for_type {
+            let matches = ids
+                .into_iter()
+                .filter_map(|id| entities.get(&(entity_type.clone(), id.to_owned())))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !matches.is_empty() {
+                found.insert(entity_type.clone(), matches);
+            }
+        }
+        Ok(found)
+    }
+
+    async fn deployment_synced(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    async fn promote_canary(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    async fn is_deployment_synced(&self) -> Result<bool, StoreError> {
+        Ok(false)
+    }
+
+    async fn unassign_subgraph(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    async fn load_dynamic_data_sources(&self) -> Result<Vec<StoredDynamicDataSource>, StoreError> {
+        Ok(vec![])
+    }
+
+    fn shard(&self) -> &str {
+        "mock"
+    }
+
+    async fn health(&self, _id: &DeploymentHash) -> Result<SubgraphHealth, StoreError> {
+        Ok(SubgraphHealth::Healthy)
+    }
+
+    fn input_schema(&self) -> Arc<Schema> {
+        self.schema.clone()
+    }
+
+    async fn flush(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
+
+/// A link resolver that serves the files from the fixture. Links can be
+/// given with or without the `/ipfs/` prefix
+#[derive(Clone, Debug, Default)]
+pub struct MockLinkResolver {
+    files: Arc<HashMap<String, Vec<u8>>>,
+}
+
+impl MockLinkResolver {
+    pub fn new(files: &HashMap<String, String>) -> Self {
+        let files = files
+            .iter()
+            .map(|(link, contents)| (link.clone(), contents.as_bytes().to_vec()))
+            .collect();
+        Self {
+            files: Arc::new(files),
+        }
+    }
+}
+
+#[async_trait]
+impl LinkResolver for MockLinkResolver {
+    fn with_timeout(&self, _timeout: Duration) -> Box<dyn LinkResolver> {
+        Box::new(self.clone())
+    }
+
+    fn with_retries(&self) -> Box<dyn LinkResolver> {
+        Box::new(self.clone())
+    }
+
+    async fn cat(&self, _logger: &Logger, link: &Link) -> Result<Vec<u8>, Error> {
+        let path = link.link.trim_start_matches("/ipfs/");
+        self.files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| anyhow!("the fixture has no file {}", link.link))
+    }
+
+    async fn json_stream(&self, logger: &Logger, link: &Link) -> Result<JsonValueStream, Error> {
+        let bytes = self.cat(logger, link).await?;
+        let text = String::from_utf8(bytes)?;
+
+        let values = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line)
+                    .map(|value| JsonStreamValue { value, line: i + 1 })
+                    .map_err(|e| anyhow!("{} at line {}: '{}'", e, i + 1, line))
+            })
+            .collect::<Vec<_>>();
+        Ok(Box::pin(stream::iter(values)))
+    }
+}
+
+struct MockEnsLookup;
+
+impl EnsLookup for MockEnsLookup {
+    fn find_name(&self, _hash: &str) -> Result<Option<String>, StoreError> {
+        Ok(None)
+    }
+}
+
+/// Runs the tests in a WASM module in the context of one data source
+pub struct TestHarness {
+    logger: Logger,
+    deployment: DeploymentHash,
+    data_source: DataSource,
+    templates: Arc<Vec<DataSourceTemplate>>,
+    schema: Arc<Schema>,
+    fixture: Fixture,
+}
+
+impl TestHarness {
+    pub fn new(
+        logger: Logger,
+        deployment: DeploymentHash,
+        data_source: DataSource,
+        templates: Vec<DataSourceTemplate>,
+        schema: Arc<Schema>,
+    ) -> Self {
+        Self {
+            logger,
+            deployment,
+            data_source,
+            templates: Arc::new(templates),
+            schema,
+            fixture: Fixture::default(),
+        }
+    }
+
+    pub fn with_fixture(self, fixture: Fixture) -> Self {
+        Self { fixture, ..self }
+    }
+
+    /// The names of the tests in `module`, i.e., of all exported functions
+    /// whose name starts with `test`
+    pub fn tests(module: &ValidModule) -> Vec<String> {
+        let mut tests = module
+            .module
+            .exports()
+            .filter(|export| export.ty().func().is_some())
+            .map(|export| export.name().to_string())
+            .filter(|name| name.starts_with(TEST_PREFIX))
+            .collect::<Vec<_>>();
+        tests.sort();
+        tests
+    }
+
+    /// Run the test `name` from `module` against a fresh copy of the
+    /// fixture. The test fails if it traps or causes a deterministic
+    /// error. On success, return the store with the changes the test made
+    pub fn run(&self, module: Arc<ValidModule>, name: &str) -> Result<Arc<MockStore>, Error> {
+        let store = Arc::new(MockStore::new(self.deployment.clone(), self.schema.clone()));
+        store.load(&self.fixture)?;
+
+        let calls = self
+            .fixture
+            .calls
+            .iter()
+            .map(|call| ResolvedCall::resolve(call, &self.data_source.mapping.abis))
+            .collect::<Result<Vec<_>, _>>()?;
+        let respond = move |call: &UnresolvedContractCall| {
+            calls
+                .iter()
+                .find(|mock| mock.matches(call))
+                .map(|mock| mock.result.clone())
+                .ok_or_else(|| {
+                    HostExportError::Deterministic(anyhow!(
+                        "the fixture has no result for the call of {}::{} at {:?} with {:?}",
+                        call.contract_name,
+                        call.function_name,
+                        call.contract_address,
+                        call.function_args
+                    ))
+                })
+        };
+
+        let network = self.data_source.network.clone().unwrap_or_default();
+        let host_exports = HostExports::new(
+            self.deployment.clone(),
+            &self.data_source,
+            network,
+            self.templates.clone(),
+            Arc::new(MockLinkResolver::new(&self.fixture.files)),
+            Arc::new(MockEnsLookup),
+        );
+        let ctx = MappingContext {
+            logger: self.logger.clone(),
+            block_ptr: BlockPtr {
+                hash: Default::default(),
+                number: self.data_source.source.start_block,
+            },
+            host_exports: Arc::new(host_exports),
+            state: BlockState::new(store.clone(), Default::default()),
+            proof_of_indexing: None,
+            host_fns: Arc::new(vec![mock_ethereum_call(Arc::new(respond))]),
+            debug_fork: None,
+        };
+
+        let registry = Arc::new(MockMetricsRegistry::new());
+        let stopwatch = StopwatchMetrics::new(
+            self.logger.clone(),
+            self.deployment.clone(),
+            registry.clone(),
+        );
+        let host_metrics = Arc::new(HostMetrics::new(
+            registry,
+            self.deployment.as_str(),
+            stopwatch,
+        ));
+
+        let instance = WasmInstance::<Chain>::from_valid_module_with_ctx(
+            module,
+            ctx,
+            host_metrics,
+            ENV_VARS.mappings.timeout,
+            ExperimentalFeatures {
+                allow_non_deterministic_ipfs: true,
+            },
+        )?;
+        let state = instance.invoke_test(name)?;
+        if let Some(error) = state.deterministic_errors.first() {
+            return Err(anyhow!("{}", error.message));
+        }
+
+        let mods = state.entity_cache.as_modifications()?.modifications;
+        // The mock store never awaits anything, so blocking on it is fine
+        futures03::executor::block_on(store.transact_block_operations(
+            BlockPtr {
+                hash: Default::default(),
+                number: self.data_source.source.start_block,
+            },
+            None,
+            mods,
+            &StopwatchMetrics::new(
+                self.logger.clone(),
+                self.deployment.clone(),
+                Arc::new(MockMetricsRegistry::new()),
+            ),
+            vec![],
+            vec![],
+            vec![],
+            vec![],
+            false,
+        ))?;
+        Ok(store)
+    }
+}
@@ -1,3 +1,4 @@
 pub mod common;
+pub mod harness;
 #[cfg(test)]
 mod test;
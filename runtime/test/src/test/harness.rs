@@ -0,0 +1,50 @@
+use graph::data::schema::Schema;
+use graph::prelude::{serde_json, Arc, DeploymentHash, Value};
+
+use crate::harness::{Fixture, MockStore};
+
+const SCHEMA: &str = "
+    type Account @entity {
+        id: ID!
+        balance: BigInt!
+        owner: Bytes
+        active: Boolean!
+    }";
+
+fn mock_store() -> MockStore {
+    let id = DeploymentHash::new("harness").unwrap();
+    let schema = Schema::parse(SCHEMA, id.clone()).unwrap();
+    MockStore::new(id, Arc::new(schema))
+}
+
+#[test]
+fn fixture_entities_follow_schema() {
+    let fixture: Fixture = serde_json::from_str(
+        r#"{ "entities": { "Account": [
+              { "id": "a1", "balance": "12345678901234567890",
+                "owner": "0x0102", "active": true } ] } }"#,
+    )
+    .unwrap();
+
+    let store = mock_store();
+    store.load(&fixture).unwrap();
+    let entities = store.entities();
+    assert_eq!(1, entities.len());
+
+    let (key, entity) = &entities[0];
+    assert_eq!("a1", key.entity_id);
+    assert!(matches!(entity.get("balance"), Some(Value::BigInt(_))));
+    assert!(matches!(entity.get("owner"), Some(Value::Bytes(_))));
+    assert_eq!(Some(&Value::Bool(true)), entity.get("active"));
+}
+
+#[test]
+fn fixture_with_unknown_attribute() {
+    let fixture: Fixture = serde_json::from_str(
+        r#"{ "entities": { "Account": [ { "id": "a1", "nickname": "x" } ] } }"#,
+    )
+    .unwrap();
+
+    let err = mock_store().load(&fixture).unwrap_err();
+    assert!(err.to_string().contains("no attribute nickname"));
+}
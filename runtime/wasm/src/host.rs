@@ -17,11 +17,13 @@ use graph::prelude::{
 use crate::mapping::{MappingContext, MappingRequest};
 use crate::{host_exports::HostExports, module::ExperimentalFeatures};
 use graph::runtime::gas::Gas;
+use graph::util::otel::Span;
 
 pub struct RuntimeHostBuilder<C: Blockchain> {
     runtime_adapter: Arc<C::RuntimeAdapter>,
     link_resolver: Arc<dyn LinkResolver>,
     ens_lookup: Arc<dyn EnsLookup>,
+    timeout: Option<Duration>,
 }
 
 impl<C: Blockchain> Clone for RuntimeHostBuilder<C> {
@@ -30,6 +32,7 @@ impl<C: Blockchain> Clone for RuntimeHostBuilder<C> {
             runtime_adapter: self.runtime_adapter.cheap_clone(),
             link_resolver: self.link_resolver.cheap_clone(),
             ens_lookup: self.ens_lookup.cheap_clone(),
+            timeout: self.timeout,
         }
     }
 }
@@ -44,8 +47,15 @@ impl<C: Blockchain> RuntimeHostBuilder<C> {
             runtime_adapter,
             link_resolver,
             ens_lookup,
+            timeout: ENV_VARS.mappings.timeout,
         }
     }
+
+    /// Use `timeout` as the handler timeout for mappings spawned by this
+    /// builder instead of `GRAPH_MAPPING_HANDLER_TIMEOUT`
+    pub fn with_timeout(self, timeout: Option<Duration>) -> Self {
+        RuntimeHostBuilder { timeout, ..self }
+    }
 }
 
 impl<C: Blockchain> RuntimeHostBuilderTrait<C> for RuntimeHostBuilder<C> {
@@ -53,6 +63,7 @@ impl<C: Blockchain> RuntimeHostBuilderTrait<C> for RuntimeHostBuilder<C> {
     type Req = MappingRequest<C>;
 
     fn spawn_mapping(
+        &self,
         raw_module: Vec<u8>,
         logger: Logger,
         subgraph_id: DeploymentHash,
@@ -67,7 +78,7 @@ impl<C: Blockchain> RuntimeHostBuilderTrait<C> for RuntimeHostBuilder<C> {
             subgraph_id,
             metrics,
             tokio::runtime::Handle::current(),
-            ENV_VARS.mappings.timeout,
+            self.timeout,
             experimental_features,
         )
     }
@@ -161,6 +172,10 @@ where
             "data_source" => &self.data_source.name(),
         );
 
+        let mut span = Span::new("mapping_handler")
+            .attr("handler", &handler)
+            .attr("data_source", self.data_source.name())
+            .attr("block.number", block_ptr.number);
         let (result_sender, result_receiver) = channel();
         let start_time = Instant::now();
         let metrics = self.metrics.clone();
@@ -189,10 +204,20 @@ where
             .context("Mapping terminated before handling trigger")?;
 
         let elapsed = start_time.elapsed();
-        metrics.observe_handler_execution_time(elapsed.as_secs_f64(), &handler);
+        metrics.observe_handler_execution_time(
+            elapsed.as_secs_f64(),
+            &handler,
+            self.data_source.name(),
+        );
 
         // If there is an error, "gas_used" is incorrectly reported as 0.
         let gas_used = result.as_ref().map(|(_, gas)| gas).unwrap_or(&Gas::ZERO);
+        metrics.trace_handler(&handler, self.data_source.name(), gas_used.value(), elapsed);
+        span.set_attr("gas_used", gas_used);
+        if let Err(MappingError::Unknown(e) | MappingError::PossibleReorg(e)) = &result {
+            span.set_error(format!("{:#}", e));
+        }
+        span.end();
         info!(
             logger, "Done processing trigger";
             &extras,
@@ -241,6 +266,10 @@ impl<C: Blockchain> RuntimeHostTrait<C> for RuntimeHost<C> {
     fn creation_block_number(&self) -> Option<BlockNumber> {
         self.data_source.creation_block()
     }
+
+    fn data_source(&self) -> &C::DataSource {
+        &self.data_source
+    }
 }
 
 impl<C: Blockchain> PartialEq for RuntimeHost<C> {
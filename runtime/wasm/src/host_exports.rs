@@ -14,6 +14,7 @@ use graph::components::store::EntityType;
 use graph::components::store::{EnsLookup, EntityKey};
 use graph::components::subgraph::{CausalityRegion, ProofOfIndexingEvent, SharedProofOfIndexing};
 use graph::data::store;
+use graph::data::subgraph::schema::MappingLog;
 use graph::ensure;
 use graph::prelude::ethabi::param_type::Reader;
 use graph::prelude::ethabi::{decode, encode, Token};
@@ -645,6 +646,8 @@ impl<C: Blockchain> HostExports<C> {
     pub(crate) fn log_log(
         &self,
         logger: &Logger,
+        state: &mut BlockState<C>,
+        block_number: BlockNumber,
         level: slog::Level,
         msg: String,
         gas: &GasCounter,
@@ -659,6 +662,15 @@ impl<C: Blockchain> HostExports<C> {
             b!("data_source" => &self.data_source_name),
         ));
 
+        if ENV_VARS.store.mapping_log_limit > 0 {
+            state.push_mapping_log(MappingLog {
+                block_number,
+                level,
+                data_source: self.data_source_name.clone(),
+                message: msg,
+            });
+        }
+
         if level == slog::Level::Critical {
             return Err(DeterministicHostError::from(anyhow!(
                 "Critical error logged in mapping"
@@ -119,6 +119,24 @@ impl<C: Blockchain> WasmInstance<C> {
         Ok(self.take_ctx().ctx.state)
     }
 
+    /// Call the exported function `name`, which takes no arguments and
+    /// returns nothing, the way a handler is called, and return the
+    /// changes it made. Used to run unit tests for mappings
+    pub fn invoke_test(mut self, name: &str) -> Result<BlockState<C>, anyhow::Error> {
+        self.instance_ctx_mut().ctx.state.enter_handler();
+
+        self.instance
+            .get_func(name)
+            .with_context(|| format!("function {} not found", name))?
+            .typed::<(), ()>()?
+            .call(())
+            .with_context(|| format!("Failed to run test '{}'", name))?;
+
+        self.instance_ctx_mut().ctx.state.exit_handler();
+
+        Ok(self.take_ctx().ctx.state)
+    }
+
     pub(crate) fn handle_trigger(
         mut self,
         trigger: TriggerWithHandler<C>,
@@ -1596,9 +1614,15 @@ impl<C: Blockchain> WasmInstanceContext<C> {
     ) -> Result<(), DeterministicHostError> {
         let level = LogLevel::from(level).into();
         let msg: String = asc_get(self, msg, gas)?;
-        self.ctx
-            .host_exports
-            .log_log(&self.ctx.logger, level, msg, gas)
+        let block_number = self.ctx.block_ptr.number;
+        self.ctx.host_exports.log_log(
+            &self.ctx.logger,
+            &mut self.ctx.state,
+            block_number,
+            level,
+            msg,
+            gas,
+        )
     }
 
     /// function encode(token: ethereum.Value): Bytes | null
@@ -0,0 +1,505 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use graph::prelude::reqwest::Url;
+use graph::prelude::*;
+use http::header::AUTHORIZATION;
+use http::{HeaderMap, StatusCode};
+use lru_time_cache::LruCache;
+
+/// The length of the window in which we count requests and complexity for
+/// rate limiting
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// The limits for queries that are made with one API key
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct ApiKeyLimits {
+    /// The name under which we report metrics for the key so that the key
+    /// itself does not show up in metrics or logs
+    pub name: String,
+    /// How many queries can be made with the key per minute. Unlimited if
+    /// not set
+    #[serde(default)]
+    pub requests_per_minute: Option<u32>,
+    /// The maximum complexity of each query made with the key. Defaults to
+    /// `GRAPH_GRAPHQL_MAX_COMPLEXITY`
+    #[serde(default)]
+    pub max_complexity: Option<u64>,
+    /// The total complexity of the queries that can be made with the key
+    /// per minute. Unlimited if not set
+    #[serde(default)]
+    pub complexity_per_minute: Option<u64>,
+}
+
+/// A source of the limits for API keys
+#[async_trait]
+pub trait ApiKeyLookup: Send + Sync + 'static {
+    /// Return the limits for `key`, or `None` if the key is not known
+    async fn lookup(&self, key: &str) -> Result<Option<ApiKeyLimits>, Error>;
+}
+
+/// API keys that are read from a JSON file mapping each key to its limits
+pub struct StaticApiKeys {
+    keys: HashMap<String, ApiKeyLimits>,
+}
+
+impl StaticApiKeys {
+    pub fn new(keys: HashMap<String, ApiKeyLimits>) -> Self {
+        StaticApiKeys { keys }
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read API keys file {}", path))?;
+        let keys = serde_json::from_str(&text)
+            .with_context(|| format!("invalid API keys file {}", path))?;
+        Ok(Self::new(keys))
+    }
+}
+
+#[async_trait]
+impl ApiKeyLookup for StaticApiKeys {
+    async fn lookup(&self, key: &str) -> Result<Option<ApiKeyLimits>, Error> {
+        Ok(self.keys.get(key).cloned())
+    }
+}
+
+/// API keys that are looked up by sending `GET <url>/<key>` to an
+/// external service. The limits for up to `capacity` known keys are cached
+/// for `ttl`; unknown keys are not cached so that clients can not fill the
+/// cache with made up keys
+pub struct ExternalApiKeys {
+    url: Url,
+    client: reqwest::Client,
+    cache: Mutex<LruCache<String, ApiKeyLimits>>,
+}
+
+impl ExternalApiKeys {
+    pub fn new(url: &str, capacity: usize, ttl: Duration) -> Result<Self, Error> {
+        let url = Url::parse(url).with_context(|| format!("invalid API keys URL {}", url))?;
+        if url.cannot_be_a_base() {
+            return Err(anyhow!("invalid API keys URL {}", url));
+        }
+        Ok(ExternalApiKeys {
+            url,
+            client: reqwest::Client::new(),
+            cache: Mutex::new(LruCache::with_expiry_duration_and_capacity(ttl, capacity)),
+        })
+    }
+
+    /// The URL for looking up `key`. The key becomes the last path segment
+    /// of the URL and is percent-encoded so that it can not change any
+    /// other part of the URL
+    fn key_url(&self, key: &str) -> Url {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .expect("the URL can be a base")
+            .pop_if_empty()
+            .push(key);
+        url
+    }
+
+    async fn fetch(&self, key: &str) -> Result<Option<ApiKeyLimits>, Error> {
+        let response = self.client.get(self.key_url(key)).send().await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let limits = response.error_for_status()?.json().await?;
+        Ok(Some(limits))
+    }
+}
+
+#[async_trait]
+impl ApiKeyLookup for ExternalApiKeys {
+    async fn lookup(&self, key: &str) -> Result<Option<ApiKeyLimits>, Error> {
+        if let Some(limits) = self.cache.lock().unwrap().get(key) {
+            return Ok(Some(limits.clone()));
+        }
+
+        let limits = self.fetch(key).await?;
+        if let Some(limits) = &limits {
+            self.cache
+                .lock()
+                .unwrap()
+                .insert(key.to_string(), limits.clone());
+        }
+        Ok(limits)
+    }
+}
+
+/// The reasons why we refuse to run a query
+#[derive(Debug)]
+pub enum AuthError {
+    /// The request did not contain an API key
+    MissingKey,
+    /// The API key in the request is not known
+    UnknownKey,
+    /// The key has been used for too many queries, or for queries that
+    /// are too complex, in the current window
+    RateLimited(String),
+    /// Looking up the key failed
+    Lookup(Error),
+}
+
+impl AuthError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AuthError::MissingKey | AuthError::UnknownKey => StatusCode::UNAUTHORIZED,
+            AuthError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AuthError::Lookup(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthError::MissingKey => write!(f, "an API key is required"),
+            AuthError::UnknownKey => write!(f, "unknown API key"),
+            AuthError::RateLimited(name) => {
+                write!(f, "the rate limit for API key `{}` was exceeded", name)
+            }
+            AuthError::Lookup(e) => write!(f, "failed to look up API key: {}", e),
+        }
+    }
+}
+
+struct ApiKeyMetrics {
+    queries: Box<CounterVec>,
+    rejected_queries: Box<CounterVec>,
+}
+
+impl ApiKeyMetrics {
+    fn new(registry: Arc<impl MetricsRegistry>) -> Self {
+        let queries = registry
+            .new_counter_vec(
+                "query_api_key_queries",
+                "The number of GraphQL queries made with each API key",
+                vec![String::from("api_key")],
+            )
+            .expect("failed to create `query_api_key_queries` counter");
+        let rejected_queries = registry
+            .new_counter_vec(
+                "query_api_key_rejected_queries",
+                "The number of GraphQL queries with each API key that were rejected",
+                vec![String::from("api_key"), String::from("reason")],
+            )
+            .expect("failed to create `query_api_key_rejected_queries` counter");
+        Self {
+            queries,
+            rejected_queries,
+        }
+    }
+}
+
+/// The usage of one key in the current rate limit window
+struct Window {
+    start: Instant,
+    requests: u32,
+    complexity: u64,
+}
+
+impl Window {
+    fn new() -> Self {
+        Window {
+            start: Instant::now(),
+            requests: 0,
+            complexity: 0,
+        }
+    }
+}
+
+/// Checks the API key of requests to the GraphQL and WebSocket servers and
+/// enforces the rate limits for each key
+pub struct ApiKeyAuth {
+    lookup: Arc<dyn ApiKeyLookup>,
+    /// For each key name, the usage in the current rate limit window
+    windows: Mutex<HashMap<String, Window>>,
+    metrics: ApiKeyMetrics,
+}
+
+impl ApiKeyAuth {
+    pub fn new(lookup: Arc<dyn ApiKeyLookup>, registry: Arc<impl MetricsRegistry>) -> Self {
+        ApiKeyAuth {
+            lookup,
+            windows: Mutex::new(HashMap::new()),
+            metrics: ApiKeyMetrics::new(registry),
+        }
+    }
+
+    /// Create the API key checks as configured by
+    /// `GRAPH_GRAPHQL_API_KEYS_FILE` or `GRAPH_GRAPHQL_API_KEYS_URL`.
+    /// Return `None` if neither is set
+    pub fn from_env(registry: Arc<impl MetricsRegistry>) -> Result<Option<Self>, Error> {
+        let lookup: Arc<dyn ApiKeyLookup> = match (
+            &ENV_VARS.graphql.api_keys_file,
+            &ENV_VARS.graphql.api_keys_url,
+        ) {
+            (Some(path), _) => Arc::new(StaticApiKeys::from_file(path)?),
+            (None, Some(url)) => Arc::new(ExternalApiKeys::new(
+                url,
+                ENV_VARS.graphql.api_keys_cache_size,
+                ENV_VARS.graphql.api_keys_cache_ttl,
+            )?),
+            (None, None) => return Ok(None),
+        };
+        Ok(Some(Self::new(lookup, registry)))
+    }
+
+    /// Check the API key in the `Authorization` header of a request and
+    /// return the limits for it
+    pub async fn authorize(&self, headers: &HeaderMap) -> Result<ApiKeyLimits, AuthError> {
+        let key = match api_key(headers) {
+            Some(key) => key,
+            None => {
+                self.reject("", "missing");
+                return Err(AuthError::MissingKey);
+            }
+        };
+
+        let limits = match self.lookup.lookup(key).await {
+            Ok(Some(limits)) => limits,
+            Ok(None) => {
+                self.reject("", "unknown");
+                return Err(AuthError::UnknownKey);
+            }
+            Err(e) => return Err(AuthError::Lookup(e)),
+        };
+
+        self.authorize_more(&limits, 1)?;
+        Ok(limits)
+    }
+
+    /// Count `count` queries that are made with the key of `limits`
+    /// against its rate limit. Requests that contain a batch of queries
+    /// are checked with `authorize` for their first query and with this
+    /// method for the rest of the batch
+    pub fn authorize_more(&self, limits: &ApiKeyLimits, count: u32) -> Result<(), AuthError> {
+        if !self.acquire(limits, count) {
+            self.reject(&limits.name, "rate_limit");
+            return Err(AuthError::RateLimited(limits.name.clone()));
+        }
+
+        self.metrics
+            .queries
+            .with_label_values(&[&limits.name])
+            .inc_by(count as f64);
+        Ok(())
+    }
+
+    /// The maximum complexity of the next query made with the key: the
+    /// smaller of the maximum complexity of each query and what is left of
+    /// the complexity budget in the current window. The global and
+    /// per-deployment limits are applied on top of this when the query runs
+    pub fn max_complexity(&self, limits: &ApiKeyLimits) -> Option<u64> {
+        let max_complexity = limits.max_complexity;
+        let budget = match limits.complexity_per_minute {
+            Some(budget) => budget,
+            None => return max_complexity,
+        };
+
+        let mut windows = self.windows.lock().unwrap();
+        let left = budget.saturating_sub(Self::window(&mut windows, &limits.name).complexity);
+        Some(max_complexity.map_or(left, |max| max.min(left)))
+    }
+
+    /// Charge the `complexity` of a query that was made with the key
+    /// against its complexity budget
+    pub fn charge(&self, limits: &ApiKeyLimits, complexity: u64) {
+        if limits.complexity_per_minute.is_none() {
+            return;
+        }
+
+        let mut windows = self.windows.lock().unwrap();
+        let window = Self::window(&mut windows, &limits.name);
+        window.complexity = window.complexity.saturating_add(complexity);
+    }
+
+    /// Count `count` requests against the rate limit for their key, and
+    /// return `false` if the key does not have that many requests or has
+    /// no complexity left in the current window
+    fn acquire(&self, limits: &ApiKeyLimits, count: u32) -> bool {
+        if limits.requests_per_minute.is_none() && limits.complexity_per_minute.is_none() {
+            return true;
+        }
+
+        let mut windows = self.windows.lock().unwrap();
+        let window = Self::window(&mut windows, &limits.name);
+        if let Some(max_requests) = limits.requests_per_minute {
+            if window.requests.saturating_add(count) > max_requests {
+                return false;
+            }
+        }
+        if let Some(budget) = limits.complexity_per_minute {
+            if window.complexity >= budget {
+                return false;
+            }
+        }
+        window.requests = window.requests.saturating_add(count);
+        true
+    }
+
+    /// The current window for the key `name`, starting a new one if the
+    /// last one has ended
+    fn window<'a>(windows: &'a mut HashMap<String, Window>, name: &str) -> &'a mut Window {
+        let window = windows.entry(name.to_string()).or_insert_with(Window::new);
+        if window.start.elapsed() >= RATE_LIMIT_WINDOW {
+            *window = Window::new();
+        }
+        window
+    }
+
+    fn reject(&self, name: &str, reason: &str) {
+        self.metrics
+            .rejected_queries
+            .with_label_values(&[name, reason])
+            .inc();
+    }
+}
+
+/// Extract the API key from an `Authorization: Bearer <key>` header
+fn api_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use graph::prelude::tokio;
+    use graph_mock::MockMetricsRegistry;
+    use http::header::AUTHORIZATION;
+    use http::{HeaderMap, HeaderValue, StatusCode};
+
+    use super::{ApiKeyAuth, ApiKeyLimits, ExternalApiKeys, StaticApiKeys};
+
+    fn auth() -> ApiKeyAuth {
+        let keys = HashMap::from([
+            (
+                "limited".to_string(),
+                ApiKeyLimits {
+                    name: "limited".to_string(),
+                    requests_per_minute: Some(2),
+                    max_complexity: Some(100),
+                    complexity_per_minute: None,
+                },
+            ),
+            (
+                "unlimited".to_string(),
+                ApiKeyLimits {
+                    name: "unlimited".to_string(),
+                    requests_per_minute: None,
+                    max_complexity: None,
+                    complexity_per_minute: None,
+                },
+            ),
+            (
+                "budget".to_string(),
+                ApiKeyLimits {
+                    name: "budget".to_string(),
+                    requests_per_minute: None,
+                    max_complexity: Some(100),
+                    complexity_per_minute: Some(250),
+                },
+            ),
+        ]);
+        ApiKeyAuth::new(
+            Arc::new(StaticApiKeys::new(keys)),
+            Arc::new(MockMetricsRegistry::new()),
+        )
+    }
+
+    fn headers(key: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", key)).unwrap(),
+        );
+        headers
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_and_unknown_keys() {
+        let auth = auth();
+
+        let err = auth.authorize(&HeaderMap::new()).await.unwrap_err();
+        assert_eq!(StatusCode::UNAUTHORIZED, err.status());
+
+        let err = auth.authorize(&headers("nope")).await.unwrap_err();
+        assert_eq!(StatusCode::UNAUTHORIZED, err.status());
+    }
+
+    #[tokio::test]
+    async fn enforces_rate_limit_per_key() {
+        let auth = auth();
+
+        for _ in 0..2 {
+            let limits = auth.authorize(&headers("limited")).await.unwrap();
+            assert_eq!(Some(100), limits.max_complexity);
+        }
+        let err = auth.authorize(&headers("limited")).await.unwrap_err();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, err.status());
+
+        // Other keys have their own limits
+        for _ in 0..5 {
+            auth.authorize(&headers("unlimited")).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn counts_each_query_in_a_batch() {
+        let auth = auth();
+
+        let limits = auth.authorize(&headers("limited")).await.unwrap();
+        let err = auth.authorize_more(&limits, 2).unwrap_err();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, err.status());
+
+        // The rejected batch did not use up the remaining request
+        auth.authorize_more(&limits, 1).unwrap();
+        let err = auth.authorize(&headers("limited")).await.unwrap_err();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, err.status());
+    }
+
+    #[tokio::test]
+    async fn consumes_complexity_budget() {
+        let auth = auth();
+
+        let limits = auth.authorize(&headers("budget")).await.unwrap();
+        assert_eq!(Some(100), auth.max_complexity(&limits));
+        auth.charge(&limits, 100);
+        auth.charge(&limits, 100);
+
+        // Only part of the budget is left for the next query
+        let limits = auth.authorize(&headers("budget")).await.unwrap();
+        assert_eq!(Some(50), auth.max_complexity(&limits));
+        auth.charge(&limits, 50);
+
+        let err = auth.authorize(&headers("budget")).await.unwrap_err();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, err.status());
+    }
+
+    #[test]
+    fn percent_encodes_keys() {
+        let keys =
+            ExternalApiKeys::new("http://keys.example.com/keys/", 10, Duration::from_secs(60))
+                .unwrap();
+        assert_eq!(
+            "http://keys.example.com/keys/abc",
+            keys.key_url("abc").as_str()
+        );
+        assert_eq!(
+            "http://keys.example.com/keys/..%2Fadmin%3Fx=1%23",
+            keys.key_url("../admin?x=1#").as_str()
+        );
+    }
+}
@@ -8,10 +8,15 @@ extern crate http;
 extern crate hyper;
 extern crate serde;
 
+mod auth;
 mod request;
+mod response;
 mod server;
 mod service;
 
+pub use self::auth::{
+    ApiKeyAuth, ApiKeyLimits, ApiKeyLookup, AuthError, ExternalApiKeys, StaticApiKeys,
+};
 pub use self::request::GraphQLRequest;
 pub use self::server::GraphQLServer;
 pub use self::service::{GraphQLService, GraphQLServiceResponse};
@@ -2,45 +2,124 @@ use graph::prelude::serde_json;
 use hyper::body::Bytes;
 
 use graph::components::server::query::GraphQLServerError;
+use graph::components::store::PersistedQueryStore;
+use graph::data::query::persisted_query_hash;
 use graph::prelude::*;
 
 /// Future for a query parsed from an HTTP request.
 pub struct GraphQLRequest {
     body: Bytes,
+    persisted_queries: Option<Arc<dyn PersistedQueryStore>>,
 }
 
 impl GraphQLRequest {
     /// Creates a new GraphQLRequest future based on an HTTP request and a result sender.
     pub fn new(body: Bytes) -> Self {
-        GraphQLRequest { body }
+        GraphQLRequest {
+            body,
+            persisted_queries: None,
+        }
     }
-}
 
-impl Future for GraphQLRequest {
-    type Item = Query;
-    type Error = GraphQLServerError;
+    /// Resolve queries that are sent by their hash with `persisted_queries`
+    pub fn with_persisted_queries(
+        mut self,
+        persisted_queries: Option<Arc<dyn PersistedQueryStore>>,
+    ) -> Self {
+        self.persisted_queries = persisted_queries;
+        self
+    }
 
-    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
-        // Parse request body as JSON
-        let json: serde_json::Value = serde_json::from_slice(&self.body)
+    fn find_persisted_query(&self, hash: &str) -> Option<String> {
+        self.persisted_queries
+            .as_ref()
+            .and_then(|persisted_queries| persisted_queries.find(hash))
+    }
+
+    /// Whether the request is a batch of queries, i.e., its body is a JSON
+    /// array rather than an object
+    pub fn is_batch(&self) -> bool {
+        self.body
+            .iter()
+            .find(|b| !b.is_ascii_whitespace())
+            .map(|b| *b == b'[')
+            .unwrap_or(false)
+    }
+
+    /// Parse the queries in a batch request. The request as a whole fails
+    /// if it is not a JSON array of acceptable size; each query in it can
+    /// fail independently of the others
+    pub fn batch(&self) -> Result<Vec<Result<Query, GraphQLServerError>>, GraphQLServerError> {
+        let json: Vec<serde_json::Value> = serde_json::from_slice(&self.body)
             .map_err(|e| GraphQLServerError::ClientError(format!("{}", e)))?;
 
+        if json.is_empty() {
+            return Err(GraphQLServerError::ClientError(String::from(
+                "The batch does not contain any queries",
+            )));
+        }
+        if json.len() > ENV_VARS.graphql.max_batch_size {
+            return Err(GraphQLServerError::ClientError(format!(
+                "The batch contains {} queries but at most {} are allowed",
+                json.len(),
+                ENV_VARS.graphql.max_batch_size
+            )));
+        }
+
+        Ok(json.iter().map(|json| self.parse_query(json)).collect())
+    }
+
+    fn parse_query(&self, json: &serde_json::Value) -> Result<Query, GraphQLServerError> {
         // Ensure the JSON data is an object
         let obj = json.as_object().ok_or_else(|| {
             GraphQLServerError::ClientError(String::from("Request data is not an object"))
         })?;
 
-        // Ensure the JSON data has a "query" field
-        let query_value = obj.get("query").ok_or_else(|| {
-            GraphQLServerError::ClientError(String::from(
-                "The \"query\" field is missing in request data",
-            ))
-        })?;
+        let hash = persisted_query_hash_of(obj)?;
 
-        // Ensure the "query" field is a string
-        let query_string = query_value.as_str().ok_or_else(|| {
-            GraphQLServerError::ClientError(String::from("The \"query\" field is not a string"))
-        })?;
+        let persisted_query;
+        let query_string = match (obj.get("query"), hash) {
+            // Run the persisted query with the given hash
+            (None, Some(hash)) => {
+                persisted_query = self.find_persisted_query(hash).ok_or_else(|| {
+                    GraphQLServerError::ClientError(String::from("PersistedQueryNotFound"))
+                })?;
+                persisted_query.as_str()
+            }
+            // Ensure the JSON data has a "query" field
+            (None, None) => {
+                return Err(GraphQLServerError::ClientError(String::from(
+                    "The \"query\" field is missing in request data",
+                )))
+            }
+            (Some(query_value), hash) => {
+                // Ensure the "query" field is a string
+                let query_string = query_value.as_str().ok_or_else(|| {
+                    GraphQLServerError::ClientError(String::from(
+                        "The \"query\" field is not a string",
+                    ))
+                })?;
+
+                let actual_hash = persisted_query_hash(query_string);
+                if let Some(hash) = hash {
+                    if hash != actual_hash {
+                        return Err(GraphQLServerError::ClientError(String::from(
+                            "The \"sha256Hash\" of the persisted query does not match the query",
+                        )));
+                    }
+                }
+
+                // Only run queries that have been registered
+                if ENV_VARS.graphql.enforce_persisted_queries
+                    && self.find_persisted_query(&actual_hash).is_none()
+                {
+                    return Err(GraphQLServerError::ClientError(String::from(
+                        "Only persisted queries can be run on this node",
+                    )));
+                }
+                query_string
+            }
+        };
 
         // Parse the "query" field of the JSON body
         let document = graphql_parser::parse_query(query_string)
@@ -60,7 +139,43 @@ impl Future for GraphQLRequest {
             )),
         }?;
 
-        Ok(Async::Ready(Query::new(document, variables)))
+        Ok(Query::new(document, variables))
+    }
+}
+
+/// Extract the hash from a request that uses the persisted query
+/// extension, i.e., that has `extensions.persistedQuery.sha256Hash`
+fn persisted_query_hash_of(
+    obj: &serde_json::Map<String, serde_json::Value>,
+) -> Result<Option<&str>, GraphQLServerError> {
+    let persisted_query = match obj
+        .get("extensions")
+        .and_then(|extensions| extensions.get("persistedQuery"))
+    {
+        None | Some(serde_json::Value::Null) => return Ok(None),
+        Some(persisted_query) => persisted_query,
+    };
+    persisted_query
+        .get("sha256Hash")
+        .and_then(|hash| hash.as_str())
+        .map(Some)
+        .ok_or_else(|| {
+            GraphQLServerError::ClientError(String::from(
+                "The \"sha256Hash\" of the persisted query is missing or not a string",
+            ))
+        })
+}
+
+impl Future for GraphQLRequest {
+    type Item = Query;
+    type Error = GraphQLServerError;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        // Parse request body as JSON
+        let json: serde_json::Value = serde_json::from_slice(&self.body)
+            .map_err(|e| GraphQLServerError::ClientError(format!("{}", e)))?;
+
+        self.parse_query(&json).map(Async::Ready)
     }
 }
 
@@ -71,7 +186,11 @@ mod tests {
     use std::collections::HashMap;
 
     use graph::{
-        data::{query::QueryTarget, value::Object},
+        components::store::PersistedQueryStore,
+        data::{
+            query::{persisted_query_hash, QueryTarget},
+            value::Object,
+        },
         prelude::*,
     };
 
@@ -82,6 +201,38 @@ mod tests {
             QueryTarget::Name(SubgraphName::new("test/request").unwrap());
     }
 
+    const USER_QUERY: &str = "{ user { name } }";
+
+    /// A persisted query store that knows only about `USER_QUERY`
+    struct TestPersistedQueries;
+
+    impl PersistedQueryStore for TestPersistedQueries {
+        fn register(&self, _hash: &str, _query: &str) -> Result<(), StoreError> {
+            unimplemented!()
+        }
+
+        fn remove(&self, _hash: &str) -> Result<bool, StoreError> {
+            unimplemented!()
+        }
+
+        fn find(&self, hash: &str) -> Option<String> {
+            if hash == persisted_query_hash(USER_QUERY) {
+                Some(USER_QUERY.to_string())
+            } else {
+                None
+            }
+        }
+
+        fn refresh(&self) -> Result<(), StoreError> {
+            unimplemented!()
+        }
+    }
+
+    fn persisted_query_request(body: String) -> GraphQLRequest {
+        GraphQLRequest::new(hyper::body::Bytes::from(body))
+            .with_persisted_queries(Some(Arc::new(TestPersistedQueries)))
+    }
+
     #[test]
     fn rejects_invalid_json() {
         let request = GraphQLRequest::new(hyper::body::Bytes::from("!@#)%"));
@@ -187,4 +338,74 @@ mod tests {
         assert_eq!(query.document, expected_query);
         assert_eq!(query.variables, Some(expected_variables));
     }
+
+    #[test]
+    fn runs_persisted_queries_by_hash() {
+        let body = format!(
+            "{{\"extensions\": {{\"persistedQuery\": {{\"version\": 1, \"sha256Hash\": \"{}\"}}}}}}",
+            persisted_query_hash(USER_QUERY)
+        );
+        let query = persisted_query_request(body)
+            .wait()
+            .expect("Should accept known persisted queries");
+
+        let expected_query = graphql_parser::parse_query(USER_QUERY)
+            .unwrap()
+            .into_static();
+        assert_eq!(query.document, expected_query);
+    }
+
+    #[test]
+    fn rejects_unknown_persisted_queries() {
+        let body = format!(
+            "{{\"extensions\": {{\"persistedQuery\": {{\"version\": 1, \"sha256Hash\": \"{}\"}}}}}}",
+            persisted_query_hash("{ other }")
+        );
+        persisted_query_request(body)
+            .wait()
+            .expect_err("Should reject unknown persisted queries");
+    }
+
+    #[test]
+    fn rejects_persisted_queries_with_wrong_hash() {
+        let body = format!(
+            "{{\"query\": \"{}\", \"extensions\": {{\"persistedQuery\": {{\"version\": 1, \"sha256Hash\": \"{}\"}}}}}}",
+            USER_QUERY,
+            persisted_query_hash("{ other }")
+        );
+        persisted_query_request(body)
+            .wait()
+            .expect_err("Should reject a hash that does not match the query");
+    }
+
+    #[test]
+    fn parses_batches() {
+        let request = GraphQLRequest::new(hyper::body::Bytes::from(
+            " [{\"query\": \"{ user { name } }\"}, {\"variables\": {}}]",
+        ));
+        assert!(request.is_batch());
+
+        let queries = request.batch().expect("Should accept a batch");
+        assert_eq!(2, queries.len());
+        assert!(queries[0].is_ok());
+        // Errors in one query do not affect the others
+        assert!(queries[1].is_err());
+
+        let request = GraphQLRequest::new(hyper::body::Bytes::from("{\"query\": \"{ user }\"}"));
+        assert!(!request.is_batch());
+    }
+
+    #[test]
+    fn rejects_empty_and_oversized_batches() {
+        let request = GraphQLRequest::new(hyper::body::Bytes::from("[]"));
+        request.batch().expect_err("Should reject an empty batch");
+
+        let queries =
+            vec!["{\"query\": \"{ user { name } }\"}"; ENV_VARS.graphql.max_batch_size + 1];
+        let request =
+            GraphQLRequest::new(hyper::body::Bytes::from(format!("[{}]", queries.join(","))));
+        request
+            .batch()
+            .expect_err("Should reject a batch that is too big");
+    }
 }
@@ -0,0 +1,330 @@
+use std::io::Write;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use graph::data::query::QueryResults;
+use graph::prelude::{hex, BLOCK_NUMBER_MAX, ENV_VARS};
+use http::header::{
+    HeaderMap, HeaderValue, ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, ETAG, IF_NONE_MATCH,
+    VARY,
+};
+use hyper::{Body, Response, StatusCode};
+use sha2::{Digest, Sha256};
+
+/// Responses smaller than this are not worth compressing
+const MIN_COMPRESSED_SIZE: usize = 1024;
+
+/// The compression schemes we support, in order of preference
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    fn name(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// Pick the encoding we prefer among the ones that the client lists in
+    /// its `Accept-Encoding` header
+    fn negotiate(headers: &HeaderMap) -> Option<Encoding> {
+        let accepted: Vec<&str> = headers
+            .get_all(ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|coding| {
+                let mut parts = coding.split(';').map(str::trim);
+                let name = parts.next()?;
+                // Codings with a quality of 0 are explicitly not acceptable
+                let refused = parts.any(|param| {
+                    param
+                        .strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .map(|q| q == 0.0)
+                        .unwrap_or(false)
+                });
+                if refused {
+                    None
+                } else {
+                    Some(name)
+                }
+            })
+            .collect();
+
+        [Encoding::Brotli, Encoding::Gzip]
+            .into_iter()
+            .find(|encoding| accepted.contains(&encoding.name()))
+    }
+
+    fn encode(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut out = Vec::new();
+                {
+                    let mut writer = brotli::CompressorWriter::new(&mut out, 4096, 5, 22);
+                    writer.write_all(data)?;
+                }
+                Ok(out)
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// The `ETag` for the response to the queries in `request_body`. Since
+/// the result of a query only changes when the blocks it was run at
+/// change, the tag is derived from those blocks. Returns `None` if the
+/// result should not be cached
+fn etag(results: &[QueryResults], request_body: &[u8]) -> Option<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(request_body);
+    for results in results {
+        if results.has_errors() {
+            return None;
+        }
+        for ptr in results.block_ptrs()? {
+            if ptr.number == BLOCK_NUMBER_MAX {
+                return None;
+            }
+            hasher.update(ptr.hash.as_slice());
+        }
+    }
+    Some(format!("\"{}\"", hex::encode(hasher.finalize())))
+}
+
+/// Turn `results` into an HTTP response to a query request with the given
+/// `headers` and `body`. Results that were computed at known blocks carry
+/// an `ETag` and can be cached; if the client already has the current
+/// result, the response is a `304 Not Modified`. Responses to `private`
+/// requests, i.e., requests that were made with an API key, may only be
+/// cached by the client and not by shared caches. The response body is
+/// compressed if the client accepts that
+pub fn query_response(
+    results: &QueryResults,
+    headers: &HeaderMap,
+    request_body: &[u8],
+    private: bool,
+) -> Response<Body> {
+    let response = results.as_http_response::<String>();
+    let etag = etag(std::slice::from_ref(results), request_body);
+    finish_response(response, etag, headers, private)
+}
+
+/// Like `query_response`, but for a batch request. The response is a JSON
+/// array with one entry for each query in the batch, and it can only be
+/// cached if all the queries can be cached
+pub fn batch_response(
+    results: &[QueryResults],
+    headers: &HeaderMap,
+    request_body: &[u8],
+    private: bool,
+) -> Response<Body> {
+    let response = QueryResults::as_batch_http_response::<String>(results);
+    let etag = etag(results, request_body);
+    finish_response(response, etag, headers, private)
+}
+
+fn finish_response(
+    response: Response<String>,
+    etag: Option<String>,
+    headers: &HeaderMap,
+    private: bool,
+) -> Response<Body> {
+    let (mut parts, json) = response.into_parts();
+
+    match etag {
+        Some(etag) => {
+            let not_modified = headers
+                .get_all(IF_NONE_MATCH)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .any(|tag| tag.trim() == etag || tag.trim() == "*");
+
+            parts.headers.insert(
+                CACHE_CONTROL,
+                HeaderValue::from_str(&format!(
+                    "{}, max-age={}",
+                    if private { "private" } else { "public" },
+                    ENV_VARS.graphql.http_cache_max_age.as_secs()
+                ))
+                .unwrap(),
+            );
+            parts
+                .headers
+                .insert(ETAG, HeaderValue::from_str(&etag).unwrap());
+
+            if not_modified {
+                parts.status = StatusCode::NOT_MODIFIED;
+                parts.headers.remove(http::header::CONTENT_TYPE);
+                return Response::from_parts(parts, Body::empty());
+            }
+        }
+        None => {
+            parts
+                .headers
+                .insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        }
+    }
+
+    if ENV_VARS.graphql.disable_http_compression {
+        return Response::from_parts(parts, Body::from(json));
+    }
+
+    parts
+        .headers
+        .insert(VARY, HeaderValue::from_static("Accept-Encoding"));
+    let encoding = Encoding::negotiate(headers).filter(|_| json.len() >= MIN_COMPRESSED_SIZE);
+    match encoding.map(|encoding| (encoding, encoding.encode(json.as_bytes()))) {
+        Some((encoding, Ok(compressed))) => {
+            parts
+                .headers
+                .insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.name()));
+            Response::from_parts(parts, Body::from(compressed))
+        }
+        // Compressing into memory can't really fail, but if it does, we
+        // just send the uncompressed response
+        Some((_, Err(_))) | None => Response::from_parts(parts, Body::from(json)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+    use graph::data::query::{QueryResult, QueryResults};
+    use graph::data::value::Object;
+    use graph::prelude::{r, tokio, BlockPtr};
+    use http::header::{
+        HeaderMap, HeaderValue, ACCEPT_ENCODING, CACHE_CONTROL, CONTENT_ENCODING, ETAG,
+        IF_NONE_MATCH,
+    };
+    use hyper::StatusCode;
+
+    use super::{batch_response, query_response, Encoding};
+
+    fn results(block: Option<BlockPtr>, size: usize) -> QueryResults {
+        let data = Object::from_iter(vec![(
+            "name".to_string(),
+            r::Value::String("x".repeat(size)),
+        )]);
+        let mut result = QueryResult::new(data);
+        result.block_ptr = block;
+        QueryResults::from(result)
+    }
+
+    fn block(number: i32) -> BlockPtr {
+        BlockPtr::from((vec![number as u8; 32], number))
+    }
+
+    fn headers(pairs: &[(http::header::HeaderName, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn negotiates_encoding() {
+        let negotiate = |value| Encoding::negotiate(&headers(&[(ACCEPT_ENCODING, value)]));
+
+        assert_eq!(Some(Encoding::Brotli), negotiate("gzip, deflate, br"));
+        assert_eq!(Some(Encoding::Gzip), negotiate("gzip;q=0.8, br;q=0"));
+        assert_eq!(None, negotiate("deflate"));
+        assert_eq!(None, Encoding::negotiate(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn etag_changes_with_block() {
+        let response = query_response(&results(Some(block(1)), 1), &HeaderMap::new(), b"q", false);
+        let etag1 = response.headers().get(ETAG).unwrap().clone();
+        assert_eq!(
+            "public, max-age=0",
+            response.headers().get(CACHE_CONTROL).unwrap()
+        );
+
+        let response = query_response(&results(Some(block(2)), 1), &HeaderMap::new(), b"q", false);
+        let etag2 = response.headers().get(ETAG).unwrap().clone();
+        assert_ne!(etag1, etag2);
+
+        // Results without a block can not be cached
+        let response = query_response(&results(None, 1), &HeaderMap::new(), b"q", false);
+        assert!(response.headers().get(ETAG).is_none());
+        assert_eq!("no-store", response.headers().get(CACHE_CONTROL).unwrap());
+    }
+
+    #[test]
+    fn responds_not_modified_for_current_etag() {
+        let response = query_response(&results(Some(block(1)), 1), &HeaderMap::new(), b"q", false);
+        let etag = response.headers().get(ETAG).unwrap().clone();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_NONE_MATCH, etag);
+        let response = query_response(&results(Some(block(1)), 1), &headers, b"q", false);
+        assert_eq!(StatusCode::NOT_MODIFIED, response.status());
+
+        let response = query_response(&results(Some(block(2)), 1), &headers, b"q", false);
+        assert_eq!(StatusCode::OK, response.status());
+    }
+
+    #[tokio::test]
+    async fn compresses_large_responses() {
+        let accept_gzip = headers(&[(ACCEPT_ENCODING, "gzip")]);
+
+        let response = query_response(&results(None, 10), &accept_gzip, b"q", false);
+        assert!(response.headers().get(CONTENT_ENCODING).is_none());
+
+        let response = query_response(&results(None, 10_000), &accept_gzip, b"q", false);
+        assert_eq!("gzip", response.headers().get(CONTENT_ENCODING).unwrap());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let mut json = String::new();
+        GzDecoder::new(body.as_ref())
+            .read_to_string(&mut json)
+            .unwrap();
+        assert!(json.starts_with("{\"data\":{\"name\":\"xxx"));
+    }
+
+    #[tokio::test]
+    async fn batch_responses_are_arrays() {
+        let batch = vec![results(Some(block(1)), 1), results(Some(block(1)), 2)];
+        let response = batch_response(&batch, &HeaderMap::new(), b"[q, q]", false);
+        assert!(response.headers().get(ETAG).is_some());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(
+            "[{\"data\":{\"name\":\"x\"}},{\"data\":{\"name\":\"xx\"}}]",
+            String::from_utf8(body.to_vec()).unwrap()
+        );
+
+        // The batch can only be cached if all its results can be cached
+        let batch = vec![results(Some(block(1)), 1), results(None, 1)];
+        let response = batch_response(&batch, &HeaderMap::new(), b"[q, q]", false);
+        assert!(response.headers().get(ETAG).is_none());
+    }
+
+    #[test]
+    fn private_responses_are_not_cached_by_shared_caches() {
+        let response = query_response(&results(Some(block(1)), 1), &HeaderMap::new(), b"q", true);
+        assert_eq!(
+            "private, max-age=0",
+            response.headers().get(CACHE_CONTROL).unwrap()
+        );
+
+        let batch = vec![results(Some(block(1)), 1)];
+        let response = batch_response(&batch, &HeaderMap::new(), b"[q]", true);
+        assert_eq!(
+            "private, max-age=0",
+            response.headers().get(CACHE_CONTROL).unwrap()
+        );
+    }
+}
@@ -1,10 +1,16 @@
 use std::net::{Ipv4Addr, SocketAddrV4};
+use std::path::PathBuf;
+use std::pin::Pin;
 
+use hyper::server::accept;
 use hyper::service::make_service_fn;
 use hyper::Server;
 
+use crate::auth::ApiKeyAuth;
 use crate::service::{GraphQLService, GraphQLServiceMetrics};
+use graph::components::store::PersistedQueryStore;
 use graph::prelude::{GraphQLServer as GraphQLServerTrait, *};
+use graph::util::tls::TlsSettings;
 use thiserror::Error;
 
 /// Errors that may occur when starting the server.
@@ -12,6 +18,10 @@ use thiserror::Error;
 pub enum GraphQLServeError {
     #[error("Bind error: {0}")]
     BindError(hyper::Error),
+    #[error("TLS bind error: {0}")]
+    TlsBindError(std::io::Error),
+    #[error("Unix socket bind error: {0}")]
+    UnixBindError(std::io::Error),
 }
 
 impl From<hyper::Error> for GraphQLServeError {
@@ -26,6 +36,11 @@ pub struct GraphQLServer<Q> {
     metrics: Arc<GraphQLServiceMetrics>,
     graphql_runner: Arc<Q>,
     node_id: NodeId,
+    persisted_queries: Option<Arc<dyn PersistedQueryStore>>,
+    api_key_auth: Option<Arc<ApiKeyAuth>>,
+    shutdown: Option<Pin<Box<dyn std::future::Future<Output = ()> + Send>>>,
+    tls: Option<Arc<TlsSettings>>,
+    unix_socket: Option<PathBuf>,
 }
 
 impl<Q> GraphQLServer<Q> {
@@ -50,8 +65,52 @@ impl<Q> GraphQLServer<Q> {
             metrics,
             graphql_runner,
             node_id,
+            persisted_queries: None,
+            api_key_auth: None,
+            shutdown: None,
+            tls: None,
+            unix_socket: None,
         }
     }
+
+    /// Look up queries that clients send by their hash in
+    /// `persisted_queries`
+    pub fn with_persisted_queries(
+        mut self,
+        persisted_queries: Arc<dyn PersistedQueryStore>,
+    ) -> Self {
+        self.persisted_queries = Some(persisted_queries);
+        self
+    }
+
+    /// Require an API key for queries and apply the limits for the key
+    pub fn with_api_key_auth(mut self, api_key_auth: Arc<ApiKeyAuth>) -> Self {
+        self.api_key_auth = Some(api_key_auth);
+        self
+    }
+
+    /// Stop accepting connections once `signal` completes, and finish
+    /// the requests that are in flight before the server shuts down
+    pub fn with_graceful_shutdown(
+        mut self,
+        signal: impl std::future::Future<Output = ()> + Send + 'static,
+    ) -> Self {
+        self.shutdown = Some(Box::pin(signal));
+        self
+    }
+
+    /// Only accept TLS connections using `tls`
+    pub fn with_tls(mut self, tls: Arc<TlsSettings>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Also listen on the unix socket at `path`. Connections to the socket
+    /// never use TLS
+    pub fn with_unix_socket(mut self, path: PathBuf) -> Self {
+        self.unix_socket = Some(path);
+        self
+    }
 }
 
 impl<Q> GraphQLServerTrait for GraphQLServer<Q>
@@ -67,9 +126,10 @@ where
     ) -> Result<Box<dyn Future<Item = (), Error = ()> + Send>, Self::ServeError> {
         let logger = self.logger.clone();
 
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
         info!(
             logger,
-            "Starting GraphQL HTTP server at: http://localhost:{}", port
+            "Starting GraphQL HTTP server at: {}://localhost:{}", scheme, port
         );
 
         let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
@@ -80,21 +140,85 @@ where
         let graphql_runner = self.graphql_runner.clone();
         let metrics = self.metrics.clone();
         let node_id = self.node_id.clone();
-        let new_service = make_service_fn(move |_| {
-            futures03::future::ok::<_, Error>(GraphQLService::new(
+        let persisted_queries = self.persisted_queries.clone();
+        let api_key_auth = self.api_key_auth.clone();
+        let new_service = move || {
+            let service = GraphQLService::new(
                 logger_for_service.clone(),
                 metrics.clone(),
                 graphql_runner.clone(),
                 ws_port,
                 node_id.clone(),
-            ))
-        });
+            );
+            let service = match &persisted_queries {
+                Some(persisted_queries) => {
+                    service.with_persisted_queries(persisted_queries.clone())
+                }
+                None => service,
+            };
+            let service = match &api_key_auth {
+                Some(api_key_auth) => service.with_api_key_auth(api_key_auth.clone()),
+                None => service,
+            };
+            futures03::future::ok::<_, Error>(service)
+        };
+
+        let shutdown = self
+            .shutdown
+            .take()
+            .unwrap_or_else(|| Box::pin(futures03::future::pending()))
+            .shared();
 
         // Create a task to run the server and handle HTTP requests
-        let task = Server::try_bind(&addr.into())?
-            .serve(new_service)
-            .map_err(move |e| error!(logger, "Server error"; "error" => format!("{}", e)));
+        let tcp_task = {
+            let new_service = new_service.clone();
+            match &self.tls {
+                Some(tls) => {
+                    let incoming = tls
+                        .incoming(addr.into())
+                        .map_err(GraphQLServeError::TlsBindError)?;
+                    Server::builder(accept::from_stream(incoming))
+                        .serve(make_service_fn(move |_| new_service()))
+                        .with_graceful_shutdown(shutdown.clone())
+                        .boxed()
+                }
+                None => Server::try_bind(&addr.into())?
+                    .serve(make_service_fn(move |_| new_service()))
+                    .with_graceful_shutdown(shutdown.clone())
+                    .boxed(),
+            }
+        };
 
+        #[cfg(unix)]
+        let unix_task = match &self.unix_socket {
+            Some(path) => {
+                info!(
+                    logger,
+                    "Starting GraphQL HTTP server at: unix:{}",
+                    path.display()
+                );
+                let listener = graph::util::unix_socket::bind(path)
+                    .map_err(GraphQLServeError::UnixBindError)?;
+                Server::builder(accept::from_stream(graph::util::unix_socket::incoming(
+                    listener,
+                )))
+                .serve(make_service_fn(move |_| new_service()))
+                .with_graceful_shutdown(shutdown)
+                .boxed()
+            }
+            None => futures03::future::ok(()).boxed(),
+        };
+        #[cfg(not(unix))]
+        let unix_task = {
+            if self.unix_socket.is_some() {
+                warn!(logger, "Unix sockets are only supported on Unix");
+            }
+            futures03::future::ok(()).boxed()
+        };
+
+        let task = futures03::future::try_join(tcp_task, unix_task)
+            .map_ok(|_| ())
+            .map_err(move |e| error!(logger, "Server error"; "error" => format!("{}", e)));
         Ok(Box::new(task.compat()))
     }
 }
@@ -6,16 +6,82 @@ use std::task::Poll;
 use std::time::Instant;
 
 use graph::prelude::*;
-use graph::{components::server::query::GraphQLServerError, data::query::QueryTarget};
+use graph::{
+    components::{server::query::GraphQLServerError, store::PersistedQueryStore},
+    data::query::{IncrementalPayloadStream, IncrementalResponse, QueryTarget},
+    util::otel::{self, Span},
+};
 use http::header;
 use http::header::{
-    ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
-    CONTENT_TYPE, LOCATION,
+    ACCEPT, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_TYPE, LOCATION,
 };
 use hyper::service::Service;
 use hyper::{Body, Method, Request, Response, StatusCode};
 
+use crate::auth::{ApiKeyAuth, AuthError};
 use crate::request::GraphQLRequest;
+use crate::response::{batch_response, query_response};
+
+const MULTIPART_MIXED: &str = "multipart/mixed";
+/// The boundary between the parts of a `multipart/mixed` response; the
+/// incremental delivery proposal recommends `-`
+const MULTIPART_BOUNDARY: &str = "-";
+const NDJSON: &str = "application/x-ndjson";
+
+/// The formats in which we can send a response in several parts
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum IncrementalFormat {
+    /// Each payload is one part of a `multipart/mixed` response
+    Multipart,
+    /// Each payload is one line of newline-delimited JSON
+    Ndjson,
+}
+
+impl IncrementalFormat {
+    /// The format that the client asked for with its `Accept` header, if
+    /// it can process responses in several parts at all
+    fn from_headers(headers: &header::HeaderMap) -> Option<Self> {
+        let accepted: Vec<_> = headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+        if accepted.iter().any(|value| value.contains(MULTIPART_MIXED)) {
+            Some(IncrementalFormat::Multipart)
+        } else if accepted.iter().any(|value| value.contains(NDJSON)) {
+            Some(IncrementalFormat::Ndjson)
+        } else {
+            None
+        }
+    }
+
+    fn content_type(&self) -> String {
+        match self {
+            IncrementalFormat::Multipart => {
+                format!("{}; boundary=\"{}\"", MULTIPART_MIXED, MULTIPART_BOUNDARY)
+            }
+            IncrementalFormat::Ndjson => NDJSON.to_string(),
+        }
+    }
+
+    fn part(&self, json: String) -> String {
+        match self {
+            IncrementalFormat::Multipart => format!(
+                "\r\n--{}\r\nContent-Type: application/json; charset=utf-8\r\n\r\n{}",
+                MULTIPART_BOUNDARY, json
+            ),
+            IncrementalFormat::Ndjson => format!("{}\n", json),
+        }
+    }
+
+    fn end(&self) -> Option<String> {
+        match self {
+            IncrementalFormat::Multipart => Some(format!("\r\n--{}--\r\n", MULTIPART_BOUNDARY)),
+            IncrementalFormat::Ndjson => None,
+        }
+    }
+}
 
 pub struct GraphQLServiceMetrics {
     query_execution_time: Box<HistogramVec>,
@@ -73,13 +139,26 @@ pub type GraphQLServiceResponse =
     Pin<Box<dyn std::future::Future<Output = GraphQLServiceResult> + Send>>;
 
 /// A Hyper Service that serves GraphQL over a POST / endpoint.
-#[derive(Debug)]
 pub struct GraphQLService<Q> {
     logger: Logger,
     metrics: Arc<GraphQLServiceMetrics>,
     graphql_runner: Arc<Q>,
     ws_port: u16,
     node_id: NodeId,
+    persisted_queries: Option<Arc<dyn PersistedQueryStore>>,
+    api_key_auth: Option<Arc<ApiKeyAuth>>,
+}
+
+impl<Q: fmt::Debug> fmt::Debug for GraphQLService<Q> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GraphQLService")
+            .field("logger", &self.logger)
+            .field("metrics", &self.metrics)
+            .field("graphql_runner", &self.graphql_runner)
+            .field("ws_port", &self.ws_port)
+            .field("node_id", &self.node_id)
+            .finish_non_exhaustive()
+    }
 }
 
 impl<Q> Clone for GraphQLService<Q> {
@@ -90,6 +169,8 @@ impl<Q> Clone for GraphQLService<Q> {
             graphql_runner: self.graphql_runner.clone(),
             ws_port: self.ws_port,
             node_id: self.node_id.clone(),
+            persisted_queries: self.persisted_queries.clone(),
+            api_key_auth: self.api_key_auth.clone(),
         }
     }
 }
@@ -112,9 +193,27 @@ where
             graphql_runner,
             ws_port,
             node_id,
+            persisted_queries: None,
+            api_key_auth: None,
         }
     }
 
+    /// Look up queries that clients send by their hash in
+    /// `persisted_queries`
+    pub fn with_persisted_queries(
+        mut self,
+        persisted_queries: Arc<dyn PersistedQueryStore>,
+    ) -> Self {
+        self.persisted_queries = Some(persisted_queries);
+        self
+    }
+
+    /// Require an API key for queries and apply the limits for the key
+    pub fn with_api_key_auth(mut self, api_key_auth: Arc<ApiKeyAuth>) -> Self {
+        self.api_key_auth = Some(api_key_auth);
+        self
+    }
+
     fn graphiql_html(&self) -> String {
         include_str!("../assets/index.html")
             .replace("__WS_PORT__", format!("{}", self.ws_port).as_str())
@@ -158,7 +257,7 @@ where
             GraphQLServerError::ClientError(format!("Invalid subgraph name {:?}", subgraph_name))
         })?;
 
-        self.handle_graphql_query(subgraph_name.into(), request.into_body())
+        self.handle_graphql_query(subgraph_name.into(), request)
             .await
     }
 
@@ -171,38 +270,205 @@ where
             .map_err(|id| GraphQLServerError::ClientError(format!("Invalid subgraph id `{}`", id)));
         match res {
             Err(_) => self.handle_not_found(),
-            Ok(id) => self
-                .handle_graphql_query(id.into(), request.into_body())
-                .boxed(),
+            Ok(id) => self.handle_graphql_query(id.into(), request).boxed(),
         }
     }
 
     async fn handle_graphql_query(
         self,
         target: QueryTarget,
-        request_body: Body,
+        request: Request<Body>,
+    ) -> GraphQLServiceResult {
+        let traceparent = request
+            .headers()
+            .get("traceparent")
+            .and_then(|value| value.to_str().ok());
+        let span = Span::root("http_query", traceparent).attr("target", format!("{:?}", target));
+        otel::in_span(&span, self.run_graphql_query(target, request)).await
+    }
+
+    async fn run_graphql_query(
+        self,
+        target: QueryTarget,
+        request: Request<Body>,
     ) -> GraphQLServiceResult {
         let service = self.clone();
         let service_metrics = self.metrics.clone();
 
+        let limits = match &self.api_key_auth {
+            Some(auth) => match auth.authorize(request.headers()).await {
+                Ok(limits) => Some(limits),
+                Err(e) => return Ok(self.auth_error_response(e)),
+            },
+            None => None,
+        };
+        let max_complexity = match (&self.api_key_auth, &limits) {
+            (Some(auth), Some(limits)) => auth.max_complexity(limits),
+            _ => None,
+        };
+        // Charge the complexity of the queries we ran against the budget
+        // of the API key
+        let charge = |complexity: u64| {
+            if let (Some(auth), Some(limits)) = (&self.api_key_auth, &limits) {
+                auth.charge(limits, complexity);
+            }
+        };
+
+        // Clients that can process responses to queries with `@defer`,
+        // `@stream`, and `@export` in several parts signal that with their
+        // `Accept` header
+        let incremental_format = IncrementalFormat::from_headers(request.headers());
+
+        let headers = request.headers().clone();
         let start = Instant::now();
-        let body = hyper::body::to_bytes(request_body)
+        let body = hyper::body::to_bytes(request.into_body())
             .map_err(|_| GraphQLServerError::InternalError("Failed to read request body".into()))
             .await?;
-        let query = GraphQLRequest::new(body).compat().await;
+        let request = GraphQLRequest::new(body.clone())
+            .with_persisted_queries(service.persisted_queries.clone());
+
+        if request.is_batch() {
+            let queries = request.batch()?;
+            // The request was counted as one query when we authorized it;
+            // the other queries in the batch count against the rate limit
+            // as well
+            if let (Some(auth), Some(limits)) = (&self.api_key_auth, &limits) {
+                if let Err(e) = auth.authorize_more(limits, queries.len() as u32 - 1) {
+                    return Ok(self.auth_error_response(e));
+                }
+            }
+            let results = service.run_batch(queries, target, max_complexity).await?;
+            charge(results.iter().map(QueryResults::complexity).sum());
+            if let Some(id) = results
+                .iter()
+                .find_map(|result| result.first().and_then(|res| res.deployment.clone()))
+            {
+                service_metrics
+                    .observe_query_execution_time(start.elapsed().as_secs_f64(), id.to_string());
+            }
+            return Ok(batch_response(&results, &headers, &body, limits.is_some()));
+        }
 
-        let result = match query {
-            Ok(query) => service.graphql_runner.run_query(query, target).await,
-            Err(GraphQLServerError::QueryError(e)) => QueryResult::from(e).into(),
-            Err(e) => return Err(e),
+        let query = request.compat().await;
+
+        let result = match (query, incremental_format) {
+            (Ok(query), Some(format)) => {
+                match service
+                    .graphql_runner
+                    .cheap_clone()
+                    .run_query_incremental(query, target, max_complexity)
+                    .await
+                {
+                    IncrementalResponse::Complete(result) => result,
+                    IncrementalResponse::Incremental {
+                        payloads,
+                        complexity,
+                    } => {
+                        charge(complexity);
+                        return Ok(self.incremental_response(format, payloads));
+                    }
+                }
+            }
+            (Ok(query), None) if max_complexity.is_some() => {
+                service
+                    .graphql_runner
+                    .run_query_with_complexity(query, target, max_complexity, None, None, None)
+                    .await
+            }
+            (Ok(query), None) => service.graphql_runner.run_query(query, target).await,
+            (Err(GraphQLServerError::QueryError(e)), _) => QueryResult::from(e).into(),
+            (Err(e), _) => return Err(e),
         };
+        charge(result.complexity());
 
         if let Some(id) = result.first().and_then(|res| res.deployment.clone()) {
             service_metrics
                 .observe_query_execution_time(start.elapsed().as_secs_f64(), id.to_string());
         }
 
-        Ok(result.as_http_response())
+        Ok(query_response(&result, &headers, &body, limits.is_some()))
+    }
+
+    fn auth_error_response(&self, e: AuthError) -> Response<Body> {
+        warn!(self.logger, "Rejected GraphQL query"; "reason" => e.to_string());
+        Response::builder()
+            .status(e.status())
+            .header(CONTENT_TYPE, "text/plain")
+            .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .body(Body::from(e.to_string()))
+            .unwrap()
+    }
+
+    /// Run the queries in a batch that could be parsed and put the results
+    /// and the errors for the ones that could not back in the order of the
+    /// batch
+    async fn run_batch(
+        &self,
+        queries: Vec<Result<Query, GraphQLServerError>>,
+        target: QueryTarget,
+        max_complexity: Option<u64>,
+    ) -> Result<Vec<QueryResults>, GraphQLServerError> {
+        let mut valid = Vec::new();
+        let mut slots = Vec::with_capacity(queries.len());
+        for query in queries {
+            match query {
+                Ok(query) => {
+                    valid.push(query);
+                    slots.push(None);
+                }
+                Err(GraphQLServerError::QueryError(e)) => {
+                    slots.push(Some(QueryResults::from(QueryResult::from(e))))
+                }
+                Err(GraphQLServerError::ClientError(msg)) => slots.push(Some(QueryResults::from(
+                    QueryExecutionError::ValidationError(None, msg),
+                ))),
+                Err(e @ GraphQLServerError::InternalError(_)) => return Err(e),
+            }
+        }
+
+        let mut results = self
+            .graphql_runner
+            .cheap_clone()
+            .run_query_batch(valid, target, max_complexity)
+            .await
+            .into_iter();
+        Ok(slots
+            .into_iter()
+            .map(|slot| slot.unwrap_or_else(|| results.next().unwrap_or_else(QueryResults::empty)))
+            .collect())
+    }
+
+    /// Send each of the `payloads` as one part of a response in `format`
+    /// as they become available
+    fn incremental_response(
+        &self,
+        format: IncrementalFormat,
+        mut payloads: IncrementalPayloadStream,
+    ) -> Response<Body> {
+        let (mut sender, body) = Body::channel();
+        let logger = self.logger.clone();
+        graph::spawn(async move {
+            while let Some(payload) = payloads.next().await {
+                let json = serde_json::to_string(&payload)
+                    .expect("Failed to serialize GraphQL response to JSON");
+                if sender.send_data(format.part(json).into()).await.is_err() {
+                    debug!(logger, "Client went away before the response was complete");
+                    return;
+                }
+            }
+            if let Some(end) = format.end() {
+                sender.send_data(end.into()).await.ok();
+            }
+        });
+
+        Response::builder()
+            .status(200)
+            .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .header(ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type, User-Agent")
+            .header(ACCESS_CONTROL_ALLOW_METHODS, "GET, OPTIONS, POST")
+            .header(CONTENT_TYPE, format.content_type())
+            .body(body)
+            .unwrap()
     }
 
     // Handles OPTIONS requests
@@ -370,7 +636,7 @@ mod tests {
 
     use graph::data::{
         graphql::effort::LoadManager,
-        query::{QueryResults, QueryTarget},
+        query::{IncrementalResponse, QueryResults, QueryStats, QueryTarget},
     };
     use graph::prelude::*;
     use graph_mock::MockMetricsRegistry;
@@ -379,6 +645,7 @@ mod tests {
 
     use super::GraphQLService;
     use super::GraphQLServiceMetrics;
+    use super::IncrementalFormat;
 
     /// A simple stupid query runner for testing.
     pub struct TestGraphQlRunner;
@@ -411,6 +678,15 @@ mod tests {
             ))
         }
 
+        async fn run_query_incremental(
+            self: Arc<Self>,
+            query: Query,
+            target: QueryTarget,
+            _max_complexity: Option<u64>,
+        ) -> IncrementalResponse {
+            IncrementalResponse::Complete(self.run_query(query, target).await)
+        }
+
         async fn run_subscription(
             self: Arc<Self>,
             _subscription: Subscription,
@@ -422,6 +698,10 @@ mod tests {
         fn load_manager(&self) -> Arc<LoadManager> {
             unimplemented!()
         }
+
+        fn query_stats(&self) -> Arc<QueryStats> {
+            unimplemented!()
+        }
     }
 
     #[test]
@@ -491,4 +771,61 @@ mod tests {
             .expect("Query result field \"name\" is not a string");
         assert_eq!(name, "Jordi".to_string());
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn posting_batches_yields_array_of_results() {
+        let logger = Logger::root(slog::Discard, o!());
+        let metrics_registry = Arc::new(MockMetricsRegistry::new());
+        let metrics = Arc::new(GraphQLServiceMetrics::new(metrics_registry));
+        let graphql_runner = Arc::new(TestGraphQlRunner);
+
+        let node_id = NodeId::new("test").unwrap();
+        let mut service = GraphQLService::new(logger, metrics, graphql_runner, 8001, node_id);
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("http://localhost:8000/subgraphs/id/{}", *USERS))
+            .body(Body::from(
+                "[{\"query\": \"{ name }\"}, {\"query\": \"{ name\"}]",
+            ))
+            .unwrap();
+
+        let response = tokio::spawn(service.call(request))
+            .await
+            .unwrap()
+            .expect("Should return a response");
+        assert_eq!(StatusCode::OK, response.status());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let results = json.as_array().expect("Batch response must be an array");
+
+        // The valid query has a result, and the invalid one an error
+        assert_eq!(2, results.len());
+        assert_eq!("Jordi", results[0]["data"]["name"]);
+        assert!(results[1]["data"].is_null());
+        assert!(results[1]["errors"].is_array());
+    }
+
+    #[test]
+    fn negotiates_incremental_format() {
+        let format = |accept: &'static str| {
+            let mut headers = http::HeaderMap::new();
+            headers.insert(http::header::ACCEPT, accept.parse().unwrap());
+            IncrementalFormat::from_headers(&headers)
+        };
+
+        assert_eq!(
+            Some(IncrementalFormat::Multipart),
+            format("multipart/mixed; deferSpec=20220824, application/json")
+        );
+        assert_eq!(
+            Some(IncrementalFormat::Ndjson),
+            format("application/x-ndjson")
+        );
+        assert_eq!(None, format("application/json"));
+
+        let part = IncrementalFormat::Ndjson.part("{\"hasNext\":false}".to_string());
+        assert_eq!("{\"hasNext\":false}\n", part);
+        assert_eq!(None, IncrementalFormat::Ndjson.end());
+    }
 }
@@ -4,7 +4,7 @@ use std::time::Duration;
 
 use graph::data::{
     graphql::effort::LoadManager,
-    query::{QueryResults, QueryTarget},
+    query::{IncrementalResponse, QueryResults, QueryStats, QueryTarget},
     value::Object,
 };
 use graph::prelude::*;
@@ -61,6 +61,15 @@ impl GraphQlRunner for TestGraphQlRunner {
         .into()
     }
 
+    async fn run_query_incremental(
+        self: Arc<Self>,
+        query: Query,
+        target: QueryTarget,
+        _max_complexity: Option<u64>,
+    ) -> IncrementalResponse {
+        IncrementalResponse::Complete(self.run_query(query, target).await)
+    }
+
     async fn run_subscription(
         self: Arc<Self>,
         _subscription: Subscription,
@@ -72,6 +81,10 @@ impl GraphQlRunner for TestGraphQlRunner {
     fn load_manager(&self) -> Arc<LoadManager> {
         unimplemented!()
     }
+
+    fn query_stats(&self) -> Arc<QueryStats> {
+        unimplemented!()
+    }
 }
 
 #[cfg(test)]
@@ -6,7 +6,9 @@ use web3::types::{Address, H256};
 use graph::blockchain::{Blockchain, BlockchainKind, BlockchainMap};
 use graph::components::store::{BlockStore, EntityType, Store};
 use graph::data::graphql::{object, IntoValue, ObjectOrInterface, ValueMap};
+use graph::data::query::{QueryShapeSummary, QueryStats, QueryStatsOrder};
 use graph::data::subgraph::features::detect_features;
+use graph::data::subgraph::schema::MappingLog;
 use graph::data::subgraph::status;
 use graph::data::value::Object;
 use graph::prelude::*;
@@ -15,12 +17,19 @@ use graph_graphql::prelude::{a, ExecutionContext, Resolver};
 use crate::auth::PoiProtection;
 
 /// Resolver for the index node GraphQL API.
+/// The most blocks for which `proofsOfIndexing` returns proofs of indexing
+/// in one call
+const MAX_POI_BLOCKS: BlockNumber = 1000;
+
 pub struct IndexNodeResolver<S: Store> {
     logger: Logger,
     blockchain_map: Arc<BlockchainMap>,
     store: Arc<S>,
     link_resolver: Arc<dyn LinkResolver>,
     bearer_token: Option<String>,
+    query_stats: Arc<QueryStats>,
+    node_id: NodeId,
+    node_role: NodeRole,
 }
 
 impl<S: Store> IndexNodeResolver<S> {
@@ -30,6 +39,9 @@ impl<S: Store> IndexNodeResolver<S> {
         link_resolver: Arc<dyn LinkResolver>,
         bearer_token: Option<String>,
         blockchain_map: Arc<BlockchainMap>,
+        query_stats: Arc<QueryStats>,
+        node_id: NodeId,
+        node_role: NodeRole,
     ) -> Self {
         let logger = logger.new(o!("component" => "IndexNodeResolver"));
 
@@ -39,6 +51,16 @@ impl<S: Store> IndexNodeResolver<S> {
             store,
             link_resolver,
             bearer_token,
+            query_stats,
+            node_id,
+            node_role,
+        }
+    }
+
+    fn resolve_node_info(&self) -> r::Value {
+        object! {
+            id: self.node_id.to_string(),
+            role: r::Value::Enum(self.node_role.as_str().to_uppercase()),
         }
     }
 
@@ -107,6 +129,65 @@ impl<S: Store> IndexNodeResolver<S> {
         Ok(entity_changes_to_graphql(entity_changes))
     }
 
+    fn resolve_query_stats(&self, field: &a::Field) -> Result<r::Value, QueryExecutionError> {
+        let subgraph_id = field
+            .get_required::<DeploymentHash>("subgraphId")
+            .expect("Valid subgraphId required");
+        let first = field
+            .get_optional::<i32>("first")
+            .expect("Valid first")
+            .unwrap_or(10)
+            .max(0) as usize;
+        let order = match field.argument_value("orderBy") {
+            Some(r::Value::Enum(order)) => match order.as_str() {
+                "meanTime" => QueryStatsOrder::MeanTime,
+                "maxTime" => QueryStatsOrder::MaxTime,
+                "count" => QueryStatsOrder::Count,
+                "rows" => QueryStatsOrder::Rows,
+                _ => QueryStatsOrder::TotalTime,
+            },
+            _ => QueryStatsOrder::TotalTime,
+        };
+
+        let shapes = self.query_stats.top(&subgraph_id, first, order);
+        Ok(r::Value::List(
+            shapes.into_iter().map(query_shape_to_graphql).collect(),
+        ))
+    }
+
+    fn resolve_subgraph_logs(&self, field: &a::Field) -> Result<r::Value, QueryExecutionError> {
+        let deployment = field
+            .get_required::<DeploymentHash>("deployment")
+            .expect("Valid deployment required");
+        let from_block = field
+            .get_optional::<BlockNumber>("fromBlock")
+            .expect("Valid fromBlock")
+            .unwrap_or(0);
+        let first = field
+            .get_optional::<i32>("first")
+            .expect("Valid first")
+            .unwrap_or(100)
+            .max(0) as usize;
+        let level = match field.argument_value("level") {
+            Some(r::Value::Enum(level)) => match level.as_str() {
+                "CRITICAL" => slog::Level::Critical,
+                "ERROR" => slog::Level::Error,
+                "WARNING" => slog::Level::Warning,
+                "INFO" => slog::Level::Info,
+                _ => slog::Level::Debug,
+            },
+            _ => slog::Level::Debug,
+        };
+
+        let logs =
+            self.store
+                .subgraph_store()
+                .mapping_logs(&deployment, from_block, level, first)?;
+        Ok(r::Value::List(
+            logs.into_iter().map(mapping_log_to_graphql).collect(),
+        ))
+    }
+
     fn resolve_block_data(&self, field: &a::Field) -> Result<r::Value, QueryExecutionError> {
         let network = field
             .get_required::<String>("network")
@@ -290,6 +371,56 @@ impl<S: Store> IndexNodeResolver<S> {
         Ok(poi)
     }
 
+    fn resolve_proofs_of_indexing(
+        &self,
+        field: &a::Field,
+    ) -> Result<r::Value, QueryExecutionError> {
+        let deployment_id = field
+            .get_required::<DeploymentHash>("subgraph")
+            .expect("Valid subgraphId required");
+        let from = field
+            .get_required::<BlockNumber>("fromBlock")
+            .expect("Valid fromBlock required");
+        let to = field
+            .get_required::<BlockNumber>("toBlock")
+            .expect("Valid toBlock required");
+        if from < 0 || to < from || to - from >= MAX_POI_BLOCKS {
+            return Err(QueryExecutionError::ValidationError(
+                Some(field.position),
+                format!(
+                    "`fromBlock` must not be negative and at most {} blocks can be requested",
+                    MAX_POI_BLOCKS
+                ),
+            ));
+        }
+
+        let mut indexer = field
+            .get_optional::<Address>("indexer")
+            .expect("Invalid indexer");
+        let poi_protection = PoiProtection::from_env(&ENV_VARS);
+        if !poi_protection.validate_access_token(self.bearer_token.as_deref()) {
+            indexer = Some(Address::zero());
+        }
+
+        let pois_fut = self
+            .store
+            .get_proofs_of_indexing(&deployment_id, &indexer, from, to);
+        let pois = futures::executor::block_on(pois_fut)?;
+        Ok(r::Value::List(
+            pois.into_iter()
+                .map(|(block, poi)| {
+                    object! {
+                        block: object! {
+                            hash: block.hash_hex(),
+                            number: block.number,
+                        },
+                        proofOfIndexing: format!("0x{}", hex::encode(&poi)),
+                    }
+                })
+                .collect(),
+        ))
+    }
+
     fn resolve_indexing_status_for_version(
         &self,
         field: &a::Field,
@@ -519,6 +650,38 @@ where
     }
 }
 
+fn query_shape_to_graphql(shape: QueryShapeSummary) -> r::Value {
+    let ms = |duration: std::time::Duration| duration.as_millis().min(i32::MAX as u128) as i32;
+    object! {
+        shapeHash: format!("{:x}", shape.shape_hash),
+        query: shape.query.as_str(),
+        count: shape.count,
+        rows: shape.rows,
+        totalTimeMs: shape.total_time.as_millis() as u64,
+        meanTimeMs: ms(shape.mean_time),
+        p50TimeMs: ms(shape.p50_time),
+        p90TimeMs: ms(shape.p90_time),
+        p99TimeMs: ms(shape.p99_time),
+        maxTimeMs: ms(shape.max_time),
+    }
+}
+
+fn mapping_log_to_graphql(log: MappingLog) -> r::Value {
+    let level = match log.level {
+        slog::Level::Critical => "CRITICAL",
+        slog::Level::Error => "ERROR",
+        slog::Level::Warning => "WARNING",
+        slog::Level::Info => "INFO",
+        slog::Level::Debug | slog::Level::Trace => "DEBUG",
+    };
+    object! {
+        blockNumber: log.block_number,
+        level: r::Value::Enum(level.to_string()),
+        dataSource: log.data_source,
+        message: log.message,
+    }
+}
+
 fn entity_changes_to_graphql(entity_changes: Vec<EntityOperation>) -> r::Value {
     // Results are sorted first alphabetically by entity type, then by entity
     // ID, and then aphabetically by field name.
@@ -649,6 +812,11 @@ impl<S: Store> Resolver for IndexNodeResolver<S> {
             (None, "CachedEthereumCall", "cachedEthereumCalls") => {
                 self.resolve_cached_ethereum_calls(field)
             }
+            (None, "QueryShapeStats", "queryStats") => self.resolve_query_stats(field),
+            (None, "SubgraphLog", "subgraphLogs") => self.resolve_subgraph_logs(field),
+            (None, "BlockProofOfIndexing", "proofsOfIndexing") => {
+                self.resolve_proofs_of_indexing(field)
+            }
 
             // Resolve fields of `Object` values (e.g. the `chains` field of `ChainIndexingStatus`)
             (value, _, _) => Ok(value.unwrap_or(r::Value::Null)),
@@ -672,6 +840,7 @@ impl<S: Store> Resolver for IndexNodeResolver<S> {
             }
             (None, "subgraphFeatures") => graph::block_on(self.resolve_subgraph_features(field)),
             (None, "entityChangesInBlock") => self.resolve_entity_changes_in_block(field),
+            (None, "nodeInfo") => Ok(self.resolve_node_info()),
 
             // Resolve fields of `Object` values (e.g. the `latestBlock` field of `EthereumBlock`)
             (value, _) => Ok(value.unwrap_or(r::Value::Null)),
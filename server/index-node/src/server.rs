@@ -1,4 +1,5 @@
 use hyper;
+use hyper::server::accept;
 use hyper::service::make_service_fn;
 use hyper::Server;
 use std::net::{Ipv4Addr, SocketAddrV4};
@@ -7,6 +8,7 @@ use graph::{
     blockchain::BlockchainMap,
     components::store::Store,
     prelude::{IndexNodeServer as IndexNodeServerTrait, *},
+    util::tls::TlsSettings,
 };
 
 use crate::service::IndexNodeService;
@@ -17,6 +19,8 @@ use thiserror::Error;
 pub enum IndexNodeServeError {
     #[error("Bind error: {0}")]
     BindError(hyper::Error),
+    #[error("TLS bind error: {0}")]
+    TlsBindError(std::io::Error),
 }
 
 impl From<hyper::Error> for IndexNodeServeError {
@@ -32,6 +36,9 @@ pub struct IndexNodeServer<Q, S> {
     graphql_runner: Arc<Q>,
     store: Arc<S>,
     link_resolver: Arc<dyn LinkResolver>,
+    node_id: NodeId,
+    node_role: NodeRole,
+    tls: Option<Arc<TlsSettings>>,
 }
 
 impl<Q, S> IndexNodeServer<Q, S> {
@@ -42,6 +49,8 @@ impl<Q, S> IndexNodeServer<Q, S> {
         graphql_runner: Arc<Q>,
         store: Arc<S>,
         link_resolver: Arc<dyn LinkResolver>,
+        node_id: NodeId,
+        node_role: NodeRole,
     ) -> Self {
         let logger = logger_factory.component_logger(
             "IndexNodeServer",
@@ -58,8 +67,17 @@ impl<Q, S> IndexNodeServer<Q, S> {
             graphql_runner,
             store,
             link_resolver,
+            node_id,
+            node_role,
+            tls: None,
         }
     }
+
+    /// Only accept TLS connections using `tls`
+    pub fn with_tls(mut self, tls: Arc<TlsSettings>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
 }
 
 impl<Q, S> IndexNodeServerTrait for IndexNodeServer<Q, S>
@@ -75,9 +93,10 @@ where
     ) -> Result<Box<dyn Future<Item = (), Error = ()> + Send>, Self::ServeError> {
         let logger = self.logger.clone();
 
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
         info!(
             logger,
-            "Starting index node server at: http://localhost:{}", port
+            "Starting index node server at: {}://localhost:{}", scheme, port
         );
 
         let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
@@ -93,15 +112,31 @@ where
             graphql_runner.clone(),
             store.clone(),
             self.link_resolver.clone(),
+            self.node_id.clone(),
+            self.node_role,
         );
-        let new_service =
-            make_service_fn(move |_| futures03::future::ok::<_, Error>(service.clone()));
 
         // Create a task to run the server and handle HTTP requests
-        let task = Server::try_bind(&addr.into())?
-            .serve(new_service)
-            .map_err(move |e| error!(logger, "Server error"; "error" => format!("{}", e)));
-
-        Ok(Box::new(task.compat()))
+        match &self.tls {
+            Some(tls) => {
+                let incoming = tls
+                    .incoming(addr.into())
+                    .map_err(IndexNodeServeError::TlsBindError)?;
+                let task = Server::builder(accept::from_stream(incoming))
+                    .serve(make_service_fn(move |_| {
+                        futures03::future::ok::<_, Error>(service.clone())
+                    }))
+                    .map_err(move |e| error!(logger, "Server error"; "error" => format!("{}", e)));
+                Ok(Box::new(task.compat()))
+            }
+            None => {
+                let task = Server::try_bind(&addr.into())?
+                    .serve(make_service_fn(move |_| {
+                        futures03::future::ok::<_, Error>(service.clone())
+                    }))
+                    .map_err(move |e| error!(logger, "Server error"; "error" => format!("{}", e)));
+                Ok(Box::new(task.compat()))
+            }
+        }
     }
 }
@@ -34,6 +34,8 @@ pub struct IndexNodeService<Q, S> {
     store: Arc<S>,
     explorer: Arc<Explorer<S>>,
     link_resolver: Arc<dyn LinkResolver>,
+    node_id: NodeId,
+    node_role: NodeRole,
 }
 
 impl<Q, S> Clone for IndexNodeService<Q, S> {
@@ -45,6 +47,8 @@ impl<Q, S> Clone for IndexNodeService<Q, S> {
             store: self.store.clone(),
             explorer: self.explorer.clone(),
             link_resolver: self.link_resolver.clone(),
+            node_id: self.node_id.clone(),
+            node_role: self.node_role,
         }
     }
 }
@@ -63,6 +67,8 @@ where
         graphql_runner: Arc<Q>,
         store: Arc<S>,
         link_resolver: Arc<dyn LinkResolver>,
+        node_id: NodeId,
+        node_role: NodeRole,
     ) -> Self {
         let explorer = Arc::new(Explorer::new(store.clone()));
 
@@ -73,6 +79,8 @@ where
             store,
             explorer,
             link_resolver,
+            node_id,
+            node_role,
         }
     }
 
@@ -137,6 +145,9 @@ where
                 self.link_resolver.clone(),
                 validated.bearer_token,
                 self.blockchain_map.clone(),
+                self.graphql_runner.query_stats(),
+                self.node_id.clone(),
+                self.node_role,
             );
             let options = QueryExecutionOptions {
                 resolver,
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+
+use graph::prelude::*;
+use jsonrpc_http_server::hyper::{header::AUTHORIZATION, Body, Request};
+use jsonrpc_http_server::jsonrpc_core;
+
+/// Allows all methods when it is listed in the methods for a token
+const ALL_METHODS: &str = "*";
+
+/// What the holder of a token may do
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct AdminToken {
+    /// The name under which we log requests made with the token so that
+    /// the token itself does not show up in logs
+    pub name: String,
+    /// The admin methods that may be called with the token
+    pub methods: Vec<String>,
+}
+
+impl AdminToken {
+    fn allows(&self, method: &str) -> bool {
+        self.methods.iter().any(|m| m == method || m == ALL_METHODS)
+    }
+}
+
+/// The reasons why we refuse an admin request
+#[derive(Debug, PartialEq)]
+pub enum AuthError {
+    /// The request did not contain a token
+    MissingToken,
+    /// The token in the request is not known
+    UnknownToken,
+    /// The token may not be used for the method
+    Forbidden { name: String, method: String },
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthError::MissingToken => write!(f, "a bearer token is required"),
+            AuthError::UnknownToken => write!(f, "unknown bearer token"),
+            AuthError::Forbidden { name, method } => {
+                write!(f, "token `{}` may not call `{}`", name, method)
+            }
+        }
+    }
+}
+
+/// Checks the bearer token of requests to the admin server against the
+/// tokens configured with `GRAPH_ADMIN_TOKENS_FILE`
+pub struct AdminAuth {
+    tokens: HashMap<String, AdminToken>,
+}
+
+impl AdminAuth {
+    pub fn new(tokens: HashMap<String, AdminToken>) -> Self {
+        AdminAuth { tokens }
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, Error> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read admin tokens file {}", path))?;
+        let tokens = serde_json::from_str(&text)
+            .with_context(|| format!("invalid admin tokens file {}", path))?;
+        Ok(Self::new(tokens))
+    }
+
+    /// Create the token checks as configured by `GRAPH_ADMIN_TOKENS_FILE`,
+    /// or return `None` if it is not set
+    pub fn from_env() -> Result<Option<Self>, Error> {
+        ENV_VARS
+            .admin_tokens_file
+            .as_deref()
+            .map(Self::from_file)
+            .transpose()
+    }
+
+    /// Check that `token` may be used to call `method` and return the name
+    /// of the token
+    pub fn authorize(&self, token: Option<&str>, method: &str) -> Result<&str, AuthError> {
+        let token = token.ok_or(AuthError::MissingToken)?;
+        let admin_token = self.tokens.get(token).ok_or(AuthError::UnknownToken)?;
+        if !admin_token.allows(method) {
+            return Err(AuthError::Forbidden {
+                name: admin_token.name.clone(),
+                method: method.to_string(),
+            });
+        }
+        Ok(&admin_token.name)
+    }
+}
+
+/// The request metadata that is passed to every admin method
+#[derive(Clone, Debug, Default)]
+pub struct AdminMeta {
+    /// The token from the `Authorization: Bearer <token>` header
+    pub token: Option<String>,
+}
+
+impl jsonrpc_core::Metadata for AdminMeta {}
+
+impl AdminMeta {
+    pub fn from_request(request: &Request<Body>) -> Self {
+        let token = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .map(str::to_string);
+        AdminMeta { token }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use jsonrpc_http_server::hyper::{header::AUTHORIZATION, Body, Request};
+
+    use super::{AdminAuth, AdminMeta, AdminToken, AuthError};
+
+    fn auth() -> AdminAuth {
+        let token = |name: &str, methods: &[&str]| AdminToken {
+            name: name.to_string(),
+            methods: methods.iter().map(|m| m.to_string()).collect(),
+        };
+        AdminAuth::new(HashMap::from([
+            (
+                "deploy-token".to_string(),
+                token("deployer", &["subgraph_create", "subgraph_deploy"]),
+            ),
+            ("root-token".to_string(), token("root", &["*"])),
+        ]))
+    }
+
+    #[test]
+    fn authorizes_methods_per_token() {
+        let auth = auth();
+
+        assert_eq!(
+            Ok("deployer"),
+            auth.authorize(Some("deploy-token"), "subgraph_deploy")
+        );
+        assert_eq!(
+            Err(AuthError::Forbidden {
+                name: "deployer".to_string(),
+                method: "subgraph_remove".to_string()
+            }),
+            auth.authorize(Some("deploy-token"), "subgraph_remove")
+        );
+        assert_eq!(
+            Ok("root"),
+            auth.authorize(Some("root-token"), "subgraph_remove")
+        );
+        assert_eq!(
+            Err(AuthError::UnknownToken),
+            auth.authorize(Some("nope"), "subgraph_deploy")
+        );
+        assert_eq!(
+            Err(AuthError::MissingToken),
+            auth.authorize(None, "subgraph_deploy")
+        );
+    }
+
+    #[test]
+    fn extracts_bearer_token() {
+        let request = Request::builder()
+            .header(AUTHORIZATION, "Bearer root-token")
+            .body(Body::empty())
+            .unwrap();
+        let meta = AdminMeta::from_request(&request);
+        assert_eq!(Some("root-token"), meta.token.as_deref());
+
+        let request = Request::builder().body(Body::empty()).unwrap();
+        assert_eq!(None, AdminMeta::from_request(&request).token);
+    }
+}
@@ -0,0 +1,123 @@
+use graph::data::graphql::effort::LoadManager;
+use graph::data::query::{QueryError, QueryResults};
+use graph::prelude::tokio::sync::Semaphore;
+use graph::prelude::*;
+use graph_graphql::prelude::{execute_query, Query as PreparedQuery, QueryExecutionOptions};
+use jsonrpc_http_server::hyper::{self, Body, Request, Response};
+
+use crate::auth::{AdminAuth, AdminMeta};
+
+mod resolver;
+
+use resolver::AdminResolver;
+
+/// The path on the admin server that serves the admin GraphQL API
+pub const GRAPHQL_PATH: &str = "/graphql";
+
+lazy_static! {
+    static ref SCHEMA: Arc<ApiSchema> = {
+        let raw_schema = include_str!("./schema.graphql");
+        let document = s::parse_schema(raw_schema).unwrap();
+        let (interfaces_for_type, types_for_interface) =
+            Schema::collect_interfaces(&document).unwrap();
+
+        Arc::new(
+            ApiSchema::from_api_schema(Schema {
+                id: DeploymentHash::new("admin").unwrap(),
+                document,
+                interfaces_for_type,
+                types_for_interface,
+            })
+            .unwrap(),
+        )
+    };
+}
+
+#[derive(Deserialize)]
+struct GraphQLRequest {
+    query: String,
+    variables: Option<QueryVariables>,
+}
+
+/// Serves the operations of the JSON-RPC admin API as GraphQL mutations
+pub struct AdminGraphQL<R> {
+    registrar: Arc<R>,
+    auth: Option<Arc<AdminAuth>>,
+    node_id: NodeId,
+    load_manager: Arc<LoadManager>,
+    /// Admin requests are executed one at a time
+    permits: Arc<Semaphore>,
+    logger: Logger,
+}
+
+impl<R: SubgraphRegistrar> AdminGraphQL<R> {
+    pub fn new(
+        registrar: Arc<R>,
+        auth: Option<Arc<AdminAuth>>,
+        node_id: NodeId,
+        load_manager: Arc<LoadManager>,
+        logger: Logger,
+    ) -> Self {
+        AdminGraphQL {
+            registrar,
+            auth,
+            node_id,
+            load_manager,
+            permits: Arc::new(Semaphore::new(1)),
+            logger,
+        }
+    }
+
+    pub async fn handle(self: Arc<Self>, request: Request<Body>) -> hyper::Result<Response<Body>> {
+        let meta = AdminMeta::from_request(&request);
+        let body = hyper::body::to_bytes(request.into_body()).await?;
+        let results = self.execute(meta, &body).await;
+        Ok(results.as_http_response())
+    }
+
+    async fn execute(&self, meta: AdminMeta, body: &[u8]) -> QueryResults {
+        let request: GraphQLRequest = match serde_json::from_slice(body) {
+            Ok(request) => request,
+            Err(e) => {
+                return QueryExecutionError::ValidationError(
+                    None,
+                    format!("invalid GraphQL request: {}", e),
+                )
+                .into()
+            }
+        };
+
+        let document = match q::parse_query::<String>(&request.query) {
+            Ok(document) => document.into_static(),
+            Err(e) => return QueryResult::from(QueryError::ParseError(Arc::new(e.into()))).into(),
+        };
+        let query = Query::new(document, request.variables);
+
+        let query = match PreparedQuery::new(&self.logger, SCHEMA.clone(), None, query, None, 100) {
+            Ok(query) => query,
+            Err(e) => return e.into(),
+        };
+
+        let resolver = AdminResolver::new(
+            self.registrar.cheap_clone(),
+            self.auth.clone(),
+            meta.token,
+            self.node_id.clone(),
+            self.permits.cheap_clone(),
+            self.logger.cheap_clone(),
+        );
+        let options = QueryExecutionOptions {
+            resolver,
+            deadline: None,
+            max_first: std::u32::MAX,
+            max_skip: std::u32::MAX,
+            load_manager: self.load_manager.cheap_clone(),
+        };
+        execute_query(query, None, None, options).await.into()
+    }
+}
+
+#[test]
+fn schema_parses() {
+    assert!(SCHEMA.mutation_type.is_some());
+}
@@ -0,0 +1,253 @@
+use std::convert::TryFrom;
+
+use graph::data::graphql::{ObjectOrInterface, TryFromValue, ValueMap};
+use graph::prelude::tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use graph::prelude::*;
+use graph_graphql::prelude::{a, ExecutionContext, Resolver};
+
+use crate::auth::AdminAuth;
+
+/// Resolver for the admin GraphQL API. Each mutation calls the
+/// registrar, just like the JSON-RPC method for the same operation
+pub struct AdminResolver<R> {
+    registrar: Arc<R>,
+    auth: Option<Arc<AdminAuth>>,
+    token: Option<String>,
+    node_id: NodeId,
+    permits: Arc<Semaphore>,
+    logger: Logger,
+}
+
+impl<R: SubgraphRegistrar> AdminResolver<R> {
+    pub fn new(
+        registrar: Arc<R>,
+        auth: Option<Arc<AdminAuth>>,
+        token: Option<String>,
+        node_id: NodeId,
+        permits: Arc<Semaphore>,
+        logger: Logger,
+    ) -> Self {
+        AdminResolver {
+            registrar,
+            auth,
+            token,
+            node_id,
+            permits,
+            logger,
+        }
+    }
+
+    /// Check that the request may call `method`. The mutations use the
+    /// names of the JSON-RPC methods so that one set of tokens governs
+    /// both protocols
+    fn authorize(&self, method: &str) -> Result<(), QueryExecutionError> {
+        let auth = match &self.auth {
+            Some(auth) => auth,
+            None => return Ok(()),
+        };
+        match auth.authorize(self.token.as_deref(), method) {
+            Ok(name) => {
+                info!(self.logger, "Authorized admin request";
+                    "method" => method,
+                    "token" => name);
+                Ok(())
+            }
+            Err(e) => {
+                warn!(self.logger, "{} refused", method; "error" => e.to_string());
+                Err(QueryExecutionError::Unauthorized(e.to_string()))
+            }
+        }
+    }
+
+    fn resolve_mutation(&self, field: &a::Field) -> Result<r::Value, QueryExecutionError> {
+        let method = match field.name.as_str() {
+            "createSubgraph" => "subgraph_create",
+            "deploy" => "subgraph_deploy",
+            "removeSubgraph" => "subgraph_remove",
+            "reassign" => "subgraph_reassign",
+            "unassign" => "subgraph_unassign",
+            "pause" => "subgraph_pause",
+            "resume" => "subgraph_resume",
+            "rewind" => "subgraph_rewind",
+            _ => return Ok(r::Value::Null),
+        };
+        self.authorize(method)?;
+
+        info!(self.logger, "Received {} request", method;
+            "arguments" => format!("{:?}", field.arguments));
+
+        let registrar = &self.registrar;
+        let result = match field.name.as_str() {
+            "createSubgraph" => {
+                let name = subgraph_name(field)?;
+                graph::block_on(registrar.create_subgraph(name))
+                    .map(|result| r::Value::String(result.id))
+            }
+            "deploy" => {
+                let name = subgraph_name(field)?;
+                let hash = argument::<DeploymentHash>(field, "ipfsHash")?;
+                let node_id = match optional_argument::<String>(field, "nodeId")? {
+                    Some(node_id) => node_id_value(node_id)?,
+                    None => self.node_id.clone(),
+                };
+                let debug_fork = optional_argument::<DeploymentHash>(field, "debugFork")?;
+                let auto_graft = optional_argument::<bool>(field, "autoGraft")?.unwrap_or(false);
+                let upgrade_in_place =
+                    optional_argument::<bool>(field, "upgradeInPlace")?.unwrap_or(false);
+                graph::block_on(registrar.create_subgraph_version(
+                    name,
+                    hash,
+                    node_id,
+                    debug_fork,
+                    None,
+                    auto_graft,
+                    upgrade_in_place,
+                ))
+                .map(|()| r::Value::Boolean(true))
+            }
+            "removeSubgraph" => {
+                let name = subgraph_name(field)?;
+                graph::block_on(registrar.remove_subgraph(name)).map(|()| r::Value::Boolean(true))
+            }
+            "reassign" => {
+                let hash = argument::<DeploymentHash>(field, "deployment")?;
+                let node_id = node_id_value(argument::<String>(field, "nodeId")?)?;
+                graph::block_on(registrar.reassign_subgraph(&hash, &node_id))
+                    .map(|()| r::Value::Boolean(true))
+            }
+            "unassign" => {
+                let hash = argument::<DeploymentHash>(field, "deployment")?;
+                graph::block_on(registrar.unassign_subgraph(&hash))
+                    .map(|()| r::Value::Boolean(true))
+            }
+            "pause" => {
+                let hash = argument::<DeploymentHash>(field, "deployment")?;
+                graph::block_on(registrar.pause_subgraph(&hash)).map(|()| r::Value::Boolean(true))
+            }
+            "resume" => {
+                let hash = argument::<DeploymentHash>(field, "deployment")?;
+                graph::block_on(registrar.resume_subgraph(&hash)).map(|()| r::Value::Boolean(true))
+            }
+            "rewind" => {
+                let hash = argument::<DeploymentHash>(field, "deployment")?;
+                let block_hash = argument::<String>(field, "blockHash")?;
+                let block_number = argument::<i32>(field, "blockNumber")?;
+                let block_ptr_to = BlockPtr::try_from((block_hash.as_str(), block_number as i64))
+                    .map_err(|e| {
+                    QueryExecutionError::ValueParseError("blockHash".to_string(), e.to_string())
+                })?;
+                graph::block_on(registrar.rewind_subgraph(&hash, block_ptr_to))
+                    .map(|()| r::Value::Boolean(true))
+            }
+            _ => unreachable!("all mutations are listed above"),
+        };
+
+        result.map_err(|e| admin_error(&self.logger, method, e))
+    }
+}
+
+fn argument<T: TryFromValue>(field: &a::Field, name: &str) -> Result<T, QueryExecutionError> {
+    field
+        .get_required::<T>(name)
+        .map_err(|e| QueryExecutionError::ValueParseError(name.to_string(), e.to_string()))
+}
+
+fn optional_argument<T: TryFromValue>(
+    field: &a::Field,
+    name: &str,
+) -> Result<Option<T>, QueryExecutionError> {
+    field
+        .get_optional::<T>(name)
+        .map_err(|e| QueryExecutionError::ValueParseError(name.to_string(), e.to_string()))
+}
+
+fn subgraph_name(field: &a::Field) -> Result<SubgraphName, QueryExecutionError> {
+    let name = argument::<String>(field, "name")?;
+    SubgraphName::new(name.clone()).map_err(|()| {
+        QueryExecutionError::ValueParseError(
+            "name".to_string(),
+            format!("invalid subgraph name `{}`", name),
+        )
+    })
+}
+
+fn node_id_value(node_id: String) -> Result<NodeId, QueryExecutionError> {
+    NodeId::new(node_id.clone()).map_err(|()| {
+        QueryExecutionError::ValueParseError(
+            "nodeId".to_string(),
+            format!("invalid node id `{}`", node_id),
+        )
+    })
+}
+
+/// Log a failed mutation and turn the error into one we can return to the
+/// client, hiding the details of internal errors the same way the JSON-RPC
+/// methods do
+fn admin_error(logger: &Logger, method: &str, e: SubgraphRegistrarError) -> QueryExecutionError {
+    error!(logger, "{} failed", method; "error" => format!("{:?}", e));
+
+    let message = if let SubgraphRegistrarError::Unknown(_) = e {
+        "internal error".to_owned()
+    } else {
+        e.to_string()
+    };
+    QueryExecutionError::AdminOperationFailed(message)
+}
+
+#[async_trait]
+impl<R: SubgraphRegistrar> Resolver for AdminResolver<R> {
+    const CACHEABLE: bool = false;
+
+    async fn query_permit(&self) -> OwnedSemaphorePermit {
+        self.permits
+            .cheap_clone()
+            .acquire_owned()
+            .await
+            .expect("the admin semaphore is never closed")
+    }
+
+    fn prefetch(
+        &self,
+        _: &ExecutionContext<Self>,
+        _: &a::SelectionSet,
+    ) -> Result<Option<r::Value>, Vec<QueryExecutionError>> {
+        Ok(None)
+    }
+
+    fn resolve_scalar_value(
+        &self,
+        parent_object_type: &s::ObjectType,
+        field: &a::Field,
+        _scalar_type: &s::ScalarType,
+        value: Option<r::Value>,
+    ) -> Result<r::Value, QueryExecutionError> {
+        match (parent_object_type.name.as_str(), field.name.as_str()) {
+            ("Query", "nodeId") => Ok(r::Value::String(self.node_id.to_string())),
+            ("Mutation", _) => self.resolve_mutation(field),
+
+            // Fallback to the same as is in the default trait implementation.
+            // See also c2112309-44fd-4a84-92a0-5a651e6ed548
+            _ => Ok(value.unwrap_or(r::Value::Null)),
+        }
+    }
+
+    fn resolve_objects(
+        &self,
+        prefetched_objects: Option<r::Value>,
+        _field: &a::Field,
+        _field_definition: &s::Field,
+        _object_type: ObjectOrInterface<'_>,
+    ) -> Result<r::Value, QueryExecutionError> {
+        Ok(prefetched_objects.unwrap_or(r::Value::Null))
+    }
+
+    fn resolve_object(
+        &self,
+        prefetched_object: Option<r::Value>,
+        _field: &a::Field,
+        _field_definition: &s::Field,
+        _object_type: ObjectOrInterface<'_>,
+    ) -> Result<r::Value, QueryExecutionError> {
+        Ok(prefetched_object.unwrap_or(r::Value::Null))
+    }
+}
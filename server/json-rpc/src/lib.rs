@@ -3,21 +3,39 @@ extern crate jsonrpc_http_server;
 extern crate lazy_static;
 extern crate serde;
 
+use graph::components::store::PersistedQueryStore;
+use graph::data::graphql::effort::LoadManager;
+use graph::data::query::persisted_query_hash;
 use graph::prelude::serde_json;
 use graph::prelude::{JsonRpcServer as JsonRpcServerTrait, *};
 use jsonrpc_http_server::{
-    jsonrpc_core::{self, Compatibility, IoHandler, Params, Value},
-    RestApi, Server, ServerBuilder,
+    hyper,
+    jsonrpc_core::{self, Compatibility, MetaIoHandler, Params, Value},
+    RequestMiddlewareAction, RestApi, Server, ServerBuilder,
 };
 
 use std::collections::BTreeMap;
 use std::io;
 use std::net::{Ipv4Addr, SocketAddrV4};
+use std::path::PathBuf;
+
+mod auth;
+mod graphql;
+#[cfg(unix)]
+mod unix;
+
+use auth::{AdminAuth, AdminMeta, AuthError};
+use graphql::{AdminGraphQL, GRAPHQL_PATH};
 
 const JSON_RPC_DEPLOY_ERROR: i64 = 0;
 const JSON_RPC_REMOVE_ERROR: i64 = 1;
 const JSON_RPC_CREATE_ERROR: i64 = 2;
 const JSON_RPC_REASSIGN_ERROR: i64 = 3;
+const JSON_RPC_PERSISTED_QUERY_ERROR: i64 = 4;
+const JSON_RPC_PAUSE_ERROR: i64 = 5;
+const JSON_RPC_RESUME_ERROR: i64 = 6;
+const JSON_RPC_UNAUTHORIZED_ERROR: i64 = 7;
+const JSON_RPC_VALIDATE_ERROR: i64 = 8;
 
 #[derive(Debug, Deserialize)]
 struct SubgraphCreateParams {
@@ -30,6 +48,13 @@ struct SubgraphDeployParams {
     ipfs_hash: DeploymentHash,
     node_id: Option<NodeId>,
     debug_fork: Option<DeploymentHash>,
+    auto_graft: Option<bool>,
+    upgrade_in_place: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphValidateParams {
+    ipfs_hash: DeploymentHash,
 }
 
 #[derive(Debug, Deserialize)]
@@ -43,15 +68,55 @@ struct SubgraphReassignParams {
     node_id: NodeId,
 }
 
+#[derive(Debug, Deserialize)]
+struct SubgraphPauseParams {
+    deployment: DeploymentHash,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubgraphResumeParams {
+    deployment: DeploymentHash,
+}
+
+#[derive(Debug, Deserialize)]
+struct PersistedQueryRegisterParams {
+    query: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PersistedQueryRemoveParams {
+    hash: String,
+}
+
 pub struct JsonRpcServer<R> {
     registrar: Arc<R>,
+    persisted_queries: Arc<dyn PersistedQueryStore>,
     http_port: u16,
     ws_port: u16,
     node_id: NodeId,
+    auth: Option<Arc<AdminAuth>>,
     logger: Logger,
 }
 
 impl<R: SubgraphRegistrar> JsonRpcServer<R> {
+    /// Check that the request may call `method`. All requests are allowed
+    /// if no admin tokens are configured
+    fn authorize(&self, meta: &AdminMeta, method: &str) -> Result<(), jsonrpc_core::Error> {
+        let auth = match &self.auth {
+            Some(auth) => auth,
+            None => return Ok(()),
+        };
+        match auth.authorize(meta.token.as_deref(), method) {
+            Ok(name) => {
+                info!(self.logger, "Authorized admin request";
+                    "method" => method,
+                    "token" => name);
+                Ok(())
+            }
+            Err(e) => Err(unauthorized_error(&self.logger, method, e)),
+        }
+    }
+
     /// Handler for the `subgraph_create` endpoint.
     async fn create_handler(
         &self,
@@ -92,6 +157,8 @@ impl<R: SubgraphRegistrar> JsonRpcServer<R> {
                 // Here it doesn't make sense to receive another
                 // startBlock, we'll use the one from the manifest.
                 None,
+                params.auto_graft.unwrap_or(false),
+                params.upgrade_in_place.unwrap_or(false),
             )
             .await
         {
@@ -106,6 +173,30 @@ impl<R: SubgraphRegistrar> JsonRpcServer<R> {
         }
     }
 
+    /// Handler for the `subgraph_validate` endpoint. Returns the problems
+    /// that would prevent deploying the subgraph
+    async fn validate_handler(
+        &self,
+        params: SubgraphValidateParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received subgraph_validate request"; "params" => format!("{:?}", params));
+
+        match self
+            .registrar
+            .validate_subgraph_version(params.ipfs_hash.clone())
+            .await
+        {
+            Ok(problems) => Ok(jsonrpc_core::to_value(problems).unwrap()),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "subgraph_validate",
+                e,
+                JSON_RPC_VALIDATE_ERROR,
+                params,
+            )),
+        }
+    }
+
     /// Handler for the `subgraph_remove` endpoint.
     async fn remove_handler(
         &self,
@@ -149,6 +240,108 @@ impl<R: SubgraphRegistrar> JsonRpcServer<R> {
             )),
         }
     }
+
+    /// Handler for the `subgraph_pause` endpoint.
+    async fn pause_handler(
+        &self,
+        params: SubgraphPauseParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        let logger = self.logger.clone();
+
+        info!(logger, "Received subgraph_pause request"; "params" => format!("{:?}", params));
+
+        match self.registrar.pause_subgraph(&params.deployment).await {
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(json_rpc_error(
+                &logger,
+                "subgraph_pause",
+                e,
+                JSON_RPC_PAUSE_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `subgraph_resume` endpoint.
+    async fn resume_handler(
+        &self,
+        params: SubgraphResumeParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        let logger = self.logger.clone();
+
+        info!(logger, "Received subgraph_resume request"; "params" => format!("{:?}", params));
+
+        match self.registrar.resume_subgraph(&params.deployment).await {
+            Ok(_) => Ok(Value::Null),
+            Err(e) => Err(json_rpc_error(
+                &logger,
+                "subgraph_resume",
+                e,
+                JSON_RPC_RESUME_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `persisted_query_register` endpoint. Returns the
+    /// hash under which clients can run the query
+    async fn persisted_query_register_handler(
+        &self,
+        params: PersistedQueryRegisterParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received persisted_query_register request"; "params" => format!("{:?}", params));
+
+        // Only accept queries that we will be able to run
+        if let Err(e) = q::parse_query::<String>(&params.query) {
+            return Err(persisted_query_error(
+                &self.logger,
+                "persisted_query_register",
+                format!("invalid query: {}", e),
+                params,
+            ));
+        }
+
+        let hash = persisted_query_hash(&params.query);
+        match self.persisted_queries.register(&hash, &params.query) {
+            Ok(()) => {
+                let mut map = BTreeMap::new();
+                map.insert("hash", hash);
+                Ok(jsonrpc_core::to_value(map).unwrap())
+            }
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "persisted_query_register",
+                e.into(),
+                JSON_RPC_PERSISTED_QUERY_ERROR,
+                params,
+            )),
+        }
+    }
+
+    /// Handler for the `persisted_query_remove` endpoint.
+    async fn persisted_query_remove_handler(
+        &self,
+        params: PersistedQueryRemoveParams,
+    ) -> Result<Value, jsonrpc_core::Error> {
+        info!(&self.logger, "Received persisted_query_remove request"; "params" => format!("{:?}", params));
+
+        match self.persisted_queries.remove(&params.hash) {
+            Ok(true) => Ok(Value::Null),
+            Ok(false) => Err(persisted_query_error(
+                &self.logger,
+                "persisted_query_remove",
+                format!("persisted query not found: {}", params.hash),
+                params,
+            )),
+            Err(e) => Err(json_rpc_error(
+                &self.logger,
+                "persisted_query_remove",
+                e.into(),
+                JSON_RPC_PERSISTED_QUERY_ERROR,
+                params,
+            )),
+        }
+    }
 }
 
 impl<R> JsonRpcServerTrait<R> for JsonRpcServer<R>
@@ -162,7 +355,10 @@ where
         http_port: u16,
         ws_port: u16,
         registrar: Arc<R>,
+        persisted_queries: Arc<dyn PersistedQueryStore>,
+        load_manager: Arc<LoadManager>,
         node_id: NodeId,
+        unix_socket: Option<PathBuf>,
         logger: Logger,
     ) -> Result<Self::Server, io::Error> {
         let logger = logger.new(o!("component" => "JsonRpcServer"));
@@ -174,57 +370,171 @@ where
 
         let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
 
-        let mut handler = IoHandler::with_compatibility(Compatibility::Both);
+        let auth = AdminAuth::from_env()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+            .map(Arc::new);
+        if auth.is_none() {
+            warn!(
+                logger,
+                "The JSON-RPC admin server accepts requests without authentication; \
+                 set GRAPH_ADMIN_TOKENS_FILE to require bearer tokens"
+            );
+        }
+
+        let mut handler = MetaIoHandler::with_compatibility(Compatibility::Both);
+
+        let graphql = Arc::new(AdminGraphQL::new(
+            registrar.cheap_clone(),
+            auth.clone(),
+            node_id.clone(),
+            load_manager,
+            logger.clone(),
+        ));
 
         let arc_self = Arc::new(JsonRpcServer {
             registrar,
+            persisted_queries,
             http_port,
             ws_port,
             node_id,
-            logger,
+            auth,
+            logger: logger.clone(),
         });
 
         let me = arc_self.clone();
-        handler.add_method("subgraph_create", move |params: Params| {
+        handler.add_method_with_meta("subgraph_create", move |params: Params, meta: AdminMeta| {
             let me = me.clone();
             async move {
+                me.authorize(&meta, "subgraph_create")?;
                 let params = params.parse()?;
                 me.create_handler(params).await
             }
         });
 
         let me = arc_self.clone();
-        handler.add_method("subgraph_deploy", move |params: Params| {
+        handler.add_method_with_meta("subgraph_deploy", move |params: Params, meta: AdminMeta| {
             let me = me.clone();
             async move {
+                me.authorize(&meta, "subgraph_deploy")?;
                 let params = params.parse()?;
                 me.deploy_handler(params).await
             }
         });
 
         let me = arc_self.clone();
-        handler.add_method("subgraph_remove", move |params: Params| {
+        handler.add_method_with_meta(
+            "subgraph_validate",
+            move |params: Params, meta: AdminMeta| {
+                let me = me.clone();
+                async move {
+                    me.authorize(&meta, "subgraph_validate")?;
+                    let params = params.parse()?;
+                    me.validate_handler(params).await
+                }
+            },
+        );
+
+        let me = arc_self.clone();
+        handler.add_method_with_meta("subgraph_remove", move |params: Params, meta: AdminMeta| {
             let me = me.clone();
             async move {
+                me.authorize(&meta, "subgraph_remove")?;
                 let params = params.parse()?;
                 me.remove_handler(params).await
             }
         });
 
-        let me = arc_self;
-        handler.add_method("subgraph_reassign", move |params: Params| {
+        let me = arc_self.clone();
+        handler.add_method_with_meta(
+            "subgraph_reassign",
+            move |params: Params, meta: AdminMeta| {
+                let me = me.clone();
+                async move {
+                    me.authorize(&meta, "subgraph_reassign")?;
+                    let params = params.parse()?;
+                    me.reassign_handler(params).await
+                }
+            },
+        );
+
+        let me = arc_self.clone();
+        handler.add_method_with_meta("subgraph_pause", move |params: Params, meta: AdminMeta| {
             let me = me.clone();
             async move {
+                me.authorize(&meta, "subgraph_pause")?;
                 let params = params.parse()?;
-                me.reassign_handler(params).await
+                me.pause_handler(params).await
             }
         });
 
-        ServerBuilder::new(handler)
-            // Enable REST API:
-            // POST /<method>/<param1>/<param2>
-            .rest_api(RestApi::Secure)
-            .start_http(&addr.into())
+        let me = arc_self.clone();
+        handler.add_method_with_meta("subgraph_resume", move |params: Params, meta: AdminMeta| {
+            let me = me.clone();
+            async move {
+                me.authorize(&meta, "subgraph_resume")?;
+                let params = params.parse()?;
+                me.resume_handler(params).await
+            }
+        });
+
+        let me = arc_self.clone();
+        handler.add_method_with_meta(
+            "persisted_query_register",
+            move |params: Params, meta: AdminMeta| {
+                let me = me.clone();
+                async move {
+                    me.authorize(&meta, "persisted_query_register")?;
+                    let params = params.parse()?;
+                    me.persisted_query_register_handler(params).await
+                }
+            },
+        );
+
+        let me = arc_self;
+        handler.add_method_with_meta(
+            "persisted_query_remove",
+            move |params: Params, meta: AdminMeta| {
+                let me = me.clone();
+                async move {
+                    me.authorize(&meta, "persisted_query_remove")?;
+                    let params = params.parse()?;
+                    me.persisted_query_remove_handler(params).await
+                }
+            },
+        );
+
+        match unix_socket {
+            #[cfg(unix)]
+            Some(path) => unix::serve(
+                &path,
+                Arc::new(handler.clone()),
+                graphql.cheap_clone(),
+                logger,
+            )?,
+            #[cfg(not(unix))]
+            Some(_) => warn!(logger, "Unix sockets are only supported on Unix"),
+            None => {}
+        }
+
+        ServerBuilder::with_meta_extractor(handler, |request: &hyper::Request<hyper::Body>| {
+            AdminMeta::from_request(request)
+        })
+        // Serve the same operations as GraphQL mutations:
+        // POST /graphql
+        .request_middleware(move |request: hyper::Request<hyper::Body>| {
+            if request.method() == hyper::Method::POST && request.uri().path() == GRAPHQL_PATH {
+                RequestMiddlewareAction::Respond {
+                    should_validate_hosts: true,
+                    response: Box::pin(graphql.cheap_clone().handle(request)),
+                }
+            } else {
+                request.into()
+            }
+        })
+        // Enable REST API:
+        // POST /<method>/<param1>/<param2>
+        .rest_api(RestApi::Secure)
+        .start_http(&addr.into())
     }
 }
 
@@ -252,6 +562,36 @@ fn json_rpc_error(
     }
 }
 
+/// Report that a request was refused because of its token
+fn unauthorized_error(logger: &Logger, operation: &str, e: AuthError) -> jsonrpc_core::Error {
+    warn!(logger, "{} refused", operation; "error" => e.to_string());
+
+    jsonrpc_core::Error {
+        code: jsonrpc_core::ErrorCode::ServerError(JSON_RPC_UNAUTHORIZED_ERROR),
+        message: format!("unauthorized: {}", e),
+        data: None,
+    }
+}
+
+/// Report an error in a request for a persisted query that is the
+/// client's fault
+fn persisted_query_error(
+    logger: &Logger,
+    operation: &str,
+    message: String,
+    params: impl std::fmt::Debug,
+) -> jsonrpc_core::Error {
+    error!(logger, "{} failed", operation;
+        "error" => &message,
+        "params" => format!("{:?}", params));
+
+    jsonrpc_core::Error {
+        code: jsonrpc_core::ErrorCode::ServerError(JSON_RPC_PERSISTED_QUERY_ERROR),
+        message,
+        data: None,
+    }
+}
+
 pub fn parse_response(response: Value) -> Result<(), jsonrpc_core::Error> {
     // serde deserialization of the `id` field to an `Id` struct is somehow
     // incompatible with the `arbitrary-precision` feature which we use, so we
@@ -0,0 +1,88 @@
+use std::io;
+use std::path::Path;
+
+use graph::prelude::*;
+use graph::util::unix_socket;
+use jsonrpc_http_server::hyper::{
+    self, header::CONTENT_TYPE, server::conn::Http, service::service_fn, Body, Method, Request,
+    Response, StatusCode,
+};
+use jsonrpc_http_server::jsonrpc_core::MetaIoHandler;
+
+use crate::auth::AdminMeta;
+use crate::graphql::{AdminGraphQL, GRAPHQL_PATH};
+
+/// Serve the JSON-RPC methods and the admin GraphQL API on the unix socket
+/// at `path`. JSON-RPC requests are only accepted as the body of a `POST`;
+/// the REST API of the HTTP server is not available on the socket
+pub(crate) fn serve<R: SubgraphRegistrar>(
+    path: &Path,
+    handler: Arc<MetaIoHandler<AdminMeta>>,
+    graphql: Arc<AdminGraphQL<R>>,
+    logger: Logger,
+) -> io::Result<()> {
+    let listener = unix_socket::bind(path)?;
+    info!(
+        logger,
+        "Starting JSON-RPC admin server at: unix:{}",
+        path.display()
+    );
+
+    graph::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    trace!(logger, "Connection error: {}", e);
+                    continue;
+                }
+            };
+            let handler = handler.cheap_clone();
+            let graphql = graphql.cheap_clone();
+            let logger = logger.clone();
+            let service = service_fn(move |request| {
+                handle(handler.cheap_clone(), graphql.cheap_clone(), request)
+            });
+            graph::spawn(async move {
+                if let Err(e) = Http::new().serve_connection(stream, service).await {
+                    trace!(logger, "Admin connection failed: {}", e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn handle<R: SubgraphRegistrar>(
+    handler: Arc<MetaIoHandler<AdminMeta>>,
+    graphql: Arc<AdminGraphQL<R>>,
+    request: Request<Body>,
+) -> hyper::Result<Response<Body>> {
+    if request.method() != Method::POST {
+        return Ok(Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .body(Body::from("Only POST requests are supported\n"))
+            .unwrap());
+    }
+    if request.uri().path() == GRAPHQL_PATH {
+        return graphql.handle(request).await;
+    }
+
+    let meta = AdminMeta::from_request(&request);
+    let body = hyper::body::to_bytes(request.into_body()).await?;
+    let body = match std::str::from_utf8(&body) {
+        Ok(body) => body,
+        Err(_) => {
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("The request body is not valid UTF-8\n"))
+                .unwrap())
+        }
+    };
+    let response = handler.handle_request(body, meta).await;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(response.unwrap_or_default()))
+        .unwrap())
+}
@@ -2,10 +2,13 @@ use std::net::{Ipv4Addr, SocketAddrV4};
 use std::sync::Arc;
 
 use anyhow::Error;
+use graph::components::server::health::Readiness;
 use graph::prometheus::{Encoder, Registry, TextEncoder};
+use graph::util::tls::TlsSettings;
 use hyper::header::{ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_TYPE};
+use hyper::server::accept;
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Response, Server};
+use hyper::{Body, Request, Response, Server, StatusCode};
 use thiserror::Error;
 
 use graph::prelude::{MetricsServer as MetricsServerTrait, *};
@@ -15,6 +18,8 @@ use graph::prelude::{MetricsServer as MetricsServerTrait, *};
 pub enum PrometheusMetricsServeError {
     #[error("Bind error: {0}")]
     BindError(hyper::Error),
+    #[error("TLS bind error: {0}")]
+    TlsBindError(std::io::Error),
 }
 
 impl From<hyper::Error> for PrometheusMetricsServeError {
@@ -26,6 +31,8 @@ impl From<hyper::Error> for PrometheusMetricsServeError {
 pub struct PrometheusMetricsServer {
     logger: Logger,
     registry: Arc<Registry>,
+    readiness: Arc<Readiness>,
+    tls: Option<Arc<TlsSettings>>,
 }
 
 impl Clone for PrometheusMetricsServer {
@@ -33,15 +40,74 @@ impl Clone for PrometheusMetricsServer {
         Self {
             logger: self.logger.clone(),
             registry: self.registry.clone(),
+            readiness: self.readiness.clone(),
+            tls: self.tls.clone(),
         }
     }
 }
 
 impl PrometheusMetricsServer {
-    pub fn new(logger_factory: &LoggerFactory, registry: Arc<Registry>) -> Self {
+    pub fn new(
+        logger_factory: &LoggerFactory,
+        registry: Arc<Registry>,
+        readiness: Arc<Readiness>,
+    ) -> Self {
         PrometheusMetricsServer {
             logger: logger_factory.component_logger("MetricsServer", None),
             registry,
+            readiness,
+            tls: None,
+        }
+    }
+
+    /// Only accept TLS connections using `tls`
+    pub fn with_tls(mut self, tls: Arc<TlsSettings>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    fn metrics(&self) -> Response<Body> {
+        let metric_families = self.registry.gather();
+        let mut buffer = vec![];
+        let encoder = TextEncoder::new();
+        encoder.encode(&metric_families, &mut buffer).unwrap();
+        Response::builder()
+            .status(200)
+            .header(CONTENT_TYPE, encoder.format_type())
+            .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+            .body(Body::from(buffer))
+            .unwrap()
+    }
+
+    /// The process is up and answering requests
+    fn live() -> Response<Body> {
+        Response::builder()
+            .status(200)
+            .header(CONTENT_TYPE, "text/plain")
+            .body(Body::from("OK"))
+            .unwrap()
+    }
+
+    /// Whether all components are ready, with the details for each of them
+    fn ready(&self) -> Response<Body> {
+        let report = self.readiness.report();
+        let status = if report.ready {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_string(&report).unwrap()))
+            .unwrap()
+    }
+
+    fn handle(&self, req: Request<Body>) -> Response<Body> {
+        match req.uri().path() {
+            "/live" => Self::live(),
+            "/ready" => self.ready(),
+            _ => self.metrics(),
         }
     }
 }
@@ -55,38 +121,44 @@ impl MetricsServerTrait for PrometheusMetricsServer {
     ) -> Result<Box<dyn Future<Item = (), Error = ()> + Send>, Self::ServeError> {
         let logger = self.logger.clone();
 
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
         info!(
             logger,
-            "Starting metrics server at: http://localhost:{}", port,
+            "Starting metrics server at: {}://localhost:{}", scheme, port,
         );
 
         let addr = SocketAddrV4::new(Ipv4Addr::new(0, 0, 0, 0), port);
 
         let server = self.clone();
-        let new_service = make_service_fn(move |_req| {
+        let new_service = move || {
             let server = server.clone();
             async move {
-                Ok::<_, Error>(service_fn(move |_| {
-                    let metric_families = server.registry.gather();
-                    let mut buffer = vec![];
-                    let encoder = TextEncoder::new();
-                    encoder.encode(&metric_families, &mut buffer).unwrap();
-                    futures03::future::ok::<_, Error>(
-                        Response::builder()
-                            .status(200)
-                            .header(CONTENT_TYPE, encoder.format_type())
-                            .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
-                            .body(Body::from(buffer))
-                            .unwrap(),
-                    )
+                Ok::<_, Error>(service_fn(move |req| {
+                    futures03::future::ok::<_, Error>(server.handle(req))
                 }))
             }
-        });
-
-        let task = Server::try_bind(&addr.into())?
-            .serve(new_service)
-            .map_err(move |e| error!(logger, "Metrics server error"; "error" => format!("{}", e)));
+        };
 
-        Ok(Box::new(task.compat()))
+        match &self.tls {
+            Some(tls) => {
+                let incoming = tls
+                    .incoming(addr.into())
+                    .map_err(PrometheusMetricsServeError::TlsBindError)?;
+                let task = Server::builder(accept::from_stream(incoming))
+                    .serve(make_service_fn(move |_| new_service()))
+                    .map_err(move |e| {
+                        error!(logger, "Metrics server error"; "error" => format!("{}", e))
+                    });
+                Ok(Box::new(task.compat()))
+            }
+            None => {
+                let task = Server::try_bind(&addr.into())?
+                    .serve(make_service_fn(move |_| new_service()))
+                    .map_err(move |e| {
+                        error!(logger, "Metrics server error"; "error" => format!("{}", e))
+                    });
+                Ok(Box::new(task.compat()))
+            }
+        }
     }
 }
@@ -5,11 +5,48 @@ use graphql_parser::parse_query;
 use http::StatusCode;
 use std::collections::HashMap;
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
 use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
 use tokio_tungstenite::WebSocketStream;
 use uuid::Uuid;
 
-use graph::{data::query::QueryTarget, prelude::*};
+use graph::{
+    blockchain::BlockHash,
+    components::store::PersistedQueryStore,
+    data::query::{persisted_query_hash, QueryTarget},
+    prelude::*,
+};
+
+/// The GraphQL over WebSocket protocol that a connection speaks. Clients
+/// choose the protocol through the `Sec-WebSocket-Protocol` header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Protocol {
+    /// The legacy `graphql-ws` protocol of `subscriptions-transport-ws`
+    GraphQlWs,
+    /// The `graphql-transport-ws` protocol of the `graphql-ws` library
+    GraphQlTransportWs,
+}
+
+impl Protocol {
+    /// Pick the protocol from the comma-separated list of protocols that
+    /// the client sent, falling back to the legacy protocol
+    pub(crate) fn from_header(header: Option<&str>) -> Self {
+        let requested = header.unwrap_or("").split(',').map(str::trim);
+        if requested.into_iter().any(|p| p == "graphql-transport-ws") {
+            Protocol::GraphQlTransportWs
+        } else {
+            Protocol::GraphQlWs
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::GraphQlWs => "graphql-ws",
+            Protocol::GraphQlTransportWs => "graphql-transport-ws",
+        }
+    }
+}
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -17,6 +54,36 @@ struct StartPayload {
     query: String,
     variables: Option<serde_json::Value>,
     operation_name: Option<String>,
+    /// A filter in the syntax of `where` arguments that the server applies
+    /// to the results of the subscription
+    #[serde(rename = "where")]
+    filter: Option<serde_json::Value>,
+    /// The cursor of the last result the client received, to resume a
+    /// subscription after reconnecting
+    cursor: Option<Cursor>,
+}
+
+/// The block of a result that was sent to a client. A client that
+/// reconnects passes the cursor of the last result it received so that it
+/// is sent the results for the blocks it missed
+#[derive(Debug, Deserialize, Serialize)]
+struct Cursor {
+    number: BlockNumber,
+    hash: String,
+}
+
+impl Cursor {
+    fn from_block_ptr(ptr: &BlockPtr) -> Self {
+        Cursor {
+            number: ptr.number,
+            hash: ptr.hash.to_string(),
+        }
+    }
+
+    fn to_block_ptr(&self) -> Result<BlockPtr, anyhow::Error> {
+        let hash = BlockHash::try_from(self.hash.as_str())?;
+        Ok(BlockPtr::new(hash, self.number))
+    }
 }
 
 /// GraphQL/WebSocket message received from a client.
@@ -35,12 +102,59 @@ enum IncomingMessage {
     Stop {
         id: String,
     },
+    #[serde(skip)]
+    Ping,
+    #[serde(skip)]
+    Pong,
+}
+
+/// Message received from a client that speaks `graphql-transport-ws`
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum TransportIncomingMessage {
+    ConnectionInit {
+        payload: Option<serde_json::Value>,
+    },
+    Ping {
+        #[allow(dead_code)]
+        payload: Option<serde_json::Value>,
+    },
+    Pong {
+        #[allow(dead_code)]
+        payload: Option<serde_json::Value>,
+    },
+    Subscribe {
+        id: String,
+        payload: StartPayload,
+    },
+    Complete {
+        id: String,
+    },
+}
+
+impl From<TransportIncomingMessage> for IncomingMessage {
+    fn from(msg: TransportIncomingMessage) -> Self {
+        use TransportIncomingMessage::*;
+        match msg {
+            ConnectionInit { payload } => IncomingMessage::ConnectionInit { payload },
+            Ping { .. } => IncomingMessage::Ping,
+            Pong { .. } => IncomingMessage::Pong,
+            Subscribe { id, payload } => IncomingMessage::Start { id, payload },
+            Complete { id } => IncomingMessage::Stop { id },
+        }
+    }
 }
 
 impl IncomingMessage {
-    pub fn from_ws_message(msg: WsMessage) -> Result<Self, WsError> {
+    pub fn from_ws_message(msg: WsMessage, protocol: Protocol) -> Result<Self, WsError> {
         let text = msg.into_text()?;
-        serde_json::from_str(text.as_str()).map_err(|e| {
+        let msg = match protocol {
+            Protocol::GraphQlWs => serde_json::from_str(text.as_str()),
+            Protocol::GraphQlTransportWs => {
+                serde_json::from_str::<TransportIncomingMessage>(text.as_str()).map(Self::from)
+            }
+        };
+        msg.map_err(|e| {
             WsError::Http(http::Response::new(Some(format!(
                 "Invalid GraphQL over WebSocket message: {}: {}",
                 text, e
@@ -61,37 +175,102 @@ enum OutgoingMessage {
     Data {
         id: String,
         payload: Arc<QueryResult>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cursor: Option<Cursor>,
     },
     Complete {
         id: String,
     },
+    /// Only used for `graphql-transport-ws`
+    Pong,
+    /// Close the connection with one of the error codes of
+    /// `graphql-transport-ws`
+    #[serde(skip)]
+    Close {
+        code: u16,
+        reason: String,
+    },
 }
 
 impl OutgoingMessage {
     pub fn from_query_result(id: String, result: Arc<QueryResult>) -> Self {
+        let cursor = result.block_ptr.as_ref().map(Cursor::from_block_ptr);
         OutgoingMessage::Data {
             id,
             payload: result,
+            cursor,
         }
     }
 
     pub fn from_error_string(id: String, s: String) -> Self {
         OutgoingMessage::Error { id, payload: s }
     }
-}
 
-impl From<OutgoingMessage> for WsMessage {
-    fn from(msg: OutgoingMessage) -> Self {
-        WsMessage::text(serde_json::to_string(&msg).expect("invalid GraphQL/WebSocket message"))
+    /// Turn the message into the form that `protocol` uses for it
+    fn into_ws_message(self, protocol: Protocol) -> WsMessage {
+        let msg = match (self, protocol) {
+            (OutgoingMessage::Close { code, reason }, _) => {
+                return WsMessage::Close(Some(CloseFrame {
+                    code: CloseCode::from(code),
+                    reason: reason.into(),
+                }))
+            }
+            (msg, Protocol::GraphQlWs) => serde_json::to_value(&msg),
+            (
+                OutgoingMessage::Data {
+                    id,
+                    payload,
+                    cursor,
+                },
+                Protocol::GraphQlTransportWs,
+            ) => serde_json::to_value(&TransportOutgoingMessage::Next {
+                id,
+                payload,
+                cursor,
+            }),
+            (OutgoingMessage::Error { id, payload }, Protocol::GraphQlTransportWs) => {
+                serde_json::to_value(&TransportOutgoingMessage::Error {
+                    id,
+                    payload: vec![TransportError { message: payload }],
+                })
+            }
+            (msg, Protocol::GraphQlTransportWs) => serde_json::to_value(&msg),
+        };
+        WsMessage::text(
+            msg.map(|msg| msg.to_string())
+                .expect("invalid GraphQL/WebSocket message"),
+        )
     }
 }
 
+/// The messages that `graphql-transport-ws` names differently or whose
+/// payload has a different shape than in the legacy protocol
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum TransportOutgoingMessage {
+    Next {
+        id: String,
+        payload: Arc<QueryResult>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cursor: Option<Cursor>,
+    },
+    Error {
+        id: String,
+        payload: Vec<TransportError>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct TransportError {
+    message: String,
+}
+
 /// Helper function to send outgoing messages.
 fn send_message(
-    sink: &mpsc::UnboundedSender<WsMessage>,
+    sink: &mpsc::UnboundedSender<OutgoingMessage>,
     msg: OutgoingMessage,
 ) -> Result<(), WsError> {
-    sink.unbounded_send(msg.into()).map_err(|_| {
+    sink.unbounded_send(msg).map_err(|_| {
         let mut response = http::Response::new(None);
         *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
         WsError::Http(response)
@@ -100,11 +279,11 @@ fn send_message(
 
 /// Helper function to send error messages.
 fn send_error_string(
-    sink: &mpsc::UnboundedSender<WsMessage>,
+    sink: &mpsc::UnboundedSender<OutgoingMessage>,
     operation_id: String,
     error: String,
 ) -> Result<(), WsError> {
-    sink.unbounded_send(OutgoingMessage::from_error_string(operation_id, error).into())
+    sink.unbounded_send(OutgoingMessage::from_error_string(operation_id, error))
         .map_err(|_| {
             let mut response = http::Response::new(None);
             *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
@@ -116,14 +295,16 @@ fn send_error_string(
 /// On drop, cancels all operations.
 struct Operations {
     operations: HashMap<String, CancelGuard>,
-    msg_sink: mpsc::UnboundedSender<WsMessage>,
+    msg_sink: mpsc::UnboundedSender<OutgoingMessage>,
+    protocol: Protocol,
 }
 
 impl Operations {
-    fn new(msg_sink: mpsc::UnboundedSender<WsMessage>) -> Self {
+    fn new(msg_sink: mpsc::UnboundedSender<OutgoingMessage>, protocol: Protocol) -> Self {
         Self {
             operations: HashMap::new(),
             msg_sink,
+            protocol,
         }
     }
 
@@ -138,6 +319,14 @@ impl Operations {
     fn stop(&mut self, operation_id: String) -> Result<(), WsError> {
         // Remove the operation with this ID from the known operations.
         match self.operations.remove(&operation_id) {
+            // With `graphql-transport-ws`, the client has already stopped
+            // listening and does not expect an answer, not even for an
+            // operation that we do not know
+            Some(stopper) if self.protocol == Protocol::GraphQlTransportWs => {
+                stopper.cancel();
+                Ok(())
+            }
+            None if self.protocol == Protocol::GraphQlTransportWs => Ok(()),
             Some(stopper) => {
                 // Cancel the subscription result stream.
                 stopper.cancel();
@@ -176,6 +365,8 @@ pub struct GraphQlConnection<Q, S> {
     graphql_runner: Arc<Q>,
     stream: WebSocketStream<S>,
     deployment: DeploymentHash,
+    protocol: Protocol,
+    persisted_queries: Option<Arc<dyn PersistedQueryStore>>,
 }
 
 impl<Q, S> GraphQlConnection<Q, S>
@@ -189,6 +380,8 @@ where
         deployment: DeploymentHash,
         stream: WebSocketStream<S>,
         graphql_runner: Arc<Q>,
+        protocol: Protocol,
+        persisted_queries: Option<Arc<dyn PersistedQueryStore>>,
     ) -> Self {
         GraphQlConnection {
             id: Uuid::new_v4().to_string(),
@@ -196,18 +389,24 @@ where
             graphql_runner,
             stream,
             deployment,
+            protocol,
+            persisted_queries,
         }
     }
 
     async fn handle_incoming_messages(
         mut ws_stream: SplitStream<WebSocketStream<S>>,
-        mut msg_sink: mpsc::UnboundedSender<WsMessage>,
+        mut msg_sink: mpsc::UnboundedSender<OutgoingMessage>,
         logger: Logger,
         connection_id: String,
         deployment: DeploymentHash,
         graphql_runner: Arc<Q>,
+        protocol: Protocol,
+        persisted_queries: Option<Arc<dyn PersistedQueryStore>>,
     ) -> Result<(), WsError> {
-        let mut operations = Operations::new(msg_sink.clone());
+        let mut operations = Operations::new(msg_sink.clone(), protocol);
+        let transport_ws = protocol == Protocol::GraphQlTransportWs;
+        let mut initialized = false;
 
         // Process incoming messages as long as the WebSocket is open
         while let Some(ws_msg) = ws_stream.try_next().await? {
@@ -218,15 +417,54 @@ where
                    "connection" => &connection_id,
                    "msg" => format!("{}", ws_msg).as_str());
 
-            let msg = IncomingMessage::from_ws_message(ws_msg.clone())?;
+            // `graphql-transport-ws` expects the server to close the
+            // connection with a specific code when the client violates the
+            // protocol
+            let close = |code: u16, reason: String| {
+                send_message(&msg_sink, OutgoingMessage::Close { code, reason })
+            };
+
+            let msg = match IncomingMessage::from_ws_message(ws_msg.clone(), protocol) {
+                Ok(msg) => msg,
+                Err(e) if transport_ws => {
+                    close(4400, e.to_string())?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
 
             debug!(logger, "GraphQL/WebSocket message";
                    "connection" => &connection_id,
                    "msg" => format!("{:?}", msg).as_str());
 
+            if transport_ws {
+                match &msg {
+                    ConnectionInit { .. } if initialized => {
+                        close(4429, "Too many initialisation requests".to_string())?;
+                        continue;
+                    }
+                    Start { .. } if !initialized => {
+                        close(4401, "Unauthorized".to_string())?;
+                        continue;
+                    }
+                    Start { id, .. } if operations.contains(id) => {
+                        close(4409, format!("Subscriber for {} already exists", id))?;
+                        continue;
+                    }
+                    _ => (),
+                }
+            }
+
             match msg {
                 // Always accept connection init requests
-                ConnectionInit { payload: _ } => send_message(&msg_sink, ConnectionAck),
+                ConnectionInit { payload: _ } => {
+                    initialized = true;
+                    send_message(&msg_sink, ConnectionAck)
+                }
+
+                // Answer pings; pongs need no answer
+                IncomingMessage::Ping => send_message(&msg_sink, OutgoingMessage::Pong),
+                IncomingMessage::Pong => Ok(()),
 
                 // When receiving a connection termination request
                 ConnectionTerminate => {
@@ -264,6 +502,22 @@ where
                         }
                     }
 
+                    // Only run queries that have been registered
+                    if ENV_VARS.graphql.enforce_persisted_queries
+                        && persisted_queries
+                            .as_ref()
+                            .and_then(|persisted_queries| {
+                                persisted_queries.find(&persisted_query_hash(&payload.query))
+                            })
+                            .is_none()
+                    {
+                        return send_error_string(
+                            &msg_sink,
+                            id,
+                            "Only persisted queries can be run on this node".to_string(),
+                        );
+                    }
+
                     // Parse the GraphQL query document; respond with a GQL_ERROR if
                     // the query is invalid
                     let query = match parse_query(&payload.query) {
@@ -301,12 +555,40 @@ where
                         }
                     };
 
+                    // Parse the filter and the cursor, if present
+                    let filter = match payload.filter {
+                        None | Some(serde_json::Value::Null) => None,
+                        Some(filter) => match SubscriptionFilter::from_json(filter) {
+                            Ok(filter) => Some(filter),
+                            Err(e) => {
+                                return send_error_string(
+                                    &msg_sink,
+                                    id,
+                                    format!("Invalid filter provided: {}", e),
+                                );
+                            }
+                        },
+                    };
+                    let cursor = match payload.cursor.as_ref().map(Cursor::to_block_ptr) {
+                        None => None,
+                        Some(Ok(cursor)) => Some(cursor),
+                        Some(Err(e)) => {
+                            return send_error_string(
+                                &msg_sink,
+                                id,
+                                format!("Invalid cursor provided: {}", e),
+                            );
+                        }
+                    };
+
                     // Construct a subscription
                     let target = QueryTarget::Deployment(deployment.clone());
                     let subscription = Subscription {
                         // Subscriptions currently do not benefit from the generational cache
                         // anyways, so don't bother passing a network.
                         query: Query::new(query, variables),
+                        filter,
+                        cursor,
                     };
 
                     debug!(logger, "Start operation";
@@ -347,7 +629,7 @@ where
 
                                         // An error means the client closed the websocket, ignore
                                         // and let it be handled in the websocket loop above.
-                                        let _ = error_sink.unbounded_send(msg.into());
+                                        let _ = error_sink.unbounded_send(msg);
                                     }
                                 }
                             };
@@ -358,13 +640,23 @@ where
                                 .map(move |result| {
                                     OutgoingMessage::from_query_result(result_id.clone(), result)
                                 })
-                                .map(WsMessage::from)
                                 .map(Ok)
                                 .compat()
                                 .forward(result_sink.sink_map_err(|_| ()))
                                 .map(|_| ())
                         });
 
+                    // With `graphql-transport-ws`, the client expects to be
+                    // told when there will be no more results
+                    let complete_sink = msg_sink.clone();
+                    let complete_id = id.clone();
+                    let run_subscription = run_subscription.then(move |_| {
+                        if transport_ws {
+                            let _ = complete_sink.unbounded_send(Complete { id: complete_id });
+                        }
+                        Ok::<(), ()>(())
+                    });
+
                     // Setup cancelation.
                     let guard = CancelGuard::new();
                     let logger = logger.clone();
@@ -404,7 +696,8 @@ where
         let (ws_sink, ws_stream) = self.stream.split();
 
         // Allocate a channel for writing
-        let (msg_sink, msg_stream) = mpsc::unbounded();
+        let (msg_sink, msg_stream) = mpsc::unbounded::<OutgoingMessage>();
+        let protocol = self.protocol;
 
         // Handle incoming messages asynchronously
         let ws_reader = Self::handle_incoming_messages(
@@ -414,10 +707,14 @@ where
             self.id.clone(),
             self.deployment.clone(),
             self.graphql_runner.clone(),
+            protocol,
+            self.persisted_queries.clone(),
         );
 
         // Send outgoing messages asynchronously
-        let ws_writer = msg_stream.forward(ws_sink.compat().sink_map_err(|_| ()));
+        let ws_writer = msg_stream
+            .map(move |msg| msg.into_ws_message(protocol))
+            .forward(ws_sink.compat().sink_map_err(|_| ()));
 
         // Silently swallow internal send results and errors. There is nothing
         // we can do about these errors ourselves. Clients will be disconnected
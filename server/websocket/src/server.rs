@@ -1,22 +1,34 @@
 use graph::{
+    components::store::PersistedQueryStore,
     data::query::QueryTarget,
     prelude::{SubscriptionServer as SubscriptionServerTrait, *},
+    util::tls::TlsSettings,
 };
+use graph_server_http::ApiKeyAuth;
 use http::header::{ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_TYPE};
 use http::{HeaderValue, Response, StatusCode};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Mutex;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
 use tokio_tungstenite::accept_hdr_async;
 use tokio_tungstenite::tungstenite::handshake::server::Request;
 
-use crate::connection::GraphQlConnection;
+use crate::connection::{GraphQlConnection, Protocol};
+
+/// A plain or a TLS connection
+trait Connection: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Connection for T {}
 
 /// A GraphQL subscription server based on Hyper / Websockets.
 pub struct SubscriptionServer<Q, S> {
     logger: Logger,
     graphql_runner: Arc<Q>,
     store: Arc<S>,
+    tls: Option<Arc<TlsSettings>>,
+    persisted_queries: Option<Arc<dyn PersistedQueryStore>>,
+    api_key_auth: Option<Arc<ApiKeyAuth>>,
 }
 
 impl<Q, S> SubscriptionServer<Q, S>
@@ -29,9 +41,35 @@ where
             logger: logger.new(o!("component" => "SubscriptionServer")),
             graphql_runner,
             store,
+            tls: None,
+            persisted_queries: None,
+            api_key_auth: None,
         }
     }
 
+    /// Only accept TLS connections using `tls`
+    pub fn with_tls(mut self, tls: Arc<TlsSettings>) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Check subscriptions against `persisted_queries` when persisted
+    /// queries are enforced
+    pub fn with_persisted_queries(
+        mut self,
+        persisted_queries: Arc<dyn PersistedQueryStore>,
+    ) -> Self {
+        self.persisted_queries = Some(persisted_queries);
+        self
+    }
+
+    /// Require an API key for each connection and apply the rate limits
+    /// for it
+    pub fn with_api_key_auth(mut self, api_key_auth: Arc<ApiKeyAuth>) -> Self {
+        self.api_key_auth = Some(api_key_auth);
+        self
+    }
+
     async fn subgraph_id_from_url_path(
         store: Arc<S>,
         path: &str,
@@ -92,9 +130,10 @@ where
     S: QueryStoreManager,
 {
     async fn serve(self, port: u16) {
+        let scheme = if self.tls.is_some() { "wss" } else { "ws" };
         info!(
             self.logger,
-            "Starting GraphQL WebSocket server at: ws://localhost:{}", port
+            "Starting GraphQL WebSocket server at: {}://localhost:{}", scheme, port
         );
 
         let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
@@ -110,16 +149,46 @@ where
                     continue;
                 }
             };
+            let stream: Box<dyn Connection> = match &self.tls {
+                Some(tls) => match tls.acceptor().accept(stream).await {
+                    Ok(stream) => Box::new(stream),
+                    Err(e) => {
+                        trace!(self.logger, "TLS handshake failed: {}", e);
+                        continue;
+                    }
+                },
+                None => Box::new(stream),
+            };
             let logger = self.logger.clone();
             let logger2 = self.logger.clone();
             let graphql_runner = self.graphql_runner.clone();
             let store = self.store.clone();
+            let persisted_queries = self.persisted_queries.clone();
+            let api_key_auth = self.api_key_auth.clone();
 
-            // Subgraph that the request is resolved to (if any)
+            // Subgraph that the request is resolved to (if any) and the
+            // protocol the client asked for
             let subgraph_id = Arc::new(Mutex::new(None));
             let accept_subgraph_id = subgraph_id.clone();
+            let protocol = Arc::new(Mutex::new(Protocol::GraphQlWs));
+            let accept_protocol = protocol.clone();
 
             accept_hdr_async(stream, move |request: &Request, mut response: Response<()>| {
+                if let Some(auth) = &api_key_auth {
+                    tokio::task::block_in_place(|| {
+                        graph::block_on(auth.authorize(request.headers()))
+                    })
+                    .map_err(|e| {
+                        warn!(logger, "Rejected WebSocket connection"; "reason" => e.to_string());
+                        Response::builder()
+                            .status(e.status())
+                            .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+                            .header(CONTENT_TYPE, "text/plain")
+                            .body(Some(e.to_string()))
+                            .unwrap()
+                    })?;
+                }
+
                 // Try to obtain the subgraph ID or name from the URL path.
                 // Return a 404 if the URL path contains no name/ID segment.
                 let path = request.uri().path();
@@ -172,9 +241,16 @@ where
                     }
 
                 *accept_subgraph_id.lock().unwrap() = Some(state.id);
+                let requested = Protocol::from_header(
+                    request
+                        .headers()
+                        .get("Sec-WebSocket-Protocol")
+                        .and_then(|value| value.to_str().ok()),
+                );
+                *accept_protocol.lock().unwrap() = requested;
                 response.headers_mut().insert(
                     "Sec-WebSocket-Protocol",
-                    HeaderValue::from_static("graphql-ws"),
+                    HeaderValue::from_static(requested.as_str()),
                 );
                 Ok(response)
             })
@@ -183,6 +259,7 @@ where
                     Ok(ws_stream) => {
                         // Obtain the subgraph ID or name that we resolved the request to
                         let subgraph_id = subgraph_id.lock().unwrap().clone().unwrap();
+                        let protocol = *protocol.lock().unwrap();
 
                         // Spawn a GraphQL over WebSocket connection
                         let service = GraphQlConnection::new(
@@ -190,6 +267,8 @@ where
                             subgraph_id,
                             ws_stream,
                             graphql_runner.clone(),
+                            protocol,
+                            persisted_queries,
                         );
 
                         graph::spawn_allow_panic(service.into_future().compat());
@@ -0,0 +1,13 @@
+create table subgraphs.mapping_log (
+    id           bigserial primary key,
+    subgraph_id  int not null
+                 references subgraphs.subgraph_deployment(id) on delete cascade,
+    block_number int not null,
+    level        int2 not null,
+    data_source  text not null,
+    message      text not null,
+    created_at   timestamptz not null default now()
+);
+
+create index mapping_log_subgraph_id_id
+    on subgraphs.mapping_log(subgraph_id, id);
@@ -0,0 +1,2 @@
+drop table subgraphs.deployment_load;
+drop table subgraphs.index_node_load;
@@ -0,0 +1,14 @@
+create table subgraphs.index_node_load(
+    node_id     text primary key,
+    networks    text[] not null,
+    reported_at timestamptz not null default now()
+);
+
+-- Rows for deployments that get removed disappear with the next report of
+-- the node that indexed them
+create table subgraphs.deployment_load(
+    id                    int primary key,
+    node_id               text not null,
+    blocks_per_minute     float8 not null,
+    db_seconds_per_minute float8 not null
+);
@@ -0,0 +1,4 @@
+alter table subgraphs.subgraph_manifest
+  drop column history_blocks;
+alter table subgraphs.subgraph_deployment
+  drop column earliest_block_number;
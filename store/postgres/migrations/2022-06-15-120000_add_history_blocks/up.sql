@@ -0,0 +1,5 @@
+alter table subgraphs.subgraph_manifest
+  add column history_blocks int4 not null default 2147483647
+  check (history_blocks > 0);
+alter table subgraphs.subgraph_deployment
+  add column earliest_block_number int4 not null default 0;
@@ -0,0 +1,3 @@
+alter table subgraphs.subgraph_manifest
+  add column partition_blocks int4
+  check (partition_blocks > 0);
@@ -0,0 +1,2 @@
+alter table subgraphs.subgraph_deployment
+  add column indexes_deferred bool not null default false;
@@ -0,0 +1 @@
+drop table subgraphs.attribute_usage;
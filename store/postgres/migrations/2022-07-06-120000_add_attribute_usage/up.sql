@@ -0,0 +1,10 @@
+create table subgraphs.attribute_usage (
+    deployment   int not null
+                 references subgraphs.subgraph_deployment(id) on delete cascade,
+    entity_type  text not null,
+    attribute    text not null,
+    filters      int8 not null default 0,
+    orders       int8 not null default 0,
+    last_used    timestamptz not null default now(),
+    primary key(deployment, entity_type, attribute)
+);
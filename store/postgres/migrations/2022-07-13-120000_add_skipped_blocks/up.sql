@@ -0,0 +1,13 @@
+create table subgraphs.skipped_block (
+    id           serial primary key,
+    subgraph_id  int not null
+                 references subgraphs.subgraph_deployment(id) on delete cascade,
+    block_number int not null,
+    handler      text,
+    reason       text not null,
+    created_at   timestamptz not null default now(),
+    removed_at   timestamptz
+);
+
+create index skipped_block_subgraph_id_block_number
+    on subgraphs.skipped_block(subgraph_id, block_number);
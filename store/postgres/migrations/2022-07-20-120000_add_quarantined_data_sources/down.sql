@@ -0,0 +1 @@
+drop table subgraphs.quarantined_data_source;
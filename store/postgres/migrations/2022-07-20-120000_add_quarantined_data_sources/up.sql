@@ -0,0 +1,13 @@
+create table subgraphs.quarantined_data_source (
+    id           serial primary key,
+    subgraph_id  int not null
+                 references subgraphs.subgraph_deployment(id) on delete cascade,
+    block_number int not null,
+    name         text not null,
+    address      bytea,
+    message      text not null,
+    created_at   timestamptz not null default now()
+);
+
+create index quarantined_data_source_subgraph_id_block_number
+    on subgraphs.quarantined_data_source(subgraph_id, block_number);
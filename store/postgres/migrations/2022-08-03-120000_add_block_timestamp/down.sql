@@ -0,0 +1,17 @@
+drop index ethereum_blocks_name_timestamp;
+alter table ethereum_blocks drop column timestamp;
+
+do $$
+declare
+    tables cursor for select namespace
+                        from ethereum_networks
+                       where namespace != 'public';
+begin
+	for table_record in tables loop
+		execute
+			'alter table '
+			|| table_record.namespace
+			|| '.blocks drop column timestamp';
+	end loop;
+end;
+$$;
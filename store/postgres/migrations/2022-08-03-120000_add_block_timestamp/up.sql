@@ -0,0 +1,34 @@
+-- Ethereum blocks store their timestamp as a hex string; other chains
+-- start recording the timestamp as new blocks are added to the cache
+alter table ethereum_blocks add column if not exists timestamp int8;
+update ethereum_blocks
+   set timestamp = ('x' || lpad(substr(data->'block'->>'timestamp', 3), 16, '0'))::bit(64)::int8
+ where data->'block'->>'timestamp' like '0x%';
+create index if not exists
+	ethereum_blocks_name_timestamp
+on
+	ethereum_blocks(network_name, timestamp, number);
+
+do $$
+declare
+    tables cursor for select namespace
+                        from ethereum_networks
+                       where namespace != 'public';
+begin
+	for table_record in tables loop
+		execute
+			'alter table '
+			|| table_record.namespace
+			|| '.blocks add column if not exists timestamp int8';
+		execute
+			'update '
+			|| table_record.namespace
+			|| '.blocks set timestamp = (''x'' || lpad(substr(data->''block''->>''timestamp'', 3), 16, ''0''))::bit(64)::int8'
+			|| ' where data->''block''->>''timestamp'' like ''0x%''';
+		execute
+			'create index if not exists blocks_timestamp on '
+			|| table_record.namespace
+			|| '.blocks(timestamp, number)';
+	end loop;
+end;
+$$;
@@ -11,8 +11,10 @@
 //! We use the following 2x 32-bit locks
 //!   * 1, n: to lock copying of the deployment with id n in the destination
 //!           shard
+//!   * 2, n: in the primary, to elect the node that ingests blocks for the
+//!           chain with id n
 
-use diesel::{sql_query, PgConnection, RunQueryDsl};
+use diesel::{sql_query, sql_types::Bool, PgConnection, RunQueryDsl};
 use graph::prelude::StoreError;
 
 use crate::command_support::catalog::Site;
@@ -44,3 +46,52 @@ pub(crate) fn unlock_copying(conn: &PgConnection, dst: &Site) -> Result<(), Stor
         .map(|_| ())
         .map_err(StoreError::from)
 }
+
+/// Try to get the lock for ingesting blocks for the chain with id
+/// `chain_id` without blocking. The lock is held until it is released
+/// with `unlock_ingestor` or the session for `conn` ends. Return `true` if
+/// we got the lock
+pub(crate) fn try_lock_ingestor(conn: &PgConnection, chain_id: i32) -> Result<bool, StoreError> {
+    #[derive(QueryableByName)]
+    struct Locked {
+        #[sql_type = "Bool"]
+        locked: bool,
+    }
+
+    sql_query(&format!(
+        "select pg_try_advisory_lock(2, {}) as locked",
+        chain_id
+    ))
+    .get_result::<Locked>(conn)
+    .map(|res| res.locked)
+    .map_err(StoreError::from)
+}
+
+/// Check that the session for `conn` still holds the lock for ingesting
+/// blocks for the chain with id `chain_id`
+pub(crate) fn holds_ingestor_lock(conn: &PgConnection, chain_id: i32) -> Result<bool, StoreError> {
+    #[derive(QueryableByName)]
+    struct Held {
+        #[sql_type = "Bool"]
+        held: bool,
+    }
+
+    // For advisory locks with two 32 bit keys, `classid` is the first and
+    // `objid` the second key
+    sql_query(&format!(
+        "select exists(select 1 from pg_locks \
+          where locktype = 'advisory' and classid = 2 and objid = {} \
+            and objsubid = 2 and granted and pid = pg_backend_pid()) as held",
+        chain_id
+    ))
+    .get_result::<Held>(conn)
+    .map(|res| res.held)
+    .map_err(StoreError::from)
+}
+
+pub(crate) fn unlock_ingestor(conn: &PgConnection, chain_id: i32) -> Result<(), StoreError> {
+    sql_query(&format!("select pg_advisory_unlock(2, {})", chain_id))
+        .execute(conn)
+        .map(|_| ())
+        .map_err(StoreError::from)
+}
@@ -0,0 +1,319 @@
+//! Which entity attributes queries filter and sort by. We count how often
+//! each attribute of a deployment is used in real queries so that
+//! `graphman index suggest` can point out attributes that are queried a
+//! lot but have no index. Counting happens in memory, and the counts are
+//! periodically added to the `attribute_usage` table in the deployment's
+//! shard by whichever node ran the queries
+use std::collections::HashMap;
+use std::mem;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use diesel::dsl::now;
+use diesel::insert_into;
+use diesel::pg::upsert::excluded;
+use diesel::pg::PgConnection;
+use diesel::prelude::{ExpressionMethods, QueryDsl, RunQueryDsl};
+
+use graph::components::store::{
+    EntityCollection, EntityFilter, EntityOrder, EntityQuery, EntityType,
+};
+use graph::prelude::StoreError;
+
+use crate::primary::{DeploymentId, Site};
+
+table! {
+    subgraphs.attribute_usage (deployment, entity_type, attribute) {
+        deployment -> Integer,
+        entity_type -> Text,
+        attribute -> Text,
+        filters -> BigInt,
+        orders -> BigInt,
+        last_used -> Timestamptz,
+    }
+}
+
+/// How often an attribute was used
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// The number of queries that filtered by the attribute
+    pub filters: i64,
+    /// The number of queries that sorted by the attribute
+    pub orders: i64,
+}
+
+impl Usage {
+    pub fn total(&self) -> i64 {
+        self.filters + self.orders
+    }
+}
+
+/// An attribute that queries use but that has no index
+#[derive(Clone, Debug)]
+pub struct IndexSuggestion {
+    pub entity_type: String,
+    pub attribute: String,
+    pub usage: Usage,
+}
+
+type Key = (DeploymentId, EntityType, String);
+
+/// How often recorded counts are written to the database
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Counts of attribute usage that have not been written to the database
+/// yet
+pub(crate) struct AttributeUsage {
+    counts: Mutex<HashMap<Key, Usage>>,
+    last_flush: Mutex<Instant>,
+}
+
+impl Default for AttributeUsage {
+    fn default() -> Self {
+        AttributeUsage {
+            counts: Mutex::new(HashMap::new()),
+            last_flush: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl AttributeUsage {
+    /// Count the attributes that `query` filters and sorts by. Return
+    /// `true` if it is time to write the counts to the database, in which
+    /// case the caller should call `flush`
+    pub fn record(&self, site: &Site, query: &EntityQuery) -> bool {
+        let entity_types: Vec<&EntityType> = match &query.collection {
+            EntityCollection::All(types) => types.iter().map(|(typ, _)| typ).collect(),
+            EntityCollection::Window(windows) => {
+                windows.iter().map(|window| &window.child_type).collect()
+            }
+        };
+
+        let mut filters = Vec::new();
+        if let Some(filter) = &query.filter {
+            filter_attributes(&entity_types, filter, &mut filters);
+        }
+        let mut orders = Vec::new();
+        order_attributes(&entity_types, &query.order, &mut orders);
+
+        let mut counts = self.counts.lock().unwrap();
+        for (entity_type, attribute) in filters {
+            counts
+                .entry((site.id, entity_type.clone(), attribute.to_string()))
+                .or_default()
+                .filters += 1;
+        }
+        for (entity_type, attribute) in orders {
+            counts
+                .entry((site.id, entity_type.clone(), attribute.to_string()))
+                .or_default()
+                .orders += 1;
+        }
+        drop(counts);
+
+        let mut last_flush = self.last_flush.lock().unwrap();
+        if last_flush.elapsed() >= FLUSH_INTERVAL {
+            *last_flush = Instant::now();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Add the counts that were recorded since the last call to the
+    /// `attribute_usage` table and return how many attributes were updated.
+    /// If writing fails, the counts are lost
+    pub fn flush(&self, conn: &PgConnection) -> Result<usize, StoreError> {
+        use attribute_usage as au;
+
+        let counts = mem::take(&mut *self.counts.lock().unwrap());
+        if counts.is_empty() {
+            return Ok(0);
+        }
+
+        let rows: Vec<_> = counts
+            .iter()
+            .map(|((deployment, entity_type, attribute), usage)| {
+                (
+                    au::deployment.eq(deployment),
+                    au::entity_type.eq(entity_type.as_str()),
+                    au::attribute.eq(attribute),
+                    au::filters.eq(usage.filters),
+                    au::orders.eq(usage.orders),
+                )
+            })
+            .collect();
+        Ok(insert_into(au::table)
+            .values(&rows)
+            .on_conflict((au::deployment, au::entity_type, au::attribute))
+            .do_update()
+            .set((
+                au::filters.eq(au::filters + excluded(au::filters)),
+                au::orders.eq(au::orders + excluded(au::orders)),
+                au::last_used.eq(now),
+            ))
+            .execute(conn)?)
+    }
+}
+
+/// The usage of all attributes of the deployment `site` that were recorded
+/// so far, by entity type and attribute
+pub(crate) fn usage(
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<Vec<(String, String, Usage)>, StoreError> {
+    use attribute_usage as au;
+
+    Ok(au::table
+        .filter(au::deployment.eq(site.id))
+        .select((au::entity_type, au::attribute, au::filters, au::orders))
+        .load::<(String, String, i64, i64)>(conn)?
+        .into_iter()
+        .map(|(entity_type, attribute, filters, orders)| {
+            (entity_type, attribute, Usage { filters, orders })
+        })
+        .collect())
+}
+
+fn filter_attributes<'a>(
+    entity_types: &[&'a EntityType],
+    filter: &'a EntityFilter,
+    out: &mut Vec<(&'a EntityType, &'a str)>,
+) {
+    use EntityFilter::*;
+
+    let mut add = |attr: &'a String| {
+        for entity_type in entity_types {
+            out.push((*entity_type, attr.as_str()));
+        }
+    };
+
+    match filter {
+        And(filters) | Or(filters) => {
+            for filter in filters {
+                filter_attributes(entity_types, filter, out);
+            }
+        }
+        Equal(attr, _)
+        | Not(attr, _)
+        | GreaterThan(attr, _)
+        | LessThan(attr, _)
+        | GreaterOrEqual(attr, _)
+        | LessOrEqual(attr, _)
+        | In(attr, _)
+        | NotIn(attr, _)
+        | Contains(attr, _)
+        | ContainsNoCase(attr, _)
+        | NotContains(attr, _)
+        | NotContainsNoCase(attr, _)
+        | StartsWith(attr, _)
+        | StartsWithNoCase(attr, _)
+        | NotStartsWith(attr, _)
+        | NotStartsWithNoCase(attr, _)
+        | EndsWith(attr, _)
+        | EndsWithNoCase(attr, _)
+        | NotEndsWith(attr, _)
+        | NotEndsWithNoCase(attr, _)
+        | Like(attr, _)
+        | LikeNoCase(attr, _)
+        | NotLike(attr, _)
+        | NotLikeNoCase(attr, _) => add(attr),
+        RowGreaterThan(pairs) | RowLessThan(pairs) => {
+            for (attr, _) in pairs {
+                add(attr)
+            }
+        }
+        Child(child) => {
+            if child.derived {
+                out.push((&child.entity_type, child.attr.as_str()));
+            } else {
+                add(&child.attr);
+            }
+            filter_attributes(&[&child.entity_type], &child.filter, out);
+        }
+    }
+}
+
+fn order_attributes<'a>(
+    entity_types: &[&'a EntityType],
+    order: &'a EntityOrder,
+    out: &mut Vec<(&'a EntityType, &'a str)>,
+) {
+    match order {
+        EntityOrder::Ascending(attr, _) | EntityOrder::Descending(attr, _) => {
+            for entity_type in entity_types {
+                out.push((*entity_type, attr.as_str()));
+            }
+        }
+        EntityOrder::Compound(orders) => {
+            for order in orders {
+                order_attributes(entity_types, order, out);
+            }
+        }
+        EntityOrder::ChildAscending(child) | EntityOrder::ChildDescending(child) => {
+            out.push((&child.entity_type, child.attribute.as_str()));
+        }
+        EntityOrder::Default | EntityOrder::Unordered => { /* only uses `id` */ }
+    }
+}
+
+/// Whether the index expression `expr` uses the column `column`
+pub(crate) fn expr_uses_column(expr: &str, column: &str) -> bool {
+    expr.split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+        .any(|word| word == column)
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::components::store::{Child, EntityOrderByChild};
+    use graph::prelude::{Value, ValueType};
+
+    use super::*;
+
+    #[test]
+    fn collects_attributes() {
+        let musician = EntityType::from("Musician");
+        let band = EntityType::from("Band");
+
+        let filter = EntityFilter::And(vec![
+            EntityFilter::Equal("name".to_string(), Value::from("Anna")),
+            EntityFilter::Child(Child {
+                attr: "bands".to_string(),
+                entity_type: band.clone(),
+                filter: Box::new(EntityFilter::StartsWith(
+                    "genre".to_string(),
+                    Value::from("ro"),
+                )),
+                derived: false,
+            }),
+        ]);
+        let mut attrs = Vec::new();
+        filter_attributes(&[&musician], &filter, &mut attrs);
+        assert_eq!(
+            vec![(&musician, "name"), (&musician, "bands"), (&band, "genre")],
+            attrs
+        );
+
+        let order = EntityOrder::Compound(vec![
+            EntityOrder::Descending("age".to_string(), ValueType::Int),
+            EntityOrder::ChildAscending(EntityOrderByChild {
+                join_attribute: "mainBand".to_string(),
+                entity_type: band.clone(),
+                attribute: "founded".to_string(),
+                value_type: ValueType::Int,
+            }),
+        ]);
+        let mut attrs = Vec::new();
+        order_attributes(&[&musician], &order, &mut attrs);
+        assert_eq!(vec![(&musician, "age"), (&band, "founded")], attrs);
+    }
+
+    #[test]
+    fn index_expressions() {
+        assert!(expr_uses_column("name", "name"));
+        assert!(expr_uses_column("\"left\"(name, 256)", "name"));
+        assert!(expr_uses_column("\"from\"", "from"));
+        assert!(!expr_uses_column("name_prefix", "name"));
+        assert!(!expr_uses_column("block_range", "name"));
+    }
+}
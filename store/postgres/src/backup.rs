@@ -0,0 +1,562 @@
+//! Backups of deployments that can be restored into another shard, or into
+//! the database of another `graph-node` installation. A backup is a
+//! directory with the following files:
+//!
+//! - `metadata.json`: the deployment's manifest, schema and head block,
+//!   and how many rows each of the other files contains
+//! - `<table>.jsonl`: one file per entity table, with one row per line as
+//!   a JSON object in the form that Postgres' `to_jsonb` produces
+//! - `dynamic_data_sources.jsonl`: the deployment's dynamic data sources
+//!
+//! The rows are written exactly as they are stored, including their
+//! `vid` and block range, so that a restored deployment has the same
+//! history as the original and can continue indexing at the block where
+//! the backup was taken.
+//!
+//! This module can also export a snapshot of the entities of a deployment
+//! as of a block for use outside of `graph-node`, e.g., for loading into a
+//! data warehouse. Snapshots contain one file `<EntityType>.jsonl` per
+//! entity type, with one entity per line in the same form as in GraphQL
+//! responses, and can not be restored.
+//!
+//! Finally, the rows of a single entity table can be exported as CSV or
+//! JSONL for ad-hoc analysis with `export_table`.
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use diesel::connection::SimpleConnection;
+use diesel::pg::PgConnection;
+use diesel::sql_types::{BigInt, Text};
+use diesel::{sql_query, RunQueryDsl};
+
+use graph::components::store::{AttributeNames, EntityCollection, EntityOrder, EntityRange};
+use graph::data::subgraph::schema::{DeploymentCreate, SubgraphManifestEntity, POI_OBJECT};
+use graph::prelude::{
+    anyhow::anyhow, r, serde_json, BlockNumber, BlockPtr, Deserialize, Logger, Serialize,
+    StoreError,
+};
+
+use crate::block_range::{BLOCK_COLUMN, BLOCK_RANGE_COLUMN};
+use crate::primary::Site;
+use crate::relational::{Layout, Table};
+use crate::{deployment, detail, dynds};
+
+/// The version of the backup format that we write, and the only one that
+/// we can restore
+const FORMAT_VERSION: u32 = 1;
+
+const METADATA_FILE: &str = "metadata.json";
+const DYNAMIC_DATA_SOURCES_FILE: &str = "dynamic_data_sources.jsonl";
+
+/// How many rows we read from or write to the database at a time
+const BATCH_SIZE: usize = 1_000;
+
+/// The formats in which `export_table` can write the rows of a table
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+}
+
+impl FromStr for ExportFormat {
+    type Err = StoreError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(ExportFormat::Csv),
+            "jsonl" => Ok(ExportFormat::Jsonl),
+            _ => Err(StoreError::Unknown(anyhow!(
+                "unknown export format `{}`; use `csv` or `jsonl`",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BackupHead {
+    pub number: BlockNumber,
+    pub hash: String,
+}
+
+/// The contents of `metadata.json`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BackupMetadata {
+    pub version: u32,
+    pub deployment: String,
+    pub network: String,
+    pub spec_version: String,
+    pub description: Option<String>,
+    pub repository: Option<String>,
+    pub features: Vec<String>,
+    pub schema: String,
+    pub history_blocks: BlockNumber,
+    /// The last block that the deployment had processed when the backup
+    /// was taken
+    pub head: Option<BackupHead>,
+    pub earliest_block: BlockNumber,
+    /// The number of rows in the backup for each table
+    pub tables: BTreeMap<String, usize>,
+    pub dynamic_data_sources: usize,
+}
+
+impl BackupMetadata {
+    /// Read the metadata of the backup in `dir`
+    pub fn read(dir: &Path) -> Result<Self, StoreError> {
+        let file = File::open(dir.join(METADATA_FILE)).map_err(io_error)?;
+        let metadata: BackupMetadata =
+            serde_json::from_reader(BufReader::new(file)).map_err(|e| anyhow!(e))?;
+        if metadata.version != FORMAT_VERSION {
+            return Err(StoreError::Unknown(anyhow!(
+                "the backup in {} has format version {}, but we can only restore version {}",
+                dir.display(),
+                metadata.version,
+                FORMAT_VERSION
+            )));
+        }
+        Ok(metadata)
+    }
+
+    fn write(&self, dir: &Path) -> Result<(), StoreError> {
+        let file = File::create(dir.join(METADATA_FILE)).map_err(io_error)?;
+        serde_json::to_writer_pretty(BufWriter::new(file), self).map_err(|e| anyhow!(e))?;
+        Ok(())
+    }
+
+    pub fn head(&self) -> Result<Option<BlockPtr>, StoreError> {
+        self.head
+            .as_ref()
+            .map(|head| {
+                hex::decode(&head.hash)
+                    .map(|hash| BlockPtr::from((hash, head.number)))
+                    .map_err(|e| StoreError::Unknown(anyhow!("invalid head block hash: {}", e)))
+            })
+            .transpose()
+    }
+
+    /// The data needed to create the deployment for this backup
+    pub(crate) fn deployment_create(&self) -> DeploymentCreate {
+        DeploymentCreate {
+            manifest: SubgraphManifestEntity {
+                spec_version: self.spec_version.clone(),
+                description: self.description.clone(),
+                repository: self.repository.clone(),
+                features: self.features.clone(),
+                schema: self.schema.clone(),
+                history_blocks: self.history_blocks,
+            },
+            earliest_block: None,
+            graft_base: None,
+            graft_block: None,
+            debug_fork: None,
+        }
+    }
+}
+
+fn io_error(e: std::io::Error) -> StoreError {
+    StoreError::Unknown(anyhow!(e))
+}
+
+fn table_file(dir: &Path, table: &Table) -> PathBuf {
+    dir.join(format!("{}.jsonl", table.name.as_str()))
+}
+
+/// Create the directory `dir` if it does not exist yet, and make sure
+/// that it is empty
+fn create_empty_dir(dir: &Path) -> Result<(), StoreError> {
+    fs::create_dir_all(dir).map_err(io_error)?;
+    if fs::read_dir(dir).map_err(io_error)?.next().is_some() {
+        return Err(StoreError::Unknown(anyhow!(
+            "the directory {} is not empty",
+            dir.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Write each of `lines` to `writer`, one per line
+fn write_lines(writer: &mut impl Write, lines: &[String]) -> Result<(), StoreError> {
+    for line in lines {
+        writeln!(writer, "{}", line).map_err(io_error)?;
+    }
+    Ok(())
+}
+
+/// Write the data of the deployment with `layout` into `dir`, which must
+/// either not exist or be empty. The caller should run this in a
+/// transaction with isolation level `repeatable read` so that the backup
+/// is consistent while the deployment keeps indexing
+pub(crate) fn dump(
+    conn: &PgConnection,
+    layout: &Layout,
+    dir: &Path,
+) -> Result<BackupMetadata, StoreError> {
+    #[derive(QueryableByName)]
+    struct Row {
+        #[sql_type = "BigInt"]
+        vid: i64,
+        #[sql_type = "Text"]
+        data: String,
+    }
+
+    let site = &layout.site;
+
+    create_empty_dir(dir)?;
+
+    let deployment = detail::deployment_entity(conn, site)?;
+    let head = deployment::block_ptr(conn, &site.deployment)?;
+    let earliest_block = deployment::earliest_block(conn, site)?;
+
+    let mut tables = BTreeMap::new();
+    for table in layout.tables.values() {
+        let query = format!(
+            "select vid, to_jsonb(t)::text as data from {} t \
+              where vid > $1 order by vid limit $2",
+            table.qualified_name
+        );
+        let mut writer = BufWriter::new(File::create(table_file(dir, table)).map_err(io_error)?);
+        let mut last_vid = -1;
+        let mut count = 0;
+        loop {
+            let rows = sql_query(&query)
+                .bind::<BigInt, _>(last_vid)
+                .bind::<BigInt, _>(BATCH_SIZE as i64)
+                .load::<Row>(conn)?;
+            for row in &rows {
+                writeln!(writer, "{}", row.data).map_err(io_error)?;
+            }
+            count += rows.len();
+            match rows.last() {
+                Some(row) if rows.len() == BATCH_SIZE => last_vid = row.vid,
+                _ => break,
+            }
+        }
+        writer.flush().map_err(io_error)?;
+        tables.insert(table.name.to_string(), count);
+    }
+
+    let dds = dynds::to_json(conn, &site.deployment)?;
+    let mut writer =
+        BufWriter::new(File::create(dir.join(DYNAMIC_DATA_SOURCES_FILE)).map_err(io_error)?);
+    write_lines(&mut writer, &dds)?;
+    writer.flush().map_err(io_error)?;
+
+    let manifest = deployment.manifest;
+    let metadata = BackupMetadata {
+        version: FORMAT_VERSION,
+        deployment: site.deployment.to_string(),
+        network: site.network.clone(),
+        spec_version: manifest.spec_version,
+        description: manifest.description,
+        repository: manifest.repository,
+        features: manifest.features,
+        schema: manifest.schema,
+        history_blocks: manifest.history_blocks,
+        head: head.map(|head| BackupHead {
+            number: head.number,
+            hash: head.hash_hex(),
+        }),
+        earliest_block,
+        tables,
+        dynamic_data_sources: dds.len(),
+    };
+    metadata.write(dir)?;
+    Ok(metadata)
+}
+
+/// Load the data from the backup in `dir` into the deployment with
+/// `layout`, which must have just been created from `metadata`. The
+/// caller should run this in a transaction
+pub(crate) fn restore(
+    conn: &PgConnection,
+    layout: &Layout,
+    dir: &Path,
+    metadata: &BackupMetadata,
+) -> Result<(), StoreError> {
+    let site = &layout.site;
+
+    for table in layout.tables.values() {
+        let expected = match metadata.tables.get(table.name.as_str()) {
+            Some(count) => *count,
+            None => {
+                return Err(StoreError::Unknown(anyhow!(
+                    "the backup in {} does not contain table {}",
+                    dir.display(),
+                    table.name
+                )))
+            }
+        };
+
+        let insert = format!(
+            "insert into {qname} \
+             select * from jsonb_populate_recordset(null::{qname}, $1::jsonb)",
+            qname = table.qualified_name
+        );
+        let file = File::open(table_file(dir, table)).map_err(io_error)?;
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        let mut count = 0;
+        for line in BufReader::new(file).lines() {
+            batch.push(line.map_err(io_error)?);
+            if batch.len() == BATCH_SIZE {
+                count += insert_batch(conn, &insert, &mut batch)?;
+            }
+        }
+        count += insert_batch(conn, &insert, &mut batch)?;
+        if count != expected {
+            return Err(StoreError::Unknown(anyhow!(
+                "the backup of table {} should contain {} rows but contains {}",
+                table.name,
+                expected,
+                count
+            )));
+        }
+
+        // Since we inserted explicit values for `vid`, the sequence for it
+        // needs to be moved past them
+        let query = format!(
+            "select setval(pg_get_serial_sequence($1, 'vid'), \
+                           (select coalesce(max(vid), 0) + 1 from {}), false)",
+            table.qualified_name
+        );
+        sql_query(&query)
+            .bind::<Text, _>(table.qualified_name.as_str())
+            .execute(conn)?;
+    }
+
+    let file = File::open(dir.join(DYNAMIC_DATA_SOURCES_FILE)).map_err(io_error)?;
+    let dds = BufReader::new(file)
+        .lines()
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(io_error)?;
+    dynds::insert_json(conn, &site.deployment, &dds)?;
+
+    if let Some(head) = metadata.head()? {
+        deployment::forward_block_ptr(conn, &site.deployment, &head)?;
+    }
+    deployment::set_earliest_block(conn, site, metadata.earliest_block)?;
+    deployment::set_entity_count(conn, site, &layout.count_query)?;
+    Ok(())
+}
+
+/// Check that the entities of `site` as of `block` are still available
+/// and return that block; if `block` is `None`, return the current head
+/// of the deployment
+pub(crate) fn snapshot_block(
+    conn: &PgConnection,
+    site: &Site,
+    block: Option<BlockNumber>,
+) -> Result<BlockNumber, StoreError> {
+    let head = deployment::block_ptr(conn, &site.deployment)?
+        .ok_or_else(|| {
+            StoreError::Unknown(anyhow!(
+                "deployment {} has not processed any blocks yet",
+                site.deployment
+            ))
+        })?
+        .number;
+    let earliest_block = deployment::earliest_block(conn, site)?;
+    let block = block.unwrap_or(head);
+    if block > head || block < earliest_block {
+        return Err(StoreError::Unknown(anyhow!(
+            "block {} is not available for deployment {}; it has data for blocks {} to {}",
+            block,
+            site.deployment,
+            earliest_block,
+            head
+        )));
+    }
+    Ok(block)
+}
+
+/// Write all entities of the deployment with `layout` as they were at
+/// `block` into `dir`, which must either not exist or be empty. If `block`
+/// is `None`, use the current head of the deployment. Return the block
+/// that was used and how many entities of each type were written. The
+/// caller should run this in a transaction with isolation level
+/// `repeatable read` so that the snapshot is consistent while the
+/// deployment keeps indexing
+pub(crate) fn export(
+    logger: &Logger,
+    conn: &PgConnection,
+    layout: &Layout,
+    block: Option<BlockNumber>,
+    dir: &Path,
+) -> Result<(BlockNumber, BTreeMap<String, usize>), StoreError> {
+    let block = snapshot_block(conn, &layout.site, block)?;
+
+    create_empty_dir(dir)?;
+
+    let mut counts = BTreeMap::new();
+    for table in layout.tables.values() {
+        if table.object == *POI_OBJECT {
+            continue;
+        }
+        let path = dir.join(format!("{}.jsonl", table.object.as_str()));
+        let mut writer = BufWriter::new(File::create(path).map_err(io_error)?);
+        let mut count = 0;
+        let mut error = None;
+        let mut sink = |batch: Vec<BTreeMap<String, r::Value>>| {
+            for entity in batch {
+                let res = serde_json::to_string(&entity)
+                    .map_err(|e| StoreError::Unknown(anyhow!(e)))
+                    .and_then(|json| writeln!(writer, "{}", json).map_err(io_error));
+                if let Err(e) = res {
+                    error = Some(e);
+                    return false;
+                }
+                count += 1;
+            }
+            true
+        };
+        layout
+            .query_cursor(
+                logger,
+                conn,
+                EntityCollection::All(vec![(table.object.clone(), AttributeNames::All)]),
+                None,
+                EntityOrder::Unordered,
+                EntityRange {
+                    first: None,
+                    skip: 0,
+                },
+                block,
+                None,
+                BATCH_SIZE,
+                &mut sink,
+            )
+            .map_err(|e| StoreError::Unknown(anyhow!("{}", e)))?;
+        if let Some(e) = error {
+            return Err(e);
+        }
+        writer.flush().map_err(io_error)?;
+        counts.insert(table.object.to_string(), count);
+    }
+    Ok((block, counts))
+}
+
+/// Quote `field` for use in a CSV file if it contains characters that
+/// require quoting
+fn csv_field(field: &str) -> String {
+    if field.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Turn the JSON representation `row` of a row into a line of CSV with
+/// the values of `columns`. Strings are written as is, `null` as an empty
+/// field, and everything else as JSON
+fn csv_line(columns: &[String], row: &str) -> Result<String, StoreError> {
+    let row: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(row).map_err(|e| StoreError::Unknown(anyhow!(e)))?;
+    let fields: Vec<_> = columns
+        .iter()
+        .map(|column| match row.get(column) {
+            None | Some(serde_json::Value::Null) => String::new(),
+            Some(serde_json::Value::String(s)) => csv_field(s),
+            Some(value) => csv_field(&value.to_string()),
+        })
+        .collect();
+    Ok(fields.join(","))
+}
+
+/// Write the rows of `table` to `writer` in `format`, streaming them from
+/// a server-side cursor so that tables of any size can be exported. Every
+/// version of an entity whose block range overlaps the blocks from `from`
+/// to `to` (both inclusive) is written; if they are `None`, the range is
+/// unbounded on that side. The rows are written as they are stored,
+/// including their `vid` and block range. Must be called in a transaction
+/// since cursors only exist inside one. Returns the number of rows written
+pub(crate) fn export_table(
+    conn: &PgConnection,
+    table: &Table,
+    from: Option<BlockNumber>,
+    to: Option<BlockNumber>,
+    format: ExportFormat,
+    writer: &mut dyn Write,
+) -> Result<usize, StoreError> {
+    #[derive(QueryableByName)]
+    struct Row {
+        #[sql_type = "Text"]
+        data: String,
+    }
+
+    const CURSOR: &str = "export_table_cursor";
+
+    let bound = |block: Option<BlockNumber>| {
+        block
+            .map(|block| block.to_string())
+            .unwrap_or_else(|| "null".to_string())
+    };
+    let (from, to) = (bound(from), bound(to));
+    let filter = if table.immutable {
+        format!(
+            "({from} is null or {block} >= {from}) and ({to} is null or {block} <= {to})",
+            block = BLOCK_COLUMN,
+            from = from,
+            to = to
+        )
+    } else {
+        format!(
+            "{} && int4range({}, {}, '[]')",
+            BLOCK_RANGE_COLUMN, from, to
+        )
+    };
+    let query = format!(
+        "declare {} no scroll cursor for \
+         select to_jsonb(t)::text as data from {} t where {} order by vid",
+        CURSOR, table.qualified_name, filter
+    );
+    conn.batch_execute(&query)?;
+
+    let mut columns = vec!["vid".to_string()];
+    columns.extend(table.columns.iter().map(|column| column.name.to_string()));
+    if table.immutable {
+        columns.push(BLOCK_COLUMN.to_string());
+    } else {
+        columns.push(BLOCK_RANGE_COLUMN.to_string());
+    }
+    if format == ExportFormat::Csv {
+        writeln!(writer, "{}", columns.join(",")).map_err(io_error)?;
+    }
+
+    let fetch = format!("fetch forward {} from {}", BATCH_SIZE, CURSOR);
+    let mut count = 0;
+    loop {
+        let rows = sql_query(&fetch).load::<Row>(conn)?;
+        if rows.is_empty() {
+            break;
+        }
+        count += rows.len();
+        for row in rows {
+            match format {
+                ExportFormat::Csv => {
+                    writeln!(writer, "{}", csv_line(&columns, &row.data)?).map_err(io_error)?
+                }
+                ExportFormat::Jsonl => writeln!(writer, "{}", row.data).map_err(io_error)?,
+            }
+        }
+    }
+    conn.batch_execute(&format!("close {}", CURSOR))?;
+    writer.flush().map_err(io_error)?;
+    Ok(count)
+}
+
+fn insert_batch(
+    conn: &PgConnection,
+    insert: &str,
+    batch: &mut Vec<String>,
+) -> Result<usize, StoreError> {
+    if batch.is_empty() {
+        return Ok(0);
+    }
+    let count = sql_query(insert)
+        .bind::<Text, _>(format!("[{}]", batch.join(",")))
+        .execute(conn)?;
+    batch.clear();
+    Ok(count)
+}
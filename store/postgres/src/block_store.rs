@@ -19,8 +19,13 @@ use graph::{
     util::timed_cache::TimedCache,
 };
 
+use diesel::{
+    r2d2::{ConnectionManager, PooledConnection},
+    PgConnection,
+};
+
 use crate::{
-    chain_head_listener::ChainHeadUpdateSender, connection_pool::ConnectionPool,
+    advisory_lock, chain_head_listener::ChainHeadUpdateSender, connection_pool::ConnectionPool,
     primary::Mirror as PrimaryMirror, ChainStore, NotificationSender, Shard, PRIMARY_SHARD,
 };
 
@@ -408,6 +413,16 @@ impl BlockStore {
         Ok(map)
     }
 
+    /// The identifier of `chain` that was recorded when the chain was
+    /// first used; all providers for the chain must report it
+    pub fn chain_identifier(&self, chain: &str) -> Result<Option<ChainIdentifier>, StoreError> {
+        self.mirror.read(|conn| {
+            primary::find_chain(conn, chain)?
+                .map(|chain| chain.network_identifier())
+                .transpose()
+        })
+    }
+
     pub fn chain_head_block(&self, chain: &str) -> Result<Option<BlockNumber>, StoreError> {
         let store = self
             .store(chain)
@@ -447,6 +462,31 @@ impl BlockStore {
             })
     }
 
+    /// Remove calls from the call caches of all chains so that each keeps
+    /// at most the last `retention_blocks` blocks and `max_calls` calls.
+    /// Returns how many calls were removed from each chain where that was
+    /// necessary
+    pub fn prune_call_caches(
+        &self,
+        retention_blocks: Option<BlockNumber>,
+        max_calls: Option<usize>,
+    ) -> Vec<(String, Result<usize, StoreError>)> {
+        let stores: Vec<_> = self.stores.read().unwrap().values().cloned().collect();
+        let mut pruned = Vec::new();
+        for store in stores {
+            if !store.call_cache_is_prunable() {
+                continue;
+            }
+            let res = match store.call_cache_cutoff(retention_blocks, max_calls) {
+                Ok(None) => continue,
+                Ok(Some(before)) => store.prune_call_cache(before),
+                Err(e) => Err(e),
+            };
+            pruned.push((store.chain.clone(), res));
+        }
+        pruned
+    }
+
     pub fn drop_chain(&self, chain: &str) -> Result<(), StoreError> {
         let chain_store = self
             .store(chain)
@@ -463,6 +503,24 @@ impl BlockStore {
         Ok(())
     }
 
+    /// Try to become the node that ingests blocks for `chain`. Return
+    /// `None` if another node already does that. The lease uses a
+    /// connection to the primary until it is dropped
+    pub fn try_ingestor_lease(&self, chain: &str) -> Result<Option<IngestorLease>, StoreError> {
+        let chain_id = self
+            .mirror
+            .read(|conn| primary::find_chain(conn, chain))?
+            .ok_or_else(|| constraint_violation!("unknown chain {}", chain))?
+            .id;
+
+        let conn = self.mirror.primary().get()?;
+        if advisory_lock::try_lock_ingestor(&conn, chain_id)? {
+            Ok(Some(IngestorLease { chain_id, conn }))
+        } else {
+            Ok(None)
+        }
+    }
+
     fn truncate_block_caches(&self) -> Result<(), StoreError> {
         for store in self.stores.read().unwrap().values() {
             store.truncate_block_cache()?
@@ -487,6 +545,31 @@ impl BlockStore {
     }
 }
 
+/// Proof that this node is the only one that ingests blocks for a chain.
+/// The lease is backed by an advisory lock in the primary and ends when it
+/// is dropped or when the connection to the primary is lost
+pub struct IngestorLease {
+    chain_id: i32,
+    conn: PooledConnection<ConnectionManager<PgConnection>>,
+}
+
+impl IngestorLease {
+    /// Check that we still hold the lease. If this returns `false`,
+    /// another node might be ingesting blocks for the chain already
+    pub fn is_held(&self) -> bool {
+        advisory_lock::holds_ingestor_lock(&self.conn, self.chain_id).unwrap_or(false)
+    }
+}
+
+impl Drop for IngestorLease {
+    fn drop(&mut self) {
+        // If the connection is broken, the lock is gone already; since
+        // the connection goes back to the pool, we need to release the
+        // lock explicitly otherwise
+        advisory_lock::unlock_ingestor(&self.conn, self.chain_id).ok();
+    }
+}
+
 impl BlockStoreTrait for BlockStore {
     type ChainStore = ChainStore;
 
@@ -1,4 +1,4 @@
-use diesel::sql_types::{Bool, Integer};
+use diesel::sql_types::{BigInt, Bool, Integer};
 use diesel::{connection::SimpleConnection, prelude::RunQueryDsl, select};
 use diesel::{insert_into, OptionalExtension};
 use diesel::{pg::PgConnection, sql_query};
@@ -12,7 +12,10 @@ use std::iter::FromIterator;
 use std::sync::Arc;
 
 use graph::prelude::anyhow::anyhow;
-use graph::{data::subgraph::schema::POI_TABLE, prelude::StoreError};
+use graph::{
+    data::subgraph::schema::POI_TABLE,
+    prelude::{BlockNumber, StoreError, ENV_VARS},
+};
 
 use crate::connection_pool::ForeignServer;
 use crate::{
@@ -57,6 +60,12 @@ pub struct Catalog {
     /// in their entirety. This influences both DDL generation and how
     /// queries are generated
     pub use_bytea_prefix: bool,
+    /// If set, tables for immutable entity types are partitioned by block
+    /// number, and each partition holds this many blocks
+    pub partition_blocks: Option<BlockNumber>,
+    /// Whether DDL generation leaves out the indexes on entity attributes
+    /// so that they can be built later with `Layout::attribute_index_ddl`
+    pub defer_indexes: bool,
 }
 
 impl Catalog {
@@ -65,6 +74,7 @@ impl Catalog {
         conn: &PgConnection,
         site: Arc<Site>,
         use_bytea_prefix: bool,
+        partition_blocks: Option<BlockNumber>,
     ) -> Result<Self, StoreError> {
         let text_columns = get_text_columns(conn, &site.namespace)?;
         let use_poi = supports_proof_of_indexing(conn, &site.namespace)?;
@@ -73,6 +83,8 @@ impl Catalog {
             text_columns,
             use_poi,
             use_bytea_prefix,
+            partition_blocks,
+            defer_indexes: false,
         })
     }
 
@@ -85,6 +97,8 @@ impl Catalog {
             use_poi: true,
             // DDL generation creates indexes for prefixes of bytes columns
             use_bytea_prefix: true,
+            partition_blocks: ENV_VARS.store.partition_blocks,
+            defer_indexes: ENV_VARS.store.defer_indexes,
         }
     }
 
@@ -97,6 +111,8 @@ impl Catalog {
             text_columns: HashMap::default(),
             use_poi: false,
             use_bytea_prefix: true,
+            partition_blocks: None,
+            defer_indexes: false,
         })
     }
 
@@ -487,3 +503,153 @@ pub(crate) fn drop_index(
         .map_err::<StoreError, _>(Into::into)?;
     Ok(())
 }
+
+/// The names of all indexes in `namespace` that Postgres marked as invalid
+/// because building them concurrently failed
+pub(crate) fn invalid_indexes(
+    conn: &PgConnection,
+    namespace: &Namespace,
+) -> Result<Vec<String>, StoreError> {
+    #[derive(QueryableByName)]
+    struct IndexName {
+        #[sql_type = "Text"]
+        name: String,
+    }
+
+    let query = "
+        select c.relname::text as name
+          from pg_class c
+               join pg_index i on i.indexrelid = c.oid
+               join pg_namespace n on c.relnamespace = n.oid
+         where n.nspname = $1
+           and not i.indisvalid";
+    Ok(sql_query(query)
+        .bind::<Text, _>(namespace.as_str())
+        .load::<IndexName>(conn)?
+        .into_iter()
+        .map(|index| index.name)
+        .collect())
+}
+
+/// The names of all valid indexes in `namespace`
+pub(crate) fn valid_indexes(
+    conn: &PgConnection,
+    namespace: &Namespace,
+) -> Result<Vec<String>, StoreError> {
+    #[derive(QueryableByName)]
+    struct IndexName {
+        #[sql_type = "Text"]
+        name: String,
+    }
+
+    let query = "
+        select c.relname::text as name
+          from pg_class c
+               join pg_index i on i.indexrelid = c.oid
+               join pg_namespace n on c.relnamespace = n.oid
+         where n.nspname = $1
+           and i.indisvalid";
+    Ok(sql_query(query)
+        .bind::<Text, _>(namespace.as_str())
+        .load::<IndexName>(conn)?
+        .into_iter()
+        .map(|index| index.name)
+        .collect())
+}
+
+/// The expression for the first key column of every valid index on
+/// `namespace.table`, like `name` or `"left"(name, 256)`
+pub(crate) fn leading_index_exprs(
+    conn: &PgConnection,
+    namespace: &Namespace,
+    table: &SqlName,
+) -> Result<Vec<String>, StoreError> {
+    #[derive(QueryableByName)]
+    struct IndexExpr {
+        #[sql_type = "Text"]
+        expr: String,
+    }
+
+    let query = "
+        select pg_get_indexdef(i.indexrelid, 1, true) as expr
+          from pg_index i
+               join pg_class c on c.oid = i.indrelid
+               join pg_namespace n on c.relnamespace = n.oid
+         where n.nspname = $1
+           and c.relname = $2
+           and i.indisvalid";
+    Ok(sql_query(query)
+        .bind::<Text, _>(namespace.as_str())
+        .bind::<Text, _>(table.as_str())
+        .load::<IndexExpr>(conn)?
+        .into_iter()
+        .map(|index| index.expr)
+        .collect())
+}
+
+/// The progress of an index that Postgres is currently building
+#[derive(Clone, Debug, QueryableByName)]
+pub struct IndexBuild {
+    #[sql_type = "Text"]
+    pub name: String,
+    #[sql_type = "Text"]
+    pub phase: String,
+    #[sql_type = "BigInt"]
+    pub blocks_done: i64,
+    #[sql_type = "BigInt"]
+    pub blocks_total: i64,
+}
+
+/// The indexes in `namespace` that are being built right now
+pub(crate) fn index_builds(
+    conn: &PgConnection,
+    namespace: &Namespace,
+) -> Result<Vec<IndexBuild>, StoreError> {
+    let query = "
+        select c.relname::text as name,
+               p.phase::text as phase,
+               p.blocks_done::int8 as blocks_done,
+               p.blocks_total::int8 as blocks_total
+          from pg_stat_progress_create_index p
+               join pg_class c on c.oid = p.index_relid
+               join pg_namespace n on c.relnamespace = n.oid
+         where n.nspname = $1
+         order by c.relname";
+    Ok(sql_query(query)
+        .bind::<Text, _>(namespace.as_str())
+        .load::<IndexBuild>(conn)?)
+}
+
+/// An estimate of the number of rows in `table` and the number of bytes
+/// that the table and its indexes take up on disk
+pub(crate) fn table_size(
+    conn: &PgConnection,
+    namespace: &Namespace,
+    table: &SqlName,
+) -> Result<(i64, i64), StoreError> {
+    #[derive(QueryableByName)]
+    struct Size {
+        #[sql_type = "BigInt"]
+        rows: i64,
+        #[sql_type = "BigInt"]
+        bytes: i64,
+    }
+
+    // `reltuples` is -1 for tables that have never been analyzed
+    const QUERY: &str = "
+        select greatest(c.reltuples, 0)::int8 as rows,
+               pg_total_relation_size(c.oid)::int8 as bytes
+          from pg_class c, pg_namespace n
+         where c.relnamespace = n.oid
+           and n.nspname = $1
+           and c.relname = $2";
+
+    let size = sql_query(QUERY)
+        .bind::<Text, _>(namespace.as_str())
+        .bind::<Text, _>(table.as_str())
+        .get_result::<Size>(conn)
+        .optional()?
+        .map(|size| (size.rows, size.bytes))
+        .unwrap_or((0, 0));
+    Ok(size)
+}
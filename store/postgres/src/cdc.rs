@@ -0,0 +1,191 @@
+//! Append-only change tables for change data capture (CDC). When
+//! `GRAPH_STORE_CDC_TABLES` is set, every entity change is also written to
+//! the table `changes$` in the deployment's schema in the same transaction
+//! as the change itself. Since rows are only ever inserted, the table is
+//! easy to consume through logical replication, e.g., with Debezium.
+//!
+//! Rows in the table are never changed; reverts are recorded as a row with
+//! op `revert` and no entity. Old rows are removed by the `PruneChangesJob`
+//! according to `GRAPH_STORE_CDC_RETENTION_BLOCKS`.
+use diesel::pg::PgConnection;
+use diesel::sql_types::{Array, Binary, Integer, Nullable, Text};
+use diesel::{connection::SimpleConnection, sql_query, RunQueryDsl};
+
+use graph::components::store::EntityModification;
+use graph::data::value::Object;
+use graph::prelude::{r, serde_json, BlockNumber, BlockPtr, StoreError};
+
+use crate::primary::Namespace;
+
+/// The name of the change table in each deployment's schema
+pub(crate) const CHANGES_TABLE: &str = "changes$";
+
+const OP_SET: &str = "set";
+const OP_REMOVE: &str = "remove";
+const OP_REVERT: &str = "revert";
+
+fn changes_table(nsp: &Namespace) -> String {
+    format!("\"{}\".\"{}\"", nsp, CHANGES_TABLE)
+}
+
+/// The DDL for the change table of the deployment in `nsp`
+pub(crate) fn ddl(nsp: &Namespace) -> String {
+    let table = changes_table(nsp);
+    format!(
+        "create table if not exists {table} (
+            seq          bigserial primary key,
+            block_number int not null,
+            block_hash   bytea not null,
+            entity_type  text,
+            entity_id    text,
+            op           text not null,
+            data         jsonb,
+            created_at   timestamptz not null default now()
+         );
+         create index if not exists \"{CHANGES_TABLE}_block_number\"
+             on {table}(block_number);\n"
+    )
+}
+
+/// Create the change table for the deployment in `nsp` if it does not
+/// exist yet
+pub(crate) fn create_table(conn: &PgConnection, nsp: &Namespace) -> Result<(), StoreError> {
+    conn.batch_execute(&ddl(nsp))?;
+    Ok(())
+}
+
+/// Turn an entity into the JSON that we store in the `data` column. We use
+/// the same representation as GraphQL responses, not the internal tagged
+/// representation of entity values
+fn entity_json(modification: &EntityModification) -> Result<Option<String>, StoreError> {
+    use EntityModification::*;
+
+    match modification {
+        Insert { data, .. } | Overwrite { data, .. } => {
+            let object = Object::from_iter(
+                data.clone()
+                    .sorted()
+                    .into_iter()
+                    .map(|(key, value)| (key, r::Value::from(value))),
+            );
+            let json = serde_json::to_string(&r::Value::Object(object))
+                .map_err(|e| StoreError::Unknown(e.into()))?;
+            Ok(Some(json))
+        }
+        Remove { .. } => Ok(None),
+    }
+}
+
+/// Record `mods` in the change table of the deployment in `nsp`
+pub(crate) fn record_changes(
+    conn: &PgConnection,
+    nsp: &Namespace,
+    block_ptr: &BlockPtr,
+    mods: &[EntityModification],
+) -> Result<usize, StoreError> {
+    if mods.is_empty() {
+        return Ok(0);
+    }
+
+    let mut entity_types = Vec::with_capacity(mods.len());
+    let mut entity_ids = Vec::with_capacity(mods.len());
+    let mut ops = Vec::with_capacity(mods.len());
+    let mut data = Vec::with_capacity(mods.len());
+    for modification in mods {
+        let key = modification.entity_key();
+        entity_types.push(key.entity_type.to_string());
+        entity_ids.push(key.entity_id.clone());
+        ops.push(if modification.is_remove() {
+            OP_REMOVE
+        } else {
+            OP_SET
+        });
+        data.push(entity_json(modification)?);
+    }
+
+    let query = format!(
+        "insert into {}(block_number, block_hash, entity_type, entity_id, op, data)
+         select $1, $2, c.entity_type, c.entity_id, c.op, c.data::jsonb
+           from unnest($3::text[], $4::text[], $5::text[], $6::text[])
+                  as c(entity_type, entity_id, op, data)",
+        changes_table(nsp)
+    );
+    Ok(sql_query(query)
+        .bind::<Integer, _>(block_ptr.number)
+        .bind::<Binary, _>(block_ptr.hash_slice())
+        .bind::<Array<Text>, _>(entity_types)
+        .bind::<Array<Text>, _>(entity_ids)
+        .bind::<Array<Text>, _>(ops)
+        .bind::<Array<Nullable<Text>>, _>(data)
+        .execute(conn)?)
+}
+
+/// Record that the deployment in `nsp` was reverted to `block_ptr`
+pub(crate) fn record_revert(
+    conn: &PgConnection,
+    nsp: &Namespace,
+    block_ptr: &BlockPtr,
+) -> Result<(), StoreError> {
+    let query = format!(
+        "insert into {}(block_number, block_hash, op) values ($1, $2, $3)",
+        changes_table(nsp)
+    );
+    sql_query(query)
+        .bind::<Integer, _>(block_ptr.number)
+        .bind::<Binary, _>(block_ptr.hash_slice())
+        .bind::<Text, _>(OP_REVERT)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Remove changes that are more than `retention` blocks behind the head of
+/// their deployment from all change tables in the shard that `conn` is
+/// connected to. Returns the number of rows that were removed
+pub(crate) fn prune(conn: &PgConnection, retention: BlockNumber) -> Result<usize, StoreError> {
+    #[derive(QueryableByName)]
+    struct ChangeTable {
+        #[sql_type = "Text"]
+        nsp: String,
+        #[sql_type = "Integer"]
+        head: BlockNumber,
+    }
+
+    let tables = sql_query(
+        "select n.nspname as nsp, d.latest_ethereum_block_number as head
+           from pg_class c
+           join pg_namespace n on c.relnamespace = n.oid
+           join subgraphs.subgraph_deployment d on n.nspname = 'sgd' || d.id
+          where c.relname = $1
+            and c.relkind = 'r'
+            and d.latest_ethereum_block_number is not null",
+    )
+    .bind::<Text, _>(CHANGES_TABLE)
+    .load::<ChangeTable>(conn)?;
+
+    let mut count = 0;
+    for table in tables {
+        let nsp = Namespace::new(table.nsp)
+            .map_err(|nsp| constraint_violation!("invalid namespace {}", nsp))?;
+        let query = format!(
+            "delete from {} where block_number < $1",
+            changes_table(&nsp)
+        );
+        count += sql_query(query)
+            .bind::<Integer, _>(table.head.saturating_sub(retention))
+            .execute(conn)?;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn change_table_ddl() {
+        let nsp = Namespace::new("sgd42".to_string()).unwrap();
+        let ddl = ddl(&nsp);
+        assert!(ddl.contains("create table if not exists \"sgd42\".\"changes$\""));
+        assert!(ddl.contains("on \"sgd42\".\"changes$\"(block_number)"));
+    }
+}
@@ -51,7 +51,7 @@ mod data {
     use diesel::dsl::sql;
     use diesel::pg::{Pg, PgConnection};
     use diesel::serialize::Output;
-    use diesel::sql_types::{BigInt, Binary, Bytea, Integer, Jsonb, Text};
+    use diesel::sql_types::{BigInt, Binary, Bool, Bytea, Integer, Jsonb, Nullable, Text};
     use diesel::types::{FromSql, ToSql};
     use diesel::{delete, insert_into, prelude::*, sql_query, update};
     use diesel_dynamic_schema as dds;
@@ -82,6 +82,7 @@ mod data {
                 parent_hash -> Nullable<Varchar>,
                 network_name -> Varchar, // REFERENCES ethereum_networks (name),
                 data -> Jsonb,
+                timestamp -> Nullable<BigInt>,
             }
         }
 
@@ -122,6 +123,20 @@ mod data {
         hash: Vec<u8>,
     }
 
+    #[derive(QueryableByName)]
+    struct BlockNumberRow {
+        #[sql_type = "BigInt"]
+        number: i64,
+    }
+
+    #[derive(QueryableByName)]
+    struct TimestampBlockRow {
+        #[sql_type = "BigInt"]
+        number: i64,
+        #[sql_type = "Bool"]
+        covered: bool,
+    }
+
     // Like H256::from_slice, but returns an error instead of panicking
     // when `bytes` does not have the right length
     fn h256_from_bytes(bytes: &[u8]) -> Result<H256, StoreError> {
@@ -330,9 +345,11 @@ mod data {
                   hash         bytea  not null primary key,
                   number       int8  not null,
                   parent_hash  bytea  not null,
-                  data         jsonb not null
+                  data         jsonb not null,
+                  timestamp    int8
                 );
                 create index blocks_number ON {nsp}.blocks using btree(number);
+                create index blocks_timestamp ON {nsp}.blocks using btree(timestamp, number);
 
                 create table {nsp}.call_cache (
 	              id               bytea not null primary key,
@@ -396,6 +413,60 @@ mod data {
             Ok(())
         }
 
+        /// Delete up to `batch_size` calls that were made at blocks before
+        /// `before` from the call cache and return how many were deleted.
+        /// All chains with shared storage use the same call cache, which can
+        /// therefore not be pruned by block number
+        pub(super) fn prune_call_cache(
+            &self,
+            conn: &PgConnection,
+            before: BlockNumber,
+            batch_size: usize,
+        ) -> Result<usize, StoreError> {
+            match self {
+                Storage::Shared => Err(StoreError::Unknown(anyhow::anyhow!(
+                    "the call cache of chains stored in the `public` schema can not be pruned"
+                ))),
+                Storage::Private(Schema { call_cache, .. }) => {
+                    let query = format!(
+                        "delete from {qname} where id in \
+                           (select id from {qname} where block_number < $1 limit $2)",
+                        qname = call_cache.qname
+                    );
+                    Ok(sql_query(query)
+                        .bind::<Integer, _>(before)
+                        .bind::<BigInt, _>(batch_size as i64)
+                        .execute(conn)?)
+                }
+            }
+        }
+
+        /// The block number of the newest call in the call cache that has
+        /// to be removed so that at most `max_calls` calls are left, or
+        /// `None` if there are not that many calls
+        pub(super) fn call_cache_size_cutoff(
+            &self,
+            conn: &PgConnection,
+            max_calls: usize,
+        ) -> Result<Option<BlockNumber>, StoreError> {
+            match self {
+                Storage::Shared => Ok(None),
+                Storage::Private(Schema { call_cache, .. }) => {
+                    let query = format!(
+                        "select block_number::int8 as number from {}
+                          order by block_number desc
+                         offset $1 limit 1",
+                        call_cache.qname
+                    );
+                    let number = sql_query(query)
+                        .bind::<BigInt, _>(max_calls as i64)
+                        .get_result::<BlockNumberRow>(conn)
+                        .optional()?;
+                    Ok(number.map(|row| row.number as BlockNumber))
+                }
+            }
+        }
+
         /// Insert a block. If the table already contains a block with the
         /// same hash, then overwrite that block since it may be adding
         /// transaction receipts. If `overwrite` is `true`, overwrite a
@@ -415,6 +486,9 @@ mod data {
 
             let number = block.number() as i64;
             let data = block.data().expect("Failed to serialize block");
+            let timestamp = block
+                .timestamp()
+                .and_then(|timestamp| i64::try_from(timestamp).ok());
             let hash = block.hash();
             let parent_hash = block.parent_hash().unwrap_or_else(|| {
                 BlockHash::try_from(NO_PARENT).expect("NO_PARENT is a valid hash")
@@ -430,6 +504,7 @@ mod data {
                         b::parent_hash.eq(parent_hash.hash_hex()),
                         b::network_name.eq(chain),
                         b::data.eq(data),
+                        b::timestamp.eq(timestamp),
                     );
 
                     if overwrite {
@@ -450,16 +525,17 @@ mod data {
                 Storage::Private(Schema { blocks, .. }) => {
                     let query = if overwrite {
                         format!(
-                            "insert into {}(hash, number, parent_hash, data) \
-                             values ($1, $2, $3, $4) \
+                            "insert into {}(hash, number, parent_hash, data, timestamp) \
+                             values ($1, $2, $3, $4, $5) \
                                  on conflict(hash) \
-                                 do update set number = $2, parent_hash = $3, data = $4",
+                                 do update set number = $2, parent_hash = $3, data = $4, \
+                                               timestamp = $5",
                             blocks.qname,
                         )
                     } else {
                         format!(
-                            "insert into {}(hash, number, parent_hash, data) \
-                             values ($1, $2, $3, $4) \
+                            "insert into {}(hash, number, parent_hash, data, timestamp) \
+                             values ($1, $2, $3, $4, $5) \
                                  on conflict(hash) do nothing",
                             blocks.qname
                         )
@@ -469,6 +545,7 @@ mod data {
                         .bind::<BigInt, _>(number)
                         .bind::<Bytea, _>(parent_hash.as_slice())
                         .bind::<Jsonb, _>(data)
+                        .bind::<Nullable<BigInt>, _>(timestamp)
                         .execute(conn)?;
                 }
             };
@@ -612,6 +689,70 @@ mod data {
                 .transpose()
         }
 
+        /// Find the block with the lowest number whose timestamp, in seconds
+        /// since the epoch, is at or after `timestamp`. Only cached blocks
+        /// are considered, and since the cache can have gaps, the answer is
+        /// only trustworthy if the cache also has the block before that
+        /// block and that block is older than `timestamp`; if it does not,
+        /// return an error rather than a block that might be too late
+        pub(super) fn block_number_by_timestamp(
+            &self,
+            conn: &PgConnection,
+            chain: &str,
+            timestamp: u64,
+        ) -> Result<Option<BlockNumber>, StoreError> {
+            let timestamp = i64::try_from(timestamp)
+                .map_err(|e| StoreError::QueryExecutionError(e.to_string()))?;
+            let row = match self {
+                Storage::Shared => sql_query(
+                    "select b.number,
+                            b.number = 0 or exists (
+                              select 1 from ethereum_blocks p
+                               where p.network_name = $1
+                                 and p.number = b.number - 1
+                                 and p.timestamp < $2) as covered
+                       from ethereum_blocks b
+                      where b.network_name = $1
+                        and b.timestamp >= $2
+                      order by b.timestamp, b.number
+                      limit 1",
+                )
+                .bind::<Text, _>(chain)
+                .bind::<BigInt, _>(timestamp)
+                .get_result::<TimestampBlockRow>(conn)
+                .optional()?,
+                Storage::Private(Schema { blocks, .. }) => {
+                    let query = format!(
+                        "select b.number,
+                                b.number = 0 or exists (
+                                  select 1 from {blocks} p
+                                   where p.number = b.number - 1
+                                     and p.timestamp < $1) as covered
+                           from {blocks} b
+                          where b.timestamp >= $1
+                          order by b.timestamp, b.number
+                          limit 1",
+                        blocks = blocks.qname
+                    );
+                    sql_query(query)
+                        .bind::<BigInt, _>(timestamp)
+                        .get_result::<TimestampBlockRow>(conn)
+                        .optional()?
+                }
+            };
+            match row {
+                None => Ok(None),
+                Some(row) if !row.covered => Err(StoreError::QueryExecutionError(format!(
+                    "the block cache for chain {} does not have the blocks around timestamp {}; \
+                     query by block number instead",
+                    chain, timestamp
+                ))),
+                Some(row) => BlockNumber::try_from(row.number)
+                    .map(Some)
+                    .map_err(|e| StoreError::QueryExecutionError(e.to_string())),
+            }
+        }
+
         /// Find the first block that is missing from the database needed to
         /// complete the chain from block `hash` to the block with number
         /// `first_block`.
@@ -1337,6 +1478,56 @@ impl ChainStore {
         self.storage.truncate_block_cache(&conn)?;
         Ok(())
     }
+
+    /// Whether the call cache of this chain can be pruned, which is not
+    /// possible for chains with shared storage
+    pub fn call_cache_is_prunable(&self) -> bool {
+        !matches!(self.storage, data::Storage::Shared)
+    }
+
+    /// Remove the calls that were made at blocks before `before` from the
+    /// call cache and return how many were removed. Calls are removed in
+    /// batches to avoid long-running transactions
+    pub fn prune_call_cache(&self, before: BlockNumber) -> Result<usize, StoreError> {
+        const BATCH_SIZE: usize = 10_000;
+
+        let mut removed = 0;
+        loop {
+            let conn = self.get_conn()?;
+            let count = self.storage.prune_call_cache(&conn, before, BATCH_SIZE)?;
+            removed += count;
+            if count < BATCH_SIZE {
+                return Ok(removed);
+            }
+        }
+    }
+
+    /// The block before which calls have to be removed from the call cache
+    /// to keep only calls from the last `retention_blocks` blocks before
+    /// the chain head and at most `max_calls` calls. Returns `None` if
+    /// nothing has to be removed
+    pub fn call_cache_cutoff(
+        &self,
+        retention_blocks: Option<BlockNumber>,
+        max_calls: Option<usize>,
+    ) -> Result<Option<BlockNumber>, StoreError> {
+        let by_age = match retention_blocks {
+            Some(retention) => self
+                .chain_head_block(&self.chain)?
+                .map(|head| head.saturating_sub(retention)),
+            None => None,
+        };
+        let by_size = match max_calls {
+            Some(max_calls) => {
+                let conn = self.get_conn()?;
+                self.storage
+                    .call_cache_size_cutoff(&conn, max_calls)?
+                    .map(|number| number + 1)
+            }
+            None => None,
+        };
+        Ok(by_age.max(by_size))
+    }
 }
 
 #[async_trait]
@@ -1635,6 +1826,17 @@ impl ChainStoreTrait for ChainStore {
             .map(|number| (self.chain.clone(), number)))
     }
 
+    fn block_number_by_timestamp(
+        &self,
+        timestamp: u64,
+    ) -> Result<Option<(String, BlockNumber)>, StoreError> {
+        let conn = self.get_conn()?;
+        Ok(self
+            .storage
+            .block_number_by_timestamp(&conn, &self.chain, timestamp)?
+            .map(|number| (self.chain.clone(), number)))
+    }
+
     async fn transaction_receipts_in_block(
         &self,
         block_hash: &H256,
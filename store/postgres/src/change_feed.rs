@@ -0,0 +1,379 @@
+//! Publish the entity changes that are written to the store to an external
+//! system, currently NATS or Kafka (through the Kafka REST proxy), so that
+//! downstream systems can react to changes without polling GraphQL.
+//!
+//! Changes are handed to a background task through a bounded buffer and
+//! published in batches. Publishing never blocks or fails writes; if the
+//! publisher can not keep up, changes are dropped and that is logged.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use graph::components::store::EntityModification;
+use graph::prelude::{
+    anyhow::{anyhow, bail},
+    async_trait, error, info, o, reqwest, serde_json,
+    tokio::{
+        self,
+        io::{AsyncBufReadExt, AsyncWriteExt, BufStream},
+        net::TcpStream,
+        sync::mpsc,
+    },
+    warn, BlockPtr, DeploymentHash, Error, Logger, Serialize, ENV_VARS,
+};
+
+/// The most changes we publish in one batch
+const BATCH_SIZE: usize = 500;
+/// How often we try to publish a batch before we give up on it
+const MAX_ATTEMPTS: usize = 5;
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ChangeOp {
+    Set,
+    Remove,
+    /// The deployment was reverted to `block`; all changes after that
+    /// block have been undone
+    Revert,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+struct ChangeBlock {
+    number: i32,
+    hash: String,
+}
+
+/// The message we publish for each entity change
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangeMessage {
+    deployment: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entity_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    op: ChangeOp,
+    block: ChangeBlock,
+}
+
+impl ChangeMessage {
+    fn block(ptr: &BlockPtr) -> ChangeBlock {
+        ChangeBlock {
+            number: ptr.number,
+            hash: ptr.hash.to_string(),
+        }
+    }
+
+    fn for_modification(block_ptr: &BlockPtr, modification: &EntityModification) -> Self {
+        let key = modification.entity_key();
+        let op = if modification.is_remove() {
+            ChangeOp::Remove
+        } else {
+            ChangeOp::Set
+        };
+        ChangeMessage {
+            deployment: key.subgraph_id.to_string(),
+            entity_type: Some(key.entity_type.to_string()),
+            id: Some(key.entity_id.clone()),
+            op,
+            block: Self::block(block_ptr),
+        }
+    }
+
+    fn for_revert(deployment: &DeploymentHash, block_ptr: &BlockPtr) -> Self {
+        ChangeMessage {
+            deployment: deployment.to_string(),
+            entity_type: None,
+            id: None,
+            op: ChangeOp::Revert,
+            block: Self::block(block_ptr),
+        }
+    }
+
+    /// The key under which Kafka stores the message; messages with the
+    /// same key go to the same partition and therefore stay in order
+    fn key(&self) -> String {
+        match &self.id {
+            Some(id) => format!("{}:{}", self.deployment, id),
+            None => self.deployment.clone(),
+        }
+    }
+}
+
+#[async_trait]
+trait Publisher: Send + Sync {
+    async fn publish(&mut self, changes: &[ChangeMessage]) -> Result<(), Error>;
+}
+
+/// A minimal NATS client that can only publish to one subject. After each
+/// batch, we send a `PING` and wait for the server's `PONG` so that we know
+/// the batch was received
+struct NatsPublisher {
+    addr: String,
+    subject: String,
+    conn: Option<BufStream<TcpStream>>,
+}
+
+impl NatsPublisher {
+    async fn connect(&self) -> Result<BufStream<TcpStream>, Error> {
+        let mut conn = BufStream::new(TcpStream::connect(&self.addr).await?);
+        // The server greets us with an `INFO` line
+        let mut line = String::new();
+        conn.read_line(&mut line).await?;
+        if !line.starts_with("INFO") {
+            bail!("unexpected greeting from NATS server: {}", line.trim_end());
+        }
+        conn.write_all(
+            b"CONNECT {\"verbose\":false,\"pedantic\":false,\"name\":\"graph-node\"}\r\n",
+        )
+        .await?;
+        Ok(conn)
+    }
+
+    async fn publish_on(
+        conn: &mut BufStream<TcpStream>,
+        subject: &str,
+        changes: &[ChangeMessage],
+    ) -> Result<(), Error> {
+        for change in changes {
+            let payload = serde_json::to_vec(change)?;
+            conn.write_all(format!("PUB {} {}\r\n", subject, payload.len()).as_bytes())
+                .await?;
+            conn.write_all(&payload).await?;
+            conn.write_all(b"\r\n").await?;
+        }
+        conn.write_all(b"PING\r\n").await?;
+        conn.flush().await?;
+
+        loop {
+            let mut line = String::new();
+            if conn.read_line(&mut line).await? == 0 {
+                bail!("NATS server closed the connection");
+            }
+            match line.trim_end() {
+                "PONG" => return Ok(()),
+                "PING" => {
+                    conn.write_all(b"PONG\r\n").await?;
+                    conn.flush().await?;
+                }
+                line if line.starts_with("-ERR") => bail!("NATS server error: {}", line),
+                // `+OK`, `INFO` and anything else needs no answer
+                _ => (),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Publisher for NatsPublisher {
+    async fn publish(&mut self, changes: &[ChangeMessage]) -> Result<(), Error> {
+        let mut conn = match self.conn.take() {
+            Some(conn) => conn,
+            None => self.connect().await?,
+        };
+        // We only put the connection back if it worked; after an error, we
+        // reconnect for the next attempt
+        Self::publish_on(&mut conn, &self.subject, changes).await?;
+        self.conn = Some(conn);
+        Ok(())
+    }
+}
+
+/// Publishes to a Kafka topic through the REST proxy; `url` is the URL of
+/// the topic, i.e., `http(s)://<proxy>/topics/<topic>`
+struct KafkaRestPublisher {
+    client: reqwest::Client,
+    url: String,
+}
+
+#[async_trait]
+impl Publisher for KafkaRestPublisher {
+    async fn publish(&mut self, changes: &[ChangeMessage]) -> Result<(), Error> {
+        let records: Vec<_> = changes
+            .iter()
+            .map(|change| serde_json::json!({ "key": change.key(), "value": change }))
+            .collect();
+        let response = self
+            .client
+            .post(&self.url)
+            .header("Content-Type", "application/vnd.kafka.json.v2+json")
+            .body(serde_json::to_vec(
+                &serde_json::json!({ "records": records }),
+            )?)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            bail!("Kafka REST proxy responded with {}: {}", status, body);
+        }
+        Ok(())
+    }
+}
+
+fn publisher_from_url(url: &str) -> Result<Box<dyn Publisher>, Error> {
+    if let Some(rest) = url.strip_prefix("nats://") {
+        let (addr, subject) = rest
+            .split_once('/')
+            .ok_or_else(|| anyhow!("the change feed URL `{}` has no subject", url))?;
+        if addr.is_empty() || subject.is_empty() || subject.contains(char::is_whitespace) {
+            bail!("the change feed URL `{}` is not a valid NATS URL", url);
+        }
+        Ok(Box::new(NatsPublisher {
+            addr: addr.to_string(),
+            subject: subject.to_string(),
+            conn: None,
+        }))
+    } else if url.starts_with("http://") || url.starts_with("https://") {
+        if !url.contains("/topics/") {
+            bail!(
+                "the change feed URL `{}` must be the URL of a topic of the Kafka REST proxy",
+                url
+            );
+        }
+        Ok(Box::new(KafkaRestPublisher {
+            client: reqwest::Client::new(),
+            url: url.to_string(),
+        }))
+    } else {
+        bail!(
+            "unsupported change feed URL `{}`; only nats:// and http(s):// URLs are supported",
+            url
+        )
+    }
+}
+
+/// The feed of entity changes. Changes are buffered and published by a
+/// background task
+pub struct ChangeFeed {
+    logger: Logger,
+    sender: mpsc::Sender<ChangeMessage>,
+    dropped: AtomicUsize,
+}
+
+impl ChangeFeed {
+    /// Create the change feed configured with `GRAPH_STORE_CHANGE_FEED_URL`,
+    /// or return `None` if no feed is configured
+    pub fn from_env(logger: &Logger) -> Result<Option<Arc<Self>>, Error> {
+        let url = match &ENV_VARS.store.change_feed_url {
+            Some(url) => url,
+            None => return Ok(None),
+        };
+        let publisher = publisher_from_url(url)?;
+        let logger = logger.new(o!("component" => "ChangeFeed"));
+        let (sender, receiver) = mpsc::channel(ENV_VARS.store.change_feed_buffer.max(1));
+
+        info!(logger, "Publishing entity changes"; "url" => url);
+        graph::spawn(Self::run(logger.clone(), publisher, receiver));
+
+        Ok(Some(Arc::new(ChangeFeed {
+            logger,
+            sender,
+            dropped: AtomicUsize::new(0),
+        })))
+    }
+
+    async fn run(
+        logger: Logger,
+        mut publisher: Box<dyn Publisher>,
+        mut receiver: mpsc::Receiver<ChangeMessage>,
+    ) {
+        while let Some(change) = receiver.recv().await {
+            let mut batch = vec![change];
+            while batch.len() < BATCH_SIZE {
+                match receiver.try_recv() {
+                    Ok(change) => batch.push(change),
+                    Err(_) => break,
+                }
+            }
+
+            let mut attempt = 1;
+            while let Err(e) = publisher.publish(&batch).await {
+                if attempt >= MAX_ATTEMPTS {
+                    error!(logger, "Failed to publish entity changes, dropping them";
+                        "changes" => batch.len(),
+                        "error" => e.to_string());
+                    break;
+                }
+                warn!(logger, "Failed to publish entity changes, will retry";
+                    "attempt" => attempt,
+                    "error" => e.to_string());
+                attempt += 1;
+                tokio::time::sleep(RETRY_DELAY * attempt as u32).await;
+            }
+        }
+    }
+
+    fn send(&self, change: ChangeMessage) {
+        if self.sender.try_send(change).is_err() {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            // Don't flood the logs when the feed is down
+            if dropped.is_power_of_two() {
+                warn!(self.logger, "The change feed buffer is full, dropping entity changes";
+                    "dropped" => dropped);
+            }
+        }
+    }
+
+    /// Publish the changes that `mods` made in block `block_ptr`
+    pub fn entities_changed(&self, block_ptr: &BlockPtr, mods: &[EntityModification]) {
+        for modification in mods {
+            self.send(ChangeMessage::for_modification(block_ptr, modification));
+        }
+    }
+
+    /// Publish that `deployment` was reverted to `block_ptr`
+    pub fn reverted(&self, deployment: &DeploymentHash, block_ptr: &BlockPtr) {
+        self.send(ChangeMessage::for_revert(deployment, block_ptr));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::components::store::{EntityModification, EntityType};
+    use graph::prelude::{serde_json, BlockPtr, DeploymentHash, EntityKey};
+
+    use super::{publisher_from_url, ChangeMessage};
+
+    #[test]
+    fn change_messages() {
+        let block = BlockPtr::from((vec![0xab; 32], 7i32));
+        let key = EntityKey {
+            subgraph_id: DeploymentHash::new("QmFeed").unwrap(),
+            entity_type: EntityType::new("Thing".to_string()),
+            entity_id: "t1".to_string(),
+        };
+        let change = ChangeMessage::for_modification(
+            &block,
+            &EntityModification::Remove { key: key.clone() },
+        );
+        assert_eq!("QmFeed:t1", change.key());
+
+        let json = serde_json::to_value(&change).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "deployment": "QmFeed",
+                "entityType": "Thing",
+                "id": "t1",
+                "op": "remove",
+                "block": { "number": 7, "hash": format!("0x{}", "ab".repeat(32)) }
+            }),
+            json
+        );
+
+        let revert = ChangeMessage::for_revert(&key.subgraph_id, &block);
+        let json = serde_json::to_value(&revert).unwrap();
+        assert_eq!("revert", json["op"]);
+        assert!(json.get("id").is_none());
+    }
+
+    #[test]
+    fn feed_urls() {
+        assert!(publisher_from_url("nats://localhost:4222/graph.changes").is_ok());
+        assert!(publisher_from_url("nats://localhost:4222").is_err());
+        assert!(publisher_from_url("http://localhost:8082/topics/changes").is_ok());
+        assert!(publisher_from_url("http://localhost:8082").is_err());
+        assert!(publisher_from_url("kafka://localhost:9092/changes").is_err());
+    }
+}
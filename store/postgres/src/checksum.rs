@@ -0,0 +1,185 @@
+//! Checksums over the entities of a deployment as of a block. The proof of
+//! indexing only covers the changes a deployment made while indexing, not
+//! what is actually in its tables, and checksums make it possible to verify
+//! that a copied or restored deployment has exactly the same data as the
+//! original, even if the two live in different installations.
+//!
+//! The checksum of a table is the sum of a hash of every entity version
+//! that is visible at the block. It does not depend on the order of rows,
+//! on the `vid` of entity versions, or on where their block ranges start
+//! and end, so that it only changes if the data of some entity changes.
+use std::collections::BTreeMap;
+
+use diesel::sql_types::{BigInt, Text};
+use diesel::{sql_query, PgConnection, RunQueryDsl};
+
+use graph::data::subgraph::schema::POI_OBJECT;
+use graph::prelude::{BlockNumber, Deserialize, Serialize, StoreError};
+
+use crate::backup::snapshot_block;
+use crate::block_range::{BLOCK_COLUMN, BLOCK_RANGE_COLUMN};
+use crate::relational::{Layout, Table};
+
+/// The checksum of the entities in one table
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, QueryableByName)]
+pub struct TableChecksum {
+    #[sql_type = "BigInt"]
+    pub entities: i64,
+    #[sql_type = "Text"]
+    pub checksum: String,
+}
+
+/// The checksums of all entity tables of a deployment at a block. These
+/// can be written to a file and compared with checksums computed in
+/// another installation
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checksums {
+    pub deployment: String,
+    pub block: BlockNumber,
+    /// Checksums by entity type
+    pub tables: BTreeMap<String, TableChecksum>,
+}
+
+impl Checksums {
+    /// Compare these checksums with `other` and return a description of
+    /// every difference. An empty result means that the data is the same
+    pub fn diff(&self, other: &Checksums) -> Vec<String> {
+        let mut diffs = Vec::new();
+        if self.block != other.block {
+            diffs.push(format!(
+                "the checksums were computed at different blocks: {} and {}",
+                self.block, other.block
+            ));
+        }
+        for (entity_type, checksum) in &self.tables {
+            match other.tables.get(entity_type) {
+                None => diffs.push(format!("{}: only in {}", entity_type, self.deployment)),
+                Some(other_checksum) if checksum.entities != other_checksum.entities => {
+                    diffs.push(format!(
+                        "{}: {} entities in {} but {} in {}",
+                        entity_type,
+                        checksum.entities,
+                        self.deployment,
+                        other_checksum.entities,
+                        other.deployment
+                    ))
+                }
+                Some(other_checksum) if checksum.checksum != other_checksum.checksum => {
+                    diffs.push(format!("{}: the data differs", entity_type))
+                }
+                Some(_) => { /* the data is the same */ }
+            }
+        }
+        for entity_type in other.tables.keys() {
+            if !self.tables.contains_key(entity_type) {
+                diffs.push(format!("{}: only in {}", entity_type, other.deployment));
+            }
+        }
+        diffs
+    }
+}
+
+fn table_checksum(
+    conn: &PgConnection,
+    table: &Table,
+    block: BlockNumber,
+) -> Result<TableChecksum, StoreError> {
+    let (visible, strip) = if table.immutable {
+        (format!("{} <= {}", BLOCK_COLUMN, block), BLOCK_COLUMN)
+    } else {
+        (
+            format!("{} @> {}", BLOCK_RANGE_COLUMN, block),
+            BLOCK_RANGE_COLUMN,
+        )
+    };
+    // Use 60 bits of the md5 hash of each row so that the sum of the
+    // hashes fits comfortably into a `numeric` without losing precision
+    let query = format!(
+        "select count(*) as entities, \
+                coalesce(sum(('x' || substr(md5((to_jsonb(t) - 'vid' - '{}')::text), 1, 15))\
+                             ::bit(60)::bigint), 0)::text as checksum \
+           from {} t where {}",
+        strip, table.qualified_name, visible
+    );
+    sql_query(query)
+        .get_result::<TableChecksum>(conn)
+        .map_err(StoreError::from)
+}
+
+/// Compute the checksums of all entity tables of the deployment with
+/// `layout` at `block`, or at its current head if `block` is `None`. The
+/// caller should run this in a transaction with isolation level
+/// `repeatable read` so that all checksums are computed against the same
+/// state of the deployment
+pub(crate) fn checksums(
+    conn: &PgConnection,
+    layout: &Layout,
+    block: Option<BlockNumber>,
+) -> Result<Checksums, StoreError> {
+    let block = snapshot_block(conn, &layout.site, block)?;
+
+    let mut tables = BTreeMap::new();
+    for table in layout.tables.values() {
+        if table.object == *POI_OBJECT {
+            continue;
+        }
+        tables.insert(
+            table.object.to_string(),
+            table_checksum(conn, table, block)?,
+        );
+    }
+    Ok(Checksums {
+        deployment: layout.site.deployment.to_string(),
+        block,
+        tables,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checksums(deployment: &str, block: BlockNumber, tables: &[(&str, i64, &str)]) -> Checksums {
+        Checksums {
+            deployment: deployment.to_string(),
+            block,
+            tables: tables
+                .iter()
+                .map(|(name, entities, checksum)| {
+                    (
+                        name.to_string(),
+                        TableChecksum {
+                            entities: *entities,
+                            checksum: checksum.to_string(),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn diff() {
+        let a = checksums("QmA", 10, &[("Token", 3, "123"), ("Pair", 2, "45")]);
+        assert!(a.diff(&a.clone()).is_empty());
+
+        let b = checksums("QmB", 10, &[("Token", 3, "124"), ("User", 1, "7")]);
+        assert_eq!(
+            vec![
+                "Pair: only in QmA".to_string(),
+                "Token: the data differs".to_string(),
+                "User: only in QmB".to_string()
+            ],
+            a.diff(&b)
+        );
+
+        let c = checksums("QmC", 11, &[("Token", 4, "123"), ("Pair", 2, "45")]);
+        assert_eq!(
+            vec![
+                "the checksums were computed at different blocks: 10 and 11".to_string(),
+                "Token: 3 entities in QmA but 4 in QmC".to_string()
+            ],
+            a.diff(&c)
+        );
+    }
+}
@@ -24,7 +24,7 @@ use graph::{
 
 use std::fmt::{self, Write};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use std::{collections::HashMap, sync::RwLock};
 
@@ -208,10 +208,14 @@ impl ForeignServer {
     }
 }
 
-/// How long to keep connections in the `fdw_pool` around before closing
-/// them on idle. This is much shorter than the default of 10 minutes.
+/// How long to keep connections in the `fdw_pool` and the `copy_pool`
+/// around before closing them on idle. This is much shorter than the default of 10 minutes.
 const FDW_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
 
+/// How often we consider changing the number of queries that may use a
+/// pool concurrently
+const QUERY_LIMIT_RESIZE_INTERVAL: Duration = Duration::from_secs(5);
+
 /// A pool goes through several states, and this enum tracks what state we
 /// are in, together with the `state_tracker` field on `ConnectionPool`.
 /// When first created, the pool is in state `Created`; once we successfully
@@ -301,6 +305,7 @@ impl ConnectionPool {
         shard_name: &str,
         pool_name: PoolName,
         postgres_url: String,
+        min_pool_size: u32,
         pool_size: u32,
         fdw_pool_size: Option<u32>,
         logger: &Logger,
@@ -312,6 +317,7 @@ impl ConnectionPool {
             shard_name,
             pool_name.as_str(),
             postgres_url,
+            min_pool_size,
             pool_size,
             fdw_pool_size,
             logger,
@@ -449,6 +455,12 @@ impl ConnectionPool {
         ForeignServer::new(pool.shard.clone(), &pool.postgres_url).map_err(|e| e.into())
     }
 
+    /// Get a connection from the pool for bulk writes. Unlike the other
+    /// connections, it supports `COPY`
+    pub fn copy_client(&self) -> Result<CopyClient, StoreError> {
+        self.get_ready()?.copy_client()
+    }
+
     /// Check that we can connect to the database
     pub fn check(&self) -> bool {
         true
@@ -510,6 +522,19 @@ fn brief_error_msg(error: &dyn std::error::Error) -> String {
         .to_string()
 }
 
+fn error_counter_for(
+    registry: &Arc<dyn MetricsRegistry>,
+    const_labels: HashMap<String, String>,
+) -> Counter {
+    registry
+        .global_counter(
+            "store_connection_error_count",
+            "The number of Postgres connections errors",
+            const_labels,
+        )
+        .expect("failed to create `store_connection_error_count` counter")
+}
+
 #[derive(Clone)]
 struct ErrorHandler {
     logger: Logger,
@@ -532,9 +557,9 @@ impl std::fmt::Debug for ErrorHandler {
     }
 }
 
-impl r2d2::HandleError<r2d2::Error> for ErrorHandler {
-    fn handle_error(&self, error: r2d2::Error) {
-        let msg = brief_error_msg(&error);
+impl ErrorHandler {
+    fn handle(&self, error: &dyn std::error::Error) {
+        let msg = brief_error_msg(error);
 
         // Don't count canceling statements for timeouts etc. as a
         // connection error. Unfortunately, we only have the textual error
@@ -562,12 +587,54 @@ impl r2d2::HandleError<r2d2::Error> for ErrorHandler {
     }
 }
 
+impl r2d2::HandleError<r2d2::Error> for ErrorHandler {
+    fn handle_error(&self, error: r2d2::Error) {
+        self.handle(&error)
+    }
+}
+
+impl r2d2::HandleError<postgres::Error> for ErrorHandler {
+    fn handle_error(&self, error: postgres::Error) {
+        self.handle(&error)
+    }
+}
+
+/// Manages connections that use the `postgres` crate instead of diesel.
+/// Diesel connections do not support `COPY`, and bulk writes therefore
+/// need connections of their own
+#[derive(Debug)]
+pub struct CopyConnectionManager {
+    postgres_url: String,
+}
+
+impl r2d2::ManageConnection for CopyConnectionManager {
+    type Connection = postgres::Client;
+    type Error = postgres::Error;
+
+    fn connect(&self) -> Result<postgres::Client, postgres::Error> {
+        postgres::Client::connect(&self.postgres_url, postgres::NoTls)
+    }
+
+    fn is_valid(&self, conn: &mut postgres::Client) -> Result<(), postgres::Error> {
+        conn.simple_query("").map(|_| ())
+    }
+
+    fn has_broken(&self, conn: &mut postgres::Client) -> bool {
+        conn.is_closed()
+    }
+}
+
+/// A connection for bulk writes with `COPY`
+pub type CopyClient = PooledConnection<CopyConnectionManager>;
+
 #[derive(Clone)]
 struct EventHandler {
     logger: Logger,
     count_gauge: Gauge,
     wait_gauge: Gauge,
     size_gauge: Gauge,
+    saturation_gauge: Gauge,
+    max_size: u32,
     wait_stats: PoolWaitStats,
     state_tracker: PoolStateTracker,
 }
@@ -579,6 +646,7 @@ impl EventHandler {
         wait_stats: PoolWaitStats,
         const_labels: HashMap<String, String>,
         state_tracker: PoolStateTracker,
+        max_size: u32,
     ) -> Self {
         let count_gauge = registry
             .global_gauge(
@@ -598,19 +666,33 @@ impl EventHandler {
             .global_gauge(
                 "store_connection_pool_size_count",
                 "Overall size of the connection pool",
-                const_labels,
+                const_labels.clone(),
             )
             .expect("failed to create `store_connection_pool_size_count` counter");
+        let saturation_gauge = registry
+            .global_gauge(
+                "store_connection_pool_saturation",
+                "The fraction of the connection pool that is in use",
+                saturation_labels(const_labels, "connections"),
+            )
+            .expect("failed to create `store_connection_pool_saturation` gauge");
         EventHandler {
             logger,
             count_gauge,
             wait_gauge,
             wait_stats,
             size_gauge,
+            saturation_gauge,
+            max_size,
             state_tracker,
         }
     }
 
+    fn update_saturation(&self) {
+        self.saturation_gauge
+            .set(self.count_gauge.get() / self.max_size.max(1) as f64);
+    }
+
     fn add_conn_wait_time(&self, duration: Duration) {
         self.wait_stats
             .write()
@@ -637,6 +719,7 @@ impl HandleEvent for EventHandler {
 
     fn handle_checkout(&self, event: e::CheckoutEvent) {
         self.count_gauge.inc();
+        self.update_saturation();
         self.add_conn_wait_time(event.duration());
         self.state_tracker.mark_available();
     }
@@ -653,6 +736,100 @@ impl HandleEvent for EventHandler {
 
     fn handle_checkin(&self, _: e::CheckinEvent) {
         self.count_gauge.dec();
+        self.update_saturation();
+    }
+}
+
+fn saturation_labels(
+    mut const_labels: HashMap<String, String>,
+    usage: &str,
+) -> HashMap<String, String> {
+    const_labels.insert("use".to_string(), usage.to_string());
+    const_labels
+}
+
+/// The number of queries that may use a pool concurrently. For pools
+/// whose size is given as a range, the limit starts at the minimum size
+/// and is adjusted between the minimum and the maximum size: it grows when
+/// queries have to wait for a permit while connections are readily
+/// available, and shrinks when queries do not have to wait, or when
+/// everything that uses the pool, including writes, has to wait for
+/// connections. That way, queries can not starve the other users of the
+/// pool of connections.
+struct QueryLimit {
+    min: usize,
+    max: usize,
+    /// The current limit and when we last changed it
+    state: Mutex<(usize, Instant)>,
+    limit_gauge: Gauge,
+    saturation_gauge: Gauge,
+}
+
+impl QueryLimit {
+    fn new(
+        min: u32,
+        max: u32,
+        registry: &Arc<dyn MetricsRegistry>,
+        const_labels: HashMap<String, String>,
+    ) -> Self {
+        let limit_gauge = registry
+            .global_gauge(
+                "store_connection_pool_query_limit",
+                "The number of queries that may use the connection pool concurrently",
+                const_labels.clone(),
+            )
+            .expect("failed to create `store_connection_pool_query_limit` gauge");
+        let saturation_gauge = registry
+            .global_gauge(
+                "store_connection_pool_saturation",
+                "The fraction of the connection pool that is in use",
+                saturation_labels(const_labels, "queries"),
+            )
+            .expect("failed to create `store_connection_pool_saturation` gauge");
+        limit_gauge.set(min as f64);
+        QueryLimit {
+            min: min as usize,
+            max: max as usize,
+            state: Mutex::new((min as usize, Instant::now())),
+            limit_gauge,
+            saturation_gauge,
+        }
+    }
+
+    /// Update the saturation metric for `semaphore`, and change the
+    /// limit if the wait times for query permits and connections warrant
+    /// it
+    fn update(&self, semaphore: &Semaphore, permit_wait: &MovingStats, conn_wait: &MovingStats) {
+        let mut state = self.state.lock().unwrap();
+        let (limit, last_resize) = &mut *state;
+
+        let permits = *limit + ENV_VARS.store.extra_query_permits;
+        let in_use = permits.saturating_sub(semaphore.available_permits());
+        self.saturation_gauge
+            .set(in_use as f64 / permits.max(1) as f64);
+
+        if self.min == self.max || last_resize.elapsed() < QUERY_LIMIT_RESIZE_INTERVAL {
+            return;
+        }
+        *last_resize = Instant::now();
+
+        let grow_wait = ENV_VARS.store.pool_grow_wait;
+        let permit_wait = permit_wait.average().unwrap_or_default();
+        let conn_wait = conn_wait.average().unwrap_or_default();
+        let step = ((self.max - self.min) / 10).max(1);
+        if permit_wait > grow_wait && conn_wait <= grow_wait && *limit < self.max {
+            let step = step.min(self.max - *limit);
+            semaphore.add_permits(step);
+            *limit += step;
+        } else if (conn_wait > grow_wait || permit_wait < grow_wait / 4) && *limit > self.min {
+            // We can only take away permits that nobody is using right now
+            let step = step.min(*limit - self.min) as u32;
+            if let Ok(permits) = semaphore.try_acquire_many(step) {
+                permits.forget();
+                *limit -= step as usize;
+            }
+        }
+        self.limit_gauge.set(*limit as f64);
     }
 }
 
@@ -672,6 +849,10 @@ pub struct PoolInner {
     // explicitly close connections to foreign servers when a connection is
     // returned to the pool.
     fdw_pool: Option<Pool<ConnectionManager<PgConnection>>>,
+    // A separate pool for connections that write entities with `COPY`.
+    // Connections are only opened once deployments write far enough
+    // behind the chain head, and they are closed when idle
+    copy_pool: Pool<CopyConnectionManager>,
     limiter: Arc<Semaphore>,
     postgres_url: String,
     pub(crate) wait_stats: PoolWaitStats,
@@ -681,6 +862,7 @@ pub struct PoolInner {
     // that waiting queries consume few resources. Still this is placed here because the semaphore
     // is sized acording to the DB connection pool size.
     query_semaphore: Arc<tokio::sync::Semaphore>,
+    query_limit: Arc<QueryLimit>,
     semaphore_wait_stats: Arc<RwLock<MovingStats>>,
     semaphore_wait_gauge: Box<Gauge>,
 }
@@ -690,6 +872,7 @@ impl PoolInner {
         shard_name: &str,
         pool_name: &str,
         postgres_url: String,
+        min_pool_size: u32,
         pool_size: u32,
         fdw_pool_size: Option<u32>,
         logger: &Logger,
@@ -704,13 +887,7 @@ impl PoolInner {
             map.insert("shard".to_string(), shard_name.to_owned());
             map
         };
-        let error_counter = registry
-            .global_counter(
-                "store_connection_error_count",
-                "The number of Postgres connections errors",
-                const_labels.clone(),
-            )
-            .expect("failed to create `store_connection_error_count` counter");
+        let error_counter = error_counter_for(&registry, const_labels.clone());
         let error_handler = Box::new(ErrorHandler::new(
             logger_pool.clone(),
             error_counter,
@@ -723,6 +900,7 @@ impl PoolInner {
             wait_stats.clone(),
             const_labels.clone(),
             state_tracker,
+            pool_size,
         ));
 
         // Connect to Postgres
@@ -732,7 +910,7 @@ impl PoolInner {
             .event_handler(event_handler.clone())
             .connection_timeout(ENV_VARS.store.connection_timeout)
             .max_size(pool_size)
-            .min_idle(ENV_VARS.store.connection_min_idle)
+            .min_idle(ENV_VARS.store.connection_min_idle.or(Some(min_pool_size)))
             .idle_timeout(Some(ENV_VARS.store.connection_idle_timeout));
         let pool = builder.build_unchecked(conn_manager);
         let fdw_pool = fdw_pool_size.map(|pool_size| {
@@ -747,6 +925,38 @@ impl PoolInner {
             builder.build_unchecked(conn_manager)
         });
 
+        let copy_pool = {
+            let const_labels = {
+                let mut map = const_labels.clone();
+                map.insert("pool".to_owned(), format!("{}_copy", pool_name));
+                map
+            };
+            let error_handler = ErrorHandler::new(
+                logger_pool.clone(),
+                error_counter_for(&registry, const_labels.clone()),
+                PoolStateTracker::new(),
+            );
+            let event_handler = EventHandler::new(
+                logger_pool.clone(),
+                registry.cheap_clone(),
+                Arc::new(RwLock::new(MovingStats::default())),
+                const_labels,
+                PoolStateTracker::new(),
+                ENV_VARS.store.bulk_write_pool_size,
+            );
+            let conn_manager = CopyConnectionManager {
+                postgres_url: postgres_url.clone(),
+            };
+            Pool::builder()
+                .error_handler(Box::new(error_handler))
+                .event_handler(Box::new(event_handler))
+                .connection_timeout(ENV_VARS.store.connection_timeout)
+                .max_size(ENV_VARS.store.bulk_write_pool_size)
+                .min_idle(Some(0))
+                .idle_timeout(Some(FDW_IDLE_TIMEOUT))
+                .build_unchecked(conn_manager)
+        };
+
         let limiter = Arc::new(Semaphore::new(pool_size as usize));
         info!(logger_store, "Pool successfully connected to Postgres");
 
@@ -754,10 +964,16 @@ impl PoolInner {
             .new_gauge(
                 "query_semaphore_wait_ms",
                 "Moving average of time spent on waiting for postgres query semaphore",
-                const_labels,
+                const_labels.clone(),
             )
             .expect("failed to create `query_effort_ms` counter");
-        let max_concurrent_queries = pool_size as usize + ENV_VARS.store.extra_query_permits;
+        let query_limit = Arc::new(QueryLimit::new(
+            min_pool_size,
+            pool_size,
+            &registry,
+            const_labels,
+        ));
+        let max_concurrent_queries = min_pool_size as usize + ENV_VARS.store.extra_query_permits;
         let query_semaphore = Arc::new(tokio::sync::Semaphore::new(max_concurrent_queries));
         PoolInner {
             logger: logger_pool,
@@ -766,10 +982,12 @@ impl PoolInner {
             postgres_url,
             pool,
             fdw_pool,
+            copy_pool,
             limiter,
             wait_stats,
             semaphore_wait_stats: Arc::new(RwLock::new(MovingStats::default())),
             query_semaphore,
+            query_limit,
             semaphore_wait_gauge,
         }
     }
@@ -916,6 +1134,12 @@ impl PoolInner {
         }
     }
 
+    /// Get a connection for writing entities with `COPY`. Waits for
+    /// `GRAPH_STORE_CONNECTION_TIMEOUT` if all of them are in use
+    pub fn copy_client(&self) -> Result<CopyClient, StoreError> {
+        self.copy_pool.get().map_err(|e| e.into())
+    }
+
     pub fn connection_detail(&self) -> Result<ForeignServer, StoreError> {
         ForeignServer::new(self.shard.clone(), &self.postgres_url).map_err(|e| e.into())
     }
@@ -965,10 +1189,13 @@ impl PoolInner {
     pub(crate) async fn query_permit(&self) -> tokio::sync::OwnedSemaphorePermit {
         let start = Instant::now();
         let permit = self.query_semaphore.cheap_clone().acquire_owned().await;
-        self.semaphore_wait_stats
-            .write()
-            .unwrap()
-            .add_and_register(start.elapsed(), &self.semaphore_wait_gauge);
+        let mut permit_wait = self.semaphore_wait_stats.write().unwrap();
+        permit_wait.add_and_register(start.elapsed(), &self.semaphore_wait_gauge);
+        self.query_limit.update(
+            &self.query_semaphore,
+            &permit_wait,
+            &self.wait_stats.read().unwrap(),
+        );
         permit.unwrap()
     }
 
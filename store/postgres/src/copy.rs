@@ -18,11 +18,12 @@ use std::{
 };
 
 use diesel::{
+    delete,
     dsl::sql,
     insert_into,
     r2d2::{ConnectionManager, PooledConnection},
     select, sql_query,
-    sql_types::Integer,
+    sql_types::{BigInt, Integer},
     update, Connection as _, ExpressionMethods, OptionalExtension, PgConnection, QueryDsl,
     RunQueryDsl,
 };
@@ -34,6 +35,7 @@ use graph::{
 
 use crate::{
     advisory_lock,
+    block_range::{BLOCK_COLUMN, BLOCK_RANGE_COLUMN},
     primary::{DeploymentId, Site},
 };
 use crate::{connection_pool::ConnectionPool, relational::Layout};
@@ -97,6 +99,17 @@ struct CopyState {
     tables: Vec<TableState>,
 }
 
+/// Forget about any copy into `dst`, including a finished one, so that
+/// the next copy into `dst` starts from scratch
+pub(crate) fn reset(conn: &PgConnection, dst: &Site) -> Result<(), StoreError> {
+    use copy_state as cs;
+    use copy_table_state as cts;
+
+    delete(cts::table.filter(cts::dst.eq(dst.id))).execute(conn)?;
+    delete(cs::table.filter(cs::dst.eq(dst.id))).execute(conn)?;
+    Ok(())
+}
+
 impl CopyState {
     fn new(
         conn: &PgConnection,
@@ -466,6 +479,11 @@ impl TableState {
         // but do not go over target_vid
         let first_batch = self.next_vid == 0;
         let last_vid = (self.next_vid + self.batch_size - 1).min(self.target_vid);
+        if let Some(size) = self.dst.partition_blocks {
+            let blocks = partition_starts(conn, &self.src, size, self.next_vid, last_vid)?;
+            self.dst
+                .create_partitions(conn, &self.dst_site.namespace, blocks)?;
+        }
         rq::CopyEntityBatchQuery::new(self.dst.as_ref(), &self.src, self.next_vid, last_vid)?
             .execute(conn)?;
 
@@ -491,6 +509,44 @@ impl TableState {
     }
 }
 
+/// Return the first block of each partition of a table partitioned into
+/// partitions of `size` blocks that the versions in `src` with `first_vid
+/// <= vid <= last_vid` will be copied into
+fn partition_starts(
+    conn: &PgConnection,
+    src: &Table,
+    size: BlockNumber,
+    first_vid: i64,
+    last_vid: i64,
+) -> Result<Vec<BlockNumber>, StoreError> {
+    #[derive(QueryableByName)]
+    struct Start {
+        #[sql_type = "Integer"]
+        block: BlockNumber,
+    }
+
+    let block = if src.immutable {
+        BLOCK_COLUMN.to_string()
+    } else {
+        format!("lower({})", BLOCK_RANGE_COLUMN)
+    };
+    let query = format!(
+        "select distinct {block} / $1 * $1 as block from {src} \
+          where vid >= $2 and vid <= $3",
+        block = block,
+        src = src.qualified_name
+    );
+    let starts = sql_query(query)
+        .bind::<Integer, _>(size)
+        .bind::<BigInt, _>(first_vid)
+        .bind::<BigInt, _>(last_vid)
+        .load::<Start>(conn)?
+        .into_iter()
+        .map(|start| start.block)
+        .collect();
+    Ok(starts)
+}
+
 // A helper for logging progress while data is being copied
 struct CopyProgress<'a> {
     logger: &'a Logger,
@@ -633,7 +689,15 @@ impl Connection {
                 if table.is_cancelled(&self.conn)? {
                     return Ok(Status::Cancelled);
                 }
-                let status = self.transaction(|conn| table.copy_batch(conn))?;
+                let status = match self.transaction(|conn| table.copy_batch(conn)) {
+                    Ok(status) => status,
+                    Err(e) => {
+                        // Partitions that were created in the transaction
+                        // are gone
+                        table.dst.forget_partitions();
+                        return Err(e);
+                    }
+                };
                 if status == Status::Cancelled {
                     return Ok(status);
                 }
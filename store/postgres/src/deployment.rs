@@ -23,14 +23,17 @@ use graph::data::subgraph::{
 };
 use graph::prelude::{
     anyhow, bigdecimal::ToPrimitive, hex, web3::types::H256, BigDecimal, BlockNumber, BlockPtr,
-    DeploymentHash, DeploymentState, Schema, StoreError,
+    DeploymentHash, DeploymentState, Schema, StoreError, BLOCK_NUMBER_MAX, ENV_VARS,
 };
 use stable_hash::crypto::SetHasher;
 use std::{collections::BTreeSet, convert::TryFrom, ops::Bound};
 use std::{str::FromStr, sync::Arc};
 
 use crate::connection_pool::ForeignServer;
-use crate::{block_range::BLOCK_RANGE_COLUMN, primary::Site};
+use crate::{
+    block_range::BLOCK_RANGE_COLUMN,
+    primary::{DeploymentId, Site},
+};
 use graph::constraint_violation;
 
 #[derive(DbEnum, Debug, Clone, Copy)]
@@ -85,6 +88,8 @@ table! {
         current_reorg_depth -> Integer,
         max_reorg_depth -> Integer,
         firehose_cursor -> Nullable<Text>,
+        earliest_block_number -> Integer,
+        indexes_deferred -> Bool,
     }
 }
 
@@ -111,6 +116,8 @@ table! {
         schema -> Text,
         graph_node_version_id -> Nullable<Integer>,
         use_bytea_prefix -> Bool,
+        history_blocks -> Integer,
+        partition_blocks -> Nullable<Integer>,
     }
 }
 
@@ -220,15 +227,32 @@ pub fn debug_fork(
     }
 }
 
-pub fn schema(conn: &PgConnection, site: &Site) -> Result<(Schema, bool), StoreError> {
+/// Return the schema of the deployment, whether it indexes `bytea` columns
+/// by prefix, and how many blocks each partition of its immutable entity
+/// tables holds, if they are partitioned
+pub fn schema(
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<(Schema, bool, Option<BlockNumber>), StoreError> {
     use subgraph_manifest as sm;
-    let (s, use_bytea_prefix) = sm::table
-        .select((sm::schema, sm::use_bytea_prefix))
+    let (s, use_bytea_prefix, partition_blocks) = sm::table
+        .select((sm::schema, sm::use_bytea_prefix, sm::partition_blocks))
         .filter(sm::id.eq(site.id))
-        .first::<(String, bool)>(conn)?;
+        .first::<(String, bool, Option<BlockNumber>)>(conn)?;
     Schema::parse(s.as_str(), site.deployment.clone())
         .map_err(StoreError::Unknown)
-        .map(|schema| (schema, use_bytea_prefix))
+        .map(|schema| (schema, use_bytea_prefix, partition_blocks))
+}
+
+/// Replace the schema of the deployment with `schema`. The tables for the
+/// deployment must already match `schema`
+pub fn set_schema(conn: &PgConnection, site: &Site, schema: &Schema) -> Result<(), StoreError> {
+    use subgraph_manifest as sm;
+
+    update(sm::table.filter(sm::id.eq(site.id)))
+        .set(sm::schema.eq(schema.document.to_string()))
+        .execute(conn)?;
+    Ok(())
 }
 
 pub fn manifest_info(
@@ -443,6 +467,45 @@ pub fn revert_block_ptr(
         .map_err(|e| e.into())
 }
 
+/// Move the graft point of `site` to `ptr`. This is needed when a
+/// deployment is rewound to before its graft point, since from then on all
+/// its data up to `ptr` comes from the graft base
+pub fn set_graft_block(conn: &PgConnection, site: &Site, ptr: &BlockPtr) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    // Work around a Diesel issue with serializing BigDecimals to numeric
+    let number = format!("{}::numeric", ptr.number);
+
+    update(d::table.filter(d::id.eq(site.id)))
+        .set((
+            d::graft_block_hash.eq(ptr.hash_slice()),
+            d::graft_block_number.eq(sql(&number)),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Move the graft point of `site` to `ptr` and reset the deployment as if
+/// it had never been started, so that the data of the graft base up to
+/// `ptr` is copied again the next time the deployment is started. The
+/// caller is responsible for removing the data of the deployment
+pub fn reset_graft(conn: &PgConnection, site: &Site, ptr: &BlockPtr) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    set_graft_block(conn, site, ptr)?;
+    update(d::table.filter(d::id.eq(site.id)))
+        .set((
+            d::latest_ethereum_block_hash.eq(None::<Vec<u8>>),
+            d::latest_ethereum_block_number.eq(None::<BigDecimal>),
+            d::firehose_cursor.eq(None::<String>),
+            d::entity_count.eq(sql("0")),
+            d::synced.eq(false),
+            d::earliest_block_number.eq(0),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
 pub fn block_ptr(conn: &PgConnection, id: &DeploymentHash) -> Result<Option<BlockPtr>, StoreError> {
     use subgraph_deployment as d;
 
@@ -541,15 +604,22 @@ pub fn state(conn: &PgConnection, id: DeploymentHash) -> Result<DeploymentState,
             d::reorg_count,
             d::max_reorg_depth,
             d::latest_ethereum_block_number,
+            d::earliest_block_number,
         ))
-        .first::<(String, i32, i32, Option<BigDecimal>)>(conn)
+        .first::<(String, i32, i32, Option<BigDecimal>, i32)>(conn)
         .optional()?
     {
         None => Err(StoreError::QueryExecutionError(format!(
             "No data found for subgraph {}",
             id
         ))),
-        Some((_, reorg_count, max_reorg_depth, latest_ethereum_block_number)) => {
+        Some((
+            _,
+            reorg_count,
+            max_reorg_depth,
+            latest_ethereum_block_number,
+            earliest_block_number,
+        )) => {
             let reorg_count = convert_to_u32(Some(reorg_count), "reorg_count", id.as_str())?;
             let max_reorg_depth =
                 convert_to_u32(Some(max_reorg_depth), "max_reorg_depth", id.as_str())?;
@@ -561,23 +631,26 @@ pub fn state(conn: &PgConnection, id: DeploymentHash) -> Result<DeploymentState,
                 reorg_count,
                 max_reorg_depth,
                 latest_ethereum_block_number,
+                earliest_block_number,
             })
         }
     }
 }
 
 /// Mark the deployment `id` as synced
-pub fn set_synced(conn: &PgConnection, id: &DeploymentHash) -> Result<(), StoreError> {
+/// Mark the deployment as synced. Returns `true` if the deployment was not
+/// synced before
+pub fn set_synced(conn: &PgConnection, id: &DeploymentHash) -> Result<bool, StoreError> {
     use subgraph_deployment as d;
 
-    update(
+    let count = update(
         d::table
             .filter(d::deployment.eq(id.as_str()))
             .filter(d::synced.eq(false)),
     )
     .set(d::synced.eq(true))
     .execute(conn)?;
-    Ok(())
+    Ok(count > 0)
 }
 
 /// Returns `true` if the deployment (as identified by `site.id`)
@@ -915,6 +988,7 @@ pub fn create_deployment(
                 repository,
                 features,
                 schema,
+                history_blocks,
             },
         earliest_block,
         graft_base,
@@ -939,6 +1013,8 @@ pub fn create_deployment(
         d::graft_block_hash.eq(b(&graft_block)),
         d::graft_block_number.eq(n(&graft_block)),
         d::debug_fork.eq(debug_fork.as_ref().map(|s| s.as_str())),
+        // This must agree with what `Catalog::for_creation` uses
+        d::indexes_deferred.eq(ENV_VARS.store.defer_indexes),
     );
 
     let graph_node_version_id = GraphNodeVersion::create_or_get(conn)?;
@@ -951,6 +1027,9 @@ pub fn create_deployment(
         m::features.eq(features),
         m::schema.eq(schema),
         m::graph_node_version_id.eq(graph_node_version_id),
+        m::history_blocks.eq(history_blocks),
+        // This must agree with what `Catalog::for_creation` uses
+        m::partition_blocks.eq(ENV_VARS.store.partition_blocks),
     );
 
     if exists && replace {
@@ -1015,6 +1094,99 @@ pub fn update_entity_count(
     Ok(())
 }
 
+/// The earliest block for which the deployment still has all its data.
+/// History before that block has been pruned
+pub fn earliest_block(conn: &PgConnection, site: &Site) -> Result<BlockNumber, StoreError> {
+    use subgraph_deployment as d;
+
+    Ok(d::table
+        .filter(d::id.eq(site.id))
+        .select(d::earliest_block_number)
+        .first::<BlockNumber>(conn)?)
+}
+
+pub fn set_earliest_block(
+    conn: &PgConnection,
+    site: &Site,
+    earliest_block: BlockNumber,
+) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    update(d::table.filter(d::id.eq(site.id)))
+        .set(d::earliest_block_number.eq(earliest_block))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Find the deployments that only keep a limited number of blocks of
+/// history and that have history that needs to be pruned. Deployments never
+/// keep fewer than `min_history_blocks` blocks. Return the id of each
+/// deployment together with the earliest block that it needs to keep
+pub fn prunable(
+    conn: &PgConnection,
+    min_history_blocks: BlockNumber,
+) -> Result<Vec<(DeploymentId, BlockNumber)>, StoreError> {
+    #[derive(QueryableByName)]
+    struct Prunable {
+        #[sql_type = "Integer"]
+        id: DeploymentId,
+        #[sql_type = "Integer"]
+        earliest_block: BlockNumber,
+    }
+
+    const QUERY: &str = "
+        select d.id,
+               d.latest_ethereum_block_number::int4
+                 - greatest(m.history_blocks, $1) as earliest_block
+          from subgraphs.subgraph_deployment d,
+               subgraphs.subgraph_manifest m
+         where d.id = m.id
+           and m.history_blocks < $2
+           and d.latest_ethereum_block_number is not null
+           and d.latest_ethereum_block_number::int4
+                 - greatest(m.history_blocks, $1) > d.earliest_block_number";
+
+    Ok(sql_query(QUERY)
+        .bind::<Integer, _>(min_history_blocks)
+        .bind::<Integer, _>(BLOCK_NUMBER_MAX)
+        .load::<Prunable>(conn)?
+        .into_iter()
+        .map(|p| (p.id, p.earliest_block))
+        .collect())
+}
+
+/// Find the deployments that have caught up with the chain head but whose
+/// attribute indexes have not been built yet
+pub fn deferred_indexes(conn: &PgConnection) -> Result<Vec<DeploymentId>, StoreError> {
+    use subgraph_deployment as d;
+
+    Ok(d::table
+        .filter(d::indexes_deferred)
+        .filter(d::synced)
+        .select(d::id)
+        .load::<DeploymentId>(conn)?)
+}
+
+/// Whether the attribute indexes of the deployment still need to be built
+pub fn indexes_deferred(conn: &PgConnection, site: &Site) -> Result<bool, StoreError> {
+    use subgraph_deployment as d;
+
+    Ok(d::table
+        .filter(d::id.eq(site.id))
+        .select(d::indexes_deferred)
+        .first::<bool>(conn)?)
+}
+
+/// Record that all attribute indexes of the deployment have been built
+pub fn set_indexes_built(conn: &PgConnection, site: &Site) -> Result<(), StoreError> {
+    use subgraph_deployment as d;
+
+    update(d::table.filter(d::id.eq(site.id)))
+        .set(d::indexes_deferred.eq(false))
+        .execute(conn)?;
+    Ok(())
+}
+
 /// Set the deployment's entity count to whatever `full_count_query` produces
 pub fn set_entity_count(
     conn: &PgConnection,
@@ -15,36 +15,53 @@ use rand::{seq::SliceRandom, thread_rng};
 use std::borrow::Cow;
 use std::collections::{BTreeMap, HashMap};
 use std::convert::Into;
+use std::io::Write;
 use std::iter::FromIterator;
 use std::ops::Bound;
 use std::ops::Deref;
+use std::path::Path;
 use std::sync::{atomic::AtomicUsize, Arc, Mutex};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use graph::components::store::EntityCollection;
+use graph::components::store::{EntityCollection, PruneReporter};
 use graph::components::subgraph::ProofOfIndexingFinisher;
 use graph::constraint_violation;
-use graph::data::subgraph::schema::{DeploymentCreate, SubgraphError, POI_OBJECT};
+use graph::data::subgraph::schema::{
+    DeploymentCreate, MappingLog, QuarantinedDataSource, SkippedBlock, SubgraphError, POI_OBJECT,
+};
 use graph::prelude::{
-    anyhow, debug, info, o, warn, web3, ApiSchema, AttributeNames, BlockNumber, BlockPtr,
-    CheapClone, DeploymentHash, DeploymentState, Entity, EntityKey, EntityModification,
-    EntityQuery, Error, Logger, QueryExecutionError, Schema, StopwatchMetrics, StoreError,
-    StoreEvent, UnfailOutcome, Value, BLOCK_NUMBER_MAX, ENV_VARS,
+    anyhow, debug, error, info, o, r, warn, web3, ApiSchema, AttributeNames, BlockNumber, BlockPtr,
+    CheapClone, DeploymentHash, DeploymentState, Entity, EntityAggregate, EntityChange, EntityKey,
+    EntityModification, EntityQuery, Error, Logger, QueryExecutionError, Schema, StopwatchMetrics,
+    StoreError, StoreEvent, UnfailOutcome, Value, BLOCK_NUMBER_MAX, ENV_VARS,
 };
+use graph::slog::Level;
 use graph_graphql::prelude::api_schema;
 use web3::types::Address;
 
+use crate::attribute_usage::{self, AttributeUsage, IndexSuggestion};
+use crate::backup::{self, BackupMetadata, ExportFormat};
 use crate::block_range::block_number;
-use crate::catalog;
+use crate::catalog::{self, IndexBuild};
+use crate::cdc;
+use crate::checksum::{self, Checksums};
 use crate::deployment;
 use crate::detail::ErrorDetail;
-use crate::relational::{Layout, LayoutCache, SqlName, Table};
+use crate::maintenance;
+use crate::mapping_log;
+use crate::quarantine;
+use crate::query_store::ExplainedQuery;
+use crate::relational::{attribute_index_method, Layout, LayoutCache, SqlName, Table};
 use crate::relational_queries::FromEntityData;
+use crate::skipped_block;
 use crate::{connection_pool::ConnectionPool, detail};
-use crate::{dynds, primary::Site};
+use crate::{
+    dynds,
+    primary::{DeploymentId, Site},
+};
 
 /// When connected to read replicas, this allows choosing which DB server to use for an operation.
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum ReplicaId {
     /// The main server has write and read access.
     Main,
@@ -72,6 +89,22 @@ pub(crate) struct SubgraphInfo {
     pub(crate) repository: Option<String>,
 }
 
+/// How far building the attribute indexes of a deployment that deferred
+/// them has progressed
+#[derive(Clone, Debug)]
+pub struct DeferredIndexStatus {
+    /// Whether some attribute indexes still need to be built
+    pub deferred: bool,
+    /// Whether the deployment has caught up with the chain head
+    pub synced: bool,
+    /// The number of attribute indexes the deployment should have
+    pub total: usize,
+    /// The attribute indexes that do not exist yet or are not valid
+    pub missing: Vec<String>,
+    /// The indexes that Postgres is building right now
+    pub builds: Vec<IndexBuild>,
+}
+
 pub struct StoreInner {
     logger: Logger,
 
@@ -93,6 +126,15 @@ pub struct StoreInner {
     /// hosts this because it lives long enough, but it is managed from
     /// the entities module
     pub(crate) layout_cache: LayoutCache,
+
+    /// The attributes that queries filtered and sorted by since they were
+    /// last written to the database
+    attribute_usage: AttributeUsage,
+
+    /// Whether a replica's copy of a deployment was recently found to be
+    /// close enough to the main database to be queried, and when we
+    /// checked that
+    replica_current: Mutex<HashMap<(ReplicaId, DeploymentId), (Instant, bool)>>,
 }
 
 /// Storage of the data for individual deployments. Each `DeploymentStore`
@@ -149,6 +191,8 @@ impl DeploymentStore {
             conn_round_robin_counter: AtomicUsize::new(0),
             subgraph_cache: Mutex::new(LruCache::with_capacity(100)),
             layout_cache: LayoutCache::new(ENV_VARS.store.query_stats_refresh_interval),
+            attribute_usage: AttributeUsage::default(),
+            replica_current: Mutex::new(HashMap::new()),
         };
 
         DeploymentStore(Arc::new(store))
@@ -201,6 +245,61 @@ impl DeploymentStore {
         })
     }
 
+    /// Return the reasons why a deployment with `schema` can not be
+    /// grafted onto `base`; see `Layout::can_copy_from`
+    pub(crate) fn graft_incompatibilities(
+        &self,
+        base: Arc<Site>,
+        schema: &Schema,
+    ) -> Result<Vec<String>, StoreError> {
+        let conn = self.get_conn()?;
+        let base_layout = self.layout(&conn, base.cheap_clone())?;
+        // The layout for `schema` is only used for the comparison and
+        // never created in the database; its site therefore does not matter
+        let catalog = catalog::Catalog::for_creation(base.cheap_clone());
+        let layout = Layout::new(base, schema, catalog)?;
+        Ok(layout.can_copy_from(&base_layout))
+    }
+
+    /// Change the schema of the deployment to `schema` without reindexing
+    /// it. That is only possible if `schema` only adds to the current
+    /// schema; see `Layout::additive_ddl`. Return the reasons why the
+    /// schema can not be changed; if there are none, the deployment now
+    /// uses `schema`
+    pub(crate) fn upgrade_schema(
+        &self,
+        site: Arc<Site>,
+        schema: &Schema,
+    ) -> Result<Vec<String>, StoreError> {
+        let conn = self.get_conn()?;
+        let errors = conn.transaction(|| -> Result<_, StoreError> {
+            let (base_schema, use_bytea_prefix, partition_blocks) =
+                deployment::schema(&conn, &site)?;
+            let catalog = catalog::Catalog::load(
+                &conn,
+                site.cheap_clone(),
+                use_bytea_prefix,
+                partition_blocks,
+            )?;
+            let base = Layout::new(site.cheap_clone(), &base_schema, catalog.clone())?;
+            let layout = Layout::new(site.cheap_clone(), schema, catalog)?;
+            match layout.additive_ddl(&base) {
+                Ok(ddl) => {
+                    conn.batch_execute(&ddl)?;
+                    deployment::set_schema(&conn, &site, schema)?;
+                    Ok(vec![])
+                }
+                Err(errors) => Ok(errors),
+            }
+        })?;
+
+        if errors.is_empty() {
+            self.layout_cache.remove(&site);
+            self.subgraph_cache.lock().unwrap().remove(&site.deployment);
+        }
+        Ok(errors)
+    }
+
     pub(crate) fn load_deployment(
         &self,
         site: &Site,
@@ -209,6 +308,13 @@ impl DeploymentStore {
         detail::deployment_entity(&conn, site)
     }
 
+    /// The number of the last block the deployment `site` has processed,
+    /// or `None` if it has not processed any blocks yet
+    pub(crate) fn head_number(&self, site: &Site) -> Result<Option<BlockNumber>, StoreError> {
+        let conn = self.get_conn()?;
+        Ok(deployment::block_ptr(&conn, &site.deployment)?.map(|ptr| ptr.number))
+    }
+
     // Remove the data and metadata for the deployment `site`. This operation
     // is not reversible
     pub(crate) fn drop_deployment(&self, site: &Site) -> Result<(), StoreError> {
@@ -226,6 +332,17 @@ impl DeploymentStore {
         site: Arc<Site>,
         query: EntityQuery,
     ) -> Result<Vec<T>, QueryExecutionError> {
+        // Writing the counts needs a runtime, which some tests do not have
+        if self.attribute_usage.record(&site, &query)
+            && tokio::runtime::Handle::try_current().is_ok()
+        {
+            let store = self.clone();
+            graph::spawn(async move {
+                if let Err(e) = store.flush_attribute_usage().await {
+                    warn!(store.logger, "Failed to write attribute usage"; "error" => e.to_string());
+                }
+            });
+        }
         let layout = self.layout(conn, site)?;
 
         let logger = query.logger.unwrap_or_else(|| self.logger.clone());
@@ -241,6 +358,71 @@ impl DeploymentStore {
         )
     }
 
+    pub(crate) fn explain_query(
+        &self,
+        conn: &PgConnection,
+        site: Arc<Site>,
+        query: EntityQuery,
+    ) -> Result<ExplainedQuery, QueryExecutionError> {
+        let layout = self.layout(conn, site)?;
+
+        layout.explain(
+            conn,
+            query.collection,
+            query.filter,
+            query.order,
+            query.range,
+            query.block,
+            query.query_id,
+        )
+    }
+
+    pub(crate) fn execute_cursor_query<T: FromEntityData>(
+        &self,
+        conn: &PgConnection,
+        site: Arc<Site>,
+        query: EntityQuery,
+        batch_size: usize,
+        sink: &mut dyn FnMut(Vec<T>) -> bool,
+    ) -> Result<(), QueryExecutionError> {
+        let layout = self.layout(conn, site)?;
+
+        let logger = query.logger.unwrap_or_else(|| self.logger.clone());
+        layout.query_cursor(
+            &logger,
+            conn,
+            query.collection,
+            query.filter,
+            query.order,
+            query.range,
+            query.block,
+            query.query_id,
+            batch_size,
+            sink,
+        )
+    }
+
+    pub(crate) fn execute_aggregate_query(
+        &self,
+        conn: &PgConnection,
+        site: Arc<Site>,
+        query: EntityQuery,
+        aggregates: &[EntityAggregate],
+    ) -> Result<Vec<r::Value>, QueryExecutionError> {
+        let layout = self.layout(conn, site)?;
+
+        let logger = query.logger.unwrap_or_else(|| self.logger.clone());
+        layout.aggregate(
+            &logger,
+            conn,
+            query.collection,
+            query.filter,
+            aggregates,
+            query.block,
+            query.query_id,
+        )
+    }
+
     fn check_interface_entity_uniqueness(
         &self,
         conn: &PgConnection,
@@ -294,6 +476,7 @@ impl DeploymentStore {
         mods: &[EntityModification],
         ptr: &BlockPtr,
         stopwatch: &StopwatchMetrics,
+        mut copy: Option<&mut postgres::Client>,
     ) -> Result<i32, StoreError> {
         use EntityModification::*;
         let mut count = 0;
@@ -328,9 +511,15 @@ impl DeploymentStore {
         // Apply modification groups.
         // Inserts:
         for (entity_type, mut entities) in inserts.into_iter() {
-            count +=
-                self.insert_entities(&entity_type, &mut entities, conn, layout, ptr, stopwatch)?
-                    as i32
+            count += self.insert_entities(
+                &entity_type,
+                &mut entities,
+                conn,
+                layout,
+                ptr,
+                stopwatch,
+                copy.as_deref_mut(),
+            )? as i32
         }
 
         // Overwrites:
@@ -361,6 +550,7 @@ impl DeploymentStore {
         layout: &'a Layout,
         ptr: &BlockPtr,
         stopwatch: &StopwatchMetrics,
+        copy: Option<&mut postgres::Client>,
     ) -> Result<usize, StoreError> {
         let section = stopwatch.start_section("check_interface_entity_uniqueness");
         for (key, _) in data.iter() {
@@ -370,7 +560,7 @@ impl DeploymentStore {
         section.end();
 
         let _section = stopwatch.start_section("apply_entity_modifications_insert");
-        layout.insert(conn, entity_type, data, block_number(ptr), stopwatch)
+        layout.insert(conn, entity_type, data, block_number(ptr), stopwatch, copy)
     }
 
     fn overwrite_entities<'a>(
@@ -609,7 +799,9 @@ impl DeploymentStore {
         deployment::exists_and_synced(&conn, id.as_str())
     }
 
-    pub(crate) fn deployment_synced(&self, id: &DeploymentHash) -> Result<(), StoreError> {
+    /// Mark the deployment as synced and return `true` if it was not synced
+    /// before
+    pub(crate) fn deployment_synced(&self, id: &DeploymentHash) -> Result<bool, StoreError> {
         let conn = self.get_conn()?;
         conn.transaction(|| deployment::set_synced(&conn, id))
     }
@@ -648,6 +840,50 @@ impl DeploymentStore {
         Ok(())
     }
 
+    /// Remove changes that are older than `retention` blocks from the
+    /// CDC change tables of all deployments in this shard
+    pub(crate) async fn prune_changes(&self, retention: BlockNumber) -> Result<usize, StoreError> {
+        self.with_conn(move |conn, _| cdc::prune(conn, retention).map_err(Into::into))
+            .await
+    }
+
+    /// The deployments in this shard that have history that needs to be
+    /// pruned, together with the earliest block each of them needs to keep
+    pub(crate) async fn prunable(&self) -> Result<Vec<(DeploymentId, BlockNumber)>, StoreError> {
+        self.with_conn(|conn, _| {
+            deployment::prunable(conn, ENV_VARS.store.min_history_blocks).map_err(Into::into)
+        })
+        .await
+    }
+
+    /// Remove all entity versions of `site` that are not needed to answer
+    /// queries at `earliest_block` or later. Queries for earlier blocks are
+    /// rejected from now on. Return the number of versions that were
+    /// removed
+    pub(crate) async fn prune(
+        &self,
+        site: Arc<Site>,
+        earliest_block: BlockNumber,
+        mut reporter: Box<dyn PruneReporter>,
+    ) -> Result<usize, StoreError> {
+        let store = self.clone();
+        self.with_conn(move |conn, _| {
+            let layout = store.layout(conn, site.clone())?;
+            // Stop queries from using the blocks we are about to prune
+            // before we remove any data
+            deployment::set_earliest_block(conn, &site, earliest_block)?;
+            layout
+                .prune(
+                    conn,
+                    earliest_block,
+                    ENV_VARS.store.prune_batch_size,
+                    reporter.as_mut(),
+                )
+                .map_err(Into::into)
+        })
+        .await
+    }
+
     pub(crate) async fn vacuum(&self) -> Result<(), StoreError> {
         self.with_conn(|conn, _| {
             conn.batch_execute("vacuum (analyze) subgraphs.subgraph_deployment")?;
@@ -656,6 +892,184 @@ impl DeploymentStore {
         .await
     }
 
+    /// Statistics about the dead and modified rows of all entity tables in
+    /// this shard
+    pub(crate) async fn table_stats(&self) -> Result<Vec<maintenance::TableStats>, StoreError> {
+        self.with_conn(|conn, _| maintenance::table_stats(conn).map_err(Into::into))
+            .await
+    }
+
+    /// Vacuum or analyze the entity table `namespace.name`
+    pub(crate) async fn maintain_table(
+        &self,
+        op: maintenance::Operation,
+        namespace: String,
+        name: String,
+    ) -> Result<(), StoreError> {
+        self.with_conn(move |conn, _| {
+            maintenance::maintain(conn, op, &namespace, &name).map_err(Into::into)
+        })
+        .await
+    }
+
+    /// The deployments in this shard that have caught up with the chain head
+    /// but still need their attribute indexes built
+    pub(crate) async fn deferred_indexes(&self) -> Result<Vec<DeploymentId>, StoreError> {
+        self.with_conn(|conn, _| deployment::deferred_indexes(conn).map_err(Into::into))
+            .await
+    }
+
+    /// Build the attribute indexes for `site` that were left out when the
+    /// deployment was created. Stop once `deadline` has passed, if there is
+    /// one, and return whether all indexes have been built. Indexes whose
+    /// concurrent build failed are dropped so that the next attempt builds
+    /// them again
+    pub(crate) async fn build_deferred_indexes(
+        &self,
+        site: Arc<Site>,
+        deadline: Option<Instant>,
+    ) -> Result<bool, StoreError> {
+        let store = self.clone();
+        self.with_conn(move |conn, _| {
+            let layout = store.layout(conn, site.clone())?;
+            let ddl = layout.attribute_index_ddl().map_err(|_| {
+                StoreError::Unknown(anyhow!("failed to generate DDL for attribute indexes"))
+            })?;
+            for stmt in ddl {
+                if deadline.map_or(false, |deadline| Instant::now() > deadline) {
+                    return Ok(false);
+                }
+                // Each statement must run on its own since Postgres does not
+                // build indexes concurrently inside a transaction
+                conn.batch_execute(&stmt)?;
+            }
+
+            let invalid = catalog::invalid_indexes(conn, &site.namespace)?;
+            if !invalid.is_empty() {
+                for index in invalid {
+                    catalog::drop_index(conn, site.namespace.as_str(), &index)?;
+                }
+                return Ok(false);
+            }
+
+            deployment::set_indexes_built(conn, &site)?;
+            Ok(true)
+        })
+        .await
+    }
+
+    /// How far building the deferred attribute indexes for `site` has
+    /// progressed
+    pub(crate) async fn deferred_index_status(
+        &self,
+        site: Arc<Site>,
+    ) -> Result<DeferredIndexStatus, StoreError> {
+        let store = self.clone();
+        self.with_conn(move |conn, _| {
+            let layout = store.layout(conn, site.clone())?;
+            let ddl = layout.attribute_index_ddl().map_err(|_| {
+                StoreError::Unknown(anyhow!("failed to generate DDL for attribute indexes"))
+            })?;
+            let valid = catalog::valid_indexes(conn, &site.namespace)?;
+            // The name of the index is the last word on the first line of
+            // each statement
+            let expected: Vec<_> = ddl
+                .iter()
+                .filter_map(|stmt| stmt.lines().next()?.split_whitespace().last())
+                .collect();
+            let missing = expected
+                .iter()
+                .filter(|name| !valid.iter().any(|index| index == *name))
+                .map(|name| name.to_string())
+                .collect();
+
+            Ok(DeferredIndexStatus {
+                deferred: deployment::indexes_deferred(conn, &site)?,
+                synced: deployment::exists_and_synced(conn, site.deployment.as_str())?,
+                total: expected.len(),
+                missing,
+                builds: catalog::index_builds(conn, &site.namespace)?,
+            })
+        })
+        .await
+    }
+
+    /// Write a backup of `site` into the directory `dir`. The backup is
+    /// taken in one transaction so that it is consistent even if the
+    /// deployment is being indexed at the same time
+    pub(crate) fn backup(&self, site: Arc<Site>, dir: &Path) -> Result<BackupMetadata, StoreError> {
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site)?;
+        conn.build_transaction()
+            .repeatable_read()
+            .read_only()
+            .run(|| backup::dump(&conn, &layout, dir))
+    }
+
+    /// Write a snapshot of all entities of `site` as of `block` into `dir`.
+    /// See `backup::export` for details
+    pub(crate) fn export(
+        &self,
+        site: Arc<Site>,
+        block: Option<BlockNumber>,
+        dir: &Path,
+    ) -> Result<(BlockNumber, BTreeMap<String, usize>), StoreError> {
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site)?;
+        conn.build_transaction()
+            .repeatable_read()
+            .read_only()
+            .run(|| backup::export(&self.logger, &conn, &layout, block, dir))
+    }
+
+    /// Write the rows of the table for `entity_name` in `site` to `writer`.
+    /// See `backup::export_table` for details
+    pub(crate) fn export_table(
+        &self,
+        site: Arc<Site>,
+        entity_name: &str,
+        from: Option<BlockNumber>,
+        to: Option<BlockNumber>,
+        format: ExportFormat,
+        writer: &mut dyn Write,
+    ) -> Result<usize, StoreError> {
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site)?;
+        let table = resolve_table_name(&layout, entity_name)?;
+        conn.build_transaction()
+            .repeatable_read()
+            .read_only()
+            .run(|| backup::export_table(&conn, table, from, to, format, writer))
+    }
+
+    /// Compute checksums over the entities of `site` at `block`. See
+    /// `checksum::checksums` for details
+    pub(crate) fn checksums(
+        &self,
+        site: Arc<Site>,
+        block: Option<BlockNumber>,
+    ) -> Result<Checksums, StoreError> {
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site)?;
+        conn.build_transaction()
+            .repeatable_read()
+            .read_only()
+            .run(|| checksum::checksums(&conn, &layout, block))
+    }
+
+    /// Load the data from the backup in `dir` into `site`, which must have
+    /// been created from `metadata` and must not contain any data yet
+    pub(crate) fn restore(
+        &self,
+        site: Arc<Site>,
+        dir: &Path,
+        metadata: &BackupMetadata,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site)?;
+        conn.transaction(|| backup::restore(&conn, &layout, dir, metadata))
+    }
+
     /// Runs the SQL `ANALYZE` command in a table.
     pub(crate) async fn analyze(
         &self,
@@ -696,8 +1110,15 @@ impl DeploymentStore {
             let column_names_sep_by_commas = column_names.join(", ");
             let table_name = &table.name;
             let index_name = format!("manual_{table_name}_{column_names_sep_by_underscores}");
+            // Postgres can not create indexes on partitioned tables
+            // concurrently
+            let concurrently = if table.is_partitioned() {
+                ""
+            } else {
+                "concurrently "
+            };
             let sql = format!(
-                "create index concurrently if not exists {index_name} \
+                "create index {concurrently}if not exists {index_name} \
                  on {schema_name}.{table_name} using {index_method} \
                  ({column_names_sep_by_commas})"
             );
@@ -720,6 +1141,109 @@ impl DeploymentStore {
         .await
     }
 
+    /// Write the attribute usage that queries recorded to the database
+    pub(crate) async fn flush_attribute_usage(&self) -> Result<usize, StoreError> {
+        let store = self.clone();
+        self.with_conn(move |conn, _| store.attribute_usage.flush(conn).map_err(Into::into))
+            .await
+    }
+
+    /// The attributes of `site` that queries used at least `min_uses` times
+    /// but that have no index, most used first. An attribute counts as
+    /// indexed if it is used in the first key column of any index
+    pub(crate) async fn index_suggestions(
+        &self,
+        site: Arc<Site>,
+        min_uses: i64,
+    ) -> Result<Vec<IndexSuggestion>, StoreError> {
+        let store = self.clone();
+        self.with_conn(move |conn, _| {
+            let layout = store.layout(conn, site.clone())?;
+            let mut leading_exprs = HashMap::new();
+            let mut suggestions = Vec::new();
+            for (entity_type, attribute, usage) in attribute_usage::usage(conn, &site)? {
+                if usage.total() < min_uses {
+                    continue;
+                }
+                // Entity types or attributes that were removed from the
+                // schema do not need an index
+                let table = match layout.table_for_entity(&EntityType::new(entity_type.clone())) {
+                    Ok(table) => table,
+                    Err(_) => continue,
+                };
+                let column = match table.column_for_field(&attribute) {
+                    Ok(column) => column,
+                    Err(_) => continue,
+                };
+                if column.is_primary_key() {
+                    continue;
+                }
+                if !leading_exprs.contains_key(&table.name) {
+                    let exprs = catalog::leading_index_exprs(conn, &site.namespace, &table.name)?;
+                    leading_exprs.insert(table.name.clone(), exprs);
+                }
+                if leading_exprs[&table.name]
+                    .iter()
+                    .any(|expr| attribute_usage::expr_uses_column(expr, column.name.as_str()))
+                {
+                    continue;
+                }
+                suggestions.push(IndexSuggestion {
+                    entity_type,
+                    attribute,
+                    usage,
+                });
+            }
+            suggestions.sort_by(|a, b| b.usage.total().cmp(&a.usage.total()));
+            Ok(suggestions)
+        })
+        .await
+    }
+
+    /// Create the index for `attribute` of `entity_type` that the
+    /// deployment would have gotten when it was created if the attribute
+    /// had not been excluded from indexing
+    pub(crate) async fn create_suggested_index(
+        &self,
+        site: Arc<Site>,
+        entity_type: &str,
+        attribute: &str,
+    ) -> Result<String, StoreError> {
+        let store = self.clone();
+        let entity_type = EntityType::new(entity_type.to_string());
+        let attribute = attribute.to_string();
+        self.with_conn(move |conn, _| {
+            let schema_name = site.namespace.clone();
+            let layout = store.layout(conn, site)?;
+            let table = layout.table_for_entity(&entity_type)?;
+            let column = table.column_for_field(&attribute)?;
+            let (method, index_expr) = attribute_index_method(table, column);
+            let table_name = &table.name;
+            let index_name = format!("manual_{table_name}_{}", column.name);
+            // Postgres can not create indexes on partitioned tables
+            // concurrently
+            let concurrently = if table.is_partitioned() {
+                ""
+            } else {
+                "concurrently "
+            };
+            let sql = format!(
+                "create index {concurrently}if not exists {index_name} \
+                 on {schema_name}.{table_name} using {method}({index_expr})"
+            );
+            // This might take a long time.
+            conn.batch_execute(&sql)?;
+            if catalog::check_index_is_valid(conn, schema_name.as_str(), &index_name)? {
+                Ok(index_name)
+            } else {
+                catalog::drop_index(conn, schema_name.as_str(), &index_name)?;
+                Err(StoreError::Canceled)
+            }
+            .map_err(Into::into)
+        })
+        .await
+    }
+
     /// Returns a list of all existing indexes for the specified Entity table.
     pub(crate) async fn indexes_for_entity(
         &self,
@@ -894,6 +1418,88 @@ impl DeploymentStore {
         Ok(Some(finisher.finish()))
     }
 
+    /// Compute the proof of indexing for each of `blocks`. Blocks that the
+    /// deployment has not processed yet are left out of the result. This
+    /// reads the digests for all blocks with one query, which is much
+    /// cheaper than calling `get_proof_of_indexing` for each block
+    pub(crate) async fn get_proofs_of_indexing(
+        &self,
+        site: Arc<Site>,
+        indexer: &Option<Address>,
+        blocks: Vec<BlockPtr>,
+    ) -> Result<Vec<(BlockPtr, [u8; 32])>, StoreError> {
+        #[derive(QueryableByName)]
+        struct Digest {
+            #[sql_type = "diesel::sql_types::Text"]
+            id: String,
+            #[sql_type = "diesel::sql_types::Binary"]
+            digest: Vec<u8>,
+            #[sql_type = "diesel::sql_types::Integer"]
+            start: BlockNumber,
+            #[sql_type = "diesel::sql_types::Integer"]
+            end: BlockNumber,
+        }
+
+        let indexer = *indexer;
+        let store = self.cheap_clone();
+        let site2 = site.cheap_clone();
+        let (blocks, digests) = self
+            .with_conn(move |conn, cancel| {
+                let layout = store.layout(conn, site2.cheap_clone())?;
+                if !layout.supports_proof_of_indexing() {
+                    return Ok((vec![], vec![]));
+                }
+                let table = layout.table_for_entity(&*POI_OBJECT)?;
+
+                conn.transaction::<_, CancelableError<StoreError>, _>(|| {
+                    let head = match Self::block_ptr_with_conn(conn, site2.cheap_clone())? {
+                        Some(head) => head.number,
+                        None => return Ok((vec![], vec![])),
+                    };
+                    let blocks: Vec<_> = blocks
+                        .into_iter()
+                        .filter(|block| block.number <= head)
+                        .collect();
+                    let (min, max) = match (
+                        blocks.iter().map(|block| block.number).min(),
+                        blocks.iter().map(|block| block.number).max(),
+                    ) {
+                        (Some(min), Some(max)) => (min, max),
+                        _ => return Ok((vec![], vec![])),
+                    };
+                    cancel.check_cancel()?;
+
+                    let query = format!(
+                        "select id, digest, lower(block_range) as start, \
+                                coalesce(upper(block_range), {}) as end \
+                           from {} \
+                          where block_range && int4range($1, $2, '[]')",
+                        BLOCK_NUMBER_MAX, table.qualified_name
+                    );
+                    let digests = diesel::sql_query(query)
+                        .bind::<diesel::sql_types::Integer, _>(min)
+                        .bind::<diesel::sql_types::Integer, _>(max)
+                        .load::<Digest>(conn)?;
+                    Ok((blocks, digests))
+                })
+            })
+            .await?;
+
+        let pois = blocks
+            .into_iter()
+            .map(|block| {
+                let mut finisher = ProofOfIndexingFinisher::new(&block, &site.deployment, &indexer);
+                for digest in &digests {
+                    if digest.start <= block.number && block.number < digest.end {
+                        finisher.add_causality_region(&digest.id, &digest.digest);
+                    }
+                }
+                (block, finisher.finish())
+            })
+            .collect();
+        Ok(pois)
+    }
+
     pub(crate) fn get(
         &self,
         site: Arc<Site>,
@@ -936,6 +1542,63 @@ impl DeploymentStore {
         Ok(changes)
     }
 
+    pub(crate) fn get_changes_in_range(
+        &self,
+        site: Arc<Site>,
+        entity_types: &[EntityType],
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<BTreeMap<BlockNumber, Vec<EntityOperation>>, StoreError> {
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site)?;
+        layout.find_changes_in_range(&conn, entity_types, from, to)
+    }
+
+    pub(crate) fn mapping_logs(
+        &self,
+        site: Arc<Site>,
+        from_block: BlockNumber,
+        level: Level,
+        first: usize,
+    ) -> Result<Vec<MappingLog>, StoreError> {
+        let conn = self.get_conn()?;
+        mapping_log::load(&conn, &site, from_block, level, first)
+    }
+
+    pub(crate) fn quarantined_data_sources(
+        &self,
+        site: Arc<Site>,
+    ) -> Result<Vec<QuarantinedDataSource>, StoreError> {
+        let conn = self.get_conn()?;
+        quarantine::load(&conn, &site)
+    }
+
+    pub(crate) fn skipped_blocks(&self, site: Arc<Site>) -> Result<Vec<SkippedBlock>, StoreError> {
+        let conn = self.get_conn()?;
+        skipped_block::load(&conn, &site)
+    }
+
+    pub(crate) fn skip_block(
+        &self,
+        site: Arc<Site>,
+        block: BlockNumber,
+        handler: Option<&str>,
+        reason: &str,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        skipped_block::insert(&conn, &site, block, handler, reason)
+    }
+
+    pub(crate) fn unskip_block(
+        &self,
+        site: Arc<Site>,
+        block: BlockNumber,
+        handler: Option<&str>,
+    ) -> Result<usize, StoreError> {
+        let conn = self.get_conn()?;
+        skipped_block::remove(&conn, &site, block, handler)
+    }
+
     // Only used by tests
     #[cfg(debug_assertions)]
     pub(crate) fn find(
@@ -956,6 +1619,9 @@ impl DeploymentStore {
         stopwatch: &StopwatchMetrics,
         data_sources: &[StoredDynamicDataSource],
         deterministic_errors: &[SubgraphError],
+        mapping_logs: &[MappingLog],
+        quarantined: &[QuarantinedDataSource],
+        bulk: bool,
     ) -> Result<StoreEvent, StoreError> {
         // All operations should apply only to data or metadata for this subgraph
         if mods
@@ -974,6 +1640,12 @@ impl DeploymentStore {
             self.get_conn()?
         };
 
+        let layout = self.layout(&conn, site.clone())?;
+        let mut copy = if bulk {
+            Some(self.pool.copy_client()?)
+        } else {
+            None
+        };
         let event = conn.transaction(|| -> Result<_, StoreError> {
             // Emit a store event for the changes we are about to make. We
             // wait with sending it until we have done all our other work
@@ -982,17 +1654,31 @@ impl DeploymentStore {
             let event: StoreEvent = mods.iter().collect();
 
             // Make the changes
-            let layout = self.layout(&conn, site.clone())?;
             let section = stopwatch.start_section("apply_entity_modifications");
-            let count = self.apply_entity_modifications(
+            let mut count = self.apply_entity_modifications(
                 &conn,
                 layout.as_ref(),
                 mods,
                 block_ptr_to,
                 stopwatch,
+                copy.as_deref_mut(),
             )?;
             section.end();
 
+            // Write the rollups of aggregations whose intervals the points
+            // that were just written closed
+            let section = stopwatch.start_section("apply_rollups");
+            let rollups = layout.rollups(&conn, mods, block_number(block_ptr_to))?;
+            count += rollups.len() as i32;
+            let event = event.extend(rollups.iter().collect());
+            section.end();
+
+            if ENV_VARS.store.cdc_tables {
+                let _section = stopwatch.start_section("record_cdc_changes");
+                cdc::record_changes(&conn, &site.namespace, block_ptr_to, mods)?;
+                cdc::record_changes(&conn, &site.namespace, block_ptr_to, &rollups)?;
+            }
+
             dynds::insert(&conn, &site.deployment, data_sources, block_ptr_to)?;
 
             if !deterministic_errors.is_empty() {
@@ -1004,6 +1690,10 @@ impl DeploymentStore {
                 )?;
             }
 
+            mapping_log::insert(&conn, &site, mapping_logs, ENV_VARS.store.mapping_log_limit)?;
+
+            quarantine::insert(&conn, &site, quarantined)?;
+
             deployment::transact_block(
                 &conn,
                 &site,
@@ -1014,9 +1704,23 @@ impl DeploymentStore {
             )?;
 
             Ok(event)
-        })?;
+        });
 
-        Ok(event)
+        if event.is_err() {
+            // Partitions that were created in the transaction are gone
+            layout.forget_partitions();
+
+            // Entities that were written with `COPY` were committed
+            // already and need to be removed again
+            if copy.is_some() {
+                let block = block_number(block_ptr_to);
+                if let Err(e) = conn.transaction(|| layout.remove_uncommitted(&conn, block)) {
+                    error!(self.logger, "Failed to remove entities after a failed write";
+                                        "block" => block, "error" => e.to_string());
+                }
+            }
+        }
+        event
     }
 
     fn rewind_with_conn(
@@ -1025,22 +1729,46 @@ impl DeploymentStore {
         site: Arc<Site>,
         block_ptr_to: BlockPtr,
         firehose_cursor: Option<&str>,
+        past_graft: bool,
     ) -> Result<StoreEvent, StoreError> {
         let event = conn.transaction(|| -> Result<_, StoreError> {
-            // Don't revert past a graft point
+            // Only revert past a graft point if we were asked to
             let info = self.subgraph_info_with_conn(conn, site.as_ref())?;
-            if let Some(graft_block) = info.graft_block {
-                if graft_block > block_ptr_to.number {
-                    return Err(anyhow!(
-                        "Can not revert subgraph `{}` to block {} as it was \
-                        grafted at block {} and reverting past a graft point \
-                        is not possible",
-                        site.deployment.clone(),
-                        block_ptr_to.number,
-                        graft_block
-                    )
-                    .into());
+            let regraft = match info.graft_block {
+                Some(graft_block) if graft_block > block_ptr_to.number => {
+                    if !past_graft {
+                        return Err(anyhow!(
+                            "Can not revert subgraph `{}` to block {} as it was \
+                            grafted at block {} and reverting past a graft point \
+                            is not possible",
+                            site.deployment.clone(),
+                            block_ptr_to.number,
+                            graft_block
+                        )
+                        .into());
+                    }
+                    true
                 }
+                _ => false,
+            };
+
+            let earliest_block = deployment::earliest_block(conn, site.as_ref())?;
+            if regraft && block_ptr_to.number < earliest_block {
+                // We don't have the history to revert to `block_ptr_to`;
+                // copy the data from the graft base again instead
+                return self.reset_graft(conn, site.cheap_clone(), &block_ptr_to);
+            }
+
+            // Don't revert into history that has been pruned
+            if block_ptr_to.number < earliest_block {
+                return Err(anyhow!(
+                    "Can not revert subgraph `{}` to block {} since its history \
+                    before block {} has been pruned",
+                    site.deployment.clone(),
+                    block_ptr_to.number,
+                    earliest_block
+                )
+                .into());
             }
 
             deployment::revert_block_ptr(conn, &site.deployment, block_ptr_to.clone())?;
@@ -1067,6 +1795,12 @@ impl DeploymentStore {
             // rest of the code that we only record history for those meta data
             // changes that might need to be reverted
             Layout::revert_metadata(&conn, &site.deployment, block)?;
+            mapping_log::revert(conn, &site, block)?;
+            quarantine::revert(conn, &site, block)?;
+
+            if ENV_VARS.store.cdc_tables {
+                cdc::record_revert(conn, &site.namespace, &block_ptr_to)?;
+            }
 
             deployment::update_entity_count(
                 conn,
@@ -1074,16 +1808,61 @@ impl DeploymentStore {
                 layout.count_query.as_str(),
                 count,
             )?;
+
+            // All the data up to `block_ptr_to` now comes from the graft
+            // base
+            if regraft {
+                deployment::set_graft_block(conn, site.as_ref(), &block_ptr_to)?;
+            }
             Ok(event)
         })?;
 
         Ok(event)
     }
 
+    /// Remove all data of `site` and make it copy the data of its graft
+    /// base up to `block_ptr_to` again the next time it is started
+    fn reset_graft(
+        &self,
+        conn: &PgConnection,
+        site: Arc<Site>,
+        block_ptr_to: &BlockPtr,
+    ) -> Result<StoreEvent, StoreError> {
+        let layout = self.layout(conn, site.cheap_clone())?;
+        let tables = layout
+            .tables
+            .values()
+            .map(|table| table.qualified_name.to_string())
+            .collect::<Vec<_>>();
+        if !tables.is_empty() {
+            conn.batch_execute(&format!("truncate table {}", tables.join(", ")))?;
+        }
+        dynds::drop(conn, &site.deployment)?;
+        deployment::revert_subgraph_errors(conn, &site.deployment, 0)?;
+        mapping_log::revert(conn, &site, 0)?;
+        quarantine::revert(conn, &site, 0)?;
+        crate::copy::reset(conn, &site)?;
+        deployment::reset_graft(conn, &site, block_ptr_to)?;
+
+        let changes = layout
+            .tables
+            .values()
+            .map(|table| EntityChange::Data {
+                subgraph_id: site.deployment.clone(),
+                entity_type: table.object.clone(),
+            })
+            .collect();
+        Ok(StoreEvent::new(changes))
+    }
+
+    /// Revert `site` to `block_ptr_to`. If `past_graft` is `true`,
+    /// `block_ptr_to` may be before the graft point of `site`, in which
+    /// case the graft point is moved to `block_ptr_to`
     pub(crate) fn rewind(
         &self,
         site: Arc<Site>,
         block_ptr_to: BlockPtr,
+        past_graft: bool,
     ) -> Result<StoreEvent, StoreError> {
         let conn = self.get_conn()?;
 
@@ -1102,7 +1881,7 @@ impl DeploymentStore {
         // When rewinding, we reset the firehose cursor to the empty string. That way, on resume,
         // Firehose will start from the block_ptr instead (with sanity check to ensure it's resume
         // at the exact block).
-        self.rewind_with_conn(&conn, site, block_ptr_to, Some(""))
+        self.rewind_with_conn(&conn, site, block_ptr_to, Some(""), past_graft)
     }
 
     pub(crate) fn revert_block_operations(
@@ -1120,7 +1899,7 @@ impl DeploymentStore {
             panic!("revert_block_operations must revert only backward, you are trying to revert forward going from subgraph block {} to new block {}", deployment_head, block_ptr_to);
         }
 
-        self.rewind_with_conn(&conn, site, block_ptr_to, firehose_cursor)
+        self.rewind_with_conn(&conn, site, block_ptr_to, firehose_cursor, false)
     }
 
     pub(crate) async fn deployment_state_from_id(
@@ -1146,6 +1925,7 @@ impl DeploymentStore {
 
     pub(crate) fn replica_for_query(
         &self,
+        site: &Site,
         for_subscription: bool,
     ) -> Result<ReplicaId, StoreError> {
         use std::sync::atomic::Ordering;
@@ -1163,9 +1943,65 @@ impl DeploymentStore {
             true => ReplicaId::Main,
         };
 
+        // Replicas that are too far behind would answer queries with stale
+        // data
+        let replica_id = match (replica_id, ENV_VARS.store.replica_max_lag) {
+            (ReplicaId::ReadOnly(_), Some(max_lag)) => {
+                if self.replica_is_current(replica_id, site, max_lag) {
+                    replica_id
+                } else {
+                    ReplicaId::Main
+                }
+            }
+            (replica_id, _) => replica_id,
+        };
+
         Ok(replica_id)
     }
 
+    /// Check whether the head of the deployment `site` on `replica` is at
+    /// most `max_lag` blocks behind its head on the main database. Results
+    /// are remembered for a few seconds so that most queries do not need
+    /// to check
+    fn replica_is_current(&self, replica: ReplicaId, site: &Site, max_lag: BlockNumber) -> bool {
+        const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+        let key = (replica, site.id);
+        if let Some((checked_at, current)) = self.replica_current.lock().unwrap().get(&key) {
+            if checked_at.elapsed() < CHECK_INTERVAL {
+                return *current;
+            }
+        }
+
+        let head = |replica: ReplicaId| -> Result<Option<BlockNumber>, Error> {
+            let conn = self.get_replica_conn(replica)?;
+            Ok(deployment::block_ptr(&conn, &site.deployment)?.map(|ptr| ptr.number))
+        };
+        // Connections are released right after each query so that we never
+        // hold more than one at a time
+        let current = match (head(ReplicaId::Main), head(replica)) {
+            (Ok(main_head), Ok(replica_head)) => within_lag(main_head, replica_head, max_lag),
+            (Err(e), _) | (_, Err(e)) => {
+                warn!(self.logger, "Failed to check how far behind a replica is";
+                                   "replica" => format!("{:?}", replica),
+                                   "deployment" => site.deployment.as_str(),
+                                   "error" => e.to_string());
+                false
+            }
+        };
+        if !current {
+            debug!(self.logger, "Replica is too far behind, sending queries to main database";
+                                "replica" => format!("{:?}", replica),
+                                "deployment" => site.deployment.as_str());
+        }
+
+        self.replica_current
+            .lock()
+            .unwrap()
+            .insert(key, (Instant::now(), current));
+        current
+    }
+
     pub(crate) async fn load_dynamic_data_sources(
         &self,
         id: DeploymentHash,
@@ -1193,26 +2029,96 @@ impl DeploymentStore {
         deployment::graft_pending(&conn, id)
     }
 
+    pub(crate) fn graft_point(
+        &self,
+        id: &DeploymentHash,
+    ) -> Result<Option<(DeploymentHash, BlockPtr)>, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::graft_point(&conn, id)
+    }
+
+    /// The latest block at or before `block` at which the proof of
+    /// indexing of `site` changed
+    pub(crate) fn nearest_poi_block(
+        &self,
+        site: Arc<Site>,
+        block: BlockNumber,
+    ) -> Result<Option<BlockNumber>, StoreError> {
+        #[derive(QueryableByName)]
+        struct PoiBlock {
+            #[sql_type = "diesel::sql_types::Nullable<diesel::sql_types::Integer>"]
+            block: Option<BlockNumber>,
+        }
+
+        let conn = self.get_conn()?;
+        let layout = self.layout(&conn, site)?;
+        if !layout.supports_proof_of_indexing() {
+            return Ok(None);
+        }
+        let table = layout.table_for_entity(&*POI_OBJECT)?;
+        let query = format!(
+            "select max(lower(block_range)) as block from {} where lower(block_range) <= $1",
+            table.qualified_name
+        );
+        Ok(diesel::sql_query(query)
+            .bind::<diesel::sql_types::Integer, _>(block)
+            .get_result::<PoiBlock>(&conn)?
+            .block)
+    }
+
+    pub(crate) fn earliest_block(&self, site: &Site) -> Result<BlockNumber, StoreError> {
+        let conn = self.get_conn()?;
+        deployment::earliest_block(&conn, site)
+    }
+
+    /// Check that `site` still has all the data needed to graft onto it at
+    /// `block`, i.e., that it has processed `block` and has not pruned the
+    /// history before it
+    pub(crate) fn check_graft_base(
+        &self,
+        site: Arc<Site>,
+        block: &BlockPtr,
+    ) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        let earliest = deployment::earliest_block(&conn, &site)?;
+        let head = Self::block_ptr_with_conn(&conn, site.cheap_clone())?
+            .map(|ptr| ptr.number)
+            .unwrap_or(-1);
+        if block.number < earliest || block.number > head {
+            return Err(anyhow!(
+                "the graft base `{}` only has data for blocks {} to {} but \
+                 data for block {} is needed",
+                site.deployment,
+                earliest,
+                head,
+                block.number
+            )
+            .into());
+        }
+        Ok(())
+    }
+
     /// Bring the subgraph into a state where we can start or resume
     /// indexing.
     ///
-    /// If `graft_src` is `Some(..)`, copy data from that subgraph. It
-    /// should only be `Some(..)` if we know we still need to copy data. The
-    /// code is idempotent so that a copy process that has been interrupted
-    /// can be resumed seamlessly, but the code sets the block pointer back
-    /// to the graph point, so that calling this needlessly with `Some(..)`
-    /// will remove any progress that might have been made since the last
-    /// time the deployment was started.
+    /// If `graft_src` is `Some(..)`, copy data from that subgraph; the last
+    /// element is the earliest block for which the source still has all
+    /// its data. It should only be `Some(..)` if we know we still need to
+    /// copy data. The code is idempotent so that a copy process that has
+    /// been interrupted can be resumed seamlessly, but the code sets the
+    /// block pointer back to the graph point, so that calling this
+    /// needlessly with `Some(..)` will remove any progress that might have
+    /// been made since the last time the deployment was started.
     pub(crate) fn start_subgraph(
         &self,
         logger: &Logger,
         site: Arc<Site>,
-        graft_src: Option<(Arc<Layout>, BlockPtr)>,
+        graft_src: Option<(Arc<Layout>, BlockPtr, BlockNumber)>,
     ) -> Result<(), StoreError> {
         let dst = self.find_layout(site)?;
 
         // Do any cleanup to bring the subgraph into a known good state
-        if let Some((src, block)) = graft_src {
+        if let Some((src, block, earliest_block)) = graft_src {
             info!(
                 logger,
                 "Initializing graft by copying data from {} to {}",
@@ -1267,6 +2173,9 @@ impl DeploymentStore {
                 info!(logger, "Rewound subgraph to block {}", block.number;
                       "time_ms" => start.elapsed().as_millis());
 
+                // The copy has no more history than its source
+                deployment::set_earliest_block(&conn, &dst.site, earliest_block)?;
+
                 let start = Instant::now();
                 deployment::set_entity_count(&conn, &dst.site, &dst.count_query)?;
                 info!(logger, "Counted the entities";
@@ -1284,7 +2193,24 @@ impl DeploymentStore {
         // deployed subgraphs so that we respect the 'startBlock' setting
         // the first time the subgraph is started
         let conn = self.get_conn()?;
-        conn.transaction(|| crate::deployment::initialize_block_ptr(&conn, &dst.site))?;
+        if ENV_VARS.store.cdc_tables {
+            cdc::create_table(&conn, &dst.site.namespace)?;
+        }
+        conn.transaction(|| -> Result<(), StoreError> {
+            crate::deployment::initialize_block_ptr(&conn, &dst.site)?;
+
+            // Entities that are written with `COPY` are committed before
+            // the rest of their block; remove any that a write that failed
+            // before the node stopped left behind
+            let head = crate::deployment::block_ptr(&conn, &dst.site.deployment)?;
+            let block = head.map_or(0, |ptr| ptr.number + 1);
+            let count = dst.remove_uncommitted(&conn, block)?;
+            if count > 0 {
+                info!(logger, "Removed {} entity versions of a write that failed", count;
+                              "block" => block);
+            }
+            Ok(())
+        })?;
         Ok(())
     }
 
@@ -1460,6 +2386,100 @@ impl DeploymentStore {
         })
     }
 
+    /// Clear the fatal error of `site` and remove all errors from the
+    /// block of the fatal error on. With `block_ptr_to`, first rewind
+    /// `site` to that block, which must be before the block of the fatal
+    /// error. Without it, the deployment head must already be before the
+    /// block of the fatal error so that the block is processed again
+    pub(crate) fn unfail(
+        &self,
+        site: Arc<Site>,
+        block_ptr_to: Option<BlockPtr>,
+    ) -> Result<StoreEvent, StoreError> {
+        let conn = self.get_conn()?;
+        let deployment_id = &site.deployment;
+
+        conn.transaction(|| {
+            let subgraph_error = match ErrorDetail::fatal(&conn, deployment_id)? {
+                Some(fatal_error) => fatal_error,
+                None => return Err(anyhow!("deployment `{}` has not failed", deployment_id).into()),
+            };
+            let error_block =
+                match crate::block_range::first_block_in_range(&subgraph_error.block_range) {
+                    Some(block) => block,
+                    None => {
+                        return Err(anyhow!(
+                            "the fatal error of deployment `{}` does not have a block number",
+                            deployment_id
+                        )
+                        .into())
+                    }
+                };
+            let head = Self::block_ptr_with_conn(&conn, site.cheap_clone())?;
+
+            let (event, reverted_block) = match block_ptr_to {
+                Some(block_ptr_to) => {
+                    if block_ptr_to.number >= error_block {
+                        return Err(anyhow!(
+                            "can not unfail deployment `{}` by rewinding to block {} \
+                             since it failed at block {}",
+                            deployment_id,
+                            block_ptr_to.number,
+                            error_block
+                        )
+                        .into());
+                    }
+                    let reverted_block = block_ptr_to.number + 1;
+                    let event = match head {
+                        Some(head) if head.number > block_ptr_to.number => self.rewind_with_conn(
+                            &conn,
+                            site.cheap_clone(),
+                            block_ptr_to,
+                            Some(""),
+                            false,
+                        )?,
+                        _ => StoreEvent::new(vec![]),
+                    };
+                    (event, reverted_block)
+                }
+                None => {
+                    if let Some(head) = head.filter(|head| head.number >= error_block) {
+                        return Err(anyhow!(
+                            "can not unfail deployment `{}` without rewinding since its \
+                             head block {} is not before block {} at which it failed",
+                            deployment_id,
+                            head.number,
+                            error_block
+                        )
+                        .into());
+                    }
+                    (StoreEvent::new(vec![]), error_block)
+                }
+            };
+
+            info!(
+                self.logger,
+                "Unfailing the deployment status";
+                "subgraph_id" => deployment_id,
+                "error_block_number" => error_block,
+                "deterministic" => subgraph_error.deterministic,
+            );
+
+            // Clear the fatal error before removing it; removing errors
+            // also sets the health according to the remaining non-fatal
+            // errors
+            deployment::update_deployment_status(
+                &conn,
+                deployment_id,
+                deployment::SubgraphHealth::Healthy,
+                None,
+            )?;
+            deployment::revert_subgraph_errors(&conn, deployment_id, reverted_block)?;
+
+            Ok(event)
+        })
+    }
+
     #[cfg(debug_assertions)]
     pub fn error_count(&self, id: &DeploymentHash) -> Result<usize, StoreError> {
         let conn = self.get_conn()?;
@@ -1523,3 +2543,40 @@ fn resolve_column_names<'a, T: AsRef<str>>(
         })
         .collect()
 }
+
+/// Whether a replica whose copy of a deployment is at `replica_head` is at
+/// most `max_lag` blocks behind the main database, where the deployment is
+/// at `main_head`. A replica that does not have any blocks for the
+/// deployment yet is only current if the main database does not have any
+/// either
+fn within_lag(
+    main_head: Option<BlockNumber>,
+    replica_head: Option<BlockNumber>,
+    max_lag: BlockNumber,
+) -> bool {
+    match (main_head, replica_head) {
+        (Some(main_head), Some(replica_head)) => main_head - replica_head <= max_lag,
+        (None, None) => true,
+        (Some(_), None) | (None, Some(_)) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::within_lag;
+
+    #[test]
+    fn lagging_replicas_are_not_current() {
+        // Replicas that are at most `max_lag` blocks behind can be queried
+        assert!(within_lag(Some(100), Some(100), 10));
+        assert!(within_lag(Some(100), Some(90), 10));
+        // Replicas that are further behind are not, and queries are sent
+        // to the main database instead
+        assert!(!within_lag(Some(100), Some(89), 10));
+        assert!(!within_lag(Some(100), None, 10));
+        // A replica that has not seen the deployment yet is current only
+        // as long as the main database has not indexed any blocks either
+        assert!(within_lag(None, None, 10));
+        assert!(!within_lag(None, Some(1), 10));
+    }
+}
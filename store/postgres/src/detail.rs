@@ -9,7 +9,9 @@ use diesel::prelude::{
 };
 use diesel_derives::Associations;
 use git_testament::{git_testament, git_testament_macros};
-use graph::data::subgraph::schema::{SubgraphError, SubgraphManifestEntity};
+use graph::data::subgraph::schema::{
+    QuarantinedDataSource, SkippedBlock, SubgraphError, SubgraphManifestEntity,
+};
 use graph::prelude::{
     bigdecimal::ToPrimitive, BigDecimal, BlockPtr, DeploymentHash, StoreError,
     SubgraphDeploymentEntity,
@@ -63,6 +65,8 @@ pub struct DeploymentDetail {
     current_reorg_depth: i32,
     max_reorg_depth: i32,
     firehose_cursor: Option<String>,
+    earliest_block_number: i32,
+    indexes_deferred: bool,
 }
 
 #[derive(Queryable, QueryableByName)]
@@ -174,6 +178,8 @@ pub(crate) fn info_from_details(
     detail: DeploymentDetail,
     fatal: Option<ErrorDetail>,
     non_fatal: Vec<ErrorDetail>,
+    skipped_blocks: Vec<SkippedBlock>,
+    quarantined_data_sources: Vec<QuarantinedDataSource>,
     sites: &[Arc<Site>],
 ) -> Result<status::Info, StoreError> {
     let DeploymentDetail {
@@ -242,6 +248,8 @@ pub(crate) fn info_from_details(
         health,
         fatal_error,
         non_fatal_errors,
+        skipped_blocks,
+        quarantined_data_sources,
         chains: vec![chain],
         entity_count,
         node: None,
@@ -312,11 +320,22 @@ pub(crate) fn deployment_statuses(
         .into_group_map()
     };
 
+    let mut skipped_blocks = {
+        let ids: Vec<_> = sites.iter().map(|site| site.id).collect();
+        crate::skipped_block::load_for_deployments(conn, &ids)?
+    };
+    let mut quarantined = {
+        let ids: Vec<_> = sites.iter().map(|site| site.id).collect();
+        crate::quarantine::load_for_deployments(conn, &ids)?
+    };
+
     details_with_fatal_error
         .into_iter()
         .map(|(detail, fatal)| {
             let non_fatal = non_fatal_errors.remove(&detail.id).unwrap_or(vec![]);
-            info_from_details(detail, fatal, non_fatal, sites)
+            let skipped = skipped_blocks.remove(&detail.id).unwrap_or(vec![]);
+            let quarantined = quarantined.remove(&detail.id).unwrap_or(vec![]);
+            info_from_details(detail, fatal, non_fatal, skipped, quarantined, sites)
         })
         .collect()
 }
@@ -336,6 +355,8 @@ struct StoredSubgraphManifest {
     schema: String,
     graph_node_version_id: Option<i32>,
     use_bytea_prefix: bool,
+    history_blocks: i32,
+    partition_blocks: Option<i32>,
 }
 
 impl From<StoredSubgraphManifest> for SubgraphManifestEntity {
@@ -346,6 +367,7 @@ impl From<StoredSubgraphManifest> for SubgraphManifestEntity {
             repository: value.repository,
             features: value.features,
             schema: value.schema,
+            history_blocks: value.history_blocks,
         }
     }
 }
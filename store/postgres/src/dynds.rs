@@ -55,6 +55,7 @@ fn to_source(
 
     Ok(Source {
         address,
+        subgraph: None,
         abi,
         start_block,
     })
@@ -131,6 +132,7 @@ pub(crate) fn insert(
                 source:
                     Source {
                         address,
+                        subgraph: _,
                         abi,
                         start_block,
                     },
@@ -231,3 +233,52 @@ pub(crate) fn drop(conn: &PgConnection, id: &DeploymentHash) -> Result<usize, St
         .execute(conn)
         .map_err(|e| e.into())
 }
+
+/// Return the dynamic data sources of deployment `id` as JSON objects, one
+/// per data source, in the order in which they were created
+pub(crate) fn to_json(conn: &PgConnection, id: &DeploymentHash) -> Result<Vec<String>, StoreError> {
+    #[derive(QueryableByName)]
+    struct Row {
+        #[sql_type = "Text"]
+        data: String,
+    }
+
+    let query = "\
+      select (to_jsonb(e) - 'vid')::text as data
+        from subgraphs.dynamic_ethereum_contract_data_source e
+       where e.deployment = $1
+       order by e.ethereum_block_number, e.vid";
+    Ok(sql_query(query)
+        .bind::<Text, _>(id.as_str())
+        .load::<Row>(conn)?
+        .into_iter()
+        .map(|row| row.data)
+        .collect())
+}
+
+/// Insert the dynamic data sources in `data`, as produced by `to_json`,
+/// for deployment `id`
+pub(crate) fn insert_json(
+    conn: &PgConnection,
+    id: &DeploymentHash,
+    data: &[String],
+) -> Result<usize, StoreError> {
+    if data.is_empty() {
+        return Ok(0);
+    }
+
+    let query = "\
+      insert into subgraphs.dynamic_ethereum_contract_data_source(name,
+             address, abi, start_block, ethereum_block_hash,
+             ethereum_block_number, deployment, context)
+      select e.name, e.address, e.abi, e.start_block,
+             e.ethereum_block_hash, e.ethereum_block_number, $2 as deployment,
+             e.context
+        from jsonb_populate_recordset(
+               null::subgraphs.dynamic_ethereum_contract_data_source,
+               $1::jsonb) e";
+    Ok(sql_query(query)
+        .bind::<Text, _>(format!("[{}]", data.join(",")))
+        .bind::<Text, _>(id.as_str())
+        .execute(conn)?)
+}
@@ -1,17 +1,18 @@
 //! Jobs for database maintenance
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use diesel::{prelude::RunQueryDsl, sql_query, sql_types::Double};
 
-use graph::prelude::{error, Logger, MetricsRegistry, StoreError, ENV_VARS};
-use graph::prometheus::Gauge;
+use graph::prelude::{error, info, BlockNumber, Logger, MetricsRegistry, StoreError, ENV_VARS};
+use graph::prometheus::{CounterVec, Gauge, GaugeVec};
 use graph::util::jobs::{Job, Runner};
 
 use crate::connection_pool::ConnectionPool;
-use crate::{unused, Store, SubgraphStore};
+use crate::maintenance::{Planner, Settings};
+use crate::{unused, BlockStore, Shard, Store, SubgraphStore};
 
 pub fn register(
     runner: &mut Runner,
@@ -25,15 +26,66 @@ pub fn register(
     );
 
     runner.register(
-        Arc::new(NotificationQueueUsage::new(primary_pool, registry)),
+        Arc::new(NotificationQueueUsage::new(primary_pool, registry.clone())),
         Duration::from_secs(60),
     );
 
+    if let Some(interval) = ENV_VARS.store.vacuum_interval {
+        runner.register(
+            Arc::new(TableMaintenanceJob::new(store.subgraph_store(), registry)),
+            interval,
+        );
+    }
+
     runner.register(
         Arc::new(MirrorPrimary::new(store.subgraph_store())),
         Duration::from_secs(15 * 60),
     );
 
+    if let Some(retention) = ENV_VARS.store.cdc_retention_blocks {
+        runner.register(
+            Arc::new(PruneChangesJob::new(store.subgraph_store(), retention)),
+            Duration::from_secs(10 * 60),
+        );
+    }
+
+    if ENV_VARS.store.call_cache_retention_blocks.is_some()
+        || ENV_VARS.store.call_cache_max_calls.is_some()
+    {
+        runner.register(
+            Arc::new(PruneCallCacheJob::new(store.block_store())),
+            Duration::from_secs(60 * 60),
+        );
+    }
+
+    runner.register(
+        Arc::new(PruneHistoryJob::new(store.subgraph_store())),
+        Duration::from_secs(10 * 60),
+    );
+
+    runner.register(
+        Arc::new(DeferredIndexesJob::new(store.subgraph_store())),
+        Duration::from_secs(5 * 60),
+    );
+
+    if let Some(interval) = ENV_VARS.rebalance_interval {
+        runner.register(
+            Arc::new(RebalanceJob::new(
+                store.subgraph_store(),
+                ENV_VARS.rebalance_max_moves,
+                ENV_VARS.rebalance_dry_run,
+            )),
+            interval,
+        );
+    }
+
+    if ENV_VARS.subgraph_version_switching_mode.is_canary() {
+        runner.register(
+            Arc::new(RetireReplacedJob::new(store.subgraph_store())),
+            Duration::from_secs(60),
+        );
+    }
+
     // Remove unused deployments every 2 hours
     runner.register(
         Arc::new(UnusedJob::new(store.subgraph_store())),
@@ -73,6 +125,347 @@ impl Job for VacuumDeploymentsJob {
     }
 }
 
+/// A job that vacuums and analyzes entity tables with many dead tuples or
+/// stale statistics. To avoid slowing down indexing, only tables that are
+/// not written to much right now are considered, and only a few of them are
+/// processed each time the job runs
+struct TableMaintenanceJob {
+    store: Arc<SubgraphStore>,
+    settings: Settings,
+    planners: Mutex<HashMap<Shard, (Planner, Instant)>>,
+    operations: Box<CounterVec>,
+    duration: Box<CounterVec>,
+    dead_ratio: Box<GaugeVec>,
+    tables_needing_vacuum: Box<GaugeVec>,
+}
+
+impl TableMaintenanceJob {
+    fn new(store: Arc<SubgraphStore>, registry: Arc<impl MetricsRegistry>) -> Self {
+        let operations = registry
+            .new_counter_vec(
+                "table_maintenance_operations",
+                "The number of vacuum and analyze operations run on entity tables",
+                vec!["shard".to_string(), "operation".to_string()],
+            )
+            .expect("Can register the table_maintenance_operations counter");
+        let duration = registry
+            .new_counter_vec(
+                "table_maintenance_seconds",
+                "The time spent on vacuum and analyze operations on entity tables",
+                vec!["shard".to_string(), "operation".to_string()],
+            )
+            .expect("Can register the table_maintenance_seconds counter");
+        let dead_ratio = registry
+            .new_gauge_vec(
+                "table_maintenance_dead_tuple_ratio",
+                "The share of dead tuples across all entity tables",
+                vec!["shard".to_string()],
+            )
+            .expect("Can register the table_maintenance_dead_tuple_ratio gauge");
+        let tables_needing_vacuum = registry
+            .new_gauge_vec(
+                "table_maintenance_tables_needing_vacuum",
+                "The number of entity tables whose dead tuples exceed the vacuum threshold",
+                vec!["shard".to_string()],
+            )
+            .expect("Can register the table_maintenance_tables_needing_vacuum gauge");
+        let settings = Settings {
+            dead_ratio: ENV_VARS.store.vacuum_dead_ratio,
+            max_write_rate: ENV_VARS.store.vacuum_max_write_rate,
+            max_tables: ENV_VARS.store.vacuum_max_tables,
+        };
+        TableMaintenanceJob {
+            store,
+            settings,
+            planners: Mutex::new(HashMap::new()),
+            operations,
+            duration,
+            dead_ratio,
+            tables_needing_vacuum,
+        }
+    }
+}
+
+#[async_trait]
+impl Job for TableMaintenanceJob {
+    fn name(&self) -> &str {
+        "Vacuum and analyze entity tables"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        for (shard, res) in self.store.table_stats().await {
+            let stats = match res {
+                Ok(stats) => stats,
+                Err(e) => {
+                    error!(logger, "Loading entity table statistics failed";
+                                   "shard" => shard.as_str(),
+                                   "error" => e.to_string());
+                    continue;
+                }
+            };
+
+            let live: i64 = stats.iter().map(|table| table.live_tuples).sum();
+            let dead: i64 = stats.iter().map(|table| table.dead_tuples).sum();
+            let ratio = if live + dead == 0 {
+                0.0
+            } else {
+                dead as f64 / (live + dead) as f64
+            };
+            let needing_vacuum = stats
+                .iter()
+                .filter(|table| Planner::needs_vacuum(table, &self.settings))
+                .count();
+            self.dead_ratio
+                .with_label_values(&[shard.as_str()])
+                .set(ratio);
+            self.tables_needing_vacuum
+                .with_label_values(&[shard.as_str()])
+                .set(needing_vacuum as f64);
+
+            let plan = {
+                let mut planners = self.planners.lock().unwrap();
+                let (planner, last) = planners
+                    .entry(shard.clone())
+                    .or_insert_with(|| (Planner::new(), Instant::now()));
+                let plan = planner.plan(stats, last.elapsed(), &self.settings);
+                *last = Instant::now();
+                plan
+            };
+
+            for (table, op) in plan {
+                let start = Instant::now();
+                let res = self
+                    .store
+                    .maintain_table(&shard, op, table.namespace.clone(), table.name.clone())
+                    .await;
+                let elapsed = start.elapsed();
+                self.operations
+                    .with_label_values(&[shard.as_str(), op.as_str()])
+                    .inc();
+                self.duration
+                    .with_label_values(&[shard.as_str(), op.as_str()])
+                    .inc_by(elapsed.as_secs_f64());
+                match res {
+                    Ok(()) => info!(logger, "Maintained entity table";
+                                            "operation" => op.as_str(),
+                                            "shard" => shard.as_str(),
+                                            "table" => format!("{}.{}", table.namespace, table.name),
+                                            "dead_ratio" => format!("{:.2}", table.dead_ratio()),
+                                            "time_ms" => elapsed.as_millis()),
+                    Err(e) => error!(logger, "Maintaining entity table failed";
+                                             "operation" => op.as_str(),
+                                             "shard" => shard.as_str(),
+                                             "table" => format!("{}.{}", table.namespace, table.name),
+                                             "error" => e.to_string()),
+                }
+            }
+        }
+    }
+}
+
+/// A job that removes old changes from the CDC change tables so that they
+/// do not grow without bounds
+struct PruneChangesJob {
+    store: Arc<SubgraphStore>,
+    retention: BlockNumber,
+}
+
+impl PruneChangesJob {
+    fn new(store: Arc<SubgraphStore>, retention: BlockNumber) -> PruneChangesJob {
+        PruneChangesJob { store, retention }
+    }
+}
+
+#[async_trait]
+impl Job for PruneChangesJob {
+    fn name(&self) -> &str {
+        "Prune CDC change tables"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        for res in self.store.prune_changes(self.retention).await {
+            match res {
+                Ok(0) => { /* nothing to do */ }
+                Ok(count) => info!(logger, "Pruned CDC change tables"; "rows" => count),
+                Err(e) => error!(logger, "Pruning CDC change tables failed: {}", e),
+            }
+        }
+    }
+}
+
+/// A job that removes old calls from the call caches of all chains
+/// according to `GRAPH_CALL_CACHE_RETENTION_BLOCKS` and
+/// `GRAPH_CALL_CACHE_MAX_CALLS`
+struct PruneCallCacheJob {
+    store: Arc<BlockStore>,
+}
+
+impl PruneCallCacheJob {
+    fn new(store: Arc<BlockStore>) -> PruneCallCacheJob {
+        PruneCallCacheJob { store }
+    }
+}
+
+#[async_trait]
+impl Job for PruneCallCacheJob {
+    fn name(&self) -> &str {
+        "Prune call caches"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        for (chain, res) in self.store.prune_call_caches(
+            ENV_VARS.store.call_cache_retention_blocks,
+            ENV_VARS.store.call_cache_max_calls,
+        ) {
+            match res {
+                Ok(0) => { /* nothing to do */ }
+                Ok(count) => info!(logger, "Pruned call cache"; "chain" => chain, "calls" => count),
+                Err(e) => error!(logger, "Pruning call cache failed: {}", e; "chain" => chain),
+            }
+        }
+    }
+}
+
+/// A job that removes entity versions that deployments no longer need
+/// because they only keep a limited number of blocks of history
+struct PruneHistoryJob {
+    store: Arc<SubgraphStore>,
+}
+
+impl PruneHistoryJob {
+    fn new(store: Arc<SubgraphStore>) -> PruneHistoryJob {
+        PruneHistoryJob { store }
+    }
+}
+
+#[async_trait]
+impl Job for PruneHistoryJob {
+    fn name(&self) -> &str {
+        "Prune history of deployments"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        for res in self.store.prune_history().await {
+            match res {
+                Ok((deployment, earliest_block, count)) => info!(
+                    logger,
+                    "Pruned history";
+                    "deployment" => deployment.to_string(),
+                    "earliest_block" => earliest_block,
+                    "versions" => count
+                ),
+                Err(e) => error!(logger, "Pruning history failed: {}", e),
+            }
+        }
+    }
+}
+
+/// A job that builds the attribute indexes of deployments that were
+/// created without them once these deployments have caught up with the
+/// chain head
+struct DeferredIndexesJob {
+    store: Arc<SubgraphStore>,
+}
+
+impl DeferredIndexesJob {
+    fn new(store: Arc<SubgraphStore>) -> DeferredIndexesJob {
+        DeferredIndexesJob { store }
+    }
+}
+
+#[async_trait]
+impl Job for DeferredIndexesJob {
+    fn name(&self) -> &str {
+        "Build deferred attribute indexes"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        // Work on building indexes for about 5 minutes; an index that is
+        // being built when the time is up is still finished
+        const BUILD_DEADLINE: Duration = Duration::from_secs(5 * 60);
+
+        let deadline = Instant::now() + BUILD_DEADLINE;
+        for res in self.store.build_deferred_indexes(deadline).await {
+            match res {
+                Ok((deployment, true)) => info!(logger, "Built deferred attribute indexes";
+                                                "deployment" => deployment.to_string()),
+                Ok((_, false)) => { /* continue next time */ }
+                Err(e) => error!(logger, "Building deferred attribute indexes failed: {}", e),
+            }
+        }
+    }
+}
+
+/// A job that moves deployments from busy index nodes to ones with less
+/// load, based on the load that index nodes report
+struct RebalanceJob {
+    store: Arc<SubgraphStore>,
+    max_moves: usize,
+    dry_run: bool,
+}
+
+impl RebalanceJob {
+    fn new(store: Arc<SubgraphStore>, max_moves: usize, dry_run: bool) -> RebalanceJob {
+        RebalanceJob {
+            store,
+            max_moves,
+            dry_run,
+        }
+    }
+}
+
+#[async_trait]
+impl Job for RebalanceJob {
+    fn name(&self) -> &str {
+        "Rebalance deployments across index nodes"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        match self.store.rebalance(self.max_moves, !self.dry_run) {
+            Ok(moves) => {
+                for mv in moves {
+                    info!(logger, "Rebalancing deployment";
+                                  "deployment" => mv.hash.to_string(),
+                                  "from" => mv.from.to_string(),
+                                  "to" => mv.to.to_string(),
+                                  "share" => format!("{:.1}%", mv.share * 100.0),
+                                  "dry_run" => self.dry_run);
+                }
+            }
+            Err(e) => error!(logger, "Rebalancing deployments failed: {}", e),
+        }
+    }
+}
+
+/// A job that unassigns deployments that were replaced by a canary once
+/// they have kept indexing for `GRAPH_CANARY_RETIRE_AFTER`
+struct RetireReplacedJob {
+    store: Arc<SubgraphStore>,
+}
+
+impl RetireReplacedJob {
+    fn new(store: Arc<SubgraphStore>) -> RetireReplacedJob {
+        RetireReplacedJob { store }
+    }
+}
+
+#[async_trait]
+impl Job for RetireReplacedJob {
+    fn name(&self) -> &str {
+        "Retire deployments replaced by canaries"
+    }
+
+    async fn run(&self, logger: &Logger) {
+        match self.store.retire_replaced_deployments() {
+            Ok(0) => { /* nothing to do */ }
+            Ok(count) => {
+                info!(logger, "Retired deployments replaced by canaries"; "count" => count)
+            }
+            Err(e) => error!(logger, "Retiring replaced deployments failed: {}", e),
+        }
+    }
+}
+
 struct NotificationQueueUsage {
     primary: ConnectionPool,
     usage_gauge: Box<Gauge>,
@@ -12,11 +12,16 @@ extern crate diesel_migrations;
 extern crate diesel_derive_enum;
 
 mod advisory_lock;
+mod attribute_usage;
+mod backup;
 mod block_range;
 mod block_store;
 mod catalog;
+mod cdc;
 mod chain_head_listener;
 mod chain_store;
+mod change_feed;
+mod checksum;
 pub mod connection_pool;
 mod copy;
 mod deployment;
@@ -27,16 +32,22 @@ mod fork;
 mod functions;
 mod jobs;
 mod jsonb;
+mod maintenance;
+mod mapping_log;
 mod notification_listener;
 mod primary;
+mod quarantine;
 pub mod query_store;
+mod rebalance;
 mod relational;
 mod relational_queries;
+mod skipped_block;
 mod sql_value;
 mod store;
 mod store_events;
 mod subgraph_store;
 pub mod transaction_receipt;
+mod webhooks;
 mod writable;
 
 #[cfg(debug_assertions)]
@@ -50,13 +61,19 @@ pub mod layout_for_tests {
     pub use crate::relational::*;
 }
 
-pub use self::block_store::BlockStore;
+pub use self::attribute_usage::IndexSuggestion;
+pub use self::backup::{BackupHead, BackupMetadata, ExportFormat};
+pub use self::block_store::{BlockStore, IngestorLease};
+pub use self::catalog::IndexBuild;
 pub use self::chain_head_listener::ChainHeadUpdateListener;
 pub use self::chain_store::ChainStore;
+pub use self::checksum::{Checksums, TableChecksum};
+pub use self::deployment_store::DeferredIndexStatus;
 pub use self::detail::DeploymentDetail;
 pub use self::jobs::register as register_jobs;
 pub use self::notification_listener::NotificationSender;
 pub use self::primary::{db_version, UnusedDeployment};
+pub use self::rebalance::DeploymentMove;
 pub use self::store::Store;
 pub use self::store_events::SubscriptionManager;
 pub use self::subgraph_store::{unused, DeploymentPlacer, Shard, SubgraphStore, PRIMARY_SHARD};
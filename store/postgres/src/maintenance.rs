@@ -0,0 +1,243 @@
+//! Vacuum and analyze entity tables that autovacuum has fallen behind on.
+//! Tables of deployments with a lot of churn accumulate dead tuples faster
+//! than autovacuum removes them. We periodically look at the dead tuples of
+//! every entity table and vacuum the worst ones, but only while they are
+//! not written to much so that the vacuum does not compete with indexing
+use std::collections::HashMap;
+use std::time::Duration;
+
+use diesel::connection::SimpleConnection;
+use diesel::sql_types::{BigInt, Text};
+use diesel::{sql_query, PgConnection, RunQueryDsl};
+use graph::prelude::StoreError;
+
+/// Tables with fewer dead tuples than this are never vacuumed since
+/// autovacuum handles them well enough
+const MIN_DEAD_TUPLES: i64 = 10_000;
+
+/// Analyze tables when more than this share of their rows was modified
+/// since they were last analyzed
+const ANALYZE_RATIO: f64 = 0.1;
+
+/// Tables with fewer modified rows than this are never analyzed
+const MIN_MODIFIED: i64 = 10_000;
+
+/// Statistics about an entity table from `pg_stat_user_tables`
+#[derive(Clone, Debug, QueryableByName)]
+pub struct TableStats {
+    #[sql_type = "Text"]
+    pub namespace: String,
+    #[sql_type = "Text"]
+    pub name: String,
+    #[sql_type = "BigInt"]
+    pub live_tuples: i64,
+    #[sql_type = "BigInt"]
+    pub dead_tuples: i64,
+    #[sql_type = "BigInt"]
+    pub modified_since_analyze: i64,
+    /// The total number of rows ever inserted, updated or deleted
+    #[sql_type = "BigInt"]
+    pub writes: i64,
+}
+
+impl TableStats {
+    pub fn dead_ratio(&self) -> f64 {
+        let total = self.live_tuples + self.dead_tuples;
+        if total == 0 {
+            0.0
+        } else {
+            self.dead_tuples as f64 / total as f64
+        }
+    }
+
+    fn key(&self) -> (String, String) {
+        (self.namespace.clone(), self.name.clone())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operation {
+    /// Vacuum and analyze the table
+    Vacuum,
+    /// Only analyze the table
+    Analyze,
+}
+
+impl Operation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Operation::Vacuum => "vacuum",
+            Operation::Analyze => "analyze",
+        }
+    }
+}
+
+/// The thresholds that decide which tables need maintenance
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub dead_ratio: f64,
+    pub max_write_rate: f64,
+    pub max_tables: usize,
+}
+
+/// Statistics for all entity tables in the shard that `conn` belongs to
+pub fn table_stats(conn: &PgConnection) -> Result<Vec<TableStats>, StoreError> {
+    const QUERY: &str = "
+        select schemaname::text as namespace,
+               relname::text as name,
+               n_live_tup as live_tuples,
+               n_dead_tup as dead_tuples,
+               n_mod_since_analyze as modified_since_analyze,
+               n_tup_ins + n_tup_upd + n_tup_del as writes
+          from pg_stat_user_tables
+         where schemaname like 'sgd%'";
+
+    Ok(sql_query(QUERY).load::<TableStats>(conn)?)
+}
+
+/// Perform `op` on the table `namespace.name`
+pub fn maintain(
+    conn: &PgConnection,
+    op: Operation,
+    namespace: &str,
+    name: &str,
+) -> Result<(), StoreError> {
+    let query = match op {
+        Operation::Vacuum => format!("vacuum (analyze) \"{}\".\"{}\"", namespace, name),
+        Operation::Analyze => format!("analyze \"{}\".\"{}\"", namespace, name),
+    };
+    Ok(conn.batch_execute(&query)?)
+}
+
+/// Decides which tables in one shard need maintenance. It remembers how
+/// many writes each table had the last time we looked to tell how busy the
+/// table is right now
+#[derive(Debug, Default)]
+pub struct Planner {
+    writes: HashMap<(String, String), i64>,
+}
+
+impl Planner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pick the tables that need maintenance from `stats`, which were taken
+    /// `elapsed` after the stats passed in the previous call. Tables that
+    /// we have not seen before are never picked since we can not tell yet
+    /// how busy they are. The tables with the most dead tuples come first,
+    /// followed by the ones that only need to be analyzed
+    pub fn plan(
+        &mut self,
+        stats: Vec<TableStats>,
+        elapsed: Duration,
+        settings: &Settings,
+    ) -> Vec<(TableStats, Operation)> {
+        let secs = elapsed.as_secs_f64().max(1.0);
+
+        let mut vacuum = Vec::new();
+        let mut analyze = Vec::new();
+        let mut writes = HashMap::new();
+        for table in stats {
+            writes.insert(table.key(), table.writes);
+
+            let prev = match self.writes.get(&table.key()) {
+                Some(prev) => *prev,
+                None => continue,
+            };
+            // The counters get reset when the statistics are reset
+            let rate = (table.writes - prev).max(0) as f64 / secs;
+            if rate > settings.max_write_rate {
+                continue;
+            }
+
+            if Self::needs_vacuum(&table, settings) {
+                vacuum.push(table);
+            } else if table.modified_since_analyze >= MIN_MODIFIED
+                && table.modified_since_analyze as f64 >= table.live_tuples as f64 * ANALYZE_RATIO
+            {
+                analyze.push(table);
+            }
+        }
+        self.writes = writes;
+
+        vacuum.sort_by(|a, b| b.dead_tuples.cmp(&a.dead_tuples));
+        analyze.sort_by(|a, b| b.modified_since_analyze.cmp(&a.modified_since_analyze));
+        vacuum
+            .into_iter()
+            .map(|table| (table, Operation::Vacuum))
+            .chain(analyze.into_iter().map(|table| (table, Operation::Analyze)))
+            .take(settings.max_tables)
+            .collect()
+    }
+
+    /// Whether `table` has enough dead tuples that it should be vacuumed,
+    /// regardless of how busy it is
+    pub fn needs_vacuum(table: &TableStats, settings: &Settings) -> bool {
+        table.dead_tuples >= MIN_DEAD_TUPLES && table.dead_ratio() >= settings.dead_ratio
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(name: &str, live: i64, dead: i64, modified: i64, writes: i64) -> TableStats {
+        TableStats {
+            namespace: "sgd1".to_string(),
+            name: name.to_string(),
+            live_tuples: live,
+            dead_tuples: dead,
+            modified_since_analyze: modified,
+            writes,
+        }
+    }
+
+    #[test]
+    fn plan_maintenance() {
+        let settings = Settings {
+            dead_ratio: 0.2,
+            max_write_rate: 100.0,
+            max_tables: 3,
+        };
+        let elapsed = Duration::from_secs(100);
+        let mut planner = Planner::new();
+
+        // Nothing happens the first time since we don't know how busy
+        // tables are
+        let stats = vec![table("a", 100_000, 50_000, 0, 0)];
+        assert!(planner.plan(stats, elapsed, &settings).is_empty());
+
+        let stats = vec![
+            // Lots of dead tuples and quiet
+            table("a", 100_000, 50_000, 0, 1_000),
+            // Lots of dead tuples, but busy
+            table("b", 100_000, 50_000, 0, 1_000_000),
+            // Few dead tuples, but lots of modifications
+            table("c", 100_000, 100, 20_000, 0),
+            // Nothing to do
+            table("d", 100_000, 100, 100, 0),
+            // Even more dead tuples and quiet
+            table("e", 100_000, 90_000, 0, 0),
+        ];
+        let mut prev: Vec<_> = stats.iter().cloned().collect();
+        for table in &mut prev {
+            table.writes = 0;
+        }
+        planner.plan(prev, elapsed, &settings);
+
+        let plan: Vec<_> = planner
+            .plan(stats, elapsed, &settings)
+            .into_iter()
+            .map(|(table, op)| (table.name, op))
+            .collect();
+        assert_eq!(
+            vec![
+                ("e".to_string(), Operation::Vacuum),
+                ("a".to_string(), Operation::Vacuum),
+                ("c".to_string(), Operation::Analyze),
+            ],
+            plan
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! Messages that mappings log with `log.info` etc. We keep the most recent
+//! `GRAPH_STORE_MAPPING_LOG_LIMIT` messages for each deployment so that
+//! subgraph authors can see them through the index-node API, even if they
+//! have no access to the logs of the node that indexes their subgraph.
+use diesel::pg::PgConnection;
+use diesel::prelude::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use diesel::sql_types::{BigInt, Integer};
+use diesel::{delete, insert_into, sql_query};
+
+use graph::data::subgraph::schema::MappingLog;
+use graph::prelude::{slog::Level, BlockNumber, StoreError};
+
+use crate::primary::Site;
+
+table! {
+    subgraphs.mapping_log (id) {
+        id -> BigInt,
+        subgraph_id -> Integer,
+        block_number -> Integer,
+        level -> SmallInt,
+        data_source -> Text,
+        message -> Text,
+    }
+}
+
+fn level_to_i16(level: Level) -> i16 {
+    level.as_usize() as i16
+}
+
+fn level_from_i16(level: i16) -> Level {
+    // Fall back to `Info` for values that we do not know; they can only
+    // come from someone modifying the table by hand
+    Level::from_usize(level as usize).unwrap_or(Level::Info)
+}
+
+/// Store `logs` for the deployment `site` and remove older messages so
+/// that at most `limit` messages are kept
+pub(crate) fn insert(
+    conn: &PgConnection,
+    site: &Site,
+    logs: &[MappingLog],
+    limit: usize,
+) -> Result<(), StoreError> {
+    use mapping_log as ml;
+
+    if logs.is_empty() || limit == 0 {
+        return Ok(());
+    }
+
+    // Messages beyond the limit would be removed right away
+    let skip = logs.len().saturating_sub(limit);
+    let rows: Vec<_> = logs[skip..]
+        .iter()
+        .map(|log| {
+            (
+                ml::subgraph_id.eq(site.id),
+                ml::block_number.eq(log.block_number),
+                ml::level.eq(level_to_i16(log.level)),
+                ml::data_source.eq(&log.data_source),
+                ml::message.eq(&log.message),
+            )
+        })
+        .collect();
+    insert_into(ml::table).values(&rows).execute(conn)?;
+
+    sql_query(
+        "delete from subgraphs.mapping_log
+          where subgraph_id = $1
+            and id <= (select id from subgraphs.mapping_log
+                        where subgraph_id = $1
+                        order by id desc
+                        offset $2 limit 1)",
+    )
+    .bind::<Integer, _>(site.id)
+    .bind::<BigInt, _>(limit as i64)
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Remove the messages logged for blocks starting at `block`
+pub(crate) fn revert(
+    conn: &PgConnection,
+    site: &Site,
+    block: BlockNumber,
+) -> Result<(), StoreError> {
+    use mapping_log as ml;
+
+    delete(
+        ml::table
+            .filter(ml::subgraph_id.eq(site.id))
+            .filter(ml::block_number.ge(block)),
+    )
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Load up to `first` messages for `site` with at least severity `level`
+/// that were logged for `from_block` or later, oldest first
+pub(crate) fn load(
+    conn: &PgConnection,
+    site: &Site,
+    from_block: BlockNumber,
+    level: Level,
+    first: usize,
+) -> Result<Vec<MappingLog>, StoreError> {
+    use mapping_log as ml;
+
+    let logs = ml::table
+        .filter(ml::subgraph_id.eq(site.id))
+        .filter(ml::block_number.ge(from_block))
+        // More severe levels have smaller numbers
+        .filter(ml::level.le(level_to_i16(level)))
+        .order_by(ml::id)
+        .limit(first as i64)
+        .select((ml::block_number, ml::level, ml::data_source, ml::message))
+        .load::<(BlockNumber, i16, String, String)>(conn)?;
+
+    Ok(logs
+        .into_iter()
+        .map(|(block_number, level, data_source, message)| MappingLog {
+            block_number,
+            level: level_from_i16(level),
+            data_source,
+            message,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::prelude::slog::Level;
+
+    use super::{level_from_i16, level_to_i16};
+
+    #[test]
+    fn levels() {
+        for level in [
+            Level::Critical,
+            Level::Error,
+            Level::Warning,
+            Level::Info,
+            Level::Debug,
+        ] {
+            assert_eq!(level, level_from_i16(level_to_i16(level)));
+        }
+        assert!(level_to_i16(Level::Error) < level_to_i16(Level::Info));
+        assert_eq!(Level::Info, level_from_i16(42));
+    }
+}
@@ -7,7 +7,7 @@ use diesel::{
     dsl::{any, exists, not, select},
     pg::Pg,
     serialize::Output,
-    sql_types::{Array, Integer, Text},
+    sql_types::{Array, Bool, Integer, Text},
     types::{FromSql, ToSql},
 };
 use diesel::{
@@ -22,10 +22,6 @@ use diesel::{
     },
     Connection as _,
 };
-use graph::{
-    components::store::DeploymentId as GraphDeploymentId,
-    prelude::{chrono, CancelHandle, CancelToken},
-};
 use graph::{
     components::store::DeploymentLocator,
     constraint_violation,
@@ -35,7 +31,14 @@ use graph::{
         EntityChangeOperation, NodeId, StoreError, SubgraphName, SubgraphVersionSwitchingMode,
     },
 };
-use graph::{data::subgraph::schema::generate_entity_id, prelude::StoreEvent};
+use graph::{
+    components::store::{DeploymentId as GraphDeploymentId, DeploymentLoad},
+    prelude::{chrono, CancelHandle, CancelToken},
+};
+use graph::{
+    data::subgraph::schema::{generate_entity_id, DeploymentPriority},
+    prelude::StoreEvent,
+};
 use itertools::Itertools;
 use maybe_owned::MaybeOwned;
 use std::{
@@ -44,13 +47,15 @@ use std::{
     convert::TryInto,
     fmt,
     io::Write,
-    time::{SystemTime, UNIX_EPOCH},
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use crate::{
     block_range::UNVERSIONED_RANGE,
     connection_pool::{ConnectionPool, ForeignServer},
     detail::DeploymentDetail,
+    rebalance::{AssignedLoad, IndexNode},
     subgraph_store::{unused, Shard, PRIMARY_SHARD},
     NotificationSender,
 };
@@ -92,6 +97,26 @@ table! {
     subgraphs.subgraph_deployment_assignment {
         id -> Integer,
         node_id -> Text,
+        paused_at -> Nullable<Timestamptz>,
+        priority -> Nullable<Text>,
+        retire_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    subgraphs.index_node_load(node_id) {
+        node_id -> Text,
+        networks -> Array<Text>,
+        reported_at -> Timestamptz,
+    }
+}
+
+table! {
+    subgraphs.deployment_load(id) {
+        id -> Integer,
+        node_id -> Text,
+        blocks_per_minute -> Double,
+        db_seconds_per_minute -> Double,
     }
 }
 
@@ -114,6 +139,13 @@ table! {
     }
 }
 
+table! {
+    public.persisted_queries(hash) {
+        hash -> Varchar,
+        query -> Text,
+    }
+}
+
 /// We used to support different layout schemes. The old 'Split' scheme
 /// which used JSONB layout has been removed, and we will only deal
 /// with relational layout. Trying to do anything with a 'Split' subgraph
@@ -185,6 +217,8 @@ allow_tables_to_appear_in_same_query!(
     deployment_schemas,
     unused_deployments,
     active_copies,
+    index_node_load,
+    deployment_load,
 );
 
 /// Information about the database schema that stores the entities for a
@@ -575,10 +609,12 @@ mod queries {
         schema.map(|schema| schema.try_into()).transpose()
     }
 
+    /// The deployments that are assigned to `node` and not paused
     pub(super) fn assignments(conn: &PgConnection, node: &NodeId) -> Result<Vec<Site>, StoreError> {
         ds::table
             .inner_join(a::table.on(a::id.eq(ds::id)))
             .filter(a::node_id.eq(node.as_str()))
+            .filter(a::paused_at.is_null())
             .select(ds::all_columns)
             .load::<Schema>(conn)?
             .into_iter()
@@ -604,6 +640,52 @@ mod queries {
         Ok(())
     }
 
+    /// The node that `site` is assigned to and whether the deployment is
+    /// paused
+    pub(super) fn assignment_status(
+        conn: &PgConnection,
+        site: &Site,
+    ) -> Result<Option<(NodeId, bool)>, StoreError> {
+        a::table
+            .filter(a::id.eq(site.id))
+            .select((a::node_id, a::paused_at.is_not_null()))
+            .first::<(String, bool)>(conn)
+            .optional()?
+            .map(|(node, paused)| {
+                let node = NodeId::new(&node).map_err(|()| {
+                    constraint_violation!(
+                        "invalid node id `{}` in assignment for `{}`",
+                        node,
+                        site.deployment
+                    )
+                })?;
+                Ok((node, paused))
+            })
+            .transpose()
+    }
+
+    pub(super) fn assignment_priority(
+        conn: &PgConnection,
+        site: &Site,
+    ) -> Result<Option<DeploymentPriority>, StoreError> {
+        a::table
+            .filter(a::id.eq(site.id))
+            .select(a::priority)
+            .first::<Option<String>>(conn)
+            .optional()?
+            .flatten()
+            .map(|priority| {
+                DeploymentPriority::from_str(&priority).map_err(|_| {
+                    constraint_violation!(
+                        "invalid priority `{}` in assignment for `{}`",
+                        priority,
+                        site.deployment
+                    )
+                })
+            })
+            .transpose()
+    }
+
     pub(super) fn assigned_node(
         conn: &PgConnection,
         site: &Site,
@@ -727,9 +809,15 @@ impl<'a> Connection<'a> {
             .filter(a::id.eq(ds::id))
             .select(ds::id);
 
-        let removed = delete(a::table.filter(not(exists(named))))
-            .returning(a::id)
-            .load::<i32>(self.conn.as_ref())?;
+        // Deployments that were replaced by a canary keep their assignment
+        // until they are retired
+        let removed = delete(
+            a::table
+                .filter(not(exists(named)))
+                .filter(sql::<Bool>("coalesce(retire_at <= now(), true)")),
+        )
+        .returning(a::id)
+        .load::<i32>(self.conn.as_ref())?;
 
         let removed: Vec<_> = ds::table
             .filter(ds::id.eq_any(removed))
@@ -763,16 +851,49 @@ impl<'a> Connection<'a> {
         Ok(events)
     }
 
+    /// Unassign deployments that were replaced by a canary once their time
+    /// to retire has come. Return the changes that were made to assignments
+    pub fn retire_replaced_deployments(&self) -> Result<Vec<EntityChange>, StoreError> {
+        self.remove_unused_assignments()
+    }
+
     /// Promote the deployment `id` to the current version everywhere where it was
     /// the pending version so far, and remove any assignments that are not needed
     /// any longer as a result. Return the changes that were made to assignments
-    /// in the process
-    pub fn promote_deployment(&self, id: &DeploymentHash) -> Result<Vec<EntityChange>, StoreError> {
+    /// in the process. With `retire_after`, the deployments that were the
+    /// current version keep their assignment for that long instead of being
+    /// unassigned right away
+    pub fn promote_deployment(
+        &self,
+        id: &DeploymentHash,
+        retire_after: Option<Duration>,
+    ) -> Result<Vec<EntityChange>, StoreError> {
+        use deployment_schemas as ds;
         use subgraph as s;
+        use subgraph_deployment_assignment as a;
         use subgraph_version as v;
 
         let conn = self.conn.as_ref();
 
+        if let Some(retire_after) = retire_after {
+            let replaced = s::table
+                .inner_join(v::table.on(s::current_version.eq(v::id.nullable())))
+                .inner_join(ds::table.on(v::deployment.eq(ds::subgraph)))
+                .filter(
+                    s::pending_version.eq_any(
+                        v::table
+                            .filter(v::deployment.eq(id.as_str()))
+                            .select(v::id.nullable()),
+                    ),
+                )
+                .filter(ds::subgraph.ne(id.as_str()))
+                .select(ds::id);
+            let retire_at = format!("now() + interval '{} seconds'", retire_after.as_secs());
+            update(a::table.filter(a::id.eq_any(replaced)))
+                .set(a::retire_at.eq(sql(&retire_at)))
+                .execute(conn)?;
+        }
+
         // Subgraphs where we need to promote the version
         let pending_subgraph_versions: Vec<(String, String)> = s::table
             .inner_join(v::table.on(s::pending_version.eq(v::id.nullable())))
@@ -893,10 +1014,12 @@ impl<'a> Connection<'a> {
 
         // Check if we even need to make any changes
         let change_needed = match (mode, current_exists_and_synced) {
-            (Instant, _) | (Synced, false) => {
+            (Instant, _) | (Synced | Canary, false) => {
                 current_deployment.as_deref() != Some(site.deployment.as_str())
             }
-            (Synced, true) => pending_deployment.as_deref() != Some(site.deployment.as_str()),
+            (Synced | Canary, true) => {
+                pending_deployment.as_deref() != Some(site.deployment.as_str())
+            }
         };
         if !change_needed {
             return Ok(vec![]);
@@ -934,7 +1057,7 @@ impl<'a> Connection<'a> {
         // overwrite the current version
         let new_exists_and_synced = exists_and_synced(&site.deployment)?;
         match (mode, current_exists_and_synced, new_exists_and_synced) {
-            (Instant, _, _) | (Synced, false, _) | (Synced, true, true) => {
+            (Instant, _, _) | (Synced | Canary, false, _) | (Synced | Canary, true, true) => {
                 subgraph_row
                     .set((
                         s::current_version.eq(&version_id),
@@ -942,7 +1065,7 @@ impl<'a> Connection<'a> {
                     ))
                     .execute(conn)?;
             }
-            (Synced, true, false) => {
+            (Synced | Canary, true, false) => {
                 subgraph_row
                     .set(s::pending_version.eq(&version_id))
                     .execute(conn)?;
@@ -1005,6 +1128,64 @@ impl<'a> Connection<'a> {
         }
     }
 
+    /// Pause indexing `site` without changing which node it is assigned
+    /// to. Pausing a deployment that is already paused does nothing. It is
+    /// an error if the deployment is not assigned to any node
+    pub fn pause_subgraph(&self, site: &Site) -> Result<Vec<EntityChange>, StoreError> {
+        use subgraph_deployment_assignment as a;
+
+        let conn = self.conn.as_ref();
+        match queries::assignment_status(conn, site)? {
+            None => Err(StoreError::DeploymentNotFound(site.deployment.to_string())),
+            Some((_, true)) => Ok(vec![]),
+            Some((_, false)) => {
+                update(a::table.filter(a::id.eq(site.id)))
+                    .set(a::paused_at.eq(sql("now()")))
+                    .execute(conn)?;
+                let change =
+                    EntityChange::for_assignment(site.into(), EntityChangeOperation::Removed);
+                Ok(vec![change])
+            }
+        }
+    }
+
+    /// Resume indexing `site` after it was paused. Resuming a deployment
+    /// that is not paused does nothing. It is an error if the deployment
+    /// is not assigned to any node
+    pub fn resume_subgraph(&self, site: &Site) -> Result<Vec<EntityChange>, StoreError> {
+        use subgraph_deployment_assignment as a;
+
+        let conn = self.conn.as_ref();
+        match queries::assignment_status(conn, site)? {
+            None => Err(StoreError::DeploymentNotFound(site.deployment.to_string())),
+            Some((_, false)) => Ok(vec![]),
+            Some((_, true)) => {
+                update(a::table.filter(a::id.eq(site.id)))
+                    .set(a::paused_at.eq(sql("null")))
+                    .execute(conn)?;
+                let change = EntityChange::for_assignment(site.into(), EntityChangeOperation::Set);
+                Ok(vec![change])
+            }
+        }
+    }
+
+    /// Set the priority with which `site` is synced, overriding any hint
+    /// in its manifest, or remove the override if `priority` is `None`.
+    /// Return `false` if the deployment is not assigned to any node
+    pub fn set_priority(
+        &self,
+        site: &Site,
+        priority: Option<DeploymentPriority>,
+    ) -> Result<bool, StoreError> {
+        use subgraph_deployment_assignment as a;
+
+        let conn = self.conn.as_ref();
+        let count = update(a::table.filter(a::id.eq(site.id)))
+            .set(a::priority.eq(priority.map(|priority| priority.as_str())))
+            .execute(conn)?;
+        Ok(count > 0)
+    }
+
     pub fn assign_subgraph(
         &self,
         site: &Site,
@@ -1491,6 +1672,154 @@ impl<'a> Connection<'a> {
             .map_err(|e| anyhow!("error looking up ens_name for hash {}: {}", hash, e).into())
     }
 
+    pub fn register_persisted_query(&self, hash: &str, query: &str) -> Result<(), StoreError> {
+        use persisted_queries as pq;
+
+        insert_into(pq::table)
+            .values((pq::hash.eq(hash), pq::query.eq(query)))
+            .on_conflict_do_nothing()
+            .execute(self.conn.as_ref())?;
+        Ok(())
+    }
+
+    pub fn remove_persisted_query(&self, hash: &str) -> Result<bool, StoreError> {
+        use persisted_queries as pq;
+
+        let count = delete(pq::table.filter(pq::hash.eq(hash))).execute(self.conn.as_ref())?;
+        Ok(count > 0)
+    }
+
+    /// Return the hash and text of all persisted queries
+    pub fn persisted_queries(&self) -> Result<Vec<(String, String)>, StoreError> {
+        use persisted_queries as pq;
+
+        pq::table
+            .select((pq::hash, pq::query))
+            .load::<(String, String)>(self.conn.as_ref())
+            .map_err(|e| anyhow!("error loading persisted queries: {}", e).into())
+    }
+
+    /// Replace the load report for `node` with `loads` and record that
+    /// `node` can index deployments for `networks`
+    pub fn report_load(
+        &self,
+        node: &NodeId,
+        networks: Vec<String>,
+        loads: Vec<DeploymentLoad>,
+    ) -> Result<(), StoreError> {
+        use deployment_load as dl;
+        use diesel::upsert::excluded;
+        use index_node_load as n;
+
+        let conn = self.conn.as_ref();
+        let rows: Vec<_> = loads
+            .iter()
+            .map(|load| {
+                (
+                    dl::id.eq(load.deployment.0),
+                    dl::node_id.eq(node.as_str()),
+                    dl::blocks_per_minute.eq(load.blocks_per_minute),
+                    dl::db_seconds_per_minute.eq(load.db_seconds_per_minute),
+                )
+            })
+            .collect();
+
+        self.transaction(|| {
+            insert_into(n::table)
+                .values((
+                    n::node_id.eq(node.as_str()),
+                    n::networks.eq(&networks),
+                    n::reported_at.eq(sql("now()")),
+                ))
+                .on_conflict(n::node_id)
+                .do_update()
+                .set((n::networks.eq(&networks), n::reported_at.eq(sql("now()"))))
+                .execute(conn)?;
+
+            delete(dl::table.filter(dl::node_id.eq(node.as_str()))).execute(conn)?;
+            // A deployment that was just reassigned might still show up in
+            // the report of its previous node; the report that comes in
+            // last wins
+            insert_into(dl::table)
+                .values(rows)
+                .on_conflict(dl::id)
+                .do_update()
+                .set((
+                    dl::node_id.eq(excluded(dl::node_id)),
+                    dl::blocks_per_minute.eq(excluded(dl::blocks_per_minute)),
+                    dl::db_seconds_per_minute.eq(excluded(dl::db_seconds_per_minute)),
+                ))
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+
+    /// The index nodes that reported their load within the last `max_age`,
+    /// and the load of deployments that are assigned to a node and not
+    /// paused. Loads are only returned if they were reported by the node
+    /// to which the deployment is assigned now
+    pub fn load_reports(
+        &self,
+        max_age: Duration,
+    ) -> Result<(Vec<IndexNode>, Vec<AssignedLoad>), StoreError> {
+        use deployment_load as dl;
+        use deployment_schemas as ds;
+        use index_node_load as n;
+        use subgraph_deployment_assignment as a;
+
+        fn node_id(id: String) -> Result<NodeId, StoreError> {
+            NodeId::new(id.clone()).map_err(|()| constraint_violation!("invalid node id `{}`", id))
+        }
+
+        let conn = self.conn.as_ref();
+        let since = chrono::Duration::from_std(max_age)
+            .ok()
+            .and_then(|age| chrono::offset::Local::now().checked_sub_signed(age))
+            .ok_or_else(|| constraint_violation!("duration {:?} is too large", max_age))?;
+
+        let nodes = n::table
+            .filter(n::reported_at.gt(since))
+            .select((n::node_id, n::networks))
+            .load::<(String, Vec<String>)>(conn)?
+            .into_iter()
+            .map(|(id, networks)| {
+                Ok(IndexNode {
+                    id: node_id(id)?,
+                    networks,
+                })
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
+
+        let loads = dl::table
+            .inner_join(a::table.on(a::id.eq(dl::id).and(a::node_id.eq(dl::node_id))))
+            .inner_join(ds::table.on(ds::id.eq(dl::id)))
+            .filter(a::paused_at.is_null())
+            .select((
+                dl::id,
+                ds::subgraph,
+                ds::network,
+                dl::node_id,
+                dl::blocks_per_minute,
+                dl::db_seconds_per_minute,
+            ))
+            .load::<(i32, String, String, String, f64, f64)>(conn)?
+            .into_iter()
+            .map(|(id, hash, network, node, blocks, db)| {
+                Ok(AssignedLoad {
+                    deployment: GraphDeploymentId::new(id),
+                    hash: DeploymentHash::new(hash)
+                        .map_err(|s| constraint_violation!("Invalid deployment id {}", s))?,
+                    network,
+                    node: node_id(node)?,
+                    blocks_per_minute: blocks,
+                    db_seconds_per_minute: db,
+                })
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
+
+        Ok((nodes, loads))
+    }
+
     pub fn record_active_copy(&self, src: &Site, dst: &Site) -> Result<(), StoreError> {
         use active_copies as cp;
 
@@ -1506,6 +1835,34 @@ impl<'a> Connection<'a> {
         Ok(())
     }
 
+    /// Return `true` if copying data into `dst` has not finished yet,
+    /// either because it is still queued or running, or because it was
+    /// cancelled
+    pub fn is_copying(&self, dst: &Site) -> Result<bool, StoreError> {
+        use active_copies as cp;
+
+        Ok(select(exists(cp::table.filter(cp::dst.eq(dst.id))))
+            .get_result::<bool>(self.conn.as_ref())?)
+    }
+
+    /// Make `dst` the active deployment for its deployment hash and remove
+    /// the assignment for `src` so that it stops indexing. If `node` is
+    /// given, `dst` is also moved to that node. Callers should run this in
+    /// a transaction so that queries and indexing switch over together
+    pub fn switch_to_copy(
+        &self,
+        src: &Site,
+        dst: &Site,
+        node: Option<&NodeId>,
+    ) -> Result<Vec<EntityChange>, StoreError> {
+        self.activate(&dst.into())?;
+        let mut changes = self.unassign_subgraph(src)?;
+        if let Some(node) = node {
+            changes.extend(self.reassign_subgraph(dst, node)?);
+        }
+        Ok(changes)
+    }
+
     pub fn copy_finished(&self, dst: &Site) -> Result<(), StoreError> {
         use active_copies as cp;
 
@@ -1659,6 +2016,17 @@ impl Mirror {
         self.read(|conn| queries::assigned_node(conn, site))
     }
 
+    pub fn assignment_status(&self, site: &Site) -> Result<Option<(NodeId, bool)>, StoreError> {
+        self.read(|conn| queries::assignment_status(conn, site))
+    }
+
+    pub fn assignment_priority(
+        &self,
+        site: &Site,
+    ) -> Result<Option<DeploymentPriority>, StoreError> {
+        self.read(|conn| queries::assignment_priority(conn, site))
+    }
+
     pub fn find_active_site(&self, subgraph: &DeploymentHash) -> Result<Option<Site>, StoreError> {
         self.read(|conn| queries::find_active_site(conn, subgraph))
     }
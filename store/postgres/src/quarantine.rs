@@ -0,0 +1,129 @@
+//! Data sources that failed with a deterministic error while
+//! `GRAPH_QUARANTINE_DATA_SOURCES` was set. Their handlers are not run
+//! anymore for later blocks so that the rest of the deployment can continue
+//! indexing. Records are written together with the block that caused the
+//! failure and are removed when that block is reverted.
+use std::collections::HashMap;
+
+use diesel::pg::PgConnection;
+use diesel::prelude::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use diesel::{delete, insert_into};
+
+use graph::data::subgraph::schema::QuarantinedDataSource;
+use graph::prelude::{BlockNumber, StoreError};
+
+use crate::primary::{DeploymentId, Site};
+
+table! {
+    subgraphs.quarantined_data_source (id) {
+        id -> Integer,
+        subgraph_id -> Integer,
+        block_number -> Integer,
+        name -> Text,
+        address -> Nullable<Binary>,
+        message -> Text,
+        created_at -> Timestamptz,
+    }
+}
+
+type Row = (String, Option<Vec<u8>>, BlockNumber, String);
+
+fn from_row((name, address, block_number, message): Row) -> QuarantinedDataSource {
+    QuarantinedDataSource {
+        name,
+        address,
+        block_number,
+        message,
+    }
+}
+
+pub(crate) fn insert(
+    conn: &PgConnection,
+    site: &Site,
+    quarantined: &[QuarantinedDataSource],
+) -> Result<(), StoreError> {
+    use quarantined_data_source as q;
+
+    if quarantined.is_empty() {
+        return Ok(());
+    }
+
+    let rows: Vec<_> = quarantined
+        .iter()
+        .map(|ds| {
+            (
+                q::subgraph_id.eq(site.id),
+                q::block_number.eq(ds.block_number),
+                q::name.eq(&ds.name),
+                q::address.eq(ds.address.as_deref()),
+                q::message.eq(&ds.message),
+            )
+        })
+        .collect();
+    insert_into(q::table).values(rows).execute(conn)?;
+    Ok(())
+}
+
+/// Remove the data sources that were quarantined at `block` or later
+pub(crate) fn revert(
+    conn: &PgConnection,
+    site: &Site,
+    block: BlockNumber,
+) -> Result<(), StoreError> {
+    use quarantined_data_source as q;
+
+    delete(
+        q::table
+            .filter(q::subgraph_id.eq(site.id))
+            .filter(q::block_number.ge(block)),
+    )
+    .execute(conn)?;
+    Ok(())
+}
+
+/// The data sources of `site` that are quarantined, ordered by the block at
+/// which they failed
+pub(crate) fn load(
+    conn: &PgConnection,
+    site: &Site,
+) -> Result<Vec<QuarantinedDataSource>, StoreError> {
+    use quarantined_data_source as q;
+
+    Ok(q::table
+        .filter(q::subgraph_id.eq(site.id))
+        .order_by((q::block_number, q::id))
+        .select((q::name, q::address, q::block_number, q::message))
+        .load::<Row>(conn)?
+        .into_iter()
+        .map(from_row)
+        .collect())
+}
+
+/// The quarantined data sources for each of the deployments with `ids`, or
+/// for all deployments if `ids` is empty
+pub(crate) fn load_for_deployments(
+    conn: &PgConnection,
+    ids: &[DeploymentId],
+) -> Result<HashMap<DeploymentId, Vec<QuarantinedDataSource>>, StoreError> {
+    use quarantined_data_source as q;
+
+    let query = q::table
+        .order_by((q::subgraph_id, q::block_number, q::id))
+        .select((
+            q::subgraph_id,
+            (q::name, q::address, q::block_number, q::message),
+        ));
+    let rows = if ids.is_empty() {
+        query.load::<(DeploymentId, Row)>(conn)?
+    } else {
+        query
+            .filter(q::subgraph_id.eq_any(ids.iter().copied()))
+            .load::<(DeploymentId, Row)>(conn)?
+    };
+
+    let mut quarantined: HashMap<_, Vec<_>> = HashMap::new();
+    for (id, row) in rows {
+        quarantined.entry(id).or_default().push(from_row(row));
+    }
+    Ok(quarantined)
+}
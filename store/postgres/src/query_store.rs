@@ -1,4 +1,5 @@
 use std::collections::BTreeMap;
+use std::sync::Mutex;
 
 use web3::types::H256;
 
@@ -8,11 +9,37 @@ use graph::prelude::*;
 
 use crate::primary::Site;
 
+/// The SQL for one query that was sent to the database together with the
+/// output of `explain (analyze, buffers)` for it
+#[derive(Clone, Debug)]
+pub struct ExplainedQuery {
+    pub sql: String,
+    pub plan: Vec<String>,
+}
+
+/// Collects the explanations of all the queries that a `QueryStore` sends
+/// to the database, in the order in which they were sent
+#[derive(Debug, Default)]
+pub struct QueryExplanations(Mutex<Vec<ExplainedQuery>>);
+
+impl QueryExplanations {
+    fn push(&self, explained: ExplainedQuery) {
+        self.0.lock().unwrap().push(explained);
+    }
+
+    /// Return all explanations collected so far and clear them
+    pub fn take(&self) -> Vec<ExplainedQuery> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
 pub(crate) struct QueryStore {
     site: Arc<Site>,
     replica_id: ReplicaId,
     store: Arc<DeploymentStore>,
     chain_store: Arc<crate::ChainStore>,
+    /// If set, explain every entity query before running it
+    explanations: Option<Arc<QueryExplanations>>,
 }
 
 impl QueryStore {
@@ -21,12 +48,14 @@ impl QueryStore {
         chain_store: Arc<crate::ChainStore>,
         site: Arc<Site>,
         replica_id: ReplicaId,
+        explanations: Option<Arc<QueryExplanations>>,
     ) -> Self {
         QueryStore {
             site,
             replica_id,
             store,
             chain_store,
+            explanations,
         }
     }
 }
@@ -42,9 +71,44 @@ impl QueryStoreTrait for QueryStore {
             .store
             .get_replica_conn(self.replica_id)
             .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        if let Some(explanations) = &self.explanations {
+            let explained = self
+                .store
+                .explain_query(&conn, self.site.clone(), query.clone())?;
+            explanations.push(explained);
+        }
         self.store.execute_query(&conn, self.site.clone(), query)
     }
 
+    fn stream_query_values(
+        &self,
+        query: EntityQuery,
+        batch_size: usize,
+        sink: &mut dyn FnMut(Vec<BTreeMap<String, r::Value>>) -> bool,
+    ) -> Result<(), QueryExecutionError> {
+        assert_eq!(&self.site.deployment, &query.subgraph_id);
+        let conn = self
+            .store
+            .get_replica_conn(self.replica_id)
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        self.store
+            .execute_cursor_query(&conn, self.site.clone(), query, batch_size, sink)
+    }
+
+    fn aggregate_query_values(
+        &self,
+        query: EntityQuery,
+        aggregates: &[EntityAggregate],
+    ) -> Result<Vec<r::Value>, QueryExecutionError> {
+        assert_eq!(&self.site.deployment, &query.subgraph_id);
+        let conn = self
+            .store
+            .get_replica_conn(self.replica_id)
+            .map_err(|e| QueryExecutionError::StoreError(e.into()))?;
+        self.store
+            .execute_aggregate_query(&conn, self.site.clone(), query, aggregates)
+    }
+
     /// Return true if the deployment with the given id is fully synced,
     /// and return false otherwise. Errors from the store are passed back up
     async fn is_deployment_synced(&self) -> Result<bool, Error> {
@@ -81,6 +145,23 @@ impl QueryStoreTrait for QueryStore {
             .transpose()
     }
 
+    fn block_number_by_timestamp(&self, timestamp: u64) -> Result<Option<BlockNumber>, StoreError> {
+        let subgraph_network = self.network_name();
+        self.chain_store
+            .block_number_by_timestamp(timestamp)?
+            .map(|(network_name, number)| {
+                if network_name == subgraph_network {
+                    Ok(number)
+                } else {
+                    Err(StoreError::QueryExecutionError(format!(
+                        "subgraph {} belongs to network {} but the block for timestamp {} belongs to network {}",
+                        &self.site.deployment, subgraph_network, timestamp, network_name
+                    )))
+                }
+            })
+            .transpose()
+    }
+
     fn wait_stats(&self) -> PoolWaitStats {
         self.store.wait_stats(self.replica_id)
     }
@@ -94,6 +175,15 @@ impl QueryStoreTrait for QueryStore {
             .await
     }
 
+    async fn skipped_blocks(&self, block: BlockNumber) -> Result<Vec<BlockNumber>, StoreError> {
+        let site = self.site.clone();
+        self.store
+            .with_conn(move |conn, _| {
+                crate::skipped_block::block_numbers(conn, &site, block).map_err(|e| e.into())
+            })
+            .await
+    }
+
     async fn deployment_state(&self) -> Result<DeploymentState, QueryExecutionError> {
         Ok(self
             .store
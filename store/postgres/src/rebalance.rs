@@ -0,0 +1,205 @@
+//! Plan how to move deployments between index nodes so that the load that
+//! indexing causes is spread evenly across them. The load of a deployment
+//! is its share of all blocks processed per minute and of all the time
+//! spent writing to the database per minute, giving both equal weight.
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use graph::components::store::DeploymentId;
+use graph::prelude::{DeploymentHash, NodeId};
+
+/// Moves that improve the balance between two nodes by less than this
+/// share of the total load are not worth the interruption of restarting a
+/// deployment
+const MIN_IMPROVEMENT: f64 = 0.02;
+
+/// An index node that reported its load recently
+#[derive(Clone, Debug)]
+pub struct IndexNode {
+    pub id: NodeId,
+    /// The networks for which the node can index deployments
+    pub networks: Vec<String>,
+}
+
+/// The load of a deployment as reported by the node it is assigned to
+#[derive(Clone, Debug)]
+pub struct AssignedLoad {
+    pub deployment: DeploymentId,
+    pub hash: DeploymentHash,
+    pub network: String,
+    pub node: NodeId,
+    pub blocks_per_minute: f64,
+    pub db_seconds_per_minute: f64,
+}
+
+/// Reassign `hash` from node `from` to node `to`
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeploymentMove {
+    pub deployment: DeploymentId,
+    pub hash: DeploymentHash,
+    pub from: NodeId,
+    pub to: NodeId,
+    /// The deployment's share of the total load, between 0 and 1
+    pub share: f64,
+}
+
+/// Plan at most `max_moves` moves that even out the load across `nodes`.
+/// Each move takes a deployment from the node with the most load and gives
+/// it to the node with the least load among the ones that index the
+/// deployment's network, picking the deployment that brings the two nodes
+/// closest to each other. Deployments on nodes that are not in `nodes`
+/// are left alone
+pub fn plan(nodes: &[IndexNode], loads: &[AssignedLoad], max_moves: usize) -> Vec<DeploymentMove> {
+    let total_blocks: f64 = loads.iter().map(|load| load.blocks_per_minute).sum();
+    let total_db: f64 = loads.iter().map(|load| load.db_seconds_per_minute).sum();
+    let share = |load: &AssignedLoad| {
+        let blocks = if total_blocks > 0.0 {
+            load.blocks_per_minute / total_blocks
+        } else {
+            0.0
+        };
+        let db = if total_db > 0.0 {
+            load.db_seconds_per_minute / total_db
+        } else {
+            0.0
+        };
+        (blocks + db) / 2.0
+    };
+
+    let mut node_load: HashMap<&NodeId, f64> = nodes.iter().map(|node| (&node.id, 0.0)).collect();
+    let mut placed: Vec<(&AssignedLoad, &NodeId, f64)> = Vec::new();
+    for load in loads {
+        if let Some(total) = node_load.get_mut(&load.node) {
+            let share = share(load);
+            *total += share;
+            placed.push((load, &load.node, share));
+        }
+    }
+
+    let mut moves = Vec::new();
+    while moves.len() < max_moves {
+        let busiest = match node_load
+            .iter()
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+            .map(|(node, _)| *node)
+        {
+            Some(node) => node,
+            None => break,
+        };
+
+        // Find the deployment on the busiest node whose move improves the
+        // balance the most
+        let mut best: Option<(usize, &NodeId, f64)> = None;
+        for (idx, (load, node, share)) in placed.iter().enumerate() {
+            if *node != busiest || *share <= 0.0 {
+                continue;
+            }
+            let target = nodes
+                .iter()
+                .filter(|n| &n.id != busiest && n.networks.contains(&load.network))
+                .min_by(|a, b| {
+                    node_load[&a.id]
+                        .partial_cmp(&node_load[&b.id])
+                        .unwrap_or(Ordering::Equal)
+                });
+            let target = match target {
+                Some(target) => &target.id,
+                None => continue,
+            };
+            let gap = node_load[busiest] - node_load[target];
+            let improvement = gap - (gap - 2.0 * share).abs();
+            if improvement >= MIN_IMPROVEMENT
+                && best.map_or(true, |(_, _, best)| improvement > best)
+            {
+                best = Some((idx, target, improvement));
+            }
+        }
+
+        let (idx, target, _) = match best {
+            Some(best) => best,
+            None => break,
+        };
+        let (load, from, share) = placed[idx];
+        *node_load.get_mut(from).unwrap() -= share;
+        *node_load.get_mut(target).unwrap() += share;
+        placed[idx].1 = target;
+        moves.push(DeploymentMove {
+            deployment: load.deployment,
+            hash: load.hash.clone(),
+            from: from.clone(),
+            to: target.clone(),
+            share,
+        });
+    }
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(name: &str, networks: &[&str]) -> IndexNode {
+        IndexNode {
+            id: NodeId::new(name).unwrap(),
+            networks: networks.iter().map(|n| n.to_string()).collect(),
+        }
+    }
+
+    fn load(id: i32, network: &str, node: &str, blocks: f64, db: f64) -> AssignedLoad {
+        AssignedLoad {
+            deployment: DeploymentId(id),
+            hash: DeploymentHash::new(format!("Qm{}", id)).unwrap(),
+            network: network.to_string(),
+            node: NodeId::new(node).unwrap(),
+            blocks_per_minute: blocks,
+            db_seconds_per_minute: db,
+        }
+    }
+
+    #[test]
+    fn moves_from_busy_to_idle_nodes() {
+        let nodes = vec![node("a", &["mainnet"]), node("b", &["mainnet"])];
+        let loads = vec![
+            load(1, "mainnet", "a", 100.0, 10.0),
+            load(2, "mainnet", "a", 100.0, 10.0),
+            load(3, "mainnet", "a", 10.0, 1.0),
+        ];
+
+        let moves = plan(&nodes, &loads, 5);
+        assert_eq!(1, moves.len());
+        assert_eq!(NodeId::new("a").unwrap(), moves[0].from);
+        assert_eq!(NodeId::new("b").unwrap(), moves[0].to);
+        assert!(moves[0].deployment == DeploymentId(1) || moves[0].deployment == DeploymentId(2));
+
+        // Of two equal deployments, only one moves
+        assert_eq!(1, plan(&nodes, &loads[0..2], 5).len());
+        assert!(plan(&nodes, &loads, 0).is_empty());
+    }
+
+    #[test]
+    fn respects_networks_and_healthy_nodes() {
+        let nodes = vec![node("a", &["mainnet"]), node("b", &["gnosis"])];
+        let loads = vec![
+            load(1, "mainnet", "a", 100.0, 10.0),
+            load(2, "mainnet", "a", 100.0, 10.0),
+        ];
+        assert!(plan(&nodes, &loads, 5).is_empty());
+
+        // Node `c` did not report and is therefore not healthy
+        let loads = vec![
+            load(1, "gnosis", "c", 100.0, 10.0),
+            load(2, "gnosis", "c", 100.0, 10.0),
+        ];
+        assert!(plan(&nodes, &loads, 5).is_empty());
+    }
+
+    #[test]
+    fn balanced_nodes_stay_put() {
+        let nodes = vec![node("a", &["mainnet"]), node("b", &["mainnet"])];
+        let loads = vec![
+            load(1, "mainnet", "a", 100.0, 10.0),
+            load(2, "mainnet", "b", 90.0, 11.0),
+        ];
+        assert!(plan(&nodes, &loads, 5).is_empty());
+    }
+}
@@ -11,8 +11,9 @@ use diesel::{debug_query, OptionalExtension, PgConnection, RunQueryDsl};
 use graph::cheap_clone::CheapClone;
 use graph::constraint_violation;
 use graph::data::graphql::{DirectiveExt, TypeExt as _};
-use graph::prelude::{q, s, StopwatchMetrics, ENV_VARS};
+use graph::prelude::{q, r, s, StopwatchMetrics, ENV_VARS};
 use graph::slog::warn;
+use graph::util::otel::Span;
 use inflector::Inflector;
 use lazy_static::lazy_static;
 use std::borrow::Cow;
@@ -23,23 +24,32 @@ use std::str::FromStr;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-use crate::relational_queries::{FindChangesQuery, FindPossibleDeletionsQuery};
+use crate::relational_queries::{
+    ChangedBlock, ChangedBlocksQuery, FindChangesQuery, FindPossibleDeletionsQuery,
+};
 use crate::{
     primary::{Namespace, Site},
+    query_store::ExplainedQuery,
     relational_queries::{
-        ClampRangeQuery, ConflictingEntityQuery, EntityData, EntityDeletion, FilterCollection,
-        FilterQuery, FindManyQuery, FindQuery, InsertQuery, RevertClampQuery, RevertRemoveQuery,
+        AggregateData, AggregateQuery, ClampRangeQuery, ConflictingEntityQuery, CopyInQuery,
+        DeclareCursorQuery, EntityData, EntityDeletion, ExistingId, ExistingIdQuery, ExplainQuery,
+        FilterCollection, FilterQuery, FindManyQuery, FindQuery, InsertQuery, PruneQuery,
+        ReturnedEntityData, RevertClampQuery, RevertRemoveQuery, RollupQuery,
     },
 };
-use graph::components::store::EntityType;
+use graph::components::store::{EntityType, PruneReporter};
 use graph::data::graphql::ext::{DirectiveFinder, DocumentExt, ObjectTypeExt};
-use graph::data::schema::{FulltextConfig, FulltextDefinition, Schema, SCHEMA_TYPE_NAME};
+use graph::data::schema::{
+    AggregationDefinition, FulltextConfig, FulltextDefinition, Schema, SCHEMA_TYPE_NAME,
+    TIMESTAMP_FIELD,
+};
 use graph::data::store::BYTES_SCALAR;
 use graph::data::subgraph::schema::{POI_OBJECT, POI_TABLE};
 use graph::prelude::{
-    anyhow, info, BlockNumber, DeploymentHash, Entity, EntityChange, EntityCollection,
-    EntityFilter, EntityKey, EntityOperation, EntityOrder, EntityRange, Logger,
-    QueryExecutionError, StoreError, StoreEvent, ValueType, BLOCK_NUMBER_MAX,
+    anyhow, info, AggregateFunction, BlockNumber, DeploymentHash, Entity, EntityAggregate,
+    EntityChange, EntityCollection, EntityFilter, EntityKey, EntityModification, EntityOperation,
+    EntityOrder, EntityRange, Logger, QueryExecutionError, StoreError, StoreEvent, Value,
+    ValueType, BLOCK_NUMBER_MAX,
 };
 
 use crate::block_range::{BLOCK_COLUMN, BLOCK_RANGE_COLUMN};
@@ -204,6 +214,9 @@ pub struct Layout {
     pub enums: EnumMap,
     /// The query to count all entities
     pub count_query: String,
+    /// The `@aggregation` types in the schema, whose entities are rollups
+    /// of timeseries
+    pub aggregations: Vec<AggregationDefinition>,
 }
 
 impl Layout {
@@ -328,6 +341,7 @@ impl Layout {
             tables,
             enums,
             count_query,
+            aggregations: schema.aggregations(),
         })
     }
 
@@ -348,6 +362,7 @@ impl Layout {
                     fulltext_fields: None,
                     is_reference: false,
                     use_prefix_comparison: false,
+                    indexed: true,
                 },
                 Column {
                     name: SqlName::from(PRIMARY_KEY_COLUMN),
@@ -359,6 +374,7 @@ impl Layout {
                     fulltext_fields: None,
                     is_reference: false,
                     use_prefix_comparison: false,
+                    indexed: true,
                 },
             ],
             /// The position of this table in all the tables for this layout; this
@@ -367,6 +383,8 @@ impl Layout {
             position: position as u32,
             is_account_like: false,
             immutable: false,
+            partition_blocks: None,
+            partitions: Arc::new(Mutex::new(BTreeSet::new())),
         }
     }
 
@@ -405,6 +423,121 @@ impl Layout {
             .collect()
     }
 
+    /// Determine if the data for `base` can be turned into data for `self`
+    /// without reindexing, i.e., if `self` only adds entity types, enums
+    /// and nullable attributes to `base`. If that is possible, return the
+    /// DDL that adds the new tables and columns; otherwise, return the
+    /// reasons why it is not possible
+    pub fn additive_ddl(&self, base: &Layout) -> Result<String, Vec<String>> {
+        let mut errors = Vec::new();
+        let mut ddl = String::new();
+
+        for (name, values) in &self.enums {
+            match base.enums.get(name) {
+                Some(base_values) if base_values != values => errors.push(format!(
+                    "The values of the enum {} can not be changed",
+                    name
+                )),
+                Some(_) => { /* unchanged */ }
+                None => {
+                    let values = values
+                        .iter()
+                        .map(|value| format!("'{}'", value))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    writeln!(
+                        ddl,
+                        "create type {}.{}\n    as enum ({});",
+                        self.catalog.site.namespace,
+                        SqlName::from(name.as_str()).quoted(),
+                        values
+                    )
+                    .expect("writing to a string does not fail");
+                }
+            }
+        }
+        for name in base.enums.keys() {
+            if !self.enums.contains_key(name) {
+                errors.push(format!("The enum {} can not be removed", name));
+            }
+        }
+
+        let mut base_tables = base.tables.values().collect::<Vec<_>>();
+        base_tables.sort_by_key(|table| table.position);
+        for base_table in base_tables {
+            match self.tables.get(&base_table.object) {
+                Some(table) if table.immutable != base_table.immutable => errors.push(format!(
+                    "The entity type {} can not change whether it is immutable",
+                    table.object
+                )),
+                Some(_) => { /* checked below */ }
+                None => errors.push(format!(
+                    "The entity type {} can not be removed",
+                    base_table.object
+                )),
+            }
+        }
+
+        let mut tables = self.tables.values().collect::<Vec<_>>();
+        tables.sort_by_key(|table| table.position);
+        for table in tables {
+            let base_table = match base.tables.get(&table.object) {
+                Some(base_table) => base_table,
+                None => {
+                    table
+                        .as_ddl(&mut ddl, self)
+                        .expect("writing to a string does not fail");
+                    continue;
+                }
+            };
+            for base_column in &base_table.columns {
+                match table.column(&base_column.name) {
+                    Some(column)
+                        if column.field_type != base_column.field_type
+                            || column.column_type != base_column.column_type =>
+                    {
+                        errors.push(format!(
+                            "The attribute {}.{} can not change its type from {} to {}",
+                            table.object, column.field, base_column.field_type, column.field_type
+                        ))
+                    }
+                    Some(_) => { /* unchanged */ }
+                    None => errors.push(format!(
+                        "The attribute {}.{} can not be removed",
+                        table.object, base_column.field
+                    )),
+                }
+            }
+            for column in &table.columns {
+                if base_table.column(&column.name).is_some() {
+                    continue;
+                }
+                if !column.is_nullable() {
+                    errors.push(format!(
+                        "The new attribute {}.{} must be nullable",
+                        table.object, column.field
+                    ));
+                } else if column.is_fulltext() {
+                    errors.push(format!(
+                        "The fulltext field {}.{} can not be added to existing data",
+                        table.object, column.field
+                    ));
+                } else {
+                    write!(ddl, "alter table {} add column", table.qualified_name)
+                        .and_then(|()| column.as_ddl(&mut ddl))
+                        .and_then(|()| writeln!(ddl, ";"))
+                        .expect("writing to a string does not fail");
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(ddl)
+        } else {
+            Err(errors)
+        }
+    }
+
     fn write_enum_ddl(&self, out: &mut dyn Write) -> Result<(), fmt::Error> {
         for (name, values) in &self.enums {
             let mut sep = "";
@@ -447,6 +580,27 @@ impl Layout {
         Ok(out)
     }
 
+    /// Generate the statements that build the indexes on entity attributes
+    /// for all tables, one statement per index. The indexes are built
+    /// concurrently wherever possible so that building them does not block
+    /// writes. This is used for deployments that deferred building these
+    /// indexes when they were created
+    pub fn attribute_index_ddl(&self) -> Result<Vec<String>, fmt::Error> {
+        let mut tables = self.tables.values().collect::<Vec<_>>();
+        tables.sort_by_key(|table| table.position);
+
+        let mut out = String::new();
+        for table in tables {
+            create_attribute_indexes(table, &mut out, self, true)?;
+        }
+        Ok(out
+            .split(";\n")
+            .map(str::trim)
+            .filter(|stmt| !stmt.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
     /// Import the database schema for this layout from its own database
     /// shard (in `self.site.shard`) into the database represented by `conn`
     /// if the schema for this layout does not exist yet
@@ -554,12 +708,65 @@ impl Layout {
             }
         }
 
-        let inserts_or_updates =
-            FindChangesQuery::new(&self.catalog.site.namespace, &tables[..], block)
-                .load::<EntityData>(conn)?;
-        let deletions =
-            FindPossibleDeletionsQuery::new(&self.catalog.site.namespace, &tables[..], block)
-                .load::<EntityDeletion>(conn)?;
+        self.find_changes_in_tables(conn, &tables, block)
+    }
+
+    /// Find the changes to entities of the given `entity_types` for all
+    /// blocks in the range `[from, to]` that changed any of them, keyed by
+    /// block number. Entity types that this layout does not know about
+    /// are ignored
+    pub fn find_changes_in_range(
+        &self,
+        conn: &PgConnection,
+        entity_types: &[EntityType],
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<BTreeMap<BlockNumber, Vec<EntityOperation>>, StoreError> {
+        let tables: Vec<_> = entity_types
+            .iter()
+            .filter_map(|entity_type| self.tables.get(entity_type))
+            .map(|table| &**table)
+            .collect();
+
+        let mut changes = BTreeMap::new();
+        if tables.is_empty() || from > to {
+            return Ok(changes);
+        }
+
+        let blocks = ChangedBlocksQuery::new(&tables, from, to).load::<ChangedBlock>(conn)?;
+        for ChangedBlock { block } in blocks {
+            // Order the changes in each block deterministically
+            let mut ops = self.find_changes_in_tables(conn, &tables, block)?;
+            ops.sort_by(|a, b| a.key().cmp(b.key()));
+            changes.insert(block, ops);
+        }
+        Ok(changes)
+    }
+
+    fn find_changes_in_tables(
+        &self,
+        conn: &PgConnection,
+        tables: &[&Table],
+        block: BlockNumber,
+    ) -> Result<Vec<EntityOperation>, StoreError> {
+        let mutable: Vec<_> = tables
+            .iter()
+            .copied()
+            .filter(|table| !table.immutable)
+            .collect();
+
+        let inserts_or_updates = if tables.is_empty() {
+            vec![]
+        } else {
+            FindChangesQuery::new(&self.catalog.site.namespace, tables, block)
+                .load::<EntityData>(conn)?
+        };
+        let deletions = if mutable.is_empty() {
+            vec![]
+        } else {
+            FindPossibleDeletionsQuery::new(&self.catalog.site.namespace, &mutable, block)
+                .load::<EntityDeletion>(conn)?
+        };
 
         let mut processed_entities = HashSet::new();
         let mut changes = Vec::new();
@@ -604,6 +811,9 @@ impl Layout {
         Ok(changes)
     }
 
+    /// Insert `entities` at `block`. With a `copy` connection, they are
+    /// written with `COPY` if the table supports that; see `CopyInQuery`
+    /// for how that differs from a normal insert
     pub fn insert<'a>(
         &'a self,
         conn: &PgConnection,
@@ -611,9 +821,34 @@ impl Layout {
         entities: &'a mut [(&'a EntityKey, Cow<'a, Entity>)],
         block: BlockNumber,
         stopwatch: &StopwatchMetrics,
+        copy: Option<&mut postgres::Client>,
     ) -> Result<usize, StoreError> {
         let table = self.table_for_entity(entity_type)?;
         let _section = stopwatch.start_section("insert_modification_insert_query");
+        if table.is_partitioned() {
+            table.create_partitions(conn, &self.site.namespace, Some(block))?;
+
+            // Unique constraints on partitioned tables must contain the
+            // partition key, and the database therefore can not make sure
+            // that ids are unique
+            let ids = entities
+                .iter()
+                .map(|(key, _)| key.entity_id.as_str())
+                .collect();
+            let existing = ExistingIdQuery::new(table, ids).load::<ExistingId>(conn)?;
+            if let Some(existing) = existing.first() {
+                return Err(StoreError::Unknown(anyhow!(
+                    "duplicate key value violates unique constraint: \
+                     an entity of type `{}` with id `{}` already exists",
+                    entity_type,
+                    existing.id
+                )));
+            }
+        }
+        if let Some(client) = copy.filter(|_| CopyInQuery::supports(table)) {
+            return CopyInQuery::new(table, entities, block)?.copy_in(client);
+        }
+
         let mut count = 0;
         // Each operation must respect the maximum number of bindings allowed in PostgreSQL queries,
         // so we need to act in chunks whose size is defined by the number of entities times the
@@ -682,9 +917,10 @@ impl Layout {
             );
         }
 
-        let filter_collection = FilterCollection::new(self, collection, filter.as_ref())?;
+        let filter_collection = FilterCollection::new(self, collection, filter.as_ref(), block)?;
         let query = FilterQuery::new(
             &filter_collection,
+            self,
             filter.as_ref(),
             order,
             range,
@@ -693,6 +929,12 @@ impl Layout {
         )?;
         let query_clone = query.clone();
 
+        let mut span = Span::new("sql_query")
+            .attr("db.system", "postgresql")
+            .attr("block.number", block);
+        if span.is_recording() {
+            span.set_attr("db.statement", debug_query(&query_clone));
+        }
         let start = Instant::now();
         let values = conn
             .transaction(|| {
@@ -716,7 +958,13 @@ impl Layout {
                     e,
                     debug_query(&query_clone).to_string()
                 )),
+            })
+            .map_err(|e| {
+                span.set_error(&e);
+                e
             })?;
+        span.set_attr("entity_count", values.len());
+        span.end();
         log_query_timing(logger, &query_clone, start.elapsed(), values.len());
         values
             .into_iter()
@@ -728,6 +976,273 @@ impl Layout {
             .collect()
     }
 
+    /// Run `explain (analyze, buffers)` for the SQL query that `query`
+    /// would run with the same arguments, and return that SQL together
+    /// with the query plan. Since the query is actually run, this takes at
+    /// least as long as running the query itself
+    pub fn explain(
+        &self,
+        conn: &PgConnection,
+        collection: EntityCollection,
+        filter: Option<EntityFilter>,
+        order: EntityOrder,
+        range: EntityRange,
+        block: BlockNumber,
+        query_id: Option<String>,
+    ) -> Result<ExplainedQuery, QueryExecutionError> {
+        let filter_collection = FilterCollection::new(self, collection, filter.as_ref(), block)?;
+        let query = FilterQuery::new(
+            &filter_collection,
+            self,
+            filter.as_ref(),
+            order,
+            range,
+            block,
+            query_id,
+        )?;
+        let sql = debug_query(&query).to_string();
+
+        let plan = conn
+            .transaction(|| {
+                if let Some(ref timeout_sql) = *STATEMENT_TIMEOUT {
+                    conn.batch_execute(timeout_sql)?;
+                }
+                ExplainQuery::new(query).load::<String>(conn)
+            })
+            .map_err(|e| {
+                QueryExecutionError::ResolveEntitiesError(format!("{}, query = {}", e, sql))
+            })?;
+        Ok(ExplainedQuery { sql, plan })
+    }
+
+    /// Like `query`, but read the matching entities through a cursor and
+    /// pass them to `sink` in batches of up to `batch_size` entities as
+    /// they are fetched so that they never all have to be held in memory.
+    /// Stops early if `sink` returns `false`, which releases the cursor
+    pub fn query_cursor<T: crate::relational_queries::FromEntityData>(
+        &self,
+        logger: &Logger,
+        conn: &PgConnection,
+        collection: EntityCollection,
+        filter: Option<EntityFilter>,
+        order: EntityOrder,
+        range: EntityRange,
+        block: BlockNumber,
+        query_id: Option<String>,
+        batch_size: usize,
+        sink: &mut dyn FnMut(Vec<T>) -> bool,
+    ) -> Result<(), QueryExecutionError> {
+        const CURSOR: &str = "export_cursor";
+
+        let filter_collection = FilterCollection::new(self, collection, filter.as_ref(), block)?;
+        let query = FilterQuery::new(
+            &filter_collection,
+            self,
+            filter.as_ref(),
+            order,
+            range,
+            block,
+            query_id,
+        )?;
+        let query_text = debug_query(&query).to_string();
+
+        let start = Instant::now();
+        let mut entity_count = 0;
+        conn.transaction::<_, StoreError, _>(|| {
+            if let Some(ref timeout_sql) = *STATEMENT_TIMEOUT {
+                conn.batch_execute(timeout_sql)?;
+            }
+            DeclareCursorQuery::new(CURSOR, query).execute(conn)?;
+            let fetch = format!("fetch forward {} from {}", batch_size.max(1), CURSOR);
+            loop {
+                let values = diesel::sql_query(&fetch).load::<EntityData>(conn)?;
+                if values.is_empty() {
+                    return Ok(());
+                }
+                entity_count += values.len();
+                let batch = values
+                    .into_iter()
+                    .map(|entity_data| entity_data.deserialize_with_layout(self))
+                    .collect::<Result<Vec<T>, _>>()?;
+                if !sink(batch) {
+                    return Ok(());
+                }
+            }
+        })
+        .map_err(|e| {
+            QueryExecutionError::ResolveEntitiesError(format!("{}, query = {}", e, query_text))
+        })?;
+
+        if ENV_VARS.log_sql_timing() {
+            info!(
+                logger,
+                "Query timing (SQL cursor)";
+                "query" => query_text.replace("\n", "\t"),
+                "time_ms" => start.elapsed().as_millis(),
+                "entity_count" => entity_count
+            );
+        }
+        Ok(())
+    }
+
+    /// Compute `aggregates` over the entities in `collection` that match
+    /// `filter`. Aggregates can only be computed for a single entity type
+    pub fn aggregate(
+        &self,
+        logger: &Logger,
+        conn: &PgConnection,
+        collection: EntityCollection,
+        filter: Option<EntityFilter>,
+        aggregates: &[EntityAggregate],
+        block: BlockNumber,
+        query_id: Option<String>,
+    ) -> Result<Vec<r::Value>, QueryExecutionError> {
+        let entity_type = match &collection {
+            EntityCollection::All(entities) if entities.len() == 1 => &entities[0].0,
+            _ => {
+                return Err(QueryExecutionError::NotSupported(
+                    "aggregates can only be computed over a single entity type".to_string(),
+                ))
+            }
+        };
+        let table = self.table_for_entity(entity_type)?;
+        let query = AggregateQuery::new(self, table, filter.as_ref(), aggregates, block, query_id)?;
+
+        let start = Instant::now();
+        let data = conn
+            .transaction(|| {
+                if let Some(ref timeout_sql) = *STATEMENT_TIMEOUT {
+                    conn.batch_execute(timeout_sql)?;
+                }
+                query.clone().get_result::<AggregateData>(conn)
+            })
+            .map_err(|e| {
+                QueryExecutionError::ResolveEntitiesError(format!(
+                    "{}, query = {}",
+                    e,
+                    debug_query(&query).to_string()
+                ))
+            })?;
+        if ENV_VARS.log_sql_timing() {
+            info!(
+                logger,
+                "Query timing (SQL)";
+                "query" => debug_query(&query).to_string().replace("\n", "\t"),
+                "time_ms" => start.elapsed().as_millis(),
+                "entity_count" => 1
+            );
+        }
+        query.values(data).map_err(|e| e.into())
+    }
+
+    /// Write the rollups of all aggregations whose source has new points
+    /// in `mods` and return them as insertions. Points must be added in
+    /// timestamp order, and a rollup is written once, as soon as a point
+    /// past the end of its interval arrives, from all the points of its
+    /// source in that interval. `mods` must therefore already have been
+    /// written
+    pub fn rollups(
+        &self,
+        conn: &PgConnection,
+        mods: &[EntityModification],
+        block: BlockNumber,
+    ) -> Result<Vec<EntityModification>, StoreError> {
+        let mut rollups = Vec::new();
+        for aggregation in &self.aggregations {
+            let mut timestamps = Vec::new();
+            for modification in mods {
+                let (key, point) = match modification {
+                    EntityModification::Insert { key, data }
+                        if key.entity_type == aggregation.source =>
+                    {
+                        (key, data)
+                    }
+                    _ => continue,
+                };
+                match point.get(TIMESTAMP_FIELD) {
+                    Some(Value::Int(timestamp)) => timestamps.push(*timestamp as i64),
+                    _ => {
+                        return Err(constraint_violation!(
+                            "timeseries entity {}[{}] has no timestamp",
+                            key.entity_type,
+                            key.entity_id
+                        ))
+                    }
+                }
+            }
+            let (new_min, new_max) = match (timestamps.iter().min(), timestamps.iter().max()) {
+                (Some(new_min), Some(new_max)) => (*new_min, *new_max),
+                _ => continue,
+            };
+
+            let source = self.table_for_entity(&aggregation.source)?;
+            let table = self.table_for_entity(&aggregation.entity_type)?;
+
+            // The latest point before this block; its intervals were still
+            // open when it was written
+            let latest = EntityAggregate {
+                function: AggregateFunction::Max,
+                attribute: Some(TIMESTAMP_FIELD.to_string()),
+            };
+            let query = AggregateQuery::new(self, source, None, &[latest], block - 1, None)?;
+            let data = query.clone().get_result::<AggregateData>(conn)?;
+            let prev_max = match query.values(data)?.pop() {
+                Some(r::Value::Int(prev_max)) => Some(prev_max),
+                _ => None,
+            };
+            if let Some(prev_max) = prev_max.filter(|prev_max| new_min < *prev_max) {
+                return Err(StoreError::Unknown(anyhow!(
+                    "points of timeseries {} must be added in timestamp order, \
+                     but a point with timestamp {} was added after one with timestamp {}",
+                    aggregation.source,
+                    new_min,
+                    prev_max
+                )));
+            }
+            let lower = prev_max.unwrap_or(new_min);
+
+            let mut ids = Vec::new();
+            for interval in &aggregation.intervals {
+                let clamp = |timestamp: i64| timestamp.clamp(i32::MIN as i64, i32::MAX as i64);
+                let start = clamp(interval.start(lower)) as i32;
+                let end = clamp(interval.start(new_max)) as i32;
+                if start >= end {
+                    continue;
+                }
+                table.create_partitions(conn, &self.site.namespace, Some(block))?;
+                let query =
+                    RollupQuery::new(source, table, aggregation, *interval, start, end, block)?;
+                ids.extend(
+                    query
+                        .get_results::<ReturnedEntityData>(conn)?
+                        .into_iter()
+                        .map(|data| data.id),
+                );
+            }
+            if ids.is_empty() {
+                continue;
+            }
+
+            let ids_for_type = BTreeMap::from_iter(Some((
+                &aggregation.entity_type,
+                ids.iter().map(String::as_str).collect(),
+            )));
+            for data in self
+                .find_many(conn, &ids_for_type, block)?
+                .into_values()
+                .flatten()
+            {
+                let key = EntityKey {
+                    subgraph_id: self.site.deployment.clone(),
+                    entity_type: aggregation.entity_type.clone(),
+                    entity_id: data.id()?,
+                };
+                rollups.push(EntityModification::Insert { key, data });
+            }
+        }
+        Ok(rollups)
+    }
+
     pub fn update<'a>(
         &'a self,
         conn: &PgConnection,
@@ -801,6 +1316,27 @@ impl Layout {
     /// numbers. After this operation, only entity versions inserted or
     /// updated at blocks with numbers strictly lower than `block` will
     /// remain
+    /// Remove all entity versions that start at `block` or later from
+    /// the tables that are written with `COPY`. Entities that are written
+    /// with `COPY` are committed before the rest of their block, and a
+    /// write that failed can leave them behind; `block` must be the block
+    /// after the deployment head so that only such leftovers are removed
+    pub fn remove_uncommitted(
+        &self,
+        conn: &PgConnection,
+        block: BlockNumber,
+    ) -> Result<usize, StoreError> {
+        let mut count = 0;
+        for table in self
+            .tables
+            .values()
+            .filter(|table| CopyInQuery::supports(table))
+        {
+            count += RevertRemoveQuery::new(table, block).execute(conn)?;
+        }
+        Ok(count)
+    }
+
     pub fn revert_block(
         &self,
         conn: &PgConnection,
@@ -856,6 +1392,53 @@ impl Layout {
         Ok((StoreEvent::new(changes), count))
     }
 
+    /// Delete all entity versions that are not visible at `earliest_block`
+    /// or any later block, and return how many versions were deleted.
+    /// Versions are deleted in batches of `batch_size`, each in its own
+    /// transaction, so that pruning does not hold locks for long and does
+    /// not block writes to the deployment. Since every batch is committed
+    /// right away, pruning that was interrupted continues where it left off
+    /// when it is started again. Immutable entities are never updated or
+    /// deleted and therefore never need to be pruned. The proof of indexing
+    /// is kept for all blocks since indexers need to be able to produce it
+    /// for any block the deployment has processed
+    pub fn prune(
+        &self,
+        conn: &PgConnection,
+        earliest_block: BlockNumber,
+        batch_size: i64,
+        reporter: &mut dyn PruneReporter,
+    ) -> Result<usize, StoreError> {
+        let mut tables: Vec<_> = self
+            .tables
+            .values()
+            // Immutable entities that were created before `earliest_block`
+            // are still current at every later block
+            .filter(|table| !table.immutable && table.object != *POI_OBJECT)
+            .collect();
+        tables.sort_by(|a, b| a.name.as_str().cmp(b.name.as_str()));
+
+        reporter.start(earliest_block);
+        let mut count = 0;
+        for table in tables {
+            let (versions, bytes) = catalog::table_size(conn, &self.site.namespace, &table.name)?;
+            reporter.start_table(table.name.as_str(), versions, bytes);
+            let mut table_count = 0;
+            loop {
+                let deleted = PruneQuery::new(table, earliest_block, batch_size).execute(conn)?;
+                table_count += deleted;
+                reporter.prune_batch(table.name.as_str(), table_count);
+                if (deleted as i64) < batch_size {
+                    break;
+                }
+            }
+            reporter.finish_table(table.name.as_str(), table_count);
+            count += table_count;
+        }
+        reporter.finish(count);
+        Ok(count)
+    }
+
     /// Revert the metadata (dynamic data sources and related entities) for
     /// the given `subgraph`.
     ///
@@ -872,6 +1455,14 @@ impl Layout {
         Ok(())
     }
 
+    /// Forget which partitions of partitioned tables exist. This must be
+    /// called when a transaction that wrote entities is rolled back
+    pub(crate) fn forget_partitions(&self) {
+        for table in self.tables.values() {
+            table.forget_partitions();
+        }
+    }
+
     pub fn is_cacheable(&self) -> bool {
         // This would be false if we still needed to migrate the Layout, but
         // since there are no migrations in the code right now, it is always
@@ -1048,12 +1639,16 @@ pub struct Column {
     /// Whether to use a prefix of the column for comparisons and index
     /// creation, or column values in their entirety
     pub use_prefix_comparison: bool,
+    /// Whether the column gets an attribute index. Subgraphs can turn
+    /// indexes off with `@index` and `@noIndex` directives in their schema
+    indexed: bool,
 }
 
 impl Column {
     fn new(
         table_name: &SqlName,
         field: &s::Field,
+        indexed: bool,
         catalog: &Catalog,
         enums: &EnumMap,
         id_types: &IdTypeMap,
@@ -1102,6 +1697,7 @@ impl Column {
             fulltext_fields: None,
             is_reference,
             use_prefix_comparison,
+            indexed,
         })
     }
 
@@ -1117,6 +1713,7 @@ impl Column {
             fulltext_fields: Some(def.included_fields.clone()),
             is_reference: false,
             use_prefix_comparison: false,
+            indexed: true,
         })
     }
 
@@ -1138,6 +1735,16 @@ impl Column {
         self.field_type.is_list()
     }
 
+    /// Whether aggregates like `sum` or `avg` can be computed over this
+    /// column
+    pub fn is_numeric(&self) -> bool {
+        !self.is_list()
+            && matches!(
+                self.column_type,
+                ColumnType::Int | ColumnType::BigInt | ColumnType::BigDecimal
+            )
+    }
+
     pub fn is_enum(&self) -> bool {
         matches!(self.column_type, ColumnType::Enum(_))
     }
@@ -1234,6 +1841,15 @@ pub struct Table {
     /// Entities in this table are immutable, i.e., will never be updated or
     /// deleted
     pub(crate) immutable: bool,
+
+    /// If set, the table is partitioned by block number and each partition
+    /// holds the entities created in this many blocks. Only tables for
+    /// immutable entities are ever partitioned
+    pub(crate) partition_blocks: Option<BlockNumber>,
+
+    /// The partitions of a partitioned table that we know exist, so that
+    /// we only issue DDL when writes reach a new partition
+    partitions: Arc<Mutex<BTreeSet<i64>>>,
 }
 
 impl Table {
@@ -1252,7 +1868,10 @@ impl Table {
             .fields
             .iter()
             .filter(|field| !field.is_derived())
-            .map(|field| Column::new(&table_name, field, catalog, enums, id_types))
+            .map(|field| {
+                let indexed = defn.is_indexed(field);
+                Column::new(&table_name, field, indexed, catalog, enums, id_types)
+            })
             .chain(fulltexts.iter().map(Column::new_fulltext))
             .collect::<Result<Vec<Column>, StoreError>>()?;
         let qualified_name = SqlName::qualified_name(&catalog.site.namespace, &table_name);
@@ -1261,14 +1880,24 @@ impl Table {
             .account_tables
             .contains(qualified_name.as_str());
 
-        let immutable = defn
-            .find_directive("entity")
-            .and_then(|dir| dir.argument("immutable"))
-            .map(|value| match value {
-                q::Value::Boolean(b) => *b,
-                _ => false,
-            })
-            .unwrap_or(false);
+        // Points in a timeseries and the rollups of aggregations are never
+        // changed once they are written
+        let immutable = defn.is_timeseries()
+            || defn.is_aggregation()
+            || defn
+                .find_directive("entity")
+                .and_then(|dir| dir.argument("immutable"))
+                .map(|value| match value {
+                    q::Value::Boolean(b) => *b,
+                    _ => false,
+                })
+                .unwrap_or(false);
+
+        let partition_blocks = if immutable {
+            catalog.partition_blocks
+        } else {
+            None
+        };
 
         let table = Table {
             object: EntityType::from(defn),
@@ -1278,6 +1907,8 @@ impl Table {
             columns,
             position,
             immutable,
+            partition_blocks,
+            partitions: Arc::new(Mutex::new(BTreeSet::new())),
         };
         Ok(table)
     }
@@ -1330,6 +1961,64 @@ impl Table {
             .expect("every table has a primary key")
     }
 
+    pub fn is_partitioned(&self) -> bool {
+        self.partition_blocks.is_some()
+    }
+
+    /// Create the partitions that will hold entities created in `blocks`
+    /// unless they already exist. Does nothing if the table is not
+    /// partitioned
+    pub(crate) fn create_partitions(
+        &self,
+        conn: &PgConnection,
+        namespace: &Namespace,
+        blocks: impl IntoIterator<Item = BlockNumber>,
+    ) -> Result<(), StoreError> {
+        let size = match self.partition_blocks {
+            Some(size) => size as i64,
+            None => return Ok(()),
+        };
+
+        let mut known = self.partitions.lock().unwrap();
+        let partitions: BTreeSet<i64> = blocks
+            .into_iter()
+            .map(|block| block as i64 / size)
+            .filter(|partition| !known.contains(partition))
+            .collect();
+        if partitions.is_empty() {
+            return Ok(());
+        }
+
+        let mut ddl = String::new();
+        for partition in &partitions {
+            let upper = (partition + 1) * size;
+            let upper = if upper > BLOCK_NUMBER_MAX as i64 {
+                "maxvalue".to_string()
+            } else {
+                upper.to_string()
+            };
+            ddl.push_str(&format!(
+                "create table if not exists {nsp}.\"{name}_p{partition}\"\n    \
+                 partition of {nsp}.{qname} for values from ({lower}) to ({upper});\n",
+                nsp = namespace,
+                name = self.name,
+                qname = self.name.quoted(),
+                partition = partition,
+                lower = partition * size,
+                upper = upper
+            ));
+        }
+        conn.batch_execute(&ddl)?;
+        known.extend(partitions);
+        Ok(())
+    }
+
+    /// Forget which partitions exist. This must be called when a
+    /// transaction that might have created partitions is rolled back
+    pub(crate) fn forget_partitions(&self) {
+        self.partitions.lock().unwrap().clear();
+    }
+
     /// Generate the DDL for one table, i.e. one `create table` statement
     /// and all `create index` statements for the table's columns
     ///
@@ -1347,7 +2036,30 @@ impl Table {
         }
 
         fn create_table(table: &Table, out: &mut String, layout: &Layout) -> fmt::Result {
-            if table.immutable {
+            if table.is_partitioned() {
+                // Postgres requires that unique constraints on partitioned
+                // tables contain the partition key; `Layout::insert` checks
+                // that ids are unique instead. Partitions are created as
+                // entities are written, see `Table::create_partitions`
+                writeln!(
+                    out,
+                    r#"
+                create table {nsp}.{name} (
+                    {vid}                  bigserial,
+                    {block}                int not null,
+                    {cols}
+                    primary key({vid}, {block}),
+                    unique({id}, {block})
+                ) partition by range({block});
+                "#,
+                    nsp = layout.catalog.site.namespace,
+                    name = table.name.quoted(),
+                    cols = columns_ddl(table)?,
+                    vid = VID_COLUMN,
+                    block = BLOCK_COLUMN,
+                    id = table.primary_key().name
+                )
+            } else if table.immutable {
                 writeln!(
                     out,
                     r#"
@@ -1443,88 +2155,133 @@ impl Table {
             }
         }
 
-        fn create_attribute_indexes(
-            table: &Table,
-            out: &mut String,
-            layout: &Layout,
-        ) -> fmt::Result {
-            // Create indexes. Skip columns whose type is an array of enum,
-            // since there is no good way to index them with Postgres 9.6.
-            // Once we move to Postgres 11, we can enable that
-            // (tracked in graph-node issue #1330)
-            for (i, column) in table
-                .columns
-                .iter()
-                .filter(|col| !(col.is_list() && col.is_enum()))
-                .enumerate()
-            {
-                if table.immutable && column.is_primary_key() {
-                    // We create a unique index on `id` in `create_table`
-                    // and don't need an explicit attribute index
-                    continue;
+        create_table(self, out, layout)?;
+        create_time_travel_indexes(self, out, layout)?;
+        if layout.catalog.defer_indexes {
+            writeln!(out)
+        } else {
+            create_attribute_indexes(self, out, layout, false)
+        }
+    }
+}
+
+/// The index method and the expression that the attribute index on
+/// `column` uses
+pub(crate) fn attribute_index_method(table: &Table, column: &Column) -> (&'static str, String) {
+    if column.is_reference() && !column.is_list() {
+        // For foreign keys, index the key together with the block range
+        // since we almost always also have a block_range clause in
+        // queries that look for specific foreign keys
+        if table.immutable {
+            let index_expr = format!("{}, {}", column.name.quoted(), BLOCK_COLUMN);
+            ("btree", index_expr)
+        } else {
+            let index_expr = format!("{}, {}", column.name.quoted(), BLOCK_RANGE_COLUMN);
+            ("gist", index_expr)
+        }
+    } else {
+        // Attributes that are plain strings or bytes are
+        // indexed with a BTree; but they can be too large for
+        // Postgres' limit on values that can go into a BTree.
+        // For those attributes, only index the first
+        // STRING_PREFIX_SIZE or BYTE_ARRAY_PREFIX_SIZE characters
+        let index_expr = if column.use_prefix_comparison {
+            match column.column_type {
+                ColumnType::String => {
+                    format!("left({}, {})", column.name.quoted(), STRING_PREFIX_SIZE)
                 }
+                ColumnType::Bytes => format!(
+                    "substring({}, 1, {})",
+                    column.name.quoted(),
+                    BYTE_ARRAY_PREFIX_SIZE
+                ),
+                _ => unreachable!("only String and Bytes can have arbitrary size"),
+            }
+        } else {
+            column.name.quoted()
+        };
 
-                let (method, index_expr) = if column.is_reference() && !column.is_list() {
-                    // For foreign keys, index the key together with the block range
-                    // since we almost always also have a block_range clause in
-                    // queries that look for specific foreign keys
-                    if table.immutable {
-                        let index_expr = format!("{}, {}", column.name.quoted(), BLOCK_COLUMN);
-                        ("btree", index_expr)
-                    } else {
-                        let index_expr =
-                            format!("{}, {}", column.name.quoted(), BLOCK_RANGE_COLUMN);
-                        ("gist", index_expr)
-                    }
-                } else {
-                    // Attributes that are plain strings or bytes are
-                    // indexed with a BTree; but they can be too large for
-                    // Postgres' limit on values that can go into a BTree.
-                    // For those attributes, only index the first
-                    // STRING_PREFIX_SIZE or BYTE_ARRAY_PREFIX_SIZE characters
-                    let index_expr = if column.use_prefix_comparison {
-                        match column.column_type {
-                            ColumnType::String => {
-                                format!("left({}, {})", column.name.quoted(), STRING_PREFIX_SIZE)
-                            }
-                            ColumnType::Bytes => format!(
-                                "substring({}, 1, {})",
-                                column.name.quoted(),
-                                BYTE_ARRAY_PREFIX_SIZE
-                            ),
-                            _ => unreachable!("only String and Bytes can have arbitrary size"),
-                        }
-                    } else {
-                        column.name.quoted()
-                    };
+        let method = if column.is_list() || column.is_fulltext() {
+            "gin"
+        } else {
+            "btree"
+        };
 
-                    let method = if column.is_list() || column.is_fulltext() {
-                        "gin"
-                    } else {
-                        "btree"
-                    };
+        (method, index_expr)
+    }
+}
 
-                    (method, index_expr)
-                };
-                write!(
+/// Generate the `create index` statements for the attributes of `table`.
+/// With `concurrently`, the indexes are built without blocking writes to
+/// the table where Postgres supports that
+fn create_attribute_indexes(
+    table: &Table,
+    out: &mut String,
+    layout: &Layout,
+    concurrently: bool,
+) -> fmt::Result {
+    // Postgres can not build indexes on partitioned tables concurrently
+    let create = match (concurrently, table.is_partitioned()) {
+        (false, _) => "create index",
+        (true, false) => "create index concurrently if not exists",
+        (true, true) => "create index if not exists",
+    };
+
+    // Columns whose type is an array of enum were not indexed in the past
+    // since Postgres 9.6 could not index them. They now come last so that
+    // the other indexes keep the names they always had
+    let (enum_lists, columns): (Vec<_>, Vec<_>) = table
+        .columns
+        .iter()
+        .partition(|col| col.is_list() && col.is_enum());
+    for (i, column) in columns.into_iter().chain(enum_lists).enumerate() {
+        if table.immutable && column.is_primary_key() {
+            // We create a unique index on `id` in `create_table`
+            // and don't need an explicit attribute index
+            continue;
+        }
+
+        if !column.indexed {
+            // The subgraph asked for this attribute not to be indexed
+            continue;
+        }
+
+        let (method, index_expr) = attribute_index_method(table, column);
+        write!(
+        out,
+        "{create} attr_{table_index}_{column_index}_{table_name}_{column_name}\n    on {schema_name}.\"{table_name}\" using {method}({index_expr});\n",
+        create = create,
+        table_index = table.position,
+        table_name = table.name,
+        column_index = i,
+        column_name = column.name,
+        schema_name = layout.catalog.site.namespace,
+        method = method,
+        index_expr = index_expr,
+    )?;
+
+        // Trigram indexes support `like` and `ilike` with arbitrary
+        // patterns, including unanchored ones
+        if ENV_VARS.store.trigram_indexes
+            && column.column_type == ColumnType::String
+            && !column.is_list()
+            && !column.is_reference()
+            && !column.is_primary_key()
+        {
+            write!(
                 out,
-                "create index attr_{table_index}_{column_index}_{table_name}_{column_name}\n    on {schema_name}.\"{table_name}\" using {method}({index_expr});\n",
+                "{create} trgm_{table_index}_{column_index}_{table_name}_{column_name}\n    on {schema_name}.\"{table_name}\" using gin({column} gin_trgm_ops);\n",
+                create = create,
                 table_index = table.position,
                 table_name = table.name,
                 column_index = i,
                 column_name = column.name,
                 schema_name = layout.catalog.site.namespace,
-                method = method,
-                index_expr = index_expr,
+                column = column.name.quoted(),
             )?;
-            }
-            writeln!(out)
         }
-
-        create_table(self, out, layout)?;
-        create_time_travel_indexes(self, out, layout)?;
-        create_attribute_indexes(self, out, layout)
     }
+    writeln!(out)
 }
 
 /// Return the enclosed named type for a field type, i.e., the type after
@@ -1571,8 +2328,9 @@ impl LayoutCache {
     }
 
     fn load(conn: &PgConnection, site: Arc<Site>) -> Result<Arc<Layout>, StoreError> {
-        let (subgraph_schema, use_bytea_prefix) = deployment::schema(conn, site.as_ref())?;
-        let catalog = Catalog::load(conn, site.clone(), use_bytea_prefix)?;
+        let (subgraph_schema, use_bytea_prefix, partition_blocks) =
+            deployment::schema(conn, site.as_ref())?;
+        let catalog = Catalog::load(conn, site.clone(), use_bytea_prefix, partition_blocks)?;
         let layout = Arc::new(Layout::new(site.clone(), &subgraph_schema, catalog)?);
         layout.refresh(conn, site)
     }
@@ -1588,6 +2346,12 @@ impl LayoutCache {
         }
     }
 
+    /// Forget the cached layout for `site`, for example, because the
+    /// deployment's schema changed
+    pub(crate) fn remove(&self, site: &Site) {
+        self.entries.lock().unwrap().remove(&site.deployment);
+    }
+
     /// Return the corresponding layout if we have one in cache already, and
     /// ignore expiration information
     pub(crate) fn find(&self, site: &Site) -> Option<Arc<Layout>> {
@@ -1741,6 +2505,114 @@ mod tests {
         check_eqv(FORWARD_ENUM_SQL, &sql);
     }
 
+    #[test]
+    fn generate_partitioned_ddl() {
+        let subgraph = DeploymentHash::new("subgraph").unwrap();
+        let schema = Schema::parse(MUSIC_GQL, subgraph.clone()).expect("Test schema invalid");
+        let namespace = Namespace::new("sgd0815".to_owned()).unwrap();
+        let site = Arc::new(make_dummy_site(subgraph, namespace, "anet".to_string()));
+        let mut catalog = Catalog::for_tests(site.clone()).expect("Can not create catalog");
+        catalog.partition_blocks = Some(10_000);
+        let layout = Layout::new(site, &schema, catalog).expect("Failed to construct Layout");
+
+        // Only the table for the immutable `Song` is partitioned
+        let song = layout.table(&"song".into()).unwrap();
+        assert_eq!(Some(10_000), song.partition_blocks);
+        let band = layout.table(&"band".into()).unwrap();
+        assert_eq!(None, band.partition_blocks);
+
+        let sql = layout.as_ddl().expect("Failed to generate DDL");
+        let sql = sql.split_whitespace().join(" ");
+        assert!(sql.contains(
+            "create table sgd0815.\"song\" ( vid bigserial, block$ int not null, \
+             \"id\" text not null, \"title\" text not null, \"written_by\" text not null, \
+             primary key(vid, block$), unique(id, block$) ) partition by range(block$);"
+        ));
+        assert_eq!(1, sql.matches("partition by range").count());
+    }
+
+    #[test]
+    fn generate_deferred_index_ddl() {
+        let subgraph = DeploymentHash::new("subgraph").unwrap();
+        let schema = Schema::parse(MUSIC_GQL, subgraph.clone()).expect("Test schema invalid");
+        let namespace = Namespace::new("sgd0815".to_owned()).unwrap();
+        let site = Arc::new(make_dummy_site(subgraph, namespace, "anet".to_string()));
+        let mut catalog = Catalog::for_tests(site.clone()).expect("Can not create catalog");
+        catalog.defer_indexes = true;
+        let layout = Layout::new(site, &schema, catalog).expect("Failed to construct Layout");
+
+        let sql = layout.as_ddl().expect("Failed to generate DDL");
+        assert!(!sql.contains("attr_"));
+        assert!(sql.contains("create index brin_song"));
+
+        let ddl = layout
+            .attribute_index_ddl()
+            .expect("Failed to generate index DDL");
+        assert_eq!(MUSIC_DDL.matches("create index attr_").count(), ddl.len());
+        assert_eq!(
+            "create index concurrently if not exists attr_0_0_musician_id\n    \
+             on sgd0815.\"musician\" using btree(\"id\")",
+            ddl[0]
+        );
+        assert!(ddl
+            .iter()
+            .all(|stmt| stmt.starts_with("create index concurrently if not exists attr_")));
+    }
+
+    #[test]
+    fn generate_ddl_with_index_hints() {
+        const GQL: &str = "
+            type Explicit @entity {
+                id: ID!
+                name: String! @index
+                note: String
+            }
+
+            type Implicit @entity {
+                id: ID!
+                name: String!
+                note: String @noIndex
+            }";
+
+        let layout = test_layout(GQL);
+        let sql = layout.as_ddl().expect("Failed to generate DDL");
+        let indexes: Vec<_> = sql
+            .split_whitespace()
+            .filter(|word| word.starts_with("attr_"))
+            .collect();
+        assert_eq!(
+            vec![
+                "attr_0_1_explicit_name",
+                "attr_1_0_implicit_id",
+                "attr_1_1_implicit_name"
+            ],
+            indexes
+        );
+    }
+
+    #[test]
+    fn generate_ddl_for_enum_lists() {
+        const GQL: &str = "
+            enum Color { red, green }
+
+            type Thing @entity {
+                id: ID!
+                colors: [Color!]!
+                name: String!
+            }";
+
+        let layout = test_layout(GQL);
+        let sql = layout.as_ddl().expect("Failed to generate DDL");
+        let sql = sql.split_whitespace().join(" ");
+        assert!(sql.contains(
+            "create index attr_0_1_thing_name \
+             on sgd0815.\"thing\" using btree(left(\"name\", 256));"
+        ));
+        assert!(sql.contains(
+            "create index attr_0_2_thing_colors on sgd0815.\"thing\" using gin(\"colors\");"
+        ));
+    }
+
     #[test]
     fn forward_enum() {
         let layout = test_layout(FORWARD_ENUM_GQL);
@@ -1816,6 +2688,54 @@ mod tests {
         );
     }
 
+    #[test]
+    fn additive_ddl() {
+        const BASE_GQL: &str = "
+            type Thing @entity { id: ID!, name: String!, other: Other }
+            type Other @entity { id: ID! }";
+
+        fn normalize(ddl: &str) -> String {
+            ddl.split_whitespace().join(" ")
+        }
+
+        let base = test_layout(BASE_GQL);
+        // An identical layout needs no changes
+        assert_eq!(Ok(String::new()), base.additive_ddl(&base));
+
+        // Adding a derived field needs no changes at all
+        let dest = test_layout(
+            "type Thing @entity { id: ID!, name: String!, other: Other }
+             type Other @entity { id: ID!, things: [Thing!]! @derivedFrom(field: \"other\") }",
+        );
+        assert_eq!(Ok(String::new()), dest.additive_ddl(&base));
+
+        // Nullable attributes and new types can be added
+        let dest = test_layout(
+            "type Thing @entity { id: ID!, name: String!, other: Other, count: Int }
+             type Other @entity { id: ID! }
+             type New @entity { id: ID! }",
+        );
+        let ddl = normalize(&dest.additive_ddl(&base).expect("changes are additive"));
+        assert!(ddl.starts_with(
+            "alter table \"sgd0815\".\"thing\" add column \"count\" integer; \
+             create table sgd0815.\"new\""
+        ));
+
+        // Anything else is not additive
+        let dest =
+            test_layout("type Thing @entity(immutable: true) { id: ID!, name: Int, count: Int! }");
+        assert_eq!(
+            Err(vec![
+                "The entity type Thing can not change whether it is immutable".to_string(),
+                "The entity type Other can not be removed".to_string(),
+                "The attribute Thing.name can not change its type from String! to Int".to_string(),
+                "The attribute Thing.other can not be removed".to_string(),
+                "The new attribute Thing.count must be nullable".to_string(),
+            ]),
+            dest.additive_ddl(&base)
+        );
+    }
+
     const THING_GQL: &str = "
         type Thing @entity {
             id: ID!
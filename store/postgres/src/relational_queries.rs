@@ -6,20 +6,27 @@
 ///! Code in this module works very hard to minimize the number of allocations
 ///! that it performs
 use diesel::pg::{Pg, PgConnection};
-use diesel::query_builder::{AstPass, QueryFragment, QueryId};
+use diesel::query_builder::{AstPass, Query, QueryFragment, QueryId};
 use diesel::query_dsl::{LoadQuery, RunQueryDsl};
 use diesel::result::{Error as DieselError, QueryResult};
-use diesel::sql_types::{Array, BigInt, Binary, Bool, Integer, Jsonb, Text};
+use diesel::sql_types::{Array, BigInt, Binary, Bool, Integer, Jsonb, Nullable, Text};
 use diesel::Connection;
 
 use graph::prelude::{
-    anyhow, r, serde_json, Attribute, BlockNumber, ChildMultiplicity, Entity, EntityCollection,
-    EntityFilter, EntityKey, EntityLink, EntityOrder, EntityRange, EntityWindow, ParentLink,
-    QueryExecutionError, StoreError, Value, ENV_VARS,
+    anyhow, r, serde_json, AggregateFunction, Attribute, BlockNumber, ChildMultiplicity, Entity,
+    EntityAggregate, EntityCollection, EntityFilter, EntityKey, EntityLink, EntityOrder,
+    EntityOrderByChild, EntityRange, EntityWindow, ParentLink, QueryExecutionError, StoreError,
+    Value, ENV_VARS,
 };
 use graph::{
-    components::store::{AttributeNames, EntityType},
-    data::{schema::FulltextAlgorithm, store::scalar},
+    components::store::{AttributeNames, Child, EntityType},
+    data::{
+        schema::{
+            AggregationDefinition, AggregationInterval, FulltextAlgorithm, INTERVAL_FIELD,
+            TIMESTAMP_FIELD,
+        },
+        store::scalar,
+    },
 };
 use itertools::Itertools;
 use std::borrow::Cow;
@@ -828,25 +835,43 @@ impl<'a> QueryFragment<Pg> for PrefixComparison<'a> {
 /// the `where` clause of a SQL query. The attributes mentioned in
 /// the `filter` must all come from the given `table`, which is used to
 /// map GraphQL names to column names, and to determine the type of the
-/// column an attribute refers to
+/// column an attribute refers to. Filters on child entities are checked
+/// against the child's table, which is looked up in the `layout`, and only
+/// consider child entity versions that are visible at `block`
 #[derive(Debug, Clone)]
 pub struct QueryFilter<'a> {
     filter: &'a EntityFilter,
+    layout: &'a Layout,
     table: &'a Table,
+    block: BlockNumber,
 }
 
 impl<'a> QueryFilter<'a> {
-    pub fn new(filter: &'a EntityFilter, table: &'a Table) -> Result<Self, StoreError> {
-        Self::valid_attributes(filter, table)?;
-        Ok(QueryFilter { filter, table })
+    pub fn new(
+        filter: &'a EntityFilter,
+        layout: &'a Layout,
+        table: &'a Table,
+        block: BlockNumber,
+    ) -> Result<Self, StoreError> {
+        Self::valid_attributes(filter, layout, table)?;
+        Ok(QueryFilter {
+            filter,
+            layout,
+            table,
+            block,
+        })
     }
 
-    fn valid_attributes(filter: &'a EntityFilter, table: &'a Table) -> Result<(), StoreError> {
+    fn valid_attributes(
+        filter: &'a EntityFilter,
+        layout: &'a Layout,
+        table: &'a Table,
+    ) -> Result<(), StoreError> {
         use EntityFilter::*;
         match filter {
             And(filters) | Or(filters) => {
                 for filter in filters {
-                    Self::valid_attributes(filter, table)?;
+                    Self::valid_attributes(filter, layout, table)?;
                 }
             }
 
@@ -869,9 +894,29 @@ impl<'a> QueryFilter<'a> {
             | EndsWith(attr, _)
             | EndsWithNoCase(attr, _)
             | NotEndsWith(attr, _)
-            | NotEndsWithNoCase(attr, _) => {
+            | NotEndsWithNoCase(attr, _)
+            | Like(attr, _)
+            | LikeNoCase(attr, _)
+            | NotLike(attr, _)
+            | NotLikeNoCase(attr, _) => {
                 table.column_for_field(attr)?;
             }
+
+            RowGreaterThan(values) | RowLessThan(values) => {
+                for (attr, _) in values {
+                    table.column_for_field(attr)?;
+                }
+            }
+
+            Child(child) => {
+                let child_table = layout.table_for_entity(&child.entity_type)?;
+                if child.derived {
+                    child_table.column_for_field(&child.attr)?;
+                } else {
+                    table.column_for_field(&child.attr)?;
+                }
+                Self::valid_attributes(&child.filter, layout, child_table)?;
+            }
         }
         Ok(())
     }
@@ -879,7 +924,9 @@ impl<'a> QueryFilter<'a> {
     fn with(&self, filter: &'a EntityFilter) -> Self {
         QueryFilter {
             filter,
+            layout: self.layout,
             table: self.table,
+            block: self.block,
         }
     }
 
@@ -1042,6 +1089,43 @@ impl<'a> QueryFilter<'a> {
         Ok(())
     }
 
+    /// Generate `(attr1, attr2) {op} (value1, value2)`
+    fn compare_row(
+        &self,
+        values: &[(Attribute, Value)],
+        op: Comparison,
+        mut out: AstPass<Pg>,
+    ) -> QueryResult<()> {
+        out.push_sql("(");
+        for (i, (attribute, _)) in values.iter().enumerate() {
+            if i > 0 {
+                out.push_sql(", ");
+            }
+            out.push_identifier(self.column(attribute).name.as_str())?;
+        }
+        out.push_sql(")");
+        out.push_sql(op.as_str());
+        out.push_sql("(");
+        for (i, (attribute, value)) in values.iter().enumerate() {
+            if i > 0 {
+                out.push_sql(", ");
+            }
+            match value {
+                Value::List(_) | Value::Null => {
+                    return Err(UnsupportedFilter {
+                        filter: op.as_str().to_owned(),
+                        value: value.clone(),
+                    }
+                    .into());
+                }
+                _ => QueryValue(value, &self.column(attribute).column_type)
+                    .walk_ast(out.reborrow())?,
+            }
+        }
+        out.push_sql(")");
+        Ok(())
+    }
+
     fn in_array(
         &self,
         attribute: &Attribute,
@@ -1167,6 +1251,98 @@ impl<'a> QueryFilter<'a> {
         }
         Ok(())
     }
+
+    /// Match the column against a `like` pattern that is passed through
+    /// unchanged so that clients can use `%` and `_` as wildcards. String
+    /// attributes can have trigram indexes that support this
+    fn like(
+        &self,
+        attribute: &Attribute,
+        value: &Value,
+        op: &str,
+        mut out: AstPass<Pg>,
+    ) -> QueryResult<()> {
+        let column = self.column(attribute);
+
+        match value {
+            Value::String(s) => {
+                out.push_identifier(column.name.as_str())?;
+                out.push_sql(op);
+                out.push_bind_param::<Text, _>(s)?
+            }
+            Value::Bool(_)
+            | Value::BigInt(_)
+            | Value::Bytes(_)
+            | Value::BigDecimal(_)
+            | Value::Int(_)
+            | Value::List(_)
+            | Value::Null => {
+                return Err(UnsupportedFilter {
+                    filter: op.to_owned(),
+                    value: value.clone(),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> QueryFilter<'a> {
+    /// Generate a semi-join that matches entities with at least one child
+    /// that matches the child filter. Depending on where the relationship
+    /// is stored, this is one of
+    ///
+    ///   attr in (select id from child where ..)
+    ///   attr && array(select id from child where ..)
+    ///   id in (select attr from child where ..)
+    ///   id in (select unnest(attr) from child where ..)
+    ///
+    /// All column references in the subquery resolve to `child`
+    fn child(&self, child: &'a Child, mut out: AstPass<Pg>) -> QueryResult<()> {
+        let child_table = self
+            .layout
+            .table_for_entity(&child.entity_type)
+            .expect("the constructor already checked that the child table exists");
+
+        if child.derived {
+            let attr = child_table
+                .column_for_field(&child.attr)
+                .expect("the constructor already checked that all attribute names are valid");
+            out.push_identifier(PRIMARY_KEY_COLUMN)?;
+            out.push_sql(" in (select ");
+            if attr.is_list() {
+                out.push_sql("unnest(");
+                out.push_identifier(attr.name.as_str())?;
+                out.push_sql(")");
+            } else {
+                out.push_identifier(attr.name.as_str())?;
+            }
+        } else {
+            let attr = self.column(&child.attr);
+            out.push_identifier(attr.name.as_str())?;
+            if attr.is_list() {
+                out.push_sql(" && array(select ");
+            } else {
+                out.push_sql(" in (select ");
+            }
+            out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        }
+        out.push_sql(" from ");
+        out.push_sql(child_table.qualified_name.as_str());
+        out.push_sql(" where ");
+        BlockRangeColumn::new(child_table, "", self.block).contains(&mut out)?;
+        out.push_sql(" and ");
+        QueryFilter {
+            filter: child.filter.as_ref(),
+            layout: self.layout,
+            table: child_table,
+            block: self.block,
+        }
+        .walk_ast(out.reborrow())?;
+        out.push_sql(")");
+        Ok(())
+    }
 }
 
 impl<'a> QueryFragment<Pg> for QueryFilter<'a> {
@@ -1217,6 +1393,16 @@ impl<'a> QueryFragment<Pg> for QueryFilter<'a> {
             NotEndsWithNoCase(attr, value) => {
                 self.starts_or_ends_with(attr, value, " not ilike ", false, out)?
             }
+
+            Like(attr, value) => self.like(attr, value, " like ", out)?,
+            LikeNoCase(attr, value) => self.like(attr, value, " ilike ", out)?,
+            NotLike(attr, value) => self.like(attr, value, " not like ", out)?,
+            NotLikeNoCase(attr, value) => self.like(attr, value, " not ilike ", out)?,
+
+            RowGreaterThan(values) => self.compare_row(values, c::Greater, out)?,
+            RowLessThan(values) => self.compare_row(values, c::Less, out)?,
+
+            Child(child) => self.child(child, out)?,
         }
         Ok(())
     }
@@ -1286,7 +1472,14 @@ impl<'a> QueryFragment<Pg> for FindChangesQuery<'a> {
             out.push_sql("  from ");
             out.push_sql(table.qualified_name.as_str());
             out.push_sql(" e\n where ");
-            BlockRangeLowerBoundClause::new("e.", self.block).walk_ast(out.reborrow())?;
+            if table.immutable {
+                out.push_sql("e.");
+                out.push_identifier(BLOCK_COLUMN)?;
+                out.push_sql(" = ");
+                out.push_bind_param::<Integer, _>(&self.block)?;
+            } else {
+                BlockRangeLowerBoundClause::new("e.", self.block).walk_ast(out.reborrow())?;
+            }
         }
 
         Ok(())
@@ -1308,8 +1501,9 @@ impl<'a> LoadQuery<PgConnection, EntityData> for FindChangesQuery<'a> {
 impl<'a, Conn> RunQueryDsl<Conn> for FindChangesQuery<'a> {}
 
 /// Builds a query over a given set of [`Table`]s in an attempt to find deleted
-/// entities; i.e. such that the block range's lower bound is equal to said
-/// block number.
+/// entities; i.e. such that the block range's upper bound is equal to said
+/// block number. Entities in immutable tables can never be deleted, and
+/// the `tables` passed to this query must therefore all be mutable.
 ///
 /// Please note that the result set from this query is *not* definitive. This
 /// query is intented to be used together with [`FindChangesQuery`]; by
@@ -1357,6 +1551,88 @@ impl<'a> LoadQuery<PgConnection, EntityDeletion> for FindPossibleDeletionsQuery<
 
 impl<'a, Conn> RunQueryDsl<Conn> for FindPossibleDeletionsQuery<'a> {}
 
+#[derive(QueryableByName)]
+pub struct ChangedBlock {
+    #[sql_type = "Integer"]
+    pub block: BlockNumber,
+}
+
+/// Builds a query over a given set of [`Table`]s that finds all blocks in
+/// the range `[from, to]` at which an entity in one of the tables was
+/// inserted, updated or deleted, i.e., the blocks at which a block range
+/// starts or ends. The blocks are returned in ascending order and without
+/// duplicates
+#[derive(Debug, Clone, Constructor)]
+pub struct ChangedBlocksQuery<'a> {
+    pub(crate) tables: &'a [&'a Table],
+    pub(crate) from: BlockNumber,
+    pub(crate) to: BlockNumber,
+}
+
+impl<'a> ChangedBlocksQuery<'a> {
+    fn select_blocks(&self, out: &mut AstPass<Pg>, table: &Table, expr: &str) -> QueryResult<()> {
+        out.push_sql("select ");
+        out.push_sql(expr);
+        out.push_sql(" as block from ");
+        out.push_sql(table.qualified_name.as_str());
+        out.push_sql(" e where ");
+        out.push_sql(expr);
+        out.push_sql(" between ");
+        out.push_bind_param::<Integer, _>(&self.from)?;
+        out.push_sql(" and ");
+        out.push_bind_param::<Integer, _>(&self.to)
+    }
+}
+
+impl<'a> QueryFragment<Pg> for ChangedBlocksQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Generate
+        //    select distinct block from (
+        //      select lower(e.block_range) as block from <mutable table> e
+        //       where lower(e.block_range) between $from and $to
+        //      union all
+        //      select upper(e.block_range) as block from <mutable table> e
+        //       where upper(e.block_range) between $from and $to
+        //      union all
+        //      select e.block$ as block from <immutable table> e
+        //       where e.block$ between $from and $to
+        //      ...) b
+        //     order by block
+        out.push_sql("select distinct block from (\n");
+        for (i, table) in self.tables.iter().enumerate() {
+            if i > 0 {
+                out.push_sql("\nunion all\n");
+            }
+            if table.immutable {
+                self.select_blocks(&mut out, table, "e.\"block$\"")?;
+            } else {
+                self.select_blocks(&mut out, table, "lower(e.block_range)")?;
+                out.push_sql("\nunion all\n");
+                self.select_blocks(&mut out, table, "upper(e.block_range)")?;
+            }
+        }
+        out.push_sql(") b\n order by block");
+
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for ChangedBlocksQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, ChangedBlock> for ChangedBlocksQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<ChangedBlock>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for ChangedBlocksQuery<'a> {}
+
 #[derive(Debug, Clone, Constructor)]
 pub struct FindManyQuery<'a> {
     pub(crate) _namespace: &'a Namespace,
@@ -1550,6 +1826,175 @@ impl<'a> LoadQuery<PgConnection, ReturnedEntityData> for InsertQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for InsertQuery<'a> {}
 
+/// Write entities with `COPY`, which is much faster than `InsertQuery` for
+/// large numbers of entities. Diesel connections do not support `COPY`,
+/// and the entities are therefore copied straight into the entity table
+/// through a separate connection. The copied rows are committed right
+/// away, before the transaction that writes the rest of the block; since
+/// they start at a block after the deployment head, queries do not see
+/// them before that transaction commits, and `Layout::remove_uncommitted`
+/// removes them if it fails
+#[derive(Debug)]
+pub struct CopyInQuery<'a> {
+    table: &'a Table,
+    entities: &'a [(&'a EntityKey, Cow<'a, Entity>)],
+    block: BlockNumber,
+}
+
+impl<'a> CopyInQuery<'a> {
+    /// Whether entities for `table` can be written with `COPY`. Fulltext
+    /// search fields are computed by the database when entities are
+    /// inserted, and tables with them need to use `InsertQuery`. Writing
+    /// into partitioned tables can create partitions in the write
+    /// transaction, which locks out the connection for `COPY`
+    pub fn supports(table: &Table) -> bool {
+        !table.is_partitioned() && !table.columns.iter().any(|column| column.is_fulltext())
+    }
+
+    pub fn new(
+        table: &'a Table,
+        entities: &'a [(&'a EntityKey, Cow<Entity>)],
+        block: BlockNumber,
+    ) -> Result<CopyInQuery<'a>, StoreError> {
+        for (entity_key, entity) in entities.iter() {
+            for column in table.columns.iter() {
+                if !column.is_nullable() && !entity.contains_key(&column.field) {
+                    return Err(StoreError::QueryExecutionError(format!(
+                    "can not insert entity {}[{}] since value for non-nullable attribute {} is missing. \
+                     To fix this, mark the attribute as nullable in the GraphQL schema or change the \
+                     mapping code to always set this attribute.",
+                    entity_key.entity_type, entity_key.entity_id, column.field
+                )));
+                }
+            }
+        }
+        Ok(CopyInQuery {
+            table,
+            entities,
+            block,
+        })
+    }
+
+    fn block_column(&self) -> &str {
+        if self.table.immutable {
+            BLOCK_COLUMN
+        } else {
+            BLOCK_RANGE_COLUMN
+        }
+    }
+
+    fn column_list(&self) -> String {
+        self.table
+            .columns
+            .iter()
+            .map(|column| column.name.quoted())
+            .chain(Some(self.block_column().to_string()))
+            .join(", ")
+    }
+
+    /// Copy the entities into the entity table and commit them. Return
+    /// the number of rows that were copied
+    pub fn copy_in(&self, client: &mut postgres::Client) -> Result<usize, StoreError> {
+        use std::io::Write;
+
+        let query = format!(
+            "copy {}({}) from stdin",
+            self.table.qualified_name,
+            self.column_list()
+        );
+        let data = self.copy_data()?;
+
+        let copy = |client: &mut postgres::Client| -> Result<u64, anyhow::Error> {
+            let mut writer = client.copy_in(query.as_str())?;
+            writer.write_all(data.as_bytes())?;
+            Ok(writer.finish()?)
+        };
+        copy(client)
+            .map(|count| count as usize)
+            .map_err(StoreError::Unknown)
+    }
+
+    /// The entities in the text format of `COPY`, one line per entity
+    fn copy_data(&self) -> Result<String, StoreError> {
+        let block = if self.table.immutable {
+            self.block.to_string()
+        } else {
+            format!("[{},)", self.block)
+        };
+
+        let mut data = String::new();
+        for (_, entity) in self.entities {
+            for column in &self.table.columns {
+                match entity.get(&column.field) {
+                    None | Some(Value::Null) => data.push_str("\\N"),
+                    Some(value) => copy_escape(&copy_text(value, &column.column_type)?, &mut data),
+                }
+                data.push('\t');
+            }
+            data.push_str(&block);
+            data.push('\n');
+        }
+        Ok(data)
+    }
+}
+
+/// The text representation of `value` that Postgres accepts as input for
+/// a column of type `column_type`. `value` must not be `Value::Null`
+fn copy_text(value: &Value, column_type: &ColumnType) -> Result<String, StoreError> {
+    fn bytes_text(bytes: &[u8]) -> String {
+        format!("\\x{}", hex::encode(bytes))
+    }
+
+    let text = match value {
+        Value::String(s) => match column_type {
+            ColumnType::Bytes => {
+                let bytes = scalar::Bytes::from_str(s)
+                    .map_err(|e| StoreError::Unknown(anyhow!("invalid bytes `{}`: {}", s, e)))?;
+                bytes_text(bytes.as_slice())
+            }
+            _ => s.clone(),
+        },
+        Value::Int(i) => i.to_string(),
+        Value::BigDecimal(d) => d.to_string(),
+        Value::Bool(b) => if *b { "t" } else { "f" }.to_string(),
+        Value::Bytes(b) => bytes_text(b.as_slice()),
+        Value::BigInt(i) => i.to_string(),
+        Value::List(values) => {
+            // An array literal like `{"a","b",NULL}`
+            let elements = values
+                .iter()
+                .map(|value| match value {
+                    Value::Null => Ok("NULL".to_string()),
+                    value => copy_text(value, column_type).map(|text| {
+                        format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+                    }),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            format!("{{{}}}", elements.join(","))
+        }
+        Value::Null => {
+            return Err(StoreError::ConstraintViolation(
+                "null values must be written as `\\N` with `COPY`".to_string(),
+            ))
+        }
+    };
+    Ok(text)
+}
+
+/// Append `text` to `out`, escaping the characters that have a special
+/// meaning in the text format of `COPY`
+fn copy_escape(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ConflictingEntityQuery<'a> {
     _layout: &'a Layout,
@@ -1619,6 +2064,58 @@ impl<'a> LoadQuery<PgConnection, ConflictingEntityData> for ConflictingEntityQue
 
 impl<'a, Conn> RunQueryDsl<Conn> for ConflictingEntityQuery<'a> {}
 
+/// Find an entity in `table` whose id is one of `ids`, at any block. The
+/// database can not enforce that ids are unique in partitioned tables, and
+/// we use this query to check that before inserting into them
+#[derive(Debug, Clone)]
+pub struct ExistingIdQuery<'a> {
+    table: &'a Table,
+    ids: Vec<&'a str>,
+}
+
+impl<'a> ExistingIdQuery<'a> {
+    pub fn new(table: &'a Table, ids: Vec<&'a str>) -> Self {
+        ExistingIdQuery { table, ids }
+    }
+}
+
+impl<'a> QueryFragment<Pg> for ExistingIdQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   select id::text as id from schema.table
+        //    where {id.is_in($ids)}
+        //    limit 1
+        out.push_sql("select id::text as id from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql("\n where ");
+        self.table.primary_key().is_in(&self.ids, &mut out)?;
+        out.push_sql("\n limit 1");
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for ExistingIdQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+#[derive(QueryableByName)]
+pub struct ExistingId {
+    #[sql_type = "Text"]
+    pub id: String,
+}
+
+impl<'a> LoadQuery<PgConnection, ExistingId> for ExistingIdQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<ExistingId>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for ExistingIdQuery<'a> {}
+
 /// A string where we have checked that it is safe to embed it literally
 /// in a string in a SQL query. In particular, we have escaped any use
 /// of the string delimiter `'`.
@@ -1761,6 +2258,7 @@ impl<'a> FilterWindow<'a> {
         layout: &'a Layout,
         window: EntityWindow,
         query_filter: Option<&'a EntityFilter>,
+        block: BlockNumber,
     ) -> Result<Self, QueryExecutionError> {
         let EntityWindow {
             child_type,
@@ -1778,7 +2276,7 @@ impl<'a> FilterWindow<'a> {
         }
 
         let query_filter = query_filter
-            .map(|filter| QueryFilter::new(filter, table))
+            .map(|filter| QueryFilter::new(filter, layout, table, block))
             .transpose()?;
         let link = TableLink::new(table, link)?;
         Ok(FilterWindow {
@@ -2101,6 +2599,7 @@ impl<'a> FilterCollection<'a> {
         layout: &'a Layout,
         collection: EntityCollection,
         filter: Option<&'a EntityFilter>,
+        block: BlockNumber,
     ) -> Result<Self, QueryExecutionError> {
         match collection {
             EntityCollection::All(entities) => {
@@ -2116,7 +2615,7 @@ impl<'a> FilterCollection<'a> {
                             .map(|rc| rc.as_ref())
                             .and_then(|table| {
                                 filter
-                                    .map(|filter| QueryFilter::new(filter, table))
+                                    .map(|filter| QueryFilter::new(filter, layout, table, block))
                                     .transpose()
                                     .map(|filter| (table, filter, column_names.clone()))
                             })
@@ -2127,7 +2626,7 @@ impl<'a> FilterCollection<'a> {
             EntityCollection::Window(windows) => {
                 let windows = windows
                     .into_iter()
-                    .map(|window| FilterWindow::new(layout, window, filter))
+                    .map(|window| FilterWindow::new(layout, window, filter, block))
                     .collect::<Result<Vec<_>, _>>()?;
                 let collection = if windows.len() == 1 {
                     let mut windows = windows;
@@ -2151,6 +2650,16 @@ impl<'a> FilterCollection<'a> {
         }
     }
 
+    /// Whether the query only touches one table without windowing, or one
+    /// window
+    fn is_single_table(&self) -> bool {
+        match self {
+            FilterCollection::All(entities) => entities.len() == 1,
+            FilterCollection::SingleWindow(_) => true,
+            FilterCollection::MultiWindow(_, _) => false,
+        }
+    }
+
     fn is_empty(&self) -> bool {
         match self {
             FilterCollection::All(entities) => entities.is_empty(),
@@ -2162,7 +2671,7 @@ impl<'a> FilterCollection<'a> {
 
 /// Convenience to pass the name of the column to order by around. If `name`
 /// is `None`, the sort key should be ignored
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub enum SortKey<'a> {
     None,
     /// Order by `id asc`
@@ -2175,11 +2684,25 @@ pub enum SortKey<'a> {
         value: Option<&'a str>,
         direction: &'static str,
     },
+    /// Order by several columns, each with its own direction, and then by
+    /// `id` in the given direction. None of the columns is `id` or a
+    /// fulltext column
+    Keys(Vec<(&'a Column, &'static str)>, &'static str),
+    /// Order by a column of the entity that `join_column` references, and
+    /// then by `id` in the same direction
+    ChildKey {
+        join_column: &'a Column,
+        child_table: &'a Table,
+        column: &'a Column,
+        direction: &'static str,
+        block: BlockNumber,
+    },
 }
 
 impl<'a> SortKey<'a> {
     fn new(
         order: EntityOrder,
+        layout: &'a Layout,
         table: &'a Table,
         filter: Option<&'a EntityFilter>,
         block: BlockNumber,
@@ -2223,15 +2746,81 @@ impl<'a> SortKey<'a> {
             }
         }
 
-        let br_column = if ENV_VARS.store.order_by_block_range {
-            Some(BlockRangeColumn::new(table, "c.", block))
-        } else {
-            None
+        fn with_child_key<'a>(
+            layout: &'a Layout,
+            table: &'a Table,
+            child: EntityOrderByChild,
+            direction: &'static str,
+            block: BlockNumber,
+        ) -> Result<SortKey<'a>, QueryExecutionError> {
+            let join_column = table.column_for_field(&child.join_attribute)?;
+            let child_table = layout.table_for_entity(&child.entity_type)?.as_ref();
+            let column = child_table.column_for_field(&child.attribute)?;
+            if column.is_fulltext() {
+                return Err(QueryExecutionError::NotSupported(format!(
+                    "ordering by the fulltext field `{}` of a related entity",
+                    child.attribute
+                )));
+            }
+            Ok(SortKey::ChildKey {
+                join_column,
+                child_table,
+                column,
+                direction,
+                block,
+            })
+        }
+
+        let br_column = if ENV_VARS.store.order_by_block_range {
+            Some(BlockRangeColumn::new(table, "c.", block))
+        } else {
+            None
         };
 
         match order {
             EntityOrder::Ascending(attr, _) => with_key(table, attr, filter, ASC, br_column),
             EntityOrder::Descending(attr, _) => with_key(table, attr, filter, DESC, br_column),
+            EntityOrder::Compound(orders) => {
+                let mut keys: Vec<(&Column, &str)> = vec![];
+                let mut id_direction = None;
+                for order in orders {
+                    let (attr, direction) = match order {
+                        EntityOrder::Ascending(attr, _) => (attr, ASC),
+                        EntityOrder::Descending(attr, _) => (attr, DESC),
+                        _ => {
+                            return Err(QueryExecutionError::NotSupported(format!(
+                                "nested compound order {:?}",
+                                order
+                            )))
+                        }
+                    };
+                    let column = table.column_for_field(&attr)?;
+                    if column.is_fulltext() {
+                        return Err(QueryExecutionError::NotSupported(format!(
+                            "ordering by the fulltext field `{}` together with other fields",
+                            attr
+                        )));
+                    }
+                    if column.is_primary_key() {
+                        // `id` is always the last sort key anyway, and
+                        // there can be nothing to sort on after it
+                        if keys.is_empty() {
+                            return with_key(table, attr, filter, direction, br_column);
+                        }
+                        id_direction = Some(direction);
+                        break;
+                    }
+                    keys.push((column, direction));
+                }
+                let id_direction = id_direction
+                    .or_else(|| keys.last().map(|(_, direction)| *direction))
+                    .unwrap_or(ASC);
+                Ok(SortKey::Keys(keys, id_direction))
+            }
+            EntityOrder::ChildAscending(child) => with_child_key(layout, table, child, ASC, block),
+            EntityOrder::ChildDescending(child) => {
+                with_child_key(layout, table, child, DESC, block)
+            }
             EntityOrder::Default => Ok(SortKey::IdAsc(br_column)),
             EntityOrder::Unordered => Ok(SortKey::None),
         }
@@ -2260,6 +2849,16 @@ impl<'a> SortKey<'a> {
                 out.push_identifier(column.name.as_str())?;
                 Ok(())
             }
+            SortKey::Keys(keys, _) => {
+                for (column, _) in keys {
+                    out.push_sql(", c.");
+                    out.push_identifier(column.name.as_str())?;
+                }
+                Ok(())
+            }
+            SortKey::ChildKey { .. } => Err(constraint_violation!(
+                "SortKey::ChildKey is only used for queries against a single table"
+            )),
         }
     }
 
@@ -2296,6 +2895,20 @@ impl<'a> SortKey<'a> {
                 out.push_sql("order by ");
                 SortKey::sort_expr(column, value, direction, out)
             }
+            SortKey::Keys(keys, id_direction) => {
+                out.push_sql("order by ");
+                SortKey::multi_sort_expr(keys, id_direction, out)
+            }
+            SortKey::ChildKey {
+                join_column,
+                child_table,
+                column,
+                direction,
+                block,
+            } => {
+                out.push_sql("order by ");
+                SortKey::child_sort_expr(join_column, child_table, column, direction, *block, out)
+            }
         }
     }
 
@@ -2322,7 +2935,81 @@ impl<'a> SortKey<'a> {
                 out.push_sql("order by g$parent_id, ");
                 SortKey::sort_expr(column, value, direction, out)
             }
+            SortKey::Keys(keys, id_direction) => {
+                out.push_sql("order by g$parent_id, ");
+                SortKey::multi_sort_expr(keys, id_direction, out)
+            }
+            SortKey::ChildKey {
+                join_column,
+                child_table,
+                column,
+                direction,
+                block,
+            } => {
+                out.push_sql("order by g$parent_id, ");
+                SortKey::child_sort_expr(join_column, child_table, column, direction, *block, out)
+            }
+        }
+    }
+
+    /// Generate
+    ///   name1 direction1, .., nameN directionN, id id_direction
+    fn multi_sort_expr(
+        keys: &[(&Column, &str)],
+        id_direction: &str,
+        out: &mut AstPass<Pg>,
+    ) -> QueryResult<()> {
+        for (column, direction) in keys {
+            out.push_identifier(column.name.as_str())?;
+            out.push_sql(" ");
+            out.push_sql(direction);
+            if ENV_VARS.store.reversible_order_by_off {
+                out.push_sql(" nulls last");
+            }
+            out.push_sql(", ");
+        }
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        if !ENV_VARS.store.reversible_order_by_off {
+            out.push_sql(" ");
+            out.push_sql(id_direction);
+        }
+        Ok(())
+    }
+
+    /// Generate
+    ///   (select cc.name from child cc
+    ///     where cc.id = c.join_column and block_range @> $block) direction,
+    ///   id direction
+    fn child_sort_expr(
+        join_column: &Column,
+        child_table: &Table,
+        column: &Column,
+        direction: &str,
+        block: BlockNumber,
+        out: &mut AstPass<Pg>,
+    ) -> QueryResult<()> {
+        out.push_sql("(select cc.");
+        out.push_identifier(column.name.as_str())?;
+        out.push_sql(" from ");
+        out.push_sql(child_table.qualified_name.as_str());
+        out.push_sql(" cc where cc.");
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        out.push_sql(" = c.");
+        out.push_identifier(join_column.name.as_str())?;
+        out.push_sql(" and ");
+        BlockRangeColumn::new(child_table, "cc.", block).contains(out)?;
+        out.push_sql(") ");
+        out.push_sql(direction);
+        if ENV_VARS.store.reversible_order_by_off {
+            out.push_sql(" nulls last, ");
+            out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        } else {
+            out.push_sql(", ");
+            out.push_identifier(PRIMARY_KEY_COLUMN)?;
+            out.push_sql(" ");
+            out.push_sql(direction);
         }
+        Ok(())
     }
 
     /// Generate
@@ -2413,6 +3100,7 @@ pub struct FilterQuery<'a> {
 impl<'a> FilterQuery<'a> {
     pub fn new(
         collection: &'a FilterCollection,
+        layout: &'a Layout,
         filter: Option<&'a EntityFilter>,
         order: EntityOrder,
         range: EntityRange,
@@ -2426,7 +3114,13 @@ impl<'a> FilterQuery<'a> {
         let first_table = collection
             .first_table()
             .expect("an entity query always contains at least one entity type/table");
-        let sort_key = SortKey::new(order, first_table, filter, block)?;
+        let sort_key = SortKey::new(order, layout, first_table, filter, block)?;
+        if matches!(sort_key, SortKey::ChildKey { .. }) && !collection.is_single_table() {
+            return Err(QueryExecutionError::NotSupported(
+                "ordering by a field of a related entity when querying several entity types"
+                    .to_string(),
+            ));
+        }
 
         Ok(FilterQuery {
             collection,
@@ -2738,6 +3432,446 @@ impl<'a> LoadQuery<PgConnection, EntityData> for FilterQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for FilterQuery<'a> {}
 
+/// Declare a cursor for a `FilterQuery` so that its rows can be fetched
+/// in batches with `fetch forward {n} from {name}`. The generated query is
+///
+///   declare {name} no scroll cursor for {query}
+///
+/// The cursor only lives until the end of the current transaction
+#[derive(Debug, Clone)]
+pub struct DeclareCursorQuery<'a> {
+    name: &'static str,
+    query: FilterQuery<'a>,
+}
+
+impl<'a> DeclareCursorQuery<'a> {
+    pub fn new(name: &'static str, query: FilterQuery<'a>) -> Self {
+        DeclareCursorQuery { name, query }
+    }
+}
+
+impl<'a> QueryFragment<Pg> for DeclareCursorQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+        out.push_sql("declare ");
+        out.push_identifier(self.name)?;
+        out.push_sql(" no scroll cursor for\n");
+        self.query.walk_ast(out.reborrow())
+    }
+}
+
+impl<'a> QueryId for DeclareCursorQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for DeclareCursorQuery<'a> {}
+
+/// Explain how the database executes a `FilterQuery` by actually running
+/// it. The generated query is
+///
+///   explain (analyze, buffers) {query}
+///
+/// and each row of the result is one line of the query plan
+#[derive(Debug, Clone)]
+pub struct ExplainQuery<'a> {
+    query: FilterQuery<'a>,
+}
+
+impl<'a> ExplainQuery<'a> {
+    pub fn new(query: FilterQuery<'a>) -> Self {
+        ExplainQuery { query }
+    }
+}
+
+impl<'a> QueryFragment<Pg> for ExplainQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+        out.push_sql("explain (analyze, buffers)\n");
+        self.query.walk_ast(out.reborrow())
+    }
+}
+
+impl<'a> QueryId for ExplainQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> Query for ExplainQuery<'a> {
+    type SqlType = Text;
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for ExplainQuery<'a> {}
+
+/// Compute aggregates over all entities in `table` that match `filter`.
+/// The generated query is
+///
+///   select array[count(*)::text, sum(c.{column})::text, ..]::text[] as aggregates
+///     from {table} c
+///    where block_range @> $block
+///      and {filter}
+///
+/// Aggregates are returned as text so that we do not lose precision on
+/// `numeric` columns
+#[derive(Debug, Clone)]
+pub struct AggregateQuery<'a> {
+    table: &'a Table,
+    filter: Option<QueryFilter<'a>>,
+    aggregates: Vec<(AggregateFunction, Option<&'a Column>)>,
+    block: BlockNumber,
+    query_id: Option<String>,
+}
+
+impl<'a> AggregateQuery<'a> {
+    pub fn new(
+        layout: &'a Layout,
+        table: &'a Table,
+        filter: Option<&'a EntityFilter>,
+        aggregates: &[EntityAggregate],
+        block: BlockNumber,
+        query_id: Option<String>,
+    ) -> Result<Self, StoreError> {
+        let filter = filter
+            .map(|filter| QueryFilter::new(filter, layout, table, block))
+            .transpose()?;
+        let aggregates = aggregates
+            .iter()
+            .map(
+                |aggregate| match (aggregate.function, &aggregate.attribute) {
+                    (AggregateFunction::Count, _) => Ok((AggregateFunction::Count, None)),
+                    (function, Some(attribute)) => {
+                        let column = table.column_for_field(attribute)?;
+                        if !column.is_numeric() {
+                            return Err(StoreError::QueryExecutionError(format!(
+                                "can not compute `{}` of attribute `{}` of entity `{}` \
+                             since it is not numeric",
+                                function, attribute, table.object
+                            )));
+                        }
+                        Ok((function, Some(column)))
+                    }
+                    (function, None) => Err(StoreError::ConstraintViolation(format!(
+                        "the aggregate `{}` needs an attribute",
+                        function
+                    ))),
+                },
+            )
+            .collect::<Result<_, _>>()?;
+        Ok(AggregateQuery {
+            table,
+            filter,
+            aggregates,
+            block,
+            query_id,
+        })
+    }
+
+    /// Convert the text values the database returned into values with
+    /// the GraphQL type of the corresponding aggregate. Counts are `Int`,
+    /// averages are `BigDecimal`, sums of `Int` are `BigInt`, and all other
+    /// aggregates have the type of the underlying attribute
+    pub fn values(&self, data: AggregateData) -> Result<Vec<r::Value>, StoreError> {
+        use AggregateFunction::*;
+
+        if data.aggregates.len() != self.aggregates.len() {
+            return Err(StoreError::ConstraintViolation(format!(
+                "expected {} aggregates but got {}",
+                self.aggregates.len(),
+                data.aggregates.len()
+            )));
+        }
+        self.aggregates
+            .iter()
+            .zip(data.aggregates)
+            .map(|((function, column), value)| {
+                let value = match value {
+                    Some(value) => value,
+                    None => return Ok(r::Value::Null),
+                };
+                let column_type = column.map(|column| &column.column_type);
+                match (function, column_type) {
+                    (Count, _) | (Min, Some(ColumnType::Int)) | (Max, Some(ColumnType::Int)) => {
+                        i64::from_str(&value).map(r::Value::Int).map_err(|e| {
+                            StoreError::Unknown(anyhow!(
+                                "failed to convert {} to Int: {}",
+                                value,
+                                e
+                            ))
+                        })
+                    }
+                    (Avg, _) | (_, Some(ColumnType::BigDecimal)) => {
+                        scalar::BigDecimal::from_str(&value)
+                            .map(|d| r::Value::String(d.to_string()))
+                            .map_err(|e| {
+                                StoreError::Unknown(anyhow!(
+                                    "failed to convert {} to BigDecimal: {}",
+                                    value,
+                                    e
+                                ))
+                            })
+                    }
+                    (_, _) => Ok(r::Value::String(value)),
+                }
+            })
+            .collect()
+    }
+}
+
+impl<'a> QueryFragment<Pg> for AggregateQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        if let Some(qid) = &self.query_id {
+            out.push_sql("/* qid: ");
+            out.push_sql(qid);
+            out.push_sql(" */\n");
+        }
+        out.push_sql("select array[");
+        for (i, (function, column)) in self.aggregates.iter().enumerate() {
+            if i > 0 {
+                out.push_sql(", ");
+            }
+            match column {
+                None => out.push_sql("count(*)"),
+                Some(column) => {
+                    out.push_sql(function.as_str());
+                    out.push_sql("(c.");
+                    out.push_identifier(column.name.as_str())?;
+                    out.push_sql(")");
+                }
+            }
+            out.push_sql("::text");
+        }
+        out.push_sql("]::text[] as aggregates");
+        out.push_sql("\n  from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql(" c");
+        out.push_sql("\n where ");
+        BlockRangeColumn::new(self.table, "c.", self.block).contains(&mut out)?;
+        if let Some(filter) = &self.filter {
+            out.push_sql(" and ");
+            filter.walk_ast(out.reborrow())?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for AggregateQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+#[derive(QueryableByName)]
+pub struct AggregateData {
+    #[sql_type = "Array<Nullable<Text>>"]
+    pub aggregates: Vec<Option<String>>,
+}
+
+impl<'a> LoadQuery<PgConnection, AggregateData> for AggregateQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<AggregateData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for AggregateQuery<'a> {}
+
+/// Write the rollups of `aggregation` for all intervals of length
+/// `interval` that start in `[start, end)`. The points of the source are
+/// grouped by the start of their interval and the values of the
+/// dimensions, and each group becomes one rollup. The generated query is
+///
+///   insert into {aggregation}(id, interval, timestamp, {dims}, {aggs}, block$)
+///   select {id}, $interval, r.ts, r.d0, .., r.a0, .., $block
+///     from (select c.timestamp - ((c.timestamp % {secs}) + {secs}) % {secs} as ts,
+///                  c.{dim} as d0, .., {fn}(c.{arg})::{type} as a0, ..
+///             from {source} c
+///            where c.timestamp >= $start and c.timestamp < $end
+///              and block$ <= $block
+///            group by ts, d0, ..) r
+///   returning id
+///
+/// The id of a rollup is the interval and its start, followed by the
+/// values of the dimensions. Each value is prefixed with its length so
+/// that different dimensions can never produce the same id
+#[derive(Debug)]
+pub struct RollupQuery<'a> {
+    source: &'a Table,
+    table: &'a Table,
+    interval: AggregationInterval,
+    timestamp: &'a Column,
+    columns: [&'a Column; 2],
+    dimensions: Vec<(&'a Column, &'a Column)>,
+    aggregates: Vec<(AggregateFunction, Option<&'a Column>, &'a Column)>,
+    start: i32,
+    end: i32,
+    block: BlockNumber,
+}
+
+impl<'a> RollupQuery<'a> {
+    pub fn new(
+        source: &'a Table,
+        table: &'a Table,
+        aggregation: &'a AggregationDefinition,
+        interval: AggregationInterval,
+        start: i32,
+        end: i32,
+        block: BlockNumber,
+    ) -> Result<Self, StoreError> {
+        let dimensions = aggregation
+            .dimensions
+            .iter()
+            .map(|dimension| {
+                Ok((
+                    source.column_for_field(dimension)?,
+                    table.column_for_field(dimension)?,
+                ))
+            })
+            .collect::<Result<_, StoreError>>()?;
+        let aggregates = aggregation
+            .aggregates
+            .iter()
+            .map(|aggregate| {
+                let arg = aggregate
+                    .arg
+                    .as_ref()
+                    .map(|arg| source.column_for_field(arg))
+                    .transpose()?;
+                Ok((
+                    aggregate.function,
+                    arg,
+                    table.column_for_field(&aggregate.field)?,
+                ))
+            })
+            .collect::<Result<_, StoreError>>()?;
+        Ok(RollupQuery {
+            source,
+            table,
+            interval,
+            timestamp: source.column_for_field(TIMESTAMP_FIELD)?,
+            columns: [
+                table.column_for_field(INTERVAL_FIELD)?,
+                table.column_for_field(TIMESTAMP_FIELD)?,
+            ],
+            dimensions,
+            aggregates,
+            start,
+            end,
+            block,
+        })
+    }
+}
+
+impl<'a> QueryFragment<Pg> for RollupQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        let timestamp = self.timestamp.name.as_str();
+        let secs = self.interval.seconds().to_string();
+        let br_column = BlockRangeColumn::new(self.table, "", self.block);
+
+        out.push_sql("insert into ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql("(");
+        out.push_identifier(PRIMARY_KEY_COLUMN)?;
+        for column in self.columns {
+            out.push_sql(", ");
+            out.push_identifier(column.name.as_str())?;
+        }
+        for (_, column) in &self.dimensions {
+            out.push_sql(", ");
+            out.push_identifier(column.name.as_str())?;
+        }
+        for (_, _, column) in &self.aggregates {
+            out.push_sql(", ");
+            out.push_identifier(column.name.as_str())?;
+        }
+        out.push_sql(", ");
+        br_column.name(&mut out);
+        out.push_sql(")\nselect ");
+        out.push_bind_param::<Text, _>(&self.interval.as_str())?;
+        out.push_sql(" || '-' || r.ts");
+        for i in 0..self.dimensions.len() {
+            out.push_sql(&format!(
+                " || '-' || coalesce(length(r.d{i}::text) || ':' || r.d{i}::text, 'null')"
+            ));
+        }
+        out.push_sql(", ");
+        out.push_bind_param::<Text, _>(&self.interval.as_str())?;
+        out.push_sql(", r.ts");
+        for i in 0..self.dimensions.len() {
+            out.push_sql(&format!(", r.d{i}"));
+        }
+        for i in 0..self.aggregates.len() {
+            out.push_sql(&format!(", r.a{i}"));
+        }
+        out.push_sql(", ");
+        br_column.literal_range_current(&mut out)?;
+
+        out.push_sql("\n  from (select c.");
+        out.push_identifier(timestamp)?;
+        out.push_sql(" - ((c.");
+        out.push_identifier(timestamp)?;
+        out.push_sql(&format!(" % {secs}) + {secs}) % {secs} as ts"));
+        for (i, (column, _)) in self.dimensions.iter().enumerate() {
+            out.push_sql(", c.");
+            out.push_identifier(column.name.as_str())?;
+            out.push_sql(&format!(" as d{i}"));
+        }
+        for (i, (function, arg, column)) in self.aggregates.iter().enumerate() {
+            out.push_sql(", ");
+            match arg {
+                None => out.push_sql("count(*)"),
+                Some(arg) => {
+                    out.push_sql(function.as_str());
+                    out.push_sql("(c.");
+                    out.push_identifier(arg.name.as_str())?;
+                    out.push_sql(")");
+                }
+            }
+            out.push_sql("::");
+            out.push_sql(column.column_type.sql_type());
+            out.push_sql(&format!(" as a{i}"));
+        }
+        out.push_sql("\n          from ");
+        out.push_sql(self.source.qualified_name.as_str());
+        out.push_sql(" c\n         where c.");
+        out.push_identifier(timestamp)?;
+        out.push_sql(" >= ");
+        out.push_bind_param::<Integer, _>(&self.start)?;
+        out.push_sql(" and c.");
+        out.push_identifier(timestamp)?;
+        out.push_sql(" < ");
+        out.push_bind_param::<Integer, _>(&self.end)?;
+        out.push_sql(" and ");
+        BlockRangeColumn::new(self.source, "c.", self.block).contains(&mut out)?;
+        out.push_sql("\n         group by ts");
+        for i in 0..self.dimensions.len() {
+            out.push_sql(&format!(", d{i}"));
+        }
+        out.push_sql(") r\nreturning ");
+        out.push_sql(PRIMARY_KEY_COLUMN);
+        out.push_sql("::text");
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for RollupQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a> LoadQuery<PgConnection, ReturnedEntityData> for RollupQuery<'a> {
+    fn internal_load(self, conn: &PgConnection) -> QueryResult<Vec<ReturnedEntityData>> {
+        conn.query_by_name(&self)
+    }
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for RollupQuery<'a> {}
+
 /// Reduce the upper bound of the current entry's block range to `block` as
 /// long as that does not result in an empty block range
 #[derive(Debug)]
@@ -2878,6 +4012,62 @@ impl<'a> LoadQuery<PgConnection, ReturnedEntityData> for RevertRemoveQuery<'a> {
 
 impl<'a, Conn> RunQueryDsl<Conn> for RevertRemoveQuery<'a> {}
 
+/// Delete a batch of entity versions from `table` that are not visible at
+/// `earliest_block` or any later block. Only versions that were updated or
+/// deleted before `earliest_block` qualify, and since writes only ever
+/// change current versions, pruning never touches the same rows as writes
+#[derive(Debug, Clone)]
+pub struct PruneQuery<'a> {
+    table: &'a Table,
+    earliest_block: BlockNumber,
+    batch_size: i64,
+}
+
+impl<'a> PruneQuery<'a> {
+    pub fn new(table: &'a Table, earliest_block: BlockNumber, batch_size: i64) -> Self {
+        Self {
+            table,
+            earliest_block,
+            batch_size,
+        }
+    }
+}
+
+impl<'a> QueryFragment<Pg> for PruneQuery<'a> {
+    fn walk_ast(&self, mut out: AstPass<Pg>) -> QueryResult<()> {
+        out.unsafe_to_cache_prepared();
+
+        // Construct a query
+        //   delete from table
+        //    where vid in (select vid from table
+        //                   where block_range << int4range($earliest_block, null)
+        //                   limit $batch_size)
+        // The condition `<<` means that the block range lies strictly
+        // before `earliest_block`, and it can use the index on
+        // `block_range`
+        out.push_sql("delete from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql("\n where vid in (select vid from ");
+        out.push_sql(self.table.qualified_name.as_str());
+        out.push_sql("\n                where ");
+        out.push_sql(BLOCK_RANGE_COLUMN);
+        out.push_sql(" << int4range(");
+        out.push_bind_param::<Integer, _>(&self.earliest_block)?;
+        out.push_sql(", null)\n                limit ");
+        out.push_bind_param::<BigInt, _>(&self.batch_size)?;
+        out.push_sql(")");
+        Ok(())
+    }
+}
+
+impl<'a> QueryId for PruneQuery<'a> {
+    type QueryId = ();
+
+    const HAS_STATIC_QUERY_ID: bool = false;
+}
+
+impl<'a, Conn> RunQueryDsl<Conn> for PruneQuery<'a> {}
+
 /// A query that unclamps the block range of all versions that contain
 /// `block` by setting the upper bound of the block range to infinity.
 #[derive(Debug, Clone)]
@@ -0,0 +1,156 @@
+//! Blocks that operators told graph-node to skip when indexing a
+//! deployment, either completely or only for one handler. Records are never
+//! deleted while the deployment exists; removing a skip only marks it as
+//! removed so that it is always possible to tell which blocks were skipped
+//! and why.
+use std::collections::HashMap;
+
+use diesel::dsl::sql;
+use diesel::pg::PgConnection;
+use diesel::prelude::{ExpressionMethods, QueryDsl, RunQueryDsl};
+use diesel::{insert_into, update};
+
+use graph::data::subgraph::schema::SkippedBlock;
+use graph::prelude::{chrono, BlockNumber, StoreError};
+
+use crate::primary::{DeploymentId, Site};
+
+table! {
+    subgraphs.skipped_block (id) {
+        id -> Integer,
+        subgraph_id -> Integer,
+        block_number -> Integer,
+        handler -> Nullable<Text>,
+        reason -> Text,
+        created_at -> Timestamptz,
+        removed_at -> Nullable<Timestamptz>,
+    }
+}
+
+type Row = (
+    BlockNumber,
+    Option<String>,
+    String,
+    chrono::DateTime<chrono::Utc>,
+);
+
+fn from_row((block_number, handler, reason, created_at): Row) -> SkippedBlock {
+    SkippedBlock {
+        block_number,
+        handler,
+        reason,
+        created_at,
+    }
+}
+
+/// Skip `block` for the deployment `site`. If `handler` is `None`, no
+/// triggers are processed for the block at all
+pub(crate) fn insert(
+    conn: &PgConnection,
+    site: &Site,
+    block: BlockNumber,
+    handler: Option<&str>,
+    reason: &str,
+) -> Result<(), StoreError> {
+    use skipped_block as sb;
+
+    insert_into(sb::table)
+        .values((
+            sb::subgraph_id.eq(site.id),
+            sb::block_number.eq(block),
+            sb::handler.eq(handler),
+            sb::reason.eq(reason),
+        ))
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Stop skipping `block`, or only `handler` in `block`, for `site` and
+/// return how many skips were removed
+pub(crate) fn remove(
+    conn: &PgConnection,
+    site: &Site,
+    block: BlockNumber,
+    handler: Option<&str>,
+) -> Result<usize, StoreError> {
+    use skipped_block as sb;
+
+    let skips = sb::table
+        .filter(sb::subgraph_id.eq(site.id))
+        .filter(sb::block_number.eq(block))
+        .filter(sb::removed_at.is_null());
+    let count = match handler {
+        Some(handler) => update(skips.filter(sb::handler.eq(handler)))
+            .set(sb::removed_at.eq(sql("now()")))
+            .execute(conn)?,
+        None => update(skips.filter(sb::handler.is_null()))
+            .set(sb::removed_at.eq(sql("now()")))
+            .execute(conn)?,
+    };
+    Ok(count)
+}
+
+/// The blocks that are currently skipped for `site`, ordered by block
+/// number
+pub(crate) fn load(conn: &PgConnection, site: &Site) -> Result<Vec<SkippedBlock>, StoreError> {
+    use skipped_block as sb;
+
+    Ok(sb::table
+        .filter(sb::subgraph_id.eq(site.id))
+        .filter(sb::removed_at.is_null())
+        .order_by((sb::block_number, sb::id))
+        .select((sb::block_number, sb::handler, sb::reason, sb::created_at))
+        .load::<Row>(conn)?
+        .into_iter()
+        .map(from_row)
+        .collect())
+}
+
+/// The numbers of the blocks up to and including `block` that are
+/// currently skipped for `site`
+pub(crate) fn block_numbers(
+    conn: &PgConnection,
+    site: &Site,
+    block: BlockNumber,
+) -> Result<Vec<BlockNumber>, StoreError> {
+    use skipped_block as sb;
+
+    Ok(sb::table
+        .filter(sb::subgraph_id.eq(site.id))
+        .filter(sb::removed_at.is_null())
+        .filter(sb::block_number.le(block))
+        .select(sb::block_number)
+        .distinct()
+        .order_by(sb::block_number)
+        .load::<BlockNumber>(conn)?)
+}
+
+/// The blocks that are currently skipped for each of the deployments with
+/// `ids`, or for all deployments if `ids` is empty
+pub(crate) fn load_for_deployments(
+    conn: &PgConnection,
+    ids: &[DeploymentId],
+) -> Result<HashMap<DeploymentId, Vec<SkippedBlock>>, StoreError> {
+    use skipped_block as sb;
+
+    let query = sb::table
+        .filter(sb::removed_at.is_null())
+        .order_by((sb::subgraph_id, sb::block_number, sb::id))
+        .select((
+            sb::subgraph_id,
+            (sb::block_number, sb::handler, sb::reason, sb::created_at),
+        ));
+    let rows = if ids.is_empty() {
+        query.load::<(DeploymentId, Row)>(conn)?
+    } else {
+        query
+            .filter(sb::subgraph_id.eq_any(ids.iter().copied()))
+            .load::<(DeploymentId, Row)>(conn)?
+    };
+
+    let mut skipped: HashMap<_, Vec<_>> = HashMap::new();
+    for (id, row) in rows {
+        skipped.entry(id).or_default().push(from_row(row));
+    }
+    Ok(skipped)
+}
@@ -5,18 +5,23 @@ use graph::{
     components::{
         server::index_node::VersionInfo,
         store::{
-            BlockStore as BlockStoreTrait, QueryStoreManager, StatusStore, Store as StoreTrait,
+            BlockStore as BlockStoreTrait, ChainStore as _, QueryStoreManager, StatusStore,
+            Store as StoreTrait,
         },
     },
     constraint_violation,
     data::subgraph::status,
     prelude::{
-        tokio, web3::types::Address, BlockPtr, CheapClone, DeploymentHash, QueryExecutionError,
-        StoreError,
+        tokio, web3::types::Address, BlockNumber, BlockPtr, CheapClone, DeploymentHash,
+        QueryExecutionError, StoreError,
     },
 };
 
-use crate::{block_store::BlockStore, query_store::QueryStore, SubgraphStore};
+use crate::{
+    block_store::BlockStore,
+    query_store::{QueryExplanations, QueryStore},
+    SubgraphStore,
+};
 
 /// The overall store of the system, consisting of a [`SubgraphStore`] and a
 /// [`BlockStore`], each of which multiplex across multiple database shards.
@@ -32,6 +37,7 @@ use crate::{block_store::BlockStore, query_store::QueryStore, SubgraphStore};
 pub struct Store {
     subgraph_store: Arc<SubgraphStore>,
     block_store: Arc<BlockStore>,
+    explanations: Option<Arc<QueryExplanations>>,
 }
 
 impl Store {
@@ -39,6 +45,18 @@ impl Store {
         Self {
             subgraph_store,
             block_store,
+            explanations: None,
+        }
+    }
+
+    /// A copy of this store whose query stores add the SQL and the query
+    /// plan of every entity query they run to `explanations`. This makes
+    /// queries considerably slower and is only meant for diagnosing them
+    pub fn with_explanations(&self, explanations: Arc<QueryExplanations>) -> Self {
+        Self {
+            subgraph_store: self.subgraph_store.cheap_clone(),
+            block_store: self.block_store.cheap_clone(),
+            explanations: Some(explanations),
         }
     }
 
@@ -92,7 +110,13 @@ impl QueryStoreManager for Store {
             )
         })?;
 
-        Ok(Arc::new(QueryStore::new(store, chain_store, site, replica)))
+        Ok(Arc::new(QueryStore::new(
+            store,
+            chain_store,
+            site,
+            replica,
+            self.explanations.cheap_clone(),
+        )))
     }
 }
 
@@ -144,6 +168,35 @@ impl StatusStore for Store {
             .await
     }
 
+    async fn get_proofs_of_indexing(
+        &self,
+        subgraph_id: &DeploymentHash,
+        indexer: &Option<Address>,
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<Vec<(BlockPtr, [u8; 32])>, StoreError> {
+        let network = self.subgraph_store.network(subgraph_id)?;
+        let chain_store = self
+            .block_store
+            .chain_store(&network)
+            .ok_or_else(|| constraint_violation!("unknown network `{}`", network))?;
+
+        let mut blocks = Vec::new();
+        for number in from..=to {
+            let hashes = chain_store
+                .block_hashes_by_block_number(number)
+                .map_err(StoreError::Unknown)?;
+            // Without a single hash we can't tell which block is the
+            // one the deployment processed
+            if let [hash] = hashes.as_slice() {
+                blocks.push(BlockPtr::from((*hash, number)));
+            }
+        }
+        self.subgraph_store
+            .get_proofs_of_indexing(subgraph_id, indexer, blocks)
+            .await
+    }
+
     async fn query_permit(&self) -> tokio::sync::OwnedSemaphorePermit {
         // Status queries go to the primary shard.
         self.block_store.query_permit_primary().await
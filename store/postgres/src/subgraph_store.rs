@@ -5,42 +5,62 @@ use diesel::{
     types::{FromSql, ToSql},
 };
 use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, RwLock},
+};
+use std::{fmt, io::Write, path::Path};
+use std::{
+    iter::FromIterator,
+    time::{Duration, Instant},
 };
-use std::{fmt, io::Write};
-use std::{iter::FromIterator, time::Duration};
 
 use graph::{
     cheap_clone::CheapClone,
     components::{
         server::index_node::VersionInfo,
-        store::{self, DeploymentLocator, EnsLookup as EnsLookupTrait, SubgraphFork},
+        store::{
+            self, DeploymentLoad, DeploymentLocator, EnsLookup as EnsLookupTrait, EntityType,
+            NoopPruneReporter, PersistedQueryStore as PersistedQueryStoreTrait, PruneReporter,
+            SubgraphFork, WritableStore as _,
+        },
     },
     constraint_violation,
     data::query::QueryTarget,
-    data::subgraph::{schema::DeploymentCreate, status},
+    data::subgraph::{
+        schema::{
+            DeploymentCreate, DeploymentPriority, MappingLog, QuarantinedDataSource, SkippedBlock,
+        },
+        status,
+    },
     prelude::StoreEvent,
     prelude::{
         anyhow, futures03::future::join_all, lazy_static, o, web3::types::Address, ApiSchema,
-        BlockNumber, BlockPtr, DeploymentHash, EntityOperation, Logger, NodeId, Schema, StoreError,
-        SubgraphName, SubgraphStore as SubgraphStoreTrait, SubgraphVersionSwitchingMode,
+        BlockNumber, BlockPtr, DeploymentHash, EntityOperation, Logger, MetricsRegistry, NodeId,
+        Schema, StoreError, SubgraphName, SubgraphStore as SubgraphStoreTrait,
+        SubgraphVersionSwitchingMode, ENV_VARS,
     },
+    slog::{warn, Level},
     url::Url,
     util::timed_cache::TimedCache,
 };
 
+use crate::attribute_usage::IndexSuggestion;
+use crate::backup::{BackupMetadata, ExportFormat};
+use crate::checksum::Checksums;
 use crate::fork;
 use crate::{
+    change_feed::ChangeFeed,
     connection_pool::ConnectionPool,
-    primary,
+    maintenance, primary,
     primary::{DeploymentId, Mirror as PrimaryMirror, Site},
+    rebalance::{self, DeploymentMove},
     relational::Layout,
+    webhooks::Webhooks,
     writable::WritableAgent,
     NotificationSender,
 };
 use crate::{
-    deployment_store::{DeploymentStore, ReplicaId},
+    deployment_store::{DeferredIndexStatus, DeploymentStore, ReplicaId},
     detail::DeploymentDetail,
     primary::UnusedDeployment,
 };
@@ -190,6 +210,7 @@ pub struct SubgraphStore {
     /// subgraph forks will fetch entities.
     /// Example: https://api.thegraph.com/subgraphs/
     fork_base: Option<Url>,
+    persisted_queries: Arc<PersistedQueries>,
 }
 
 impl SubgraphStore {
@@ -213,10 +234,16 @@ impl SubgraphStore {
         placer: Arc<dyn DeploymentPlacer + Send + Sync + 'static>,
         sender: Arc<NotificationSender>,
         fork_base: Option<Url>,
+        registry: Arc<dyn MetricsRegistry>,
     ) -> Self {
+        let inner = Arc::new(SubgraphStoreInner::new(
+            logger, stores, placer, sender, registry,
+        ));
+        let persisted_queries = Arc::new(PersistedQueries::new(inner.mirror.primary().clone()));
         Self {
-            inner: Arc::new(SubgraphStoreInner::new(logger, stores, placer, sender)),
+            inner,
             fork_base,
+            persisted_queries,
         }
     }
 
@@ -229,9 +256,59 @@ impl SubgraphStore {
         self.inner.get_proof_of_indexing(id, indexer, block).await
     }
 
+    pub(crate) async fn get_proofs_of_indexing(
+        &self,
+        id: &DeploymentHash,
+        indexer: &Option<Address>,
+        blocks: Vec<BlockPtr>,
+    ) -> Result<Vec<(BlockPtr, [u8; 32])>, StoreError> {
+        self.inner.get_proofs_of_indexing(id, indexer, blocks).await
+    }
+
+    /// The network that the deployment `id` indexes
+    pub(crate) fn network(&self, id: &DeploymentHash) -> Result<String, StoreError> {
+        self.inner.site(id).map(|site| site.network.clone())
+    }
+
     pub fn notification_sender(&self) -> Arc<NotificationSender> {
         self.sender.clone()
     }
+
+    /// Plan at most `max_moves` moves of deployments that spread the load
+    /// of indexing evenly across healthy index nodes, and make them if
+    /// `apply` is `true`. Index nodes are healthy if they reported their
+    /// load within the last three `GRAPH_LOAD_REPORT_INTERVAL`s
+    pub fn rebalance(
+        &self,
+        max_moves: usize,
+        apply: bool,
+    ) -> Result<Vec<DeploymentMove>, StoreError> {
+        let max_age = ENV_VARS
+            .load_report_interval
+            .unwrap_or(Duration::from_secs(60))
+            * 3;
+
+        let (nodes, loads) = self.primary_conn()?.load_reports(max_age)?;
+        let moves = rebalance::plan(&nodes, &loads, max_moves);
+        if apply {
+            for mv in &moves {
+                let loc = DeploymentLocator::new(mv.deployment, mv.hash.clone());
+                self.reassign_subgraph(&loc, &mv.to)?;
+            }
+        }
+        Ok(moves)
+    }
+
+    /// Unassign the deployments that were replaced by a canary and whose
+    /// time to retire has come. Return how many deployments were unassigned
+    pub fn retire_replaced_deployments(&self) -> Result<usize, StoreError> {
+        let changes = self.primary_conn()?.retire_replaced_deployments()?;
+        let count = changes.len();
+        if count > 0 {
+            self.send_store_event(&StoreEvent::new(changes))?;
+        }
+        Ok(count)
+    }
 }
 
 impl std::ops::Deref for SubgraphStore {
@@ -255,6 +332,11 @@ pub struct SubgraphStoreInner {
     placer: Arc<dyn DeploymentPlacer + Send + Sync + 'static>,
     sender: Arc<NotificationSender>,
     writables: Mutex<HashMap<DeploymentId, Arc<WritableAgent>>>,
+    /// Where to publish entity changes, if anywhere
+    change_feed: Option<Arc<ChangeFeed>>,
+    /// Where to send lifecycle events of deployments, if anywhere
+    webhooks: Option<Arc<Webhooks>>,
+    registry: Arc<dyn MetricsRegistry>,
 }
 
 impl SubgraphStoreInner {
@@ -277,6 +359,7 @@ impl SubgraphStoreInner {
         stores: Vec<(Shard, ConnectionPool, Vec<ConnectionPool>, Vec<usize>)>,
         placer: Arc<dyn DeploymentPlacer + Send + Sync + 'static>,
         sender: Arc<NotificationSender>,
+        registry: Arc<dyn MetricsRegistry>,
     ) -> Self {
         let mirror = {
             let pools = HashMap::from_iter(
@@ -302,6 +385,10 @@ impl SubgraphStoreInner {
             },
         ));
         let sites = TimedCache::new(SITES_CACHE_TTL);
+        let change_feed =
+            ChangeFeed::from_env(logger).expect("GRAPH_STORE_CHANGE_FEED_URL is invalid");
+        let webhooks =
+            Webhooks::from_env(logger).expect("the lifecycle webhook configuration is invalid");
         SubgraphStoreInner {
             mirror,
             stores,
@@ -309,9 +396,20 @@ impl SubgraphStoreInner {
             placer,
             sender,
             writables: Mutex::new(HashMap::new()),
+            change_feed,
+            webhooks,
+            registry,
         }
     }
 
+    pub(crate) fn change_feed(&self) -> Option<&Arc<ChangeFeed>> {
+        self.change_feed.as_ref()
+    }
+
+    pub(crate) fn webhooks(&self) -> Option<&Arc<Webhooks>> {
+        self.webhooks.as_ref()
+    }
+
     // Only needed for tests
     #[cfg(debug_assertions)]
     pub(crate) fn clear_caches(&self) {
@@ -629,6 +727,206 @@ impl SubgraphStoreInner {
         Ok(())
     }
 
+    /// Switch over from the currently active deployment with the same hash
+    /// as `dst` to the copy `dst`. The copy must have finished copying data,
+    /// must not have failed, and must be at or past the current head of
+    /// the source, so that queries never see the deployment go back to an
+    /// earlier block. Activating the copy and removing the assignment of
+    /// the source happen in one transaction, so that queries and indexing
+    /// switch over at the same time; if `node` is given, the copy is moved
+    /// to that node in the same transaction. Return the source, which is
+    /// now unused and can be removed with `remove_deployment`
+    pub fn switch_to_copy(
+        &self,
+        dst: &DeploymentLocator,
+        node: Option<NodeId>,
+    ) -> Result<DeploymentLocator, StoreError> {
+        let dst = self.find_site(dst.id.into())?;
+        let dst_loc = DeploymentLocator::from(dst.as_ref());
+        let src = self
+            .mirror
+            .find_active_site(&dst.deployment)?
+            .ok_or_else(|| StoreError::DeploymentNotFound(dst.deployment.to_string()))?;
+        let src = Arc::new(src);
+        let src_loc = DeploymentLocator::from(src.as_ref());
+
+        if src.id == dst.id {
+            return Err(StoreError::Unknown(anyhow!(
+                "deployment {} is already the active copy",
+                dst_loc
+            )));
+        }
+        if self.primary_conn()?.is_copying(dst.as_ref())? {
+            return Err(StoreError::Unknown(anyhow!(
+                "copying data into {} has not finished yet",
+                dst_loc
+            )));
+        }
+
+        let dst_store = self.for_site(dst.as_ref())?;
+        if dst_store.load_deployment(dst.as_ref())?.failed {
+            return Err(StoreError::Unknown(anyhow!(
+                "can not switch to {} because it has failed",
+                dst_loc
+            )));
+        }
+        let src_store = self.for_site(src.as_ref())?;
+
+        let pconn = self.primary_conn()?;
+        pconn.transaction(|| -> Result<_, StoreError> {
+            // The source keeps indexing until the switch, so we check how
+            // far behind the copy is as late as possible
+            let lag = copy_lag(
+                src_store.head_number(src.as_ref())?,
+                dst_store.head_number(dst.as_ref())?,
+            );
+            if lag > 0 {
+                return Err(StoreError::Unknown(anyhow!(
+                    "copy {} is {} blocks behind {}; it can only be switched to once it has caught up",
+                    dst_loc,
+                    lag,
+                    src_loc
+                )));
+            }
+
+            let changes = pconn.switch_to_copy(src.as_ref(), dst.as_ref(), node.as_ref())?;
+            let event = StoreEvent::new(changes);
+            pconn.send_store_event(&self.sender, &event)?;
+            Ok(())
+        })?;
+        drop(pconn);
+
+        // As a side-effect, this will update the `self.sites` cache with
+        // the new active site
+        self.find_site(dst.id)?;
+        Ok(src_loc)
+    }
+
+    /// Write a backup of `deployment` into the directory `dir`, which must
+    /// be empty or not exist yet
+    pub fn backup(
+        &self,
+        deployment: &DeploymentLocator,
+        dir: &Path,
+    ) -> Result<BackupMetadata, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        let store = self.for_site(site.as_ref())?;
+        store.backup(site, dir)
+    }
+
+    /// Write all entities of `deployment` as they were at `block`, or at
+    /// the current head if `block` is `None`, into `dir`, one file per
+    /// entity type. Return the block of the snapshot and the number of
+    /// entities of each type
+    pub fn export(
+        &self,
+        deployment: &DeploymentLocator,
+        block: Option<BlockNumber>,
+        dir: &Path,
+    ) -> Result<(BlockNumber, BTreeMap<String, usize>), StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        let store = self.for_site(site.as_ref())?;
+        store.export(site, block, dir)
+    }
+
+    /// Write the rows of the table for `entity_name` in `deployment` whose
+    /// block range overlaps the blocks from `from` to `to` to `writer` in
+    /// `format`. Return the number of rows written
+    pub fn export_table(
+        &self,
+        deployment: &DeploymentLocator,
+        entity_name: &str,
+        from: Option<BlockNumber>,
+        to: Option<BlockNumber>,
+        format: ExportFormat,
+        writer: &mut dyn Write,
+    ) -> Result<usize, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        let store = self.for_site(site.as_ref())?;
+        store.export_table(site, entity_name, from, to, format, writer)
+    }
+
+    /// Compute checksums over the entities of `deployment` as they were
+    /// at `block`, or at the current head if `block` is `None`. Comparing
+    /// them with the checksums of another deployment shows whether the two
+    /// have exactly the same data
+    pub fn checksums(
+        &self,
+        deployment: &DeploymentLocator,
+        block: Option<BlockNumber>,
+    ) -> Result<Checksums, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        let store = self.for_site(site.as_ref())?;
+        store.checksums(site, block)
+    }
+
+    /// Create a deployment in `shard` from the backup in `dir` and assign
+    /// it to `node`. If `name` is given, the deployment becomes the current
+    /// version of that subgraph, which is created if it does not exist
+    /// yet. The deployment must not exist yet. If restoring fails, the
+    /// partially restored deployment is not assigned to any node and can be
+    /// removed like any other unused deployment
+    pub fn restore(
+        &self,
+        dir: &Path,
+        shard: Shard,
+        node: NodeId,
+        name: Option<SubgraphName>,
+    ) -> Result<DeploymentLocator, StoreError> {
+        let metadata = BackupMetadata::read(dir)?;
+        let hash = DeploymentHash::new(metadata.deployment.clone()).map_err(|hash| {
+            StoreError::Unknown(anyhow!("invalid deployment hash `{}` in backup", hash))
+        })?;
+        if self.mirror.find_active_site(&hash)?.is_some() {
+            return Err(StoreError::Unknown(anyhow!(
+                "deployment {} already exists; it must be removed before it can be restored",
+                hash
+            )));
+        }
+        let schema = Schema::parse(&metadata.schema, hash.clone())?;
+
+        let deployment_store = self
+            .stores
+            .get(&shard)
+            .ok_or_else(|| StoreError::UnknownShard(shard.to_string()))?;
+        let site = self
+            .primary_conn()?
+            .allocate_site(shard, &hash, metadata.network.clone())?;
+        let site = Arc::new(site);
+
+        deployment_store.create_deployment(
+            &schema,
+            metadata.deployment_create(),
+            site.clone(),
+            None,
+            false,
+        )?;
+        deployment_store.restore(site.clone(), dir, &metadata)?;
+
+        let exists_and_synced = |id: &DeploymentHash| {
+            let (store, _) = self.store(id)?;
+            store.deployment_exists_and_synced(id)
+        };
+
+        let pconn = self.primary_conn()?;
+        pconn.transaction(|| -> Result<_, StoreError> {
+            let changes = match name {
+                Some(name) => pconn.create_subgraph_version(
+                    name,
+                    &site,
+                    node,
+                    SubgraphVersionSwitchingMode::Instant,
+                    exists_and_synced,
+                )?,
+                None => pconn.assign_subgraph(&site, &node)?,
+            };
+            let event = StoreEvent::new(changes);
+            pconn.send_store_event(&self.sender, &event)?;
+            Ok(())
+        })?;
+        Ok(site.as_ref().into())
+    }
+
     // Only for tests to simplify their handling of test fixtures, so that
     // tests can reset the block pointer of a subgraph by recreating it
     #[cfg(debug_assertions)]
@@ -671,7 +969,7 @@ impl SubgraphStoreInner {
         };
 
         let (store, site) = self.store(&id)?;
-        let replica = store.replica_for_query(for_subscription)?;
+        let replica = store.replica_for_query(&site, for_subscription)?;
 
         Ok((store.clone(), site, replica))
     }
@@ -891,12 +1189,192 @@ impl SubgraphStoreInner {
         join_all(self.stores.values().map(|store| store.vacuum())).await
     }
 
-    pub fn rewind(&self, id: DeploymentHash, block_ptr_to: BlockPtr) -> Result<(), StoreError> {
+    /// Statistics about the entity tables in each shard
+    pub(crate) async fn table_stats(
+        &self,
+    ) -> Vec<(Shard, Result<Vec<maintenance::TableStats>, StoreError>)> {
+        join_all(
+            self.stores
+                .iter()
+                .map(|(shard, store)| async move { (shard.clone(), store.table_stats().await) }),
+        )
+        .await
+    }
+
+    /// Vacuum or analyze the entity table `namespace.name` in `shard`
+    pub(crate) async fn maintain_table(
+        &self,
+        shard: &Shard,
+        op: maintenance::Operation,
+        namespace: String,
+        name: String,
+    ) -> Result<(), StoreError> {
+        let store = self
+            .stores
+            .get(shard)
+            .ok_or_else(|| StoreError::UnknownShard(shard.to_string()))?;
+        store.maintain_table(op, namespace, name).await
+    }
+
+    /// Build the attribute indexes that deployments deferred until they
+    /// caught up with the chain head. Stop starting new work once
+    /// `deadline` has passed. Return each deployment that was worked on and
+    /// whether all its indexes have been built
+    pub(crate) async fn build_deferred_indexes(
+        &self,
+        deadline: Instant,
+    ) -> Vec<Result<(DeploymentHash, bool), StoreError>> {
+        let mut results = Vec::new();
+        for store in self.stores.values() {
+            let ids = match store.deferred_indexes().await {
+                Ok(ids) => ids,
+                Err(e) => {
+                    results.push(Err(e));
+                    continue;
+                }
+            };
+            for id in ids {
+                if Instant::now() > deadline {
+                    return results;
+                }
+                let res = match self.find_site(id) {
+                    Ok(site) => {
+                        let deployment = site.deployment.clone();
+                        store
+                            .build_deferred_indexes(site, Some(deadline))
+                            .await
+                            .map(|done| (deployment, done))
+                    }
+                    Err(e) => Err(e),
+                };
+                results.push(res);
+            }
+        }
+        results
+    }
+
+    pub(crate) async fn prune_changes(
+        &self,
+        retention: BlockNumber,
+    ) -> Vec<Result<usize, StoreError>> {
+        join_all(
+            self.stores
+                .values()
+                .map(|store| store.prune_changes(retention)),
+        )
+        .await
+    }
+
+    /// Prune the history of all deployments that only keep a limited number
+    /// of blocks of history. For each deployment that was pruned, return
+    /// its new earliest block and how many entity versions were removed
+    pub(crate) async fn prune_history(
+        &self,
+    ) -> Vec<Result<(DeploymentHash, BlockNumber, usize), StoreError>> {
+        let mut results = Vec::new();
+        for store in self.stores.values() {
+            let prunable = match store.prunable().await {
+                Ok(prunable) => prunable,
+                Err(e) => {
+                    results.push(Err(e));
+                    continue;
+                }
+            };
+            for (id, earliest_block) in prunable {
+                let res = match self.find_site(id) {
+                    Ok(site) => {
+                        let deployment = site.deployment.clone();
+                        store
+                            .prune(site, earliest_block, Box::new(NoopPruneReporter))
+                            .await
+                            .map(|count| (deployment, earliest_block, count))
+                    }
+                    Err(e) => Err(e),
+                };
+                results.push(res);
+            }
+        }
+        results
+    }
+
+    /// Rewind the deployment `id` to `block_ptr_to`. With `past_graft`,
+    /// `block_ptr_to` may lie before the graft point of the deployment; if
+    /// the deployment does not have the history to revert to that block
+    /// itself, its data is removed and copied from the graft base again
+    /// when the deployment is started next
+    pub fn rewind(
+        &self,
+        id: DeploymentHash,
+        block_ptr_to: BlockPtr,
+        past_graft: bool,
+    ) -> Result<(), StoreError> {
         let (store, site) = self.store(&id)?;
-        let event = store.rewind(site, block_ptr_to)?;
+        if past_graft {
+            if let Some((base, graft_block)) = store.graft_point(&id)? {
+                if block_ptr_to.number < graft_block.number
+                    && block_ptr_to.number < store.earliest_block(&site)?
+                {
+                    let (base_store, base_site) = self.store(&base)?;
+                    base_store.check_graft_base(base_site, &block_ptr_to)?;
+                }
+            }
+        }
+        let event = store.rewind(site, block_ptr_to, past_graft)?;
+        self.send_store_event(&event)
+    }
+
+    /// Clear the fatal error of the failed deployment `id` so that it
+    /// processes the block at which it failed again. With `block_ptr_to`,
+    /// the deployment is also rewound to that block in the same
+    /// transaction; see `DeploymentStore::unfail`
+    pub fn unfail(
+        &self,
+        id: &DeploymentHash,
+        block_ptr_to: Option<BlockPtr>,
+    ) -> Result<(), StoreError> {
+        let (store, site) = self.store(id)?;
+        let event = store.unfail(site, block_ptr_to)?;
         self.send_store_event(&event)
     }
 
+    /// Skip `block`, or only `handler` in `block`, when indexing the
+    /// deployment `id`. Changes take effect when the deployment is started
+    /// the next time
+    pub fn skip_block(
+        &self,
+        id: &DeploymentHash,
+        block: BlockNumber,
+        handler: Option<&str>,
+        reason: &str,
+    ) -> Result<(), StoreError> {
+        let (store, site) = self.store(id)?;
+        store.skip_block(site, block, handler, reason)
+    }
+
+    /// Stop skipping `block`, or only `handler` in `block`, when indexing
+    /// the deployment `id`, and return how many skips were removed
+    pub fn unskip_block(
+        &self,
+        id: &DeploymentHash,
+        block: BlockNumber,
+        handler: Option<&str>,
+    ) -> Result<usize, StoreError> {
+        let (store, site) = self.store(id)?;
+        store.unskip_block(site, block, handler)
+    }
+
+    /// The latest block at or before `block` at which the proof of
+    /// indexing of the deployment `id` changed, i.e., the latest block up to
+    /// `block` for which a proof of indexing is stored
+    pub fn nearest_poi_block(
+        &self,
+        id: &DeploymentHash,
+        block: BlockNumber,
+    ) -> Result<Option<BlockNumber>, StoreError> {
+        let (store, site) = self.store(id)?;
+        store.nearest_poi_block(site, block)
+    }
+
     pub(crate) async fn get_proof_of_indexing(
         &self,
         id: &DeploymentHash,
@@ -907,6 +1385,16 @@ impl SubgraphStoreInner {
         store.get_proof_of_indexing(site, indexer, block).await
     }
 
+    pub(crate) async fn get_proofs_of_indexing(
+        &self,
+        id: &DeploymentHash,
+        indexer: &Option<Address>,
+        blocks: Vec<BlockPtr>,
+    ) -> Result<Vec<(BlockPtr, [u8; 32])>, StoreError> {
+        let (store, site) = self.store(id)?;
+        store.get_proofs_of_indexing(site, indexer, blocks).await
+    }
+
     // Only used by tests
     #[cfg(debug_assertions)]
     pub fn find(
@@ -947,6 +1435,27 @@ impl SubgraphStoreInner {
         store.analyze(site, entity_name).await
     }
 
+    /// Prune the history of `deployment` so that it only keeps
+    /// `history_blocks` blocks of history, and report progress to
+    /// `reporter`. Running this again after it was interrupted continues
+    /// pruning where it left off
+    pub async fn prune(
+        &self,
+        reporter: Box<dyn PruneReporter>,
+        deployment: &DeploymentLocator,
+        history_blocks: BlockNumber,
+    ) -> Result<usize, StoreError> {
+        let (store, site) = self.store(&deployment.hash)?;
+        let state = store
+            .deployment_state_from_id(site.deployment.clone())
+            .await?;
+        // Never move the earliest block backwards; data before it is gone
+        // already, but an earlier run might not have finished removing it
+        let earliest_block =
+            (state.latest_ethereum_block_number - history_blocks).max(state.earliest_block_number);
+        store.prune(site, earliest_block, reporter).await
+    }
+
     pub async fn create_manual_index(
         &self,
         deployment: &DeploymentLocator,
@@ -969,6 +1478,50 @@ impl SubgraphStoreInner {
         store.indexes_for_entity(site, entity_name).await
     }
 
+    /// Build the attribute indexes that `deployment` deferred right away,
+    /// without waiting for it to catch up with the chain head. Return
+    /// whether all indexes have been built
+    pub async fn build_deferred_indexes_for(
+        &self,
+        deployment: &DeploymentLocator,
+    ) -> Result<bool, StoreError> {
+        let (store, site) = self.store(&deployment.hash)?;
+        store.build_deferred_indexes(site, None).await
+    }
+
+    pub async fn deferred_index_status(
+        &self,
+        deployment: &DeploymentLocator,
+    ) -> Result<DeferredIndexStatus, StoreError> {
+        let (store, site) = self.store(&deployment.hash)?;
+        store.deferred_index_status(site).await
+    }
+
+    /// The attributes of `deployment` that queries used at least
+    /// `min_uses` times but that have no index
+    pub async fn index_suggestions(
+        &self,
+        deployment: &DeploymentLocator,
+        min_uses: i64,
+    ) -> Result<Vec<IndexSuggestion>, StoreError> {
+        let (store, site) = self.store(&deployment.hash)?;
+        store.index_suggestions(site, min_uses).await
+    }
+
+    /// Create the index for an attribute that `index_suggestions` returned
+    /// and return its name
+    pub async fn create_suggested_index(
+        &self,
+        deployment: &DeploymentLocator,
+        entity_type: &str,
+        attribute: &str,
+    ) -> Result<String, StoreError> {
+        let (store, site) = self.store(&deployment.hash)?;
+        store
+            .create_suggested_index(site, entity_type, attribute)
+            .await
+    }
+
     pub async fn drop_index_for_deployment(
         &self,
         deployment: &DeploymentLocator,
@@ -990,6 +1543,53 @@ impl EnsLookupTrait for EnsLookup {
     }
 }
 
+struct PersistedQueries {
+    primary: ConnectionPool,
+    /// The persisted queries by hash. Lookups only use this copy, which is
+    /// updated when queries are registered or removed through this node,
+    /// and reloaded from the database by `refresh`
+    queries: RwLock<HashMap<String, String>>,
+}
+
+impl PersistedQueries {
+    fn new(primary: ConnectionPool) -> Self {
+        PersistedQueries {
+            primary,
+            queries: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl PersistedQueryStoreTrait for PersistedQueries {
+    fn register(&self, hash: &str, query: &str) -> Result<(), StoreError> {
+        let conn = self.primary.get()?;
+        primary::Connection::new(conn).register_persisted_query(hash, query)?;
+        self.queries
+            .write()
+            .unwrap()
+            .insert(hash.to_string(), query.to_string());
+        Ok(())
+    }
+
+    fn remove(&self, hash: &str) -> Result<bool, StoreError> {
+        let conn = self.primary.get()?;
+        let removed = primary::Connection::new(conn).remove_persisted_query(hash)?;
+        self.queries.write().unwrap().remove(hash);
+        Ok(removed)
+    }
+
+    fn find(&self, hash: &str) -> Option<String> {
+        self.queries.read().unwrap().get(hash).cloned()
+    }
+
+    fn refresh(&self) -> Result<(), StoreError> {
+        let conn = self.primary.get()?;
+        let queries = primary::Connection::new(conn).persisted_queries()?;
+        *self.queries.write().unwrap() = HashMap::from_iter(queries);
+        Ok(())
+    }
+}
+
 #[async_trait::async_trait]
 impl SubgraphStoreTrait for SubgraphStore {
     fn ens_lookup(&self) -> Arc<dyn EnsLookupTrait> {
@@ -998,6 +1598,10 @@ impl SubgraphStoreTrait for SubgraphStore {
         })
     }
 
+    fn persisted_queries(&self) -> Arc<dyn PersistedQueryStoreTrait> {
+        self.persisted_queries.cheap_clone()
+    }
+
     // FIXME: This method should not get a node_id
     fn create_subgraph_deployment(
         &self,
@@ -1042,24 +1646,122 @@ impl SubgraphStoreTrait for SubgraphStore {
         pconn.transaction(|| -> Result<_, StoreError> {
             let changes = pconn.reassign_subgraph(site.as_ref(), node_id)?;
             pconn.send_store_event(&self.sender, &StoreEvent::new(changes))
+        })?;
+
+        if let Some(webhooks) = self.webhooks() {
+            webhooks.reassigned(&site.deployment, node_id);
+        }
+        Ok(())
+    }
+
+    fn pause_subgraph(&self, deployment: &DeploymentLocator) -> Result<(), StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        let pconn = self.primary_conn()?;
+        pconn.transaction(|| -> Result<_, StoreError> {
+            let changes = pconn.pause_subgraph(site.as_ref())?;
+            pconn.send_store_event(&self.sender, &StoreEvent::new(changes))
         })
     }
 
+    fn resume_subgraph(&self, deployment: &DeploymentLocator) -> Result<(), StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        let pconn = self.primary_conn()?;
+        pconn.transaction(|| -> Result<_, StoreError> {
+            let changes = pconn.resume_subgraph(site.as_ref())?;
+            pconn.send_store_event(&self.sender, &StoreEvent::new(changes))
+        })
+    }
+
+    fn unassign_subgraph(&self, deployment: &DeploymentLocator) -> Result<(), StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        let pconn = self.primary_conn()?;
+        pconn.transaction(|| -> Result<_, StoreError> {
+            let changes = pconn.unassign_subgraph(site.as_ref())?;
+            pconn.send_store_event(&self.sender, &StoreEvent::new(changes))
+        })
+    }
+
+    fn rewind_subgraph(
+        &self,
+        deployment: &DeploymentLocator,
+        block_ptr_to: BlockPtr,
+    ) -> Result<(), StoreError> {
+        self.rewind(deployment.hash.clone(), block_ptr_to, false)
+    }
+
     fn assigned_node(&self, deployment: &DeploymentLocator) -> Result<Option<NodeId>, StoreError> {
         let site = self.find_site(deployment.id.into())?;
         self.mirror.assigned_node(site.as_ref())
     }
 
+    fn assignment_status(
+        &self,
+        deployment: &DeploymentLocator,
+    ) -> Result<Option<(NodeId, bool)>, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.mirror.assignment_status(site.as_ref())
+    }
+
+    fn assignment_priority(
+        &self,
+        deployment: &DeploymentLocator,
+    ) -> Result<Option<DeploymentPriority>, StoreError> {
+        let site = self.find_site(deployment.id.into())?;
+        self.mirror.assignment_priority(site.as_ref())
+    }
+
     fn assignments(&self, node: &NodeId) -> Result<Vec<DeploymentLocator>, StoreError> {
         self.mirror
             .assignments(node)
             .map(|sites| sites.iter().map(|site| site.into()).collect())
     }
 
+    fn report_load(
+        &self,
+        node: &NodeId,
+        networks: Vec<String>,
+        loads: Vec<DeploymentLoad>,
+    ) -> Result<(), StoreError> {
+        self.primary_conn()?.report_load(node, networks, loads)
+    }
+
     fn subgraph_exists(&self, name: &SubgraphName) -> Result<bool, StoreError> {
         self.mirror.subgraph_exists(name)
     }
 
+    fn current_deployment(
+        &self,
+        name: &SubgraphName,
+    ) -> Result<Option<DeploymentHash>, StoreError> {
+        Ok(self
+            .mirror
+            .subgraph_version(name.as_str(), true)?
+            .map(|site| site.deployment))
+    }
+
+    fn graft_incompatibilities(
+        &self,
+        base: &DeploymentHash,
+        schema: &Schema,
+    ) -> Result<Vec<String>, StoreError> {
+        let (store, site) = self.store(base)?;
+        store.graft_incompatibilities(site, schema)
+    }
+
+    fn earliest_block(&self, id: &DeploymentHash) -> Result<BlockNumber, StoreError> {
+        let (store, site) = self.store(id)?;
+        store.earliest_block(&site)
+    }
+
+    fn upgrade_schema(
+        &self,
+        id: &DeploymentHash,
+        schema: &Schema,
+    ) -> Result<Vec<String>, StoreError> {
+        let (store, site) = self.store(id)?;
+        store.upgrade_schema(site, schema)
+    }
+
     fn entity_changes_in_block(
         &self,
         subgraph_id: &DeploymentHash,
@@ -1070,6 +1772,49 @@ impl SubgraphStoreTrait for SubgraphStore {
         Ok(changes)
     }
 
+    fn entity_changes_in_block_range(
+        &self,
+        subgraph_id: &DeploymentHash,
+        entity_types: &[EntityType],
+        from: BlockNumber,
+        to: BlockNumber,
+    ) -> Result<BTreeMap<BlockNumber, Vec<EntityOperation>>, StoreError> {
+        let (store, site) = self.store(subgraph_id)?;
+        store.get_changes_in_range(site, entity_types, from, to)
+    }
+
+    fn deployment_network(&self, id: &DeploymentHash) -> Result<String, StoreError> {
+        let site = self.site(id)?;
+        Ok(site.network.clone())
+    }
+
+    fn mapping_logs(
+        &self,
+        subgraph_id: &DeploymentHash,
+        from_block: BlockNumber,
+        level: Level,
+        first: usize,
+    ) -> Result<Vec<MappingLog>, StoreError> {
+        let (store, site) = self.store(subgraph_id)?;
+        store.mapping_logs(site, from_block, level, first)
+    }
+
+    fn skipped_blocks(
+        &self,
+        subgraph_id: &DeploymentHash,
+    ) -> Result<Vec<SkippedBlock>, StoreError> {
+        let (store, site) = self.store(subgraph_id)?;
+        store.skipped_blocks(site)
+    }
+
+    fn quarantined_data_sources(
+        &self,
+        subgraph_id: &DeploymentHash,
+    ) -> Result<Vec<QuarantinedDataSource>, StoreError> {
+        let (store, site) = self.store(subgraph_id)?;
+        store.quarantined_data_sources(site)
+    }
+
     fn input_schema(&self, id: &DeploymentHash) -> Result<Arc<Schema>, StoreError> {
         let (store, site) = self.store(id)?;
         let info = store.subgraph_info(&site)?;
@@ -1112,8 +1857,22 @@ impl SubgraphStoreTrait for SubgraphStore {
         // We cache writables to make sure calls to this method are
         // idempotent and there is ever only one `WritableStore` for any
         // deployment
-        if let Some(writable) = self.writables.lock().unwrap().get(&deployment) {
-            return Ok(writable.cheap_clone());
+        let stopped = {
+            let mut writables = self.writables.lock().unwrap();
+            match writables.get(&deployment) {
+                Some(writable) if !writable.is_stopped() => return Ok(writable.cheap_clone()),
+                Some(_) => writables.remove(&deployment),
+                None => None,
+            }
+        };
+        // The deployment was stopped; the new `WritableStore` must only
+        // start once the old one has written everything that was queued.
+        // If that failed, indexing resumes from the last block written
+        if let Some(stopped) = stopped {
+            if let Err(e) = stopped.flush().await {
+                warn!(logger, "Writing queued blocks of the stopped deployment failed";
+                      "error" => e.to_string());
+            }
         }
 
         // Ideally the lower level functions would be asyncified.
@@ -1124,7 +1883,15 @@ impl SubgraphStoreTrait for SubgraphStore {
         .await
         .unwrap()?; // Propagate panics, there shouldn't be any.
 
-        let writable = Arc::new(WritableAgent::new(self.as_ref().clone(), logger, site).await?);
+        let writable = Arc::new(
+            WritableAgent::new(
+                self.as_ref().clone(),
+                logger,
+                site,
+                self.registry.cheap_clone(),
+            )
+            .await?,
+        );
         self.writables
             .lock()
             .unwrap()
@@ -1132,6 +1899,17 @@ impl SubgraphStoreTrait for SubgraphStore {
         Ok(writable)
     }
 
+    fn stop_subgraph(
+        &self,
+        deployment: graph::components::store::DeploymentId,
+    ) -> Result<(), StoreError> {
+        let deployment = deployment.into();
+        if let Some(writable) = self.writables.lock().unwrap().get(&deployment) {
+            writable.stop();
+        }
+        Ok(())
+    }
+
     fn is_deployed(&self, id: &DeploymentHash) -> Result<bool, StoreError> {
         match self.site(id) {
             Ok(_) => Ok(true),
@@ -1155,3 +1933,33 @@ impl SubgraphStoreTrait for SubgraphStore {
             .collect())
     }
 }
+
+/// How many blocks a copy whose head is `dst_head` is behind its source
+/// whose head is `src_head`; `0` if the copy is at or past the head of the
+/// source
+fn copy_lag(src_head: Option<BlockNumber>, dst_head: Option<BlockNumber>) -> BlockNumber {
+    match (src_head, dst_head) {
+        (Some(src_head), Some(dst_head)) => (src_head - dst_head).max(0),
+        (Some(src_head), None) => src_head + 1,
+        (None, _) => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::copy_lag;
+
+    #[test]
+    fn copies_must_catch_up_before_switching() {
+        // A copy can only be switched to when it is at or past the head of
+        // the source
+        assert_eq!(0, copy_lag(Some(100), Some(100)));
+        assert_eq!(0, copy_lag(Some(100), Some(101)));
+        assert_eq!(0, copy_lag(None, None));
+        assert_eq!(0, copy_lag(None, Some(5)));
+
+        // Copies that are behind by even a single block can not
+        assert_eq!(1, copy_lag(Some(100), Some(99)));
+        assert_eq!(101, copy_lag(Some(100), None));
+    }
+}
@@ -0,0 +1,232 @@
+//! Notify HTTP webhooks about lifecycle events of deployments, e.g., when a
+//! deployment fails or finishes syncing, so that operators do not have to
+//! derive these events from metrics.
+//!
+//! Each event is posted as a JSON object to all configured URLs from a
+//! background task. Delivery is retried a few times, but never blocks or
+//! fails the operation that caused the event.
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use graph::data::subgraph::schema::SubgraphError;
+use graph::prelude::{
+    anyhow::bail, chrono, error, info, o, reqwest, serde_json, tokio, warn, BlockPtr,
+    DeploymentHash, Error, Logger, NodeId, Serialize, ENV_VARS,
+};
+
+/// How often we try to deliver an event to a webhook before we give up
+const MAX_ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LifecycleEvent {
+    /// A failed deployment became healthy again
+    Healthy,
+    /// The deployment failed with a deterministic error
+    Failed,
+    /// The deployment caught up with the chain head for the first time
+    Synced,
+    /// The deployment was assigned to a different node
+    Reassigned,
+}
+
+impl FromStr for LifecycleEvent {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "healthy" => Ok(LifecycleEvent::Healthy),
+            "failed" => Ok(LifecycleEvent::Failed),
+            "synced" => Ok(LifecycleEvent::Synced),
+            "reassigned" => Ok(LifecycleEvent::Reassigned),
+            _ => bail!(
+                "unknown lifecycle event `{}`; valid events are healthy, failed, synced, and reassigned",
+                s
+            ),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+struct EventBlock {
+    number: i32,
+    hash: String,
+}
+
+impl From<&BlockPtr> for EventBlock {
+    fn from(ptr: &BlockPtr) -> Self {
+        EventBlock {
+            number: ptr.number,
+            hash: ptr.hash.to_string(),
+        }
+    }
+}
+
+/// The body of the request we send to webhooks
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+struct EventMessage {
+    event: LifecycleEvent,
+    deployment: String,
+    timestamp: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    block: Option<EventBlock>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    node: Option<String>,
+}
+
+impl EventMessage {
+    fn new(event: LifecycleEvent, deployment: &DeploymentHash) -> Self {
+        EventMessage {
+            event,
+            deployment: deployment.to_string(),
+            timestamp: chrono::Utc::now().to_rfc3339(),
+            block: None,
+            error: None,
+            node: None,
+        }
+    }
+}
+
+/// The webhooks that are notified of lifecycle events
+pub struct Webhooks {
+    logger: Logger,
+    client: reqwest::Client,
+    urls: Vec<String>,
+    events: Vec<LifecycleEvent>,
+}
+
+impl Webhooks {
+    /// Create the webhooks configured with `GRAPH_LIFECYCLE_WEBHOOK_URLS`,
+    /// or return `None` if no webhooks are configured
+    pub fn from_env(logger: &Logger) -> Result<Option<Arc<Self>>, Error> {
+        let urls = &ENV_VARS.store.lifecycle_webhook_urls;
+        if urls.is_empty() {
+            return Ok(None);
+        }
+        for url in urls {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                bail!("the webhook URL `{}` must be an http(s):// URL", url);
+            }
+        }
+        let events = ENV_VARS
+            .store
+            .lifecycle_webhook_events
+            .iter()
+            .map(|event| event.parse())
+            .collect::<Result<Vec<_>, _>>()?;
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+        let logger = logger.new(o!("component" => "Webhooks"));
+
+        info!(logger, "Sending lifecycle events to webhooks";
+            "urls" => urls.join(", "),
+            "events" => ENV_VARS.store.lifecycle_webhook_events.join(", "));
+
+        Ok(Some(Arc::new(Webhooks {
+            logger,
+            client,
+            urls: urls.clone(),
+            events,
+        })))
+    }
+
+    fn send(&self, message: EventMessage) {
+        if !self.events.contains(&message.event) {
+            return;
+        }
+        for url in &self.urls {
+            let logger = self.logger.new(o!("url" => url.clone()));
+            let client = self.client.clone();
+            let url = url.clone();
+            let message = message.clone();
+            graph::spawn(async move {
+                let mut attempt = 1;
+                loop {
+                    let res = client.post(&url).json(&message).send().await;
+                    let err = match res {
+                        Ok(response) if response.status().is_success() => return,
+                        Ok(response) => format!("webhook responded with {}", response.status()),
+                        Err(e) => e.to_string(),
+                    };
+                    if attempt >= MAX_ATTEMPTS {
+                        error!(logger, "Failed to notify webhook, dropping event";
+                            "event" => format!("{:?}", message.event),
+                            "deployment" => &message.deployment,
+                            "error" => err);
+                        return;
+                    }
+                    warn!(logger, "Failed to notify webhook, will retry";
+                        "attempt" => attempt,
+                        "error" => err);
+                    attempt += 1;
+                    tokio::time::sleep(RETRY_DELAY * attempt).await;
+                }
+            });
+        }
+    }
+
+    /// Notify webhooks that `deployment` became healthy again at
+    /// `block_ptr`
+    pub fn healthy(&self, deployment: &DeploymentHash, block_ptr: &BlockPtr) {
+        let mut message = EventMessage::new(LifecycleEvent::Healthy, deployment);
+        message.block = Some(block_ptr.into());
+        self.send(message);
+    }
+
+    /// Notify webhooks that a deployment failed with `error`. Only
+    /// deterministic errors are reported
+    pub fn failed(&self, error: &SubgraphError) {
+        if !error.deterministic {
+            return;
+        }
+        let mut message = EventMessage::new(LifecycleEvent::Failed, &error.subgraph_id);
+        message.block = error.block_ptr.as_ref().map(EventBlock::from);
+        message.error = Some(error.message.clone());
+        self.send(message);
+    }
+
+    /// Notify webhooks that `deployment` is synced
+    pub fn synced(&self, deployment: &DeploymentHash) {
+        self.send(EventMessage::new(LifecycleEvent::Synced, deployment));
+    }
+
+    /// Notify webhooks that `deployment` was assigned to `node`
+    pub fn reassigned(&self, deployment: &DeploymentHash, node: &NodeId) {
+        let mut message = EventMessage::new(LifecycleEvent::Reassigned, deployment);
+        message.node = Some(node.to_string());
+        self.send(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use graph::prelude::{serde_json, BlockPtr, DeploymentHash};
+
+    use super::{EventMessage, LifecycleEvent};
+
+    #[test]
+    fn event_messages() {
+        let deployment = DeploymentHash::new("QmHooks").unwrap();
+        let mut message = EventMessage::new(LifecycleEvent::Healthy, &deployment);
+        message.block = Some((&BlockPtr::from((vec![0xab; 32], 7i32))).into());
+
+        let json = serde_json::to_value(&message).unwrap();
+        assert_eq!("healthy", json["event"]);
+        assert_eq!("QmHooks", json["deployment"]);
+        assert_eq!(7, json["block"]["number"]);
+        assert!(json.get("error").is_none());
+        assert!(json.get("node").is_none());
+
+        for event in ["healthy", "failed", "synced", "reassigned"] {
+            let parsed: LifecycleEvent = event.parse().unwrap();
+            assert_eq!(event, serde_json::to_value(parsed).unwrap());
+        }
+        assert!("unhealthy".parse::<LifecycleEvent>().is_err());
+    }
+}
@@ -1,23 +1,27 @@
-use std::sync::Mutex;
-use std::time::Duration;
-use std::{collections::BTreeMap, sync::Arc};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
 
 use graph::data::subgraph::schema;
 use graph::prelude::{Entity, Schema, SubgraphStore as _};
 use graph::{
     cheap_clone::CheapClone,
     components::store::{self, EntityType, WritableStore as WritableStoreTrait},
-    data::subgraph::schema::SubgraphError,
+    data::subgraph::schema::{MappingLog, QuarantinedDataSource, SubgraphError},
     prelude::{
-        BlockPtr, DeploymentHash, EntityKey, EntityModification, Error, Logger, StopwatchMetrics,
-        StoreError, StoreEvent, UnfailOutcome, ENV_VARS,
+        anyhow, BlockPtr, DeploymentHash, EntityKey, EntityModification, Error, Logger,
+        MetricsRegistry, StopwatchMetrics, StoreError, StoreEvent, UnfailOutcome, ENV_VARS,
     },
+    prometheus::{Counter, Gauge},
     slog::{error, warn},
     util::backoff::ExponentialBackoff,
 };
 use store::StoredDynamicDataSource;
 
+use crate::change_feed::ChangeFeed;
 use crate::deployment_store::DeploymentStore;
+use crate::webhooks::Webhooks;
 use crate::{primary, primary::Site, relational::Layout, SubgraphStore};
 
 /// A wrapper around `SubgraphStore` that only exposes functions that are
@@ -39,6 +43,14 @@ impl WritableSubgraphStore {
     fn layout(&self, id: &DeploymentHash) -> Result<Arc<Layout>, StoreError> {
         self.0.layout(id)
     }
+
+    fn change_feed(&self) -> Option<&Arc<ChangeFeed>> {
+        self.0.change_feed()
+    }
+
+    fn webhooks(&self) -> Option<&Arc<Webhooks>> {
+        self.0.webhooks()
+    }
 }
 
 pub(crate) struct WritableStore {
@@ -153,7 +165,19 @@ impl WritableStore {
             let graft_base = match store.graft_pending(&self.site.deployment)? {
                 Some((base_id, base_ptr)) => {
                     let src = self.store.layout(&base_id)?;
-                    Some((src, base_ptr))
+                    let earliest_block = self
+                        .store
+                        .for_site(&src.site)?
+                        .earliest_block(&src.site)?;
+                    if base_ptr.number < earliest_block {
+                        return Err(StoreError::Unknown(anyhow!(
+                            "can not graft onto `{}` at block {} since its history before block {} has been pruned",
+                            base_id,
+                            base_ptr.number,
+                            earliest_block
+                        )));
+                    }
+                    Some((src, base_ptr, earliest_block))
                 }
                 None => None,
             };
@@ -175,7 +199,12 @@ impl WritableStore {
             )?;
 
             self.try_send_store_event(event)
-        })
+        })?;
+
+        if let Some(feed) = self.store.change_feed() {
+            feed.reverted(&self.site.deployment, &block_ptr_to);
+        }
+        Ok(())
     }
 
     fn unfail_deterministic_error(
@@ -183,20 +212,30 @@ impl WritableStore {
         current_ptr: &BlockPtr,
         parent_ptr: &BlockPtr,
     ) -> Result<UnfailOutcome, StoreError> {
-        self.retry("unfail_deterministic_error", || {
+        let outcome = self.retry("unfail_deterministic_error", || {
             self.writable
                 .unfail_deterministic_error(self.site.clone(), current_ptr, parent_ptr)
-        })
+        })?;
+
+        if let (UnfailOutcome::Unfailed, Some(webhooks)) = (&outcome, self.store.webhooks()) {
+            webhooks.healthy(&self.site.deployment, parent_ptr);
+        }
+        Ok(outcome)
     }
 
     fn unfail_non_deterministic_error(
         &self,
         current_ptr: &BlockPtr,
     ) -> Result<UnfailOutcome, StoreError> {
-        self.retry("unfail_non_deterministic_error", || {
+        let outcome = self.retry("unfail_non_deterministic_error", || {
             self.writable
                 .unfail_non_deterministic_error(self.site.clone(), current_ptr)
-        })
+        })?;
+
+        if let (UnfailOutcome::Unfailed, Some(webhooks)) = (&outcome, self.store.webhooks()) {
+            webhooks.healthy(&self.site.deployment, current_ptr);
+        }
+        Ok(outcome)
     }
 
     async fn fail_subgraph(&self, error: SubgraphError) -> Result<(), StoreError> {
@@ -209,7 +248,12 @@ impl WritableStore {
                     .await
             }
         })
-        .await
+        .await?;
+
+        if let Some(webhooks) = self.store.webhooks() {
+            webhooks.failed(&error);
+        }
+        Ok(())
     }
 
     async fn supports_proof_of_indexing(&self) -> Result<bool, StoreError> {
@@ -233,6 +277,9 @@ impl WritableStore {
         stopwatch: &StopwatchMetrics,
         data_sources: &[StoredDynamicDataSource],
         deterministic_errors: &[SubgraphError],
+        mapping_logs: &[MappingLog],
+        quarantined: &[QuarantinedDataSource],
+        bulk: bool,
     ) -> Result<(), StoreError> {
         assert!(
             same_subgraph(mods, &self.site.deployment),
@@ -247,12 +294,20 @@ impl WritableStore {
                 stopwatch,
                 data_sources,
                 deterministic_errors,
+                mapping_logs,
+                quarantined,
+                bulk,
             )?;
 
             let _section = stopwatch.start_section("send_store_event");
             self.try_send_store_event(event)?;
             Ok(())
-        })
+        })?;
+
+        if let Some(feed) = self.store.change_feed() {
+            feed.entities_changed(block_ptr_to, mods);
+        }
+        Ok(())
     }
 
     fn get_many(
@@ -294,20 +349,48 @@ impl WritableStore {
     }
 
     fn deployment_synced(&self) -> Result<(), StoreError> {
-        self.retry("deployment_synced", || {
+        let newly_synced = self.retry("deployment_synced", || {
             let event = {
                 // Make sure we drop `pconn` before we call into the deployment
                 // store so that we do not hold two database connections which
                 // might come from the same pool and could therefore deadlock
                 let pconn = self.store.primary_conn()?;
                 pconn.transaction(|| -> Result<_, Error> {
-                    let changes = pconn.promote_deployment(&self.site.deployment)?;
+                    // Canaries are promoted once they are healthy, not
+                    // when they are synced
+                    let changes = if ENV_VARS.subgraph_version_switching_mode.is_canary() {
+                        vec![]
+                    } else {
+                        pconn.promote_deployment(&self.site.deployment, None)?
+                    };
                     Ok(StoreEvent::new(changes))
                 })?
             };
 
-            self.writable.deployment_synced(&self.site.deployment)?;
+            let newly_synced = self.writable.deployment_synced(&self.site.deployment)?;
+
+            self.store.send_store_event(&event)?;
+            Ok(newly_synced)
+        })?;
 
+        if let (true, Some(webhooks)) = (newly_synced, self.store.webhooks()) {
+            webhooks.synced(&self.site.deployment);
+        }
+        Ok(())
+    }
+
+    fn promote_canary(&self) -> Result<(), StoreError> {
+        self.retry("promote_canary", || {
+            let event = {
+                let pconn = self.store.primary_conn()?;
+                pconn.transaction(|| -> Result<_, Error> {
+                    let changes = pconn.promote_deployment(
+                        &self.site.deployment,
+                        Some(ENV_VARS.canary_retire_after),
+                    )?;
+                    Ok(StoreEvent::new(changes))
+                })?
+            };
             self.store.send_store_event(&event)
         })
     }
@@ -332,11 +415,353 @@ fn same_subgraph(mods: &[EntityModification], id: &DeploymentHash) -> bool {
     mods.iter().all(|md| &md.entity_key().subgraph_id == id)
 }
 
-#[allow(dead_code)]
+/// The changes from processing one block that still need to be written to
+/// the store
+struct Request {
+    block_ptr: BlockPtr,
+    firehose_cursor: Option<String>,
+    mods: Vec<EntityModification>,
+    stopwatch: StopwatchMetrics,
+    data_sources: Vec<StoredDynamicDataSource>,
+    deterministic_errors: Vec<SubgraphError>,
+    mapping_logs: Vec<MappingLog>,
+    quarantined: Vec<QuarantinedDataSource>,
+    bulk: bool,
+}
+
+impl Request {
+    fn execute(&self, store: &WritableStore) -> Result<(), StoreError> {
+        store.transact_block_operations(
+            &self.block_ptr,
+            self.firehose_cursor.as_deref(),
+            &self.mods,
+            &self.stopwatch,
+            &self.data_sources,
+            &self.deterministic_errors,
+            &self.mapping_logs,
+            &self.quarantined,
+            self.bulk,
+        )
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    /// The blocks that still need to be written, oldest first. The block
+    /// that is currently being written stays at the front of the queue
+    /// until it has been committed
+    requests: VecDeque<Arc<Request>>,
+    /// The error from a failed write that has not been reported yet
+    error: Option<StoreError>,
+    /// Set when a write failed. Nothing is written after that until the
+    /// deployment is started again
+    poisoned: bool,
+    /// Set when the deployment is stopped. The background thread exits
+    /// once it has written the blocks that are still in the queue
+    stop: bool,
+}
+
+impl QueueState {
+    fn check_error(&mut self) -> Result<(), StoreError> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        if self.poisoned {
+            return Err(StoreError::Unknown(anyhow!(
+                "writing an earlier block failed; the deployment needs to be restarted"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A queue of blocks that a background thread writes to the store in the
+/// order in which they were processed. This lets indexing continue with
+/// the next block while earlier blocks are still being written. Reads
+/// through the queue see the changes from blocks that have not been
+/// written yet.
+///
+/// Methods that wait for the queue block the calling thread and must
+/// therefore only be called through `WritableAgent::blocking`
+struct Queue {
+    store: Arc<WritableStore>,
+    capacity: usize,
+    state: Mutex<QueueState>,
+    changed: Condvar,
+    /// The number of blocks waiting to be written
+    queue_size: Box<Gauge>,
+    /// The time spent waiting for room in the queue
+    queue_wait: Box<Counter>,
+}
+
+impl Queue {
+    fn start(
+        store: Arc<WritableStore>,
+        capacity: usize,
+        registry: Arc<dyn MetricsRegistry>,
+    ) -> Result<Arc<Self>, StoreError> {
+        let deployment = store.site.deployment.as_str();
+        let queue_size = registry
+            .new_deployment_gauge(
+                "deployment_write_queue_size",
+                "The number of processed blocks that are waiting to be written",
+                deployment,
+            )
+            .map_err(Error::from)?;
+        let queue_wait = registry
+            .new_deployment_counter(
+                "deployment_write_queue_wait_secs",
+                "The time spent waiting for room in the write queue",
+                deployment,
+            )
+            .map_err(Error::from)?;
+
+        let queue = Arc::new(Queue {
+            store,
+            capacity: capacity.max(1),
+            state: Mutex::new(QueueState::default()),
+            changed: Condvar::new(),
+            queue_size,
+            queue_wait,
+        });
+
+        let writer = queue.cheap_clone();
+        std::thread::Builder::new()
+            .name(format!("writer-{}", writer.store.site.namespace))
+            .spawn(move || writer.run())
+            .map_err(Error::from)?;
+        Ok(queue)
+    }
+
+    /// The loop of the background thread that writes blocks
+    fn run(&self) {
+        loop {
+            let req = {
+                let mut state = self.state.lock().unwrap();
+                loop {
+                    if state.stop && (state.requests.is_empty() || state.poisoned) {
+                        return;
+                    }
+                    if !state.poisoned {
+                        if let Some(req) = state.requests.front() {
+                            break req.cheap_clone();
+                        }
+                    }
+                    state = self.changed.wait(state).unwrap();
+                }
+            };
+
+            let res = req.execute(&self.store);
+
+            let mut state = self.state.lock().unwrap();
+            match res {
+                Ok(()) => {
+                    state.requests.pop_front();
+                }
+                Err(e) => {
+                    error!(self.store.logger, "Writing block failed";
+                                              "block" => req.block_ptr.to_string(),
+                                              "error" => e.to_string());
+                    state.error = Some(e);
+                    state.poisoned = true;
+                }
+            }
+            self.queue_size.set(state.requests.len() as f64);
+            self.changed.notify_all();
+        }
+    }
+
+    /// Add a block to the queue, waiting for room if the queue is full
+    fn push(&self, req: Request) -> Result<(), StoreError> {
+        let start = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        while state.requests.len() >= self.capacity && !state.poisoned {
+            state = self.changed.wait(state).unwrap();
+        }
+        self.queue_wait.inc_by(start.elapsed().as_secs_f64());
+        state.check_error()?;
+        if state.stop {
+            return Err(StoreError::Unknown(anyhow!(
+                "the writer for deployment {} has been stopped",
+                self.store.site.deployment
+            )));
+        }
+
+        state.requests.push_back(Arc::new(req));
+        self.queue_size.set(state.requests.len() as f64);
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    /// Wait until all blocks in the queue have been written
+    fn flush(&self) -> Result<(), StoreError> {
+        let mut state = self.state.lock().unwrap();
+        while !state.requests.is_empty() && !state.poisoned {
+            state = self.changed.wait(state).unwrap();
+        }
+        state.check_error()
+    }
+
+    /// Prepare the queue for the deployment being started again. Blocks
+    /// that are still waiting are written unless writing failed earlier, in
+    /// which case they are dropped since indexing resumes from the last
+    /// block that was written
+    fn restart(&self) {
+        let mut state = self.state.lock().unwrap();
+        while !state.requests.is_empty() && !state.poisoned {
+            state = self.changed.wait(state).unwrap();
+        }
+        state.requests.clear();
+        state.error = None;
+        state.poisoned = false;
+        self.queue_size.set(0.0);
+        self.changed.notify_all();
+    }
+
+    /// Tell the background thread to exit once it has written the blocks
+    /// that are still in the queue
+    fn stop(&self) {
+        self.state.lock().unwrap().stop = true;
+        self.changed.notify_all();
+    }
+
+    /// Look up the entity `key` in the blocks that have not been written
+    /// yet. Return `None` if none of them changed the entity, and
+    /// `Some(None)` if the latest change removed it
+    fn get(&self, key: &EntityKey) -> Option<Option<Entity>> {
+        let state = self.state.lock().unwrap();
+        state.requests.iter().rev().find_map(|req| {
+            req.mods
+                .iter()
+                .find(|md| md.entity_key() == key)
+                .map(|md| match md {
+                    EntityModification::Insert { data, .. }
+                    | EntityModification::Overwrite { data, .. } => Some(data.clone()),
+                    EntityModification::Remove { .. } => None,
+                })
+        })
+    }
+
+    /// The latest version of the entities in `ids_for_type` that were
+    /// changed by blocks that have not been written yet. Entities that were
+    /// removed map to `None`
+    fn get_many(
+        &self,
+        ids_for_type: &BTreeMap<&EntityType, Vec<&str>>,
+    ) -> HashMap<(EntityType, String), Option<Entity>> {
+        let wanted: HashSet<(&EntityType, &str)> = ids_for_type
+            .iter()
+            .flat_map(|(entity_type, ids)| ids.iter().map(move |id| (*entity_type, *id)))
+            .collect();
+
+        let state = self.state.lock().unwrap();
+        let mut changes = HashMap::new();
+        for req in &state.requests {
+            for md in &req.mods {
+                let key = md.entity_key();
+                if !wanted.contains(&(&key.entity_type, key.entity_id.as_str())) {
+                    continue;
+                }
+                let data = match md {
+                    EntityModification::Insert { data, .. }
+                    | EntityModification::Overwrite { data, .. } => Some(data.clone()),
+                    EntityModification::Remove { .. } => None,
+                };
+                changes.insert((key.entity_type.clone(), key.entity_id.clone()), data);
+            }
+        }
+        changes
+    }
+}
+
+/// How the `WritableAgent` writes blocks to the store
+enum Writer {
+    /// Write each block before `transact_block_operations` returns
+    Sync(Arc<WritableStore>),
+    /// Write blocks in the background
+    Async(Arc<Queue>),
+}
+
+impl Writer {
+    fn new(
+        store: Arc<WritableStore>,
+        registry: Arc<dyn MetricsRegistry>,
+    ) -> Result<Self, StoreError> {
+        match ENV_VARS.store.write_queue_size {
+            0 => Ok(Writer::Sync(store)),
+            capacity => Queue::start(store, capacity, registry).map(Writer::Async),
+        }
+    }
+
+    fn write(&self, req: Request) -> Result<(), StoreError> {
+        match self {
+            Writer::Sync(store) => req.execute(store),
+            Writer::Async(queue) => queue.push(req),
+        }
+    }
+
+    fn flush(&self) -> Result<(), StoreError> {
+        match self {
+            Writer::Sync(_) => Ok(()),
+            Writer::Async(queue) => queue.flush(),
+        }
+    }
+
+    fn restart(&self) {
+        match self {
+            Writer::Sync(_) => { /* nothing to do */ }
+            Writer::Async(queue) => queue.restart(),
+        }
+    }
+
+    fn get(&self, key: &EntityKey) -> Result<Option<Entity>, StoreError> {
+        match self {
+            Writer::Sync(store) => store.get(key),
+            Writer::Async(queue) => match queue.get(key) {
+                Some(entity) => Ok(entity),
+                None => queue.store.get(key),
+            },
+        }
+    }
+
+    fn get_many(
+        &self,
+        ids_for_type: BTreeMap<&EntityType, Vec<&str>>,
+    ) -> Result<BTreeMap<EntityType, Vec<Entity>>, StoreError> {
+        let queue = match self {
+            Writer::Sync(store) => return store.get_many(ids_for_type),
+            Writer::Async(queue) => queue,
+        };
+
+        let changes = queue.get_many(&ids_for_type);
+        let unchanged: BTreeMap<&EntityType, Vec<&str>> = ids_for_type
+            .into_iter()
+            .map(|(entity_type, ids)| {
+                let ids: Vec<_> = ids
+                    .into_iter()
+                    .filter(|id| !changes.contains_key(&(entity_type.clone(), id.to_string())))
+                    .collect();
+                (entity_type, ids)
+            })
+            .filter(|(_, ids)| !ids.is_empty())
+            .collect();
+
+        let mut entities = queue.store.get_many(unchanged)?;
+        for ((entity_type, _), entity) in changes {
+            if let Some(entity) = entity {
+                entities.entry(entity_type).or_default().push(entity);
+            }
+        }
+        Ok(entities)
+    }
+}
+
 pub struct WritableAgent {
     store: Arc<WritableStore>,
+    writer: Arc<Writer>,
     block_ptr: Mutex<Option<BlockPtr>>,
     block_cursor: Mutex<Option<String>>,
+    stopped: AtomicBool,
 }
 
 impl WritableAgent {
@@ -344,16 +769,55 @@ impl WritableAgent {
         subgraph_store: SubgraphStore,
         logger: Logger,
         site: Arc<Site>,
+        registry: Arc<dyn MetricsRegistry>,
     ) -> Result<Self, StoreError> {
         let store = Arc::new(WritableStore::new(subgraph_store, logger, site)?);
+        let writer = Arc::new(Writer::new(store.cheap_clone(), registry)?);
         let block_ptr = Mutex::new(store.block_ptr().await?);
         let block_cursor = Mutex::new(store.block_cursor().await?);
         Ok(Self {
             store,
+            writer,
             block_ptr,
             block_cursor,
+            stopped: AtomicBool::new(false),
         })
     }
+
+    /// Run `f` on a blocking thread since writing can wait for room in the
+    /// write queue, for the queue to drain, or for the database
+    async fn blocking<T, F>(&self, f: F) -> Result<T, StoreError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&WritableStore, &Writer) -> Result<T, StoreError> + Send + 'static,
+    {
+        let store = self.store.cheap_clone();
+        let writer = self.writer.cheap_clone();
+        graph::spawn_blocking_allow_panic(move || f(&store, &writer))
+            .await
+            .map_err(Error::from)?
+    }
+
+    /// Stop the background writer once it has written the blocks that
+    /// are still queued. Called when the deployment is stopped
+    pub(crate) fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Writer::Async(queue) = self.writer.as_ref() {
+            queue.stop();
+        }
+    }
+
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for WritableAgent {
+    fn drop(&mut self) {
+        if let Writer::Async(queue) = self.writer.as_ref() {
+            queue.stop();
+        }
+    }
 }
 
 #[allow(unused_variables)]
@@ -374,11 +838,12 @@ impl WritableStoreTrait for WritableAgent {
     }
 
     async fn start_subgraph_deployment(&self, logger: &Logger) -> Result<(), StoreError> {
-        let store = self.store.cheap_clone();
         let logger = logger.cheap_clone();
-        graph::spawn_blocking_allow_panic(move || store.start_subgraph_deployment(&logger))
-            .await
-            .map_err(Error::from)??;
+        self.blocking(move |store, writer| {
+            writer.restart();
+            store.start_subgraph_deployment(&logger)
+        })
+        .await?;
 
         // Refresh all in memory state in case this instance was used before
         *self.block_ptr.lock().unwrap() = self.store.block_ptr().await?;
@@ -387,26 +852,35 @@ impl WritableStoreTrait for WritableAgent {
         Ok(())
     }
 
-    fn revert_block_operations(
+    async fn revert_block_operations(
         &self,
         block_ptr_to: BlockPtr,
         firehose_cursor: Option<&str>,
     ) -> Result<(), StoreError> {
+        let firehose_cursor = firehose_cursor.map(str::to_string);
         *self.block_ptr.lock().unwrap() = Some(block_ptr_to.clone());
-        // TODO: If we haven't written the block yet, revert in memory. If
-        // we have, revert in the database
-        self.store
-            .revert_block_operations(block_ptr_to, firehose_cursor)
+        *self.block_cursor.lock().unwrap() = firehose_cursor.clone();
+        self.blocking(move |store, writer| {
+            writer.flush()?;
+            store.revert_block_operations(block_ptr_to, firehose_cursor.as_deref())
+        })
+        .await
     }
 
-    fn unfail_deterministic_error(
+    async fn unfail_deterministic_error(
         &self,
         current_ptr: &BlockPtr,
         parent_ptr: &BlockPtr,
     ) -> Result<UnfailOutcome, StoreError> {
-        let outcome = self
-            .store
-            .unfail_deterministic_error(current_ptr, parent_ptr)?;
+        let outcome = {
+            let current_ptr = current_ptr.clone();
+            let parent_ptr = parent_ptr.clone();
+            self.blocking(move |store, writer| {
+                writer.flush()?;
+                store.unfail_deterministic_error(&current_ptr, &parent_ptr)
+            })
+            .await?
+        };
 
         if let UnfailOutcome::Unfailed = outcome {
             *self.block_ptr.lock().unwrap() = Some(parent_ptr.clone());
@@ -415,17 +889,23 @@ impl WritableStoreTrait for WritableAgent {
         Ok(outcome)
     }
 
-    fn unfail_non_deterministic_error(
+    async fn unfail_non_deterministic_error(
         &self,
         current_ptr: &BlockPtr,
     ) -> Result<UnfailOutcome, StoreError> {
         // We don't have to update in memory self.block_ptr
         // because the method call below doesn't rewind/revert
         // any block.
-        self.store.unfail_non_deterministic_error(current_ptr)
+        let current_ptr = current_ptr.clone();
+        self.blocking(move |store, writer| {
+            writer.flush()?;
+            store.unfail_non_deterministic_error(&current_ptr)
+        })
+        .await
     }
 
     async fn fail_subgraph(&self, error: SubgraphError) -> Result<(), StoreError> {
+        self.flush().await?;
         self.store.fail_subgraph(error).await
     }
 
@@ -434,10 +914,10 @@ impl WritableStoreTrait for WritableAgent {
     }
 
     fn get(&self, key: &EntityKey) -> Result<Option<Entity>, StoreError> {
-        self.store.get(key)
+        self.writer.get(key)
     }
 
-    fn transact_block_operations(
+    async fn transact_block_operations(
         &self,
         block_ptr_to: BlockPtr,
         firehose_cursor: Option<String>,
@@ -445,15 +925,22 @@ impl WritableStoreTrait for WritableAgent {
         stopwatch: &StopwatchMetrics,
         data_sources: Vec<StoredDynamicDataSource>,
         deterministic_errors: Vec<SubgraphError>,
+        mapping_logs: Vec<MappingLog>,
+        quarantined: Vec<QuarantinedDataSource>,
+        bulk: bool,
     ) -> Result<(), StoreError> {
-        self.store.transact_block_operations(
-            &block_ptr_to,
-            firehose_cursor.as_deref(),
-            &mods,
-            &stopwatch,
-            &data_sources,
-            &deterministic_errors,
-        )?;
+        let req = Request {
+            block_ptr: block_ptr_to.clone(),
+            firehose_cursor: firehose_cursor.clone(),
+            mods,
+            stopwatch: stopwatch.clone(),
+            data_sources,
+            deterministic_errors,
+            mapping_logs,
+            quarantined,
+            bulk,
+        };
+        self.blocking(move |_, writer| writer.write(req)).await?;
 
         *self.block_ptr.lock().unwrap() = Some(block_ptr_to);
         *self.block_cursor.lock().unwrap() = firehose_cursor;
@@ -465,23 +952,39 @@ impl WritableStoreTrait for WritableAgent {
         &self,
         ids_for_type: BTreeMap<&EntityType, Vec<&str>>,
     ) -> Result<BTreeMap<EntityType, Vec<Entity>>, StoreError> {
-        self.store.get_many(ids_for_type)
+        self.writer.get_many(ids_for_type)
     }
 
-    fn deployment_synced(&self) -> Result<(), StoreError> {
-        self.store.deployment_synced()
+    async fn deployment_synced(&self) -> Result<(), StoreError> {
+        self.blocking(|store, writer| {
+            writer.flush()?;
+            store.deployment_synced()
+        })
+        .await
+    }
+
+    async fn promote_canary(&self) -> Result<(), StoreError> {
+        self.blocking(|store, writer| {
+            writer.flush()?;
+            store.promote_canary()
+        })
+        .await
     }
 
     async fn is_deployment_synced(&self) -> Result<bool, StoreError> {
         self.store.is_deployment_synced().await
     }
 
-    fn unassign_subgraph(&self) -> Result<(), StoreError> {
-        self.store.unassign_subgraph()
+    async fn unassign_subgraph(&self) -> Result<(), StoreError> {
+        self.blocking(|store, writer| {
+            writer.flush()?;
+            store.unassign_subgraph()
+        })
+        .await
     }
 
     async fn load_dynamic_data_sources(&self) -> Result<Vec<StoredDynamicDataSource>, StoreError> {
-        // TODO: Combine in-memory and stored data sources
+        self.flush().await?;
         self.store.load_dynamic_data_sources().await
     }
 
@@ -496,4 +999,8 @@ impl WritableStoreTrait for WritableAgent {
     fn input_schema(&self) -> Arc<Schema> {
         self.store.input_schema()
     }
+
+    async fn flush(&self) -> Result<(), StoreError> {
+        self.blocking(|_, writer| writer.flush()).await
+    }
 }
@@ -199,6 +199,29 @@ fn block_number() {
     })
 }
 
+#[test]
+fn block_number_by_timestamp() {
+    // Block three is missing from the cache
+    let chain = vec![&*GENESIS_BLOCK, &*BLOCK_ONE, &*BLOCK_TWO, &*BLOCK_FOUR];
+
+    run_test(chain, |store, _| {
+        let number = |timestamp| {
+            store
+                .block_number_by_timestamp(timestamp)
+                .map(|block| block.map(|(_, number)| number))
+        };
+
+        assert_eq!(Some(0), number(0)?);
+        assert_eq!(Some(1), number(5)?);
+        assert_eq!(Some(2), number(20)?);
+        // Block four has the first timestamp at or after 25 that we know
+        // of, but we can not tell whether block three does, too
+        assert!(number(25).is_err());
+        assert_eq!(None, number(41)?);
+        Ok(())
+    });
+}
+
 #[test]
 fn block_hashes_by_number() {
     let chain = vec![
@@ -369,6 +392,47 @@ fn eth_call_cache() {
     })
 }
 
+#[test]
+fn prune_eth_call_cache() {
+    let chain = vec![&*GENESIS_BLOCK, &*BLOCK_ONE, &*BLOCK_TWO];
+
+    run_test(chain, |store, _| {
+        if !store.call_cache_is_prunable() {
+            assert!(store.prune_call_cache(2).is_err());
+            return Ok(());
+        }
+
+        let address = H160([1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        let call: [u8; 6] = [1, 2, 3, 4, 5, 6];
+        let return_value: [u8; 3] = [7, 8, 9];
+
+        for block in [&*BLOCK_ONE, &*BLOCK_TWO] {
+            store
+                .set_call(address, &call, block.block_ptr(), &return_value)
+                .unwrap();
+        }
+
+        // Nothing needs to be removed to keep two calls
+        assert_eq!(None, store.call_cache_cutoff(None, Some(2)).unwrap());
+        // Keeping one call means removing the call made at block 1
+        let before = store.call_cache_cutoff(None, Some(1)).unwrap();
+        assert_eq!(Some(2), before);
+
+        assert_eq!(1, store.prune_call_cache(2).unwrap());
+        assert!(store
+            .get_call(address, &call, BLOCK_ONE.block_ptr())
+            .unwrap()
+            .is_none());
+        assert!(store
+            .get_call(address, &call, BLOCK_TWO.block_ptr())
+            .unwrap()
+            .is_some());
+        assert_eq!(0, store.prune_call_cache(2).unwrap());
+
+        Ok(())
+    })
+}
+
 #[test]
 /// Tests only query correctness. No data is involved.
 fn test_transaction_receipts_in_block_function() {
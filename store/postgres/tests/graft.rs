@@ -324,12 +324,14 @@ async fn check_graft(
         .writable(LOGGER.clone(), deployment.id)
         .await?
         .revert_block_operations(BLOCKS[1].clone(), None)
+        .await
         .expect("We can revert a block we just created");
 
     let err = store
         .writable(LOGGER.clone(), deployment.id)
         .await?
         .revert_block_operations(BLOCKS[0].clone(), None)
+        .await
         .expect_err("Reverting past graft point is not allowed");
 
     assert!(err.to_string().contains("Can not revert subgraph"));
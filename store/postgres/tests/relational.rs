@@ -5,8 +5,8 @@ use graph::entity;
 use graph::prelude::BlockNumber;
 use graph::prelude::{
     o, slog, tokio, web3::types::H256, DeploymentHash, Entity, EntityCollection, EntityFilter,
-    EntityKey, EntityOrder, EntityQuery, EntityRange, Logger, Schema, StopwatchMetrics, Value,
-    ValueType, BLOCK_NUMBER_MAX,
+    EntityKey, EntityModification, EntityOrder, EntityQuery, EntityRange, Logger, Schema,
+    StopwatchMetrics, StoreError, Value, ValueType, BLOCK_NUMBER_MAX,
 };
 use graph_mock::MockMetricsRegistry;
 use graph_store_postgres::layout_for_tests::set_account_like;
@@ -15,6 +15,7 @@ use graph_store_postgres::layout_for_tests::SqlName;
 use hex_literal::hex;
 use lazy_static::lazy_static;
 use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::panic;
 use std::str::FromStr;
 use std::sync::Arc;
@@ -22,12 +23,12 @@ use std::thread::sleep;
 use std::time::Duration;
 
 use graph::{
-    components::store::{AttributeNames, EntityType},
+    components::store::{AttributeNames, EntityType, NoopPruneReporter},
     data::store::scalar::{BigDecimal, BigInt, Bytes},
 };
 use graph_store_postgres::{
     layout_for_tests::make_dummy_site,
-    layout_for_tests::{Layout, Namespace, STRING_PREFIX_SIZE},
+    layout_for_tests::{Catalog, Layout, Namespace, STRING_PREFIX_SIZE},
 };
 
 use test_store::*;
@@ -128,6 +129,22 @@ const THINGS_GQL: &str = r#"
         description: String,
         test: String
     }
+
+    type Trade @entity(timeseries: true) {
+        id: ID!,
+        timestamp: Int!,
+        token: String!,
+        amount: BigDecimal!
+    }
+
+    type TradeStats @aggregation(intervals: ["hour", "day"], source: "Trade") {
+        id: ID!,
+        interval: String!,
+        timestamp: Int!,
+        token: String!,
+        count: Int! @aggregate(fn: "count"),
+        volume: BigDecimal! @aggregate(fn: "sum", arg: "amount")
+    }
 "#;
 
 lazy_static! {
@@ -221,6 +238,7 @@ fn insert_entity_at(
             &mut entities_with_keys,
             block,
             &MOCK_STOPWATCH,
+            None,
         )
         .expect(&errmsg);
     assert_eq!(inserted, entities_with_keys_owned.len());
@@ -410,6 +428,26 @@ fn create_schema(conn: &PgConnection) -> Layout {
         .expect("Failed to create relational schema")
 }
 
+/// Like `create_schema`, but partition the tables for immutable entities
+/// into partitions of `size` blocks
+fn create_partitioned_schema(conn: &PgConnection, size: BlockNumber) -> Layout {
+    let schema = Schema::parse(THINGS_GQL, THINGS_SUBGRAPH_ID.clone()).unwrap();
+    let site = Arc::new(make_dummy_site(
+        THINGS_SUBGRAPH_ID.clone(),
+        NAMESPACE.clone(),
+        NETWORK_NAME.to_string(),
+    ));
+    let query = format!("create schema {}", NAMESPACE.as_str());
+    conn.batch_execute(&*query).unwrap();
+
+    let mut catalog = Catalog::for_tests(site.clone()).unwrap();
+    catalog.partition_blocks = Some(size);
+    let layout = Layout::new(site, &schema, catalog).expect("Failed to create layout");
+    conn.batch_execute(&layout.as_ddl().unwrap())
+        .expect("Failed to create relational schema");
+    layout
+}
+
 fn scrub(entity: &Entity) -> Entity {
     let mut scrubbed = Entity::new();
     // merge_remove_null_fields has the side-effect of removing any attribute
@@ -497,6 +535,83 @@ fn find() {
     });
 }
 
+#[test]
+fn insert_with_copy() {
+    run_test(|conn, layout| {
+        let mut copy = copy_client();
+        let mut scalar = SCALAR_ENTITY.clone();
+        scalar.set("string", "tab\there\nnewline \"quoted\" back\\slash");
+        scalar.set("strings", vec!["a,b", "{c}", "\"d\"", "e\\f"]);
+        let mink = entity! { id: "m1", order: 7 };
+        let scalar_key = EntityKey::data(
+            THINGS_SUBGRAPH_ID.clone(),
+            "Scalar".to_owned(),
+            "one".to_owned(),
+        );
+        let mink_key = EntityKey::data(
+            THINGS_SUBGRAPH_ID.clone(),
+            "Mink".to_owned(),
+            "m1".to_owned(),
+        );
+
+        let mut entities = vec![(&scalar_key, Cow::from(&scalar))];
+        let count = layout
+            .insert(
+                conn,
+                &*SCALAR,
+                &mut entities,
+                3,
+                &MOCK_STOPWATCH,
+                Some(&mut *copy),
+            )
+            .expect("Failed to copy Scalar[one]");
+        assert_eq!(1, count);
+
+        let mink_type = EntityType::from("Mink");
+        let mut entities = vec![(&mink_key, Cow::from(&mink))];
+        let count = layout
+            .insert(
+                conn,
+                &mink_type,
+                &mut entities,
+                3,
+                &MOCK_STOPWATCH,
+                Some(&mut *copy),
+            )
+            .expect("Failed to copy Mink[m1]");
+        assert_eq!(1, count);
+
+        let entity = layout
+            .find(conn, &*SCALAR, "one", BLOCK_NUMBER_MAX)
+            .expect("Failed to read Scalar[one]")
+            .unwrap();
+        assert_entity_eq!(scrub(&scalar), entity);
+        assert!(layout.find(conn, &*SCALAR, "one", 2).unwrap().is_none());
+
+        let entity = layout
+            .find(conn, &mink_type, "m1", 3)
+            .expect("Failed to read Mink[m1]")
+            .unwrap();
+        assert_eq!(Some(&Value::from(7)), entity.get("order"));
+        assert!(layout.find(conn, &mink_type, "m1", 2).unwrap().is_none());
+
+        // Copied entities are committed right away; if the write of their
+        // block fails, they have to be removed again
+        let count = layout
+            .remove_uncommitted(conn, 3)
+            .expect("Failed to remove copied entities");
+        assert_eq!(2, count);
+        assert!(layout
+            .find(conn, &*SCALAR, "one", BLOCK_NUMBER_MAX)
+            .unwrap()
+            .is_none());
+        assert!(layout
+            .find(conn, &mink_type, "m1", BLOCK_NUMBER_MAX)
+            .unwrap()
+            .is_none());
+    });
+}
+
 #[test]
 fn insert_null_fulltext_fields() {
     run_test(|conn, layout| {
@@ -965,6 +1080,111 @@ fn revert_block() {
     });
 }
 
+#[test]
+fn prune() {
+    run_test(|conn, layout| {
+        let id = "fred";
+        let cat = EntityType::from("Cat");
+
+        let set_fred = |name, block| {
+            let fred = entity! {
+                id: id,
+                name: name
+            };
+            if block == 0 {
+                insert_entity_at(conn, layout, "Cat", vec![fred], block);
+            } else {
+                update_entity_at(conn, layout, "Cat", vec![fred], block);
+            }
+        };
+
+        let assert_fred = |name: &str, block| {
+            let fred = layout
+                .find(conn, &cat, id, block)
+                .unwrap()
+                .expect("there's a fred");
+            assert_eq!(name, fred.get("name").unwrap().as_str().unwrap())
+        };
+
+        let set_poi = |digest: &str, block| {
+            let poi = entity! {
+                id: "region",
+                digest: Bytes::from_str(digest).unwrap()
+            };
+            if block == 0 {
+                insert_entity_at(conn, layout, "Poi$", vec![poi], block);
+            } else {
+                update_entity_at(conn, layout, "Poi$", vec![poi], block);
+            }
+        };
+
+        set_fred("zero", 0);
+        set_fred("one", 1);
+        set_fred("two", 2);
+        set_fred("three", 3);
+        set_poi("0x00", 0);
+        set_poi("0x01", 1);
+        set_poi("0x02", 2);
+
+        // Use a batch size of 1 so that pruning needs several batches
+        let count = layout.prune(conn, 2, 1, &mut NoopPruneReporter).unwrap();
+        assert_eq!(2, count);
+        assert_fred("two", 2);
+        assert_fred("three", BLOCK_NUMBER_MAX);
+        assert!(layout.find(conn, &cat, id, 1).unwrap().is_none());
+
+        // The proof of indexing is kept for blocks before the earliest block
+        let poi = EntityType::from("Poi$");
+        for block in 0..3 {
+            assert!(layout.find(conn, &poi, "region", block).unwrap().is_some());
+        }
+
+        // Pruning again does not remove anything
+        assert_eq!(0, layout.prune(conn, 2, 1, &mut NoopPruneReporter).unwrap());
+    });
+}
+
+#[test]
+fn prune_keeps_immutable_entities() {
+    run_test_with_conn(|conn| {
+        remove_schema(conn);
+        let layout = create_partitioned_schema(conn, 10);
+        let mink = EntityType::from("Mink");
+
+        let insert_mink = |id: &str, block| {
+            let key = EntityKey::data(THINGS_SUBGRAPH_ID.clone(), "Mink".to_owned(), id.to_owned());
+            let entity = entity! { id: id, order: block };
+            let mut entities = vec![(&key, Cow::from(&entity))];
+            layout.insert(conn, &mink, &mut entities, block, &MOCK_STOPWATCH, None)
+        };
+
+        // The first two minks go into the partition for blocks 0 to 9, and
+        // the others each into their own partition
+        insert_mink("m1", 1).unwrap();
+        insert_mink("m2", 5).unwrap();
+        insert_mink("m3", 12).unwrap();
+        insert_mink("m4", 25).unwrap();
+
+        // The database can't enforce unique ids across partitions, but
+        // inserting an existing id still fails
+        let err = insert_mink("m1", 26).unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+        let err = insert_mink("m1", 2).unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+
+        // Immutable entities created before the earliest block are still
+        // current at the head, and pruning must not remove them
+        let count = layout.prune(conn, 20, 1, &mut NoopPruneReporter).unwrap();
+        assert_eq!(0, count);
+        for id in ["m1", "m2", "m3", "m4"] {
+            assert!(layout
+                .find(conn, &mink, id, BLOCK_NUMBER_MAX)
+                .unwrap()
+                .is_some());
+        }
+    });
+}
+
 struct QueryChecker<'a> {
     conn: &'a PgConnection,
     layout: &'a Layout,
@@ -1744,3 +1964,122 @@ fn check_filters() {
             .check(vec!["a2", "a2b"], filter_not_in(vec![&a1, &a3]));
     });
 }
+
+#[test]
+fn compute_rollups() {
+    fn rollups(
+        conn: &PgConnection,
+        layout: &Layout,
+        trades: Vec<Entity>,
+        block: BlockNumber,
+    ) -> Result<BTreeMap<String, Entity>, StoreError> {
+        insert_entity_at(conn, layout, "Trade", trades.clone(), block);
+
+        let mods: Vec<_> = trades
+            .into_iter()
+            .map(|data| EntityModification::Insert {
+                key: EntityKey::data(
+                    THINGS_SUBGRAPH_ID.clone(),
+                    "Trade".to_owned(),
+                    data.id().unwrap(),
+                ),
+                data,
+            })
+            .collect();
+        Ok(layout
+            .rollups(conn, &mods, block)?
+            .into_iter()
+            .map(|rollup| match rollup {
+                EntityModification::Insert { key, data } => (key.entity_id, data),
+                _ => panic!("expected only inserts but got {:?}", rollup),
+            })
+            .collect())
+    }
+
+    run_test(|conn, layout| {
+        // None of the intervals of these points are closed yet
+        let trades = vec![
+            entity! { id: "t1", timestamp: 3600, token: "a", amount: BigDecimal::from(1) },
+            entity! { id: "t2", timestamp: 3700, token: "a", amount: BigDecimal::from(2) },
+            entity! { id: "t3", timestamp: 3800, token: "b", amount: BigDecimal::from(8) },
+        ];
+        let written = rollups(conn, layout, trades, 1).expect("rollups can be computed");
+        assert!(written.is_empty(), "{:?}", written);
+
+        // Closes the hour that starts at 3600, but not the day
+        let trades =
+            vec![entity! { id: "t4", timestamp: 7300, token: "a", amount: BigDecimal::from(4) }];
+        let written = rollups(conn, layout, trades, 2).expect("rollups can be computed");
+        assert_eq!(
+            vec!["hour-3600-1:a", "hour-3600-1:b"],
+            written.keys().map(String::as_str).collect::<Vec<_>>()
+        );
+        let hour = &written["hour-3600-1:a"];
+        assert_eq!(Some(&Value::from("hour")), hour.get("interval"));
+        assert_eq!(Some(&Value::Int(3600)), hour.get("timestamp"));
+        assert_eq!(Some(&Value::Int(2)), hour.get("count"));
+        assert_eq!(Some(&Value::from(BigDecimal::from(3))), hour.get("volume"));
+        let stats = EntityType::from("TradeStats");
+        assert!(layout
+            .find(conn, &stats, "hour-3600-1:a", 1)
+            .unwrap()
+            .is_none());
+        assert_eq!(
+            Some(hour),
+            layout
+                .find(conn, &stats, "hour-3600-1:a", 2)
+                .unwrap()
+                .as_ref()
+        );
+
+        // Closes the hour that starts at 7200 and the first day
+        let trades =
+            vec![entity! { id: "t5", timestamp: 86500, token: "b", amount: BigDecimal::from(16) }];
+        let written = rollups(conn, layout, trades, 3).expect("rollups can be computed");
+        assert_eq!(
+            vec!["day-0-1:a", "day-0-1:b", "hour-7200-1:a"],
+            written.keys().map(String::as_str).collect::<Vec<_>>()
+        );
+        let day = &written["day-0-1:a"];
+        assert_eq!(Some(&Value::Int(0)), day.get("timestamp"));
+        assert_eq!(Some(&Value::Int(3)), day.get("count"));
+        assert_eq!(Some(&Value::from(BigDecimal::from(7))), day.get("volume"));
+
+        // Points must be added in timestamp order
+        let trades =
+            vec![entity! { id: "t6", timestamp: 7400, token: "a", amount: BigDecimal::from(32) }];
+        rollups(conn, layout, trades, 4).expect_err("points must be in timestamp order");
+    });
+}
+
+#[test]
+fn explain_query() {
+    run_test(|conn, layout| {
+        insert_users(conn, layout);
+
+        let query = user_query().filter(EntityFilter::Equal("name".into(), "Cindini".into()));
+        let explained = layout
+            .explain(
+                conn,
+                query.collection,
+                query.filter,
+                query.order,
+                query.range,
+                BLOCK_NUMBER_MAX,
+                None,
+            )
+            .expect("layout.explain succeeds");
+
+        assert!(explained.sql.contains("user"), "{}", explained.sql);
+        assert!(!explained.plan.is_empty());
+        // Since the query is actually run, the plan contains timings
+        assert!(
+            explained
+                .plan
+                .iter()
+                .any(|line| line.starts_with("Execution Time")),
+            "{:?}",
+            explained.plan
+        );
+    });
+}
@@ -102,6 +102,7 @@ fn insert_entity(conn: &PgConnection, layout: &Layout, entity_type: &str, entity
             entities.as_mut_slice(),
             0,
             &MOCK_STOPWATCH,
+            None,
         )
         .expect(&errmsg);
 }
@@ -1141,6 +1141,7 @@ fn mock_data_source() -> graph_chain_ethereum::DataSource {
         network: Some(String::from("mainnet")),
         source: Source {
             address: Some(Address::from_str("0123123123012312312301231231230123123123").unwrap()),
+            subgraph: None,
             abi: String::from("123123"),
             start_block: 0,
         },
@@ -1153,6 +1154,7 @@ fn mock_data_source() -> graph_chain_ethereum::DataSource {
             event_handlers: vec![],
             call_handlers: vec![],
             block_handlers: vec![],
+            entity_handlers: vec![],
             link: Link {
                 link: "link".to_owned(),
             },
@@ -1550,7 +1552,11 @@ fn handle_large_string_with_index() {
                 &stopwatch_metrics,
                 Vec::new(),
                 Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                false,
             )
+            .await
             .expect("Failed to insert large text");
 
         let query = user_query()
@@ -1644,7 +1650,11 @@ fn handle_large_bytea_with_index() {
                 &stopwatch_metrics,
                 Vec::new(),
                 Vec::new(),
+                Vec::new(),
+                Vec::new(),
+                false,
             )
+            .await
             .expect("Failed to insert large text");
 
         let query = user_query()
@@ -4,7 +4,7 @@ use graph::{
         store::{DeploymentLocator, StatusStore},
     },
     data::subgraph::schema::SubgraphHealth,
-    data::subgraph::schema::{DeploymentCreate, SubgraphError},
+    data::subgraph::schema::{DeploymentCreate, MappingLog, SubgraphError},
     prelude::EntityChange,
     prelude::EntityChangeOperation,
     prelude::QueryStoreManager,
@@ -16,6 +16,7 @@ use graph::{
     prelude::{futures03, StoreEvent},
     prelude::{CheapClone, DeploymentHash, NodeId, SubgraphStore as _},
     semver::Version,
+    slog::Level,
 };
 use graph_store_postgres::layout_for_tests::Connection as Primary;
 use graph_store_postgres::SubgraphStore;
@@ -96,6 +97,39 @@ fn reassign_subgraph() {
     })
 }
 
+#[test]
+fn pause_resume_subgraph() {
+    run_test_sequentially(|store| async move {
+        let hash = DeploymentHash::new("pauseSubgraph").unwrap();
+        remove_subgraphs();
+        let id = create_test_subgraph(&hash, SUBGRAPH_GQL).await;
+        let store = store.subgraph_store();
+        let node = store.assigned_node(&id).unwrap().unwrap();
+
+        // Pausing twice only stops the deployment once, and pausing keeps
+        // the assignment
+        for expected in [vec![StoreEvent::new(vec![unassigned(&id)])], vec![]] {
+            let (_, events) = tap_store_events(|| store.pause_subgraph(&id).unwrap());
+            assert_eq!(expected, events);
+            assert_eq!(
+                Some((node.clone(), true)),
+                store.assignment_status(&id).unwrap()
+            );
+            assert!(!store.assignments(&node).unwrap().contains(&id));
+        }
+
+        for expected in [vec![StoreEvent::new(vec![assigned(&id)])], vec![]] {
+            let (_, events) = tap_store_events(|| store.resume_subgraph(&id).unwrap());
+            assert_eq!(expected, events);
+            assert_eq!(
+                Some((node.clone(), false)),
+                store.assignment_status(&id).unwrap()
+            );
+            assert!(store.assignments(&node).unwrap().contains(&id));
+        }
+    })
+}
+
 #[test]
 fn create_subgraph() {
     const SUBGRAPH_NAME: &str = "create/subgraph";
@@ -166,10 +200,16 @@ fn create_subgraph() {
     }
 
     fn deployment_synced(store: &Arc<SubgraphStore>, deployment: &DeploymentLocator) {
-        futures03::executor::block_on(store.cheap_clone().writable(LOGGER.clone(), deployment.id))
-            .expect("can get writable")
-            .deployment_synced()
-            .unwrap();
+        futures03::executor::block_on(async {
+            store
+                .cheap_clone()
+                .writable(LOGGER.clone(), deployment.id)
+                .await
+                .expect("can get writable")
+                .deployment_synced()
+                .await
+        })
+        .unwrap();
     }
 
     // Test VersionSwitchingMode::Instant
@@ -315,6 +355,53 @@ fn create_subgraph() {
         let (current, pending) = subgraph_deployments(&primary);
         assert_eq!(Some(ID3), current.as_deref());
         assert_eq!(None, pending.as_deref());
+    });
+
+    // Test VersionSwitchingMode::Canary
+    run_test_sequentially(|store| async move {
+        remove_subgraphs();
+        let store = store.subgraph_store();
+
+        const MODE: SubgraphVersionSwitchingMode = SubgraphVersionSwitchingMode::Canary;
+        const ID1: &str = "canary";
+        const ID2: &str = "canary2";
+
+        let primary = primary_connection();
+
+        // Deploy and sync the first version
+        let (deployment1, _) = deploy(store.as_ref(), ID1, MODE);
+        deployment_synced(&store, &deployment1);
+
+        // Deploy when current is synced adds pending
+        let (deployment2, events) = deploy(store.as_ref(), ID2, MODE);
+        let expected = deploy_event(&deployment2);
+        assert_eq!(expected, events);
+
+        let (current, pending) = subgraph_deployments(&primary);
+        assert_eq!(Some(ID1), current.as_deref());
+        assert_eq!(Some(ID2), pending.as_deref());
+
+        // Promoting the canary makes it current, but the version it
+        // replaced stays assigned until it is retired
+        let (_, events) = tap_store_events(|| {
+            futures03::executor::block_on(async {
+                store
+                    .cheap_clone()
+                    .writable(LOGGER.clone(), deployment2.id)
+                    .await
+                    .expect("can get writable")
+                    .promote_canary()
+                    .await
+            })
+            .unwrap()
+        });
+        assert!(events.iter().all(|event| event.changes.is_empty()));
+
+        let (current, pending) = subgraph_deployments(&primary);
+        assert_eq!(Some(ID2), current.as_deref());
+        assert_eq!(None, pending.as_deref());
+
+        assert_eq!(0, store.retire_replaced_deployments().unwrap());
     })
 }
 
@@ -527,6 +614,58 @@ fn subgraph_error() {
     })
 }
 
+#[test]
+fn mapping_logs() {
+    test_store::run_test_sequentially(|store| async move {
+        let subgraph_id = DeploymentHash::new("mappingLogs").unwrap();
+        let deployment =
+            test_store::create_test_subgraph(&subgraph_id, "type Foo { id: ID! }").await;
+
+        let log = |block_number, level, message: &str| MappingLog {
+            block_number,
+            level,
+            data_source: "Token".to_string(),
+            message: message.to_string(),
+        };
+        let logs = |from_block, level| {
+            store
+                .subgraph_store()
+                .mapping_logs(&subgraph_id, from_block, level, 100)
+                .unwrap()
+                .into_iter()
+                .map(|log| log.message)
+                .collect::<Vec<_>>()
+        };
+
+        transact_mapping_logs(
+            &store,
+            &deployment,
+            BLOCKS[1].clone(),
+            vec![log(1, Level::Info, "one"), log(1, Level::Debug, "two")],
+        )
+        .await
+        .unwrap();
+        transact_mapping_logs(
+            &store,
+            &deployment,
+            BLOCKS[2].clone(),
+            vec![log(2, Level::Error, "three")],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(vec!["one", "two", "three"], logs(0, Level::Debug));
+        assert_eq!(vec!["one", "three"], logs(0, Level::Info));
+        assert_eq!(vec!["three"], logs(2, Level::Debug));
+
+        // Reverting removes the messages for reverted blocks
+        revert_block(&store, &deployment, &BLOCKS[1]).await;
+        assert_eq!(vec!["one", "two"], logs(0, Level::Debug));
+
+        test_store::remove_subgraph(&subgraph_id);
+    })
+}
+
 #[test]
 fn fatal_vs_non_fatal() {
     async fn setup() -> DeploymentLocator {
@@ -646,6 +785,7 @@ fn fail_unfail_deterministic_error() {
         // Unfail the subgraph.
         let outcome = writable
             .unfail_deterministic_error(&BLOCKS[1], &BLOCKS[0])
+            .await
             .unwrap();
 
         // We don't have fatal errors anymore and the block got reverted.
@@ -719,6 +859,7 @@ fn fail_unfail_deterministic_error_noop() {
         // Run unfail with no errors results in NOOP.
         let outcome = writable
             .unfail_deterministic_error(&BLOCKS[1], &BLOCKS[0])
+            .await
             .unwrap();
 
         // Nothing to unfail, state continues the same.
@@ -750,6 +891,7 @@ fn fail_unfail_deterministic_error_noop() {
         // Running unfail_deterministic_error against a NON-deterministic error will do nothing.
         let outcome = writable
             .unfail_deterministic_error(&BLOCKS[1], &BLOCKS[0])
+            .await
             .unwrap();
 
         // State continues the same, nothing happened.
@@ -776,6 +918,7 @@ fn fail_unfail_deterministic_error_noop() {
         // the hashes won't match and there's nothing to revert.
         let outcome = writable
             .unfail_deterministic_error(&BLOCKS[1], &BLOCKS[0])
+            .await
             .unwrap();
 
         // State continues the same.
@@ -866,7 +1009,10 @@ fn fail_unfail_non_deterministic_error() {
         assert_eq!(Some(1), vi.latest_ethereum_block_number);
 
         // Unfail the subgraph and delete the fatal error.
-        let outcome = writable.unfail_non_deterministic_error(&BLOCKS[1]).unwrap();
+        let outcome = writable
+            .unfail_non_deterministic_error(&BLOCKS[1])
+            .await
+            .unwrap();
 
         // We don't have fatal errors anymore and the subgraph is healthy.
         assert_eq!(outcome, UnfailOutcome::Unfailed);
@@ -937,7 +1083,10 @@ fn fail_unfail_non_deterministic_error_noop() {
             .expect("can get writable");
 
         // Running unfail without any errors will do nothing.
-        let outcome = writable.unfail_non_deterministic_error(&BLOCKS[1]).unwrap();
+        let outcome = writable
+            .unfail_non_deterministic_error(&BLOCKS[1])
+            .await
+            .unwrap();
 
         // State continues the same, nothing happened.
         assert_eq!(outcome, UnfailOutcome::Noop);
@@ -966,7 +1115,10 @@ fn fail_unfail_non_deterministic_error_noop() {
         assert_eq!(Some(1), vi.latest_ethereum_block_number);
 
         // Running unfail_non_deterministic_error will be NOOP, the error is deterministic.
-        let outcome = writable.unfail_non_deterministic_error(&BLOCKS[1]).unwrap();
+        let outcome = writable
+            .unfail_non_deterministic_error(&BLOCKS[1])
+            .await
+            .unwrap();
 
         // Nothing happeened, state continues the same.
         assert_eq!(outcome, UnfailOutcome::Noop);
@@ -988,7 +1140,10 @@ fn fail_unfail_non_deterministic_error_noop() {
         writable.fail_subgraph(error).await.unwrap();
 
         // Since the block range of the block won't match the deployment head, this will be NOOP.
-        let outcome = writable.unfail_non_deterministic_error(&BLOCKS[1]).unwrap();
+        let outcome = writable
+            .unfail_non_deterministic_error(&BLOCKS[1])
+            .await
+            .unwrap();
 
         // State continues the same besides a new error added to the database.
         assert_eq!(outcome, UnfailOutcome::Noop);
@@ -38,7 +38,9 @@ lazy_static! {
 // Hash indicating 'no parent'
 pub const NO_PARENT: &str = "0000000000000000000000000000000000000000000000000000000000000000";
 /// The parts of an Ethereum block that are interesting for these tests:
-/// the block number, hash, and the hash of the parent block
+/// the block number, hash, and the hash of the parent block. Fake blocks
+/// are produced every 10 seconds, and their timestamp is ten times their
+/// number
 #[derive(Clone, Debug, PartialEq)]
 pub struct FakeBlock {
     pub number: BlockNumber,
@@ -71,6 +73,10 @@ impl FakeBlock {
         BlockPtr::from((self.block_hash(), self.number))
     }
 
+    pub fn block_timestamp(&self) -> u64 {
+        self.number as u64 * 10
+    }
+
     pub fn as_ethereum_block(&self) -> EthereumBlock {
         let parent_hash = H256::from_str(self.parent_hash.as_str()).expect("invalid parent hash");
 
@@ -78,6 +84,7 @@ impl FakeBlock {
         block.number = Some(self.number.into());
         block.parent_hash = parent_hash;
         block.hash = Some(self.block_hash());
+        block.timestamp = self.block_timestamp().into();
 
         EthereumBlock {
             block: Arc::new(block),
@@ -105,6 +112,10 @@ impl Block for FakeBlock {
     fn data(&self) -> Result<serde_json::Value, serde_json::Error> {
         serde_json::to_value(self.as_ethereum_block())
     }
+
+    fn timestamp(&self) -> Option<u64> {
+        Some(self.block_timestamp())
+    }
 }
 
 pub type FakeBlockList<'a> = Vec<&'static FakeBlock>;
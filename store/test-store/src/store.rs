@@ -2,7 +2,7 @@ use diesel::{self, PgConnection};
 use graph::data::graphql::effort::LoadManager;
 use graph::data::query::QueryResults;
 use graph::data::query::QueryTarget;
-use graph::data::subgraph::schema::{DeploymentCreate, SubgraphError};
+use graph::data::subgraph::schema::{DeploymentCreate, MappingLog, SubgraphError};
 use graph::log;
 use graph::prelude::{QueryStoreManager as _, SubgraphStore as _, *};
 use graph::semver::Version;
@@ -19,7 +19,10 @@ use graph_mock::MockMetricsRegistry;
 use graph_node::config::{Config, Opt};
 use graph_node::store_builder::StoreBuilder;
 use graph_store_postgres::layout_for_tests::FAKE_NETWORK_SHARED;
-use graph_store_postgres::{connection_pool::ConnectionPool, Shard, SubscriptionManager};
+use graph_store_postgres::{
+    connection_pool::{ConnectionPool, CopyClient},
+    Shard, SubscriptionManager,
+};
 use graph_store_postgres::{
     BlockStore as DieselBlockStore, DeploymentPlacer, SubgraphStore as DieselSubgraphStore,
     PRIMARY_SHARD,
@@ -136,6 +139,13 @@ where
     test(&conn);
 }
 
+/// Get a connection into the primary database that supports `COPY`
+pub fn copy_client() -> CopyClient {
+    PRIMARY_POOL
+        .copy_client()
+        .expect("failed to get a connection for COPY to the primary database")
+}
+
 pub fn remove_subgraphs() {
     SUBGRAPH_STORE
         .delete_all_entities_for_test_use_only()
@@ -229,7 +239,41 @@ pub async fn transact_errors(
             &stopwatch_metrics,
             Vec::new(),
             errs,
+            Vec::new(),
+            Vec::new(),
+            false,
         )
+        .await
+}
+
+pub async fn transact_mapping_logs(
+    store: &Arc<Store>,
+    deployment: &DeploymentLocator,
+    block_ptr_to: BlockPtr,
+    logs: Vec<MappingLog>,
+) -> Result<(), StoreError> {
+    let metrics_registry = Arc::new(MockMetricsRegistry::new());
+    let stopwatch_metrics = StopwatchMetrics::new(
+        Logger::root(slog::Discard, o!()),
+        deployment.hash.clone(),
+        metrics_registry.clone(),
+    );
+    store
+        .subgraph_store()
+        .writable(LOGGER.clone(), deployment.id.clone())
+        .await?
+        .transact_block_operations(
+            block_ptr_to,
+            None,
+            Vec::new(),
+            &stopwatch_metrics,
+            Vec::new(),
+            Vec::new(),
+            logs,
+            Vec::new(),
+            false,
+        )
+        .await
 }
 
 /// Convenience to transact EntityOperation instead of EntityModification
@@ -263,14 +307,17 @@ pub fn transact_entities_and_dynamic_data_sources(
         deployment.hash.clone(),
         metrics_registry.clone(),
     );
-    store.transact_block_operations(
+    futures03::executor::block_on(store.transact_block_operations(
         block_ptr_to,
         None,
         mods,
         &stopwatch_metrics,
         data_sources,
         Vec::new(),
-    )
+        Vec::new(),
+        Vec::new(),
+        false,
+    ))
 }
 
 pub async fn revert_block(store: &Arc<Store>, deployment: &DeploymentLocator, ptr: &BlockPtr) {
@@ -280,6 +327,7 @@ pub async fn revert_block(store: &Arc<Store>, deployment: &DeploymentLocator, pt
         .await
         .expect("can get writable")
         .revert_block_operations(ptr.clone(), None)
+        .await
         .unwrap();
 }
 
@@ -420,7 +468,8 @@ async fn execute_subgraph_query_internal(
                 bc,
                 error_policy,
                 query.schema.id().clone(),
-                result_size_metrics()
+                result_size_metrics(),
+                None
             )
             .await
         );